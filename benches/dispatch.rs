@@ -0,0 +1,50 @@
+use bytes::BytesMut;
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use rustis::{handler::process_command, kv::KvStore, parser::parse};
+
+const PIPELINE: usize = 32;
+
+/// A pipeline of `PIPELINE` commands alternating between `SET` and `GET`
+/// on a handful of keys, like `redis-benchmark -P 32 -t set,get`.
+fn pipeline() -> BytesMut {
+    let mut buf = BytesMut::new();
+    for i in 0..PIPELINE {
+        let key = format!("key:{}", i % 8);
+        let command = if i % 2 == 0 {
+            format!(
+                "*3\r\n$3\r\nSET\r\n${}\r\n{key}\r\n$3\r\nxxx\r\n",
+                key.len()
+            )
+        } else {
+            format!("*2\r\n$3\r\nGET\r\n${}\r\n{key}\r\n", key.len())
+        };
+        buf.extend_from_slice(command.as_bytes());
+    }
+    buf
+}
+
+/// Parse, execute and serialize the replies of a whole pipeline on one
+/// thread: what a connection and its worker do per batch, without the
+/// channels and sockets between them.
+fn bench_pipeline(c: &mut Criterion) {
+    let input = pipeline();
+    let mut kv = KvStore::new();
+    let mut out = BytesMut::with_capacity(4096);
+
+    let mut group = c.benchmark_group("dispatch");
+    group.throughput(Throughput::Elements(PIPELINE as u64));
+    group.bench_function("pipeline_set_get_32", |b| {
+        b.iter(|| {
+            let mut buf = input.clone();
+            out.clear();
+            while !buf.is_empty() {
+                let command = parse(&mut buf).unwrap();
+                process_command(&mut kv, command).serialize(&mut out);
+            }
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_pipeline);
+criterion_main!(benches);
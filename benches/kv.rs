@@ -0,0 +1,80 @@
+use bytes::Bytes;
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion};
+use rustis::kv::KvStore;
+
+const KEYS: usize = 100_000;
+
+fn keys() -> Vec<Bytes> {
+    (0..KEYS).map(|i| Bytes::from(format!("key:{i}"))).collect()
+}
+
+fn bench_strings(c: &mut Criterion) {
+    let keys = keys();
+    let value = Bytes::from_static(b"a value of some thirty-odd bytes");
+    let mut store = KvStore::new();
+    for key in &keys {
+        store.set(key.clone(), value.clone());
+    }
+
+    let mut group = c.benchmark_group("strings");
+    let mut i = 0;
+    group.bench_function("set_existing", |b| {
+        b.iter(|| {
+            i = (i + 1) % KEYS;
+            store.set(keys[i].clone(), value.clone());
+        })
+    });
+    group.bench_function("set_new", |b| {
+        b.iter_batched(
+            KvStore::new,
+            |mut store| {
+                for key in &keys[..1000] {
+                    store.set(key.clone(), value.clone());
+                }
+                store
+            },
+            BatchSize::LargeInput,
+        )
+    });
+    group.bench_function("get_hit", |b| {
+        b.iter(|| {
+            i = (i + 1) % KEYS;
+            black_box(store.get(&keys[i]));
+        })
+    });
+    let missing = Bytes::from_static(b"missing");
+    group.bench_function("get_miss", |b| b.iter(|| black_box(store.get(&missing))));
+    group.finish();
+}
+
+#[cfg(feature = "lists")]
+fn bench_lists(c: &mut Criterion) {
+    let key = Bytes::from_static(b"list");
+    let value = Bytes::from_static(b"element");
+    let mut group = c.benchmark_group("lists");
+
+    // the first 8KB stay packed, so small and big lists take different paths
+    for (name, len) in [("listpack", 100), ("quicklist", 10_000)] {
+        let mut store = KvStore::new();
+        store.rpush(key.clone(), vec![value.clone(); len]).unwrap();
+        group.bench_function(format!("lpush_rpop_{name}"), |b| {
+            b.iter(|| {
+                store.lpush(key.clone(), vec![value.clone()]).unwrap();
+                black_box(store.rpop(&key, 1).unwrap());
+            })
+        });
+        group.bench_function(format!("rpush_lpop_{name}"), |b| {
+            b.iter(|| {
+                store.rpush(key.clone(), vec![value.clone()]).unwrap();
+                black_box(store.lpop(&key, 1).unwrap());
+            })
+        });
+    }
+    group.finish();
+}
+
+#[cfg(feature = "lists")]
+criterion_group!(benches, bench_strings, bench_lists);
+#[cfg(not(feature = "lists"))]
+criterion_group!(benches, bench_strings);
+criterion_main!(benches);
@@ -0,0 +1,80 @@
+use bytes::{Bytes, BytesMut};
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use rustis::{message::ResponseValue, parser::parse};
+
+/// `count` copies of `SET key:<n> <value>` back to back, as a pipelining
+/// client would send them.
+fn pipelined_sets(count: usize, value_len: usize) -> BytesMut {
+    let value = "x".repeat(value_len);
+    let mut buf = BytesMut::new();
+    for i in 0..count {
+        let key = format!("key:{i}");
+        buf.extend_from_slice(
+            format!(
+                "*3\r\n$3\r\nSET\r\n${}\r\n{key}\r\n${}\r\n{value}\r\n",
+                key.len(),
+                value.len()
+            )
+            .as_bytes(),
+        );
+    }
+    buf
+}
+
+fn bench_parse(c: &mut Criterion) {
+    let mut group = c.benchmark_group("parse");
+    for value_len in [16, 1024] {
+        let input = pipelined_sets(100, value_len);
+        group.throughput(Throughput::Bytes(input.len() as u64));
+        group.bench_with_input(
+            BenchmarkId::new("set_x100", value_len),
+            &input,
+            |b, input| {
+                b.iter(|| {
+                    let mut buf = input.clone();
+                    while !buf.is_empty() {
+                        black_box(parse(&mut buf).unwrap());
+                    }
+                })
+            },
+        );
+    }
+    group.bench_function("inline_ping", |b| {
+        b.iter(|| black_box(parse(&mut BytesMut::from(&b"PING\r\n"[..])).unwrap()))
+    });
+    group.finish();
+}
+
+fn bench_serialize(c: &mut Criterion) {
+    let mut group = c.benchmark_group("serialize");
+    let replies = [
+        ("ok", ResponseValue::SimpleString(Bytes::from_static(b"OK"))),
+        ("integer", ResponseValue::Integer(1_234_567)),
+        (
+            "bulk_1k",
+            ResponseValue::BulkString(Some(Bytes::from(vec![b'x'; 1024]))),
+        ),
+        (
+            "array_100",
+            ResponseValue::Array(Some(
+                (0..100)
+                    .map(|i| ResponseValue::BulkString(Some(Bytes::from(format!("member:{i}")))))
+                    .collect(),
+            )),
+        ),
+    ];
+    for (name, reply) in &replies {
+        group.throughput(Throughput::Bytes(reply.encoded_len() as u64));
+        group.bench_function(*name, |b| {
+            let mut dst = BytesMut::with_capacity(64 * 1024);
+            b.iter(|| {
+                dst.clear();
+                black_box(reply).serialize(&mut dst);
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_parse, bench_serialize);
+criterion_main!(benches);
@@ -0,0 +1,128 @@
+#![no_main]
+
+//! Arbitrary commands run on a shard by `process_command`, as a worker runs
+//! what the router hands it. Names are picked among the real commands and
+//! keys among a few, so commands keep hitting the same values; arguments
+//! are anything a client could send. No command may panic, and every reply
+//! must encode to RESP that decodes back to it, line breaks inside errors
+//! aside, or a client would lose track of the stream.
+
+use arbitrary::Arbitrary;
+use bytes::{Bytes, BytesMut};
+use libfuzzer_sys::fuzz_target;
+use rustis::{
+    config::Config, handler::process_command, kv::KvStore, message::ResponseValue, parser::parse,
+};
+
+const NAMES: &[&str] = &[
+    "PING",
+    "CONFIG",
+    "DBSIZE",
+    "FLUSHALL",
+    "FLUSHDB",
+    "MEMORY",
+    "OBJECT",
+    "GET",
+    "SET",
+    "SETEX",
+    "PSETEX",
+    "SETNX",
+    "GETSET",
+    "MGET",
+    "MSET",
+    "DEL",
+    "UNLINK",
+    "EXISTS",
+    "LPUSH",
+    "LPOP",
+    "RPUSH",
+    "RPOP",
+    "LRANGE",
+    "SADD",
+    "SPOP",
+    "SMEMBERS",
+    "SSCAN",
+    "EXPORT",
+    "NOSUCHCOMMAND",
+];
+
+/// Words commands take as arguments.
+const WORDS: &[&str] = &[
+    "USAGE", "SAMPLES", "ENCODING", "FREQ", "IDLETIME", "ASYNC", "SYNC", "GET", "EX", "PX", "EXAT",
+    "PXAT", "NX", "XX", "KEEPTTL",
+];
+
+#[derive(Debug, Arbitrary)]
+struct Command {
+    name: u8,
+    args: Vec<Arg>,
+}
+
+#[derive(Debug, Arbitrary)]
+enum Arg {
+    Key(u8),
+    Word(u8),
+    Number(i8),
+    Bytes(Vec<u8>),
+    /// Not a bulk string, which clients aren't supposed to send.
+    Integer(i64),
+    Nil,
+}
+
+impl Arg {
+    fn into_value(self) -> ResponseValue {
+        let bulk = |s: String| ResponseValue::BulkString(Some(Bytes::from(s)));
+        match self {
+            Arg::Key(n) => bulk(format!("key:{}", n % 8)),
+            Arg::Word(n) => bulk(WORDS[n as usize % WORDS.len()].to_string()),
+            Arg::Number(n) => bulk(n.to_string()),
+            Arg::Bytes(bytes) => ResponseValue::BulkString(Some(bytes.into())),
+            Arg::Integer(n) => ResponseValue::Integer(n),
+            Arg::Nil => ResponseValue::BulkString(None),
+        }
+    }
+}
+
+/// `reply` as a client reads it: `\r\n` in error and status lines is sent
+/// as two spaces.
+fn as_sent(reply: ResponseValue) -> ResponseValue {
+    let line = |s: Bytes| {
+        let mut out = s.to_vec();
+        for i in 0..out.len().saturating_sub(1) {
+            if &out[i..i + 2] == b"\r\n" {
+                out[i..i + 2].copy_from_slice(b"  ");
+            }
+        }
+        Bytes::from(out)
+    };
+    match reply {
+        ResponseValue::SimpleString(s) => ResponseValue::SimpleString(line(s)),
+        ResponseValue::Error(s) => ResponseValue::Error(line(s)),
+        ResponseValue::Array(Some(items)) => {
+            ResponseValue::Array(Some(items.into_iter().map(as_sent).collect()))
+        }
+        reply => reply,
+    }
+}
+
+fuzz_target!(|commands: Vec<Command>| {
+    // small encodings, so values keep converting between them
+    let mut kv = KvStore::from_config(&Config {
+        list_max_listpack_size: 4,
+        set_max_intset_entries: 4,
+        ..Config::default()
+    });
+    for command in commands {
+        let name = NAMES[command.name as usize % NAMES.len()];
+        let mut frame = vec![ResponseValue::BulkString(Some(Bytes::from_static(
+            name.as_bytes(),
+        )))];
+        frame.extend(command.args.into_iter().map(Arg::into_value));
+
+        let reply = process_command(&mut kv, ResponseValue::Array(Some(frame)));
+        let mut encoded = BytesMut::new();
+        reply.serialize(&mut encoded);
+        assert_eq!(parse(&mut encoded), Ok(as_sent(reply)));
+        assert!(encoded.is_empty(), "the reply is read whole");
+    }
+});
@@ -0,0 +1,42 @@
+#![no_main]
+
+//! Arbitrary bytes, as a client could send them, through the RESP decoder.
+//! Whatever the input it must not panic, every frame it returns must come
+//! back unchanged from encoding and decoding it again, and every prefix of
+//! a frame must read as incomplete, as a connection sees it when the frame
+//! arrives in pieces. Read as a request, the input must not panic either.
+
+use bytes::BytesMut;
+use libfuzzer_sys::fuzz_target;
+use rustis::parser::{parse, parse_request, BufParseError};
+
+fuzz_target!(|data: &[u8]| {
+    let _ = parse_request(&mut BytesMut::from(data));
+
+    let mut buffer = BytesMut::from(data);
+    let mut first = true;
+    loop {
+        let before = buffer.clone();
+        let Ok(frame) = parse(&mut buffer) else {
+            break;
+        };
+        let len = before.len() - buffer.len();
+        assert!(len > 0, "a frame takes up bytes");
+
+        let mut encoded = BytesMut::new();
+        frame.serialize(&mut encoded);
+        assert_eq!(encoded.len(), frame.encoded_len());
+        let mut decoded = encoded.clone();
+        assert_eq!(parse(&mut decoded), Ok(frame));
+        assert!(decoded.is_empty(), "the frame is read whole");
+
+        // quadratic, so only for the first frame
+        if first {
+            for cut in 0..len {
+                let mut prefix = BytesMut::from(&before[..cut]);
+                assert_eq!(parse(&mut prefix), Err(BufParseError::Incomplete));
+            }
+            first = false;
+        }
+    }
+});
@@ -0,0 +1,49 @@
+/*
+ * The C ABI of the store, as `rustis::ffi` defines it, for keeping a rustis
+ * keyspace in-process as a cache. Build the library with
+ *
+ *     cargo rustc --release --lib --no-default-features \
+ *         --features ffi,lists,sets --crate-type staticlib
+ *
+ * (or `cdylib`) and link against `target/release/librustis.a`.
+ *
+ * Commands go in as RESP, an array of bulk strings per command as a client
+ * sends, or inline commands, and replies come out as RESP. A request may
+ * hold several commands; the reply then holds their replies, in order. A
+ * request ending in a partial command, or with one that can't be parsed,
+ * gets an error reply for it, and anything after it is ignored. A store may
+ * be used from several threads at once, which take turns.
+ */
+
+#ifndef RUSTIS_H
+#define RUSTIS_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct rustis_store rustis_store;
+typedef struct rustis_reply rustis_reply;
+
+/* An empty keyspace with the default config. */
+rustis_store *rustis_store_new(void);
+/* NULL is ignored. No other thread may be using the store. */
+void rustis_store_free(rustis_store *store);
+
+/* NULL only if store is. request may be NULL when len is 0. */
+rustis_reply *rustis_execute(const rustis_store *store, const uint8_t *request,
+                             size_t len);
+/* Valid until the reply is freed. */
+const uint8_t *rustis_reply_data(const rustis_reply *reply);
+size_t rustis_reply_len(const rustis_reply *reply);
+/* NULL is ignored. */
+void rustis_reply_free(rustis_reply *reply);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* RUSTIS_H */
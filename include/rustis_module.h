@@ -0,0 +1,96 @@
+/*
+ * The C ABI of rustis modules, as `rustis::module_abi` defines it.
+ *
+ * A module is a shared library exporting
+ *
+ *     int rustis_module_init(const RustisModuleApi *api,
+ *                            RustisRegistration *registration,
+ *                            size_t argc, const char *const *argv);
+ *
+ * called once at startup with the arguments `--loadmodule` gave it. It
+ * checks `api->abi_version`, registers its commands and data types, and
+ * returns RUSTIS_OK, or anything else to fail the startup.
+ *
+ * A command handler runs on the worker owning its first argument. It replies
+ * exactly once (an array counts as one reply, filled in by the next `len`
+ * ones) and reaches the keyspace through `api`, with a context valid only
+ * until it returns. Strings are a pointer and a length, except names passed
+ * at registration, which are NUL terminated. Values of a module type may be
+ * copied and freed on any thread.
+ */
+
+#ifndef RUSTIS_MODULE_H
+#define RUSTIS_MODULE_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define RUSTIS_ABI_VERSION 1
+
+#define RUSTIS_OK 0
+#define RUSTIS_ERR -1
+
+#define RUSTIS_KEY_FOUND 1
+#define RUSTIS_KEY_MISSING 0
+#define RUSTIS_KEY_WRONGTYPE -1
+
+typedef struct RustisRegistration RustisRegistration;
+typedef struct RustisCommandContext RustisCommandContext;
+typedef struct RustisModuleType RustisModuleType;
+
+/* argv[i] is argvlen[i] bytes long, the command's name left out. */
+typedef void (*RustisCommandHandler)(RustisCommandContext *ctx, size_t argc,
+                                     const uint8_t *const *argv,
+                                     const size_t *argvlen);
+
+typedef struct RustisTypeMethods {
+    void (*free)(void *value);
+    void *(*copy)(const void *value);
+    /* Approximate bytes held by a value; may be NULL. */
+    size_t (*memory_usage)(const void *value);
+} RustisTypeMethods;
+
+typedef struct RustisModuleApi {
+    uint32_t abi_version;
+    /* flags: space separated, from "write", "deny-oom", "readonly", "fast". */
+    int (*register_command)(RustisRegistration *registration, const char *name,
+                            const char *flags, RustisCommandHandler handler);
+    /* NULL if the name is taken. */
+    const RustisModuleType *(*register_type)(RustisRegistration *registration,
+                                             const char *name,
+                                             const RustisTypeMethods *methods);
+    void (*reply_simple_string)(RustisCommandContext *ctx, const uint8_t *s,
+                                size_t len);
+    void (*reply_error)(RustisCommandContext *ctx, const uint8_t *s, size_t len);
+    void (*reply_integer)(RustisCommandContext *ctx, int64_t n);
+    void (*reply_bulk)(RustisCommandContext *ctx, const uint8_t *s, size_t len);
+    void (*reply_null)(RustisCommandContext *ctx);
+    void (*reply_array)(RustisCommandContext *ctx, size_t len);
+    /* The string stays valid until the handler returns. */
+    int (*string_get)(RustisCommandContext *ctx, const uint8_t *key,
+                      size_t keylen, const uint8_t **value, size_t *len);
+    void (*string_set)(RustisCommandContext *ctx, const uint8_t *key,
+                       size_t keylen, const uint8_t *value, size_t len);
+    /* The value may be changed in place. */
+    int (*value_get)(RustisCommandContext *ctx, const uint8_t *key,
+                     size_t keylen, const RustisModuleType *type, void **value);
+    /* The server owns the value from then on. */
+    void (*value_set)(RustisCommandContext *ctx, const uint8_t *key,
+                      size_t keylen, const RustisModuleType *type, void *value);
+    int (*key_delete)(RustisCommandContext *ctx, const uint8_t *key,
+                      size_t keylen);
+} RustisModuleApi;
+
+int rustis_module_init(const RustisModuleApi *api,
+                       RustisRegistration *registration, size_t argc,
+                       const char *const *argv);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* RUSTIS_MODULE_H */
@@ -0,0 +1,122 @@
+/// Allocator the server binary is built with: `mimalloc` with the
+/// `mimalloc` feature, otherwise jemalloc with the default `jemalloc`
+/// feature, otherwise the system's. Reported as `mem_allocator`.
+pub const NAME: &str = if cfg!(feature = "mimalloc") {
+    "mimalloc"
+} else if cfg!(all(feature = "jemalloc", not(target_env = "msvc"))) {
+    "jemalloc-5.3.0"
+} else {
+    "libc"
+};
+
+/// What the allocator says about its own memory, as far as it says
+/// anything: jemalloc reports all three, mimalloc only what it has
+/// committed and touched, the system allocator nothing.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct AllocatorStats {
+    /// Bytes handed out to the program.
+    pub allocated: Option<usize>,
+    /// Bytes in the pages those allocations sit in.
+    pub active: Option<usize>,
+    /// Bytes the allocator has resident, free pages it still holds included.
+    pub resident: Option<usize>,
+}
+
+impl AllocatorStats {
+    /// `active / allocated`: how much the allocator loses to fragmentation.
+    pub fn frag_ratio(&self) -> Option<f64> {
+        ratio(self.active?, self.allocated?)
+    }
+
+    /// `resident / active`: how much it holds on to without using.
+    pub fn rss_ratio(&self) -> Option<f64> {
+        ratio(self.resident?, self.active?)
+    }
+}
+
+fn ratio(numerator: usize, denominator: usize) -> Option<f64> {
+    (denominator > 0).then(|| numerator as f64 / denominator as f64)
+}
+
+#[cfg(feature = "mimalloc")]
+pub fn stats() -> AllocatorStats {
+    use std::ptr::null_mut;
+
+    let (mut rss, mut commit) = (0, 0);
+    // SAFETY: mimalloc skips the null pointers and fills in the others
+    unsafe {
+        libmimalloc_sys::mi_process_info(
+            null_mut(),
+            null_mut(),
+            null_mut(),
+            &mut rss,
+            null_mut(),
+            &mut commit,
+            null_mut(),
+            null_mut(),
+        );
+    }
+    AllocatorStats {
+        allocated: None,
+        active: Some(commit),
+        resident: Some(rss),
+    }
+}
+
+#[cfg(all(
+    feature = "jemalloc",
+    not(feature = "mimalloc"),
+    not(target_env = "msvc")
+))]
+pub fn stats() -> AllocatorStats {
+    use tikv_jemalloc_ctl::{epoch, stats};
+
+    // jemalloc caches its statistics until the epoch moves on
+    let _ = epoch::advance();
+    AllocatorStats {
+        allocated: stats::allocated::read().ok(),
+        active: stats::active::read().ok(),
+        resident: stats::resident::read().ok(),
+    }
+}
+
+#[cfg(not(any(
+    feature = "mimalloc",
+    all(feature = "jemalloc", not(target_env = "msvc"))
+)))]
+pub fn stats() -> AllocatorStats {
+    AllocatorStats::default()
+}
+
+/// Turns the calling thread's allocation cache on or off. Active defrag
+/// turns it off so that what it reallocates comes from the allocator's
+/// fullest pages instead of straight back out of the cache it just freed
+/// into. Only jemalloc has such a cache.
+pub fn set_thread_cache(enabled: bool) {
+    #[cfg(all(
+        feature = "jemalloc",
+        not(feature = "mimalloc"),
+        not(target_env = "msvc")
+    ))]
+    {
+        use tikv_jemalloc_ctl::{Access, AsName};
+
+        let _ = b"thread.tcache.enabled\0".name().write(enabled);
+    }
+    let _ = enabled;
+}
+
+/// Resident set size of the whole process, as the kernel counts it.
+#[cfg(target_os = "linux")]
+pub fn process_rss() -> Option<usize> {
+    let statm = std::fs::read_to_string("/proc/self/statm").ok()?;
+    let pages: usize = statm.split_whitespace().nth(1)?.parse().ok()?;
+    // SAFETY: sysconf has no preconditions
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+    Some(pages * usize::try_from(page_size).ok()?)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn process_rss() -> Option<usize> {
+    None
+}
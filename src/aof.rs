@@ -0,0 +1,212 @@
+//! Checking append-only files, for `rustis-check-aof`, the way
+//! `redis-check-aof` does: the file must be a run of commands as RESP arrays
+//! of bulk strings, with every MULTI closed by an EXEC.
+//!
+//! The check finds how far the file is sound, `ok_up_to`, and what is wrong
+//! past it. A transaction left open counts as broken from its MULTI, so
+//! truncating the file there, as `--fix` does, never leaves half of one to
+//! be replayed. Lines starting with `#` are annotations, such as Redis 7's
+//! `#TS:` timestamps, and a file may start with an RDB snapshot, as Redis
+//! writes with `aof-use-rdb-preamble`; that is checked by `rdb::read`.
+
+use std::{fs::OpenOptions, io, path::Path};
+
+use crate::rdb;
+
+/// What checking an AOF found.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AofCheck {
+    pub size: usize,
+    /// Bytes from the start that hold whole commands and transactions.
+    pub ok_up_to: usize,
+    /// Lines in those bytes.
+    pub ok_up_to_line: usize,
+    /// Commands read, up to the first problem.
+    pub commands: usize,
+    pub error: Option<AofError>,
+}
+
+/// The first problem in an AOF.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AofError {
+    pub offset: usize,
+    pub message: String,
+}
+
+impl AofCheck {
+    pub fn is_valid(&self) -> bool {
+        self.error.is_none()
+    }
+
+    /// Bytes `--fix` would cut off.
+    pub fn diff(&self) -> usize {
+        self.size - self.ok_up_to
+    }
+}
+
+/// Checks an AOF's bytes.
+pub fn check(data: &[u8]) -> AofCheck {
+    let mut reader = Reader {
+        data,
+        pos: 0,
+        lines: 0,
+    };
+    let mut check = AofCheck {
+        size: data.len(),
+        ok_up_to: 0,
+        ok_up_to_line: 0,
+        commands: 0,
+        error: None,
+    };
+
+    if data.starts_with(b"REDIS") {
+        let snapshot = rdb::read(data);
+        if let Some(err) = snapshot.error {
+            check.error = Some(AofError {
+                offset: err.offset,
+                message: format!("RDB preamble is not sane: {}", err.message),
+            });
+            return check;
+        }
+        if let Some(rdb::Checksum::Mismatch { .. }) = snapshot.checksum {
+            check.error = Some(AofError {
+                offset: snapshot.len,
+                message: "RDB preamble checksum mismatch".into(),
+            });
+            return check;
+        }
+        reader.pos = snapshot.len;
+    }
+
+    // where the open transaction started, if one is
+    let mut multi = None;
+    loop {
+        if multi.is_none() {
+            check.ok_up_to = reader.pos;
+            check.ok_up_to_line = reader.lines;
+        }
+        if reader.pos == data.len() {
+            break;
+        }
+        let start = reader.pos;
+        let name = match reader.command() {
+            Ok(Some(name)) => name,
+            Ok(None) => continue,
+            Err(message) => {
+                check.error = Some(AofError {
+                    offset: reader.pos,
+                    message,
+                });
+                return check;
+            }
+        };
+        let message = if name.eq_ignore_ascii_case(b"MULTI") {
+            match multi {
+                Some(_) => Some("Unexpected MULTI"),
+                None => {
+                    multi = Some(start);
+                    None
+                }
+            }
+        } else if name.eq_ignore_ascii_case(b"EXEC") {
+            match multi.take() {
+                Some(_) => None,
+                None => Some("Unexpected EXEC"),
+            }
+        } else {
+            None
+        };
+        if let Some(message) = message {
+            check.error = Some(AofError {
+                offset: start,
+                message: message.into(),
+            });
+            return check;
+        }
+        check.commands += 1;
+    }
+    if let Some(start) = multi {
+        check.error = Some(AofError {
+            offset: start,
+            message: "Reached EOF before reading EXEC for MULTI".into(),
+        });
+    }
+    check
+}
+
+/// Cuts the file at `path` down to `len` bytes, as `--fix` does with
+/// `ok_up_to`.
+pub fn truncate(path: &Path, len: usize) -> io::Result<()> {
+    let file = OpenOptions::new().write(true).open(path)?;
+    file.set_len(len as u64)?;
+    file.sync_all()
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    lines: usize,
+}
+
+impl<'a> Reader<'a> {
+    /// Reads a command, returning its name, or `None` for an annotation.
+    fn command(&mut self) -> Result<Option<Vec<u8>>, String> {
+        if self.data[self.pos] == b'#' {
+            self.line()?;
+            return Ok(None);
+        }
+        let argc = self.count(b'*')?;
+        if argc == 0 {
+            return Err("Command with no arguments".into());
+        }
+        let mut name = Vec::new();
+        for i in 0..argc {
+            let len = self.count(b'$')?;
+            let arg = self
+                .data
+                .get(self.pos..self.pos.saturating_add(len))
+                .ok_or("Unexpected EOF reading an argument")?;
+            if i == 0 {
+                name = arg.to_vec();
+            }
+            self.pos += len;
+            match self.data.get(self.pos..self.pos + 2) {
+                Some(b"\r\n") => {}
+                Some(_) => return Err("Expected \\r\\n after an argument".into()),
+                None => return Err("Unexpected EOF reading an argument".into()),
+            }
+            self.pos += 2;
+            self.lines += 1;
+        }
+        Ok(Some(name))
+    }
+
+    /// Reads a `<prefix><count>\r\n` line.
+    fn count(&mut self, prefix: u8) -> Result<usize, String> {
+        let line = self.line()?;
+        match line.split_first() {
+            Some((&first, digits)) if first == prefix => std::str::from_utf8(digits)
+                .ok()
+                .and_then(|digits| digits.parse().ok())
+                .ok_or_else(|| format!("Invalid count '{}'", String::from_utf8_lossy(digits))),
+            Some((&first, _)) => Err(format!(
+                "Expected prefix '{}', got: '{}'",
+                prefix as char,
+                first.escape_ascii()
+            )),
+            None => Err(format!(
+                "Expected prefix '{}', got an empty line",
+                prefix as char
+            )),
+        }
+    }
+
+    /// Reads a line ending in `\r\n`, returning it without.
+    fn line(&mut self) -> Result<&'a [u8], String> {
+        let rest = &self.data[self.pos..];
+        let end = memchr::memmem::find(rest, b"\r\n").ok_or("Unexpected EOF reading a line")?;
+        self.pos += end + 2;
+        self.lines += 1;
+        Ok(&rest[..end])
+    }
+}
@@ -0,0 +1,211 @@
+//! The audit log: with `--audit-log <file>`, every write command a client
+//! sends is appended to `file`, one line each,
+//!
+//! ```text
+//! 16 Oct 2026 10:00:00.123 id=7 addr=127.0.0.1:50312 user=default "SET" "key" "value"
+//! ```
+//!
+//! UTC time, the client's id and address, the user it runs as (always
+//! `default`, there being no ACLs) and the command with its arguments quoted
+//! as `MONITOR` does, cut short after `MAX_ARGS_LEN` bytes.
+//!
+//! Commands are logged as they are received, before they run. Lines are
+//! written on a thread of their own, which moves the file to `file.1` (and
+//! `file.1` to `file.2`, and so on) once it would grow past
+//! `--audit-log-max-size`, keeping `--audit-log-files` old files.
+
+use std::{
+    fmt::Write as _,
+    fs::{self, File, OpenOptions},
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::{
+        mpsc::{self, Sender},
+        OnceLock,
+    },
+    time::SystemTime,
+};
+
+use tracing::warn;
+
+use crate::{
+    config::Config, connection::Client, handler::is_write_command, log::timestamp,
+    message::ResponseValue,
+};
+
+/// Bytes of quoted command and arguments an entry keeps; longer ones end in
+/// `...`.
+pub const MAX_ARGS_LEN: usize = 1024;
+
+static AUDIT_LOG: OnceLock<Sender<String>> = OnceLock::new();
+
+/// Opens the audit log `config` asks for, if any, and starts the thread
+/// writing it.
+pub fn init(config: &Config) -> io::Result<()> {
+    let Some(path) = &config.audit_log else {
+        return Ok(());
+    };
+    let mut file = RotatingFile::open(
+        path,
+        config.audit_log_max_size as u64,
+        config.audit_log_files,
+    )?;
+    let (tx, rx) = mpsc::channel::<String>();
+    std::thread::Builder::new()
+        .name("audit-log".into())
+        .spawn(move || {
+            while let Ok(line) = rx.recv() {
+                // flush once per burst rather than once per line
+                let written = std::iter::once(line)
+                    .chain(rx.try_iter())
+                    .try_for_each(|line| file.write_line(&line))
+                    .and_then(|()| file.flush());
+                if let Err(err) = written {
+                    warn!("Can't write the audit log: {err}");
+                }
+            }
+        })?;
+    let _ = AUDIT_LOG.set(tx);
+    Ok(())
+}
+
+/// Logs `frame` if it is a write command and the audit log is on.
+pub fn record(client: &Client, frame: &ResponseValue) {
+    let Some(tx) = AUDIT_LOG.get() else {
+        return;
+    };
+    if let Some(line) = entry(SystemTime::now(), client, frame) {
+        let _ = tx.send(line);
+    }
+}
+
+/// The audit log line for `frame`, sent by `client` at `time`, or `None` if
+/// it isn't a write command.
+pub fn entry(time: SystemTime, client: &Client, frame: &ResponseValue) -> Option<String> {
+    let ResponseValue::Array(Some(items)) = frame else {
+        return None;
+    };
+    if !is_write_command(frame) {
+        return None;
+    }
+    let mut line = format!(
+        "{} id={} addr={} user=default",
+        timestamp(time),
+        client.id,
+        client.addr
+    );
+    let limit = line.len() + MAX_ARGS_LEN;
+    for item in items {
+        if line.len() > limit {
+            break;
+        }
+        line.push(' ');
+        match item {
+            ResponseValue::BulkString(Some(arg)) => quote(&mut line, arg, limit),
+            _ => line.push_str("\"\""),
+        }
+    }
+    // everything quoted is ASCII, so any byte is a char boundary
+    if line.len() > limit {
+        line.truncate(limit);
+        line.push_str("...");
+    }
+    Some(line)
+}
+
+/// Appends `arg` to `line` in double quotes, escaping what isn't printable
+/// ASCII, stopping a little past `limit` so a huge value isn't copied whole.
+pub(crate) fn quote(line: &mut String, arg: &[u8], limit: usize) {
+    line.push('"');
+    for &byte in arg {
+        if line.len() > limit {
+            return;
+        }
+        match byte {
+            b'"' => line.push_str("\\\""),
+            b'\\' => line.push_str("\\\\"),
+            b'\n' => line.push_str("\\n"),
+            b'\r' => line.push_str("\\r"),
+            b'\t' => line.push_str("\\t"),
+            b' '..=b'~' => line.push(byte as char),
+            _ => {
+                let _ = write!(line, "\\x{byte:02x}");
+            }
+        }
+    }
+    line.push('"');
+}
+
+/// A log file that is moved aside once it reaches its size limit.
+pub struct RotatingFile {
+    path: PathBuf,
+    file: BufWriter<File>,
+    size: u64,
+    /// Size past which the file is rotated, 0 for never.
+    max_size: u64,
+    /// Rotated files kept, `path.1` being the newest.
+    files: usize,
+}
+
+impl RotatingFile {
+    /// Opens `path` for appending, creating it if needed.
+    pub fn open(path: &Path, max_size: u64, files: usize) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let size = file.metadata()?.len();
+        Ok(Self {
+            path: path.to_path_buf(),
+            file: BufWriter::new(file),
+            size,
+            max_size,
+            files,
+        })
+    }
+
+    /// Appends `line` and a newline, rotating first if they would take the
+    /// file past its size limit.
+    pub fn write_line(&mut self, line: &str) -> io::Result<()> {
+        let len = line.len() as u64 + 1;
+        if self.max_size > 0 && self.size > 0 && self.size + len > self.max_size {
+            self.rotate()?;
+        }
+        self.file.write_all(line.as_bytes())?;
+        self.file.write_all(b"\n")?;
+        self.size += len;
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+
+    /// Shifts `path.1` to `path.2` and so on, dropping the oldest, moves the
+    /// current file to `path.1` and starts an empty one.
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        if self.files == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            for n in (1..self.files).rev() {
+                let from = self.rotated(n);
+                if from.exists() {
+                    fs::rename(from, self.rotated(n + 1))?;
+                }
+            }
+            fs::rename(&self.path, self.rotated(1))?;
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        self.file = BufWriter::new(file);
+        self.size = 0;
+        Ok(())
+    }
+
+    /// The path of the `n`th most recent rotated file.
+    fn rotated(&self, n: usize) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{n}"));
+        path.into()
+    }
+}
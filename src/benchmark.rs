@@ -0,0 +1,403 @@
+use std::{
+    fmt::Write as _,
+    io,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use bytes::{BufMut, BytesMut};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+use crate::{
+    message::ResponseValue,
+    parser::{parse, BufParseError},
+};
+
+/// A command `rustis-benchmark` can load the server with, named as in
+/// `redis-benchmark -t`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Test {
+    Ping,
+    Set,
+    Get,
+    Incr,
+    Lpush,
+    Rpush,
+    Lpop,
+    Rpop,
+    Sadd,
+    Spop,
+}
+
+impl Test {
+    pub const ALL: [Test; 10] = [
+        Test::Ping,
+        Test::Set,
+        Test::Get,
+        Test::Incr,
+        Test::Lpush,
+        Test::Rpush,
+        Test::Lpop,
+        Test::Rpop,
+        Test::Sadd,
+        Test::Spop,
+    ];
+
+    fn from_name(name: &str) -> Option<Self> {
+        Test::ALL
+            .into_iter()
+            .find(|test| test.name().eq_ignore_ascii_case(name))
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Test::Ping => "PING",
+            Test::Set => "SET",
+            Test::Get => "GET",
+            Test::Incr => "INCR",
+            Test::Lpush => "LPUSH",
+            Test::Rpush => "RPUSH",
+            Test::Lpop => "LPOP",
+            Test::Rpop => "RPOP",
+            Test::Sadd => "SADD",
+            Test::Spop => "SPOP",
+        }
+    }
+
+    /// Appends the request for one command to `dst`. String commands use
+    /// `key:<n>`, with `n` below the `-r` keyspace, and the collection
+    /// commands one fixed key each, like `redis-benchmark`.
+    pub fn encode(self, dst: &mut BytesMut, key: usize, value: &[u8]) {
+        let key = format!("key:{key:012}");
+        let args: &[&[u8]] = match self {
+            Test::Ping => &[b"PING"],
+            Test::Set => &[b"SET", key.as_bytes(), value],
+            Test::Get => &[b"GET", key.as_bytes()],
+            Test::Incr => &[b"INCR", b"counter:rand"],
+            Test::Lpush => &[b"LPUSH", b"mylist", value],
+            Test::Rpush => &[b"RPUSH", b"mylist", value],
+            Test::Lpop => &[b"LPOP", b"mylist"],
+            Test::Rpop => &[b"RPOP", b"mylist"],
+            Test::Sadd => &[b"SADD", b"myset", key.as_bytes()],
+            Test::Spop => &[b"SPOP", b"myset"],
+        };
+        dst.put_slice(format!("*{}\r\n", args.len()).as_bytes());
+        for arg in args {
+            dst.put_slice(format!("${}\r\n", arg.len()).as_bytes());
+            dst.put_slice(arg);
+            dst.put_slice(b"\r\n");
+        }
+    }
+}
+
+/// `rustis-benchmark` options, spelled like `redis-benchmark`'s.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BenchmarkConfig {
+    pub host: String,
+    pub port: u16,
+    /// Connections open at once (`-c`).
+    pub clients: usize,
+    /// Requests per test, over all connections (`-n`).
+    pub requests: usize,
+    /// Requests each connection sends before reading replies (`-P`).
+    pub pipeline: usize,
+    /// Bytes in the values of SET, LPUSH and RPUSH (`-d`).
+    pub data_size: usize,
+    /// Spread string keys over this many random names instead of one (`-r`).
+    pub keyspace: Option<usize>,
+    pub tests: Vec<Test>,
+    /// Print one line per test instead of the full report (`-q`).
+    pub quiet: bool,
+    /// Print the results as CSV (`--csv`).
+    pub csv: bool,
+}
+
+impl Default for BenchmarkConfig {
+    fn default() -> Self {
+        Self {
+            host: "127.0.0.1".to_string(),
+            port: 6379,
+            clients: 50,
+            requests: 100_000,
+            pipeline: 1,
+            data_size: 3,
+            keyspace: None,
+            tests: Test::ALL.to_vec(),
+            quiet: false,
+            csv: false,
+        }
+    }
+}
+
+fn parse_count(flag: &str, value: Option<String>) -> Result<usize, String> {
+    match value.as_deref().map(str::parse) {
+        Some(Ok(n)) if n > 0 => Ok(n),
+        _ => Err(format!("{flag} needs a positive number")),
+    }
+}
+
+impl BenchmarkConfig {
+    pub fn from_args<I: IntoIterator<Item = String>>(args: I) -> Result<Self, String> {
+        let mut config = BenchmarkConfig::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-h" => config.host = args.next().ok_or("-h needs a hostname")?,
+                "-p" => {
+                    config.port = args
+                        .next()
+                        .and_then(|port| port.parse().ok())
+                        .ok_or("-p needs a port number")?
+                }
+                "-c" => config.clients = parse_count(&arg, args.next())?,
+                "-n" => config.requests = parse_count(&arg, args.next())?,
+                "-P" => config.pipeline = parse_count(&arg, args.next())?,
+                "-d" => config.data_size = parse_count(&arg, args.next())?,
+                "-r" => config.keyspace = Some(parse_count(&arg, args.next())?),
+                "-t" => {
+                    let names = args.next().ok_or("-t needs a list of tests")?;
+                    config.tests = names
+                        .split(',')
+                        .map(|name| {
+                            Test::from_name(name).ok_or_else(|| format!("Unknown test '{name}'"))
+                        })
+                        .collect::<Result<_, _>>()?;
+                }
+                "-q" => config.quiet = true,
+                "--csv" => config.csv = true,
+                _ => return Err(format!("Unrecognized option '{arg}'")),
+            }
+        }
+        Ok(config)
+    }
+}
+
+/// What one test measured.
+#[derive(Debug, Clone)]
+pub struct Report {
+    pub test: Test,
+    pub requests: usize,
+    /// Replies that were errors, e.g. for a command the server lacks.
+    pub errors: usize,
+    pub elapsed: Duration,
+    /// Latency of every request, sorted.
+    latencies: Vec<Duration>,
+}
+
+impl Report {
+    pub fn new(test: Test, errors: usize, elapsed: Duration, mut latencies: Vec<Duration>) -> Self {
+        latencies.sort_unstable();
+        Self {
+            test,
+            requests: latencies.len(),
+            errors,
+            elapsed,
+            latencies,
+        }
+    }
+
+    pub fn requests_per_sec(&self) -> f64 {
+        self.requests as f64 / self.elapsed.as_secs_f64()
+    }
+
+    /// Latency under which `percent` of requests completed.
+    pub fn percentile(&self, percent: f64) -> Duration {
+        let Some(last) = self.latencies.len().checked_sub(1) else {
+            return Duration::ZERO;
+        };
+        let rank = (percent / 100.0 * self.latencies.len() as f64).ceil() as usize;
+        self.latencies[rank.saturating_sub(1).min(last)]
+    }
+
+    pub fn average(&self) -> Duration {
+        let total: Duration = self.latencies.iter().sum();
+        total / self.latencies.len().max(1) as u32
+    }
+
+    pub const CSV_HEADER: &'static str =
+        "\"test\",\"rps\",\"avg_latency_ms\",\"min_latency_ms\",\"p50_latency_ms\",\"p95_latency_ms\",\"p99_latency_ms\",\"max_latency_ms\"";
+
+    pub fn to_csv(&self) -> String {
+        format!(
+            "\"{}\",\"{:.2}\",\"{:.3}\",\"{:.3}\",\"{:.3}\",\"{:.3}\",\"{:.3}\",\"{:.3}\"",
+            self.test.name(),
+            self.requests_per_sec(),
+            millis(self.average()),
+            millis(self.percentile(0.0)),
+            millis(self.percentile(50.0)),
+            millis(self.percentile(95.0)),
+            millis(self.percentile(99.0)),
+            millis(self.percentile(100.0)),
+        )
+    }
+
+    pub fn to_summary(&self) -> String {
+        let mut out = format!(
+            "{}: {:.2} requests per second, p50={:.3} msec",
+            self.test.name(),
+            self.requests_per_sec(),
+            millis(self.percentile(50.0)),
+        );
+        if self.errors > 0 {
+            let _ = write!(out, " ({} error replies)", self.errors);
+        }
+        out
+    }
+
+    /// The multi-line report `redis-benchmark` prints without `-q`.
+    pub fn to_text(&self, config: &BenchmarkConfig) -> String {
+        let mut out = String::new();
+        let _ = writeln!(out, "====== {} ======", self.test.name());
+        let _ = writeln!(
+            out,
+            "  {} requests completed in {:.2} seconds",
+            self.requests,
+            self.elapsed.as_secs_f64()
+        );
+        let _ = writeln!(out, "  {} parallel clients", config.clients);
+        let _ = writeln!(out, "  {} bytes payload", config.data_size);
+        let _ = writeln!(out, "  {} requests per pipeline", config.pipeline);
+        if self.errors > 0 {
+            let _ = writeln!(out, "  {} error replies", self.errors);
+        }
+        let _ = writeln!(out, "\nLatency by percentile distribution:");
+        for percent in [0.0, 50.0, 75.0, 90.0, 95.0, 99.0, 99.9, 100.0] {
+            let _ = writeln!(
+                out,
+                "{percent:7.3}% <= {:.3} milliseconds",
+                millis(self.percentile(percent))
+            );
+        }
+        let _ = writeln!(out, "\nSummary:");
+        let _ = writeln!(
+            out,
+            "  throughput summary: {:.2} requests per second",
+            self.requests_per_sec()
+        );
+        let _ = writeln!(
+            out,
+            "  latency summary (msec): avg={:.3} min={:.3} p50={:.3} p95={:.3} p99={:.3} max={:.3}",
+            millis(self.average()),
+            millis(self.percentile(0.0)),
+            millis(self.percentile(50.0)),
+            millis(self.percentile(95.0)),
+            millis(self.percentile(99.0)),
+            millis(self.percentile(100.0)),
+        );
+        out
+    }
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+/// Runs `test` against the server: `config.clients` connections share
+/// `config.requests` requests, each sending `config.pipeline` at a time and
+/// timing every request from when its batch was written to when its reply
+/// arrived, as `redis-benchmark` does.
+pub async fn run(config: &BenchmarkConfig, test: Test) -> io::Result<Report> {
+    let addr = format!("{}:{}", config.host, config.port);
+    let mut streams = Vec::with_capacity(config.clients);
+    for _ in 0..config.clients {
+        let stream = TcpStream::connect(&addr).await?;
+        stream.set_nodelay(true)?;
+        streams.push(stream);
+    }
+
+    let remaining = Arc::new(AtomicUsize::new(config.requests));
+    let value = vec![b'x'; config.data_size];
+    let start = Instant::now();
+    let clients: Vec<_> = streams
+        .into_iter()
+        .enumerate()
+        .map(|(id, stream)| {
+            let remaining = remaining.clone();
+            let value = value.clone();
+            let (pipeline, keyspace) = (config.pipeline, config.keyspace);
+            tokio::spawn(async move {
+                client(stream, id, test, &remaining, pipeline, keyspace, &value).await
+            })
+        })
+        .collect();
+
+    let (mut errors, mut latencies) = (0, Vec::with_capacity(config.requests));
+    for client in clients {
+        let (client_errors, client_latencies) = client.await.map_err(io::Error::other)??;
+        errors += client_errors;
+        latencies.extend(client_latencies);
+    }
+    Ok(Report::new(test, errors, start.elapsed(), latencies))
+}
+
+/// One connection's share of a test: returns its error replies and the
+/// latency of each request.
+async fn client(
+    mut stream: TcpStream,
+    id: usize,
+    test: Test,
+    remaining: &AtomicUsize,
+    pipeline: usize,
+    keyspace: Option<usize>,
+    value: &[u8],
+) -> io::Result<(usize, Vec<Duration>)> {
+    // xorshift seeded per connection, so `-r` keys differ between clients
+    let mut seed = (id as u64 + 1).wrapping_mul(0x9E37_79B9_7F4A_7C15);
+    let mut next_key = || match keyspace {
+        Some(keyspace) => {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            (seed % keyspace as u64) as usize
+        }
+        None => 0,
+    };
+
+    let (mut request, mut reply) = (BytesMut::new(), BytesMut::with_capacity(16 * 1024));
+    let (mut errors, mut latencies) = (0, Vec::new());
+    loop {
+        let batch = remaining
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |left| {
+                (left > 0).then(|| left - pipeline.min(left))
+            })
+            .map_or(0, |left| pipeline.min(left));
+        if batch == 0 {
+            return Ok((errors, latencies));
+        }
+
+        request.clear();
+        for _ in 0..batch {
+            test.encode(&mut request, next_key(), value);
+        }
+        let sent = Instant::now();
+        stream.write_all(&request).await?;
+
+        let mut replies = 0;
+        while replies < batch {
+            match parse(&mut reply) {
+                Ok(ResponseValue::Error(_)) => errors += 1,
+                Ok(_) => {}
+                Err(BufParseError::Incomplete) => {
+                    if stream.read_buf(&mut reply).await? == 0 {
+                        return Err(io::ErrorKind::UnexpectedEof.into());
+                    }
+                    continue;
+                }
+                Err(err) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("{err:?}"),
+                    ))
+                }
+            }
+            replies += 1;
+            latencies.push(sent.elapsed());
+        }
+    }
+}
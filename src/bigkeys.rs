@@ -0,0 +1,162 @@
+//! `MEMORY BIGKEYS [count]`: the largest keys of each type, with their
+//! element counts and approximate bytes, like `redis-cli --bigkeys` without
+//! the round trips.
+//!
+//! Every worker walks its shard `SCAN_BUCKETS` buckets at a time, serving its
+//! mailbox in between, so a scan of a big shard never stalls the commands
+//! queued behind it; it replies once the walk is done, and the router merges
+//! the shards' lists.
+
+use bytes::Bytes;
+
+use crate::{
+    kv::{KvStore, ValueType, VALUE_TYPES},
+    message::{ResponseMessage, ResponseValue, WorkerMessage},
+};
+
+/// Keys listed per type when no count is given.
+pub const DEFAULT_COUNT: usize = 5;
+
+/// Buckets a worker walks per step of a scan.
+pub const SCAN_BUCKETS: usize = 100;
+
+/// The count `MEMORY BIGKEYS [count]` in `frame` asks for, or its error
+/// reply; `None` if `frame` is another command.
+pub fn requested_count(frame: &ResponseValue) -> Option<Result<usize, ResponseValue>> {
+    let ResponseValue::Array(Some(items)) = frame else {
+        return None;
+    };
+    match items.as_slice() {
+        [ResponseValue::BulkString(Some(cmd)), ResponseValue::BulkString(Some(sub)), args @ ..]
+            if cmd.eq_ignore_ascii_case(b"MEMORY") && sub.eq_ignore_ascii_case(b"BIGKEYS") =>
+        {
+            Some(match args {
+                [] => Ok(DEFAULT_COUNT),
+                [ResponseValue::BulkString(Some(count))] => std::str::from_utf8(count)
+                    .ok()
+                    .and_then(|count| count.parse().ok())
+                    .filter(|count| *count > 0)
+                    .ok_or_else(|| {
+                        ResponseValue::Error("ERR count must be a positive integer".into())
+                    }),
+                _ => Err(ResponseValue::Error(
+                    "ERR wrong number of arguments for 'memory|bigkeys' command".into(),
+                )),
+            })
+        }
+        _ => None,
+    }
+}
+
+struct BigKey {
+    key: Bytes,
+    elements: usize,
+    bytes: usize,
+}
+
+/// A `MEMORY BIGKEYS` in progress on one worker.
+pub struct BigKeysScan {
+    msg: WorkerMessage,
+    count: usize,
+    cursor: usize,
+    /// The largest keys of each type so far, indexed by `ValueType`,
+    /// largest first.
+    largest: [Vec<BigKey>; VALUE_TYPES],
+}
+
+impl BigKeysScan {
+    /// Starts the scan `msg` asks for, keeping it to reply to at the end.
+    pub fn new(msg: WorkerMessage, count: usize) -> Self {
+        Self {
+            msg,
+            count,
+            cursor: 0,
+            largest: Default::default(),
+        }
+    }
+
+    /// Walks the next `SCAN_BUCKETS` buckets of `kv`, returning whether the
+    /// whole shard has been walked.
+    pub fn step(&mut self, kv: &mut KvStore) -> bool {
+        let (count, largest) = (self.count, &mut self.largest);
+        let next = kv.scan(self.cursor, SCAN_BUCKETS, |key, value, bytes, _| {
+            let largest = &mut largest[value.value_type() as usize];
+            let elements = value.elements();
+            if largest.len() == count && largest.last().is_some_and(|last| last.bytes >= bytes) {
+                return;
+            }
+            // a key can be seen twice if the table was resized mid-walk
+            largest.retain(|big| big.key != *key);
+            let at = largest.partition_point(|big| big.bytes >= bytes);
+            largest.insert(
+                at,
+                BigKey {
+                    key: key.clone(),
+                    elements,
+                    bytes,
+                },
+            );
+            largest.truncate(count);
+        });
+        match next {
+            Some(cursor) => {
+                self.cursor = cursor;
+                false
+            }
+            None => true,
+        }
+    }
+
+    /// Replies with the largest keys found, by type.
+    pub fn finish(self) {
+        let entries = ValueType::ALL
+            .iter()
+            .zip(self.largest)
+            .flat_map(|(value_type, largest)| {
+                largest.into_iter().map(move |big| entry(*value_type, big))
+            })
+            .collect();
+        self.msg.tx.send(ResponseMessage {
+            seq: self.msg.seq,
+            response_value: ResponseValue::Array(Some(entries)),
+            trace: self.msg.trace,
+        });
+    }
+}
+
+/// `[type, key, elements, bytes]`.
+fn entry(value_type: ValueType, big: BigKey) -> ResponseValue {
+    ResponseValue::Array(Some(vec![
+        ResponseValue::BulkString(Some(value_type.name().into())),
+        ResponseValue::BulkString(Some(big.key)),
+        ResponseValue::Integer(big.elements as i64),
+        ResponseValue::Integer(big.bytes as i64),
+    ]))
+}
+
+/// Merges the shards' `entries` into the `count` largest of each type,
+/// types in `ValueType` order, largest first.
+pub fn merge(mut entries: Vec<ResponseValue>, count: usize) -> ResponseValue {
+    let rank = |entry: &ResponseValue| match entry {
+        ResponseValue::Array(Some(fields)) => match fields.as_slice() {
+            [ResponseValue::BulkString(Some(name)), _, _, ResponseValue::Integer(bytes)] => (
+                ValueType::ALL
+                    .iter()
+                    .position(|value_type| value_type.name().as_bytes() == name),
+                -bytes,
+            ),
+            _ => (None, 0),
+        },
+        _ => (None, 0),
+    };
+    entries.sort_by_key(rank);
+    let mut listed = [0; VALUE_TYPES];
+    entries.retain(|entry| match rank(entry).0 {
+        Some(value_type) => {
+            listed[value_type] += 1;
+            listed[value_type] <= count
+        }
+        None => false,
+    });
+    ResponseValue::Array(Some(entries))
+}
@@ -0,0 +1,44 @@
+use std::env;
+
+use rustis::benchmark::{run, BenchmarkConfig, Report};
+use tokio::runtime::Builder;
+
+fn main() {
+    let config = match BenchmarkConfig::from_args(env::args().skip(1)) {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("{err}");
+            eprintln!(
+                "Usage: rustis-benchmark [-h <host>] [-p <port>] [-c <clients>] [-n <requests>] \
+                 [-P <pipeline>] [-d <size>] [-r <keyspace>] [-t <tests>] [-q] [--csv]"
+            );
+            std::process::exit(1);
+        }
+    };
+
+    let runtime = Builder::new_multi_thread().enable_all().build().unwrap();
+    if config.csv {
+        println!("{}", Report::CSV_HEADER);
+    }
+    for &test in &config.tests {
+        let report = match runtime.block_on(run(&config, test)) {
+            Ok(report) => report,
+            Err(err) => {
+                eprintln!(
+                    "{}: could not benchmark {}:{}: {err}",
+                    test.name(),
+                    config.host,
+                    config.port
+                );
+                std::process::exit(1);
+            }
+        };
+        if config.csv {
+            println!("{}", report.to_csv());
+        } else if config.quiet {
+            println!("{}", report.to_summary());
+        } else {
+            println!("{}", report.to_text(&config));
+        }
+    }
+}
@@ -0,0 +1,76 @@
+use std::{
+    env, fs,
+    io::{self, Write},
+    path::PathBuf,
+};
+
+use rustis::aof;
+
+fn main() {
+    let mut fix = false;
+    let mut path = None;
+    for arg in env::args().skip(1) {
+        match arg.as_str() {
+            "--fix" => fix = true,
+            _ if path.is_none() && !arg.starts_with('-') => path = Some(PathBuf::from(arg)),
+            _ => usage(&format!("Unrecognized option '{arg}'")),
+        }
+    }
+    let Some(path) = path else {
+        usage("No AOF file given");
+    };
+
+    let data = fs::read(&path).unwrap_or_else(|err| {
+        eprintln!("Can't read {}: {err}", path.display());
+        std::process::exit(1);
+    });
+    let check = aof::check(&data);
+    if let Some(err) = &check.error {
+        println!("0x{:08x}: {}", err.offset, err.message);
+    }
+    println!(
+        "AOF analyzed: filename={}, size={}, ok_up_to={}, ok_up_to_line={}, diff={}",
+        path.display(),
+        check.size,
+        check.ok_up_to,
+        check.ok_up_to_line,
+        check.diff()
+    );
+    if check.is_valid() {
+        println!("AOF {} is valid", path.display());
+        return;
+    }
+    if !fix {
+        println!(
+            "AOF {} is not valid. Use the --fix option to try fixing it.",
+            path.display()
+        );
+        std::process::exit(1);
+    }
+
+    println!(
+        "This will shrink the AOF from {} bytes, with {} bytes, to {} bytes",
+        check.size,
+        check.diff(),
+        check.ok_up_to
+    );
+    print!("Continue? [y/N]: ");
+    let _ = io::stdout().flush();
+    let mut answer = String::new();
+    let _ = io::stdin().read_line(&mut answer);
+    if !answer.trim().eq_ignore_ascii_case("y") {
+        println!("Aborting...");
+        std::process::exit(1);
+    }
+    if let Err(err) = aof::truncate(&path, check.ok_up_to) {
+        println!("Failed to truncate AOF: {err}");
+        std::process::exit(1);
+    }
+    println!("Successfully truncated AOF {}", path.display());
+}
+
+fn usage(err: &str) -> ! {
+    eprintln!("{err}");
+    eprintln!("Usage: rustis-check-aof [--fix] <file.aof>");
+    std::process::exit(1);
+}
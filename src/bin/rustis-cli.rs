@@ -0,0 +1,295 @@
+use std::{
+    env,
+    io::{self, BufRead, BufReader, IsTerminal, Write},
+    path::PathBuf,
+};
+
+use rustis::{
+    cli::{format_reply, split_line, CliConfig, ClusterConnection, Connection, Output},
+    export::{self, Format, Importer, Writer},
+    message::ResponseValue,
+};
+use rustyline::{error::ReadlineError, DefaultEditor};
+
+/// Kept in the home directory, like `redis-cli`'s `.rediscli_history`.
+const HISTORY_FILE: &str = ".rustiscli_history";
+
+fn main() {
+    let config = match CliConfig::from_args(env::args().skip(1)) {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("{err}");
+            eprintln!(
+                "Usage: rustis-cli [-h <host>] [-p <port>] [-a <password>] [-n <db>] [-c] \
+                 [--raw|--no-raw] [--pipe] [--export [--pattern <pattern>]] [--import] \
+                 [--csv] [cmd [arg ...]]"
+            );
+            std::process::exit(1);
+        }
+    };
+    let output = config.output.unwrap_or(if io::stdout().is_terminal() {
+        Output::Formatted
+    } else {
+        Output::Raw
+    });
+
+    let format = match config.csv {
+        true => Format::Csv,
+        false => Format::Json,
+    };
+    let ok = if config.pipe {
+        mass_insert(&config)
+    } else if config.export {
+        export(&config, format)
+    } else if config.import {
+        import(&config, format)
+    } else if !config.command.is_empty() {
+        let args: Vec<Vec<u8>> = config
+            .command
+            .iter()
+            .map(|arg| arg.clone().into())
+            .collect();
+        connect_client(&config).is_some_and(|mut client| run(&mut client, &args, output))
+    } else if io::stdin().is_terminal() {
+        repl(&config, output);
+        true
+    } else {
+        pipe(&config, output)
+    };
+    std::process::exit(if ok { 0 } else { 1 });
+}
+
+fn connect<T>(config: &CliConfig, open: fn(&CliConfig) -> io::Result<T>) -> Option<T> {
+    match open(config) {
+        Ok(connection) => Some(connection),
+        Err(err) => {
+            eprintln!(
+                "Could not connect to rustis at {}:{}: {err}",
+                config.host, config.port
+            );
+            None
+        }
+    }
+}
+
+/// Where commands typed or piped in go: the server, or with `-c` the
+/// nodes of a cluster.
+enum Client {
+    Node(Connection),
+    Cluster(ClusterConnection),
+}
+
+impl Client {
+    /// Sends a command and waits for its reply; on a terminal, says where
+    /// a cluster redirected it, as `redis-cli -c` does.
+    fn command(&mut self, args: &[Vec<u8>], output: Output) -> io::Result<ResponseValue> {
+        match self {
+            Client::Node(connection) => connection.command(args),
+            Client::Cluster(cluster) => cluster.command(args, |redirect| {
+                if output == Output::Formatted {
+                    println!(
+                        "-> Redirected to slot [{}] located at {}:{}",
+                        redirect.slot, redirect.host, redirect.port
+                    );
+                }
+            }),
+        }
+    }
+
+    /// The prompt, naming the node that served the last reply.
+    fn prompt(&self, config: &CliConfig) -> String {
+        match self {
+            Client::Node(_) => config.prompt(),
+            Client::Cluster(cluster) => cluster.config().prompt(),
+        }
+    }
+}
+
+fn connect_client(config: &CliConfig) -> Option<Client> {
+    match config.cluster {
+        true => connect(config, ClusterConnection::open).map(Client::Cluster),
+        false => connect(config, Connection::open).map(Client::Node),
+    }
+}
+
+fn print(reply: &ResponseValue, output: Output) {
+    let mut line = format_reply(reply, output);
+    line.push(b'\n');
+    let _ = io::stdout().lock().write_all(&line);
+}
+
+/// Runs one command and prints its reply; false if it failed.
+fn run(client: &mut Client, args: &[Vec<u8>], output: Output) -> bool {
+    match client.command(args, output) {
+        Ok(reply) => {
+            print(&reply, output);
+            !matches!(reply, ResponseValue::Error(_))
+        }
+        Err(err) => {
+            eprintln!("Error: {err}");
+            false
+        }
+    }
+}
+
+/// `--pipe`: streams stdin to the server and reports like `redis-cli
+/// --pipe`; false if any command failed.
+fn mass_insert(config: &CliConfig) -> bool {
+    let Some(mut connection) = connect(config, Connection::open) else {
+        return false;
+    };
+    let report = connection.pipe(
+        io::stdin(),
+        |message| eprintln!("{}", String::from_utf8_lossy(message)),
+        || eprintln!("All data transferred. Waiting for the last reply..."),
+    );
+    match report {
+        Ok(report) => {
+            eprintln!("Last reply received from server.");
+            eprintln!("errors: {}, replies: {}", report.errors, report.replies);
+            report.errors == 0
+        }
+        Err(err) => {
+            eprintln!("Error: {err}");
+            false
+        }
+    }
+}
+
+/// `--export`: writes the keyspace to stdout; false if it failed.
+fn export(config: &CliConfig, format: Format) -> bool {
+    let Some(mut connection) = connect(config, Connection::open) else {
+        return false;
+    };
+    let mut writer = Writer::new(io::stdout().lock(), format);
+    let exported = export::export(
+        &mut connection,
+        config.pattern.as_deref(),
+        &mut writer,
+        |skipped| eprintln!("{skipped}"),
+    );
+    match exported {
+        Ok(keys) => {
+            eprintln!("keys: {keys}");
+            true
+        }
+        Err(err) => {
+            eprintln!("Error: {err}");
+            false
+        }
+    }
+}
+
+/// `--import`: streams the dump on stdin to the server; false if it failed
+/// or any command did.
+fn import(config: &CliConfig, format: Format) -> bool {
+    let Some(mut connection) = connect(config, Connection::open) else {
+        return false;
+    };
+    let mut importer = Importer::new(BufReader::new(io::stdin()), format);
+    let report = connection.pipe(
+        &mut importer,
+        |message| eprintln!("{}", String::from_utf8_lossy(message)),
+        || {},
+    );
+    match report {
+        Ok(report) => {
+            eprintln!("keys: {}, errors: {}", importer.keys, report.errors);
+            if importer.ttls_dropped > 0 {
+                eprintln!(
+                    "TTLs dropped: {} (imported lists and sets don't expire)",
+                    importer.ttls_dropped
+                );
+            }
+            report.errors == 0
+        }
+        Err(err) => {
+            eprintln!("Error: {err}");
+            false
+        }
+    }
+}
+
+/// Runs the commands read from stdin, one per line, for scripts; false if
+/// any of them failed.
+fn pipe(config: &CliConfig, output: Output) -> bool {
+    let Some(mut client) = connect_client(config) else {
+        return false;
+    };
+    let mut ok = true;
+    for line in io::stdin().lock().lines() {
+        let Ok(line) = line else {
+            return false;
+        };
+        match split_line(&line) {
+            Some(args) if args.is_empty() => {}
+            Some(args) => ok &= run(&mut client, &args, output),
+            None => {
+                eprintln!("Invalid argument(s)");
+                ok = false;
+            }
+        }
+    }
+    ok
+}
+
+/// The interactive prompt, with history kept across sessions. A lost
+/// connection is opened again by the next command.
+fn repl(config: &CliConfig, output: Output) {
+    let mut editor = match DefaultEditor::new() {
+        Ok(editor) => editor,
+        Err(err) => {
+            eprintln!("Can't read from the terminal: {err}");
+            return;
+        }
+    };
+    let history = env::var_os("HOME").map(|home| PathBuf::from(home).join(HISTORY_FILE));
+    if let Some(path) = &history {
+        let _ = editor.load_history(path);
+    }
+
+    let mut client = connect_client(config);
+    loop {
+        let prompt = match &client {
+            Some(client) => client.prompt(config),
+            None => "not connected> ".to_string(),
+        };
+        let line = match editor.readline(&prompt) {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted | ReadlineError::Eof) => break,
+            Err(err) => {
+                eprintln!("Can't read from the terminal: {err}");
+                break;
+            }
+        };
+        let Some(args) = split_line(&line) else {
+            println!("Invalid argument(s)");
+            continue;
+        };
+        let Some(name) = args.first() else {
+            continue;
+        };
+        let _ = editor.add_history_entry(line.as_str());
+        if name.eq_ignore_ascii_case(b"quit") || name.eq_ignore_ascii_case(b"exit") {
+            break;
+        }
+
+        if client.is_none() {
+            client = connect_client(config);
+        }
+        let Some(open) = &mut client else {
+            continue;
+        };
+        match open.command(&args, output) {
+            Ok(reply) => print(&reply, output),
+            Err(err) => {
+                println!("Error: {err}");
+                client = None;
+            }
+        }
+    }
+
+    if let Some(path) = &history {
+        let _ = editor.save_history(path);
+    }
+}
@@ -0,0 +1,39 @@
+use std::{env, path::PathBuf};
+
+use rustis::rdb;
+
+fn main() {
+    let mut json = false;
+    let mut path = None;
+    for arg in env::args().skip(1) {
+        match arg.as_str() {
+            "--json" => json = true,
+            _ if path.is_none() && !arg.starts_with('-') => path = Some(PathBuf::from(arg)),
+            _ => usage(&format!("Unrecognized option '{arg}'")),
+        }
+    }
+    let Some(path) = path else {
+        usage("No snapshot file given");
+    };
+
+    let snapshot = match rdb::load(&path) {
+        Ok(snapshot) => snapshot,
+        Err(err) => {
+            eprintln!("Can't read {}: {err}", path.display());
+            std::process::exit(1);
+        }
+    };
+    if json {
+        println!("{}", snapshot.to_json());
+    } else {
+        println!("{}", snapshot.to_text());
+    }
+    // a broken file or checksum fails, for scripts auditing backups
+    std::process::exit(if snapshot.is_valid() { 0 } else { 1 });
+}
+
+fn usage(err: &str) -> ! {
+    eprintln!("{err}");
+    eprintln!("Usage: rustis-rdb [--json] <dump.rdb>");
+    std::process::exit(1);
+}
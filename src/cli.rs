@@ -0,0 +1,540 @@
+//! `rustis-cli`, a command line client like `redis-cli`: its options, a
+//! blocking connection to the server, and replies printed the way
+//! `redis-cli` prints them.
+//!
+//! Typed lines are split into arguments as inline requests are, so quoting
+//! works as in `redis-cli`. Replies are printed formatted for a person
+//! (`(integer) 1`, `"value"`, numbered array elements) or raw, one value per
+//! line for scripts; like `redis-cli`, the output is raw unless it goes to a
+//! terminal, `--raw` and `--no-raw` deciding otherwise.
+//!
+//! `--pipe` is `redis-cli`'s mass insertion: stdin, already RESP, is
+//! streamed to the server as it is while the replies are read back and
+//! counted, then an ECHO of a random marker shows when the last one is in.
+//!
+//! `--export` writes the keyspace, or the keys matching `--pattern`, to
+//! stdout as JSON lines, or CSV with `--csv`, and `--import` reads such a
+//! dump from stdin back into the server; see `export`.
+//!
+//! `-c` follows cluster redirections as `redis-cli -c` does: a `-MOVED`
+//! reply is retried on the node it names, which is then remembered as the
+//! owner of the slot, and an `-ASK` one on the node it names after an
+//! `ASKING`, the node that sent it staying the owner. Commands are sent to
+//! the owner of their first argument's slot when it is known, to the node
+//! last used otherwise.
+
+use std::{
+    collections::HashMap,
+    hash::{BuildHasher, Hasher, RandomState},
+    io::{self, Read, Write},
+    net::{Shutdown, TcpStream},
+    thread,
+};
+
+use bytes::BytesMut;
+
+use crate::{
+    audit::quote,
+    message::ResponseValue,
+    parser::{self, BufParseError},
+};
+
+/// How replies are printed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Output {
+    /// As `redis-cli` does on a terminal.
+    Formatted,
+    /// Values only, for scripts (`--raw`).
+    Raw,
+}
+
+/// `rustis-cli` options, spelled like `redis-cli`'s.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CliConfig {
+    pub host: String,
+    pub port: u16,
+    /// Sent with AUTH once connected (`-a`).
+    pub password: Option<String>,
+    /// Database to SELECT once connected (`-n`).
+    pub db: u32,
+    /// `--raw` or `--no-raw`; otherwise raw unless printing to a terminal.
+    pub output: Option<Output>,
+    /// A command to run instead of reading them from the terminal or stdin.
+    pub command: Vec<String>,
+    /// Streams stdin to the server as raw RESP (`--pipe`).
+    pub pipe: bool,
+    /// Dumps the keyspace to stdout (`--export`).
+    pub export: bool,
+    /// Loads a dump from stdin (`--import`).
+    pub import: bool,
+    /// Keys `--export` writes (`--pattern`), as `MATCH` takes them.
+    pub pattern: Option<String>,
+    /// Dumps in CSV rather than JSON lines (`--csv`).
+    pub csv: bool,
+    /// Follows cluster redirections (`-c`).
+    pub cluster: bool,
+}
+
+impl Default for CliConfig {
+    fn default() -> Self {
+        Self {
+            host: "127.0.0.1".to_string(),
+            port: 6379,
+            password: None,
+            db: 0,
+            output: None,
+            command: Vec::new(),
+            pipe: false,
+            export: false,
+            import: false,
+            pattern: None,
+            csv: false,
+            cluster: false,
+        }
+    }
+}
+
+impl CliConfig {
+    /// Options come first; the first argument that isn't one starts the
+    /// command.
+    pub fn from_args<I: IntoIterator<Item = String>>(args: I) -> Result<Self, String> {
+        let mut config = CliConfig::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-h" => config.host = args.next().ok_or("-h needs a hostname")?,
+                "-p" => {
+                    config.port = args
+                        .next()
+                        .and_then(|port| port.parse().ok())
+                        .ok_or("-p needs a port number")?
+                }
+                "-a" => config.password = Some(args.next().ok_or("-a needs a password")?),
+                "-n" => {
+                    config.db = args
+                        .next()
+                        .and_then(|db| db.parse().ok())
+                        .ok_or("-n needs a database number")?
+                }
+                "--raw" => config.output = Some(Output::Raw),
+                "--no-raw" => config.output = Some(Output::Formatted),
+                "--pipe" => config.pipe = true,
+                "--export" => config.export = true,
+                "--import" => config.import = true,
+                "--pattern" => {
+                    config.pattern = Some(args.next().ok_or("--pattern needs a pattern")?)
+                }
+                "--csv" => config.csv = true,
+                "-c" => config.cluster = true,
+                _ if arg.starts_with('-') => return Err(format!("Unrecognized option '{arg}'")),
+                _ => {
+                    config.command.push(arg);
+                    config.command.extend(args.by_ref());
+                }
+            }
+        }
+        Ok(config)
+    }
+
+    /// What the interactive prompt shows, e.g. `127.0.0.1:6379[1]> `.
+    pub fn prompt(&self) -> String {
+        match self.db {
+            0 => format!("{}:{}> ", self.host, self.port),
+            db => format!("{}:{}[{db}]> ", self.host, self.port),
+        }
+    }
+}
+
+/// What a `--pipe` run got back.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PipeReport {
+    pub replies: usize,
+    pub errors: usize,
+}
+
+/// Splits a typed line into a command's arguments, with `redis-cli`'s
+/// quoting; `None` if its quotes are unbalanced.
+pub fn split_line(line: &str) -> Option<Vec<Vec<u8>>> {
+    parser::split_quoted_args(line.as_bytes()).ok()
+}
+
+/// A connection to the server, sending one command at a time.
+#[derive(Debug)]
+pub struct Connection {
+    stream: TcpStream,
+    buffer: BytesMut,
+}
+
+impl Connection {
+    /// Connects to the server `config` names, then authenticates and selects
+    /// its database if it asks to, failing if the server refuses either.
+    pub fn open(config: &CliConfig) -> io::Result<Self> {
+        let stream = TcpStream::connect((config.host.as_str(), config.port))?;
+        stream.set_nodelay(true)?;
+        let mut connection = Self {
+            stream,
+            buffer: BytesMut::with_capacity(16 * 1024),
+        };
+        if let Some(password) = &config.password {
+            connection.expect_ok(&[b"AUTH".to_vec(), password.clone().into_bytes()])?;
+        }
+        if config.db != 0 {
+            connection.expect_ok(&[b"SELECT".to_vec(), config.db.to_string().into_bytes()])?;
+        }
+        Ok(connection)
+    }
+
+    fn expect_ok(&mut self, args: &[Vec<u8>]) -> io::Result<()> {
+        match self.command(args)? {
+            ResponseValue::Error(message) => Err(io::Error::other(format!(
+                "{} failed: {}",
+                String::from_utf8_lossy(&args[0]),
+                String::from_utf8_lossy(&message)
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    /// Sends the command made of `args` and waits for its reply.
+    pub fn command(&mut self, args: &[Vec<u8>]) -> io::Result<ResponseValue> {
+        let frame = ResponseValue::Array(Some(
+            args.iter()
+                .map(|arg| ResponseValue::BulkString(Some(arg.clone().into())))
+                .collect(),
+        ));
+        let mut request = BytesMut::with_capacity(frame.encoded_len());
+        frame.serialize(&mut request);
+        self.stream.write_all(&request)?;
+        self.read_reply()
+    }
+
+    /// Streams `input`, commands as RESP, to the server while reading their
+    /// replies, passing error replies to `on_error` and calling `on_sent`
+    /// once all of `input` is written. Returns when the reply to the last
+    /// command is in.
+    pub fn pipe<R: Read + Send>(
+        &mut self,
+        mut input: R,
+        mut on_error: impl FnMut(&[u8]),
+        on_sent: impl FnOnce() + Send,
+    ) -> io::Result<PipeReport> {
+        let marker = format!(
+            "{:016x}{:016x}",
+            RandomState::new().build_hasher().finish(),
+            RandomState::new().build_hasher().finish()
+        );
+        let mut writer = self.stream.try_clone()?;
+        let echo = format!("*2\r\n$4\r\nECHO\r\n${}\r\n{marker}\r\n", marker.len());
+
+        thread::scope(|scope| {
+            let sending = scope.spawn(move || -> io::Result<()> {
+                let sent = io::copy(&mut input, &mut writer)
+                    .and_then(|_| writer.write_all(echo.as_bytes()));
+                if sent.is_err() {
+                    // the reader is waiting on the marker, which won't come
+                    let _ = writer.shutdown(Shutdown::Both);
+                }
+                sent?;
+                on_sent();
+                Ok(())
+            });
+
+            let mut report = PipeReport::default();
+            let read = loop {
+                match self.read_reply() {
+                    Ok(ResponseValue::BulkString(Some(reply))) if reply == marker.as_bytes() => {
+                        break Ok(report);
+                    }
+                    Ok(reply) => {
+                        report.replies += 1;
+                        if let ResponseValue::Error(message) = &reply {
+                            report.errors += 1;
+                            on_error(message);
+                        }
+                    }
+                    Err(err) => break Err(err),
+                }
+            };
+            if read.is_err() {
+                // unblocks the sender if the server stopped reading
+                let _ = self.stream.shutdown(Shutdown::Both);
+            }
+            // an error writing explains a failed read better
+            sending.join().expect("the sending thread doesn't panic")?;
+            read
+        })
+    }
+
+    fn read_reply(&mut self) -> io::Result<ResponseValue> {
+        let mut chunk = [0; 16 * 1024];
+        loop {
+            match parser::parse(&mut self.buffer) {
+                Ok(reply) => return Ok(reply),
+                Err(BufParseError::Incomplete) => {}
+                Err(err) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("invalid reply: {err:?}"),
+                    ));
+                }
+            }
+            let read = self.stream.read(&mut chunk)?;
+            if read == 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "Server closed the connection",
+                ));
+            }
+            self.buffer.extend_from_slice(&chunk[..read]);
+        }
+    }
+}
+
+/// Number of hash slots of a cluster.
+pub const CLUSTER_SLOTS: u16 = 16384;
+
+/// Redirections a command may go through before `ClusterConnection` gives
+/// up on it, as a guard against nodes sending it around in circles.
+pub const MAX_REDIRECTS: usize = 16;
+
+/// The hash slot of `key`, as Redis Cluster computes it: CRC16 of the key,
+/// or of its hash tag, the part between the first `{` and the next `}` if
+/// that isn't empty.
+pub fn key_slot(key: &[u8]) -> u16 {
+    let tag = key
+        .iter()
+        .position(|&b| b == b'{')
+        .and_then(|open| {
+            let rest = &key[open + 1..];
+            let close = rest.iter().position(|&b| b == b'}')?;
+            (close > 0).then(|| &rest[..close])
+        })
+        .unwrap_or(key);
+    crc16(tag) % CLUSTER_SLOTS
+}
+
+/// CRC-16/XMODEM, the checksum Redis Cluster hashes keys with.
+fn crc16(data: &[u8]) -> u16 {
+    let mut crc: u16 = 0;
+    for &byte in data {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+/// A `-MOVED` or `-ASK` reply: the slot of the command's key is served by
+/// another node, for good or, with `ask`, for this command only.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Redirect {
+    pub slot: u16,
+    pub host: String,
+    pub port: u16,
+    pub ask: bool,
+}
+
+impl Redirect {
+    /// Parses `MOVED <slot> <host>:<port>` and `ASK <slot> <host>:<port>`;
+    /// `None` for any other error.
+    pub fn parse(message: &[u8]) -> Option<Self> {
+        let message = std::str::from_utf8(message).ok()?;
+        let mut parts = message.split(' ');
+        let ask = match parts.next()? {
+            "MOVED" => false,
+            "ASK" => true,
+            _ => return None,
+        };
+        let slot = parts.next()?.parse().ok()?;
+        let (host, port) = parts.next()?.rsplit_once(':')?;
+        if parts.next().is_some() {
+            return None;
+        }
+        Some(Self {
+            slot,
+            host: host.to_string(),
+            port: port.parse().ok()?,
+            ask,
+        })
+    }
+}
+
+/// Connections to the nodes of a cluster, for `-c`: sends each command to
+/// the node that serves its key and follows redirections to the others.
+#[derive(Debug)]
+pub struct ClusterConnection {
+    /// The options, `host` and `port` naming the node that served the last
+    /// reply.
+    config: CliConfig,
+    nodes: HashMap<(String, u16), Connection>,
+    /// Owners of slots, as `-MOVED` replies told them or `-ASK` replies
+    /// showed them.
+    slots: HashMap<u16, (String, u16)>,
+}
+
+impl ClusterConnection {
+    /// Connects to the node `config` names, as `Connection::open` does.
+    pub fn open(config: &CliConfig) -> io::Result<Self> {
+        let connection = Connection::open(config)?;
+        Ok(Self {
+            config: config.clone(),
+            nodes: HashMap::from([((config.host.clone(), config.port), connection)]),
+            slots: HashMap::new(),
+        })
+    }
+
+    /// The options, with `host` and `port` set to the node that served the
+    /// last reply, so its `prompt` shows where commands go.
+    pub fn config(&self) -> &CliConfig {
+        &self.config
+    }
+
+    /// Sends the command made of `args` to the node serving its first
+    /// argument's slot and waits for the reply, following up to
+    /// `MAX_REDIRECTS` redirections and calling `on_redirect` for each.
+    pub fn command(
+        &mut self,
+        args: &[Vec<u8>],
+        mut on_redirect: impl FnMut(&Redirect),
+    ) -> io::Result<ResponseValue> {
+        let mut node = match args.get(1) {
+            Some(key) => self.slots.get(&key_slot(key)).cloned(),
+            None => None,
+        }
+        .unwrap_or_else(|| (self.config.host.clone(), self.config.port));
+        let mut asking = false;
+        for _ in 0..=MAX_REDIRECTS {
+            let connection = self.node(&node)?;
+            if asking {
+                connection.expect_ok(&[b"ASKING".to_vec()])?;
+            }
+            let reply = connection.command(args)?;
+            (self.config.host, self.config.port) = node.clone();
+            let redirect = match &reply {
+                ResponseValue::Error(message) => Redirect::parse(message),
+                _ => None,
+            };
+            let Some(redirect) = redirect else {
+                return Ok(reply);
+            };
+            on_redirect(&redirect);
+            let to = (redirect.host, redirect.port);
+            // only the owner of a slot sends an ASK for it
+            let owner = if redirect.ask { &node } else { &to };
+            self.slots.insert(redirect.slot, owner.clone());
+            node = to;
+            asking = redirect.ask;
+        }
+        Err(io::Error::other("Too many cluster redirections"))
+    }
+
+    /// The connection to `node`, opened if there isn't one yet.
+    fn node(&mut self, node: &(String, u16)) -> io::Result<&mut Connection> {
+        if !self.nodes.contains_key(node) {
+            let config = CliConfig {
+                host: node.0.clone(),
+                port: node.1,
+                ..self.config.clone()
+            };
+            self.nodes.insert(node.clone(), Connection::open(&config)?);
+        }
+        Ok(self.nodes.get_mut(node).expect("inserted above"))
+    }
+}
+
+/// `reply` as `rustis-cli` prints it, without the final newline.
+pub fn format_reply(reply: &ResponseValue, output: Output) -> Vec<u8> {
+    let mut out = Vec::new();
+    match output {
+        Output::Formatted => {
+            let mut text = String::new();
+            format_tty(reply, 0, &mut text);
+            out.extend_from_slice(text.as_bytes());
+        }
+        Output::Raw => format_raw(reply, &mut out),
+    }
+    out
+}
+
+/// Appends `reply` to `out` like `redis-cli`, every line after the first
+/// indented by `indent` spaces, so array elements line up under their
+/// numbers.
+fn format_tty(reply: &ResponseValue, indent: usize, out: &mut String) {
+    match reply {
+        ResponseValue::SimpleString(s) => out.push_str(&String::from_utf8_lossy(s)),
+        ResponseValue::Error(message) => {
+            out.push_str("(error) ");
+            out.push_str(&String::from_utf8_lossy(message));
+        }
+        ResponseValue::Integer(n) => out.push_str(&format!("(integer) {n}")),
+        ResponseValue::BulkString(None) | ResponseValue::Array(None) => out.push_str("(nil)"),
+        ResponseValue::BulkString(Some(s)) => quote(out, s, usize::MAX),
+        ResponseValue::BigNumber(n) => {
+            out.push_str("(big number) ");
+            out.push_str(&String::from_utf8_lossy(n));
+        }
+        ResponseValue::VerbatimString { data, .. } => {
+            out.push_str(&String::from_utf8_lossy(data));
+        }
+        ResponseValue::Array(Some(items)) if items.is_empty() => out.push_str("(empty array)"),
+        ResponseValue::Array(Some(items)) => {
+            let width = items.len().to_string().len();
+            for (i, item) in items.iter().enumerate() {
+                let label = format!("{:>width$}) ", i + 1);
+                new_line(out, i, indent);
+                out.push_str(&label);
+                format_tty(item, indent + label.len(), out);
+            }
+        }
+        ResponseValue::Attribute { attrs, value } => {
+            // printed as a map, ahead of the value
+            let width = attrs.len().to_string().len();
+            for (i, (key, attr)) in attrs.iter().enumerate() {
+                let label = format!("{:>width$}# ", i + 1);
+                new_line(out, i, indent);
+                out.push_str(&label);
+                format_tty(key, indent + label.len(), out);
+                out.push_str(" => ");
+                format_tty(attr, indent + label.len(), out);
+            }
+            new_line(out, attrs.len(), indent);
+            format_tty(value, indent, out);
+        }
+    }
+}
+
+/// Starts the `i`th line of a multi-line reply.
+fn new_line(out: &mut String, i: usize, indent: usize) {
+    if i > 0 {
+        out.push('\n');
+        out.extend(std::iter::repeat_n(' ', indent));
+    }
+}
+
+/// Appends `reply` to `out` as `redis-cli --raw` does: values as they are,
+/// one per line, nil as an empty line.
+fn format_raw(reply: &ResponseValue, out: &mut Vec<u8>) {
+    match reply {
+        ResponseValue::SimpleString(s)
+        | ResponseValue::Error(s)
+        | ResponseValue::BulkString(Some(s))
+        | ResponseValue::BigNumber(s)
+        | ResponseValue::VerbatimString { data: s, .. } => out.extend_from_slice(s),
+        ResponseValue::Integer(n) => out.extend_from_slice(n.to_string().as_bytes()),
+        ResponseValue::BulkString(None) | ResponseValue::Array(None) => {}
+        ResponseValue::Array(Some(items)) => {
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    out.push(b'\n');
+                }
+                format_raw(item, out);
+            }
+        }
+        ResponseValue::Attribute { value, .. } => format_raw(value, out),
+    }
+}
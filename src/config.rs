@@ -0,0 +1,748 @@
+use std::{
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    path::PathBuf,
+    time::Duration,
+};
+
+#[cfg(feature = "lists")]
+use crate::list::LIST_MAX_LISTPACK_SIZE;
+#[cfg(feature = "sets")]
+use crate::set::SET_MAX_INTSET_ENTRIES;
+
+/// Default for how many messages a worker takes off its mailbox per wakeup
+/// (`--worker-batch-size`).
+pub const WORKER_BATCH_SIZE: usize = 128;
+
+/// Most `--expire-jitter-percent` may be, so a jittered TTL keeps at least
+/// half of what it was given and never runs out on the spot.
+pub const MAX_EXPIRE_JITTER_PERCENT: u64 = 50;
+
+/// Where a daemonized server writes its pid when no `--pidfile` is given.
+pub const DEFAULT_PIDFILE: &str = "/var/run/rustis.pid";
+
+/// How the server reports its state to a supervisor, like Redis' `supervised`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Supervised {
+    #[default]
+    No,
+    /// Send `READY=1`/`STOPPING=1` to systemd over `$NOTIFY_SOCKET`.
+    Systemd,
+    /// Systemd if `$NOTIFY_SOCKET` is set, otherwise nothing.
+    Auto,
+}
+
+impl Supervised {
+    fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "no" => Some(Supervised::No),
+            "systemd" => Some(Supervised::Systemd),
+            "auto" => Some(Supervised::Auto),
+            _ => None,
+        }
+    }
+
+    pub fn is_systemd(self) -> bool {
+        match self {
+            Supervised::No => false,
+            Supervised::Systemd => true,
+            Supervised::Auto => std::env::var_os("NOTIFY_SOCKET").is_some(),
+        }
+    }
+}
+
+/// What a worker does after a command panics, once it has logged a crash
+/// report and replied with an error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WorkerPanic {
+    /// Shut the whole server down, exiting with status 1.
+    #[default]
+    Shutdown,
+    /// Go on serving the mailbox with the same shard, dropping the keys of
+    /// the command, which it may have left half updated.
+    Restart,
+}
+
+impl WorkerPanic {
+    fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "shutdown" => Some(WorkerPanic::Shutdown),
+            "restart" => Some(WorkerPanic::Restart),
+            _ => None,
+        }
+    }
+}
+
+/// Least severe messages the server logs, like Redis' `loglevel`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub enum LogLevel {
+    Debug,
+    Verbose,
+    #[default]
+    Notice,
+    Warning,
+    /// Log nothing at all.
+    Nothing,
+}
+
+impl LogLevel {
+    fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "debug" => Some(LogLevel::Debug),
+            "verbose" => Some(LogLevel::Verbose),
+            "notice" => Some(LogLevel::Notice),
+            "warning" => Some(LogLevel::Warning),
+            "nothing" => Some(LogLevel::Nothing),
+            _ => None,
+        }
+    }
+}
+
+/// What to evict once `maxmemory` is reached, like Redis' `maxmemory-policy`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MaxmemoryPolicy {
+    /// Evict nothing; commands that may grow the dataset fail with `-OOM`.
+    #[default]
+    NoEviction,
+    AllKeysLru,
+    AllKeysLfu,
+    AllKeysRandom,
+    VolatileLru,
+    VolatileLfu,
+    VolatileRandom,
+    VolatileTtl,
+}
+
+impl MaxmemoryPolicy {
+    fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "noeviction" => Some(MaxmemoryPolicy::NoEviction),
+            "allkeys-lru" => Some(MaxmemoryPolicy::AllKeysLru),
+            "allkeys-lfu" => Some(MaxmemoryPolicy::AllKeysLfu),
+            "allkeys-random" => Some(MaxmemoryPolicy::AllKeysRandom),
+            "volatile-lru" => Some(MaxmemoryPolicy::VolatileLru),
+            "volatile-lfu" => Some(MaxmemoryPolicy::VolatileLfu),
+            "volatile-random" => Some(MaxmemoryPolicy::VolatileRandom),
+            "volatile-ttl" => Some(MaxmemoryPolicy::VolatileTtl),
+            _ => None,
+        }
+    }
+}
+
+/// Which deletions hand big values to the lazy-free thread instead of
+/// dropping them on the worker, like Redis' `lazyfree-lazy-*` options.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct LazyFree {
+    /// Keys evicted under `maxmemory`.
+    pub eviction: bool,
+    /// Keys removed because their TTL passed.
+    pub expire: bool,
+    /// Values the server replaces itself, such as a `SET` overwriting a key.
+    pub server_del: bool,
+    /// `DEL`, which then behaves like `UNLINK`.
+    pub user_del: bool,
+    /// `FLUSHALL`/`FLUSHDB` without `SYNC` or `ASYNC`.
+    pub user_flush: bool,
+}
+
+/// When and how hard workers defragment their shards, like Redis'
+/// `activedefrag` and `active-defrag-*` options.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ActiveDefrag {
+    pub enabled: bool,
+    /// Fragmentation, in bytes, below which nothing is done.
+    pub ignore_bytes: usize,
+    /// Fragmentation percentage at which defrag starts, with the least
+    /// effort.
+    pub threshold_lower: u32,
+    /// Fragmentation percentage at which it makes the most effort.
+    pub threshold_upper: u32,
+    /// Least and most CPU, in percent of each worker's time, spent on it.
+    pub cycle_min: u32,
+    pub cycle_max: u32,
+}
+
+impl Default for ActiveDefrag {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            ignore_bytes: 100 * 1024 * 1024,
+            threshold_lower: 10,
+            threshold_upper: 100,
+            cycle_min: 1,
+            cycle_max: 25,
+        }
+    }
+}
+
+/// Kinds of clients that get their own `client-output-buffer-limit`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClientClass {
+    Normal,
+    Replica,
+    PubSub,
+}
+
+impl ClientClass {
+    fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "normal" => Some(ClientClass::Normal),
+            "replica" | "slave" => Some(ClientClass::Replica),
+            "pubsub" => Some(ClientClass::PubSub),
+            _ => None,
+        }
+    }
+}
+
+/// How much unsent reply data a client may accumulate before it is dropped.
+/// A zero `hard` or `soft` disables that limit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct OutputBufferLimit {
+    /// Disconnect as soon as pending output reaches this many bytes.
+    pub hard: usize,
+    /// Disconnect once pending output stays at or above this many bytes...
+    pub soft: usize,
+    /// ...for this long.
+    pub soft_duration: Duration,
+}
+
+impl OutputBufferLimit {
+    pub fn new(hard: usize, soft: usize, soft_seconds: u64) -> Self {
+        Self {
+            hard,
+            soft,
+            soft_duration: Duration::from_secs(soft_seconds),
+        }
+    }
+
+    pub fn is_unlimited(&self) -> bool {
+        self.hard == 0 && self.soft == 0
+    }
+}
+
+/// `client-output-buffer-limit` for every client class, with Redis' defaults.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OutputBufferLimits {
+    pub normal: OutputBufferLimit,
+    pub replica: OutputBufferLimit,
+    pub pubsub: OutputBufferLimit,
+}
+
+impl Default for OutputBufferLimits {
+    fn default() -> Self {
+        Self {
+            normal: OutputBufferLimit::new(0, 0, 0),
+            replica: OutputBufferLimit::new(256 << 20, 64 << 20, 60),
+            pubsub: OutputBufferLimit::new(32 << 20, 8 << 20, 60),
+        }
+    }
+}
+
+impl OutputBufferLimits {
+    pub fn for_class(&self, class: ClientClass) -> OutputBufferLimit {
+        match class {
+            ClientClass::Normal => self.normal,
+            ClientClass::Replica => self.replica,
+            ClientClass::PubSub => self.pubsub,
+        }
+    }
+
+    fn set(&mut self, class: ClientClass, limit: OutputBufferLimit) {
+        match class {
+            ClientClass::Normal => self.normal = limit,
+            ClientClass::Replica => self.replica = limit,
+            ClientClass::PubSub => self.pubsub = limit,
+        }
+    }
+}
+
+/// A module to load at startup, from `--loadmodule "<path> [arg ...]"`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LoadModule {
+    pub path: PathBuf,
+    /// Passed to the module's init function.
+    pub args: Vec<String>,
+}
+
+impl LoadModule {
+    fn parse(value: &str) -> Option<Self> {
+        let mut parts = value.split_whitespace();
+        Some(Self {
+            path: PathBuf::from(parts.next()?),
+            args: parts.map(str::to_string).collect(),
+        })
+    }
+}
+
+/// Parses a byte count with an optional Redis-style unit: `1024`, `64kb`,
+/// `256mb`, `1gb` (powers of 1024) or `1k`, `1m`, `1g` (powers of 1000).
+pub fn parse_memory(value: &str) -> Option<usize> {
+    let lower = value.to_ascii_lowercase();
+    let split = lower
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(lower.len());
+    let (digits, unit) = lower.split_at(split);
+    let multiplier: usize = match unit {
+        "" | "b" => 1,
+        "k" => 1000,
+        "kb" => 1024,
+        "m" => 1000 * 1000,
+        "mb" => 1024 * 1024,
+        "g" => 1000 * 1000 * 1000,
+        "gb" => 1024 * 1024 * 1024,
+        _ => return None,
+    };
+    digits.parse::<usize>().ok()?.checked_mul(multiplier)
+}
+
+/// Parses a list of cores like Redis' `server_cpulist`: comma-separated
+/// cores (`3`), ranges (`0-7`) and ranges with a step (`0-7:2`, every other
+/// core), e.g. `0-3,8,10-15:2`.
+pub fn parse_cpulist(value: &str) -> Option<Vec<usize>> {
+    let mut cores = Vec::new();
+    for item in value.split(',') {
+        let (range, step) = match item.split_once(':') {
+            Some((range, step)) => (range, step.parse().ok().filter(|&step| step > 0)?),
+            None => (item, 1),
+        };
+        let (first, last): (usize, usize) = match range.split_once('-') {
+            Some((first, last)) => (first.parse().ok()?, last.parse().ok()?),
+            None => {
+                let core = range.parse().ok()?;
+                (core, core)
+            }
+        };
+        if first > last {
+            return None;
+        }
+        cores.extend((first..=last).step_by(step));
+    }
+    Some(cores)
+}
+
+/// Server settings, read from the command line.
+///
+/// The first positional argument is still accepted as the port so
+/// `cargo run --release -- 6380` keeps working.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+    pub bind: IpAddr,
+    pub port: u16,
+    /// Give every worker thread its own `SO_REUSEPORT` listener instead of
+    /// running a single accept loop on the main thread.
+    pub reuseport: bool,
+    /// `SO_KEEPALIVE` idle time in seconds for client sockets, 0 to disable.
+    pub tcp_keepalive: u64,
+    /// Close clients that send nothing for this many seconds, 0 to disable.
+    pub timeout: u64,
+    pub client_output_buffer_limit: OutputBufferLimits,
+    /// Largest unparsed request a single client may buffer, in bytes. Clients
+    /// going over it are disconnected. 0 disables the limit.
+    pub client_query_buffer_limit: usize,
+    /// Most clients connected at once; further connections are refused.
+    /// 0 disables the limit.
+    pub maxclients: usize,
+    /// Fork into the background and detach from the terminal.
+    pub daemonize: bool,
+    /// File to write the server's pid to.
+    pub pidfile: Option<PathBuf>,
+    /// File that the log, stdout and stderr are appended to.
+    pub logfile: Option<PathBuf>,
+    pub loglevel: LogLevel,
+    pub supervised: Supervised,
+    /// Worker threads, each owning a shard of the keyspace; 0 for one per
+    /// core left after `reserved_cores`.
+    pub workers: usize,
+    /// Threads accepting and serving client connections, each with its own
+    /// `SO_REUSEPORT` listener when there are more than one.
+    pub io_threads: usize,
+    /// Cores, counted from the first, that workers are neither pinned to
+    /// nor counted for, left to the OS, the network stack and the IO
+    /// threads.
+    pub reserved_cores: usize,
+    /// Cores workers are pinned to, in turn, instead of every core but the
+    /// reserved ones.
+    pub worker_cpulist: Option<Vec<usize>>,
+    /// Whether workers are pinned to cores at all.
+    pub worker_pinning: bool,
+    /// Whether a pinned worker prefers memory of its core's NUMA node, so
+    /// its shard is allocated there (Linux only).
+    pub worker_numa_local: bool,
+    /// Whether workers ask for the highest thread priority, which usually
+    /// takes elevated privileges.
+    pub worker_high_priority: bool,
+    pub worker_panic: WorkerPanic,
+    /// Most commands a worker takes off its mailbox and runs per wakeup.
+    pub worker_batch_size: usize,
+    /// Memory the dataset may use, in bytes, before `maxmemory_policy`
+    /// kicks in. 0 disables the limit.
+    pub maxmemory: usize,
+    pub maxmemory_policy: MaxmemoryPolicy,
+    /// Keys sampled per eviction; more is closer to true LRU/LFU but slower.
+    pub maxmemory_samples: usize,
+    /// How slowly LFU counters grow: higher takes more hits per step.
+    pub lfu_log_factor: u32,
+    /// Minutes without access after which a key's LFU counter drops by one,
+    /// 0 to never decay.
+    pub lfu_decay_time: u32,
+    pub lazyfree: LazyFree,
+    /// Largest list kept packed: positive counts elements, -1 to -5 mean
+    /// 4KB to 64KB.
+    #[cfg(feature = "lists")]
+    pub list_max_listpack_size: i64,
+    /// Largest set of integers kept as a sorted array.
+    #[cfg(feature = "sets")]
+    pub set_max_intset_entries: usize,
+    /// Most elements a command may build a reply of, or free, on a worker
+    /// in one go, to bound how long one command holds up its shard. It
+    /// counts elements, not time: bigger `LRANGE` and `SMEMBERS` replies are
+    /// refused, `SSCAN` counts capped and bigger synchronous flushes are done
+    /// on the lazy-free thread. 0 disables the budget.
+    pub command_budget: usize,
+    /// Most a TTL given as a duration is cut short by, in percent, picked at
+    /// random for each key so that keys set alike don't all expire at once.
+    /// 0 applies TTLs exactly; at most `MAX_EXPIRE_JITTER_PERCENT`.
+    pub expire_jitter_percent: u64,
+    /// Keys each worker's shard is sized for up front, so filling it doesn't
+    /// resize the table along the way.
+    pub shard_capacity: usize,
+    pub active_defrag: ActiveDefrag,
+    /// Whether workers time every command for INFO `latencystats`.
+    pub latency_tracking: bool,
+    /// Whether `GET` and `MGET` are answered from snapshots of the shards
+    /// the workers publish after every batch, rather than by the workers.
+    pub read_snapshots: bool,
+    /// File write commands are logged to, for auditing; none by default.
+    pub audit_log: Option<PathBuf>,
+    /// Size in bytes past which the audit log is rotated, 0 for never.
+    pub audit_log_max_size: usize,
+    /// Rotated audit logs kept, as `<file>.1` (newest) to `<file>.<n>`.
+    pub audit_log_files: usize,
+    /// StatsD server, as `host:port`, metrics are pushed to over UDP; none
+    /// by default.
+    pub statsd: Option<String>,
+    /// Seconds between two pushes of metrics to StatsD.
+    pub statsd_interval: u64,
+    /// Prepended, with a dot, to the name of every metric sent to StatsD.
+    pub statsd_prefix: String,
+    /// OTLP/HTTP endpoint sampled command traces are sent to.
+    pub otel_endpoint: String,
+    /// Share of commands traced, from 0 (none, the default) to 1 (all).
+    pub otel_sample_ratio: f64,
+    /// Modules loaded at startup, in order; `--loadmodule` may be repeated.
+    pub loadmodule: Vec<LoadModule>,
+    /// File of commands, as RESP, run before the server takes connections
+    /// (`--load`); see `load`.
+    pub load: Option<PathBuf>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            bind: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            port: 6379,
+            reuseport: false,
+            tcp_keepalive: 300,
+            timeout: 0,
+            client_output_buffer_limit: OutputBufferLimits::default(),
+            client_query_buffer_limit: 1024 * 1024 * 1024,
+            maxclients: 10000,
+            daemonize: false,
+            pidfile: None,
+            logfile: None,
+            loglevel: LogLevel::Notice,
+            supervised: Supervised::No,
+            workers: 0,
+            io_threads: 1,
+            reserved_cores: 0,
+            worker_cpulist: None,
+            worker_pinning: true,
+            worker_numa_local: false,
+            worker_high_priority: false,
+            worker_panic: WorkerPanic::Shutdown,
+            worker_batch_size: WORKER_BATCH_SIZE,
+            maxmemory: 0,
+            maxmemory_policy: MaxmemoryPolicy::NoEviction,
+            maxmemory_samples: 5,
+            lfu_log_factor: 10,
+            lfu_decay_time: 1,
+            lazyfree: LazyFree::default(),
+            #[cfg(feature = "lists")]
+            list_max_listpack_size: LIST_MAX_LISTPACK_SIZE,
+            #[cfg(feature = "sets")]
+            set_max_intset_entries: SET_MAX_INTSET_ENTRIES,
+            command_budget: 0,
+            expire_jitter_percent: 0,
+            shard_capacity: 0,
+            active_defrag: ActiveDefrag::default(),
+            latency_tracking: true,
+            read_snapshots: false,
+            audit_log: None,
+            audit_log_max_size: 64 * 1024 * 1024,
+            audit_log_files: 10,
+            statsd: None,
+            statsd_interval: 10,
+            statsd_prefix: "rustis".to_string(),
+            otel_endpoint: "http://localhost:4318/v1/traces".to_string(),
+            otel_sample_ratio: 0.0,
+            loadmodule: Vec::new(),
+            load: None,
+        }
+    }
+}
+
+fn parse_value<T: std::str::FromStr>(flag: &str, value: Option<String>) -> Result<T, String> {
+    let value = value.ok_or_else(|| format!("missing value for '{}'", flag))?;
+    value
+        .parse()
+        .map_err(|_| format!("invalid value for '{}': {}", flag, value))
+}
+
+/// Parses `<class> <hard> <soft> <soft seconds>`, e.g. `pubsub 32mb 8mb 60`.
+fn parse_output_buffer_limit(value: &str) -> Option<(ClientClass, OutputBufferLimit)> {
+    let parts: Vec<&str> = value.split_whitespace().collect();
+    let [class, hard, soft, seconds] = parts.as_slice() else {
+        return None;
+    };
+
+    Some((
+        ClientClass::from_name(class)?,
+        OutputBufferLimit::new(
+            parse_memory(hard)?,
+            parse_memory(soft)?,
+            seconds.parse().ok()?,
+        ),
+    ))
+}
+
+fn parse_yes_no(flag: &str, value: Option<String>) -> Result<bool, String> {
+    let value = value.ok_or_else(|| format!("missing value for '{}'", flag))?;
+    match value.to_ascii_lowercase().as_str() {
+        "yes" => Ok(true),
+        "no" => Ok(false),
+        _ => Err(format!("invalid value for '{}': {}", flag, value)),
+    }
+}
+
+fn parse_percent(flag: &str, value: Option<String>) -> Result<u32, String> {
+    let percent = parse_value(flag, value)?;
+    if !(1..=99).contains(&percent) {
+        return Err(format!("invalid value for '{}': {}", flag, percent));
+    }
+    Ok(percent)
+}
+
+fn parse_memory_value(flag: &str, value: Option<String>) -> Result<usize, String> {
+    let value = value.ok_or_else(|| format!("missing value for '{}'", flag))?;
+    parse_memory(&value).ok_or_else(|| format!("invalid value for '{}': {}", flag, value))
+}
+
+impl Config {
+    pub fn from_args<I: IntoIterator<Item = String>>(args: I) -> Result<Self, String> {
+        let mut config = Config::default();
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--port" => config.port = parse_value(&arg, args.next())?,
+                "--bind" => config.bind = parse_value(&arg, args.next())?,
+                "--reuseport" => config.reuseport = true,
+                "--tcp-keepalive" => config.tcp_keepalive = parse_value(&arg, args.next())?,
+                "--timeout" => config.timeout = parse_value(&arg, args.next())?,
+                "--daemonize" => config.daemonize = parse_yes_no(&arg, args.next())?,
+                "--pidfile" => config.pidfile = Some(parse_value(&arg, args.next())?),
+                "--logfile" => config.logfile = Some(parse_value(&arg, args.next())?),
+                "--loglevel" => {
+                    let value = args
+                        .next()
+                        .ok_or_else(|| format!("missing value for '{}'", arg))?;
+                    config.loglevel = LogLevel::from_name(&value)
+                        .ok_or_else(|| format!("invalid value for '{}': {}", arg, value))?;
+                }
+                "--supervised" => {
+                    let value = args
+                        .next()
+                        .ok_or_else(|| format!("missing value for '{}'", arg))?;
+                    config.supervised = Supervised::from_name(&value)
+                        .ok_or_else(|| format!("invalid value for '{}': {}", arg, value))?;
+                }
+                "--worker-panic" => {
+                    let value = args
+                        .next()
+                        .ok_or_else(|| format!("missing value for '{}'", arg))?;
+                    config.worker_panic = WorkerPanic::from_name(&value)
+                        .ok_or_else(|| format!("invalid value for '{}': {}", arg, value))?;
+                }
+                "--worker-threads" => config.workers = parse_value(&arg, args.next())?,
+                "--io-threads" => {
+                    config.io_threads = parse_value(&arg, args.next())?;
+                    if config.io_threads == 0 {
+                        return Err(format!("invalid value for '{}': 0", arg));
+                    }
+                }
+                "--reserved-cores" => config.reserved_cores = parse_value(&arg, args.next())?,
+                "--worker-cpulist" => {
+                    let value = args
+                        .next()
+                        .ok_or_else(|| format!("missing value for '{}'", arg))?;
+                    config.worker_cpulist = Some(
+                        parse_cpulist(&value)
+                            .ok_or_else(|| format!("invalid value for '{}': {}", arg, value))?,
+                    );
+                }
+                "--worker-pinning" => config.worker_pinning = parse_yes_no(&arg, args.next())?,
+                "--worker-numa-local" => {
+                    config.worker_numa_local = parse_yes_no(&arg, args.next())?
+                }
+                "--worker-high-priority" => {
+                    config.worker_high_priority = parse_yes_no(&arg, args.next())?
+                }
+                "--worker-batch-size" => {
+                    config.worker_batch_size = parse_value(&arg, args.next())?;
+                    if config.worker_batch_size == 0 {
+                        return Err(format!("invalid value for '{}': 0", arg));
+                    }
+                }
+                "--maxmemory" => config.maxmemory = parse_memory_value(&arg, args.next())?,
+                "--maxmemory-policy" => {
+                    let value = args
+                        .next()
+                        .ok_or_else(|| format!("missing value for '{}'", arg))?;
+                    config.maxmemory_policy = MaxmemoryPolicy::from_name(&value)
+                        .ok_or_else(|| format!("invalid value for '{}': {}", arg, value))?;
+                }
+                "--maxmemory-samples" => {
+                    config.maxmemory_samples = parse_value(&arg, args.next())?;
+                    if config.maxmemory_samples == 0 {
+                        return Err(format!("invalid value for '{}': 0", arg));
+                    }
+                }
+                "--lfu-log-factor" => config.lfu_log_factor = parse_value(&arg, args.next())?,
+                "--lfu-decay-time" => config.lfu_decay_time = parse_value(&arg, args.next())?,
+                "--lazyfree-lazy-eviction" => {
+                    config.lazyfree.eviction = parse_yes_no(&arg, args.next())?
+                }
+                "--lazyfree-lazy-expire" => {
+                    config.lazyfree.expire = parse_yes_no(&arg, args.next())?
+                }
+                "--lazyfree-lazy-server-del" => {
+                    config.lazyfree.server_del = parse_yes_no(&arg, args.next())?
+                }
+                "--lazyfree-lazy-user-del" => {
+                    config.lazyfree.user_del = parse_yes_no(&arg, args.next())?
+                }
+                "--lazyfree-lazy-user-flush" => {
+                    config.lazyfree.user_flush = parse_yes_no(&arg, args.next())?
+                }
+                "--activedefrag" => config.active_defrag.enabled = parse_yes_no(&arg, args.next())?,
+                "--active-defrag-ignore-bytes" => {
+                    config.active_defrag.ignore_bytes = parse_memory_value(&arg, args.next())?
+                }
+                "--active-defrag-threshold-lower" => {
+                    config.active_defrag.threshold_lower = parse_value(&arg, args.next())?
+                }
+                "--active-defrag-threshold-upper" => {
+                    config.active_defrag.threshold_upper = parse_value(&arg, args.next())?
+                }
+                "--active-defrag-cycle-min" => {
+                    config.active_defrag.cycle_min = parse_percent(&arg, args.next())?
+                }
+                "--active-defrag-cycle-max" => {
+                    config.active_defrag.cycle_max = parse_percent(&arg, args.next())?
+                }
+                #[cfg(feature = "lists")]
+                "--list-max-listpack-size" => {
+                    config.list_max_listpack_size = parse_value(&arg, args.next())?;
+                    if config.list_max_listpack_size == 0 {
+                        return Err(format!("invalid value for '{}': 0", arg));
+                    }
+                }
+                #[cfg(feature = "sets")]
+                "--set-max-intset-entries" => {
+                    config.set_max_intset_entries = parse_value(&arg, args.next())?;
+                }
+                "--command-budget" => config.command_budget = parse_value(&arg, args.next())?,
+                "--expire-jitter-percent" => {
+                    config.expire_jitter_percent = parse_value(&arg, args.next())?;
+                    if config.expire_jitter_percent > MAX_EXPIRE_JITTER_PERCENT {
+                        return Err(format!(
+                            "invalid value for '{}': {}",
+                            arg, config.expire_jitter_percent
+                        ));
+                    }
+                }
+                "--shard-capacity" => {
+                    config.shard_capacity = parse_value(&arg, args.next())?;
+                }
+                "--latency-tracking" => {
+                    config.latency_tracking = parse_yes_no(&arg, args.next())?;
+                }
+                "--read-snapshots" => {
+                    config.read_snapshots = parse_yes_no(&arg, args.next())?;
+                }
+                "--audit-log" => config.audit_log = Some(parse_value(&arg, args.next())?),
+                "--audit-log-max-size" => {
+                    config.audit_log_max_size = parse_memory_value(&arg, args.next())?
+                }
+                "--audit-log-files" => config.audit_log_files = parse_value(&arg, args.next())?,
+                "--statsd" => config.statsd = Some(parse_value(&arg, args.next())?),
+                "--statsd-interval" => {
+                    config.statsd_interval = parse_value(&arg, args.next())?;
+                    if config.statsd_interval == 0 {
+                        return Err(format!("invalid value for '{}': 0", arg));
+                    }
+                }
+                "--statsd-prefix" => config.statsd_prefix = parse_value(&arg, args.next())?,
+                "--otel-endpoint" => config.otel_endpoint = parse_value(&arg, args.next())?,
+                "--otel-sample-ratio" => {
+                    config.otel_sample_ratio = parse_value(&arg, args.next())?;
+                    if !(0.0..=1.0).contains(&config.otel_sample_ratio) {
+                        return Err(format!("'{}' must be between 0 and 1", arg));
+                    }
+                }
+                "--maxclients" => config.maxclients = parse_value(&arg, args.next())?,
+                "--client-query-buffer-limit" => {
+                    config.client_query_buffer_limit = parse_memory_value(&arg, args.next())?
+                }
+                "--client-output-buffer-limit" => {
+                    let value = args
+                        .next()
+                        .ok_or_else(|| format!("missing value for '{}'", arg))?;
+                    let (class, limit) = parse_output_buffer_limit(&value)
+                        .ok_or_else(|| format!("invalid value for '{}': {}", arg, value))?;
+                    config.client_output_buffer_limit.set(class, limit);
+                }
+                "--loadmodule" => {
+                    let value = args
+                        .next()
+                        .ok_or_else(|| format!("missing value for '{}'", arg))?;
+                    let module = LoadModule::parse(&value)
+                        .ok_or_else(|| format!("invalid value for '{}': {}", arg, value))?;
+                    config.loadmodule.push(module);
+                }
+                "--load" => config.load = Some(parse_value(&arg, args.next())?),
+                _ if !arg.starts_with("--") => config.port = parse_value("port", Some(arg))?,
+                _ => return Err(format!("unknown option '{}'", arg)),
+            }
+        }
+        // workers serve as soon as they're listening, before a file could load
+        if config.reuseport && config.load.is_some() {
+            return Err("'--load' can't be used with '--reuseport'".to_string());
+        }
+
+        Ok(config)
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        SocketAddr::new(self.bind, self.port)
+    }
+
+    /// The pidfile to write, if any. Like Redis, a daemonized server always
+    /// writes one, falling back to `DEFAULT_PIDFILE`.
+    pub fn pidfile_path(&self) -> Option<PathBuf> {
+        match &self.pidfile {
+            Some(path) => Some(path.clone()),
+            None if self.daemonize => Some(PathBuf::from(DEFAULT_PIDFILE)),
+            None => None,
+        }
+    }
+}
@@ -1,155 +1,864 @@
-use std::{env, sync::Arc};
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::{self, ErrorKind, IoSlice},
+    net::SocketAddr,
+    rc::Rc,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
-use bytes::BytesMut;
+use bytes::{Buf, Bytes, BytesMut};
+use socket2::{Domain, Protocol, SockRef, Socket, TcpKeepalive, Type};
 use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt},
-    net::{
-        tcp::{OwnedReadHalf, OwnedWriteHalf},
-        TcpListener, TcpStream,
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        Semaphore,
     },
-    sync::mpsc::{UnboundedReceiver, UnboundedSender},
-    task,
+    task, time,
 };
+use tracing::{debug, info, info_span, warn, Instrument};
 
 use crate::{
+    audit,
+    config::{ClientClass, Config, OutputBufferLimit},
+    daemon::{notify_supervisor, shutdown_signal},
+    hooks,
     message::{ResponseMessage, ResponseValue, WorkerMessage},
-    parser::{parse, BufParseError},
-    router::route_message,
+    parser::{parse_request, BufParseError},
+    router::route_command,
+    snapshot::Unanswered,
+    stats::{admit_client, ServerStats, STATS},
+    telemetry::{CommandTrace, Sample},
 };
 
-pub async fn spawn_io(router: Arc<Vec<UnboundedSender<WorkerMessage>>>) -> tokio::io::Result<()> {
-    let args: Vec<String> = env::args().collect();
-    let port = args
-        .get(1)
-        .and_then(|s| s.parse::<u16>().ok())
-        .unwrap_or(6379);
-    let addr = format!("127.0.0.1:{}", port);
-    let listener = TcpListener::bind(&addr).await?;
-    println!("Listening on port {port}");
+/// Initial capacity of a connection's read buffer, and the size oversized
+/// buffers are shrunk back to.
+pub const READ_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Read buffers that grew past this for a large request are reallocated at
+/// `READ_BUFFER_SIZE` once that request has been consumed.
+pub const READ_BUFFER_SHRINK_THRESHOLD: usize = 1024 * 1024;
+
+/// Replies that may sit in a connection's reply channel before the reader
+/// stops dispatching new commands for it.
+pub const REPLY_CHANNEL_CAPACITY: usize = 1024;
+
+/// Commands a connection may have outstanding, from the moment they are read
+/// until their reply is written. Past this the reader stops reading from the
+/// socket until replies drain, so one client pipelining millions of commands
+/// cannot take over the workers' mailboxes or pile up reply memory.
+pub const MAX_IN_FLIGHT: usize = 1024;
+
+/// The writer holds back (corks) small batches of replies while workers are
+/// still computing more for the same connection, so a pipeline is flushed with
+/// a few large writes instead of one syscall per wakeup. It never waits longer
+/// than this...
+pub const CORK_MAX_LATENCY: Duration = Duration::from_micros(100);
+
+/// ...and flushes right away once this many bytes are ready.
+pub const CORK_FLUSH_THRESHOLD: usize = 16 * 1024;
+
+/// How often a write the client isn't reading fast enough is checked
+/// against its output buffer limits, along with every reply that arrives
+/// meanwhile.
+pub const OUTPUT_LIMIT_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How long the accept loop pauses after a failed `accept`, e.g. when the
+/// process is out of file descriptors, before trying again.
+pub const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Sent to connections refused because `maxclients` clients are connected.
+pub(crate) const MAX_CLIENTS_REPLY: &[u8] = b"-ERR max number of clients reached\r\n";
+
+/// Serves clients on the current thread until a shutdown signal arrives.
+/// With `io-threads` above 1, as many threads share the address through
+/// `SO_REUSEPORT` listeners, the others spawned here, so the kernel spreads
+/// connections over them; they go down with the process.
+pub async fn spawn_io(
+    router: Arc<Vec<Sender<WorkerMessage>>>,
+    config: &Config,
+) -> tokio::io::Result<()> {
+    let listener = match config.io_threads {
+        1 => TcpListener::bind(config.addr()).await?,
+        _ => reuseport_listener(config.addr())?,
+    };
+    // the address bound, in case the port was 0
+    let addr = listener.local_addr()?;
+    let config = Arc::new(config.clone());
+    for io_thread in 1..config.io_threads {
+        let (router, config) = (router.clone(), config.clone());
+        std::thread::Builder::new()
+            .name(format!("io-{io_thread}"))
+            .spawn(move || serve_reuseport(io_thread, addr, router, config))?;
+    }
+    info!("Listening on port {}", config.port);
+    notify_supervisor(config.supervised, "READY=1");
 
     let local = task::LocalSet::new();
 
     local
-        .run_until(async move {
-            loop {
-                let (stream, _) = listener.accept().await.unwrap();
-
-                let router_clone = router.clone();
-                tokio::task::spawn_local(async move {
-                    if let Err(e) = handle_connection(stream, &router_clone).await {
-                        match e.kind() {
-                            std::io::ErrorKind::ConnectionReset => {}
-                            _ => eprintln!("Error handling connection: {:?}", e),
-                        }
-                    }
-                });
+        .run_until(async {
+            tokio::select! {
+                _ = accept_loop(listener, router, config.clone()) => {}
+                _ = shutdown_signal() => info!("Received shutdown signal, exiting"),
             }
         })
         .await;
     Ok(())
 }
 
-async fn handle_connection(
+/// Runs an extra IO thread of `spawn_io`: a `SO_REUSEPORT` listener on
+/// `addr` and the connections it accepts, forever.
+fn serve_reuseport(
+    io_thread: usize,
+    addr: SocketAddr,
+    router: Arc<Vec<Sender<WorkerMessage>>>,
+    config: Arc<Config>,
+) {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    let local = task::LocalSet::new();
+
+    local.block_on(&runtime, async move {
+        let listener = match reuseport_listener(addr) {
+            Ok(listener) => listener,
+            Err(err) => {
+                tracing::error!("IO thread {io_thread} failed to bind {addr}: {err}");
+                std::process::exit(1);
+            }
+        };
+        accept_loop(listener, router, config).await
+    })
+}
+
+/// Binds a listener with `SO_REUSEPORT` set, so several threads can each own
+/// one on the same address and let the kernel spread incoming connections.
+///
+/// Must be called from within a tokio runtime.
+pub fn reuseport_listener(addr: SocketAddr) -> tokio::io::Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    socket.set_reuse_address(true)?;
+    #[cfg(unix)]
+    socket.set_reuse_port(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(1024)?;
+
+    TcpListener::from_std(socket.into())
+}
+
+/// A connected client, as the audit log and connection hooks see it.
+#[derive(Clone, Copy, Debug)]
+pub struct Client {
+    /// Unique for the life of the process, as in `CLIENT ID`.
+    pub id: u64,
+    pub addr: SocketAddr,
+}
+
+/// Accepts connections forever, handling each one on the current `LocalSet`.
+/// Failed accepts are logged and retried, so running out of file descriptors
+/// slows the server down instead of taking it out.
+pub async fn accept_loop(
+    listener: TcpListener,
+    router: Arc<Vec<Sender<WorkerMessage>>>,
+    config: Arc<Config>,
+) {
+    let mut reserve = ReservedFd::new();
+    loop {
+        let (stream, addr) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(err) => {
+                if is_transient_accept_error(&err) {
+                    continue;
+                }
+                warn!("Error accepting connection: {}", err);
+                if is_resource_exhausted(&err) && reserve.release() {
+                    // take the pending connection off the backlog and close it
+                    // rather than leave the client hanging
+                    if let Ok(Ok(_)) = time::timeout(ACCEPT_BACKOFF, listener.accept()).await {
+                        ServerStats::incr(&STATS.rejected_connections, 1);
+                    }
+                    reserve.restore();
+                }
+                time::sleep(ACCEPT_BACKOFF).await;
+                continue;
+            }
+        };
+        let Some(slot) = admit_client(config.maxclients) else {
+            tokio::task::spawn_local(async move {
+                let mut stream = stream;
+                let _ = stream.write_all(MAX_CLIENTS_REPLY).await;
+            });
+            continue;
+        };
+
+        let client = Client {
+            id: slot.id(),
+            addr,
+        };
+        let span = info_span!("client", id = client.id, %addr);
+        if let Some(refusal) = hooks::connect(&client) {
+            span.in_scope(|| debug!("Connection refused by hook"));
+            tokio::task::spawn_local(async move {
+                let mut stream = stream;
+                let _ = stream.write_all(&refusal).await;
+            });
+            continue;
+        }
+
+        let router_clone = router.clone();
+        let config = config.clone();
+        tokio::task::spawn_local(
+            async move {
+                debug!("Accepted connection");
+                let result = handle_connection(stream, client, &router_clone, &config).await;
+                match &result {
+                    Ok(()) => debug!("Client closed connection"),
+                    Err(e) if e.kind() == io::ErrorKind::ConnectionReset => {
+                        debug!("Client reset connection")
+                    }
+                    Err(e) => warn!("Error handling connection: {:?}", e),
+                }
+                hooks::disconnect(&client, result.as_ref().err());
+                drop(slot);
+            }
+            .instrument(span),
+        );
+    }
+}
+
+/// Errors from `accept` that only concern the one connection being accepted,
+/// such as a client that reset before we got to it.
+pub fn is_transient_accept_error(err: &io::Error) -> bool {
+    matches!(
+        err.kind(),
+        ErrorKind::ConnectionAborted
+            | ErrorKind::ConnectionReset
+            | ErrorKind::Interrupted
+            | ErrorKind::WouldBlock
+    )
+}
+
+/// Whether `accept` failed because the process or system ran out of file
+/// descriptors or socket memory.
+pub fn is_resource_exhausted(err: &io::Error) -> bool {
+    #[cfg(unix)]
+    {
+        matches!(
+            err.raw_os_error(),
+            Some(libc::EMFILE | libc::ENFILE | libc::ENOBUFS | libc::ENOMEM)
+        )
+    }
+    #[cfg(not(unix))]
+    {
+        err.kind() == ErrorKind::OutOfMemory
+    }
+}
+
+/// A spare file descriptor kept open for when the process runs out. Closing it
+/// leaves room to accept one pending connection and close it straight away.
+pub(crate) struct ReservedFd(Option<File>);
+
+impl ReservedFd {
+    pub(crate) fn new() -> Self {
+        Self(Self::open())
+    }
+
+    fn open() -> Option<File> {
+        File::open(if cfg!(windows) { "NUL" } else { "/dev/null" }).ok()
+    }
+
+    /// Closes the spare descriptor, returning whether one was held.
+    pub(crate) fn release(&mut self) -> bool {
+        self.0.take().is_some()
+    }
+
+    /// Reopens the spare descriptor if it was released.
+    pub(crate) fn restore(&mut self) {
+        if self.0.is_none() {
+            self.0 = Self::open();
+        }
+    }
+}
+
+pub async fn handle_connection(
     stream: TcpStream,
-    router: &[UnboundedSender<WorkerMessage>],
+    client: Client,
+    router: &[Sender<WorkerMessage>],
+    config: &Config,
 ) -> tokio::io::Result<()> {
     stream.set_nodelay(true)?;
+    if config.tcp_keepalive > 0 {
+        set_keepalive(
+            SockRef::from(&stream),
+            Duration::from_secs(config.tcp_keepalive),
+        )?;
+    }
 
     let (read_half, write_half) = stream.into_split();
+    serve_stream(read_half, write_half, client, router, config).await
+}
 
-    let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+/// Serves a client over the two halves of any byte stream, the way
+/// `handle_connection` does over TCP; the simulation's in-memory
+/// connections come through here. The writer runs as a task of its own on
+/// the current `LocalSet`.
+pub async fn serve_stream<R, W>(
+    read_half: R,
+    write_half: W,
+    client: Client,
+    router: &[Sender<WorkerMessage>],
+    config: &Config,
+) -> tokio::io::Result<()>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin + 'static,
+{
+    let (tx, rx) = mpsc::channel(REPLY_CHANNEL_CAPACITY);
+    let in_flight = Rc::new(Semaphore::new(MAX_IN_FLIGHT));
+    let unanswered = Rc::new(Unanswered::new(router.len()));
 
-    tokio::task::spawn_local(async move { writer_task(write_half, rx).await });
+    let limit = OutputLimitTracker::new(
+        config
+            .client_output_buffer_limit
+            .for_class(ClientClass::Normal),
+    );
+    let writer_in_flight = in_flight.clone();
+    let writer_unanswered = unanswered.clone();
+    tokio::task::spawn_local(
+        async move {
+            let result =
+                writer_task(write_half, rx, limit, &writer_in_flight, &writer_unanswered).await;
+            // wakes a reader waiting on in-flight commands that will never be answered
+            writer_in_flight.close();
+            result
+        }
+        .in_current_span(),
+    );
 
-    reader_task(read_half, tx, router).await?;
+    reader_task(
+        read_half,
+        tx,
+        &in_flight,
+        &unanswered,
+        &client,
+        router,
+        config,
+    )
+    .await?;
 
     Ok(())
 }
 
-async fn writer_task(
-    mut write_half: OwnedWriteHalf,
-    mut rx: UnboundedReceiver<ResponseMessage>,
+/// Turns on TCP keepalive the way Redis does: first probe after `idle`, then
+/// every `idle / 3`, giving up after three unanswered probes.
+pub(crate) fn set_keepalive(stream: SockRef<'_>, idle: Duration) -> tokio::io::Result<()> {
+    let keepalive = TcpKeepalive::new().with_time(idle);
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    let keepalive = keepalive
+        .with_interval((idle / 3).max(Duration::from_secs(1)))
+        .with_retries(3);
+
+    stream.set_tcp_keepalive(&keepalive)
+}
+
+/// Puts replies back into request order. Workers answer out of order, so a
+/// reply is held here until every earlier sequence number has been written.
+/// The reader numbers a connection's commands from 1, the first written.
+///
+/// Sequence numbers are dense, so replies live in a ring indexed by their
+/// distance from the next one to write. No more than `MAX_IN_FLIGHT` replies
+/// are ever outstanding, so after warming up it never allocates.
+pub struct ReplyQueue {
+    next_seq: u64,
+    slots: VecDeque<Option<(ResponseValue, CommandTrace)>>,
+    /// Encoded size of the replies in `slots`, kept up to date so output
+    /// limits can be checked on every reply without walking the queue.
+    pending_bytes: usize,
+}
+
+impl Default for ReplyQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ReplyQueue {
+    pub fn new() -> Self {
+        Self {
+            next_seq: 1,
+            slots: VecDeque::with_capacity(MAX_IN_FLIGHT),
+            pending_bytes: 0,
+        }
+    }
+
+    pub fn insert(&mut self, msg: ResponseMessage) {
+        let Some(offset) = msg.seq.checked_sub(self.next_seq) else {
+            return; // already written, cannot happen with dense sequence numbers
+        };
+        let offset = offset as usize;
+        if offset >= self.slots.len() {
+            self.slots.resize_with(offset + 1, || None);
+        }
+        self.pending_bytes += msg.response_value.encoded_len();
+        self.slots[offset] = Some((msg.response_value, msg.trace));
+    }
+
+    /// Sequence number of the next reply to write; every one before it has
+    /// been.
+    pub fn next_seq(&self) -> u64 {
+        self.next_seq
+    }
+
+    /// Whether the next reply to write is in.
+    pub fn has_ready(&self) -> bool {
+        self.slots.front().is_some_and(Option::is_some)
+    }
+
+    /// Encoded size of the replies still waiting for an earlier one.
+    pub fn pending_bytes(&self) -> usize {
+        self.pending_bytes
+    }
+
+    /// Serializes every reply that is next in line, returning how many.
+    pub fn serialize_ready(&mut self, dst: &mut BytesMut, chunks: &mut Vec<Bytes>) -> usize {
+        let mut count = 0;
+        while let Some(slot) = self.slots.front_mut() {
+            let Some((response_value, trace)) = slot.take() else {
+                break; // still waiting on this one
+            };
+            self.slots.pop_front();
+            self.pending_bytes -= response_value.encoded_len();
+            let _serialize = trace.stage("serialize");
+            if let ResponseValue::Error(message) = &response_value {
+                STATS.record_error(message);
+            }
+            response_value.serialize_vectored(dst, chunks);
+            self.next_seq += 1;
+            count += 1;
+        }
+        count
+    }
+}
+
+/// Enforces a connection's `client-output-buffer-limit` on the reply bytes
+/// the writer has accumulated but not yet sent: those queued, and those of
+/// the write under way the client has yet to take.
+pub struct OutputLimitTracker {
+    limit: OutputBufferLimit,
+    soft_since: Option<Instant>,
+}
+
+impl OutputLimitTracker {
+    pub fn new(limit: OutputBufferLimit) -> Self {
+        Self {
+            limit,
+            soft_since: None,
+        }
+    }
+
+    pub fn is_unlimited(&self) -> bool {
+        self.limit.is_unlimited()
+    }
+
+    /// Whether a client with `pending` unsent bytes at `now` must be dropped.
+    pub fn exceeded(&mut self, pending: usize, now: Instant) -> bool {
+        if self.limit.hard > 0 && pending >= self.limit.hard {
+            return true;
+        }
+
+        if self.limit.soft > 0 && pending >= self.limit.soft {
+            let since = *self.soft_since.get_or_insert(now);
+            return now.duration_since(since) >= self.limit.soft_duration;
+        }
+
+        self.soft_since = None;
+        false
+    }
+
+    /// Checks everything the writer is holding on to for this connection:
+    /// `queue`, and `unwritten` bytes already taken out of it.
+    pub(crate) fn check(&mut self, queue: &ReplyQueue, unwritten: usize) -> bool {
+        if self.is_unlimited() {
+            return false;
+        }
+
+        let pending = queue.pending_bytes() + unwritten;
+        let exceeded = self.exceeded(pending, Instant::now());
+        if exceeded {
+            warn!(
+                "Client closed for overcoming of output buffer limits ({} bytes pending)",
+                pending
+            );
+        }
+        exceeded
+    }
+}
+
+async fn writer_task<W: AsyncWrite + Unpin>(
+    mut write_half: W,
+    mut rx: Receiver<ResponseMessage>,
+    mut limit: OutputLimitTracker,
+    in_flight: &Semaphore,
+    unanswered: &Unanswered,
 ) -> tokio::io::Result<()> {
-    let mut last_seq: u64 = 0;
-    let mut buffer = std::collections::BTreeMap::new();
+    let mut queue = ReplyQueue::new();
     let mut write_buffer = BytesMut::with_capacity(64 * 1024);
-    while let Some(first_message) = rx.recv().await {
-        // collect message from recv
-        buffer.insert(first_message.seq, first_message.response_value);
+    let mut chunks: Vec<Bytes> = Vec::new();
+    loop {
+        // replies taken in during the last write may be ready already
+        if !queue.has_ready() {
+            let Some(msg) = rx.recv().await else {
+                break;
+            };
+            queue.insert(msg);
+        }
+
+        let replies = cork(&mut rx, &mut queue, &mut write_buffer, &mut chunks).await;
+        unanswered.answered_below(queue.next_seq());
+
+        let unwritten = write_buffer.len() + chunks.iter().map(Bytes::len).sum::<usize>();
+        // dropping rx stops the reader too
+        if limit.check(&queue, unwritten) {
+            return Ok(());
+        }
+
+        let written = if !chunks.is_empty() {
+            // large bulk strings were kept out of write_buffer, flush everything vectored
+            if !write_buffer.is_empty() {
+                chunks.push(write_buffer.split().freeze());
+            }
+            let mut out = ChunkQueue::new(chunks.drain(..));
+            write_checked(&mut write_half, &mut out, &mut rx, &mut queue, &mut limit).await?
+        } else {
+            let mut out = &write_buffer[..];
+            let written =
+                write_checked(&mut write_half, &mut out, &mut rx, &mut queue, &mut limit).await?;
+            write_buffer.clear();
+            written
+        };
+        if !written {
+            return Ok(());
+        }
+        ServerStats::incr(&STATS.total_net_output_bytes, unwritten as u64);
+        in_flight.add_permits(replies);
+    }
+
+    // every sender is gone: the reader has stopped and all in-flight replies
+    // have been written, so close our side instead of waiting for the peer
+    write_half.shutdown().await?;
+    Ok(())
+}
+
+/// Writes all of `out`, taking the replies that arrive meanwhile into
+/// `queue`. Each of them, and every `OUTPUT_LIMIT_CHECK_INTERVAL`, checks
+/// `limit` against the queue plus what is left of `out`, so a client that
+/// stops reading is dropped rather than waited on. Returns false if it is
+/// to be dropped.
+async fn write_checked<W: AsyncWrite + Unpin, B: Buf>(
+    write_half: &mut W,
+    out: &mut B,
+    rx: &mut Receiver<ResponseMessage>,
+    queue: &mut ReplyQueue,
+    limit: &mut OutputLimitTracker,
+) -> io::Result<bool> {
+    if limit.is_unlimited() {
+        write_half.write_all_buf(out).await?;
+        return Ok(true);
+    }
+    let mut check = time::interval_at(
+        time::Instant::now() + OUTPUT_LIMIT_CHECK_INTERVAL,
+        OUTPUT_LIMIT_CHECK_INTERVAL,
+    );
+    let mut receiving = true;
+    while out.has_remaining() {
+        tokio::select! {
+            written = write_half.write_buf(out) => {
+                if written? == 0 {
+                    return Err(ErrorKind::WriteZero.into());
+                }
+                continue;
+            }
+            msg = rx.recv(), if receiving => match msg {
+                Some(msg) => queue.insert(msg),
+                None => receiving = false,
+            },
+            _ = check.tick() => {}
+        }
+        if limit.check(queue, out.remaining()) {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
 
+/// Serializes the replies that are next in line into `buffer` and `chunks`,
+/// then, while `should_cork`, waits on `rx` for more of them, for no longer
+/// than `CORK_MAX_LATENCY` in all. Returns how many replies were serialized.
+pub(crate) async fn cork(
+    rx: &mut Receiver<ResponseMessage>,
+    queue: &mut ReplyQueue,
+    buffer: &mut BytesMut,
+    chunks: &mut Vec<Bytes>,
+) -> usize {
+    let deadline = time::Instant::now() + CORK_MAX_LATENCY;
+    let mut replies = 0;
+    loop {
         // drain any message currently in channel
         while let Ok(msg) = rx.try_recv() {
-            buffer.insert(msg.seq, msg.response_value);
+            queue.insert(msg);
         }
 
         // write to write buffer
-        while let Some(response_value) = buffer.remove(&(last_seq + 1)) {
-            response_value.serialize(&mut write_buffer);
-            last_seq += 1;
+        replies += queue.serialize_ready(buffer, chunks);
+
+        if !should_cork(rx, buffer, chunks) {
+            return replies;
+        }
+        tokio::select! {
+            msg = rx.recv() => match msg {
+                Some(msg) => queue.insert(msg),
+                None => return replies,
+            },
+            _ = time::sleep_until(deadline) => return replies,
         }
+    }
+}
 
-        if !write_buffer.is_empty() {
-            write_half.write_all(&write_buffer).await?;
-            write_buffer.clear();
+/// Whether the writer should wait for more replies before flushing: some are
+/// still being computed by workers and what is buffered is small.
+fn should_cork(rx: &Receiver<ResponseMessage>, buffer: &BytesMut, chunks: &[Bytes]) -> bool {
+    // permits handed out to workers that have not sent their reply yet
+    let in_flight = rx
+        .max_capacity()
+        .saturating_sub(rx.capacity())
+        .saturating_sub(rx.len());
+
+    in_flight > 0 && chunks.is_empty() && buffer.len() < CORK_FLUSH_THRESHOLD
+}
+
+/// Output chunks waiting to be written, exposed as a `Buf` so tokio can hand
+/// them to the socket with a single vectored write.
+struct ChunkQueue {
+    chunks: VecDeque<Bytes>,
+    remaining: usize,
+}
+
+impl ChunkQueue {
+    fn new(chunks: impl IntoIterator<Item = Bytes>) -> Self {
+        let chunks: VecDeque<Bytes> = chunks.into_iter().filter(|c| !c.is_empty()).collect();
+        let remaining = chunks.iter().map(Bytes::len).sum();
+        Self { chunks, remaining }
+    }
+}
+
+impl Buf for ChunkQueue {
+    fn remaining(&self) -> usize {
+        self.remaining
+    }
+
+    fn chunk(&self) -> &[u8] {
+        self.chunks.front().map(|c| &c[..]).unwrap_or(&[])
+    }
+
+    fn chunks_vectored<'a>(&'a self, dst: &mut [IoSlice<'a>]) -> usize {
+        let mut n = 0;
+        for (slot, chunk) in dst.iter_mut().zip(self.chunks.iter()) {
+            *slot = IoSlice::new(chunk);
+            n += 1;
         }
+        n
+    }
+
+    fn advance(&mut self, mut cnt: usize) {
+        self.remaining -= cnt;
+        while cnt > 0 {
+            let front = self
+                .chunks
+                .front_mut()
+                .expect("advance past end of ChunkQueue");
+            if cnt < front.len() {
+                front.advance(cnt);
+                return;
+            }
+            cnt -= front.len();
+            self.chunks.pop_front();
+        }
+    }
+}
+
+/// Whether `value` is a request whose command name is `name`.
+fn is_command(value: &ResponseValue, name: &[u8]) -> bool {
+    match value {
+        ResponseValue::Array(Some(items)) => matches!(
+            items.first(),
+            Some(ResponseValue::BulkString(Some(cmd))) if cmd.eq_ignore_ascii_case(name)
+        ),
+        _ => false,
     }
-    Ok(())
 }
 
-async fn reader_task(
-    mut read_half: OwnedReadHalf,
-    tx: UnboundedSender<ResponseMessage>,
-    router: &[UnboundedSender<WorkerMessage>],
+async fn reader_task<R: AsyncRead + Unpin>(
+    mut read_half: R,
+    tx: Sender<ResponseMessage>,
+    in_flight: &Semaphore,
+    unanswered: &Unanswered,
+    client: &Client,
+    router: &[Sender<WorkerMessage>],
+    config: &Config,
 ) -> tokio::io::Result<()> {
-    let mut read_buffer = BytesMut::with_capacity(64 * 1024);
+    let idle_timeout = (config.timeout > 0).then(|| Duration::from_secs(config.timeout));
+    let mut read_buffer = BytesMut::with_capacity(READ_BUFFER_SIZE);
 
     let mut seq: u64 = 0;
     loop {
         read_buffer.reserve(1024);
-        if read_half.read_buf(&mut read_buffer).await? == 0 {
+        shrink_read_buffer(&mut read_buffer);
+        let read = match read_or_stop(read_half.read_buf(&mut read_buffer), idle_timeout, &tx).await
+        {
+            Some(read) => read?,
+            None => break,
+        };
+        if read == 0 {
             break; //
         }
+        ServerStats::incr(&STATS.total_net_input_bytes, read as u64);
 
-        loop {
-            match parse(&mut read_buffer) {
-                Ok(value) => {
-                    seq += 1;
-                    let tx_clone = tx.clone();
-                    route_message(router, value, seq, tx_clone);
-                }
-                Err(BufParseError::Incomplete) => {
-                    break;
-                }
-                Err(BufParseError::InvalidFirstByte(b)) => {
-                    match b {
-                        Some(byte) => {
-                            let s = format!("-ERR invalid first byte: {}", byte);
-                            let _ = tx.send(ResponseMessage {
-                                seq,
-                                response_value: ResponseValue::Error(s.into()),
-                            });
-                        }
-                        None => {
-                            let _ = tx.send(ResponseMessage {
-                                seq,
-                                response_value: ResponseValue::Error(
-                                    "ERR first byte not found".into(),
-                                ),
-                            });
-                        }
-                    };
-                    return Ok(()); // Close connection on protocol error
-                }
-                _ => {
-                    let _ = tx.send(ResponseMessage {
-                        seq,
-                        response_value: ResponseValue::Error("ERR internal server error".into()),
-                    });
-                    return Ok(()); // Close connection on error
-                }
-            }
+        if !dispatch_frames(
+            &mut read_buffer,
+            &mut seq,
+            &tx,
+            in_flight,
+            unanswered,
+            client,
+            router,
+        )
+        .await
+        {
+            break;
+        }
+        if over_query_buffer_limit(&read_buffer, config) {
+            break;
         }
     }
 
     Ok(())
 }
+
+/// Swaps a read buffer that grew for some large request back to
+/// `READ_BUFFER_SIZE`, once what is left in it fits comfortably. Without this
+/// a single 10MB SET would pin 10MB for the rest of the connection.
+pub fn shrink_read_buffer(read_buffer: &mut BytesMut) {
+    if read_buffer.capacity() > READ_BUFFER_SHRINK_THRESHOLD
+        && read_buffer.len() <= READ_BUFFER_SIZE / 2
+    {
+        let mut shrunk = BytesMut::with_capacity(READ_BUFFER_SIZE);
+        shrunk.extend_from_slice(read_buffer);
+        *read_buffer = shrunk;
+    }
+}
+
+/// Whether the unparsed part of a request has outgrown the
+/// `client-query-buffer-limit`, in which case the client is dropped.
+pub(crate) fn over_query_buffer_limit(read_buffer: &BytesMut, config: &Config) -> bool {
+    let limit = config.client_query_buffer_limit;
+    if limit > 0 && read_buffer.len() > limit {
+        warn!(
+            "Closing client that reached max query buffer length ({} bytes)",
+            read_buffer.len()
+        );
+        return true;
+    }
+    false
+}
+
+/// Awaits a socket read, or returns `None` if the client stays idle past
+/// `idle_timeout` or the writer has given up on the connection (e.g. over its
+/// output buffer limit). Either way the writer flushes what it can and closes.
+pub(crate) async fn read_or_stop<F: Future>(
+    read: F,
+    idle_timeout: Option<Duration>,
+    tx: &Sender<ResponseMessage>,
+) -> Option<F::Output> {
+    let read = async {
+        match idle_timeout {
+            Some(limit) => time::timeout(limit, read).await.ok(),
+            None => Some(read.await),
+        }
+    };
+
+    tokio::select! {
+        biased;
+        _ = tx.closed() => None,
+        result = read => result,
+    }
+}
+
+/// Parses every complete frame in `read_buffer` and hands it to a worker.
+/// Returns `false` once the connection should stop reading, after QUIT or a
+/// protocol error; the writer then closes it when the last reply is out.
+///
+/// Every command takes one of the connection's `in_flight` permits, which the
+/// writer hands back once the reply is written; with `MAX_IN_FLIGHT`
+/// outstanding this waits, and the socket is not read meanwhile. The shards
+/// each command goes to are told to `unanswered`, see `snapshot`.
+pub(crate) async fn dispatch_frames(
+    read_buffer: &mut BytesMut,
+    seq: &mut u64,
+    tx: &Sender<ResponseMessage>,
+    in_flight: &Semaphore,
+    unanswered: &Unanswered,
+    client: &Client,
+    router: &[Sender<WorkerMessage>],
+) -> bool {
+    loop {
+        let sample = Sample::next();
+        match parse_request(read_buffer) {
+            Ok(value) => {
+                let trace = CommandTrace::start(sample, &value);
+                let Ok(slot) = in_flight.acquire().await else {
+                    return false; // writer is gone
+                };
+                slot.forget();
+                *seq += 1;
+                ServerStats::incr(&STATS.total_commands_processed, 1);
+                audit::record(client, &value);
+                if is_command(&value, b"QUIT") {
+                    let _ = tx
+                        .send(ResponseMessage {
+                            seq: *seq,
+                            response_value: ResponseValue::SimpleString("OK".into()),
+                            trace,
+                        })
+                        .await;
+                    return false; // writer closes the socket once everything is flushed
+                }
+                // waits while the reply channel is full, throttling the client
+                let Ok(permit) = tx.clone().reserve_owned().await else {
+                    return false; // writer is gone
+                };
+                let _route = trace.stage("route");
+                route_command(router, value, *seq, permit, trace, Some(unanswered)).await;
+            }
+            Err(BufParseError::Incomplete) => {
+                return true;
+            }
+            Err(err) => {
+                *seq += 1;
+                let _ = tx
+                    .send(ResponseMessage {
+                        seq: *seq,
+                        response_value: ResponseValue::Error(err.reply_message().into()),
+                        trace: CommandTrace::default(),
+                    })
+                    .await;
+                return false; // Close connection on protocol error
+            }
+        }
+    }
+}
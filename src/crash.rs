@@ -0,0 +1,135 @@
+//! What happens when a thread panics.
+//!
+//! `install_hook` swaps the default panic message for a crash report in the
+//! log: where the panic happened and why, and for a worker its id, the
+//! command it was running and a backtrace. Workers run every command through
+//! `catch_command`, so a command that panics gets an error reply rather than
+//! taking its shard down with the thread. Then, as `--worker-panic` says,
+//! the worker either carries on with its mailbox and shard, dropping the keys
+//! of the command as it may have left them half updated, or has the server
+//! shut down, exiting with status 1. A panic on a worker outside of a command
+//! always shuts the server down. This takes unwinding, which is why release
+//! builds don't abort on panics.
+
+use std::{
+    backtrace::Backtrace,
+    cell::{Cell, RefCell},
+    fmt::Write as _,
+    panic::{self, AssertUnwindSafe, PanicHookInfo},
+    sync::atomic::{AtomicBool, Ordering},
+    thread,
+};
+
+use bytes::Bytes;
+use tokio::sync::Notify;
+
+use crate::{config::WorkerPanic, message::ResponseValue};
+
+/// The reply to a command that panicked.
+pub const PANIC_ERROR: &str = "ERR internal error, see the server log";
+
+/// Longest key a crash report shows in full.
+const MAX_KEY_LEN: usize = 128;
+
+static CRASHED: AtomicBool = AtomicBool::new(false);
+static SHUTDOWN: Notify = Notify::const_new();
+
+thread_local! {
+    static WORKER_ID: Cell<Option<usize>> = const { Cell::new(None) };
+    /// The name and first key of the command this thread is running.
+    static COMMAND: RefCell<Option<(&'static str, Option<Bytes>)>> = const { RefCell::new(None) };
+}
+
+/// Logs a crash report for every panic, in place of the default message.
+pub fn install_hook() {
+    panic::set_hook(Box::new(|info| tracing::error!("{}", report(info))));
+}
+
+/// The crash report for the panic `info` describes, on the current thread.
+pub fn report(info: &PanicHookInfo<'_>) -> String {
+    let mut report = String::from("=== RUSTIS BUG REPORT START ===\n");
+    let thread = thread::current();
+    let _ = write!(
+        report,
+        "thread '{}' panicked",
+        thread.name().unwrap_or("<unnamed>")
+    );
+    if let Some(location) = info.location() {
+        let _ = write!(report, " at {location}");
+    }
+    let _ = writeln!(
+        report,
+        ":\n{}",
+        info.payload_as_str().unwrap_or("Box<dyn Any>")
+    );
+    if let Some(worker_id) = WORKER_ID.get() {
+        let _ = writeln!(report, "worker: {worker_id}");
+    }
+    // a panic while the command is being swapped must not panic again here
+    COMMAND.with(|command| {
+        let Some((name, key)) = command
+            .try_borrow()
+            .ok()
+            .and_then(|command| command.clone())
+        else {
+            return;
+        };
+        let _ = write!(report, "command: {name}");
+        if let Some(key) = key {
+            let shown = &key[..key.len().min(MAX_KEY_LEN)];
+            let _ = write!(report, ", key {:?}", String::from_utf8_lossy(shown));
+            if key.len() > MAX_KEY_LEN {
+                let _ = write!(report, " ({} bytes)", key.len());
+            }
+        }
+        report.push('\n');
+    });
+    let _ = write!(
+        report,
+        "backtrace:\n{}=== RUSTIS BUG REPORT END ===",
+        Backtrace::force_capture()
+    );
+    report
+}
+
+/// Marks the current thread as worker `worker_id` in crash reports.
+pub fn set_worker(worker_id: usize) {
+    WORKER_ID.set(Some(worker_id));
+}
+
+/// Runs `command`, the `name` command on `key`, returning its reply, or
+/// `None` if it panicked, for the worker to reply with `PANIC_ERROR` and, as
+/// `policy` says, drop the command's keys. With `WorkerPanic::Shutdown` the
+/// server shuts down.
+pub fn catch_command(
+    name: &'static str,
+    key: Option<&Bytes>,
+    policy: WorkerPanic,
+    command: impl FnOnce() -> ResponseValue,
+) -> Option<ResponseValue> {
+    COMMAND.set(Some((name, key.cloned())));
+    let result = panic::catch_unwind(AssertUnwindSafe(command));
+    COMMAND.set(None);
+    if result.is_err() && policy == WorkerPanic::Shutdown {
+        tracing::error!("Shutting down after '{name}' panicked");
+        shutdown();
+    }
+    result.ok()
+}
+
+/// Has the server shut down because of a panic.
+pub fn shutdown() {
+    CRASHED.store(true, Ordering::Relaxed);
+    SHUTDOWN.notify_one();
+}
+
+/// Whether a panic called for a shutdown, which should then exit with an
+/// error status.
+pub fn crashed() -> bool {
+    CRASHED.load(Ordering::Relaxed)
+}
+
+/// Resolves once a panic calls for a shutdown.
+pub async fn shutdown_requested() {
+    SHUTDOWN.notified().await
+}
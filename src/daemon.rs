@@ -0,0 +1,225 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io,
+    path::{Path, PathBuf},
+};
+
+use crate::{config::Supervised, crash, info::render_info};
+
+/// Detaches from the terminal like Redis' `daemonize yes`: forks, lets the
+/// parent exit, starts a new session and points stdin at `/dev/null`.
+///
+/// Must be called before any thread is spawned, since only the calling thread
+/// survives the fork.
+#[cfg(unix)]
+pub fn daemonize() -> io::Result<()> {
+    // SAFETY: we are still single threaded, so forking is sound.
+    match unsafe { libc::fork() } {
+        -1 => return Err(io::Error::last_os_error()),
+        0 => {}
+        _ => std::process::exit(0),
+    }
+
+    // SAFETY: plain syscall, the child is not a process group leader.
+    if unsafe { libc::setsid() } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+
+    redirect(&File::open("/dev/null")?, &[libc::STDIN_FILENO])
+}
+
+#[cfg(not(unix))]
+pub fn daemonize() -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "daemonize is only supported on unix",
+    ))
+}
+
+/// Sends stdout and stderr to `logfile`, or to `/dev/null` when there is none
+/// (a daemon has no terminal to write to).
+#[cfg(unix)]
+pub fn redirect_output(logfile: Option<&Path>) -> io::Result<()> {
+    let target = match logfile {
+        Some(path) => OpenOptions::new().create(true).append(true).open(path)?,
+        None => OpenOptions::new().write(true).open("/dev/null")?,
+    };
+    redirect(&target, &[libc::STDOUT_FILENO, libc::STDERR_FILENO])
+}
+
+#[cfg(not(unix))]
+pub fn redirect_output(logfile: Option<&Path>) -> io::Result<()> {
+    match logfile {
+        Some(_) => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "logfile is only supported on unix",
+        )),
+        None => Ok(()),
+    }
+}
+
+#[cfg(unix)]
+fn redirect(target: &File, fds: &[libc::c_int]) -> io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    for &fd in fds {
+        // SAFETY: both descriptors are valid for the duration of the call.
+        if unsafe { libc::dup2(target.as_raw_fd(), fd) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/// The server's pid written to `path`, removed again when dropped.
+#[derive(Debug)]
+pub struct PidFile {
+    path: PathBuf,
+}
+
+impl PidFile {
+    pub fn create(path: impl Into<PathBuf>) -> io::Result<Self> {
+        let path = path.into();
+        fs::write(&path, format!("{}\n", std::process::id()))?;
+        Ok(Self { path })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Sends `state` (e.g. `READY=1`) to systemd over `$NOTIFY_SOCKET`. Does
+/// nothing when the variable is unset, i.e. not running as `Type=notify`.
+#[cfg(target_os = "linux")]
+pub fn sd_notify(state: &str) -> io::Result<()> {
+    use std::os::{
+        linux::net::SocketAddrExt,
+        unix::net::{SocketAddr, UnixDatagram},
+    };
+
+    let Some(path) = std::env::var_os("NOTIFY_SOCKET") else {
+        return Ok(());
+    };
+    // a leading '@' names a socket in the abstract namespace
+    let addr = match path.as_encoded_bytes().strip_prefix(b"@") {
+        Some(name) => SocketAddr::from_abstract_name(name)?,
+        None => SocketAddr::from_pathname(&path)?,
+    };
+
+    UnixDatagram::unbound()?.send_to_addr(state.as_bytes(), &addr)?;
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn sd_notify(_state: &str) -> io::Result<()> {
+    Ok(())
+}
+
+/// Tells the supervisor, if there is one, that the server changed state.
+pub fn notify_supervisor(supervised: Supervised, state: &str) {
+    if !supervised.is_systemd() {
+        return;
+    }
+    if let Err(err) = sd_notify(state) {
+        tracing::warn!("Failed to notify systemd ({}): {}", state, err);
+    }
+}
+
+/// Starts a thread answering SIGUSR1 by logging a snapshot of every INFO
+/// section, per-worker queue depths, memory and command counts included,
+/// and SIGHUP by reopening `logfile`, so that logrotate can move it aside
+/// without a restart. Does nothing but on unix.
+#[cfg(unix)]
+pub fn spawn_signal_handler(logfile: Option<PathBuf>) -> io::Result<()> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_io()
+        .build()?;
+    // registered before returning, so the signals are handled from now on
+    let (mut usr1, mut hup) = {
+        let _runtime = runtime.enter();
+        (
+            signal(SignalKind::user_defined1())?,
+            signal(SignalKind::hangup())?,
+        )
+    };
+    std::thread::Builder::new()
+        .name("signals".into())
+        .spawn(move || {
+            runtime.block_on(async move {
+                loop {
+                    tokio::select! {
+                        _ = usr1.recv() => log_stats(),
+                        _ = hup.recv() => reopen_log(logfile.as_deref()),
+                    }
+                }
+            })
+        })?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn spawn_signal_handler(_logfile: Option<PathBuf>) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(unix)]
+fn log_stats() {
+    tracing::info!("Stats snapshot requested by SIGUSR1");
+    for line in render_info(Some("everything")).lines() {
+        if !line.is_empty() {
+            tracing::info!("{line}");
+        }
+    }
+}
+
+#[cfg(unix)]
+fn reopen_log(logfile: Option<&Path>) {
+    let Some(logfile) = logfile else {
+        return;
+    };
+    match redirect_output(Some(logfile)) {
+        Ok(()) => tracing::info!("Reopened the log file on SIGHUP"),
+        Err(err) => tracing::warn!("Can't reopen the log file {}: {err}", logfile.display()),
+    }
+}
+
+/// Resolves once the process is asked to stop with SIGTERM or Ctrl-C, or a
+/// panic calls for a shutdown.
+pub async fn shutdown_signal() {
+    tokio::select! {
+        _ = stop_signal() => {}
+        _ = crash::shutdown_requested() => {}
+    }
+}
+
+async fn stop_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        match signal(SignalKind::terminate()) {
+            Ok(mut sigterm) => {
+                tokio::select! {
+                    _ = sigterm.recv() => {}
+                    _ = tokio::signal::ctrl_c() => {}
+                }
+            }
+            Err(_) => {
+                let _ = tokio::signal::ctrl_c().await;
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+    }
+}
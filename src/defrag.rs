@@ -0,0 +1,102 @@
+use std::time::{Duration, Instant};
+
+use crate::{
+    allocator::{self, AllocatorStats},
+    config::{ActiveDefrag, Config},
+    kv::KvStore,
+    stats::{ServerStats, STATS},
+};
+
+/// How often a worker with `activedefrag` on checks for fragmentation and,
+/// while there is some, spends part of its time on defrag.
+pub const DEFRAG_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Buckets walked between checks of the time budget.
+const BUCKETS_PER_CHECK: usize = 16;
+
+/// Active defrag for one worker's shard, like Redis' `activedefrag`.
+///
+/// Churn leaves the allocator holding pages that are mostly free, so the
+/// process keeps more memory than it uses. Once the allocator reports
+/// enough of that, the worker walks its keyspace a slice at a time and
+/// copies every key and value into fresh allocations, packing them into
+/// fuller pages so the emptied ones can go back to the OS. Each slice gets
+/// between `active-defrag-cycle-min` and `active-defrag-cycle-max` percent
+/// of `DEFRAG_INTERVAL`, more the more fragmented memory is.
+///
+/// Redis asks jemalloc which allocations sit in sparse pages and moves only
+/// those; without that hint every allocation is moved, so a walk costs
+/// about as much as copying the shard.
+pub struct Defragger {
+    settings: ActiveDefrag,
+    /// Where the walk through the keyspace is, `None` between walks.
+    cursor: Option<usize>,
+}
+
+impl Defragger {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            settings: config.active_defrag,
+            cursor: None,
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.settings.enabled
+    }
+
+    /// Whether a walk through the keyspace is under way.
+    pub fn running(&self) -> bool {
+        self.cursor.is_some()
+    }
+
+    /// Runs one interval's slice of defrag, if memory is fragmented enough
+    /// or a walk is under way. A walk that has started finishes, at the
+    /// least effort, even if fragmentation drops below the threshold.
+    pub fn cycle(&mut self, kv: &mut KvStore) {
+        let effort = self.effort(&allocator::stats());
+        let (cursor, percent) = match (self.cursor, effort) {
+            (None, None) => return,
+            (None, Some(percent)) => (0, percent),
+            (Some(cursor), percent) => (cursor, percent.unwrap_or(self.settings.cycle_min)),
+        };
+
+        let budget = DEFRAG_INTERVAL * percent / 100;
+        let start = Instant::now();
+        allocator::set_thread_cache(false);
+        self.cursor = Some(cursor);
+        while let Some(cursor) = self.cursor {
+            let (next, hits, keys) = kv.defrag(cursor, BUCKETS_PER_CHECK);
+            ServerStats::incr(&STATS.active_defrag_hits, hits as u64);
+            ServerStats::incr(&STATS.active_defrag_key_hits, keys as u64);
+            self.cursor = next;
+            if start.elapsed() >= budget {
+                break;
+            }
+        }
+        allocator::set_thread_cache(true);
+    }
+
+    /// Percent of the interval to spend on defrag given the allocator's
+    /// numbers, `None` if memory isn't fragmented enough to bother.
+    pub fn effort(&self, stats: &AllocatorStats) -> Option<u32> {
+        let (allocated, active) = (stats.allocated?, stats.active?);
+        let wasted = active.saturating_sub(allocated);
+        let percent = (wasted * 100).checked_div(allocated)? as u32;
+        let ActiveDefrag {
+            ignore_bytes,
+            threshold_lower: lower,
+            threshold_upper: upper,
+            cycle_min: min,
+            cycle_max: max,
+            ..
+        } = self.settings;
+        if wasted < ignore_bytes || percent < lower {
+            return None;
+        }
+
+        let max = max.max(min);
+        let scaled = (percent - lower) * (max - min) / upper.saturating_sub(lower).max(1);
+        Some((min + scaled).min(max))
+    }
+}
@@ -0,0 +1,506 @@
+use std::{
+    borrow::Borrow,
+    fmt,
+    hash::{BuildHasher, Hash, RandomState},
+    iter, mem,
+};
+
+/// Smallest table a dict allocates, as in Redis.
+const INITIAL_SIZE: usize = 4;
+/// A table is shrunk once it is filled below this percentage, as in Redis.
+const MIN_FILL: usize = 10;
+/// Empty buckets a rehash step may skip over for each bucket it moves.
+const EMPTY_VISITS: usize = 10;
+
+type Link<K, V> = Option<Box<Node<K, V>>>;
+
+struct Node<K, V> {
+    hash: u64,
+    key: K,
+    value: V,
+    next: Link<K, V>,
+}
+
+struct Table<K, V> {
+    buckets: Vec<Link<K, V>>,
+    len: usize,
+}
+
+impl<K, V> Table<K, V> {
+    fn with_size(size: usize) -> Self {
+        Self {
+            buckets: iter::repeat_with(|| None).take(size).collect(),
+            len: 0,
+        }
+    }
+
+    fn bucket(&self, hash: u64) -> usize {
+        hash as usize & (self.buckets.len() - 1)
+    }
+
+    fn find<Q>(&self, hash: u64, key: &Q) -> Option<&Node<K, V>>
+    where
+        K: Borrow<Q>,
+        Q: Eq + ?Sized,
+    {
+        if self.len == 0 {
+            return None;
+        }
+        let mut link = self.buckets[self.bucket(hash)].as_deref();
+        while let Some(node) = link {
+            if node.hash == hash && node.key.borrow() == key {
+                return Some(node);
+            }
+            link = node.next.as_deref();
+        }
+        None
+    }
+
+    fn find_mut<Q>(&mut self, hash: u64, key: &Q) -> Option<&mut Node<K, V>>
+    where
+        K: Borrow<Q>,
+        Q: Eq + ?Sized,
+    {
+        if self.len == 0 {
+            return None;
+        }
+        let bucket = self.bucket(hash);
+        let mut link = self.buckets[bucket].as_deref_mut();
+        while let Some(node) = link {
+            if node.hash == hash && node.key.borrow() == key {
+                return Some(node);
+            }
+            link = node.next.as_deref_mut();
+        }
+        None
+    }
+
+    fn push(&mut self, mut node: Box<Node<K, V>>) {
+        let bucket = self.bucket(node.hash);
+        node.next = self.buckets[bucket].take();
+        self.buckets[bucket] = Some(node);
+        self.len += 1;
+    }
+
+    fn remove<Q>(&mut self, hash: u64, key: &Q) -> Option<Box<Node<K, V>>>
+    where
+        K: Borrow<Q>,
+        Q: Eq + ?Sized,
+    {
+        if self.len == 0 {
+            return None;
+        }
+        let bucket = self.bucket(hash);
+        let mut link = &mut self.buckets[bucket];
+        while link
+            .as_ref()
+            .is_some_and(|node| node.hash != hash || node.key.borrow() != key)
+        {
+            link = &mut link.as_mut().unwrap().next;
+        }
+        let mut node = link.take()?;
+        *link = node.next.take();
+        self.len -= 1;
+        Some(node)
+    }
+
+    fn iter(&self) -> impl Iterator<Item = &Node<K, V>> {
+        self.buckets
+            .iter()
+            .flat_map(|link| iter::successors(link.as_deref(), |node| node.next.as_deref()))
+    }
+}
+
+/// The cursor after `cursor` in a table of `mask + 1` buckets: one more,
+/// counting in reverse bit order, and 0 after the last.
+fn next_cursor(cursor: usize, mask: usize) -> usize {
+    (cursor | !mask)
+        .reverse_bits()
+        .wrapping_add(1)
+        .reverse_bits()
+}
+
+/// A hash table that grows and shrinks a little at a time, like Redis'
+/// dict. Resizing allocates the new table and then moves one bucket per
+/// insert or removal, plus whatever `rehash` gets through, so the cost of
+/// moving millions of keys is spread out instead of stalling the shard while
+/// `HashMap` reallocates. Lookups check both tables until it is done.
+pub struct Dict<K, V> {
+    table: Table<K, V>,
+    /// Table being rehashed into, and the next bucket of `table` to move.
+    rehashing: Option<(Table<K, V>, usize)>,
+    /// Buckets the table never shrinks below.
+    min_size: usize,
+    hasher: RandomState,
+}
+
+impl<K, V> Default for Dict<K, V> {
+    fn default() -> Self {
+        Self::with_capacity(0)
+    }
+}
+
+impl<K: fmt::Debug, V: fmt::Debug> fmt::Debug for Dict<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+impl<K, V> Dict<K, V> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A dict with room for `capacity` entries up front, which it keeps even
+    /// when emptied.
+    pub fn with_capacity(capacity: usize) -> Self {
+        let min_size = match capacity {
+            0 => 0,
+            _ => capacity.next_power_of_two().max(INITIAL_SIZE),
+        };
+        Self {
+            table: Table::with_size(min_size),
+            rehashing: None,
+            min_size,
+            hasher: RandomState::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.table.len + self.rehashing.as_ref().map_or(0, |(next, _)| next.len)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Buckets allocated, counting both tables while rehashing.
+    pub fn buckets(&self) -> usize {
+        self.table.buckets.len()
+            + self
+                .rehashing
+                .as_ref()
+                .map_or(0, |(next, _)| next.buckets.len())
+    }
+
+    pub fn is_rehashing(&self) -> bool {
+        self.rehashing.is_some()
+    }
+
+    /// Takes everything out, leaving an empty dict of the same capacity.
+    pub fn take(&mut self) -> Self {
+        let empty = Self {
+            table: Table::with_size(self.min_size),
+            rehashing: None,
+            min_size: self.min_size,
+            hasher: self.hasher.clone(),
+        };
+        mem::replace(self, empty)
+    }
+
+    pub fn clear(&mut self) {
+        self.table = Table::with_size(self.min_size);
+        self.rehashing = None;
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
+        let next = self.rehashing.as_ref().map(|(next, _)| next);
+        self.table
+            .iter()
+            .chain(next.into_iter().flat_map(Table::iter))
+            .map(|node| (&node.key, &node.value))
+    }
+
+    /// Moves up to `buckets` buckets into the new table, if one is being
+    /// rehashed into. Returns whether there is more to move.
+    pub fn rehash(&mut self, buckets: usize) -> bool {
+        let Some((next, cursor)) = &mut self.rehashing else {
+            return false;
+        };
+        let mut empty_visits = buckets * EMPTY_VISITS;
+        let mut moved = 0;
+        while moved < buckets && self.table.len > 0 {
+            let Some(mut link) = self.table.buckets[*cursor].take() else {
+                *cursor += 1;
+                empty_visits -= 1;
+                if empty_visits == 0 {
+                    return true;
+                }
+                continue;
+            };
+            loop {
+                let rest = link.next.take();
+                self.table.len -= 1;
+                next.push(link);
+                match rest {
+                    Some(rest) => link = rest,
+                    None => break,
+                }
+            }
+            *cursor += 1;
+            moved += 1;
+        }
+        if self.table.len > 0 {
+            return true;
+        }
+        let (next, _) = self.rehashing.take().unwrap();
+        self.table = next;
+        false
+    }
+
+    /// Moves the nodes in bucket `cursor` of the table into fresh
+    /// allocations, after letting `f` do the same for their contents, for
+    /// active defrag. Returns the bucket to carry on from, `None` once the
+    /// table has been walked. Does nothing while rehashing, which moves every
+    /// node anyway.
+    pub fn defrag_bucket(
+        &mut self,
+        cursor: usize,
+        mut f: impl FnMut(&mut K, &mut V),
+    ) -> Option<usize> {
+        if self.rehashing.is_some() {
+            return Some(cursor);
+        }
+        let bucket = self.table.buckets.get_mut(cursor)?;
+        let mut link = bucket.take();
+        let mut moved = None;
+        while let Some(mut node) = link {
+            link = node.next.take();
+            f(&mut node.key, &mut node.value);
+            let mut node = Box::new(*node);
+            node.next = moved;
+            moved = Some(node);
+        }
+        *bucket = moved;
+        Some(cursor + 1)
+    }
+
+    /// Calls `f` on every entry in the bucket `cursor` stands for, starting
+    /// from 0. Returns the cursor to carry on from, `None` once the dict has
+    /// been walked.
+    ///
+    /// As in Redis' `dictScan`, cursors count up in reverse bit order, so a
+    /// bucket is walked together with the buckets it splits into or merges
+    /// from when the table is resized: an entry that is there for the whole
+    /// walk is seen, however the table grows or shrinks during it, though it
+    /// may be seen twice after a shrink. While rehashing, the bucket of the
+    /// smaller table and every bucket of the larger one it maps to are
+    /// walked in one call.
+    pub fn scan_bucket(&self, cursor: usize, mut f: impl FnMut(&K, &V)) -> Option<usize> {
+        let mut visit = |table: &Table<K, V>, bucket: usize| {
+            let mut link = table.buckets[bucket].as_deref();
+            while let Some(node) = link {
+                f(&node.key, &node.value);
+                link = node.next.as_deref();
+            }
+        };
+        let next = match &self.rehashing {
+            None if self.table.buckets.is_empty() => return None,
+            None => {
+                let mask = self.table.buckets.len() - 1;
+                visit(&self.table, cursor & mask);
+                next_cursor(cursor, mask)
+            }
+            Some((next, _)) => {
+                let (small, large) = if self.table.buckets.len() <= next.buckets.len() {
+                    (&self.table, next)
+                } else {
+                    (next, &self.table)
+                };
+                let small_mask = small.buckets.len() - 1;
+                let large_mask = large.buckets.len() - 1;
+                visit(small, cursor & small_mask);
+                let mut cursor = cursor;
+                loop {
+                    visit(large, cursor & large_mask);
+                    cursor = next_cursor(cursor, large_mask);
+                    if cursor & (small_mask ^ large_mask) == 0 {
+                        break cursor;
+                    }
+                }
+            }
+        };
+        (next != 0).then_some(next)
+    }
+
+    /// Up to `count` entries from buckets picked at random, for eviction to
+    /// choose from, like Redis' `dictGetSomeKeys`: it walks on from a random
+    /// bucket of both tables, jumping elsewhere after a run of empty ones,
+    /// and gives up after `10 * count` buckets, so it may return fewer.
+    /// `random` is called for every jump.
+    pub fn sample(&self, count: usize, mut random: impl FnMut() -> u64) -> Vec<(&K, &V)> {
+        let mut sampled = Vec::with_capacity(count.min(self.len()));
+        if self.is_empty() {
+            return sampled;
+        }
+        let next = self.rehashing.as_ref().map(|(next, _)| next);
+        let tables = iter::once(&self.table).chain(next);
+        let mask = tables
+            .clone()
+            .map(|table| table.buckets.len())
+            .max()
+            .unwrap()
+            - 1;
+        let mut bucket = random() as usize & mask;
+        let mut empty = 0;
+        for _ in 0..count * 10 {
+            for table in tables.clone() {
+                match table.buckets.get(bucket) {
+                    // past the end of the smaller table
+                    None => {}
+                    Some(None) => empty += 1,
+                    Some(Some(node)) => {
+                        empty = 0;
+                        let chain = iter::successors(Some(&**node), |node| node.next.as_deref());
+                        for node in chain {
+                            sampled.push((&node.key, &node.value));
+                            if sampled.len() == count {
+                                return sampled;
+                            }
+                        }
+                    }
+                }
+            }
+            if empty >= 5 && empty > count {
+                bucket = random() as usize & mask;
+                empty = 0;
+            } else {
+                bucket = (bucket + 1) & mask;
+            }
+        }
+        sampled
+    }
+
+    /// Starts moving everything into a table of `size` buckets.
+    fn resize(&mut self, size: usize) {
+        if self.table.len == 0 {
+            self.table = Table::with_size(size);
+        } else {
+            self.rehashing = Some((Table::with_size(size), 0));
+        }
+    }
+
+    fn grow_if_needed(&mut self) {
+        if self.rehashing.is_none() && self.table.len >= self.table.buckets.len() {
+            let size = (self.table.len + 1).next_power_of_two();
+            self.resize(size.max(INITIAL_SIZE).max(self.min_size));
+        }
+    }
+
+    fn shrink_if_needed(&mut self) {
+        let size = self.table.buckets.len();
+        if self.rehashing.is_none()
+            && size > self.min_size.max(INITIAL_SIZE)
+            && self.table.len * 100 < size * MIN_FILL
+        {
+            let target = self.table.len.next_power_of_two();
+            self.resize(target.max(INITIAL_SIZE).max(self.min_size));
+        }
+    }
+}
+
+impl<K: Hash + Eq, V> Dict<K, V> {
+    fn find<Q>(&self, hash: u64, key: &Q) -> Option<&Node<K, V>>
+    where
+        K: Borrow<Q>,
+        Q: Eq + ?Sized,
+    {
+        self.table.find(hash, key).or_else(|| {
+            let (next, _) = self.rehashing.as_ref()?;
+            next.find(hash, key)
+        })
+    }
+
+    fn find_mut<Q>(&mut self, hash: u64, key: &Q) -> Option<&mut Node<K, V>>
+    where
+        K: Borrow<Q>,
+        Q: Eq + ?Sized,
+    {
+        match self.table.find_mut(hash, key) {
+            Some(node) => Some(node),
+            None => self.rehashing.as_mut()?.0.find_mut(hash, key),
+        }
+    }
+
+    /// Adds a new node, into the table being rehashed into if there is one.
+    fn push(&mut self, hash: u64, key: K, value: V) -> &mut V {
+        self.grow_if_needed();
+        let table = match &mut self.rehashing {
+            Some((next, _)) => next,
+            None => &mut self.table,
+        };
+        table.push(Box::new(Node {
+            hash,
+            key,
+            value,
+            next: None,
+        }));
+        let bucket = table.bucket(hash);
+        &mut table.buckets[bucket].as_mut().unwrap().value
+    }
+
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let hash = self.hasher.hash_one(key);
+        self.find(hash, key).map(|node| &node.value)
+    }
+
+    pub fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.rehash(1);
+        let hash = self.hasher.hash_one(key);
+        self.find_mut(hash, key).map(|node| &mut node.value)
+    }
+
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.get(key).is_some()
+    }
+
+    /// Sets `key` to `value`, returning the value it replaced.
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        self.rehash(1);
+        let hash = self.hasher.hash_one(&key);
+        if let Some(node) = self.find_mut(hash, &key) {
+            return Some(mem::replace(&mut node.value, value));
+        }
+        self.push(hash, key, value);
+        None
+    }
+
+    /// The value at `key`, inserting the one `default` makes from the key if
+    /// there is none.
+    pub fn get_or_insert_with(&mut self, key: K, default: impl FnOnce(&K) -> V) -> &mut V {
+        self.rehash(1);
+        let hash = self.hasher.hash_one(&key);
+        if self.find(hash, &key).is_some() {
+            return &mut self.find_mut(hash, &key).unwrap().value;
+        }
+        let value = default(&key);
+        self.push(hash, key, value)
+    }
+
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.rehash(1);
+        let hash = self.hasher.hash_one(key);
+        let node = match self.table.remove(hash, key) {
+            Some(node) => node,
+            None => self.rehashing.as_mut()?.0.remove(hash, key)?,
+        };
+        self.shrink_if_needed();
+        Some(node.value)
+    }
+}
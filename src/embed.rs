@@ -0,0 +1,240 @@
+//! Running the server inside another program, as `rustis::Server`:
+//!
+//! ```no_run
+//! let mut server = rustis::Server::builder().port(0).workers(2).build()?;
+//! server.start()?;
+//! println!("serving on {}", server.addr());
+//! server.shutdown();
+//! # Ok::<(), std::io::Error>(())
+//! ```
+//!
+//! `client` gives a `LocalClient` to run commands without a socket, and
+//! `store` a `Store` with typed calls for the common ones.
+//! `build` binds the listening socket, so the address is known (and port 0
+//! resolved) before anything runs; `start` spawns the workers and a thread
+//! serving connections, like the binary without `--reuseport`; `shutdown`,
+//! or dropping the server, closes every connection and joins the threads.
+//! Logging, the audit log and metric exporters are the embedding program's
+//! to set up, with `log::init`, `audit::init` and the like.
+
+use std::{
+    io,
+    net::{IpAddr, SocketAddr, TcpListener as StdTcpListener},
+    path::PathBuf,
+    sync::Arc,
+    thread::{self, JoinHandle},
+};
+
+use tokio::{
+    net::TcpListener,
+    runtime::Builder,
+    sync::{mpsc::Sender, oneshot},
+    task::LocalSet,
+};
+
+use crate::{
+    config::{Config, MaxmemoryPolicy},
+    connection::accept_loop,
+    load::load_file,
+    local::LocalClient,
+    message::WorkerMessage,
+    store::Store,
+    threads::spawn_workers,
+};
+
+/// Settings for a `Server`, starting from `Config::default()`.
+#[derive(Debug, Clone, Default)]
+pub struct ServerBuilder {
+    config: Config,
+}
+
+impl ServerBuilder {
+    /// Starts from `config` rather than the defaults, for settings the
+    /// builder has no method for.
+    pub fn config(config: Config) -> Self {
+        Self { config }
+    }
+
+    /// Port to listen on; 0 picks a free one, see `Server::addr`.
+    pub fn port(mut self, port: u16) -> Self {
+        self.config.port = port;
+        self
+    }
+
+    /// Address to listen on, `127.0.0.1` by default.
+    pub fn bind(mut self, addr: IpAddr) -> Self {
+        self.config.bind = addr;
+        self
+    }
+
+    /// Worker threads, each owning a shard; 0 (the default) for one per core.
+    pub fn workers(mut self, workers: usize) -> Self {
+        self.config.workers = workers;
+        self
+    }
+
+    /// Bytes the dataset may use before `maxmemory_policy` kicks in; 0 for
+    /// no limit.
+    pub fn maxmemory(mut self, bytes: usize) -> Self {
+        self.config.maxmemory = bytes;
+        self
+    }
+
+    pub fn maxmemory_policy(mut self, policy: MaxmemoryPolicy) -> Self {
+        self.config.maxmemory_policy = policy;
+        self
+    }
+
+    /// A file of commands, as RESP, `start` runs before serving, like
+    /// `--load`.
+    pub fn load(mut self, path: impl Into<PathBuf>) -> Self {
+        self.config.load = Some(path.into());
+        self
+    }
+
+    /// Binds the listening socket, without serving anything yet.
+    pub fn build(self) -> io::Result<Server> {
+        let listener = StdTcpListener::bind(self.config.addr())?;
+        listener.set_nonblocking(true)?;
+        Ok(Server {
+            addr: listener.local_addr()?,
+            config: Arc::new(self.config),
+            listener: Some(listener),
+            running: None,
+        })
+    }
+}
+
+/// A server running in this process, see the module docs.
+#[derive(Debug)]
+pub struct Server {
+    config: Arc<Config>,
+    addr: SocketAddr,
+    /// Until `start` hands it to the IO thread.
+    listener: Option<StdTcpListener>,
+    running: Option<Running>,
+}
+
+#[derive(Debug)]
+struct Running {
+    /// Kept for `LocalClient`s, which only hold on to it weakly.
+    router: Arc<Vec<Sender<WorkerMessage>>>,
+    stop: oneshot::Sender<()>,
+    io: JoinHandle<()>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl Server {
+    pub fn builder() -> ServerBuilder {
+        ServerBuilder::default()
+    }
+
+    /// The address the server listens on, with the actual port if it was
+    /// built with port 0.
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Spawns the workers and the thread serving connections, returning once
+    /// they run, after the file given to `load`, if any, has. A server only
+    /// starts once; after that this fails, as it does if the file can't be
+    /// read or parsed.
+    pub fn start(&mut self) -> io::Result<()> {
+        let Some(listener) = self.listener.take() else {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                "the server was already started",
+            ));
+        };
+        let runtime = Builder::new_current_thread().enable_all().build()?;
+        let (router, workers) = spawn_workers(self.config.clone(), false);
+        let router = Arc::new(router);
+        if let Some(path) = &self.config.load {
+            // on a thread of its own, as this may be called from async code
+            let loaded = thread::scope(|scope| {
+                scope
+                    .spawn(|| runtime.block_on(load_file(&router, path)))
+                    .join()
+                    .expect("loading doesn't panic")
+            });
+            if let Err(err) = loaded {
+                drop(router);
+                for worker in workers {
+                    let _ = worker.join();
+                }
+                return Err(io::Error::new(io::ErrorKind::InvalidData, err));
+            }
+        }
+        let io_router = router.clone();
+        let config = self.config.clone();
+        let (stop, stopped) = oneshot::channel();
+        let io = thread::Builder::new()
+            .name("rustis-io".into())
+            .spawn(move || {
+                let local = LocalSet::new();
+                local.block_on(&runtime, async move {
+                    let listener = match TcpListener::from_std(listener) {
+                        Ok(listener) => listener,
+                        Err(err) => {
+                            tracing::error!("Can't serve on the listener: {err}");
+                            return;
+                        }
+                    };
+                    tokio::select! {
+                        _ = accept_loop(listener, io_router, config) => {}
+                        _ = stopped => {}
+                    }
+                });
+                // dropping the connections' tasks drops the last senders to
+                // the workers, which then exit
+                drop(local);
+            })?;
+        self.running = Some(Running {
+            router,
+            stop,
+            io,
+            workers,
+        });
+        Ok(())
+    }
+
+    /// A client running commands on this server in-process. It answers with
+    /// an error once the server is shut down, or if it isn't started yet.
+    pub fn client(&self) -> LocalClient {
+        match &self.running {
+            Some(running) => LocalClient::new(&running.router),
+            None => LocalClient::disconnected(),
+        }
+    }
+
+    /// The dataset, for typed calls like `store.get(key).await`.
+    pub fn store(&self) -> Store {
+        Store::new(self.client())
+    }
+
+    /// Closes the listener and every connection, then waits for the threads
+    /// to finish. Does nothing if the server isn't running.
+    pub fn shutdown(&mut self) {
+        let Some(running) = self.running.take() else {
+            return;
+        };
+        let _ = running.stop.send(());
+        let _ = running.io.join();
+        // the workers exit once the last senders to them are gone, those of
+        // local clients running a command aside
+        drop(running.router);
+        for worker in running.workers {
+            let _ = worker.join();
+        }
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
@@ -0,0 +1,74 @@
+use std::sync::atomic::Ordering;
+
+use crate::{
+    config::{Config, MaxmemoryPolicy},
+    kv::KvStore,
+    stats::{ServerStats, STATS},
+};
+
+/// Enforces `maxmemory` for one worker's shard.
+///
+/// The limit covers the whole dataset, so each worker adds its shard's usage
+/// to `STATS.used_memory`. To keep workers off that shared counter on every
+/// write, a worker only publishes its changes once per batch and counts its
+/// unpublished ones on top when checking the limit.
+pub struct MemoryLimit {
+    maxmemory: usize,
+    policy: MaxmemoryPolicy,
+    samples: usize,
+    /// The shard's usage as last added to `STATS.used_memory`.
+    published: usize,
+}
+
+impl MemoryLimit {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            maxmemory: config.maxmemory,
+            policy: config.maxmemory_policy,
+            samples: config.maxmemory_samples,
+            published: 0,
+        }
+    }
+
+    /// Evicts keys from `kv` until the dataset fits in `maxmemory`. Returns
+    /// false if it still does not, in which case commands that may grow the
+    /// dataset are refused.
+    pub fn make_room(&mut self, kv: &mut KvStore) -> bool {
+        if self.maxmemory == 0 {
+            return true;
+        }
+        while self.used_memory(kv) > self.maxmemory {
+            if kv.evict(self.policy, self.samples).is_none() {
+                return false;
+            }
+            ServerStats::incr(&STATS.evicted_keys, 1);
+        }
+        true
+    }
+
+    /// Adds the shard's changes since the last call to `STATS.used_memory`.
+    pub fn publish(&mut self, kv: &KvStore) {
+        let used = kv.used_memory();
+        if used > self.published {
+            ServerStats::incr(&STATS.used_memory, (used - self.published) as u64);
+        } else if used < self.published {
+            STATS
+                .used_memory
+                .fetch_sub((self.published - used) as u64, Ordering::Relaxed);
+        }
+        self.published = used;
+    }
+
+    fn used_memory(&self, kv: &KvStore) -> usize {
+        (ServerStats::get(&STATS.used_memory) as usize + kv.used_memory())
+            .saturating_sub(self.published)
+    }
+}
+
+impl Drop for MemoryLimit {
+    fn drop(&mut self) {
+        STATS
+            .used_memory
+            .fetch_sub(self.published as u64, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,577 @@
+//! Keyspace dumps for `rustis-cli --export` and `--import`: keys with their
+//! type, TTL and value as JSON lines or CSV, for backups and debugging
+//! without RDB tooling.
+//!
+//! `--export` walks the keyspace with `EXPORT`, a shard at a time, writing
+//! each key as it comes; like `SCAN`, it sees every key there from start to
+//! end, but isn't a snapshot of one moment. A JSON line is a key:
+//!
+//! ```text
+//! {"key":"fruits","type":"list","ttl":-1,"value":["apple","pear"]}
+//! ```
+//!
+//! with strings, a key or an element, as JSON strings if they are UTF-8 and
+//! as `{"base64":"..."}` if not. In CSV, under a `key,type,ttl,value`
+//! header, each element of a list or set is a row of its own, and the bytes
+//! are written as they are, quoted when they need to be. Values of module
+//! types can't be exported and are left out.
+//!
+//! `--import` reads either back and streams it to the server as commands:
+//! a `DEL` of each key, so it is replaced, then `SET`, with `PX` for a
+//! string's TTL, `RPUSH` or `SADD`.
+
+use std::{
+    fmt,
+    io::{self, BufRead, Read, Write},
+};
+
+use bytes::{Bytes, BytesMut};
+use serde_json::{json, Map, Value};
+
+use crate::{cli::Connection, message::ResponseValue};
+
+/// Elements sent per `RPUSH` or `SADD` when importing.
+pub const IMPORT_BATCH: usize = 1000;
+
+/// What a dump is written in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// A JSON object per key, per line.
+    Json,
+    /// `key,type,ttl,value` rows, a row per element.
+    Csv,
+}
+
+/// A key as dumped.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+    pub key: Bytes,
+    /// Milliseconds to live, -1 for none.
+    pub ttl: i64,
+    pub value: RecordValue,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RecordValue {
+    String(Bytes),
+    List(Vec<Bytes>),
+    Set(Vec<Bytes>),
+}
+
+impl RecordValue {
+    /// The type, as `EXPORT` and the dumps name it.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            RecordValue::String(_) => "string",
+            RecordValue::List(_) => "list",
+            RecordValue::Set(_) => "set",
+        }
+    }
+}
+
+/// Why an `EXPORT` entry has no record.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EntryError {
+    /// A value of this module type.
+    Module { key: Bytes, kind: String },
+    /// Not an entry as `EXPORT` replies with them.
+    Malformed,
+}
+
+impl fmt::Display for EntryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EntryError::Module { key, kind } => write!(
+                f,
+                "skipped '{}', a value of module type {kind}",
+                key.escape_ascii()
+            ),
+            EntryError::Malformed => write!(f, "malformed EXPORT entry"),
+        }
+    }
+}
+
+impl Record {
+    /// The record of an `[key, type, ttl, value]` entry of an `EXPORT` reply.
+    pub fn from_entry(entry: ResponseValue) -> Result<Self, EntryError> {
+        let ResponseValue::Array(Some(fields)) = entry else {
+            return Err(EntryError::Malformed);
+        };
+        let Ok(
+            [ResponseValue::BulkString(Some(key)), ResponseValue::BulkString(Some(kind)), ResponseValue::Integer(ttl), value],
+        ) = <[ResponseValue; 4]>::try_from(fields)
+        else {
+            return Err(EntryError::Malformed);
+        };
+        let elements = |value: ResponseValue| -> Result<Vec<Bytes>, EntryError> {
+            match value {
+                ResponseValue::Array(Some(items)) => items
+                    .into_iter()
+                    .map(|item| match item {
+                        ResponseValue::BulkString(Some(item)) => Ok(item),
+                        _ => Err(EntryError::Malformed),
+                    })
+                    .collect(),
+                _ => Err(EntryError::Malformed),
+            }
+        };
+        let value = match (&kind[..], value) {
+            (b"string", ResponseValue::BulkString(Some(value))) => RecordValue::String(value),
+            (b"list", value) => RecordValue::List(elements(value)?),
+            (b"set", value) => RecordValue::Set(elements(value)?),
+            (_, ResponseValue::BulkString(None)) => {
+                return Err(EntryError::Module {
+                    key,
+                    kind: String::from_utf8_lossy(&kind).into_owned(),
+                });
+            }
+            _ => return Err(EntryError::Malformed),
+        };
+        Ok(Record { key, ttl, value })
+    }
+
+    /// The commands recreating the key: `first` says whether to delete what
+    /// is there first, so the key is replaced rather than added to.
+    pub fn commands(&self, first: bool) -> Vec<ResponseValue> {
+        let command = |name: &'static str, args: &[Bytes]| {
+            let mut items = vec![ResponseValue::BulkString(Some(name.into()))];
+            items.push(ResponseValue::BulkString(Some(self.key.clone())));
+            items.extend(
+                args.iter()
+                    .cloned()
+                    .map(Some)
+                    .map(ResponseValue::BulkString),
+            );
+            ResponseValue::Array(Some(items))
+        };
+        let mut commands = Vec::new();
+        if first {
+            commands.push(command("DEL", &[]));
+        }
+        match &self.value {
+            // a key expiring this very millisecond still gets one
+            RecordValue::String(value) if self.ttl >= 0 => commands.push(command(
+                "SET",
+                &[
+                    value.clone(),
+                    Bytes::from("PX"),
+                    self.ttl.max(1).to_string().into(),
+                ],
+            )),
+            RecordValue::String(value) => {
+                commands.push(command("SET", std::slice::from_ref(value)))
+            }
+            RecordValue::List(items) => commands.extend(
+                items
+                    .chunks(IMPORT_BATCH)
+                    .map(|chunk| command("RPUSH", chunk)),
+            ),
+            RecordValue::Set(members) => commands.extend(
+                members
+                    .chunks(IMPORT_BATCH)
+                    .map(|chunk| command("SADD", chunk)),
+            ),
+        }
+        commands
+    }
+}
+
+/// Writes the keys matching `pattern`, or every key, to `out`, walking the
+/// keyspace `EXPORT` by `EXPORT`; values of module types are passed to
+/// `on_skip` instead. Returns the keys written.
+pub fn export<W: Write>(
+    connection: &mut Connection,
+    pattern: Option<&str>,
+    writer: &mut Writer<W>,
+    mut on_skip: impl FnMut(&EntryError),
+) -> io::Result<usize> {
+    let mut cursor = b"0".to_vec();
+    let mut keys = 0;
+    loop {
+        let mut args = vec![b"EXPORT".to_vec(), cursor];
+        if let Some(pattern) = pattern {
+            args.extend([b"MATCH".to_vec(), pattern.as_bytes().to_vec()]);
+        }
+        let parts = match connection.command(&args)? {
+            ResponseValue::Array(Some(parts)) => parts,
+            ResponseValue::Error(message) => {
+                return Err(io::Error::other(
+                    String::from_utf8_lossy(&message).into_owned(),
+                ));
+            }
+            _ => return Err(invalid("malformed EXPORT reply")),
+        };
+        let Ok([ResponseValue::BulkString(Some(next)), ResponseValue::Array(Some(entries))]) =
+            <[ResponseValue; 2]>::try_from(parts)
+        else {
+            return Err(invalid("malformed EXPORT reply"));
+        };
+        for entry in entries {
+            match Record::from_entry(entry) {
+                Ok(record) => {
+                    writer.write(&record)?;
+                    keys += 1;
+                }
+                Err(err) => on_skip(&err),
+            }
+        }
+        if next[..] == *b"0" {
+            writer.flush()?;
+            return Ok(keys);
+        }
+        cursor = next.to_vec();
+    }
+}
+
+/// Writes records to a dump.
+pub struct Writer<W: Write> {
+    out: W,
+    format: Format,
+    header: bool,
+}
+
+impl<W: Write> Writer<W> {
+    pub fn new(out: W, format: Format) -> Self {
+        Self {
+            out,
+            format,
+            header: false,
+        }
+    }
+
+    pub fn write(&mut self, record: &Record) -> io::Result<()> {
+        match self.format {
+            Format::Json => {
+                let value = match &record.value {
+                    RecordValue::String(value) => json_bytes(value),
+                    RecordValue::List(items) | RecordValue::Set(items) => {
+                        Value::Array(items.iter().map(|item| json_bytes(item)).collect())
+                    }
+                };
+                // field by field, as a map would sort them
+                self.out.write_all(b"{\"key\":")?;
+                serde_json::to_writer(&mut self.out, &json_bytes(&record.key))?;
+                write!(
+                    self.out,
+                    ",\"type\":\"{}\",\"ttl\":{},\"value\":",
+                    record.value.kind(),
+                    record.ttl
+                )?;
+                serde_json::to_writer(&mut self.out, &value)?;
+                self.out.write_all(b"}\n")
+            }
+            Format::Csv => {
+                if !self.header {
+                    self.out.write_all(b"key,type,ttl,value\r\n")?;
+                    self.header = true;
+                }
+                let items = match &record.value {
+                    RecordValue::String(value) => std::slice::from_ref(value),
+                    RecordValue::List(items) | RecordValue::Set(items) => items,
+                };
+                let ttl = record.ttl.to_string();
+                for item in items {
+                    let fields = [
+                        &record.key[..],
+                        record.value.kind().as_bytes(),
+                        ttl.as_bytes(),
+                        &item[..],
+                    ];
+                    for (i, field) in fields.into_iter().enumerate() {
+                        if i > 0 {
+                            self.out.write_all(b",")?;
+                        }
+                        write_csv_field(&mut self.out, field)?;
+                    }
+                    self.out.write_all(b"\r\n")?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+fn json_bytes(bytes: &[u8]) -> Value {
+    match std::str::from_utf8(bytes) {
+        Ok(text) => Value::String(text.to_string()),
+        Err(_) => json!({ "base64": base64_encode(bytes) }),
+    }
+}
+
+fn write_csv_field(out: &mut impl Write, field: &[u8]) -> io::Result<()> {
+    if !field
+        .iter()
+        .any(|c| matches!(c, b',' | b'"' | b'\r' | b'\n'))
+    {
+        return out.write_all(field);
+    }
+    out.write_all(b"\"")?;
+    for part in field.split_inclusive(|&c| c == b'"') {
+        out.write_all(part)?;
+        if part.ends_with(b"\"") {
+            out.write_all(b"\"")?;
+        }
+    }
+    out.write_all(b"\"")
+}
+
+/// Reads the records of a dump. A CSV row is a record of one element; the
+/// rows of a key follow each other, as they were written.
+pub struct Reader<R: BufRead> {
+    input: R,
+    format: Format,
+    /// Lines read so far, to say where a dump is broken.
+    line: usize,
+}
+
+impl<R: BufRead> Reader<R> {
+    pub fn new(input: R, format: Format) -> Self {
+        Self {
+            input,
+            format,
+            line: 0,
+        }
+    }
+
+    /// The next record, `None` at the end of the dump.
+    pub fn next_record(&mut self) -> io::Result<Option<Record>> {
+        let record = match self.format {
+            Format::Json => self.next_json(),
+            Format::Csv => self.next_csv(),
+        };
+        record.map_err(|err| match err.kind() {
+            io::ErrorKind::InvalidData => {
+                io::Error::new(err.kind(), format!("line {}: {err}", self.line))
+            }
+            _ => err,
+        })
+    }
+
+    fn next_json(&mut self) -> io::Result<Option<Record>> {
+        let mut line = String::new();
+        loop {
+            line.clear();
+            if self.input.read_line(&mut line)? == 0 {
+                return Ok(None);
+            }
+            self.line += 1;
+            if !line.trim().is_empty() {
+                break;
+            }
+        }
+        let object: Map<String, Value> = serde_json::from_str(&line).map_err(invalid)?;
+        let field = |name: &str| {
+            object
+                .get(name)
+                .ok_or_else(|| invalid(format!("no \"{name}\"")))
+        };
+        let key = bytes_from_json(field("key")?)?;
+        let ttl = field("ttl")?
+            .as_i64()
+            .ok_or_else(|| invalid("\"ttl\" isn't an integer"))?;
+        let kind = field("type")?.as_str().unwrap_or_default();
+        let elements = || -> io::Result<Vec<Bytes>> {
+            match field("value")? {
+                Value::Array(items) => items.iter().map(bytes_from_json).collect(),
+                _ => Err(invalid("\"value\" isn't an array")),
+            }
+        };
+        let value = match kind {
+            "string" => RecordValue::String(bytes_from_json(field("value")?)?),
+            "list" => RecordValue::List(elements()?),
+            "set" => RecordValue::Set(elements()?),
+            _ => return Err(invalid(format!("unknown type {:?}", field("type")?))),
+        };
+        Ok(Some(Record { key, ttl, value }))
+    }
+
+    fn next_csv(&mut self) -> io::Result<Option<Record>> {
+        loop {
+            let Some(row) = self.csv_row()? else {
+                return Ok(None);
+            };
+            if row.len() == 1 && row[0].is_empty() {
+                continue;
+            }
+            let Ok([key, kind, ttl, item]) = <[Vec<u8>; 4]>::try_from(row) else {
+                return Err(invalid("a row has 4 fields"));
+            };
+            if self.line == 1 && key == b"key" && kind == b"type" {
+                continue;
+            }
+            let ttl = std::str::from_utf8(&ttl)
+                .ok()
+                .and_then(|ttl| ttl.parse().ok())
+                .ok_or_else(|| invalid("the ttl isn't an integer"))?;
+            let item = Bytes::from(item);
+            let value = match &kind[..] {
+                b"string" => RecordValue::String(item),
+                b"list" => RecordValue::List(vec![item]),
+                b"set" => RecordValue::Set(vec![item]),
+                _ => {
+                    let kind = String::from_utf8_lossy(&kind);
+                    return Err(invalid(format!("unknown type {kind:?}")));
+                }
+            };
+            return Ok(Some(Record {
+                key: key.into(),
+                ttl,
+                value,
+            }));
+        }
+    }
+
+    /// The fields of the next row, which a quoted field may spread over
+    /// several lines.
+    fn csv_row(&mut self) -> io::Result<Option<Vec<Vec<u8>>>> {
+        let mut line = Vec::new();
+        if self.input.read_until(b'\n', &mut line)? == 0 {
+            return Ok(None);
+        }
+        self.line += 1;
+        let mut fields = vec![Vec::new()];
+        let mut quoted = false;
+        let mut at = 0;
+        loop {
+            let Some(&c) = line.get(at) else {
+                if !quoted {
+                    break;
+                }
+                // the newline was inside quotes
+                if self.input.read_until(b'\n', &mut line)? == 0 {
+                    return Err(invalid("a quoted field isn't closed"));
+                }
+                self.line += 1;
+                continue;
+            };
+            at += 1;
+            let field = fields.last_mut().expect("one field at least");
+            match (quoted, c) {
+                (true, b'"') if line.get(at) == Some(&b'"') => {
+                    field.push(b'"');
+                    at += 1;
+                }
+                (true, b'"') => quoted = false,
+                (true, c) => field.push(c),
+                (false, b'"') if field.is_empty() => quoted = true,
+                (false, b',') => fields.push(Vec::new()),
+                (false, b'\r') if line[at..] == *b"\n" => {}
+                (false, b'\n') => {}
+                (false, c) => field.push(c),
+            }
+        }
+        Ok(Some(fields))
+    }
+}
+
+fn invalid(message: impl fmt::Display) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+fn bytes_from_json(value: &Value) -> io::Result<Bytes> {
+    match value {
+        Value::String(text) => Ok(Bytes::copy_from_slice(text.as_bytes())),
+        Value::Object(object) => object
+            .get("base64")
+            .and_then(Value::as_str)
+            .and_then(base64_decode)
+            .map(Bytes::from)
+            .ok_or_else(|| invalid("not a string, nor {\"base64\": ...}")),
+        _ => Err(invalid("not a string, nor {\"base64\": ...}")),
+    }
+}
+
+/// The commands to import a dump, as RESP, read as a stream: each key is
+/// replaced, and lists and sets with a TTL are imported without one, as
+/// nothing can give them a TTL yet, and counted.
+pub struct Importer<R: BufRead> {
+    reader: Reader<R>,
+    /// RESP not read yet.
+    pending: BytesMut,
+    /// The key of the last record, whose rows may go on.
+    last_key: Option<Bytes>,
+    pub keys: usize,
+    pub ttls_dropped: usize,
+}
+
+impl<R: BufRead> Importer<R> {
+    pub fn new(input: R, format: Format) -> Self {
+        Self {
+            reader: Reader::new(input, format),
+            pending: BytesMut::new(),
+            last_key: None,
+            keys: 0,
+            ttls_dropped: 0,
+        }
+    }
+}
+
+impl<R: BufRead> Read for Importer<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pending.is_empty() {
+            let Some(record) = self.reader.next_record()? else {
+                return Ok(0);
+            };
+            let first = self.last_key.as_ref() != Some(&record.key);
+            if first {
+                self.keys += 1;
+                if record.ttl >= 0 && !matches!(record.value, RecordValue::String(_)) {
+                    self.ttls_dropped += 1;
+                }
+                self.last_key = Some(record.key.clone());
+            }
+            for command in record.commands(first) {
+                command.serialize(&mut self.pending);
+            }
+        }
+        let len = buf.len().min(self.pending.len());
+        buf[..len].copy_from_slice(&self.pending.split_to(len));
+        Ok(len)
+    }
+}
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn base64_encode(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, &b)| n | (b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(BASE64[(n >> (18 - 6 * i) & 63) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+fn base64_decode(text: &str) -> Option<Vec<u8>> {
+    let text = text.as_bytes();
+    if !text.len().is_multiple_of(4) {
+        return None;
+    }
+    let mut out = Vec::with_capacity(text.len() / 4 * 3);
+    for chunk in text.chunks(4) {
+        let padding = chunk.iter().rev().take_while(|&&c| c == b'=').count();
+        if padding > 2 {
+            return None;
+        }
+        let mut n = 0u32;
+        for &c in &chunk[..4 - padding] {
+            let digit = BASE64.iter().position(|&d| d == c)? as u32;
+            n = n << 6 | digit;
+        }
+        n <<= 6 * padding;
+        out.extend_from_slice(&n.to_be_bytes()[1..4 - padding]);
+    }
+    Some(out)
+}
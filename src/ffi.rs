@@ -0,0 +1,157 @@
+//! A C ABI for the store, so programs in other languages can keep a rustis
+//! keyspace in-process as a cache, declared for C in `include/rustis.h`.
+//! Built with the `ffi` feature, on the runtime-free core, as a library C
+//! links against:
+//!
+//! ```text
+//! cargo rustc --release --lib --no-default-features --features ffi,lists,sets --crate-type staticlib
+//! ```
+//!
+//! (or `cdylib` for a shared library). A `rustis_store` is one `KvStore`
+//! behind a lock, so it can be shared between threads, which take turns.
+//! Commands go in and replies come out as RESP, the bytes a client would
+//! send and get, with commands run by `process_command`: those the server's
+//! router answers itself, like `ECHO` or `INFO`, are unknown here. A command
+//! that panics gets an error reply, as it would from a worker.
+
+use std::{
+    panic::{self, AssertUnwindSafe},
+    slice,
+    sync::{Mutex, PoisonError},
+};
+
+use bytes::BytesMut;
+
+use crate::{
+    config::Config,
+    handler::process_command,
+    kv::KvStore,
+    message::ResponseValue,
+    parser::{parse_request, BufParseError},
+};
+
+/// The reply to a command that panicked.
+pub const PANIC_ERROR: &str = "ERR internal error";
+
+/// The reply to a command cut short at the end of the request.
+pub const INCOMPLETE_ERROR: &str = "ERR Protocol error: incomplete command";
+
+/// A keyspace, as C holds it.
+#[allow(non_camel_case_types)]
+pub struct rustis_store(Mutex<KvStore>);
+
+/// The RESP encoded replies to a request.
+#[allow(non_camel_case_types)]
+pub struct rustis_reply(Vec<u8>);
+
+impl rustis_store {
+    /// Runs every command in `request` and returns their replies, in order.
+    /// The commands must be whole: after one that isn't, or that can't be
+    /// parsed, there is an error reply and the rest is ignored, as a server
+    /// would close the connection.
+    pub fn execute(&self, request: &[u8]) -> Vec<u8> {
+        let mut request = BytesMut::from(request);
+        let mut replies = BytesMut::new();
+        let mut kv = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        while !request.is_empty() {
+            let command = match parse_request(&mut request) {
+                Ok(command) => command,
+                Err(err) => {
+                    let message = match err {
+                        BufParseError::Incomplete => INCOMPLETE_ERROR.into(),
+                        err => err.reply_message(),
+                    };
+                    ResponseValue::Error(message.into()).serialize(&mut replies);
+                    break;
+                }
+            };
+            panic::catch_unwind(AssertUnwindSafe(|| process_command(&mut kv, command)))
+                .unwrap_or_else(|_| ResponseValue::Error(PANIC_ERROR.into()))
+                .serialize(&mut replies);
+        }
+        replies.into()
+    }
+}
+
+/// A new, empty keyspace with the default config, freed with
+/// `rustis_store_free`.
+#[unsafe(no_mangle)]
+pub extern "C" fn rustis_store_new() -> *mut rustis_store {
+    let kv = KvStore::from_config(&Config::default());
+    Box::into_raw(Box::new(rustis_store(Mutex::new(kv))))
+}
+
+/// Frees `store` and everything in it; NULL is ignored.
+///
+/// # Safety
+///
+/// `store` is NULL or from `rustis_store_new`, not freed yet, and not in
+/// use on another thread.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn rustis_store_free(store: *mut rustis_store) {
+    if !store.is_null() {
+        // SAFETY: the caller gives back what `rustis_store_new` boxed
+        drop(unsafe { Box::from_raw(store) });
+    }
+}
+
+/// Runs the commands in the `len` bytes at `request`, see
+/// `rustis_store::execute`, returning their replies, to be freed with
+/// `rustis_reply_free`. NULL only if `store` is.
+///
+/// # Safety
+///
+/// `store` is NULL or a live store, and `request` points to `len` readable
+/// bytes, or is anything when `len` is 0.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn rustis_execute(
+    store: *const rustis_store,
+    request: *const u8,
+    len: usize,
+) -> *mut rustis_reply {
+    // SAFETY: the caller says the store is live
+    let Some(store) = (unsafe { store.as_ref() }) else {
+        return std::ptr::null_mut();
+    };
+    let request = match len {
+        0 => &[][..],
+        // SAFETY: the caller says these bytes are readable
+        len => unsafe { slice::from_raw_parts(request, len) },
+    };
+    Box::into_raw(Box::new(rustis_reply(store.execute(request))))
+}
+
+/// The first byte of `reply`, valid until it is freed.
+///
+/// # Safety
+///
+/// `reply` is from `rustis_execute` and not freed yet.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn rustis_reply_data(reply: *const rustis_reply) -> *const u8 {
+    // SAFETY: the caller says the reply is live
+    unsafe { (*reply).0.as_ptr() }
+}
+
+/// The length of `reply` in bytes.
+///
+/// # Safety
+///
+/// Like `rustis_reply_data`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn rustis_reply_len(reply: *const rustis_reply) -> usize {
+    // SAFETY: the caller says the reply is live
+    unsafe { (*reply).0.len() }
+}
+
+/// Frees `reply`; NULL is ignored.
+///
+/// # Safety
+///
+/// `reply` is NULL or from `rustis_execute`, and not freed yet.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn rustis_reply_free(reply: *mut rustis_reply) {
+    if !reply.is_null() {
+        // SAFETY: the caller gives back what `rustis_execute` boxed
+        drop(unsafe { Box::from_raw(reply) });
+    }
+}
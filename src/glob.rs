@@ -0,0 +1,73 @@
+//! Glob-style patterns, as Redis matches keys against them for `MATCH`
+//! options: `*` is any run of bytes, `?` any one byte, `[abc]`, `[^abc]`
+//! and `[a-z]` a byte of a class, and `\` takes the byte after it as it is.
+
+/// Whether `pattern` matches the whole of `string`. Like Redis, a class left
+/// open runs to the end of the pattern.
+pub fn glob_match(pattern: &[u8], string: &[u8]) -> bool {
+    let (mut p, mut s) = (0, 0);
+    // where the last `*` was, and how much of `string` it has taken so far
+    let mut star = None;
+    while s < string.len() {
+        if pattern.get(p) == Some(&b'*') {
+            p += 1;
+            star = Some((p, s));
+            continue;
+        }
+        if let Some(len) = match_one(&pattern[p..], string[s]) {
+            p += len;
+            s += 1;
+            continue;
+        }
+        // let the last `*` take one more byte and try again from there
+        let Some((after_star, taken)) = star else {
+            return false;
+        };
+        p = after_star;
+        s = taken + 1;
+        star = Some((after_star, s));
+    }
+    pattern[p..].iter().all(|&c| c == b'*')
+}
+
+/// If the start of `pattern`, a single byte's worth of it, matches `c`:
+/// how long that start is.
+fn match_one(pattern: &[u8], c: u8) -> Option<usize> {
+    match pattern {
+        [] | [b'*', ..] => None,
+        [b'?', ..] => Some(1),
+        [b'\\', escaped, ..] => (*escaped == c).then_some(2),
+        [b'[', class @ ..] => {
+            let (matched, len) = match_class(class, c);
+            matched.then_some(len + 1)
+        }
+        [literal, ..] => (*literal == c).then_some(1),
+    }
+}
+
+/// Whether `c` is in the class `class` starts with, past its `[`, and how
+/// long the class is, its `]` included.
+fn match_class(class: &[u8], c: u8) -> (bool, usize) {
+    let negated = class.first() == Some(&b'^');
+    let mut i = usize::from(negated);
+    let mut matched = false;
+    while i < class.len() && class[i] != b']' {
+        match class[i..] {
+            [b'\\', escaped, ..] => {
+                matched |= escaped == c;
+                i += 2;
+            }
+            [start, b'-', end, ..] if end != b']' => {
+                let (low, high) = (start.min(end), start.max(end));
+                matched |= (low..=high).contains(&c);
+                i += 3;
+            }
+            [member, ..] => {
+                matched |= member == c;
+                i += 1;
+            }
+            [] => unreachable!("checked above"),
+        }
+    }
+    (matched != negated, (i + 1).min(class.len()))
+}
@@ -1,7 +1,39 @@
 use bytes::Bytes;
 
-use crate::kv::{KvStore, RedisValue};
+use crate::glob::glob_match;
+#[cfg(any(feature = "lists", feature = "sets"))]
+use crate::kv::DatabaseError;
+use crate::kv::{unix_time_ms, KvStore, RedisValue};
 use crate::message::ResponseValue;
+use crate::module;
+use crate::router;
+use crate::string::EMBSTR_SIZE_LIMIT;
+
+/// Arguments shorter than this are copied before being stored, longer ones
+/// are stored as they are. Same cut-off as Redis' `PROTO_MBULK_BIG_ARG`.
+pub const COMPACT_THRESHOLD: usize = 32 * 1024;
+
+/// `bytes` ready to be stored. Parsed arguments are slices of the
+/// connection's read buffer, so keeping a small one as is would pin the
+/// whole buffer for as long as the key lives; a big one makes up most of
+/// what it pins and is shared rather than copied.
+pub fn compact(bytes: &Bytes) -> Bytes {
+    if bytes.len() < COMPACT_THRESHOLD {
+        Bytes::copy_from_slice(bytes)
+    } else {
+        bytes.clone()
+    }
+}
+
+/// A string value ready to be stored. Short ones are copied inline by the
+/// store anyway, so copying them here first would be wasted.
+fn compact_value(bytes: &Bytes) -> Bytes {
+    if bytes.len() <= EMBSTR_SIZE_LIMIT {
+        bytes.clone()
+    } else {
+        compact(bytes)
+    }
+}
 
 fn parse_int(value: &ResponseValue) -> Result<i64, Bytes> {
     match value {
@@ -15,7 +47,215 @@ fn parse_int(value: &ResponseValue) -> Result<i64, Bytes> {
     }
 }
 
-pub fn process_command(kv: &KvStore, value: ResponseValue) -> ResponseValue {
+/// The count of a pop, as Redis takes it: a non-negative integer.
+#[cfg(any(feature = "lists", feature = "sets"))]
+fn parse_count(value: &ResponseValue) -> Result<i64, ResponseValue> {
+    match parse_int(value) {
+        Ok(count) if count >= 0 => Ok(count),
+        _ => Err(ResponseValue::Error(
+            "ERR value is out of range, must be positive".into(),
+        )),
+    }
+}
+
+/// Commands that may grow the dataset, refused while over `maxmemory` (Redis'
+/// `denyoom` flag).
+const DENYOOM_COMMANDS: &[&[u8]] = &[
+    b"SET",
+    b"GETSET",
+    b"SETEX",
+    b"PSETEX",
+    b"SETNX",
+    b"MSET",
+    #[cfg(feature = "lists")]
+    b"LPUSH",
+    #[cfg(feature = "lists")]
+    b"RPUSH",
+    #[cfg(feature = "sets")]
+    b"SADD",
+];
+
+/// Commands that change the dataset, as the audit log records them.
+const WRITE_COMMANDS: &[&[u8]] = &[
+    b"SET",
+    b"GETSET",
+    b"SETEX",
+    b"PSETEX",
+    b"SETNX",
+    b"MSET",
+    b"DEL",
+    b"UNLINK",
+    #[cfg(feature = "lists")]
+    b"LPUSH",
+    #[cfg(feature = "lists")]
+    b"RPUSH",
+    #[cfg(feature = "lists")]
+    b"LPOP",
+    #[cfg(feature = "lists")]
+    b"RPOP",
+    #[cfg(feature = "sets")]
+    b"SADD",
+    #[cfg(feature = "sets")]
+    b"SPOP",
+    b"FLUSHALL",
+    b"FLUSHDB",
+];
+
+pub const OOM_ERROR: &str = "OOM command not allowed when used memory > 'maxmemory'.";
+
+/// Whether `value` is a command that must be refused when out of memory.
+pub fn denies_oom(value: &ResponseValue) -> bool {
+    match value {
+        ResponseValue::Array(Some(items)) => match items.first() {
+            Some(ResponseValue::BulkString(Some(cmd))) => {
+                DENYOOM_COMMANDS
+                    .iter()
+                    .any(|name| cmd.eq_ignore_ascii_case(name))
+                    || module::find(cmd).is_some_and(|command| command.flags.denyoom)
+            }
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+/// Whether `value` is a command that changes the dataset.
+pub fn is_write_command(value: &ResponseValue) -> bool {
+    match value {
+        ResponseValue::Array(Some(items)) => match items.first() {
+            Some(ResponseValue::BulkString(Some(cmd))) => {
+                WRITE_COMMANDS
+                    .iter()
+                    .any(|name| cmd.eq_ignore_ascii_case(name))
+                    || module::find(cmd).is_some_and(|command| command.flags.write)
+            }
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+/// A command `process_command` runs.
+struct Command {
+    /// By which INFO `latencystats` reports it.
+    name: &'static str,
+    /// Least and most arguments it takes, past its name.
+    min: usize,
+    max: usize,
+    /// Arguments past the least come in groups this big, like `MSET`'s key
+    /// and value pairs.
+    group: usize,
+}
+
+/// No most arguments.
+const ANY: usize = usize::MAX;
+
+const fn command(name: &'static str, min: usize, max: usize) -> Command {
+    Command {
+        name,
+        min,
+        max,
+        group: 1,
+    }
+}
+
+impl Command {
+    fn takes(&self, args: usize) -> bool {
+        (self.min..=self.max).contains(&args) && (args - self.min).is_multiple_of(self.group)
+    }
+}
+
+/// Every command `process_command` runs, and the arguments it takes,
+/// checked before it runs. Those of data types left out of the build aren't
+/// commands at all.
+const COMMANDS: &[Command] = &[
+    command("ping", 0, 1),
+    command("config", 1, ANY),
+    command("dbsize", 0, 0),
+    command("flushall", 0, ANY),
+    command("flushdb", 0, ANY),
+    command("memory", 1, ANY),
+    command("object", 1, ANY),
+    command("get", 1, 1),
+    command("set", 2, ANY),
+    command("getset", 2, 2),
+    command("setex", 3, 3),
+    command("psetex", 3, 3),
+    command("setnx", 2, 2),
+    command("mget", 1, ANY),
+    Command {
+        group: 2,
+        ..command("mset", 2, ANY)
+    },
+    command("del", 1, ANY),
+    command("unlink", 1, ANY),
+    command("exists", 1, ANY),
+    command("export", 1, ANY),
+    #[cfg(feature = "lists")]
+    command("lpush", 2, ANY),
+    #[cfg(feature = "lists")]
+    command("lpop", 1, 2),
+    #[cfg(feature = "lists")]
+    command("rpush", 2, ANY),
+    #[cfg(feature = "lists")]
+    command("rpop", 1, 2),
+    #[cfg(feature = "lists")]
+    command("lrange", 3, 3),
+    #[cfg(feature = "sets")]
+    command("sadd", 2, ANY),
+    #[cfg(feature = "sets")]
+    command("spop", 1, ANY),
+    #[cfg(feature = "sets")]
+    command("smembers", 1, 1),
+    #[cfg(feature = "sets")]
+    command("sscan", 2, 4),
+];
+
+fn find_command(name: &[u8]) -> Option<&'static Command> {
+    COMMANDS
+        .iter()
+        .find(|command| name.eq_ignore_ascii_case(command.name.as_bytes()))
+}
+
+/// The error reply to the command in `items` if it is one a worker runs,
+/// given the wrong number of arguments: `ERR wrong number of arguments for
+/// 'name' command`, as Redis has it.
+pub fn arity_error(items: &[ResponseValue]) -> Option<ResponseValue> {
+    let Some((ResponseValue::BulkString(Some(name)), args)) = items.split_first() else {
+        return None;
+    };
+    let command = find_command(name)?;
+    (!command.takes(args.len())).then(|| {
+        ResponseValue::Error(
+            format!(
+                "ERR wrong number of arguments for '{}' command",
+                command.name
+            )
+            .into(),
+        )
+    })
+}
+
+/// Whether the server runs a command called `name` itself, on a worker.
+pub(crate) fn is_builtin(name: &[u8]) -> bool {
+    find_command(name).is_some()
+}
+
+/// The name of the command in `value`, if it is one a worker runs, a
+/// module's included.
+pub fn command_name(value: &ResponseValue) -> Option<&'static str> {
+    match value {
+        ResponseValue::Array(Some(items)) => match items.first() {
+            Some(ResponseValue::BulkString(Some(cmd))) => find_command(cmd)
+                .map(|command| command.name)
+                .or_else(|| Some(module::find(cmd)?.name)),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+pub fn process_command(kv: &mut KvStore, value: ResponseValue) -> ResponseValue {
     let items = match value {
         ResponseValue::Array(Some(items)) => items,
         _ => return ResponseValue::Error("request must be array".into()),
@@ -29,16 +269,70 @@ pub fn process_command(kv: &KvStore, value: ResponseValue) -> ResponseValue {
         Some((ResponseValue::BulkString(Some(bytes)), rest)) => (bytes, rest),
         _ => return ResponseValue::Error("command must be bulk string".into()),
     };
+    if let Some(error) = arity_error(&items) {
+        return error;
+    }
 
     if cmd.eq_ignore_ascii_case(b"PING") {
         ResponseValue::SimpleString("PONG".into())
     } else if cmd.eq_ignore_ascii_case(b"CONFIG") {
-        ResponseValue::Array(None)
+        router::config(args)
+    } else if cmd.eq_ignore_ascii_case(b"DBSIZE") {
+        ResponseValue::Integer(kv.len() as i64)
+    } else if cmd.eq_ignore_ascii_case(b"FLUSHALL") || cmd.eq_ignore_ascii_case(b"FLUSHDB") {
+        handle_flush(kv, args)
+    } else if cmd.eq_ignore_ascii_case(b"MEMORY") {
+        handle_memory_usage(kv, args)
+    } else if cmd.eq_ignore_ascii_case(b"OBJECT") {
+        handle_object(kv, args)
     } else if cmd.eq_ignore_ascii_case(b"GET") {
         handle_get(kv, args)
     } else if cmd.eq_ignore_ascii_case(b"SET") {
         handle_set(kv, args)
-    } else if cmd.eq_ignore_ascii_case(b"LPUSH") {
+    } else if cmd.eq_ignore_ascii_case(b"GETSET") {
+        handle_getset(kv, args)
+    } else if cmd.eq_ignore_ascii_case(b"SETEX") {
+        handle_setex(kv, args, 1000)
+    } else if cmd.eq_ignore_ascii_case(b"PSETEX") {
+        handle_setex(kv, args, 1)
+    } else if cmd.eq_ignore_ascii_case(b"SETNX") {
+        handle_setnx(kv, args)
+    } else if cmd.eq_ignore_ascii_case(b"MGET") {
+        handle_mget(kv, args)
+    } else if cmd.eq_ignore_ascii_case(b"MSET") {
+        handle_mset(kv, args)
+    } else if cmd.eq_ignore_ascii_case(b"DEL") {
+        handle_del(kv, args, false)
+    } else if cmd.eq_ignore_ascii_case(b"UNLINK") {
+        handle_del(kv, args, true)
+    } else if cmd.eq_ignore_ascii_case(b"EXISTS") {
+        handle_exists(kv, args)
+    } else if cmd.eq_ignore_ascii_case(b"EXPORT") {
+        handle_export(kv, args)
+    } else {
+        #[cfg(feature = "lists")]
+        if let Some(reply) = process_list_command(kv, cmd, args) {
+            return reply;
+        }
+        #[cfg(feature = "sets")]
+        if let Some(reply) = process_set_command(kv, cmd, args) {
+            return reply;
+        }
+        if let Some(command) = module::find(cmd) {
+            return module::run(command, kv, args);
+        }
+        ResponseValue::Error("invalid command".into())
+    }
+}
+
+/// Runs `cmd` if it is a list command.
+#[cfg(feature = "lists")]
+fn process_list_command(
+    kv: &mut KvStore,
+    cmd: &[u8],
+    args: &[ResponseValue],
+) -> Option<ResponseValue> {
+    let reply = if cmd.eq_ignore_ascii_case(b"LPUSH") {
         handle_lpush(kv, args)
     } else if cmd.eq_ignore_ascii_case(b"LPOP") {
         handle_lpop(kv, args)
@@ -48,194 +342,647 @@ pub fn process_command(kv: &KvStore, value: ResponseValue) -> ResponseValue {
         handle_rpop(kv, args)
     } else if cmd.eq_ignore_ascii_case(b"LRANGE") {
         handle_lrange(kv, args)
-    } else if cmd.eq_ignore_ascii_case(b"SADD") {
+    } else {
+        return None;
+    };
+    Some(reply)
+}
+
+/// Runs `cmd` if it is a set command.
+#[cfg(feature = "sets")]
+fn process_set_command(
+    kv: &mut KvStore,
+    cmd: &[u8],
+    args: &[ResponseValue],
+) -> Option<ResponseValue> {
+    let reply = if cmd.eq_ignore_ascii_case(b"SADD") {
         handle_sadd(kv, args)
     } else if cmd.eq_ignore_ascii_case(b"SPOP") {
         handle_spop(kv, args)
     } else if cmd.eq_ignore_ascii_case(b"SMEMBERS") {
         handle_smembers(kv, args)
+    } else if cmd.eq_ignore_ascii_case(b"SSCAN") {
+        handle_sscan(kv, args)
     } else {
-        ResponseValue::Error("invalid command".into())
+        return None;
+    };
+    Some(reply)
+}
+
+/// `MEMORY USAGE key [SAMPLES count]`, the only `MEMORY` subcommand that
+/// reaches a worker. Sizes are tracked per key, so `SAMPLES` changes nothing.
+fn handle_memory_usage(kv: &KvStore, args: &[ResponseValue]) -> ResponseValue {
+    let key = match args {
+        [ResponseValue::BulkString(Some(sub)), ResponseValue::BulkString(Some(key)), rest @ ..]
+            if sub.eq_ignore_ascii_case(b"USAGE") =>
+        {
+            match rest {
+                [] => key,
+                [ResponseValue::BulkString(Some(option)), count]
+                    if option.eq_ignore_ascii_case(b"SAMPLES") && parse_int(count).is_ok() =>
+                {
+                    key
+                }
+                _ => return ResponseValue::Error("ERR syntax error".into()),
+            }
+        }
+        _ => return ResponseValue::Error("ERR unknown MEMORY subcommand".into()),
+    };
+
+    match kv.memory_usage(key) {
+        Some(bytes) => ResponseValue::Integer(bytes as i64),
+        None => ResponseValue::BulkString(None),
     }
 }
 
-fn handle_get(kv: &KvStore, args: &[ResponseValue]) -> ResponseValue {
-    if args.len() != 1 {
-        return ResponseValue::Error("ERR wrong number of arguments for 'get' command".into());
+/// `OBJECT HELP`, as Redis words it.
+const OBJECT_HELP: &[&str] = &[
+    "OBJECT <subcommand> [<arg> [value] [opt] ...]. Subcommands are:",
+    "ENCODING <key>",
+    "    Return the kind of internal representation used in order to store the value",
+    "    associated with a <key>.",
+    "FREQ <key>",
+    "    Return the access frequency index of the <key>. The returned integer is",
+    "    proportional to the logarithm of the recent access frequency of the key.",
+    "IDLETIME <key>",
+    "    Return the idle time of the <key>, that is the approximated number of",
+    "    seconds elapsed since the last access to the key.",
+    "REFCOUNT <key>",
+    "    Return the number of references of the value associated with the specified",
+    "    <key>.",
+    "HELP",
+    "    Print this help.",
+];
+
+/// `OBJECT HELP`, answered by the router as it takes no key. Gets the
+/// arguments after `OBJECT`.
+pub fn object_help(args: &[ResponseValue]) -> ResponseValue {
+    match args {
+        [_] => ResponseValue::Array(Some(
+            OBJECT_HELP
+                .iter()
+                .map(|line| ResponseValue::SimpleString(Bytes::from_static(line.as_bytes())))
+                .collect(),
+        )),
+        _ => ResponseValue::Error(
+            "ERR unknown subcommand or wrong number of arguments for 'help'. Try OBJECT HELP."
+                .into(),
+        ),
     }
+}
 
-    let key = match args.first() {
-        Some(ResponseValue::BulkString(Some(bytes))) => bytes,
-        Some(_) => return ResponseValue::Error("ERR key must be bulk string".into()),
-        None => return ResponseValue::Error("ERR invalid number of arguments".into()),
+fn handle_object(kv: &KvStore, args: &[ResponseValue]) -> ResponseValue {
+    if matches!(args.first(), Some(ResponseValue::BulkString(Some(sub))) if sub.eq_ignore_ascii_case(b"HELP"))
+    {
+        return object_help(args);
+    }
+    let (subcommand, key) = match args {
+        [ResponseValue::BulkString(Some(sub)), ResponseValue::BulkString(Some(key))] => (sub, key),
+        [ResponseValue::BulkString(Some(sub)), ..] => {
+            return ResponseValue::Error(
+                format!(
+                "ERR unknown subcommand or wrong number of arguments for '{}'. Try OBJECT HELP.",
+                String::from_utf8_lossy(sub)
+            )
+                .into(),
+            )
+        }
+        _ => {
+            return ResponseValue::Error(
+                "ERR wrong number of arguments for 'object' command".into(),
+            )
+        }
+    };
+
+    if subcommand.eq_ignore_ascii_case(b"ENCODING") {
+        return match kv.object_encoding(key) {
+            Some(encoding) => {
+                ResponseValue::BulkString(Some(Bytes::from_static(encoding.as_bytes())))
+            }
+            None => ResponseValue::BulkString(None),
+        };
+    }
+
+    // both are tracked whatever the maxmemory-policy, unlike Redis
+    let reply = if subcommand.eq_ignore_ascii_case(b"FREQ") {
+        kv.object_freq(key).map(i64::from)
+    } else if subcommand.eq_ignore_ascii_case(b"IDLETIME") {
+        kv.object_idletime(key).map(|seconds| seconds as i64)
+    } else if subcommand.eq_ignore_ascii_case(b"REFCOUNT") {
+        // values are never shared between keys, so each has the one
+        kv.contains_key(key).then_some(1)
+    } else {
+        return ResponseValue::Error(
+            format!(
+                "ERR unknown subcommand '{}'. Try OBJECT HELP.",
+                String::from_utf8_lossy(subcommand)
+            )
+            .into(),
+        );
+    };
+
+    match reply {
+        Some(value) => ResponseValue::Integer(value),
+        None => ResponseValue::BulkString(None),
+    }
+}
+
+fn handle_get(kv: &KvStore, args: &[ResponseValue]) -> ResponseValue {
+    let key = match &args[0] {
+        ResponseValue::BulkString(Some(bytes)) => bytes,
+        _ => return ResponseValue::Error("ERR key must be bulk string".into()),
     };
 
     match kv.get(key) {
-        Ok(Some(RedisValue::String(b))) => ResponseValue::BulkString(Some(b)),
-        Ok(Some(_)) => ResponseValue::Error(
+        Some(RedisValue::String(s)) => ResponseValue::BulkString(Some(s.to_bytes())),
+        Some(_) => ResponseValue::Error(
             "WRONGTYPE Operation against a key holding the wrong kind of value".into(),
         ),
-        Ok(None) => ResponseValue::BulkString(None),
-        Err(_) => ResponseValue::Error("internal server error".into()),
+        None => ResponseValue::BulkString(None),
+    }
+}
+
+/// The options of a `SET`, past its key and value.
+#[derive(Clone, Copy, Debug, Default)]
+struct SetOptions {
+    /// `GET`: reply with the value the key held before, instead of `OK`.
+    get: bool,
+    condition: Option<SetCondition>,
+    expiry: Option<SetExpiry>,
+}
+
+/// When a `SET` goes ahead.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum SetCondition {
+    /// `NX`: only if the key doesn't exist.
+    Missing,
+    /// `XX`: only if it does.
+    Exists,
+}
+
+/// What becomes of the key's TTL; without either, a `SET` clears it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum SetExpiry {
+    /// `KEEPTTL`: the key keeps the TTL it had.
+    Keep,
+    /// `EXAT` or `PXAT`: it expires then, in `unix_time_ms()`.
+    At(u64),
+    /// `EX` or `PX`: it expires then, less the jitter
+    /// `expire-jitter-percent` allows.
+    In(u64),
+}
+
+impl SetOptions {
+    fn parse(args: &[ResponseValue]) -> Result<Self, ResponseValue> {
+        let syntax_error = || ResponseValue::Error("ERR syntax error".into());
+        let mut options = SetOptions::default();
+        // like Redis, an option may be repeated, but not mixed with one it
+        // conflicts with
+        let mut expiry_option = None;
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let ResponseValue::BulkString(Some(option)) = arg else {
+                return Err(syntax_error());
+            };
+            let option = option.to_ascii_uppercase();
+            let condition = match option.as_slice() {
+                b"GET" => {
+                    options.get = true;
+                    continue;
+                }
+                b"NX" => SetCondition::Missing,
+                b"XX" => SetCondition::Exists,
+                _ => {
+                    // milliseconds per unit, and whether counted from the
+                    // Unix epoch rather than from now
+                    let expiry = match option.as_slice() {
+                        b"KEEPTTL" => None,
+                        b"EX" => Some((1000, false)),
+                        b"PX" => Some((1, false)),
+                        b"EXAT" => Some((1000, true)),
+                        b"PXAT" => Some((1, true)),
+                        _ => return Err(syntax_error()),
+                    };
+                    if expiry_option.as_ref().is_some_and(|given| *given != option) {
+                        return Err(syntax_error());
+                    }
+                    options.expiry = Some(match expiry {
+                        None => SetExpiry::Keep,
+                        Some((unit, absolute)) => {
+                            let time = args.next().ok_or_else(syntax_error)?;
+                            let at = expiry_time(time, unit, absolute, "set")?;
+                            if absolute {
+                                SetExpiry::At(at)
+                            } else {
+                                SetExpiry::In(at)
+                            }
+                        }
+                    });
+                    expiry_option = Some(option);
+                    continue;
+                }
+            };
+            if options.condition.is_some_and(|given| given != condition) {
+                return Err(syntax_error());
+            }
+            options.condition = Some(condition);
+        }
+        Ok(options)
     }
 }
 
-fn handle_set(kv: &KvStore, args: &[ResponseValue]) -> ResponseValue {
-    if args.len() != 2 {
-        return ResponseValue::Error("ERR wrong number of arguments for 'set' command".into());
+/// The time, in `unix_time_ms()`, `arg` stands for as a count of `unit`
+/// milliseconds from now, or from the Unix epoch if `absolute`. It must be
+/// positive, and not overflow.
+fn expiry_time(
+    arg: &ResponseValue,
+    unit: i64,
+    absolute: bool,
+    command: &str,
+) -> Result<u64, ResponseValue> {
+    let invalid =
+        || ResponseValue::Error(format!("ERR invalid expire time in '{command}' command").into());
+    let time = parse_int(arg).map_err(ResponseValue::Error)?;
+    if time <= 0 {
+        return Err(invalid());
     }
+    let from = if absolute { 0 } else { unix_time_ms() as i64 };
+    time.checked_mul(unit)
+        .and_then(|ms| ms.checked_add(from))
+        .map(|at| at as u64)
+        .ok_or_else(invalid)
+}
+
+fn handle_set(kv: &mut KvStore, args: &[ResponseValue]) -> ResponseValue {
+    match SetOptions::parse(&args[2..]) {
+        Ok(options) => set_with(kv, &args[0], &args[1], options),
+        Err(reply) => reply,
+    }
+}
 
-    let key = match args.first() {
-        Some(ResponseValue::BulkString(Some(bytes))) => Bytes::copy_from_slice(bytes),
-        Some(_) => return ResponseValue::Error("ERR key must be bulk string".into()),
-        None => return ResponseValue::Error("ERR invalid number of arguments".into()),
+/// `GETSET key value`, the legacy spelling of `SET key value GET`.
+fn handle_getset(kv: &mut KvStore, args: &[ResponseValue]) -> ResponseValue {
+    let options = SetOptions {
+        get: true,
+        ..SetOptions::default()
     };
+    set_with(kv, &args[0], &args[1], options)
+}
 
-    let value = match args.get(1) {
-        Some(ResponseValue::BulkString(Some(bytes))) => Bytes::copy_from_slice(bytes),
-        Some(_) => return ResponseValue::Error("ERR value must be bulk string".into()),
-        None => return ResponseValue::Error("ERR invalid number of arguments".into()),
+/// `SETEX key seconds value` and `PSETEX key milliseconds value`, the legacy
+/// spellings of `SET key value EX seconds` and `PX milliseconds`.
+fn handle_setex(kv: &mut KvStore, args: &[ResponseValue], unit: i64) -> ResponseValue {
+    let command = if unit == 1 { "psetex" } else { "setex" };
+    let options = match expiry_time(&args[1], unit, false, command) {
+        Ok(at) => SetOptions {
+            expiry: Some(SetExpiry::In(at)),
+            ..SetOptions::default()
+        },
+        Err(reply) => return reply,
     };
+    set_with(kv, &args[0], &args[2], options)
+}
 
-    match kv.set(key, value) {
-        Ok(()) => ResponseValue::SimpleString("OK".into()),
-        Err(_) => ResponseValue::Error("internal server error (poisoned lock)".into()),
+/// `SETNX key value`, the legacy spelling of `SET key value NX`, replying 1
+/// if the key was set and 0 if not.
+fn handle_setnx(kv: &mut KvStore, args: &[ResponseValue]) -> ResponseValue {
+    let options = SetOptions {
+        condition: Some(SetCondition::Missing),
+        ..SetOptions::default()
+    };
+    match set_with(kv, &args[0], &args[1], options) {
+        ResponseValue::SimpleString(_) => ResponseValue::Integer(1),
+        ResponseValue::BulkString(None) => ResponseValue::Integer(0),
+        reply => reply,
     }
 }
 
-fn handle_lpush(kv: &KvStore, args: &[ResponseValue]) -> ResponseValue {
-    let key = match args.first() {
-        Some(ResponseValue::BulkString(Some(bytes))) => Bytes::copy_from_slice(bytes),
-        Some(_) => return ResponseValue::Error("ERR key must be bulk string".into()),
-        None => return ResponseValue::Error("ERR invalid number of arguments".into()),
+/// Sets `key` to `value` as `options` say, the read of the old value with
+/// `GET` and the write done together, with no other command run between
+/// them. A key holding another type is left alone when its old value was
+/// asked for. Replies `OK`, or nil when `NX` or `XX` held the write back,
+/// or with `GET` the old value either way.
+fn set_with(
+    kv: &mut KvStore,
+    key: &ResponseValue,
+    value: &ResponseValue,
+    options: SetOptions,
+) -> ResponseValue {
+    let key = match key {
+        ResponseValue::BulkString(Some(bytes)) => compact(bytes),
+        _ => return ResponseValue::Error("ERR key must be bulk string".into()),
+    };
+    let value = match value {
+        ResponseValue::BulkString(Some(bytes)) => compact_value(bytes),
+        _ => return ResponseValue::Error("ERR value must be bulk string".into()),
     };
 
-    let mut values = Vec::with_capacity(args.len().saturating_sub(1));
-    for arg in &args[1..] {
-        if let ResponseValue::BulkString(Some(bytes)) = arg {
-            values.push(Bytes::copy_from_slice(bytes));
-        } else {
-            return ResponseValue::Error("ERR pushed values must be bulk strings".into());
+    let reply = if options.get {
+        match kv.get(&key) {
+            Some(RedisValue::String(s)) => ResponseValue::BulkString(Some(s.to_bytes())),
+            Some(_) => {
+                return ResponseValue::Error(
+                    "WRONGTYPE Operation against a key holding the wrong kind of value".into(),
+                )
+            }
+            None => ResponseValue::BulkString(None),
         }
+    } else {
+        ResponseValue::SimpleString("OK".into())
+    };
+    let held_back = match options.condition {
+        Some(SetCondition::Missing) => kv.contains_key(&key),
+        Some(SetCondition::Exists) => !kv.contains_key(&key),
+        None => false,
+    };
+    if held_back {
+        return if options.get {
+            reply
+        } else {
+            ResponseValue::BulkString(None)
+        };
     }
 
-    match kv.lpush(key, values) {
-        Ok(size) => ResponseValue::Integer(size),
-        Err(err) => ResponseValue::Error(format!("ERR internal db error: {:?}", err).into()),
+    let expires_at = match options.expiry {
+        Some(SetExpiry::Keep) => kv.expires_at(&key),
+        Some(SetExpiry::At(at)) => Some(at),
+        Some(SetExpiry::In(at)) => Some(kv.jitter_expiry(at)),
+        None => None,
+    };
+    kv.set(key.clone(), value);
+    if expires_at.is_some() {
+        kv.set_expires_at(&key, expires_at);
     }
+    reply
 }
 
-fn handle_lpop(kv: &KvStore, args: &[ResponseValue]) -> ResponseValue {
-    let key = match args.first() {
-        Some(ResponseValue::BulkString(Some(bytes))) => bytes,
-        Some(_) => return ResponseValue::Error("ERR key must be bulk string".into()),
-        None => return ResponseValue::Error("ERR invalid number of arguments".into()),
-    };
+fn handle_mget(kv: &KvStore, args: &[ResponseValue]) -> ResponseValue {
+    let mut values = Vec::with_capacity(args.len());
+    for arg in args {
+        let key = match arg {
+            ResponseValue::BulkString(Some(bytes)) => bytes,
+            _ => return ResponseValue::Error("ERR key must be bulk string".into()),
+        };
+        // keys holding other types read as nil, same as Redis
+        values.push(match kv.get(key) {
+            Some(RedisValue::String(s)) => ResponseValue::BulkString(Some(s.to_bytes())),
+            _ => ResponseValue::BulkString(None),
+        });
+    }
+    ResponseValue::Array(Some(values))
+}
 
-    let count = match args.get(1) {
-        Some(ResponseValue::BulkString(Some(bytes))) => {
-            match String::from_utf8_lossy(bytes).parse::<i64>() {
-                Ok(num) => num,
-                Err(err) => return ResponseValue::Error(format!("ERR {:?}", err).into()),
+fn handle_mset(kv: &mut KvStore, args: &[ResponseValue]) -> ResponseValue {
+    let mut pairs = Vec::with_capacity(args.len() / 2);
+    for pair in args.chunks(2) {
+        match (&pair[0], &pair[1]) {
+            (ResponseValue::BulkString(Some(key)), ResponseValue::BulkString(Some(value))) => {
+                pairs.push((compact(key), compact_value(value)))
             }
+            _ => return ResponseValue::Error("ERR key and value must be bulk strings".into()),
         }
-        Some(_) => return ResponseValue::Error("ERR count must be bulk string".into()),
-        None => 1, // Default count is 1 if not provided
-    };
+    }
+    for (key, value) in pairs {
+        kv.set(key, value);
+    }
+    ResponseValue::SimpleString("OK".into())
+}
 
-    match kv.lpop(key, count) {
-        Ok(bytes_vec) => {
-            if bytes_vec.len() == 1 {
-                ResponseValue::BulkString(Some(bytes_vec[0].clone()))
-            } else {
-                let response_elements: Vec<ResponseValue> = bytes_vec
-                    .into_iter()
-                    .map(|b| ResponseValue::BulkString(Some(b)))
-                    .collect();
-                ResponseValue::Array(Some(response_elements))
+/// `FLUSHALL`/`FLUSHDB [ASYNC|SYNC]`; without a mode,
+/// `lazyfree-lazy-user-flush` and `command-budget` decide.
+fn handle_flush(kv: &mut KvStore, args: &[ResponseValue]) -> ResponseValue {
+    match args {
+        [] => kv.clear(),
+        [ResponseValue::BulkString(Some(mode))] if mode.eq_ignore_ascii_case(b"ASYNC") => {
+            kv.flush(true)
+        }
+        [ResponseValue::BulkString(Some(mode))] if mode.eq_ignore_ascii_case(b"SYNC") => {
+            kv.flush(false)
+        }
+        _ => return ResponseValue::Error("ERR syntax error".into()),
+    }
+    ResponseValue::SimpleString("OK".into())
+}
+
+/// `DEL`, or with `unlink` `UNLINK`, which frees big values in the
+/// background.
+fn handle_del(kv: &mut KvStore, args: &[ResponseValue], unlink: bool) -> ResponseValue {
+    let mut deleted = 0;
+    for arg in args {
+        match arg {
+            ResponseValue::BulkString(Some(key)) if unlink => deleted += kv.unlink(key) as i64,
+            ResponseValue::BulkString(Some(key)) => deleted += kv.del(key) as i64,
+            _ => return ResponseValue::Error("ERR key must be bulk string".into()),
+        }
+    }
+    ResponseValue::Integer(deleted)
+}
+
+fn handle_exists(kv: &KvStore, args: &[ResponseValue]) -> ResponseValue {
+    let mut found = 0;
+    for arg in args {
+        match arg {
+            ResponseValue::BulkString(Some(key)) => found += kv.exists(key) as i64,
+            _ => return ResponseValue::Error("ERR key must be bulk string".into()),
+        }
+    }
+    ResponseValue::Integer(found)
+}
+
+/// Buckets `EXPORT` walks when no `COUNT` is given.
+pub const EXPORT_COUNT: usize = 10;
+
+/// `EXPORT cursor [MATCH pattern] [COUNT count]`: the keys in the next
+/// `count` buckets of the keyspace, walked like `SCAN` walks it, with their
+/// values, for backups. Replies with the cursor to go on from, 0 once the
+/// walk is done, and an entry `[key, type, ttl, value]` per key: the value
+/// is a bulk string for strings, an array of the elements for lists and
+/// sets, and nil for a module's values. `ttl` is the milliseconds the key
+/// has left, -1 if it doesn't expire. Keys there for the whole walk are
+/// all exported, even if the shard is resized during it, and a key may come
+/// twice if it shrinks.
+///
+/// The router turns shard cursors into cursors of the whole keyspace.
+fn handle_export(kv: &mut KvStore, args: &[ResponseValue]) -> ResponseValue {
+    let (cursor, options) = (&args[0], &args[1..]);
+    let cursor = match parse_int(cursor) {
+        Ok(cursor) if cursor >= 0 => cursor as usize,
+        _ => return ResponseValue::Error("ERR invalid cursor".into()),
+    };
+    let mut pattern = None;
+    let mut count = EXPORT_COUNT;
+    for option in options.chunks(2) {
+        match option {
+            [ResponseValue::BulkString(Some(name)), ResponseValue::BulkString(Some(value))]
+                if name.eq_ignore_ascii_case(b"MATCH") =>
+            {
+                pattern = Some(value)
+            }
+            [ResponseValue::BulkString(Some(name)), value]
+                if name.eq_ignore_ascii_case(b"COUNT") =>
+            {
+                match parse_int(value) {
+                    Ok(n) if n >= 1 => count = n as usize,
+                    Ok(_) => return ResponseValue::Error("ERR syntax error".into()),
+                    Err(err) => return ResponseValue::Error(err),
+                }
             }
+            _ => return ResponseValue::Error("ERR syntax error".into()),
         }
-        Err(err) => ResponseValue::Error(format!("ERR {:?}", err).into()),
     }
+
+    let now = unix_time_ms();
+    let mut entries = Vec::new();
+    let next = kv.scan(cursor, count, |key, value, _, expires_at| {
+        if pattern.is_none_or(|pattern| glob_match(pattern, key)) {
+            let ttl = expires_at.map_or(-1, |at| at.saturating_sub(now) as i64);
+            entries.push(export_entry(key, ttl, value));
+        }
+    });
+    let next = next.unwrap_or(0);
+    ResponseValue::Array(Some(vec![
+        ResponseValue::BulkString(Some(next.to_string().into())),
+        ResponseValue::Array(Some(entries)),
+    ]))
 }
 
-fn handle_rpush(kv: &KvStore, args: &[ResponseValue]) -> ResponseValue {
-    let key = match args.first() {
-        Some(ResponseValue::BulkString(Some(bytes))) => Bytes::copy_from_slice(bytes),
-        Some(_) => return ResponseValue::Error("ERR key must be bulk string".into()),
-        None => return ResponseValue::Error("ERR invalid number of arguments".into()),
+fn export_entry(key: &Bytes, ttl: i64, value: &RedisValue) -> ResponseValue {
+    #[cfg(any(feature = "lists", feature = "sets"))]
+    let elements = |items: Vec<Bytes>| {
+        ResponseValue::Array(Some(
+            items
+                .into_iter()
+                .map(|item| ResponseValue::BulkString(Some(item)))
+                .collect(),
+        ))
+    };
+    let (kind, value) = match value {
+        RedisValue::String(string) => {
+            ("string", ResponseValue::BulkString(Some(string.to_bytes())))
+        }
+        #[cfg(feature = "lists")]
+        RedisValue::List(list) => ("list", elements(list.iter().collect())),
+        #[cfg(feature = "sets")]
+        RedisValue::Set(set) => ("set", elements(set.iter().collect())),
+        RedisValue::Module(value) => (value.type_name(), ResponseValue::BulkString(None)),
+    };
+    ResponseValue::Array(Some(vec![
+        ResponseValue::BulkString(Some(key.clone())),
+        ResponseValue::BulkString(Some(kind.into())),
+        ResponseValue::Integer(ttl),
+        value,
+    ]))
+}
+
+#[cfg(feature = "lists")]
+fn handle_lpush(kv: &mut KvStore, args: &[ResponseValue]) -> ResponseValue {
+    let key = match &args[0] {
+        ResponseValue::BulkString(Some(bytes)) => compact(bytes),
+        _ => return ResponseValue::Error("ERR key must be bulk string".into()),
     };
 
     let mut values = Vec::with_capacity(args.len().saturating_sub(1));
     for arg in &args[1..] {
         if let ResponseValue::BulkString(Some(bytes)) = arg {
-            values.push(Bytes::copy_from_slice(bytes));
+            values.push(compact(bytes));
         } else {
             return ResponseValue::Error("ERR pushed values must be bulk strings".into());
         }
     }
 
-    match kv.rpush(key, values) {
+    match kv.lpush(key, values) {
         Ok(size) => ResponseValue::Integer(size),
-        Err(err) => ResponseValue::Error(format!("ERR internal db error: {:?}", err).into()),
+        Err(err) => database_error(err),
     }
 }
 
-fn handle_rpop(kv: &KvStore, args: &[ResponseValue]) -> ResponseValue {
-    let key = match args.first() {
-        Some(ResponseValue::BulkString(Some(bytes))) => bytes,
-        Some(_) => return ResponseValue::Error("ERR key must be bulk string".into()),
-        None => return ResponseValue::Error("ERR invalid number of arguments".into()),
+#[cfg(feature = "lists")]
+fn handle_lpop(kv: &mut KvStore, args: &[ResponseValue]) -> ResponseValue {
+    handle_pop(kv, args, KvStore::lpop)
+}
+
+/// `LPOP key [count]` and `RPOP key [count]`, popping with `pop`: without a
+/// count, the element popped or nil, and with one, an array of up to `count`
+/// elements in the order they were popped, or nil if the key is missing.
+#[cfg(feature = "lists")]
+fn handle_pop(
+    kv: &mut KvStore,
+    args: &[ResponseValue],
+    pop: fn(&mut KvStore, &Bytes, i64) -> Result<Vec<Bytes>, DatabaseError>,
+) -> ResponseValue {
+    let key = match &args[0] {
+        ResponseValue::BulkString(Some(bytes)) => bytes,
+        _ => return ResponseValue::Error("ERR key must be bulk string".into()),
+    };
+    let count = match args.get(1).map(parse_count) {
+        Some(Ok(count)) => Some(count),
+        Some(Err(reply)) => return reply,
+        None => None,
     };
 
-    let count = match args.get(1) {
-        Some(ResponseValue::BulkString(Some(bytes))) => {
-            match String::from_utf8_lossy(bytes).parse::<i64>() {
-                Ok(num) => num,
-                Err(err) => return ResponseValue::Error(format!("ERR {:?}", err).into()),
-            }
-        }
-        Some(_) => return ResponseValue::Error("ERR count must be bulk string".into()),
-        None => 1, // Default count is 1 if not provided
+    if !kv.contains_key(key) {
+        return match count {
+            Some(_) => ResponseValue::Array(None),
+            None => ResponseValue::BulkString(None),
+        };
+    }
+    let popped = match pop(kv, key, count.unwrap_or(1)) {
+        Ok(popped) => popped,
+        Err(err) => return database_error(err),
     };
+    match count {
+        Some(_) => ResponseValue::Array(Some(
+            popped
+                .into_iter()
+                .map(|b| ResponseValue::BulkString(Some(b)))
+                .collect(),
+        )),
+        None => ResponseValue::BulkString(popped.into_iter().next()),
+    }
+}
 
-    match kv.rpop(key, count) {
-        Ok(bytes_vec) => {
-            if bytes_vec.len() == 1 {
-                ResponseValue::BulkString(Some(bytes_vec[0].clone()))
-            } else {
-                let response_elements: Vec<ResponseValue> = bytes_vec
-                    .into_iter()
-                    .map(|b| ResponseValue::BulkString(Some(b)))
-                    .collect();
-                ResponseValue::Array(Some(response_elements))
-            }
+#[cfg(feature = "lists")]
+fn handle_rpush(kv: &mut KvStore, args: &[ResponseValue]) -> ResponseValue {
+    let key = match &args[0] {
+        ResponseValue::BulkString(Some(bytes)) => compact(bytes),
+        _ => return ResponseValue::Error("ERR key must be bulk string".into()),
+    };
+
+    let mut values = Vec::with_capacity(args.len().saturating_sub(1));
+    for arg in &args[1..] {
+        if let ResponseValue::BulkString(Some(bytes)) = arg {
+            values.push(compact(bytes));
+        } else {
+            return ResponseValue::Error("ERR pushed values must be bulk strings".into());
         }
-        Err(err) => ResponseValue::Error(format!("ERR {:?}", err).into()),
     }
+
+    match kv.rpush(key, values) {
+        Ok(size) => ResponseValue::Integer(size),
+        Err(err) => database_error(err),
+    }
+}
+
+#[cfg(feature = "lists")]
+fn handle_rpop(kv: &mut KvStore, args: &[ResponseValue]) -> ResponseValue {
+    handle_pop(kv, args, KvStore::rpop)
 }
 
+#[cfg(feature = "lists")]
 fn handle_lrange(kv: &KvStore, args: &[ResponseValue]) -> ResponseValue {
-    let key = match args.first() {
-        Some(ResponseValue::BulkString(Some(bytes))) => bytes,
-        Some(_) => return ResponseValue::Error("ERR key must be bulk string".into()),
-        None => return ResponseValue::Error("ERR invalid number of arguments".into()),
+    let key = match &args[0] {
+        ResponseValue::BulkString(Some(bytes)) => bytes,
+        _ => return ResponseValue::Error("ERR key must be bulk string".into()),
     };
 
-    let start = match args.get(1) {
-        Some(value) => match parse_int(value) {
-            Ok(integer) => integer,
-            Err(err) => return ResponseValue::Error(err),
-        },
-        None => return ResponseValue::Error("ERR invalid number of arguments".into()),
+    let start = match parse_int(&args[1]) {
+        Ok(integer) => integer,
+        Err(err) => return ResponseValue::Error(err),
     };
 
-    let stop = match args.get(2) {
-        Some(value) => match parse_int(value) {
-            Ok(integer) => integer,
-            Err(err) => return ResponseValue::Error(err),
-        },
-        None => return ResponseValue::Error("ERR invalid number of arguments".into()),
+    let stop = match parse_int(&args[2]) {
+        Ok(integer) => integer,
+        Err(err) => return ResponseValue::Error(err),
     };
 
     match kv.lrange(key, start, stop) {
@@ -247,21 +994,21 @@ fn handle_lrange(kv: &KvStore, args: &[ResponseValue]) -> ResponseValue {
 
             ResponseValue::Array(Some(response_elements))
         }
-        Err(err) => ResponseValue::Error(format!("ERR {:?}", err).into()),
+        Err(err) => database_error(err),
     }
 }
 
-fn handle_sadd(kv: &KvStore, args: &[ResponseValue]) -> ResponseValue {
-    let key = match args.first() {
-        Some(ResponseValue::BulkString(Some(bytes))) => Bytes::copy_from_slice(bytes),
-        Some(_) => return ResponseValue::Error("ERR key must be bulk string".into()),
-        None => return ResponseValue::Error("ERR invalid number of arguments".into()),
+#[cfg(feature = "sets")]
+fn handle_sadd(kv: &mut KvStore, args: &[ResponseValue]) -> ResponseValue {
+    let key = match &args[0] {
+        ResponseValue::BulkString(Some(bytes)) => compact(bytes),
+        _ => return ResponseValue::Error("ERR key must be bulk string".into()),
     };
 
     let mut values = Vec::with_capacity(args.len().saturating_sub(1));
     for arg in &args[1..] {
         if let ResponseValue::BulkString(Some(bytes)) = arg {
-            let to_push = Bytes::copy_from_slice(bytes);
+            let to_push = compact(bytes);
             values.push(to_push);
         } else {
             return ResponseValue::Error("ERR pushed values must be bulk strings".into());
@@ -270,42 +1017,49 @@ fn handle_sadd(kv: &KvStore, args: &[ResponseValue]) -> ResponseValue {
 
     match kv.sadd(key, values) {
         Ok(size) => ResponseValue::Integer(size),
-        Err(err) => ResponseValue::Error(format!("ERR internal db error: {:?}", err).into()),
+        Err(err) => database_error(err),
     }
 }
 
-fn handle_spop(kv: &KvStore, args: &[ResponseValue]) -> ResponseValue {
-    let key = match args.first() {
-        Some(ResponseValue::BulkString(Some(bytes))) => bytes,
-        Some(_) => return ResponseValue::Error("ERR key must be bulk string".into()),
-        None => return ResponseValue::Error("ERR invalid number of arguments".into()),
+/// `SPOP key [count]`: without a count, the member popped or nil, as Redis
+/// replies; with one, an array of up to `count` members, empty if the key is
+/// missing.
+#[cfg(feature = "sets")]
+fn handle_spop(kv: &mut KvStore, args: &[ResponseValue]) -> ResponseValue {
+    let key = match &args[0] {
+        ResponseValue::BulkString(Some(bytes)) => bytes,
+        _ => return ResponseValue::Error("ERR key must be bulk string".into()),
     };
 
-    let count = match args.get(1) {
-        Some(value) => match parse_int(value) {
-            Ok(n) => n,
-            Err(e) => return ResponseValue::Error(e),
+    let count = match args {
+        [_] => None,
+        [_, count] => match parse_count(count) {
+            Ok(count) => Some(count),
+            Err(reply) => return reply,
         },
-        None => 1,
+        _ => return ResponseValue::Error("ERR syntax error".into()),
     };
 
-    match kv.spop(key, count) {
-        Ok(bytes_vec) => {
-            let response_vector: Vec<ResponseValue> = bytes_vec
+    let popped = match kv.spop(key, count.unwrap_or(1)) {
+        Ok(popped) => popped,
+        Err(err) => return database_error(err),
+    };
+    match count {
+        Some(_) => ResponseValue::Array(Some(
+            popped
                 .into_iter()
                 .map(|b| ResponseValue::BulkString(Some(b)))
-                .collect();
-            ResponseValue::Array(Some(response_vector))
-        }
-        Err(e) => ResponseValue::Error(format!("ERR: {:?}", e).into()),
+                .collect(),
+        )),
+        None => ResponseValue::BulkString(popped.into_iter().next()),
     }
 }
 
+#[cfg(feature = "sets")]
 fn handle_smembers(kv: &KvStore, args: &[ResponseValue]) -> ResponseValue {
-    let key = match args.first() {
-        Some(ResponseValue::BulkString(Some(bytes))) => bytes,
-        Some(_) => return ResponseValue::Error("ERR key must be bulk string".into()),
-        None => return ResponseValue::Error("ERR invalid number of arguments".into()),
+    let key = match &args[0] {
+        ResponseValue::BulkString(Some(bytes)) => bytes,
+        _ => return ResponseValue::Error("ERR key must be bulk string".into()),
     };
 
     match kv.smembers(key) {
@@ -316,6 +1070,73 @@ fn handle_smembers(kv: &KvStore, args: &[ResponseValue]) -> ResponseValue {
                 .collect();
             ResponseValue::Array(Some(response_elements))
         }
-        Err(e) => ResponseValue::Error(format!("ERR {:?}", e).into()),
+        Err(err) => database_error(err),
+    }
+}
+
+/// `SSCAN key cursor [COUNT count]`, without `MATCH`, see `KvStore::sscan`.
+#[cfg(feature = "sets")]
+fn handle_sscan(kv: &KvStore, args: &[ResponseValue]) -> ResponseValue {
+    let key = match &args[0] {
+        ResponseValue::BulkString(Some(bytes)) => bytes,
+        _ => return ResponseValue::Error("ERR key must be bulk string".into()),
+    };
+
+    let cursor = match &args[1] {
+        ResponseValue::BulkString(Some(bytes)) => std::str::from_utf8(bytes)
+            .ok()
+            .and_then(|cursor| cursor.parse::<usize>().ok()),
+        _ => None,
+    };
+    let Some(cursor) = cursor else {
+        return ResponseValue::Error("ERR invalid cursor".into());
+    };
+
+    let count = match &args[2..] {
+        [] => 10,
+        [ResponseValue::BulkString(Some(option)), count]
+            if option.eq_ignore_ascii_case(b"COUNT") =>
+        {
+            match parse_int(count) {
+                Ok(count) if count >= 1 => count as usize,
+                Ok(_) => return ResponseValue::Error("ERR syntax error".into()),
+                Err(err) => return ResponseValue::Error(err),
+            }
+        }
+        _ => return ResponseValue::Error("ERR syntax error".into()),
+    };
+
+    match kv.sscan(key, cursor, count) {
+        Ok((next, members)) => ResponseValue::Array(Some(vec![
+            ResponseValue::BulkString(Some(next.to_string().into())),
+            ResponseValue::Array(Some(
+                members
+                    .into_iter()
+                    .map(|b| ResponseValue::BulkString(Some(b)))
+                    .collect(),
+            )),
+        ])),
+        Err(err) => database_error(err),
+    }
+}
+
+/// The error reply for `err`.
+#[cfg(any(feature = "lists", feature = "sets"))]
+fn database_error(err: DatabaseError) -> ResponseValue {
+    match err {
+        DatabaseError::WrongType => ResponseValue::Error(
+            "WRONGTYPE Operation against a key holding the wrong kind of value".into(),
+        ),
+        DatabaseError::OverBudget {
+            elements,
+            budget,
+            instead,
+        } => ResponseValue::Error(
+            format!(
+                "ERR the reply would have {elements} elements, more than command-budget \
+                 {budget} allows; use {instead}"
+            )
+            .into(),
+        ),
     }
 }
@@ -0,0 +1,56 @@
+//! Connection lifecycle hooks, for programs embedding the server to add
+//! their own admission control, quotas or auditing.
+//!
+//! `install` registers a `ConnectionHooks` for the whole process. The accept
+//! loops call it when a client connects, once `maxclients` has let it in, and
+//! again when the client is gone. Hooks run on the thread serving the
+//! connection, in between its other work, so they must be quick. Connects,
+//! refusals and disconnects are also logged as `tracing` events in the
+//! `client` span, at `verbose`. There is no `AUTH` yet, hence no
+//! authentication hook.
+
+use std::{io, sync::OnceLock};
+
+use crate::{
+    connection::Client,
+    stats::{ServerStats, STATS},
+};
+
+/// Callbacks on a client's connection. Both do nothing unless overridden.
+pub trait ConnectionHooks: Send + Sync {
+    /// Called when `client` connects. An `Err` refuses it: the client is sent
+    /// the message as an error reply, e.g. `ERR quota exceeded`, and closed,
+    /// and it counts toward `rejected_connections`.
+    fn on_connect(&self, _client: &Client) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// Called when a client `on_connect` let in is gone, with the error that
+    /// ended the connection if it didn't close cleanly.
+    fn on_disconnect(&self, _client: &Client, _error: Option<&io::Error>) {}
+}
+
+static HOOKS: OnceLock<Box<dyn ConnectionHooks>> = OnceLock::new();
+
+/// Calls `hooks` for every connection accepted from now on. Only the first
+/// hooks installed count; returns whether these are them.
+pub fn install(hooks: impl ConnectionHooks + 'static) -> bool {
+    HOOKS.set(Box::new(hooks)).is_ok()
+}
+
+/// Runs the connect hook, returning the reply refusing `client`, if it is
+/// refused.
+pub(crate) fn connect(client: &Client) -> Option<Vec<u8>> {
+    let refusal = HOOKS.get()?.on_connect(client).err()?;
+    ServerStats::incr(&STATS.rejected_connections, 1);
+    // whatever the hook said, the reply is a single line
+    let refusal = refusal.replace(['\r', '\n'], " ");
+    Some(format!("-{refusal}\r\n").into_bytes())
+}
+
+/// Runs the disconnect hook.
+pub(crate) fn disconnect(client: &Client, error: Option<&io::Error>) {
+    if let Some(hooks) = HOOKS.get() {
+        hooks.on_disconnect(client, error);
+    }
+}
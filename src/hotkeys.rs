@@ -0,0 +1,115 @@
+//! Hot key tracking, for the `HOTKEYS` command.
+//!
+//! Each worker counts the keys its commands touch in a count-min sketch,
+//! which estimates any key's count in fixed memory, and keeps the `TOP_KEYS`
+//! keys with the highest estimates on the side. Every `SAMPLE_SIZE` accesses
+//! all counts are halved, TinyLFU style, so keys that went cold make way for
+//! the ones hot now.
+
+use std::hash::{BuildHasher, RandomState};
+
+use bytes::Bytes;
+
+/// Rows of the sketch, each with its own hash of the key; an estimate is
+/// the smallest of a key's counters.
+const DEPTH: usize = 4;
+/// Counters per row.
+const WIDTH: usize = 4096;
+/// Accesses between two halvings of every count.
+pub const SAMPLE_SIZE: u32 = 10 * WIDTH as u32;
+/// Keys kept per worker, the most `HOTKEYS` can list per worker.
+pub const TOP_KEYS: usize = 16;
+
+/// A worker's access counts and the hottest keys among them.
+pub struct HotKeys {
+    counters: Box<[[u32; WIDTH]; DEPTH]>,
+    hasher: RandomState,
+    /// Accesses since the counts were last halved.
+    accesses: u32,
+    /// The hottest keys, with their estimated counts, hottest first.
+    top: Vec<(Bytes, u32)>,
+}
+
+impl Default for HotKeys {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HotKeys {
+    pub fn new() -> Self {
+        Self {
+            counters: Box::new([[0; WIDTH]; DEPTH]),
+            hasher: RandomState::new(),
+            accesses: 0,
+            top: Vec::with_capacity(TOP_KEYS),
+        }
+    }
+
+    /// Counts an access to `key`.
+    pub fn record(&mut self, key: &Bytes) {
+        let hash = self.hasher.hash_one(key);
+        // double hashing: row i uses h1 + i * h2, from the two halves
+        let (h1, h2) = (hash as u32 as usize, (hash >> 32) as usize | 1);
+        let mut estimate = u32::MAX;
+        for (row, counters) in self.counters.iter_mut().enumerate() {
+            let counter = &mut counters[h1.wrapping_add(row.wrapping_mul(h2)) % WIDTH];
+            *counter = counter.saturating_add(1);
+            estimate = estimate.min(*counter);
+        }
+        self.promote(key, estimate);
+
+        self.accesses += 1;
+        if self.accesses >= SAMPLE_SIZE {
+            self.halve();
+        }
+    }
+
+    /// Puts `key` in its place among the hottest keys, if it belongs there.
+    fn promote(&mut self, key: &Bytes, estimate: u32) {
+        let full = self.top.len() == TOP_KEYS;
+        if full
+            && self
+                .top
+                .last()
+                .is_some_and(|(_, coldest)| estimate <= *coldest)
+        {
+            return;
+        }
+        let mut at = match self.top.iter().position(|(top, _)| top == key) {
+            Some(at) => {
+                self.top[at].1 = estimate;
+                at
+            }
+            None => {
+                if full {
+                    self.top.pop();
+                }
+                self.top.push((key.clone(), estimate));
+                self.top.len() - 1
+            }
+        };
+        // estimates only grow between halvings, so the key can only move up
+        while at > 0 && self.top[at - 1].1 < estimate {
+            self.top.swap(at - 1, at);
+            at -= 1;
+        }
+    }
+
+    fn halve(&mut self) {
+        for counter in self.counters.iter_mut().flatten() {
+            *counter /= 2;
+        }
+        for (_, count) in &mut self.top {
+            *count /= 2;
+        }
+        self.top.retain(|(_, count)| *count > 0);
+        self.accesses = 0;
+    }
+
+    /// The hottest keys with their estimated recent access counts, hottest
+    /// first.
+    pub fn top(&self) -> &[(Bytes, u32)] {
+        &self.top
+    }
+}
@@ -0,0 +1,313 @@
+use std::{
+    fmt::Write,
+    process,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    allocator,
+    kv::ValueType,
+    latency::PERCENTILES,
+    server::{self, cpu_time, MULTIPLEXING_API},
+    stats::{ServerStats, STATS},
+};
+
+/// Sections in the order INFO lists them, and whether a bare `INFO` (or
+/// `INFO default`) includes them; `INFO all` and `INFO everything` include
+/// them all.
+const SECTIONS: &[(&str, bool)] = &[
+    ("server", true),
+    ("clients", true),
+    ("memory", true),
+    ("stats", true),
+    ("replication", true),
+    ("cpu", true),
+    ("workers", true),
+    ("errorstats", true),
+    ("latencystats", false),
+    ("keyspace", true),
+];
+
+/// Renders the INFO reply. `section` picks one section by name; `None` and
+/// `default` return the default sections, `all` and `everything` all of
+/// them.
+pub fn render_info(section: Option<&str>) -> String {
+    let wanted = section.map(str::to_ascii_lowercase);
+    let mut out = String::new();
+
+    for &(name, default) in SECTIONS {
+        let include = match wanted.as_deref() {
+            None | Some("default") => default,
+            Some("all") | Some("everything") => true,
+            Some(wanted) => wanted == name,
+        };
+        if !include {
+            continue;
+        }
+
+        if !out.is_empty() {
+            out.push_str("\r\n");
+        }
+        match name {
+            "server" => write_server(&mut out),
+            "clients" => write_clients(&mut out),
+            "memory" => write_memory(&mut out),
+            "stats" => write_stats(&mut out),
+            "replication" => write_replication(&mut out),
+            "cpu" => write_cpu(&mut out),
+            "workers" => write_workers(&mut out),
+            "errorstats" => write_errorstats(&mut out),
+            "latencystats" => write_latencystats(&mut out),
+            "keyspace" => write_keyspace(&mut out),
+            _ => {}
+        }
+    }
+
+    out
+}
+
+fn write_server(out: &mut String) {
+    let server = server::identity();
+    let uptime = server.uptime().as_secs();
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let executable = std::env::current_exe().unwrap_or_default();
+    let _ = write!(
+        out,
+        "# Server\r\n\
+         rustis_version:{}\r\n\
+         rustis_mode:standalone\r\n\
+         os:{} {}\r\n\
+         arch_bits:{}\r\n\
+         multiplexing_api:{MULTIPLEXING_API}\r\n\
+         process_id:{}\r\n\
+         process_supervised:{}\r\n\
+         run_id:{}\r\n\
+         tcp_port:{}\r\n\
+         server_time_usec:{}\r\n\
+         uptime_in_seconds:{uptime}\r\n\
+         uptime_in_days:{}\r\n\
+         executable:{}\r\n",
+        env!("CARGO_PKG_VERSION"),
+        std::env::consts::OS,
+        std::env::consts::ARCH,
+        usize::BITS,
+        process::id(),
+        if server.supervised { "systemd" } else { "no" },
+        server.run_id,
+        server.tcp_port,
+        now.as_micros(),
+        uptime / 86_400,
+        executable.display(),
+    );
+}
+
+fn write_clients(out: &mut String) {
+    let _ = write!(
+        out,
+        "# Clients\r\nconnected_clients:{}\r\n",
+        ServerStats::get(&STATS.connected_clients)
+    );
+}
+
+fn write_memory(out: &mut String) {
+    let used = ServerStats::get(&STATS.used_memory);
+    let _ = write!(
+        out,
+        "# Memory\r\n\
+         used_memory:{used}\r\n\
+         used_memory_human:{}\r\n\
+         lazyfree_pending_objects:{}\r\n",
+        human_bytes(used),
+        ServerStats::get(&STATS.lazyfree_pending_objects),
+    );
+
+    let rss = allocator::process_rss().unwrap_or(0) as u64;
+    let _ = write!(
+        out,
+        "used_memory_rss:{rss}\r\n\
+         used_memory_rss_human:{}\r\n\
+         mem_fragmentation_ratio:{:.2}\r\n\
+         mem_allocator:{}\r\n",
+        human_bytes(rss),
+        if used > 0 {
+            rss as f64 / used as f64
+        } else {
+            0.0
+        },
+        allocator::NAME,
+    );
+    let stats = allocator::stats();
+    for (name, bytes) in [
+        ("allocated", stats.allocated),
+        ("active", stats.active),
+        ("resident", stats.resident),
+    ] {
+        if let Some(bytes) = bytes {
+            let _ = write!(out, "allocator_{name}:{bytes}\r\n");
+        }
+    }
+    for (name, ratio) in [("frag", stats.frag_ratio()), ("rss", stats.rss_ratio())] {
+        if let Some(ratio) = ratio {
+            let _ = write!(out, "allocator_{name}_ratio:{ratio:.2}\r\n");
+        }
+    }
+
+    for (value_type, usage) in ValueType::ALL.iter().zip(STATS.dataset_usage()) {
+        let _ = write!(
+            out,
+            "used_memory_{}:keys={},bytes={}\r\n",
+            value_type.name(),
+            usage.keys,
+            usage.bytes
+        );
+    }
+}
+
+/// `1.50M`-style sizes, as Redis prints them.
+fn human_bytes(bytes: u64) -> String {
+    const UNITS: &[&str] = &["K", "M", "G", "T"];
+    if bytes < 1024 {
+        return format!("{bytes}B");
+    }
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{value:.2}{}", UNITS[unit])
+}
+
+fn write_stats(out: &mut String) {
+    let _ = write!(
+        out,
+        "# Stats\r\n\
+         total_connections_received:{}\r\n\
+         total_commands_processed:{}\r\n\
+         instantaneous_ops_per_sec:{}\r\n\
+         total_net_input_bytes:{}\r\n\
+         total_net_output_bytes:{}\r\n\
+         instantaneous_input_kbps:{:.2}\r\n\
+         instantaneous_output_kbps:{:.2}\r\n\
+         rejected_connections:{}\r\n\
+         expired_keys:{}\r\n\
+         evicted_keys:{}\r\n\
+         keyspace_hits:{}\r\n\
+         keyspace_misses:{}\r\n\
+         lazyfreed_objects:{}\r\n\
+         active_defrag_hits:{}\r\n\
+         active_defrag_key_hits:{}\r\n\
+         total_error_replies:{}\r\n",
+        ServerStats::get(&STATS.total_connections_received),
+        ServerStats::get(&STATS.total_commands_processed),
+        STATS.instantaneous_ops_per_sec(),
+        ServerStats::get(&STATS.total_net_input_bytes),
+        ServerStats::get(&STATS.total_net_output_bytes),
+        STATS.instantaneous_input_kbps(),
+        STATS.instantaneous_output_kbps(),
+        ServerStats::get(&STATS.rejected_connections),
+        STATS.expired_keys(),
+        ServerStats::get(&STATS.evicted_keys),
+        STATS.keyspace_hits(),
+        STATS.keyspace_misses(),
+        ServerStats::get(&STATS.lazyfreed_objects),
+        ServerStats::get(&STATS.active_defrag_hits),
+        ServerStats::get(&STATS.active_defrag_key_hits),
+        ServerStats::get(&STATS.total_error_replies),
+    );
+}
+
+/// Always a master without replicas, for now; the replication id is still
+/// the server's own.
+fn write_replication(out: &mut String) {
+    let _ = write!(
+        out,
+        "# Replication\r\n\
+         role:master\r\n\
+         connected_slaves:0\r\n\
+         master_replid:{}\r\n\
+         master_replid2:{}\r\n\
+         master_repl_offset:0\r\n\
+         second_repl_offset:-1\r\n",
+        server::identity().replid,
+        "0".repeat(server::ID_LEN),
+    );
+}
+
+/// CPU time of the whole process, in seconds, where the OS tells.
+fn write_cpu(out: &mut String) {
+    out.push_str("# CPU\r\n");
+    if let Some((sys, user)) = cpu_time() {
+        let _ = write!(
+            out,
+            "used_cpu_sys:{:.6}\r\nused_cpu_user:{:.6}\r\n",
+            sys.as_secs_f64(),
+            user.as_secs_f64()
+        );
+    }
+}
+
+/// One line per worker, so a shard pinned by a hot key stands out.
+fn write_workers(out: &mut String) {
+    let workers = STATS.workers();
+    let hot = workers.iter().filter(|(_, w)| w.is_hot()).count();
+    let _ = write!(
+        out,
+        "# Workers\r\nworkers:{}\r\nhot_workers:{hot}\r\n",
+        workers.len()
+    );
+    for (id, worker) in workers {
+        let _ = write!(
+            out,
+            "worker{id}:commands_processed={},queue_depth={},used_memory={},hot={}\r\n",
+            ServerStats::get(&worker.commands_processed),
+            ServerStats::get(&worker.queue_depth),
+            worker.used_memory(),
+            worker.is_hot() as u8,
+        );
+    }
+}
+
+/// Error replies sent to clients per prefix (`ERR`, `WRONGTYPE`, ...),
+/// counted as they are written out, whichever stage produced them.
+fn write_errorstats(out: &mut String) {
+    out.push_str("# Errorstats\r\n");
+    for (prefix, count) in STATS.error_replies() {
+        let _ = write!(out, "errorstat_{prefix}:count={count}\r\n");
+    }
+}
+
+/// Execution time percentiles per command, in microseconds, from the
+/// workers' histograms. Time spent parsing, routing and waiting in a worker's
+/// mailbox isn't included, and a multi-key command split over several workers
+/// counts once per worker.
+fn write_latencystats(out: &mut String) {
+    out.push_str("# Latencystats\r\n");
+    for (command, histogram) in STATS.command_latencies().iter() {
+        let _ = write!(out, "latency_percentiles_usec_{command}:");
+        for (i, percentile) in PERCENTILES.iter().enumerate() {
+            let usec = histogram.percentile(*percentile) as f64 / 1000.0;
+            let sep = if i == 0 { "" } else { "," };
+            let _ = write!(out, "{sep}p{percentile}={usec:.3}");
+        }
+        out.push_str("\r\n");
+    }
+}
+
+/// The one database, `db0`, as Redis lists it, and like Redis nothing when
+/// it is empty. The key count is the sum of what the workers publish after
+/// every batch, so nothing is scanned, and so are `expires` and `avg_ttl`,
+/// which come from the workers' expiry indexes.
+fn write_keyspace(out: &mut String) {
+    out.push_str("# Keyspace\r\n");
+    let keys: usize = STATS.dataset_usage().iter().map(|usage| usage.keys).sum();
+    if keys > 0 {
+        let (expires, avg_ttl) = STATS.expires();
+        let _ = write!(
+            out,
+            "db0:keys={keys},expires={expires},avg_ttl={avg_ttl}\r\n"
+        );
+    }
+}
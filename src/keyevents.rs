@@ -0,0 +1,70 @@
+//! Key removal hooks, for programs embedding the server to keep secondary
+//! indexes or write-behind caches of their own in step with the dataset.
+//!
+//! `install` registers a `KeyEventHooks` for the whole process, which every
+//! shard then calls when it evicts a key under `maxmemory`, removes one whose
+//! TTL passed, drops one a panicking command touched, or is flushed. Only removals the server decides on are
+//! reported: `DEL`, `UNLINK` and overwrites are the client's own doing. Hooks
+//! run on the worker owning the key, in between its commands, so they must be
+//! quick; a flush calls them once per key before dropping any.
+
+use std::sync::OnceLock;
+
+use bytes::Bytes;
+
+/// Why a key was removed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyEvent {
+    /// Evicted to stay under `maxmemory`.
+    Evicted,
+    /// Its TTL passed, found by a command or the active expire cycle.
+    Expired,
+    /// Removed by `FLUSHALL` or `FLUSHDB`.
+    Flushed,
+    /// Dropped with `worker-panic restart`, as a command on it panicked and
+    /// may have left it half updated.
+    Dropped,
+}
+
+impl KeyEvent {
+    pub fn name(self) -> &'static str {
+        match self {
+            KeyEvent::Evicted => "evicted",
+            KeyEvent::Expired => "expired",
+            KeyEvent::Flushed => "flushed",
+            KeyEvent::Dropped => "dropped",
+        }
+    }
+}
+
+/// Callback on a key the server removed. Closures taking the key and the
+/// event are hooks too.
+pub trait KeyEventHooks: Send + Sync {
+    fn on_removed(&self, key: &Bytes, event: KeyEvent);
+}
+
+impl<F: Fn(&Bytes, KeyEvent) + Send + Sync> KeyEventHooks for F {
+    fn on_removed(&self, key: &Bytes, event: KeyEvent) {
+        self(key, event)
+    }
+}
+
+static HOOKS: OnceLock<Box<dyn KeyEventHooks>> = OnceLock::new();
+
+/// Calls `hooks` for every key removed from now on. Only the first hooks
+/// installed count; returns whether these are them.
+pub fn install(hooks: impl KeyEventHooks + 'static) -> bool {
+    HOOKS.set(Box::new(hooks)).is_ok()
+}
+
+/// Whether hooks are installed, so a flush only walks its keys if so.
+pub(crate) fn installed() -> bool {
+    HOOKS.get().is_some()
+}
+
+/// Runs the hooks on `key`.
+pub(crate) fn removed(key: &Bytes, event: KeyEvent) {
+    if let Some(hooks) = HOOKS.get() {
+        hooks.on_removed(key, event);
+    }
+}
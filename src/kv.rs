@@ -1,25 +1,486 @@
 use bytes::Bytes;
-use std::cell::RefCell;
-use std::collections::{HashMap, HashSet, VecDeque};
-use std::rc::Rc;
+use std::{
+    cell::Cell,
+    collections::{BTreeSet, HashSet},
+    hash::{BuildHasher, Hasher, RandomState},
+    sync::OnceLock,
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
 
-#[derive(Debug)]
+#[cfg(feature = "lists")]
+use crate::list::{List, LIST_MAX_LISTPACK_SIZE};
+#[cfg(feature = "sets")]
+use crate::set::{Set, SET_MAX_INTSET_ENTRIES};
+use crate::{
+    config::{Config, LazyFree, MaxmemoryPolicy, MAX_EXPIRE_JITTER_PERCENT},
+    dict::Dict,
+    keyevents::{self, KeyEvent},
+    lazyfree,
+    module::ModuleValue,
+    string::StringValue,
+};
+
+#[derive(Debug, PartialEq, Eq)]
 pub enum DatabaseError {
-    PoisonedLock,
     WrongType,
+    /// The reply would hold more elements than `command-budget` lets a
+    /// command build in one go; `instead` is how to read it in parts.
+    OverBudget {
+        elements: usize,
+        budget: usize,
+        instead: &'static str,
+    },
 }
 
 #[derive(Clone, Debug, PartialEq)]
 pub enum RedisValue {
-    String(Bytes),
-    List(VecDeque<Bytes>),
-    Set(HashSet<Bytes>),
+    String(StringValue),
+    #[cfg(feature = "lists")]
+    List(List),
+    #[cfg(feature = "sets")]
+    Set(Set),
+    /// A value of a data type a module added.
+    Module(ModuleValue),
+}
+
+impl RedisValue {
+    /// The value's size as `MEMORY BIGKEYS` reports it: bytes of a string,
+    /// items of a list, members of a set, what a module says of its values.
+    pub fn elements(&self) -> usize {
+        match self {
+            RedisValue::String(string) => string.len(),
+            #[cfg(feature = "lists")]
+            RedisValue::List(list) => list.len(),
+            #[cfg(feature = "sets")]
+            RedisValue::Set(set) => set.len(),
+            RedisValue::Module(value) => value.elements(),
+        }
+    }
+
+    /// Approximate bytes held by the value, not counting its key. Walks the
+    /// whole value; the store keeps this up to date per key instead.
+    pub fn memory_usage(&self) -> usize {
+        match self {
+            RedisValue::String(string) => string.memory_usage(),
+            #[cfg(feature = "lists")]
+            RedisValue::List(list) => list.memory_usage(),
+            #[cfg(feature = "sets")]
+            RedisValue::Set(set) => set.memory_usage(),
+            RedisValue::Module(value) => value.memory_usage(),
+        }
+    }
+
+    /// Name of the value's encoding, as `OBJECT ENCODING` reports it.
+    pub fn encoding(&self) -> &'static str {
+        match self {
+            RedisValue::String(string) => string.encoding(),
+            #[cfg(feature = "lists")]
+            RedisValue::List(list) => list.encoding(),
+            #[cfg(feature = "sets")]
+            RedisValue::Set(set) => set.encoding(),
+            RedisValue::Module(value) => value.type_name(),
+        }
+    }
+
+    /// Moves the value into fresh allocations, for active defrag. Returns the
+    /// allocations moved.
+    pub fn defrag(&mut self) -> usize {
+        match self {
+            RedisValue::String(string) => string.defrag(),
+            #[cfg(feature = "lists")]
+            RedisValue::List(list) => list.defrag(),
+            #[cfg(feature = "sets")]
+            RedisValue::Set(set) => set.defrag(),
+            // opaque to the server
+            RedisValue::Module(_) => 0,
+        }
+    }
+
+    pub fn value_type(&self) -> ValueType {
+        match self {
+            RedisValue::String(_) => ValueType::String,
+            #[cfg(feature = "lists")]
+            RedisValue::List(_) => ValueType::List,
+            #[cfg(feature = "sets")]
+            RedisValue::Set(_) => ValueType::Set,
+            RedisValue::Module(_) => ValueType::Module,
+        }
+    }
+}
+
+/// Serialized as what it holds, not how it's encoded: a string's bytes, or
+/// the elements of a list or set, tagged with the type. Module values can't
+/// be serialized.
+#[cfg(feature = "serde")]
+impl serde::Serialize for RedisValue {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        // the same indices whatever types are built, for formats using them
+        match self {
+            RedisValue::String(string) => {
+                serializer.serialize_newtype_variant("RedisValue", 0, "String", string)
+            }
+            #[cfg(feature = "lists")]
+            RedisValue::List(list) => {
+                serializer.serialize_newtype_variant("RedisValue", 1, "List", list)
+            }
+            #[cfg(feature = "sets")]
+            RedisValue::Set(set) => {
+                serializer.serialize_newtype_variant("RedisValue", 2, "Set", set)
+            }
+            RedisValue::Module(value) => Err(serde::ser::Error::custom(format_args!(
+                "can't serialize a value of module type {}",
+                value.type_name()
+            ))),
+        }
+    }
+}
+
+/// A value of a type that isn't built in is an error.
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for RedisValue {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(serde::Deserialize)]
+        #[serde(rename = "RedisValue")]
+        enum Repr {
+            String(StringValue),
+            #[cfg(feature = "lists")]
+            List(List),
+            #[cfg(not(feature = "lists"))]
+            List(serde::de::IgnoredAny),
+            #[cfg(feature = "sets")]
+            Set(Set),
+            #[cfg(not(feature = "sets"))]
+            Set(serde::de::IgnoredAny),
+        }
+
+        match Repr::deserialize(deserializer)? {
+            Repr::String(string) => Ok(RedisValue::String(string)),
+            #[cfg(feature = "lists")]
+            Repr::List(list) => Ok(RedisValue::List(list)),
+            #[cfg(not(feature = "lists"))]
+            Repr::List(_) => Err(serde::de::Error::custom("lists aren't built in")),
+            #[cfg(feature = "sets")]
+            Repr::Set(set) => Ok(RedisValue::Set(set)),
+            #[cfg(not(feature = "sets"))]
+            Repr::Set(_) => Err(serde::de::Error::custom("sets aren't built in")),
+        }
+    }
+}
+
+/// The kinds of value a key can hold.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValueType {
+    String,
+    List,
+    Set,
+    Module,
+}
+
+/// How many `ValueType`s there are, for arrays indexed by them.
+pub const VALUE_TYPES: usize = 4;
+
+impl ValueType {
+    pub const ALL: [ValueType; VALUE_TYPES] = [
+        ValueType::String,
+        ValueType::List,
+        ValueType::Set,
+        ValueType::Module,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ValueType::String => "string",
+            ValueType::List => "list",
+            ValueType::Set => "set",
+            ValueType::Module => "module",
+        }
+    }
+}
+
+/// Keys of one type in a shard and the approximate bytes they hold, keys
+/// included.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TypeUsage {
+    pub keys: usize,
+    pub bytes: usize,
+}
+
+/// A shard's `TypeUsage`, indexed by `ValueType`.
+#[derive(Debug, Default)]
+struct Usage([TypeUsage; VALUE_TYPES]);
+
+impl Usage {
+    fn add_key(&mut self, value_type: ValueType, bytes: usize) {
+        let usage = &mut self.0[value_type as usize];
+        usage.keys += 1;
+        usage.bytes += bytes;
+    }
+
+    fn remove_key(&mut self, value_type: ValueType, bytes: usize) {
+        let usage = &mut self.0[value_type as usize];
+        usage.keys -= 1;
+        usage.bytes -= bytes;
+    }
+
+    fn grow(&mut self, value_type: ValueType, bytes: usize) {
+        self.0[value_type as usize].bytes += bytes;
+    }
+
+    fn shrink(&mut self, value_type: ValueType, bytes: usize) {
+        self.0[value_type as usize].bytes -= bytes;
+    }
+
+    fn total(&self) -> usize {
+        self.0.iter().map(|usage| usage.bytes).sum()
+    }
+}
+
+/// The keys of a shard that have a TTL, ordered by when they expire, so the
+/// next ones due are found without walking the keyspace.
+#[derive(Debug, Default)]
+struct Expires {
+    index: BTreeSet<(u64, Bytes)>,
+    /// Sum of the expiry times in `index`, for `avg_ttl`.
+    sum: u128,
+}
+
+impl Expires {
+    fn add(&mut self, at: u64, key: Bytes) {
+        if self.index.insert((at, key)) {
+            self.sum += at as u128;
+        }
+    }
+
+    fn remove(&mut self, at: u64, key: &Bytes) {
+        if self.index.remove(&(at, key.clone())) {
+            self.sum -= at as u128;
+        }
+    }
+
+    /// The key that expires first, if it is due at `now`.
+    fn due(&self, now: u64) -> Option<&Bytes> {
+        self.index
+            .first()
+            .filter(|(at, _)| *at <= now)
+            .map(|(_, key)| key)
+    }
+
+    /// Up to `count` keys, starting from a random expiry time between the
+    /// first and the last, and wrapping around.
+    fn sample(&self, random: u64, count: usize) -> impl Iterator<Item = &Bytes> {
+        let (first, last) = match (self.index.first(), self.index.last()) {
+            (Some((first, _)), Some((last, _))) => (*first, *last),
+            _ => (0, 0),
+        };
+        let pivot = first + random % (last - first + 1);
+        self.index
+            .range((pivot, Bytes::new())..)
+            .chain(&self.index)
+            .take(count.clamp(1, self.index.len().max(1)))
+            .map(|(_, key)| key)
+    }
+
+    fn clear(&mut self) {
+        *self = Self::default();
+    }
 }
 
-#[derive(Clone, Debug)]
+/// Rough cost of a key besides its bytes: its hash table slot.
+const KEY_OVERHEAD: usize = std::mem::size_of::<(Bytes, Entry)>();
+/// Rough cost of a list or set element besides its bytes.
+#[cfg(any(feature = "lists", feature = "sets"))]
+const ELEMENT_OVERHEAD: usize = std::mem::size_of::<Bytes>();
+
+/// Drops a value taken out of the store, on the lazy-free thread if `lazy`.
+fn dispose(value: RedisValue, lazy: bool) {
+    if lazy {
+        lazyfree::free_value(value);
+    }
+}
+
+fn key_size(key: &[u8]) -> usize {
+    KEY_OVERHEAD + key.len()
+}
+
+#[cfg(any(feature = "lists", feature = "sets"))]
+pub(crate) fn element_size(element: &Bytes) -> usize {
+    ELEMENT_OVERHEAD + element.len()
+}
+
+/// Milliseconds since the clock was first read, wrapping. Key access times
+/// are kept on this clock.
+pub fn lru_clock() -> u32 {
+    static START: OnceLock<Instant> = OnceLock::new();
+    START.get_or_init(Instant::now).elapsed().as_millis() as u32
+}
+
+/// Milliseconds since the Unix epoch, the clock key expiry times are on; in a
+/// `Simulation`, on its virtual clock.
+pub fn unix_time_ms() -> u64 {
+    #[cfg(feature = "sim")]
+    if let Some(now) = crate::sim::virtual_unix_time_ms() {
+        return now;
+    }
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_millis() as u64)
+}
+
+/// Starting LFU counter of a new key, so it is not evicted before it had a
+/// chance to be accessed again (Redis' `LFU_INIT_VAL`).
+pub const LFU_INIT_VAL: u8 = 5;
+
+/// Tuning of the LFU counters, as Redis' `lfu-log-factor` and
+/// `lfu-decay-time`.
+#[derive(Debug)]
+struct Lfu {
+    /// How many accesses it takes to move a counter up: with 10, a counter
+    /// saturates after about a million hits.
+    log_factor: u32,
+    /// Minutes without access after which a counter is decremented by one.
+    /// 0 never decays.
+    decay_time: u32,
+    /// xorshift state, for the counters' probabilistic increment and for
+    /// eviction samples.
+    rng: Cell<u64>,
+}
+
+impl Lfu {
+    fn next_random(&self) -> u64 {
+        let mut x = self.rng.get();
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.rng.set(x);
+        x
+    }
+
+    /// `counter` after `idle_ms` without an access.
+    fn decay(&self, counter: u8, idle_ms: u32) -> u8 {
+        if self.decay_time == 0 {
+            return counter;
+        }
+        let periods = idle_ms / 60_000 / self.decay_time;
+        counter.saturating_sub(periods.min(u8::MAX as u32) as u8)
+    }
+
+    /// Logarithmic increment: the higher the counter, the less likely an
+    /// access moves it.
+    fn increment(&self, counter: u8) -> u8 {
+        if counter == u8::MAX {
+            return counter;
+        }
+        let base = counter.saturating_sub(LFU_INIT_VAL) as f64;
+        let p = 1.0 / (base * self.log_factor as f64 + 1.0);
+        let r = (self.next_random() >> 11) as f64 / (1u64 << 53) as f64;
+        if r < p {
+            counter + 1
+        } else {
+            counter
+        }
+    }
+}
+
+/// A stored value and the access metadata eviction decides on. Reads update
+/// the metadata too, hence the cells.
+#[derive(Debug)]
+struct Entry {
+    value: RedisValue,
+    /// `value.memory_usage()`, kept up to date as the value changes.
+    size: usize,
+    /// `lru_clock()` at the last access.
+    lru: Cell<u32>,
+    /// LFU counter as of the last access, see `Lfu`.
+    lfu: Cell<u8>,
+    /// When the key expires, in `unix_time_ms()`; `None` if it doesn't.
+    expires_at: Option<u64>,
+}
+
+impl Entry {
+    fn new(value: RedisValue) -> Self {
+        Self {
+            size: value.memory_usage(),
+            value,
+            lru: Cell::new(lru_clock()),
+            lfu: Cell::new(LFU_INIT_VAL),
+            expires_at: None,
+        }
+    }
+
+    fn is_expired(&self, now: u64) -> bool {
+        self.expires_at.is_some_and(|at| at <= now)
+    }
+
+    /// Records an access.
+    fn touch(&self, lfu: &Lfu) {
+        let now = lru_clock();
+        let counter = lfu.decay(self.lfu.get(), self.idle(now));
+        self.lfu.set(lfu.increment(counter));
+        self.lru.set(now);
+    }
+
+    fn idle(&self, now: u32) -> u32 {
+        now.wrapping_sub(self.lru.get())
+    }
+
+    fn frequency(&self, lfu: &Lfu, now: u32) -> u8 {
+        lfu.decay(self.lfu.get(), self.idle(now))
+    }
+}
+
+/// One worker's shard of the keyspace. The router sends every key to the same
+/// worker, so the shard is only ever touched by the thread that owns it and
+/// needs no lock or shared ownership.
+#[derive(Debug)]
 pub struct KvStore {
     // We use Bytes because it's cheap to clone (reference counted)
-    db: Rc<RefCell<HashMap<Bytes, RedisValue>>>,
+    db: Dict<Bytes, Entry>,
+    /// Approximate bytes held by the keys and values, per type, kept up to
+    /// date on every change.
+    usage: Usage,
+    expires: Expires,
+    lfu: Lfu,
+    lazyfree: LazyFree,
+    /// `list-max-listpack-size`, see `list::fits_listpack`.
+    #[cfg(feature = "lists")]
+    list_max_listpack_size: i64,
+    #[cfg(feature = "sets")]
+    set_max_intset_entries: usize,
+    /// `command-budget`, 0 for none.
+    command_budget: usize,
+    /// `expire-jitter-percent`, see `jitter_expiry`.
+    expire_jitter_percent: u64,
+    /// Reads that found their key, and reads that didn't, for INFO's
+    /// `keyspace_hits` and `keyspace_misses`.
+    keyspace_hits: Cell<u64>,
+    keyspace_misses: Cell<u64>,
+    /// Keys removed because their TTL passed, for INFO's `expired_keys`.
+    expired_keys: u64,
+    /// What changed since `take_changes`, once `track_changes` was called.
+    changes: Option<Changes>,
+}
+
+/// The keys of a shard written to, removed or given another TTL, and
+/// whether it was flushed before them, for a copy of it to catch up with.
+#[derive(Debug, Default, Clone)]
+pub struct Changes {
+    pub flushed: bool,
+    pub keys: HashSet<Bytes>,
+}
+
+impl Changes {
+    pub fn is_empty(&self) -> bool {
+        !self.flushed && self.keys.is_empty()
+    }
+
+    /// Adds `other`'s changes, which came after these.
+    pub fn merge(&mut self, other: &Changes) {
+        if other.flushed {
+            *self = other.clone();
+        } else {
+            self.keys.extend(other.keys.iter().cloned());
+        }
+    }
 }
 
 impl Default for KvStore {
@@ -28,204 +489,768 @@ impl Default for KvStore {
     }
 }
 
-fn resolve_range(start: i64, stop: i64, len: usize) -> (usize, usize) {
+/// The first and last index `LRANGE start stop` covers in a list of `len`
+/// elements, as Redis resolves them: negative ones count from the end, and
+/// the range is cut to the list; `None` if nothing of it is left.
+#[cfg(feature = "lists")]
+fn resolve_range(start: i64, stop: i64, len: usize) -> Option<(usize, usize)> {
     let len = len as i64;
+    let start = if start < 0 {
+        (len + start).max(0)
+    } else {
+        start
+    };
+    let stop = if stop < 0 { len + stop } else { stop };
 
-    let mut start = if start < 0 { len + start } else { start };
-    let mut stop = if stop < 0 { len + stop } else { stop };
-
-    start = start.clamp(0, len);
-    stop = stop.clamp(0, len - 1);
-
-    if start > stop || len == 0 {
-        return (0, 0); // Empty range
+    if start > stop || start >= len {
+        return None;
     }
-
-    (start as usize, stop as usize)
+    Some((start as usize, stop.min(len - 1) as usize))
 }
 
 impl KvStore {
     pub fn new() -> Self {
+        Self::with_lfu(10, 1)
+    }
+
+    /// A store whose LFU counters use the given `lfu-log-factor` and
+    /// `lfu-decay-time` (minutes).
+    pub fn with_lfu(log_factor: u32, decay_time: u32) -> Self {
         Self {
-            db: Rc::new(RefCell::new(HashMap::new())),
+            db: Dict::new(),
+            usage: Usage::default(),
+            expires: Expires::default(),
+            lfu: Lfu {
+                log_factor,
+                decay_time,
+                rng: Cell::new(RandomState::new().build_hasher().finish() | 1),
+            },
+            lazyfree: LazyFree::default(),
+            #[cfg(feature = "lists")]
+            list_max_listpack_size: LIST_MAX_LISTPACK_SIZE,
+            #[cfg(feature = "sets")]
+            set_max_intset_entries: SET_MAX_INTSET_ENTRIES,
+            command_budget: 0,
+            expire_jitter_percent: 0,
+            keyspace_hits: Cell::new(0),
+            keyspace_misses: Cell::new(0),
+            expired_keys: 0,
+            changes: None,
+        }
+    }
+
+    /// A store set up with the server's LFU and lazy-free settings.
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            db: Dict::with_capacity(config.shard_capacity),
+            lazyfree: config.lazyfree,
+            #[cfg(feature = "lists")]
+            list_max_listpack_size: config.list_max_listpack_size,
+            #[cfg(feature = "sets")]
+            set_max_intset_entries: config.set_max_intset_entries,
+            command_budget: config.command_budget,
+            expire_jitter_percent: config.expire_jitter_percent.min(MAX_EXPIRE_JITTER_PERCENT),
+            ..Self::with_lfu(config.lfu_log_factor, config.lfu_decay_time)
+        }
+    }
+
+    /// Stores the string `value` at `key`, replacing whatever was there,
+    /// whatever its type, and its TTL.
+    pub fn set(&mut self, key: Bytes, value: Bytes) {
+        let key_size = key_size(&key);
+        let value = StringValue::from(value);
+        self.usage
+            .add_key(ValueType::String, key_size + value.memory_usage());
+        self.changed(&key);
+        if let Some(old) = self
+            .db
+            .insert(key.clone(), Entry::new(RedisValue::String(value)))
+        {
+            self.replaced(&key, key_size, old);
+        }
+    }
+
+    /// Stores `value` at `key`, replacing whatever was there.
+    pub fn insert(&mut self, key: Bytes, value: RedisValue) {
+        let key_size = key_size(&key);
+        let entry = Entry::new(value);
+        self.usage
+            .add_key(entry.value.value_type(), key_size + entry.size);
+        self.changed(&key);
+        if let Some(old) = self.db.insert(key.clone(), entry) {
+            self.replaced(&key, key_size, old);
+        }
+    }
+
+    /// Accounts for `old`, the entry at `key` a new value took the place of.
+    fn replaced(&mut self, key: &Bytes, key_size: usize, old: Entry) {
+        self.usage
+            .remove_key(old.value.value_type(), key_size + old.size);
+        if let Some(at) = old.expires_at {
+            self.expires.remove(at, key);
         }
+        dispose(old.value, self.lazyfree.server_del);
     }
 
-    pub fn set(&self, key: Bytes, value: Bytes) -> Result<(), DatabaseError> {
-        let mut db = self.db.borrow_mut();
+    /// Runs `f` on the value at `key`, if there is one, then takes its size
+    /// again. Counts as an access, not as a read.
+    pub fn update<R>(&mut self, key: &Bytes, f: impl FnOnce(&mut RedisValue) -> R) -> Option<R> {
+        self.expire_if_needed(key);
+        let entry = self.db.get_mut(key)?;
+        entry.touch(&self.lfu);
+        let result = f(&mut entry.value);
+        if let Some(changes) = &mut self.changes {
+            changes.keys.insert(key.clone());
+        }
+        let (before, value_type) = (entry.size, entry.value.value_type());
+        entry.size = entry.value.memory_usage();
+        if entry.size >= before {
+            self.usage.grow(value_type, entry.size - before);
+        } else {
+            self.usage.shrink(value_type, before - entry.size);
+        }
+        Some(result)
+    }
+
+    /// Looks `key` up for a read, counting it as an access and as a
+    /// keyspace hit or miss. Writes and `OBJECT`/`MEMORY` lookups don't count.
+    pub fn get(&self, key: &Bytes) -> Option<&RedisValue> {
+        let entry = self.lookup(key);
+        self.count_lookup(entry.is_some());
+        let entry = entry?;
+        entry.touch(&self.lfu);
+        Some(&entry.value)
+    }
+
+    fn count_lookup(&self, hit: bool) {
+        let counter = if hit {
+            &self.keyspace_hits
+        } else {
+            &self.keyspace_misses
+        };
+        counter.set(counter.get() + 1);
+    }
+
+    /// Reads that found their key since the store was created.
+    pub fn keyspace_hits(&self) -> u64 {
+        self.keyspace_hits.get()
+    }
+
+    /// Reads that didn't find their key since the store was created.
+    pub fn keyspace_misses(&self) -> u64 {
+        self.keyspace_misses.get()
+    }
+
+    /// `OBJECT FREQ`: the key's LFU counter, without counting as an access.
+    pub fn object_freq(&self, key: &Bytes) -> Option<u8> {
+        let entry = self.lookup(key)?;
+        Some(entry.frequency(&self.lfu, lru_clock()))
+    }
+
+    /// `OBJECT ENCODING`: how the key's value is stored, without counting as
+    /// an access.
+    pub fn object_encoding(&self, key: &Bytes) -> Option<&'static str> {
+        Some(self.lookup(key)?.value.encoding())
+    }
+
+    /// `MEMORY USAGE`: approximate bytes held by the key and its value.
+    pub fn memory_usage(&self, key: &Bytes) -> Option<usize> {
+        let entry = self.lookup(key)?;
+        Some(key_size(key) + entry.size)
+    }
+
+    /// `OBJECT IDLETIME`: seconds since the key was last accessed.
+    pub fn object_idletime(&self, key: &Bytes) -> Option<u64> {
+        let entry = self.lookup(key)?;
+        Some(entry.idle(lru_clock()) as u64 / 1000)
+    }
+
+    /// When `key` expires, in `unix_time_ms()`: `None` if it doesn't, or
+    /// doesn't exist.
+    pub fn expires_at(&self, key: &Bytes) -> Option<u64> {
+        self.lookup(key)?.expires_at
+    }
+
+    /// Makes `key` expire at `at`, in `unix_time_ms()`, or never with `None`.
+    /// Returns whether the key exists.
+    pub fn set_expires_at(&mut self, key: &Bytes, at: Option<u64>) -> bool {
+        self.expire_if_needed(key);
+        let Some(entry) = self.db.get_mut(key) else {
+            return false;
+        };
+        if let Some(changes) = &mut self.changes {
+            changes.keys.insert(key.clone());
+        }
+        if let Some(old) = std::mem::replace(&mut entry.expires_at, at) {
+            self.expires.remove(old, key);
+        }
+        if let Some(at) = at {
+            self.expires.add(at, key.clone());
+        }
+        true
+    }
+
+    /// `at`, in `unix_time_ms()`, brought forward by a random share of the
+    /// time left until then, up to `expire-jitter-percent` of it, so that
+    /// keys given the same TTL expire spread out rather than all at once.
+    pub fn jitter_expiry(&self, at: u64) -> u64 {
+        if self.expire_jitter_percent == 0 {
+            return at;
+        }
+        let left = at.saturating_sub(unix_time_ms()) as u128;
+        let most = left * self.expire_jitter_percent as u128 / 100;
+        let cut = (self.lfu.next_random() as u128 * (most + 1)) >> 64;
+        at - cut as u64
+    }
+
+    /// Keys in this shard with a TTL, for INFO's `expires`.
+    pub fn volatile_keys(&self) -> usize {
+        self.expires.index.len()
+    }
+
+    /// Average milliseconds left before the keys with a TTL expire, 0 if
+    /// there are none, for INFO's `avg_ttl`.
+    pub fn avg_ttl(&self) -> u64 {
+        let count = self.expires.index.len() as u128;
+        if count == 0 {
+            return 0;
+        }
+        (self.expires.sum / count).saturating_sub(unix_time_ms() as u128) as u64
+    }
 
-        db.insert(key, RedisValue::String(value));
+    /// Keys removed since the store was created because their TTL passed,
+    /// whether a command or `expire_keys` got to them.
+    pub fn expired_keys(&self) -> u64 {
+        self.expired_keys
+    }
+
+    /// Removes up to `max` keys whose TTL has passed, soonest first, like
+    /// Redis' active expire cycle. Returns how many it removed.
+    pub fn expire_keys(&mut self, max: usize) -> usize {
+        let now = unix_time_ms();
+        let mut removed = 0;
+        while removed < max {
+            let Some(key) = self.expires.due(now).cloned() else {
+                break;
+            };
+            self.expire(&key);
+            removed += 1;
+        }
+        removed
+    }
+
+    /// Number of keys in this shard, counting those expired but not removed
+    /// yet, as Redis' `DBSIZE` does.
+    pub fn len(&self) -> usize {
+        self.db.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.db.is_empty()
+    }
+
+    /// Approximate bytes held by this shard's keys and values.
+    pub fn used_memory(&self) -> usize {
+        self.usage.total()
+    }
+
+    /// Keys and bytes held by each type of value, indexed by `ValueType`.
+    pub fn type_usage(&self) -> [TypeUsage; VALUE_TYPES] {
+        self.usage.0
+    }
+
+    /// Moves up to `buckets` buckets of a resizing keyspace into its new
+    /// table, like Redis' `activerehashing`. Returns whether there is more
+    /// to move.
+    pub fn rehash(&mut self, buckets: usize) -> bool {
+        self.db.rehash(buckets)
+    }
+
+    /// Moves the keys and values in up to `buckets` buckets of the keyspace,
+    /// starting at `cursor`, into fresh allocations, for active defrag.
+    /// Returns the cursor to carry on from, `None` once the whole keyspace
+    /// has been walked, and the allocations and keys moved.
+    pub fn defrag(&mut self, cursor: usize, buckets: usize) -> (Option<usize>, usize, usize) {
+        let (mut hits, mut keys) = (0, 0);
+        let mut cursor = Some(cursor);
+        for _ in 0..buckets {
+            let Some(at) = cursor else {
+                break;
+            };
+            let expires = &mut self.expires;
+            cursor = self.db.defrag_bucket(at, |key, entry| {
+                *key = Bytes::copy_from_slice(key);
+                if let Some(at) = entry.expires_at {
+                    // so the index doesn't keep the old copy alive
+                    expires.index.replace((at, key.clone()));
+                }
+                // the key, the value and the node holding them
+                hits += entry.value.defrag() + 2;
+                keys += 1;
+            });
+        }
+        (cursor, hits, keys)
+    }
+
+    /// Calls `f` with the key, value, approximate bytes and expiry time of
+    /// every live key in up to `buckets` buckets of the keyspace, starting at
+    /// `cursor` (0 for the first), without counting them as accessed. Returns
+    /// the cursor to carry on from, `None` once the whole keyspace has been
+    /// walked. Like `SCAN`, a walk sees every key that is there from its
+    /// start to its end, even if the keyspace is resized in between, and may
+    /// see a key twice if it shrinks; see `Dict::scan_bucket`.
+    pub fn scan(
+        &self,
+        cursor: usize,
+        buckets: usize,
+        mut f: impl FnMut(&Bytes, &RedisValue, usize, Option<u64>),
+    ) -> Option<usize> {
+        let now = unix_time_ms();
+        let mut cursor = Some(cursor);
+        for _ in 0..buckets {
+            let at = cursor?;
+            cursor = self.db.scan_bucket(at, |key, entry| {
+                if !entry.is_expired(now) {
+                    f(
+                        key,
+                        &entry.value,
+                        key_size(key) + entry.size,
+                        entry.expires_at,
+                    )
+                }
+            });
+        }
+        cursor
+    }
+
+    /// Fails with `OverBudget` if a reply of `elements` elements is more
+    /// than `command-budget` allows, pointing to `instead`.
+    #[cfg(any(feature = "lists", feature = "sets"))]
+    fn check_budget(&self, elements: usize, instead: &'static str) -> Result<(), DatabaseError> {
+        let budget = self.command_budget;
+        if budget > 0 && elements > budget {
+            return Err(DatabaseError::OverBudget {
+                elements,
+                budget,
+                instead,
+            });
+        }
         Ok(())
     }
 
-    pub fn get(&self, key: &Bytes) -> Result<Option<RedisValue>, DatabaseError> {
-        let db = self.db.borrow();
-        Ok(db.get(key).cloned()) // Cloning Bytes is O(1)
+    /// Removes every key in this shard, on the lazy-free thread if
+    /// `lazyfree-lazy-user-flush` is set or there are more keys than
+    /// `command-budget`.
+    pub fn clear(&mut self) {
+        let over_budget = self.command_budget > 0 && self.db.len() > self.command_budget;
+        self.flush(self.lazyfree.user_flush || over_budget);
     }
 
-    pub fn lpush(&self, key: Bytes, values: Vec<Bytes>) -> Result<i64, DatabaseError> {
-        let mut db = self.db.borrow_mut();
+    /// Removes every key in this shard, telling the key event hooks about
+    /// each first. With `lazy`, the keys are dropped on the lazy-free thread
+    /// (`FLUSHALL ASYNC`).
+    pub fn flush(&mut self, lazy: bool) {
+        if keyevents::installed() {
+            for (key, _) in self.db.iter() {
+                keyevents::removed(key, KeyEvent::Flushed);
+            }
+        }
+        if lazy && !self.db.is_empty() {
+            lazyfree::free(Box::new(self.db.take()));
+        } else {
+            self.db.clear();
+        }
+        self.usage = Usage::default();
+        self.expires.clear();
+        if let Some(changes) = &mut self.changes {
+            *changes = Changes {
+                flushed: true,
+                keys: HashSet::new(),
+            };
+        }
+    }
+
+    /// Removes `key` whatever its type, returning whether it existed. Like
+    /// `unlink` if `lazyfree-lazy-user-del` is set.
+    pub fn del(&mut self, key: &Bytes) -> bool {
+        self.expire_if_needed(key);
+        let lazy = self.lazyfree.user_del;
+        self.remove(key).map(|value| dispose(value, lazy)).is_some()
+    }
 
-        let entry = db
-            .entry(key)
-            .or_insert_with(|| RedisValue::List(VecDeque::new()));
-        match entry {
+    /// Removes `key` like `del`, but leaves dropping a big value to the
+    /// lazy-free thread.
+    pub fn unlink(&mut self, key: &Bytes) -> bool {
+        self.expire_if_needed(key);
+        self.remove(key).map(|value| dispose(value, true)).is_some()
+    }
+
+    /// Removes `key`, which a command that panicked may have left half
+    /// updated, telling the key event hooks. Returns whether it existed.
+    pub fn discard(&mut self, key: &Bytes) -> bool {
+        let Some(value) = self.remove(key) else {
+            return false;
+        };
+        dispose(value, false);
+        keyevents::removed(key, KeyEvent::Dropped);
+        true
+    }
+
+    /// Whether `key` exists, counted as a keyspace hit or miss but not as an
+    /// access.
+    pub fn exists(&self, key: &Bytes) -> bool {
+        let found = self.contains_key(key);
+        self.count_lookup(found);
+        found
+    }
+
+    /// The value at `key`, counted neither as a read nor as an access.
+    pub fn peek(&self, key: &Bytes) -> Option<&RedisValue> {
+        self.lookup(key).map(|entry| &entry.value)
+    }
+
+    /// Records the keys changed from now on, for `take_changes`.
+    pub fn track_changes(&mut self) {
+        self.changes.get_or_insert_default();
+    }
+
+    /// What changed since the last call, or since `track_changes`.
+    pub fn take_changes(&mut self) -> Changes {
+        self.changes
+            .as_mut()
+            .map(std::mem::take)
+            .unwrap_or_default()
+    }
+
+    fn changed(&mut self, key: &Bytes) {
+        if let Some(changes) = &mut self.changes {
+            changes.keys.insert(key.clone());
+        }
+    }
+
+    /// Whether `key` exists, counted neither as a read nor as an access.
+    pub fn contains_key(&self, key: &Bytes) -> bool {
+        self.lookup(key).is_some()
+    }
+
+    /// The entry at `key`, unless it has expired.
+    fn lookup(&self, key: &Bytes) -> Option<&Entry> {
+        self.db
+            .get(key)
+            .filter(|entry| !entry.is_expired(unix_time_ms()))
+    }
+
+    /// Removes `key` if it has expired, so that a write finds it missing.
+    /// Reads skip expired keys without removing them, as they can't change
+    /// the store.
+    fn expire_if_needed(&mut self, key: &Bytes) {
+        let expired = self
+            .db
+            .get(key)
+            .is_some_and(|entry| entry.is_expired(unix_time_ms()));
+        if expired {
+            self.expire(key);
+        }
+    }
+
+    fn expire(&mut self, key: &Bytes) {
+        if let Some(value) = self.remove(key) {
+            dispose(value, self.lazyfree.expire);
+            self.expired_keys += 1;
+            keyevents::removed(key, KeyEvent::Expired);
+        }
+    }
+
+    /// Evicts one key chosen by `policy` among `samples` keys picked at
+    /// random, like Redis' approximated LRU/LFU. The `volatile-*` policies
+    /// only pick among keys with a TTL, `volatile-ttl` the one that expires
+    /// first. Returns the bytes freed, or `None` if the policy has nothing
+    /// it may evict.
+    pub fn evict(&mut self, policy: MaxmemoryPolicy, samples: usize) -> Option<usize> {
+        if self.db.is_empty() {
+            return None;
+        }
+        let now = lru_clock();
+        let random = self.lfu.next_random();
+        let sampled: Vec<(&Bytes, &Entry)> = match policy {
+            MaxmemoryPolicy::NoEviction => return None,
+            MaxmemoryPolicy::AllKeysLru
+            | MaxmemoryPolicy::AllKeysLfu
+            | MaxmemoryPolicy::AllKeysRandom => loop {
+                // a sparse table may take a few tries
+                let sampled = self.db.sample(samples.max(1), || self.lfu.next_random());
+                if !sampled.is_empty() {
+                    break sampled;
+                }
+            },
+            // the first key of the index is the one that expires first
+            MaxmemoryPolicy::VolatileTtl => self.volatile_entries(0, 1),
+            MaxmemoryPolicy::VolatileLru
+            | MaxmemoryPolicy::VolatileLfu
+            | MaxmemoryPolicy::VolatileRandom => self.volatile_entries(random, samples),
+        };
+        let sampled = sampled.into_iter();
+
+        let victim = match policy {
+            MaxmemoryPolicy::NoEviction => None,
+            MaxmemoryPolicy::AllKeysRandom
+            | MaxmemoryPolicy::VolatileRandom
+            | MaxmemoryPolicy::VolatileTtl => sampled.map(|(key, _)| key).next(),
+            MaxmemoryPolicy::AllKeysLru | MaxmemoryPolicy::VolatileLru => sampled
+                .max_by_key(|(_, entry)| entry.idle(now))
+                .map(|(key, _)| key),
+            MaxmemoryPolicy::AllKeysLfu | MaxmemoryPolicy::VolatileLfu => sampled
+                .min_by_key(|(_, entry)| {
+                    let frequency = entry.frequency(&self.lfu, now);
+                    (frequency, std::cmp::Reverse(entry.idle(now)))
+                })
+                .map(|(key, _)| key),
+        }?
+        .clone();
+
+        let before = self.used_memory();
+        let value = self.remove(&victim)?;
+        dispose(value, self.lazyfree.eviction);
+        keyevents::removed(&victim, KeyEvent::Evicted);
+        Some(before - self.used_memory())
+    }
+
+    /// Up to `count` keys with a TTL and their entries, see `Expires::sample`.
+    fn volatile_entries(&self, random: u64, count: usize) -> Vec<(&Bytes, &Entry)> {
+        self.expires
+            .sample(random, count)
+            .filter_map(|key| Some((key, self.db.get(key)?)))
+            .collect()
+    }
+
+    fn remove(&mut self, key: &Bytes) -> Option<RedisValue> {
+        let entry = self.db.remove(key)?;
+        self.changed(key);
+        self.usage
+            .remove_key(entry.value.value_type(), key_size(key) + entry.size);
+        if let Some(at) = entry.expires_at {
+            self.expires.remove(at, key);
+        }
+        Some(entry.value)
+    }
+
+    /// The entry at `key`, created with `empty` if missing. Counts as an
+    /// access.
+    #[cfg(any(feature = "lists", feature = "sets"))]
+    fn entry_or_insert(&mut self, key: Bytes, empty: fn() -> RedisValue) -> &mut Entry {
+        self.expire_if_needed(&key);
+        self.changed(&key);
+        let usage = &mut self.usage;
+        let entry = self.db.get_or_insert_with(key, |key| {
+            let value = empty();
+            usage.add_key(value.value_type(), key_size(key) + value.memory_usage());
+            Entry::new(value)
+        });
+        entry.touch(&self.lfu);
+        entry
+    }
+
+    #[cfg(feature = "lists")]
+    pub fn lpush(&mut self, key: Bytes, values: Vec<Bytes>) -> Result<i64, DatabaseError> {
+        let max_listpack_size = self.list_max_listpack_size;
+        let entry = self.entry_or_insert(key, || RedisValue::List(List::new()));
+        let (len, added) = match &mut entry.value {
             RedisValue::List(list) => {
+                let before = list.memory_usage();
                 for val in values {
-                    list.push_front(val);
+                    list.push_front(val, max_listpack_size);
                 }
-                Ok(list.len() as i64)
+                (list.len() as i64, list.memory_usage() - before)
             }
-            _ => Err(DatabaseError::WrongType),
-        }
+            _ => return Err(DatabaseError::WrongType),
+        };
+        entry.size += added;
+
+        self.usage.grow(ValueType::List, added);
+        Ok(len)
     }
 
-    pub fn lpop(&self, key: &Bytes, count: i64) -> Result<Vec<Bytes>, DatabaseError> {
-        let mut db = self.db.borrow_mut();
-        let (popped_elements, should_remove) = match db.get_mut(key) {
-            Some(RedisValue::List(list)) => {
-                let length = list.len();
-                let num_pop = std::cmp::min(length, count as usize);
-                let popped: Vec<Bytes> = list.drain(..num_pop).collect();
-                (popped, list.is_empty())
+    #[cfg(feature = "lists")]
+    pub fn lpop(&mut self, key: &Bytes, count: i64) -> Result<Vec<Bytes>, DatabaseError> {
+        self.expire_if_needed(key);
+        let (popped_elements, should_remove, removed) = match self.db.get_mut(key) {
+            Some(entry) => {
+                let (popped, empty, removed) = match &mut entry.value {
+                    RedisValue::List(list) => {
+                        let before = list.memory_usage();
+                        let num_pop = std::cmp::min(list.len(), count as usize);
+                        let popped: Vec<Bytes> =
+                            (0..num_pop).filter_map(|_| list.pop_front()).collect();
+                        (popped, list.is_empty(), before - list.memory_usage())
+                    }
+                    _ => return Err(DatabaseError::WrongType),
+                };
+                entry.size -= removed;
+                (popped, empty, removed)
             }
-            Some(_) => return Err(DatabaseError::WrongType),
             None => return Ok(vec![]),
         };
 
+        self.usage.shrink(ValueType::List, removed);
         if should_remove {
-            db.remove(key);
+            self.remove(key);
         }
 
         Ok(popped_elements)
     }
 
-    pub fn rpush(&self, key: Bytes, values: Vec<Bytes>) -> Result<i64, DatabaseError> {
-        let mut db = self.db.borrow_mut();
-
-        let entry = db
-            .entry(key)
-            .or_insert_with(|| RedisValue::List(VecDeque::new()));
-        match entry {
+    #[cfg(feature = "lists")]
+    pub fn rpush(&mut self, key: Bytes, values: Vec<Bytes>) -> Result<i64, DatabaseError> {
+        let max_listpack_size = self.list_max_listpack_size;
+        let entry = self.entry_or_insert(key, || RedisValue::List(List::new()));
+        let (len, added) = match &mut entry.value {
             RedisValue::List(list) => {
+                let before = list.memory_usage();
                 for val in values {
-                    list.push_back(val);
+                    list.push_back(val, max_listpack_size);
                 }
-                Ok(list.len() as i64)
+                (list.len() as i64, list.memory_usage() - before)
             }
-            _ => Err(DatabaseError::WrongType),
-        }
+            _ => return Err(DatabaseError::WrongType),
+        };
+        entry.size += added;
+
+        self.usage.grow(ValueType::List, added);
+        Ok(len)
     }
 
-    pub fn rpop(&self, key: &Bytes, count: i64) -> Result<Vec<Bytes>, DatabaseError> {
-        let mut db = self.db.borrow_mut();
-        let (popped_elements, should_remove) = match db.get_mut(key) {
-            Some(RedisValue::List(list)) => {
-                let length = list.len();
-                let num_pop = std::cmp::min(length, count as usize);
-                let popped: Vec<Bytes> = list.drain((length - num_pop)..).collect();
-                (popped, list.is_empty())
+    #[cfg(feature = "lists")]
+    pub fn rpop(&mut self, key: &Bytes, count: i64) -> Result<Vec<Bytes>, DatabaseError> {
+        self.expire_if_needed(key);
+        let (popped_elements, should_remove, removed) = match self.db.get_mut(key) {
+            Some(entry) => {
+                let (popped, empty, removed) = match &mut entry.value {
+                    RedisValue::List(list) => {
+                        let before = list.memory_usage();
+                        let num_pop = std::cmp::min(list.len(), count as usize);
+                        // rightmost first, the order they are popped in
+                        let popped: Vec<Bytes> =
+                            (0..num_pop).filter_map(|_| list.pop_back()).collect();
+                        (popped, list.is_empty(), before - list.memory_usage())
+                    }
+                    _ => return Err(DatabaseError::WrongType),
+                };
+                entry.size -= removed;
+                (popped, empty, removed)
             }
-            Some(_) => return Err(DatabaseError::WrongType),
             None => return Ok(vec![]),
         };
 
+        self.usage.shrink(ValueType::List, removed);
         if should_remove {
-            db.remove(key);
+            self.remove(key);
         }
 
         Ok(popped_elements)
     }
 
+    #[cfg(feature = "lists")]
     pub fn lrange(&self, key: &Bytes, start: i64, stop: i64) -> Result<Vec<Bytes>, DatabaseError> {
-        let db = self.db.borrow();
-
-        let val = match db.get(key) {
+        let val = match self.get(key) {
             Some(RedisValue::List(list)) => list,
             Some(_) => return Err(DatabaseError::WrongType),
             None => return Ok(vec![]),
         };
 
-        let len = val.len();
-        if len == 0 {
+        let Some((start_idx, stop_idx)) = resolve_range(start, stop, val.len()) else {
             return Ok(vec![]);
-        }
-
-        let (start_idx, stop_idx) = resolve_range(start, stop, len);
-
-        if start_idx > stop_idx && len > 0 && !(start_idx == 0 && stop_idx == 0) {
-            return Ok(vec![]);
-        }
-
+        };
         let count = (stop_idx - start_idx) + 1;
-        let result = val
-            .iter()
-            .skip(start_idx)
-            .take(count)
-            .cloned() // Increments ref-count on Bytes, very fast
-            .collect();
+        self.check_budget(count, "smaller LRANGE ranges")?;
+        let result = val.iter().skip(start_idx).take(count).collect();
 
         Ok(result)
     }
 
-    pub fn sadd(&self, key: Bytes, values: Vec<Bytes>) -> Result<i64, DatabaseError> {
-        let mut db = self.db.borrow_mut();
-
-        let entry = db
-            .entry(key)
-            .or_insert_with(|| RedisValue::Set(HashSet::new()));
+    #[cfg(feature = "sets")]
+    pub fn sadd(&mut self, key: Bytes, values: Vec<Bytes>) -> Result<i64, DatabaseError> {
+        let max_intset_entries = self.set_max_intset_entries;
+        let entry = self.entry_or_insert(key, || RedisValue::Set(Set::new()));
 
-        match entry {
+        let (count, added) = match &mut entry.value {
             RedisValue::Set(set) => {
+                let before = set.memory_usage();
                 let mut count = 0;
                 for val in values {
-                    if set.insert(val) {
-                        count += 1
+                    if set.insert(val, max_intset_entries) {
+                        count += 1;
                     };
                 }
-                Ok(count)
+                (count, set.memory_usage() - before)
             }
-            _ => Err(DatabaseError::WrongType),
-        }
-    }
-
-    pub fn spop(&self, key: &Bytes, count: i64) -> Result<Vec<Bytes>, DatabaseError> {
-        let mut db = self.db.borrow_mut();
+            _ => return Err(DatabaseError::WrongType),
+        };
+        entry.size += added;
 
-        let (popped_elements, should_remove) = match db.get_mut(key) {
-            Some(RedisValue::Set(set)) => {
-                let num_to_pop = std::cmp::min(set.len(), count as usize);
-                let mut popped = Vec::with_capacity(num_to_pop);
+        self.usage.grow(ValueType::Set, added);
+        Ok(count)
+    }
 
-                for _ in 0..num_to_pop {
-                    if let Some(member) = set.iter().next().cloned() {
-                        set.remove(&member);
-                        popped.push(member);
+    #[cfg(feature = "sets")]
+    pub fn spop(&mut self, key: &Bytes, count: i64) -> Result<Vec<Bytes>, DatabaseError> {
+        self.expire_if_needed(key);
+        let (popped_elements, should_remove, removed) = match self.db.get_mut(key) {
+            Some(entry) => {
+                let (popped, empty, removed) = match &mut entry.value {
+                    RedisValue::Set(set) => {
+                        let before = set.memory_usage();
+                        let num_to_pop = std::cmp::min(set.len(), count as usize);
+                        let popped: Vec<Bytes> =
+                            (0..num_to_pop).filter_map(|_| set.pop()).collect();
+                        (popped, set.is_empty(), before - set.memory_usage())
                     }
-                }
-                (popped, set.is_empty())
+                    _ => return Err(DatabaseError::WrongType),
+                };
+                entry.size -= removed;
+                (popped, empty, removed)
             }
-            Some(_) => return Err(DatabaseError::WrongType),
             None => return Ok(vec![]),
         };
 
+        self.usage.shrink(ValueType::Set, removed);
         if should_remove {
-            db.remove(key);
+            self.remove(key);
         }
 
         Ok(popped_elements)
     }
 
+    #[cfg(feature = "sets")]
     pub fn smembers(&self, key: &Bytes) -> Result<Vec<Bytes>, DatabaseError> {
-        let db = self.db.borrow();
-
-        match db.get(key) {
+        match self.get(key) {
             Some(RedisValue::Set(set)) => {
-                let members: Vec<Bytes> = set.iter().cloned().collect();
+                self.check_budget(set.len(), "SSCAN")?;
+                let members: Vec<Bytes> = set.iter().collect();
                 Ok(members)
             }
             Some(_) => Err(DatabaseError::WrongType),
             None => Ok(vec![]),
         }
     }
+
+    /// Up to `count` members of the set at `key` from `cursor` on, no more
+    /// than `command-budget` allows unless it is an intset, and the cursor
+    /// to carry on from, 0 once done; see `Set::scan`.
+    #[cfg(feature = "sets")]
+    pub fn sscan(
+        &self,
+        key: &Bytes,
+        cursor: usize,
+        count: usize,
+    ) -> Result<(usize, Vec<Bytes>), DatabaseError> {
+        let count = match self.command_budget {
+            0 => count,
+            budget => count.min(budget),
+        };
+        match self.get(key) {
+            Some(RedisValue::Set(set)) => Ok(set.scan(cursor, count)),
+            Some(_) => Err(DatabaseError::WrongType),
+            None => Ok((0, vec![])),
+        }
+    }
 }
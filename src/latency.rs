@@ -0,0 +1,130 @@
+//! Per-command execution time histograms, for INFO `latencystats`.
+//!
+//! Each worker records how long every command it runs takes into a
+//! histogram per command name; INFO merges the workers' histograms and reads
+//! percentiles off the sum. Like HdrHistogram, buckets are linear within each
+//! power of two, so any recorded value is off by less than 1%, whatever its
+//! magnitude, in a fixed 25KB per command and worker.
+
+use std::collections::BTreeMap;
+
+/// Sub-buckets each power of two is split into: 2^7, under 1% apart.
+const SUB_BUCKET_BITS: u32 = 7;
+const SUB_BUCKETS: usize = 1 << SUB_BUCKET_BITS;
+
+/// Highest value tracked, in nanoseconds, about a second (as in Redis);
+/// slower commands are counted as taking this long.
+pub const MAX_NANOS: u64 = (1 << 30) - 1;
+
+const BUCKETS: usize = (30 - SUB_BUCKET_BITS as usize + 1) * SUB_BUCKETS;
+
+/// Percentiles INFO `latencystats` reports, as Redis' default
+/// `latency-tracking-info-percentiles`.
+pub const PERCENTILES: [f64; 3] = [50.0, 99.0, 99.9];
+
+/// Counts of values, in nanoseconds, in log-linear buckets.
+#[derive(Clone)]
+pub struct Histogram {
+    counts: Box<[u64]>,
+    total: u64,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Histogram {
+    pub fn new() -> Self {
+        Self {
+            counts: vec![0; BUCKETS].into_boxed_slice(),
+            total: 0,
+        }
+    }
+
+    pub fn record(&mut self, nanos: u64) {
+        self.counts[bucket(nanos.min(MAX_NANOS))] += 1;
+        self.total += 1;
+    }
+
+    /// Adds `other`'s counts to these.
+    pub fn merge(&mut self, other: &Histogram) {
+        for (count, other) in self.counts.iter_mut().zip(other.counts.iter()) {
+            *count += other;
+        }
+        self.total += other.total;
+    }
+
+    /// Values recorded.
+    pub fn len(&self) -> u64 {
+        self.total
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.total == 0
+    }
+
+    /// The value `percentile` percent of recorded values are at or below,
+    /// as the highest value of its bucket; 0 when nothing was recorded.
+    pub fn percentile(&self, percentile: f64) -> u64 {
+        if self.is_empty() {
+            return 0;
+        }
+        let rank = ((percentile / 100.0 * self.total as f64).ceil() as u64).clamp(1, self.total);
+        let mut seen = 0;
+        for (index, count) in self.counts.iter().enumerate() {
+            seen += count;
+            if seen >= rank && *count > 0 {
+                return highest_in_bucket(index);
+            }
+        }
+        0
+    }
+}
+
+/// Values below `SUB_BUCKETS` get a bucket each. Above, a value whose
+/// highest bit is `exp` lands in one of `SUB_BUCKETS` buckets after those of
+/// `exp - 1`, picked by the bits just below its highest.
+fn bucket(value: u64) -> usize {
+    if value < SUB_BUCKETS as u64 {
+        return value as usize;
+    }
+    let shift = 63 - value.leading_zeros() - SUB_BUCKET_BITS;
+    shift as usize * SUB_BUCKETS + (value >> shift) as usize
+}
+
+fn highest_in_bucket(index: usize) -> u64 {
+    if index < SUB_BUCKETS {
+        return index as u64;
+    }
+    let shift = index / SUB_BUCKETS - 1;
+    let lowest = ((index % SUB_BUCKETS + SUB_BUCKETS) as u64) << shift;
+    lowest + (1 << shift) - 1
+}
+
+/// Execution time histograms by command name, as kept by one worker.
+#[derive(Default)]
+pub struct CommandLatencies {
+    commands: BTreeMap<&'static str, Histogram>,
+}
+
+impl CommandLatencies {
+    pub fn record(&mut self, command: &'static str, nanos: u64) {
+        self.commands.entry(command).or_default().record(nanos);
+    }
+
+    /// Adds `other`'s histograms to these.
+    pub fn merge(&mut self, other: &CommandLatencies) {
+        for (command, histogram) in &other.commands {
+            self.commands.entry(command).or_default().merge(histogram);
+        }
+    }
+
+    /// Histograms by command name, in name order.
+    pub fn iter(&self) -> impl Iterator<Item = (&'static str, &Histogram)> {
+        self.commands
+            .iter()
+            .map(|(command, histogram)| (*command, histogram))
+    }
+}
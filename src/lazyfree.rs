@@ -0,0 +1,77 @@
+use std::sync::{
+    atomic::Ordering,
+    mpsc::{self, Sender},
+    OnceLock,
+};
+
+use crate::{
+    kv::RedisValue,
+    stats::{ServerStats, STATS},
+};
+
+/// Values with more elements than this are dropped on the lazy-free thread
+/// rather than by the worker (Redis' `LAZYFREE_THRESHOLD`). Below it,
+/// handing the value over costs more than dropping it.
+pub const LAZYFREE_THRESHOLD: usize = 64;
+
+/// Anything the lazy-free thread can drop.
+type Garbage = Box<dyn Send>;
+
+/// Drops `value` on the lazy-free thread if it is big enough to stall the
+/// worker, otherwise right away.
+pub fn free_value(value: RedisValue) {
+    let effort = match &value {
+        RedisValue::String(_) => 1,
+        // packed lists and intsets are a single allocation
+        #[cfg(feature = "lists")]
+        RedisValue::List(crate::list::List::Packed(_)) => 1,
+        #[cfg(feature = "lists")]
+        RedisValue::List(list) => list.len(),
+        #[cfg(feature = "sets")]
+        RedisValue::Set(crate::set::Set::Ints(_)) => 1,
+        #[cfg(feature = "sets")]
+        RedisValue::Set(set) => set.len(),
+        RedisValue::Module(value) => value.elements(),
+    };
+    if effort > LAZYFREE_THRESHOLD {
+        free(Box::new(value));
+    }
+    // small values are dropped here
+}
+
+/// Drops `garbage` on the lazy-free thread, whatever its size.
+pub fn free(garbage: Garbage) {
+    ServerStats::incr(&STATS.lazyfree_pending_objects, 1);
+    // if the thread is gone, pay for the drop here
+    if let Err(err) = reclaimer().send(garbage) {
+        drop(err.0);
+        reclaimed();
+    }
+}
+
+fn reclaimer() -> &'static Sender<Garbage> {
+    static RECLAIMER: OnceLock<Sender<Garbage>> = OnceLock::new();
+    RECLAIMER.get_or_init(|| {
+        let (tx, rx) = mpsc::channel::<Garbage>();
+        let spawned = std::thread::Builder::new()
+            .name("lazy-free".into())
+            .spawn(move || {
+                for garbage in rx {
+                    drop(garbage);
+                    reclaimed();
+                }
+            });
+        // e.g. on WASM; with `rx` gone, everything is dropped in place
+        if let Err(err) = spawned {
+            tracing::warn!("Can't spawn the lazy-free thread, freeing in place: {err}");
+        }
+        tx
+    })
+}
+
+fn reclaimed() {
+    STATS
+        .lazyfree_pending_objects
+        .fetch_sub(1, Ordering::Relaxed);
+    ServerStats::incr(&STATS.lazyfreed_objects, 1);
+}
@@ -1,8 +1,86 @@
+pub mod allocator;
+#[cfg(feature = "server")]
+pub mod aof;
+#[cfg(feature = "server")]
+pub mod audit;
+#[cfg(feature = "server")]
+pub mod benchmark;
+#[cfg(feature = "server")]
+pub mod bigkeys;
+#[cfg(feature = "server")]
+pub mod cli;
+pub mod config;
+#[cfg(feature = "server")]
 pub mod connection;
+#[cfg(feature = "server")]
+pub mod crash;
+#[cfg(feature = "server")]
+pub mod daemon;
+pub mod defrag;
+pub mod dict;
+#[cfg(feature = "server")]
+pub mod embed;
+pub mod evict;
+#[cfg(feature = "cli")]
+pub mod export;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod glob;
 pub mod handler;
+#[cfg(feature = "server")]
+pub mod hooks;
+pub mod hotkeys;
+pub mod info;
+pub mod keyevents;
 pub mod kv;
+pub mod latency;
+pub mod lazyfree;
+#[cfg(feature = "lists")]
+pub mod list;
+#[cfg(feature = "lists")]
+pub mod listpack;
+#[cfg(feature = "server")]
+pub mod load;
+#[cfg(feature = "server")]
+pub mod loader;
+#[cfg(feature = "server")]
+pub mod local;
+#[cfg(feature = "server")]
+pub mod log;
 pub mod message;
+pub mod module;
+pub mod module_abi;
 pub mod parser;
+#[cfg(feature = "server")]
+pub mod rdb;
 pub mod router;
+pub mod server;
+#[cfg(feature = "sets")]
+pub mod set;
+#[cfg(feature = "sim")]
+pub mod sim;
+#[cfg(feature = "server")]
+pub mod snapshot;
+pub mod stats;
+#[cfg(feature = "server")]
+pub mod statsd;
+#[cfg(feature = "server")]
+pub mod store;
+pub mod string;
+#[cfg(feature = "server")]
+pub mod telemetry;
+#[cfg(feature = "testing")]
+pub mod testing;
+#[cfg(feature = "server")]
 pub mod threads;
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+pub mod uring;
+#[cfg(feature = "server")]
 pub mod worker;
+
+#[cfg(feature = "server")]
+pub use embed::{Server, ServerBuilder};
+#[cfg(feature = "server")]
+pub use local::LocalClient;
+#[cfg(feature = "server")]
+pub use store::Store;
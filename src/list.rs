@@ -0,0 +1,194 @@
+use std::collections::VecDeque;
+
+use bytes::Bytes;
+
+use crate::{kv::element_size, listpack::Listpack};
+
+/// Default `list-max-listpack-size`: packed lists hold up to 8KB.
+pub const LIST_MAX_LISTPACK_SIZE: i64 = -2;
+
+/// Whether a packed list of `entries` elements taking `bytes` stays within
+/// `list-max-listpack-size`: a positive limit counts elements, -1 to -5 allow
+/// 4KB, 8KB, 16KB, 32KB or 64KB, as in Redis.
+pub fn fits_listpack(max_size: i64, entries: usize, bytes: usize) -> bool {
+    if max_size > 0 {
+        entries <= max_size as usize
+    } else {
+        let shift = max_size.clamp(-5, -1).unsigned_abs() - 1;
+        bytes <= 4096 << shift
+    }
+}
+
+/// A list value. Small lists are packed into one buffer; past
+/// `list-max-listpack-size` they become a deque of `Bytes`, like Redis'
+/// listpack and quicklist encodings. A list that has grown stays a deque.
+#[derive(Clone, Debug)]
+pub enum List {
+    Packed(Listpack),
+    Deque {
+        items: VecDeque<Bytes>,
+        /// Sum of `element_size` over `items`.
+        bytes: usize,
+    },
+}
+
+impl Default for List {
+    fn default() -> Self {
+        List::Packed(Listpack::new())
+    }
+}
+
+impl PartialEq for List {
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len() && self.iter().eq(other.iter())
+    }
+}
+
+impl List {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            List::Packed(pack) => pack.len(),
+            List::Deque { items, .. } => items.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Name of the encoding, as `OBJECT ENCODING` reports it.
+    pub fn encoding(&self) -> &'static str {
+        match self {
+            List::Packed(_) => "listpack",
+            List::Deque { .. } => "quicklist",
+        }
+    }
+
+    /// Approximate bytes held by the elements.
+    pub fn memory_usage(&self) -> usize {
+        match self {
+            List::Packed(pack) => pack.bytes(),
+            List::Deque { bytes, .. } => *bytes,
+        }
+    }
+
+    pub fn push_front(&mut self, value: Bytes, max_listpack_size: i64) {
+        self.make_room(&value, max_listpack_size);
+        match self {
+            List::Packed(pack) => pack.push_front(&value),
+            List::Deque { items, bytes } => {
+                *bytes += element_size(&value);
+                items.push_front(value);
+            }
+        }
+    }
+
+    pub fn push_back(&mut self, value: Bytes, max_listpack_size: i64) {
+        self.make_room(&value, max_listpack_size);
+        match self {
+            List::Packed(pack) => pack.push_back(&value),
+            List::Deque { items, bytes } => {
+                *bytes += element_size(&value);
+                items.push_back(value);
+            }
+        }
+    }
+
+    pub fn pop_front(&mut self) -> Option<Bytes> {
+        match self {
+            List::Packed(pack) => pack.pop_front(),
+            List::Deque { items, bytes } => {
+                let value = items.pop_front()?;
+                *bytes -= element_size(&value);
+                Some(value)
+            }
+        }
+    }
+
+    pub fn pop_back(&mut self) -> Option<Bytes> {
+        match self {
+            List::Packed(pack) => pack.pop_back(),
+            List::Deque { items, bytes } => {
+                let value = items.pop_back()?;
+                *bytes -= element_size(&value);
+                Some(value)
+            }
+        }
+    }
+
+    /// The elements from head to tail. Packed elements are copied out.
+    pub fn iter(&self) -> impl Iterator<Item = Bytes> + '_ {
+        let (packed, deque) = match self {
+            List::Packed(pack) => (Some(pack.iter().map(Bytes::copy_from_slice)), None),
+            List::Deque { items, .. } => (None, Some(items.iter().cloned())),
+        };
+        packed
+            .into_iter()
+            .flatten()
+            .chain(deque.into_iter().flatten())
+    }
+
+    /// Moves the list into fresh allocations, for active defrag. Returns the
+    /// allocations moved.
+    pub fn defrag(&mut self) -> usize {
+        match self {
+            List::Packed(pack) => {
+                pack.defrag();
+                1
+            }
+            List::Deque { items, .. } => {
+                *items = items
+                    .iter()
+                    .map(|item| Bytes::copy_from_slice(item))
+                    .collect();
+                items.len() + 1
+            }
+        }
+    }
+
+    /// Unpacks the list if adding `value` would take it past the limit.
+    fn make_room(&mut self, value: &Bytes, max_listpack_size: i64) {
+        let List::Packed(pack) = self else {
+            return;
+        };
+        let bytes = pack.bytes() + Listpack::entry_size(value);
+        if fits_listpack(max_listpack_size, pack.len() + 1, bytes) {
+            return;
+        }
+
+        let items: VecDeque<Bytes> = pack.iter().map(Bytes::copy_from_slice).collect();
+        let bytes = items.iter().map(element_size).sum();
+        *self = List::Deque { items, bytes };
+    }
+}
+
+/// Serialized as the sequence of its elements, from head to tail.
+#[cfg(feature = "serde")]
+impl serde::Serialize for List {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeSeq;
+
+        // with its length, which the chained iterator can't tell
+        let mut seq = serializer.serialize_seq(Some(self.len()))?;
+        for value in self.iter() {
+            seq.serialize_element(&value)?;
+        }
+        seq.end()
+    }
+}
+
+/// Encoded as the default `list-max-listpack-size` would have it.
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for List {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let mut list = List::new();
+        for value in Vec::<Bytes>::deserialize(deserializer)? {
+            list.push_back(value, LIST_MAX_LISTPACK_SIZE);
+        }
+        Ok(list)
+    }
+}
@@ -0,0 +1,154 @@
+use bytes::Bytes;
+
+/// Elements packed back to back in a single allocation, like Redis'
+/// listpack. Each one is stored as `len, bytes, len`, with both lengths as
+/// varints and the trailing one written backwards, so the pack can be
+/// popped from either end without a scan. A handful of short elements takes
+/// a fraction of what a `VecDeque<Bytes>` needs, at the cost of inserts at
+/// the front moving everything behind them.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Listpack {
+    buf: Vec<u8>,
+    len: usize,
+}
+
+impl Listpack {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Bytes the packed elements take up.
+    pub fn bytes(&self) -> usize {
+        self.buf.len()
+    }
+
+    /// Bytes `element` takes up once packed.
+    pub fn entry_size(element: &[u8]) -> usize {
+        element.len() + 2 * varint_len(element.len())
+    }
+
+    /// Moves the elements into a fresh, exactly sized buffer, for active
+    /// defrag.
+    pub fn defrag(&mut self) {
+        self.buf = self.buf.clone();
+    }
+
+    pub fn push_back(&mut self, element: &[u8]) {
+        self.buf.reserve(Self::entry_size(element));
+        encode_entry(&mut self.buf, element);
+        self.len += 1;
+    }
+
+    pub fn push_front(&mut self, element: &[u8]) {
+        let mut entry = Vec::with_capacity(Self::entry_size(element));
+        encode_entry(&mut entry, element);
+        self.buf.splice(0..0, entry);
+        self.len += 1;
+    }
+
+    pub fn pop_front(&mut self) -> Option<Bytes> {
+        if self.is_empty() {
+            return None;
+        }
+        let (len, header) = read_varint(&self.buf);
+        let element = Bytes::copy_from_slice(&self.buf[header..header + len]);
+        self.buf.drain(..header + len + varint_len(len));
+        self.len -= 1;
+        Some(element)
+    }
+
+    pub fn pop_back(&mut self) -> Option<Bytes> {
+        if self.is_empty() {
+            return None;
+        }
+        let end = self.buf.len();
+        let (len, trailer) = read_varint_backwards(&self.buf);
+        let start = end - trailer - len;
+        let element = Bytes::copy_from_slice(&self.buf[start..end - trailer]);
+        self.buf.truncate(start - varint_len(len));
+        self.len -= 1;
+        Some(element)
+    }
+
+    /// The elements from front to back.
+    pub fn iter(&self) -> Iter<'_> {
+        Iter { rest: &self.buf }
+    }
+}
+
+pub struct Iter<'a> {
+    rest: &'a [u8],
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = &'a [u8];
+
+    fn next(&mut self) -> Option<&'a [u8]> {
+        if self.rest.is_empty() {
+            return None;
+        }
+        let (len, header) = read_varint(self.rest);
+        let element = &self.rest[header..header + len];
+        self.rest = &self.rest[header + len + varint_len(len)..];
+        Some(element)
+    }
+}
+
+fn encode_entry(buf: &mut Vec<u8>, element: &[u8]) {
+    write_varint(buf, element.len());
+    buf.extend_from_slice(element);
+    let start = buf.len();
+    write_varint(buf, element.len());
+    buf[start..].reverse();
+}
+
+fn varint_len(mut value: usize) -> usize {
+    let mut len = 1;
+    while value >= 0x80 {
+        value >>= 7;
+        len += 1;
+    }
+    len
+}
+
+/// LEB128: seven bits per byte, low bits first, high bit set on all but the
+/// last byte.
+fn write_varint(buf: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        buf.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+/// The varint at the start of `buf`, and how many bytes it took.
+fn read_varint(buf: &[u8]) -> (usize, usize) {
+    let mut value = 0;
+    for (i, byte) in buf.iter().enumerate() {
+        value |= ((byte & 0x7f) as usize) << (7 * i);
+        if byte & 0x80 == 0 {
+            return (value, i + 1);
+        }
+    }
+    unreachable!("truncated listpack entry")
+}
+
+/// The backwards varint at the end of `buf`, and how many bytes it took.
+fn read_varint_backwards(buf: &[u8]) -> (usize, usize) {
+    let mut value = 0;
+    for (i, byte) in buf.iter().rev().enumerate() {
+        value |= ((byte & 0x7f) as usize) << (7 * i);
+        if byte & 0x80 == 0 {
+            return (value, i + 1);
+        }
+    }
+    unreachable!("truncated listpack entry")
+}
@@ -0,0 +1,110 @@
+//! Preloading a dataset at startup, from `--load <file>`: a file of
+//! commands as RESP, the same a client would stream with `rustis-cli
+//! --pipe`, run before the server takes any connection.
+//!
+//! Commands go through `route_message` as a connection's would, pipelined:
+//! up to `LOAD_IN_FLIGHT` are queued to the workers ahead of their replies,
+//! which are only counted, so a big file loads about as fast as the workers
+//! can run it. Inline commands work too, one per line. Like `LocalClient`'s,
+//! loaded commands are not written to the audit log; unlike them they don't
+//! count toward `total_commands_processed`, as no client sent them.
+
+use std::{fs, path::Path};
+
+use bytes::BytesMut;
+use tokio::sync::mpsc::{self, Sender};
+
+use crate::{
+    message::{ResponseValue, WorkerMessage},
+    parser::{self, BufParseError},
+    router::route_message,
+    telemetry::CommandTrace,
+};
+
+/// Commands queued to the workers whose replies haven't been read yet.
+pub const LOAD_IN_FLIGHT: usize = 1024;
+
+/// What loading a file did.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LoadReport {
+    pub commands: usize,
+    /// Commands answered with an error; loading goes on past them, as
+    /// `redis-cli --pipe` does.
+    pub errors: usize,
+    /// The error of the first command, in the file's order, that got one.
+    pub first_error: Option<String>,
+}
+
+/// Reads the file at `path` and runs its commands, see `load`.
+pub async fn load_file(
+    router: &[Sender<WorkerMessage>],
+    path: &Path,
+) -> Result<LoadReport, String> {
+    let data = fs::read(path).map_err(|err| err.to_string())?;
+    load(router, BytesMut::from(&data[..])).await
+}
+
+/// Runs the commands in `data` on the workers behind `router`, returning
+/// once every one has been answered. A file that isn't a run of commands
+/// fails, at the offset of the first one that can't be parsed, after those
+/// before it have run.
+pub async fn load(
+    router: &[Sender<WorkerMessage>],
+    mut data: BytesMut,
+) -> Result<LoadReport, String> {
+    let (tx, mut rx) = mpsc::channel(LOAD_IN_FLIGHT);
+    let size = data.len();
+
+    let send = async move {
+        let mut commands = 0;
+        while !data.is_empty() {
+            let offset = size - data.len();
+            let frame = match parser::parse_request(&mut data) {
+                Ok(frame) => frame,
+                Err(BufParseError::Incomplete) => {
+                    return Err(format!("Unexpected end of file at byte {offset}"));
+                }
+                Err(err) => {
+                    return Err(format!("{} at byte {offset}", err.reply_message()));
+                }
+            };
+            // waits while `LOAD_IN_FLIGHT` replies are unread
+            let permit = tx
+                .clone()
+                .reserve_owned()
+                .await
+                .expect("the receiver is alive");
+            route_message(
+                router,
+                frame,
+                commands as u64,
+                permit,
+                CommandTrace::default(),
+            )
+            .await;
+            commands += 1;
+        }
+        Ok(commands)
+    };
+
+    // ends once the permits, each holding a sender, are all used or dropped
+    let receive = async {
+        let mut report = LoadReport::default();
+        // replies from different workers come back out of order
+        let mut first = u64::MAX;
+        while let Some(reply) = rx.recv().await {
+            if let ResponseValue::Error(message) = reply.response_value {
+                report.errors += 1;
+                if reply.seq < first {
+                    first = reply.seq;
+                    report.first_error = Some(String::from_utf8_lossy(&message).into_owned());
+                }
+            }
+        }
+        report
+    };
+
+    let (sent, mut report) = tokio::join!(send, receive);
+    report.commands = sent?;
+    Ok(report)
+}
@@ -0,0 +1,184 @@
+//! Cache-aside loading, for programs embedding the server in front of a
+//! slower backend.
+//!
+//! `register` adds a loader for the keys matching a glob pattern. When a
+//! `GET` finds such a key missing, the worker owning it runs the loader,
+//! stores what it returns with the loader's TTL and answers the `GET` with
+//! it. Other `GET`s of the key arriving meanwhile wait on the same load, so a
+//! stampede on a cold key makes a single backend call. The worker keeps
+//! serving other commands while a load runs; if one of them sets the key in
+//! the meantime, it is kept and the loaded value is only replied.
+
+use std::{
+    collections::HashMap,
+    future::Future,
+    pin::Pin,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+use bytes::Bytes;
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+
+use crate::{
+    evict::MemoryLimit,
+    glob::glob_match,
+    kv::{unix_time_ms, KvStore},
+    message::{ResponseMessage, ResponseValue, WorkerMessage},
+};
+
+/// What a loader returns: the value, `None` if the backend has none either,
+/// or an error to reply with, e.g. `ERR backend unavailable`.
+pub type LoadResult = Result<Option<Bytes>, String>;
+
+type LoadFuture = Pin<Box<dyn Future<Output = LoadResult> + Send>>;
+
+struct Loader {
+    pattern: Bytes,
+    ttl: Option<Duration>,
+    load: Box<dyn Fn(Bytes) -> LoadFuture + Send + Sync>,
+}
+
+static LOADERS: RwLock<Vec<Arc<Loader>>> = RwLock::new(Vec::new());
+
+/// Loads the keys matching `pattern`, like `KEYS` patterns, with `load` when
+/// a `GET` misses them, keeping what it returns for `ttl`, or for good with
+/// `None`. A key is loaded by the first loader registered whose pattern
+/// matches it.
+pub fn register<F, Fut>(pattern: &str, ttl: Option<Duration>, load: F)
+where
+    F: Fn(Bytes) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = LoadResult> + Send + 'static,
+{
+    let loader = Loader {
+        pattern: Bytes::copy_from_slice(pattern.as_bytes()),
+        ttl,
+        load: Box::new(move |key| Box::pin(load(key))),
+    };
+    LOADERS.write().unwrap().push(Arc::new(loader));
+}
+
+/// The loader for `key`, if any.
+fn find(key: &[u8]) -> Option<Arc<Loader>> {
+    let loaders = LOADERS.read().unwrap();
+    loaders
+        .iter()
+        .find(|loader| glob_match(&loader.pattern, key))
+        .cloned()
+}
+
+/// Whether a loader covers `key`.
+pub(crate) fn covers(key: &[u8]) -> bool {
+    find(key).is_some()
+}
+
+/// The key of a `GET`.
+fn get_key(value: &ResponseValue) -> Option<&Bytes> {
+    match value {
+        ResponseValue::Array(Some(items)) => match items.as_slice() {
+            [ResponseValue::BulkString(Some(cmd)), ResponseValue::BulkString(Some(key))]
+                if cmd.eq_ignore_ascii_case(b"GET") =>
+            {
+                Some(key)
+            }
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// The loads running for one worker's keys, and the `GET`s waiting on each.
+pub(crate) struct Loads {
+    waiting: HashMap<Bytes, (Option<Duration>, Vec<WorkerMessage>)>,
+    done_tx: UnboundedSender<(Bytes, LoadResult)>,
+    done_rx: UnboundedReceiver<(Bytes, LoadResult)>,
+}
+
+impl Loads {
+    pub(crate) fn new() -> Self {
+        let (done_tx, done_rx) = mpsc::unbounded_channel();
+        Self {
+            waiting: HashMap::new(),
+            done_tx,
+            done_rx,
+        }
+    }
+
+    /// Holds on to `msg` if it is a `GET` of a key missing from `kv` that a
+    /// loader covers, starting the load unless one is running already.
+    /// Otherwise hands `msg` back, to be run as usual.
+    pub(crate) fn wait(&mut self, kv: &KvStore, msg: WorkerMessage) -> Option<WorkerMessage> {
+        let Some(key) = get_key(&msg.response_value) else {
+            return Some(msg);
+        };
+        if kv.contains_key(key) {
+            return Some(msg);
+        }
+        if let Some((_, waiting)) = self.waiting.get_mut(key) {
+            waiting.push(msg);
+            return None;
+        }
+        let Some(loader) = find(key) else {
+            return Some(msg);
+        };
+
+        let key = key.clone();
+        let load = (loader.load)(key.clone());
+        let done = self.done_tx.clone();
+        let loading = key.clone();
+        tokio::spawn(async move {
+            // on a task of its own, so a loader that panics still answers
+            let loaded = tokio::spawn(load)
+                .await
+                .unwrap_or_else(|_| Err("ERR the loader failed".into()));
+            let _ = done.send((loading, loaded));
+        });
+        self.waiting.insert(key, (loader.ttl, vec![msg]));
+        None
+    }
+
+    /// The next load to finish, with what it returned. Never resolves while
+    /// none are running.
+    pub(crate) async fn next(&mut self) -> (Bytes, LoadResult) {
+        self.done_rx
+            .recv()
+            .await
+            .expect("the loads hold a sender themselves")
+    }
+
+    /// Stores what was loaded for `key`, unless the key was set meanwhile or
+    /// there is no room for it, and answers the `GET`s waiting on it.
+    pub(crate) fn finish(
+        &mut self,
+        kv: &mut KvStore,
+        memory: &mut MemoryLimit,
+        key: Bytes,
+        loaded: LoadResult,
+    ) {
+        let Some((ttl, waiting)) = self.waiting.remove(&key) else {
+            return;
+        };
+        let reply = match loaded {
+            Ok(Some(value)) => {
+                if !kv.contains_key(&key) && memory.make_room(kv) {
+                    kv.set(key.clone(), value.clone());
+                    if let Some(ttl) = ttl {
+                        let at = unix_time_ms() + ttl.as_millis() as u64;
+                        kv.set_expires_at(&key, Some(kv.jitter_expiry(at)));
+                    }
+                }
+                ResponseValue::BulkString(Some(value))
+            }
+            Ok(None) => ResponseValue::BulkString(None),
+            // whatever the loader said, the reply is a single line
+            Err(err) => ResponseValue::Error(err.replace(['\r', '\n'], " ").into()),
+        };
+        for msg in waiting {
+            msg.tx.send(ResponseMessage {
+                seq: msg.seq,
+                response_value: reply.clone(),
+                trace: msg.trace,
+            });
+        }
+    }
+}
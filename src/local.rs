@@ -0,0 +1,86 @@
+//! `LocalClient`: commands straight into the router and workers, without a
+//! socket, for embedding programs and tests.
+//!
+//! A command goes through `route_message` as if a connection had parsed it,
+//! so it is routed, split across shards and gathered, and run exactly as it
+//! would be over TCP; only the RESP encoding on either side is skipped.
+//! Commands count toward `total_commands_processed` and error replies toward
+//! `errorstats`, as theirs do. They are not written to the audit log, which
+//! identifies clients by address.
+
+use std::sync::{Arc, Weak};
+
+use bytes::Bytes;
+use tokio::sync::mpsc::{self, Sender};
+
+use crate::{
+    message::{ResponseValue, WorkerMessage},
+    router::route_message,
+    stats::{ServerStats, STATS},
+    telemetry::CommandTrace,
+};
+
+/// The reply to commands sent once the server is gone.
+pub const SHUT_DOWN_ERROR: &str = "ERR server is shut down";
+
+/// A handle for running commands in-process; cheap to clone.
+#[derive(Clone, Debug)]
+pub struct LocalClient {
+    /// Weak, so clients left around don't keep the workers' mailboxes open
+    /// past a shutdown.
+    router: Weak<Vec<Sender<WorkerMessage>>>,
+}
+
+impl LocalClient {
+    /// A client for the workers behind `router`, working for as long as
+    /// `router` is kept alive elsewhere.
+    pub fn new(router: &Arc<Vec<Sender<WorkerMessage>>>) -> Self {
+        Self {
+            router: Arc::downgrade(router),
+        }
+    }
+
+    /// A client with no server behind it, answering everything with
+    /// `SHUT_DOWN_ERROR`.
+    pub fn disconnected() -> Self {
+        Self {
+            router: Weak::new(),
+        }
+    }
+
+    /// Runs the command made of `args`, e.g. `["SET", "key", "value"]`.
+    pub async fn command<I>(&self, args: I) -> ResponseValue
+    where
+        I: IntoIterator,
+        I::Item: Into<Bytes>,
+    {
+        let frame = args
+            .into_iter()
+            .map(|arg| ResponseValue::BulkString(Some(arg.into())))
+            .collect();
+        self.send(ResponseValue::Array(Some(frame))).await
+    }
+
+    /// Runs `frame`, an array of bulk strings as a client would send.
+    pub async fn send(&self, frame: ResponseValue) -> ResponseValue {
+        let Some(router) = self.router.upgrade() else {
+            return ResponseValue::Error(SHUT_DOWN_ERROR.into());
+        };
+        let (tx, mut rx) = mpsc::channel(1);
+        // the permit holds the only sender, so a dropped reply ends `recv`
+        let permit = tx.reserve_owned().await.expect("the receiver is alive");
+        ServerStats::incr(&STATS.total_commands_processed, 1);
+        route_message(&router, frame, 0, permit, CommandTrace::default()).await;
+        drop(router);
+
+        // a worker that went away mid-command drops the reply slot unused
+        let reply = match rx.recv().await {
+            Some(reply) => reply.response_value,
+            None => ResponseValue::Error(SHUT_DOWN_ERROR.into()),
+        };
+        if let ResponseValue::Error(message) = &reply {
+            STATS.record_error(message);
+        }
+        reply
+    }
+}
@@ -0,0 +1,129 @@
+use std::{
+    fmt, io, process,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use tracing::{level_filters::LevelFilter, Event, Level, Subscriber};
+use tracing_subscriber::{
+    fmt::{
+        format::{self, FormatEvent, FormatFields},
+        FmtContext, FormattedFields,
+    },
+    registry::LookupSpan,
+};
+
+use crate::config::LogLevel;
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+impl LogLevel {
+    /// The `tracing` levels logged at this `loglevel`: Redis' debug,
+    /// verbose, notice and warning are TRACE, DEBUG, INFO and WARN.
+    pub fn filter(self) -> LevelFilter {
+        match self {
+            LogLevel::Debug => LevelFilter::TRACE,
+            LogLevel::Verbose => LevelFilter::DEBUG,
+            LogLevel::Notice => LevelFilter::INFO,
+            LogLevel::Warning => LevelFilter::WARN,
+            LogLevel::Nothing => LevelFilter::OFF,
+        }
+    }
+}
+
+/// Starts logging to stdout at `level`, which `--logfile` points at a file.
+/// Does nothing if logging was already set up, e.g. by an embedding program.
+pub fn init(level: LogLevel) {
+    let _ = tracing_subscriber::fmt()
+        .with_max_level(level.filter())
+        .event_format(RedisFormat)
+        .with_writer(io::stdout)
+        .try_init();
+}
+
+/// Formats events the way Redis writes its log,
+///
+/// ```text
+/// 4242:M 16 Oct 2026 10:00:00.123 * Listening on port 6379
+/// ```
+///
+/// pid, role, UTC time and a mark for the level (`.` debug, `-` verbose,
+/// `*` notice, `#` warning), followed by the message, its fields, and the
+/// fields of the spans it happened in, such as a connection's client id and
+/// address.
+pub struct RedisFormat;
+
+impl<S, N> FormatEvent<S, N> for RedisFormat
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        mut writer: format::Writer<'_>,
+        event: &Event<'_>,
+    ) -> fmt::Result {
+        let mark = match *event.metadata().level() {
+            Level::TRACE => '.',
+            Level::DEBUG => '-',
+            Level::INFO => '*',
+            _ => '#',
+        };
+        write!(
+            writer,
+            "{}:M {} {mark} ",
+            process::id(),
+            timestamp(SystemTime::now())
+        )?;
+        ctx.field_format().format_fields(writer.by_ref(), event)?;
+        if let Some(scope) = ctx.event_scope() {
+            for span in scope.from_root() {
+                let extensions = span.extensions();
+                let fields = extensions.get::<FormattedFields<N>>();
+                if let Some(fields) = fields.filter(|fields| !fields.is_empty()) {
+                    write!(writer, " {fields}")?;
+                }
+            }
+        }
+        writeln!(writer)
+    }
+}
+
+/// `time` as Redis prints it in the log, `16 Oct 2026 10:00:00.123`, in UTC.
+pub fn timestamp(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs();
+    let (year, month, day) = civil_from_days(secs / 86_400);
+    let secs = secs % 86_400;
+    format!(
+        "{day:02} {} {year} {:02}:{:02}:{:02}.{:03}",
+        MONTHS[month - 1],
+        secs / 3600,
+        secs % 3600 / 60,
+        secs % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+/// Year, month and day of the `days`th day since 1970-01-01, after Howard
+/// Hinnant's `civil_from_days`.
+fn civil_from_days(days: u64) -> (u64, usize, u64) {
+    // shift the epoch to 0000-03-01, so leap days fall at the end of a year
+    let days = days + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_from_march = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_from_march + 2) / 5 + 1;
+    let month = if month_from_march < 10 {
+        month_from_march + 3
+    } else {
+        month_from_march - 9
+    };
+    let year = era * 400 + year_of_era + u64::from(month <= 2);
+    (year, month as usize, day)
+}
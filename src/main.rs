@@ -1,21 +1,151 @@
-use std::sync::Arc;
+use std::{env, path::Path, sync::Arc};
 
-use rustis::{connection::spawn_io, threads::spawn_threads};
-#[cfg(not(target_env = "msvc"))]
-use tikv_jemallocator::Jemalloc;
+#[cfg(not(all(feature = "io-uring", target_os = "linux")))]
+use rustis::connection::spawn_io;
+use rustis::{
+    config::Config,
+    daemon::{
+        daemonize, notify_supervisor, redirect_output, shutdown_signal, spawn_signal_handler,
+        PidFile,
+    },
+    load::load_file,
+    message::WorkerMessage,
+    stats::spawn_sampler,
+    threads::{spawn_reuseport_threads, spawn_threads},
+};
 use tokio::runtime::Builder;
+use tokio::sync::mpsc::Sender;
 
-#[cfg(not(target_env = "msvc"))]
+#[cfg(feature = "mimalloc")]
 #[global_allocator]
-static GLOBAL: Jemalloc = Jemalloc;
+static GLOBAL: mimalloc::MiMalloc = mimalloc::MiMalloc;
+
+#[cfg(all(
+    feature = "jemalloc",
+    not(feature = "mimalloc"),
+    not(target_env = "msvc")
+))]
+#[global_allocator]
+static GLOBAL: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
 
 fn main() {
-    // spawn threads
-    let vec_router = spawn_threads();
+    let config = match Config::from_args(env::args().skip(1)) {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("{err}");
+            std::process::exit(1);
+        }
+    };
+
+    // forking only keeps the calling thread, so this comes before anything
+    // spawns one
+    if config.daemonize {
+        daemonize().unwrap_or_else(|err| {
+            eprintln!("Can't daemonize: {err}");
+            std::process::exit(1);
+        });
+    }
+    if config.daemonize || config.logfile.is_some() {
+        redirect_output(config.logfile.as_deref()).unwrap_or_else(|err| {
+            eprintln!("Can't open the log file: {err}");
+            std::process::exit(1);
+        });
+    }
+    rustis::log::init(config.loglevel);
+    rustis::crash::install_hook();
+    rustis::server::init(&config);
+    if let Err(err) = rustis::telemetry::init(&config) {
+        tracing::warn!("Can't export traces: {err}");
+    }
+    if let Err(err) = rustis::statsd::init(&config) {
+        tracing::warn!("Can't push metrics to StatsD: {err}");
+    }
+    if let Err(err) = rustis::audit::init(&config) {
+        tracing::error!("Can't open the audit log: {err}");
+        std::process::exit(1);
+    }
+    if let Err(err) = rustis::module::init(&config) {
+        tracing::error!("{err}");
+        std::process::exit(1);
+    }
+
+    let _pidfile = config
+        .pidfile_path()
+        .and_then(|path| match PidFile::create(&path) {
+            Ok(pidfile) => Some(pidfile),
+            Err(err) => {
+                tracing::warn!("Failed to write PID file {}: {err}", path.display());
+                None
+            }
+        });
+
+    spawn_sampler();
+    if let Err(err) = spawn_signal_handler(config.logfile.clone()) {
+        tracing::warn!("Can't handle SIGUSR1 and SIGHUP: {err}");
+    }
+
+    if config.reuseport {
+        // every worker accepts on its own listener, main thread just waits
+        let _workers = spawn_reuseport_threads(&config);
+        tracing::info!("Listening on port {} (SO_REUSEPORT)", config.port);
+        notify_supervisor(config.supervised, "READY=1");
+
+        let runtime = Builder::new_current_thread().enable_all().build().unwrap();
+        runtime.block_on(shutdown_signal());
+        tracing::info!("Received shutdown signal, exiting");
+    } else {
+        // spawn threads
+        let vec_router = spawn_threads(&config);
+
+        if let Some(path) = &config.load {
+            preload(&vec_router, path);
+        }
 
-    let router = Arc::new(vec_router);
+        let router = Arc::new(vec_router);
 
+        // returns on SIGTERM/Ctrl-C
+        serve(router, &config);
+    }
+
+    notify_supervisor(config.supervised, "STOPPING=1");
+    rustis::telemetry::shutdown();
+    if rustis::crash::crashed() {
+        drop(_pidfile);
+        std::process::exit(1);
+    }
+}
+
+/// Runs the commands in `--load`'s file, exiting if it can't be read or
+/// parsed.
+fn preload(router: &[Sender<WorkerMessage>], path: &Path) {
+    let runtime = Builder::new_current_thread().enable_all().build().unwrap();
+    match runtime.block_on(load_file(router, path)) {
+        Ok(report) => {
+            tracing::info!(
+                "Loaded {} commands from {} ({} errors)",
+                report.commands,
+                path.display(),
+                report.errors
+            );
+            if let Some(err) = report.first_error {
+                tracing::warn!("First error while loading {}: {err}", path.display());
+            }
+        }
+        Err(err) => {
+            tracing::error!("Can't load {}: {err}", path.display());
+            std::process::exit(1);
+        }
+    }
+}
+
+#[cfg(not(all(feature = "io-uring", target_os = "linux")))]
+fn serve(router: Arc<Vec<Sender<WorkerMessage>>>, config: &Config) {
     let runtime = Builder::new_current_thread().enable_all().build().unwrap();
 
-    runtime.block_on(spawn_io(router)).unwrap();
+    runtime.block_on(spawn_io(router, config)).unwrap();
+}
+
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+fn serve(router: Arc<Vec<Sender<WorkerMessage>>>, config: &Config) {
+    rustis::uring::spawn_io(router, config).unwrap();
 }
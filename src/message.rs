@@ -1,35 +1,125 @@
 use bytes::{BufMut, Bytes, BytesMut};
-use tokio::sync::{mpsc::UnboundedSender, oneshot};
+use memchr::memmem;
+#[cfg(feature = "server")]
+use tokio::sync::{mpsc::OwnedPermit, oneshot};
 
-pub enum ShardRequest {
-    Commmand {
-        args: Vec<Bytes>,
-        response_tx: oneshot::Sender<ResponseValue>,
-    },
-}
+#[cfg(feature = "server")]
+use crate::telemetry::CommandTrace;
 
 #[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum ResponseValue {
     SimpleString(Bytes),
     Error(Bytes),
     Integer(i64),
     BulkString(Option<Bytes>),
     Array(Option<Vec<ResponseValue>>),
+    /// RESP3 `(` big number, kept as its decimal digits.
+    BigNumber(Bytes),
+    /// RESP3 `=` verbatim string; `format` is the three byte type hint (e.g. `txt`, `mkd`).
+    VerbatimString {
+        format: [u8; 3],
+        data: Bytes,
+    },
+    /// RESP3 `|` attribute map, sent ahead of the reply it annotates.
+    Attribute {
+        attrs: Vec<(ResponseValue, ResponseValue)>,
+        value: Box<ResponseValue>,
+    },
+}
+
+/// Bulk strings at least this large are handed to the socket as their own
+/// `Bytes` chunk instead of being copied into the write buffer.
+pub const VECTORED_WRITE_THRESHOLD: usize = 16 * 1024;
+
+/// Number of bytes needed to print `n` in decimal (including the sign).
+fn decimal_len(n: i64) -> usize {
+    let mut len = if n < 0 { 2 } else { 1 };
+    let mut n = n.unsigned_abs();
+    while n >= 10 {
+        n /= 10;
+        len += 1;
+    }
+    len
 }
 
 impl ResponseValue {
+    /// Exact number of bytes `serialize` will write for this value.
+    pub fn encoded_len(&self) -> usize {
+        match self {
+            ResponseValue::SimpleString(s) | ResponseValue::Error(s) => 1 + s.len() + 2,
+            ResponseValue::Integer(i) => 1 + decimal_len(*i) + 2,
+            ResponseValue::BulkString(None) | ResponseValue::Array(None) => 5,
+            ResponseValue::BulkString(Some(data)) => {
+                1 + decimal_len(data.len() as i64) + 2 + data.len() + 2
+            }
+            ResponseValue::Array(Some(items)) => {
+                1 + decimal_len(items.len() as i64)
+                    + 2
+                    + items.iter().map(ResponseValue::encoded_len).sum::<usize>()
+            }
+            ResponseValue::BigNumber(digits) => 1 + digits.len() + 2,
+            ResponseValue::VerbatimString { data, .. } => {
+                let len = 4 + data.len();
+                1 + decimal_len(len as i64) + 2 + len + 2
+            }
+            ResponseValue::Attribute { attrs, value } => {
+                1 + decimal_len(attrs.len() as i64)
+                    + 2
+                    + attrs
+                        .iter()
+                        .map(|(k, v)| k.encoded_len() + v.encoded_len())
+                        .sum::<usize>()
+                    + value.encoded_len()
+            }
+        }
+    }
+
     pub fn serialize(&self, dst: &mut BytesMut) {
+        dst.reserve(self.encoded_len());
+        self.write_to(dst);
+    }
+
+    /// Serializes into `dst`, except that bulk strings of at least
+    /// `VECTORED_WRITE_THRESHOLD` bytes are not copied: the pending contents of
+    /// `dst` and the payload itself are pushed onto `chunks` instead, so the
+    /// caller can write everything out with a single vectored write.
+    pub fn serialize_vectored(&self, dst: &mut BytesMut, chunks: &mut Vec<Bytes>) {
         match self {
-            ResponseValue::SimpleString(s) => {
-                dst.put_u8(b'+');
-                dst.put_slice(s);
+            ResponseValue::BulkString(Some(data)) if data.len() >= VECTORED_WRITE_THRESHOLD => {
+                dst.put_u8(b'$');
+                dst.put_slice(data.len().to_string().as_bytes());
+                dst.put_slice(b"\r\n");
+                chunks.push(dst.split().freeze());
+                chunks.push(data.clone());
                 dst.put_slice(b"\r\n");
             }
-            ResponseValue::Error(msg) => {
-                dst.put_u8(b'-');
-                dst.put_slice(msg);
+            ResponseValue::Array(Some(items)) => {
+                dst.put_u8(b'*');
+                dst.put_slice(items.len().to_string().as_bytes());
+                dst.put_slice(b"\r\n");
+                for item in items {
+                    item.serialize_vectored(dst, chunks);
+                }
+            }
+            ResponseValue::Attribute { attrs, value } => {
+                dst.put_u8(b'|');
+                dst.put_slice(attrs.len().to_string().as_bytes());
                 dst.put_slice(b"\r\n");
+                for (k, v) in attrs {
+                    k.write_to(dst);
+                    v.write_to(dst);
+                }
+                value.serialize_vectored(dst, chunks);
             }
+            _ => self.serialize(dst),
+        }
+    }
+
+    fn write_to(&self, dst: &mut BytesMut) {
+        match self {
+            ResponseValue::SimpleString(s) => put_line(dst, b'+', s),
+            ResponseValue::Error(msg) => put_line(dst, b'-', msg),
             ResponseValue::Integer(i) => {
                 dst.put_u8(b':');
                 let val_str = i.to_string();
@@ -54,20 +144,95 @@ impl ResponseValue {
                 dst.put_slice(items.len().to_string().as_bytes());
                 dst.put_slice(b"\r\n");
                 for item in items {
-                    item.serialize(dst);
+                    item.write_to(dst);
+                }
+            }
+            ResponseValue::BigNumber(digits) => {
+                dst.put_u8(b'(');
+                dst.put_slice(digits);
+                dst.put_slice(b"\r\n");
+            }
+            ResponseValue::VerbatimString { format, data } => {
+                dst.put_u8(b'=');
+                dst.put_slice((4 + data.len()).to_string().as_bytes());
+                dst.put_slice(b"\r\n");
+                dst.put_slice(format);
+                dst.put_u8(b':');
+                dst.put_slice(data);
+                dst.put_slice(b"\r\n");
+            }
+            ResponseValue::Attribute { attrs, value } => {
+                dst.put_u8(b'|');
+                dst.put_slice(attrs.len().to_string().as_bytes());
+                dst.put_slice(b"\r\n");
+                for (k, v) in attrs {
+                    k.write_to(dst);
+                    v.write_to(dst);
                 }
+                value.write_to(dst);
             }
         }
     }
 }
 
+/// Writes a `+` or `-` line. A `\r\n` inside, say from a client's
+/// argument quoted in an error, would end it early and the rest would be
+/// read as another reply, so each is sent as two spaces, like Redis turns
+/// line breaks in error messages into spaces.
+fn put_line(dst: &mut BytesMut, prefix: u8, line: &[u8]) {
+    dst.put_u8(prefix);
+    let mut rest = line;
+    while let Some(at) = memmem::find(rest, b"\r\n") {
+        dst.put_slice(&rest[..at]);
+        dst.put_slice(b"  ");
+        rest = &rest[at + 2..];
+    }
+    dst.put_slice(rest);
+    dst.put_slice(b"\r\n");
+}
+
+#[cfg(feature = "server")]
 pub struct WorkerMessage {
     pub seq: u64,
     pub response_value: ResponseValue,
-    pub tx: UnboundedSender<ResponseMessage>,
+    pub tx: ReplyTo,
+    pub trace: CommandTrace,
+}
+
+/// Where a worker sends the reply to a command.
+#[cfg(feature = "server")]
+pub enum ReplyTo {
+    /// Slot reserved in the connection's reply channel for this response.
+    Writer(OwnedPermit<ResponseMessage>),
+    /// The router, gathering the parts of a command that was split across
+    /// shards into one reply.
+    Gather(oneshot::Sender<ResponseValue>),
+}
+
+#[cfg(feature = "server")]
+impl ReplyTo {
+    pub fn send(self, msg: ResponseMessage) {
+        match self {
+            ReplyTo::Writer(permit) => {
+                permit.send(msg);
+            }
+            ReplyTo::Gather(tx) => {
+                let _ = tx.send(msg.response_value);
+            }
+        }
+    }
+}
+
+#[cfg(feature = "server")]
+impl From<OwnedPermit<ResponseMessage>> for ReplyTo {
+    fn from(permit: OwnedPermit<ResponseMessage>) -> Self {
+        ReplyTo::Writer(permit)
+    }
 }
 
+#[cfg(feature = "server")]
 pub struct ResponseMessage {
     pub seq: u64,
     pub response_value: ResponseValue,
+    pub trace: CommandTrace,
 }
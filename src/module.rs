@@ -0,0 +1,246 @@
+//! Modules: commands and data types added to the server at startup, like
+//! Redis Modules, for things such as JSON documents or search indexes.
+//!
+//! A module is either a shared library named by `--loadmodule`, speaking the
+//! C ABI of `module_abi`, or Rust code of a program embedding the server,
+//! registering closures and `ModuleData` types on a `Modules` it then
+//! `install`s. Either way a command runs on the worker owning its first
+//! argument, the key, with that worker's shard to itself, and a module's
+//! values are stored as `RedisValue::Module`, living in the keyspace like any
+//! other: deleted, evicted, freed lazily and counted by `MEMORY USAGE`.
+//! Modules can't replace the server's own commands, and are never unloaded.
+
+use std::{any::Any, fmt, path::Path, sync::OnceLock};
+
+use bytes::Bytes;
+
+use crate::{
+    config::Config,
+    handler,
+    kv::KvStore,
+    message::ResponseValue,
+    module_abi::{self, ModuleInit, ModuleType},
+    router,
+};
+
+/// A value of a module's data type.
+pub trait ModuleData: Any + Send + fmt::Debug {
+    /// The type's name, as `OBJECT ENCODING` reports it.
+    fn type_name(&self) -> &'static str;
+
+    /// Approximate bytes held by the value.
+    fn memory_usage(&self) -> usize;
+
+    /// The value's size for `MEMORY BIGKEYS`.
+    fn elements(&self) -> usize {
+        1
+    }
+
+    fn clone_data(&self) -> Box<dyn ModuleData>;
+}
+
+/// A module's value, as stored in the keyspace.
+#[derive(Debug)]
+pub struct ModuleValue(Box<dyn ModuleData>);
+
+impl ModuleValue {
+    pub fn new(data: impl ModuleData) -> Self {
+        Self(Box::new(data))
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        self.0.type_name()
+    }
+
+    pub fn memory_usage(&self) -> usize {
+        self.0.memory_usage()
+    }
+
+    pub fn elements(&self) -> usize {
+        self.0.elements()
+    }
+
+    /// The value, if it is a `T`.
+    pub fn downcast_ref<T: ModuleData>(&self) -> Option<&T> {
+        (self.0.as_ref() as &dyn Any).downcast_ref()
+    }
+
+    pub fn downcast_mut<T: ModuleData>(&mut self) -> Option<&mut T> {
+        (self.0.as_mut() as &mut dyn Any).downcast_mut()
+    }
+}
+
+impl Clone for ModuleValue {
+    fn clone(&self) -> Self {
+        Self(self.0.clone_data())
+    }
+}
+
+/// Module values are opaque, so one only equals itself.
+impl PartialEq for ModuleValue {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::addr_eq(self.0.as_ref(), other.0.as_ref())
+    }
+}
+
+/// What a module command runs, given the worker's shard and the command's
+/// arguments, its name left out.
+pub type CommandFn = dyn Fn(&mut KvStore, &[Bytes]) -> ResponseValue + Send + Sync;
+
+/// How a module command behaves, like Redis' command flags.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CommandFlags {
+    /// It changes the dataset, so the audit log records it.
+    pub write: bool,
+    /// It may grow the dataset, so it is refused while over `maxmemory`.
+    pub denyoom: bool,
+}
+
+impl CommandFlags {
+    /// Flags from their Redis names separated by spaces, e.g. `write
+    /// deny-oom`. `readonly` and `fast` are accepted and change nothing.
+    pub fn parse(flags: &str) -> Result<Self, String> {
+        let mut parsed = Self::default();
+        for flag in flags.split_whitespace() {
+            match flag.to_ascii_lowercase().as_str() {
+                "write" => parsed.write = true,
+                "deny-oom" => parsed.denyoom = true,
+                "readonly" | "fast" => {}
+                _ => return Err(format!("unknown command flag '{flag}'")),
+            }
+        }
+        Ok(parsed)
+    }
+}
+
+pub(crate) struct Command {
+    /// Lower case, as INFO `latencystats` reports it.
+    pub(crate) name: &'static str,
+    pub(crate) flags: CommandFlags,
+    run: Box<CommandFn>,
+}
+
+/// The commands and data types of the modules loaded so far, until they are
+/// `install`ed.
+#[derive(Default)]
+pub struct Modules {
+    commands: Vec<Command>,
+    pub(crate) types: Vec<&'static ModuleType>,
+}
+
+impl fmt::Debug for Modules {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Modules")
+            .field(
+                "commands",
+                &self.commands.iter().map(|c| c.name).collect::<Vec<_>>(),
+            )
+            .field("types", &self.types)
+            .finish()
+    }
+}
+
+impl Modules {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the command `name`, case insensitive, run by `run`. Fails if
+    /// the server or a module already has a command by that name.
+    pub fn command(
+        &mut self,
+        name: &str,
+        flags: CommandFlags,
+        run: impl Fn(&mut KvStore, &[Bytes]) -> ResponseValue + Send + Sync + 'static,
+    ) -> Result<(), String> {
+        let name = name.to_ascii_lowercase();
+        if name.is_empty() || name.contains(|c: char| c.is_whitespace() || c.is_control()) {
+            return Err(format!("invalid command name '{name}'"));
+        }
+        let taken = handler::is_builtin(name.as_bytes())
+            || router::is_routed(name.as_bytes())
+            || self.commands.iter().any(|command| command.name == name);
+        if taken {
+            return Err(format!("command '{name}' already exists"));
+        }
+        self.commands.push(Command {
+            // registered once for the life of the process
+            name: Box::leak(name.into_boxed_str()),
+            flags,
+            run: Box::new(run),
+        });
+        Ok(())
+    }
+
+    /// Loads the shared library at `path` and runs its init function with
+    /// `args`.
+    pub fn load(&mut self, path: &Path, args: &[String]) -> Result<(), String> {
+        module_abi::load(self, path, args)
+    }
+
+    /// Runs a module's init function with `args`, as loading a library
+    /// exporting it would, for modules linked into the program. Whatever it
+    /// registered is taken back if it fails.
+    ///
+    /// # Safety
+    ///
+    /// `init` must follow the contract of `module_abi`.
+    pub unsafe fn init(&mut self, init: ModuleInit, args: &[String]) -> Result<(), String> {
+        let (commands, types) = (self.commands.len(), self.types.len());
+        // SAFETY: passed on to the caller
+        let result = unsafe { module_abi::run_init(self, init, args) };
+        if result.is_err() {
+            self.commands.truncate(commands);
+            self.types.truncate(types);
+        }
+        result
+    }
+}
+
+static MODULES: OnceLock<Modules> = OnceLock::new();
+
+/// Makes `modules`' commands available from now on. Only the first modules
+/// installed count; returns whether these are them.
+pub fn install(modules: Modules) -> bool {
+    MODULES.set(modules).is_ok()
+}
+
+/// Loads the modules `config` names, then installs them.
+pub fn init(config: &Config) -> Result<(), String> {
+    if config.loadmodule.is_empty() {
+        return Ok(());
+    }
+    let mut modules = Modules::new();
+    for module in &config.loadmodule {
+        modules
+            .load(&module.path, &module.args)
+            .map_err(|err| format!("Can't load module {}: {err}", module.path.display()))?;
+        tracing::info!("Loaded module {}", module.path.display());
+    }
+    install(modules);
+    Ok(())
+}
+
+/// The module command called `cmd`, if there is one.
+pub(crate) fn find(cmd: &[u8]) -> Option<&'static Command> {
+    MODULES
+        .get()?
+        .commands
+        .iter()
+        .find(|command| cmd.eq_ignore_ascii_case(command.name.as_bytes()))
+}
+
+/// Runs `command` with `args`, which must all be bulk strings.
+pub(crate) fn run(command: &Command, kv: &mut KvStore, args: &[ResponseValue]) -> ResponseValue {
+    let args: Option<Vec<Bytes>> = args
+        .iter()
+        .map(|arg| match arg {
+            ResponseValue::BulkString(Some(arg)) => Some(arg.clone()),
+            _ => None,
+        })
+        .collect();
+    match args {
+        Some(args) => (command.run)(kv, &args),
+        None => ResponseValue::Error("ERR arguments must be bulk strings".into()),
+    }
+}
@@ -0,0 +1,566 @@
+//! The C ABI of modules loaded from shared libraries, declared for C in
+//! `include/rustis_module.h`.
+//!
+//! A module exports `rustis_module_init`, a `ModuleInit` called once at
+//! startup with the function table `API` and the arguments `--loadmodule`
+//! gave it. It checks `abi_version`, registers its commands and data types,
+//! and returns 0, or anything else to fail the startup. A command's handler
+//! is called on a worker with its context and arguments, and replies and
+//! reaches the keyspace through `API` with that context, which is only valid
+//! until it returns. Strings are a pointer and a length, except names passed
+//! at registration, which are NUL terminated. New functions are only ever
+//! added at the end of `ModuleApi`; anything else bumps `ABI_VERSION`.
+
+use std::{
+    ffi::{c_char, c_int, c_void, CStr, CString},
+    path::Path,
+};
+
+use bytes::Bytes;
+
+use crate::{
+    kv::{KvStore, RedisValue},
+    message::ResponseValue,
+    module::{CommandFlags, ModuleData, ModuleValue, Modules},
+};
+
+pub const ABI_VERSION: u32 = 1;
+
+/// The function a module library exports.
+pub const INIT_SYMBOL: &CStr = c"rustis_module_init";
+
+/// Success, from registration functions and `ModuleInit`.
+pub const OK: c_int = 0;
+/// Failure of a registration function.
+pub const ERR: c_int = -1;
+
+/// Results of the key lookups.
+pub const KEY_FOUND: c_int = 1;
+pub const KEY_MISSING: c_int = 0;
+pub const KEY_WRONGTYPE: c_int = -1;
+
+pub type ModuleInit = unsafe extern "C" fn(
+    api: *const ModuleApi,
+    registration: *mut Registration,
+    argc: usize,
+    argv: *const *const c_char,
+) -> c_int;
+
+/// Runs a command: `argv[i]` is `argvlen[i]` bytes long, the command's name
+/// left out.
+pub type CommandHandler = unsafe extern "C" fn(
+    ctx: *mut CommandContext,
+    argc: usize,
+    argv: *const *const u8,
+    argvlen: *const usize,
+);
+
+/// What the server needs to know of a module's data type.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct TypeMethods {
+    /// Frees a value; may be called on another thread than the one that
+    /// created it, like the others.
+    pub free: unsafe extern "C" fn(value: *mut c_void),
+    pub copy: unsafe extern "C" fn(value: *const c_void) -> *mut c_void,
+    /// Approximate bytes held by a value; none counts only the pointer.
+    pub memory_usage: Option<unsafe extern "C" fn(value: *const c_void) -> usize>,
+}
+
+/// A module's data type, the handle it passes to the value functions.
+#[derive(Debug)]
+pub struct ModuleType {
+    name: &'static str,
+    methods: TypeMethods,
+}
+
+/// The functions modules call into the server.
+#[repr(C)]
+#[derive(Debug)]
+pub struct ModuleApi {
+    pub abi_version: u32,
+    /// Adds a command, with space separated flags as `CommandFlags::parse`
+    /// takes them. `ERR` if the name is taken.
+    pub register_command: unsafe extern "C" fn(
+        registration: *mut Registration,
+        name: *const c_char,
+        flags: *const c_char,
+        handler: CommandHandler,
+    ) -> c_int,
+    /// Adds a data type, null if the name is taken.
+    pub register_type: unsafe extern "C" fn(
+        registration: *mut Registration,
+        name: *const c_char,
+        methods: *const TypeMethods,
+    ) -> *const ModuleType,
+    pub reply_simple_string:
+        unsafe extern "C" fn(ctx: *mut CommandContext, s: *const u8, len: usize),
+    /// An error reply, e.g. `ERR no such thing`.
+    pub reply_error: unsafe extern "C" fn(ctx: *mut CommandContext, s: *const u8, len: usize),
+    pub reply_integer: unsafe extern "C" fn(ctx: *mut CommandContext, n: i64),
+    pub reply_bulk: unsafe extern "C" fn(ctx: *mut CommandContext, s: *const u8, len: usize),
+    pub reply_null: unsafe extern "C" fn(ctx: *mut CommandContext),
+    /// Starts an array, which the next `len` replies fill in.
+    pub reply_array: unsafe extern "C" fn(ctx: *mut CommandContext, len: usize),
+    /// Points `value` and `len` at the string at `key`, valid until the
+    /// handler returns.
+    pub string_get: unsafe extern "C" fn(
+        ctx: *mut CommandContext,
+        key: *const u8,
+        keylen: usize,
+        value: *mut *const u8,
+        len: *mut usize,
+    ) -> c_int,
+    pub string_set: unsafe extern "C" fn(
+        ctx: *mut CommandContext,
+        key: *const u8,
+        keylen: usize,
+        value: *const u8,
+        len: usize,
+    ),
+    /// Points `value` at the value of type `ty` at `key`, which the handler
+    /// may change in place.
+    pub value_get: unsafe extern "C" fn(
+        ctx: *mut CommandContext,
+        key: *const u8,
+        keylen: usize,
+        ty: *const ModuleType,
+        value: *mut *mut c_void,
+    ) -> c_int,
+    /// Stores `value`, of type `ty`, at `key`; the server owns it from then
+    /// on.
+    pub value_set: unsafe extern "C" fn(
+        ctx: *mut CommandContext,
+        key: *const u8,
+        keylen: usize,
+        ty: *const ModuleType,
+        value: *mut c_void,
+    ),
+    /// `KEY_FOUND` if there was a key to delete, else `KEY_MISSING`.
+    pub key_delete:
+        unsafe extern "C" fn(ctx: *mut CommandContext, key: *const u8, keylen: usize) -> c_int,
+}
+
+pub static API: ModuleApi = ModuleApi {
+    abi_version: ABI_VERSION,
+    register_command,
+    register_type,
+    reply_simple_string,
+    reply_error,
+    reply_integer,
+    reply_bulk,
+    reply_null,
+    reply_array,
+    string_get,
+    string_set,
+    value_get,
+    value_set,
+    key_delete,
+};
+
+/// A module's init function registering into `Modules`.
+pub struct Registration<'a> {
+    modules: &'a mut Modules,
+}
+
+/// A module command running on a worker.
+pub struct CommandContext<'a> {
+    kv: &'a mut KvStore,
+    reply: Reply,
+    /// Strings handed out by `string_get`, kept alive for the handler.
+    held: Vec<Bytes>,
+    /// Keys whose values the handler may have changed, sized again after.
+    changed: Vec<Bytes>,
+}
+
+/// A reply built up call by call.
+#[derive(Default)]
+struct Reply {
+    reply: Option<ResponseValue>,
+    /// Arrays started and not filled in yet, innermost last, with their
+    /// lengths.
+    open: Vec<(Vec<ResponseValue>, usize)>,
+}
+
+impl Reply {
+    fn push(&mut self, mut value: ResponseValue) {
+        while let Some((items, len)) = self.open.last_mut() {
+            items.push(value);
+            if items.len() < *len {
+                return;
+            }
+            let (items, _) = self.open.pop().expect("an array is open");
+            value = ResponseValue::Array(Some(items));
+        }
+        // only the first reply counts
+        self.reply.get_or_insert(value);
+    }
+
+    fn array(&mut self, len: usize) {
+        if len == 0 {
+            self.push(ResponseValue::Array(Some(Vec::new())));
+        } else {
+            self.open.push((Vec::with_capacity(len), len));
+        }
+    }
+
+    fn finish(self) -> ResponseValue {
+        match self.reply {
+            Some(reply) if self.open.is_empty() => reply,
+            _ => ResponseValue::Error("ERR module command sent no reply".into()),
+        }
+    }
+}
+
+/// A value of a type registered over the ABI.
+#[derive(Debug)]
+struct ForeignValue {
+    ty: &'static ModuleType,
+    value: *mut c_void,
+}
+
+// SAFETY: values may be used and freed from any thread, see `TypeMethods`
+unsafe impl Send for ForeignValue {}
+
+impl Drop for ForeignValue {
+    fn drop(&mut self) {
+        // SAFETY: the value is the type's and owned here
+        unsafe { (self.ty.methods.free)(self.value) }
+    }
+}
+
+impl ModuleData for ForeignValue {
+    fn type_name(&self) -> &'static str {
+        self.ty.name
+    }
+
+    fn memory_usage(&self) -> usize {
+        let value = match self.ty.methods.memory_usage {
+            // SAFETY: the value is the type's
+            Some(memory_usage) => unsafe { memory_usage(self.value) },
+            None => 0,
+        };
+        std::mem::size_of::<Self>() + value
+    }
+
+    fn clone_data(&self) -> Box<dyn ModuleData> {
+        Box::new(Self {
+            ty: self.ty,
+            // SAFETY: the value is the type's
+            value: unsafe { (self.ty.methods.copy)(self.value) },
+        })
+    }
+}
+
+/// Loads the library at `path` and runs its init function.
+#[cfg(unix)]
+pub(crate) fn load(modules: &mut Modules, path: &Path, args: &[String]) -> Result<(), String> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let c_path = CString::new(path.as_os_str().as_bytes()).map_err(|err| err.to_string())?;
+    // SAFETY: the path is NUL terminated; loading runs the library's
+    // constructors, which is what asking for it means
+    let handle = unsafe { libc::dlopen(c_path.as_ptr(), libc::RTLD_NOW | libc::RTLD_LOCAL) };
+    if handle.is_null() {
+        return Err(dl_error());
+    }
+    // SAFETY: `handle` is open and the name NUL terminated
+    let symbol = unsafe { libc::dlsym(handle, INIT_SYMBOL.as_ptr()) };
+    if symbol.is_null() {
+        // SAFETY: nothing of the library is in use
+        unsafe { libc::dlclose(handle) };
+        return Err(format!("no {} function", INIT_SYMBOL.to_string_lossy()));
+    }
+    // SAFETY: modules export their init function under this name
+    let init: ModuleInit = unsafe { std::mem::transmute(symbol) };
+    // SAFETY: the module follows the ABI
+    let result = unsafe { modules.init(init, args) };
+    if result.is_err() {
+        // SAFETY: whatever it registered was taken back
+        unsafe { libc::dlclose(handle) };
+    }
+    // otherwise the library stays loaded for good: its code runs commands
+    // and frees values until the process exits
+    result
+}
+
+#[cfg(unix)]
+fn dl_error() -> String {
+    // SAFETY: dlerror returns null or a NUL terminated message
+    let message = unsafe { libc::dlerror() };
+    if message.is_null() {
+        return "unknown error".into();
+    }
+    // SAFETY: see above
+    unsafe { CStr::from_ptr(message) }
+        .to_string_lossy()
+        .into_owned()
+}
+
+#[cfg(not(unix))]
+pub(crate) fn load(_modules: &mut Modules, _path: &Path, _args: &[String]) -> Result<(), String> {
+    Err("loading modules isn't supported on this platform".into())
+}
+
+/// Calls `init` to register into `modules`.
+///
+/// # Safety
+///
+/// `init` must follow the ABI.
+pub(crate) unsafe fn run_init(
+    modules: &mut Modules,
+    init: ModuleInit,
+    args: &[String],
+) -> Result<(), String> {
+    let args = args
+        .iter()
+        .map(|arg| CString::new(arg.as_str()))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|err| err.to_string())?;
+    let argv: Vec<*const c_char> = args.iter().map(|arg| arg.as_ptr()).collect();
+    let mut registration = Registration { modules };
+    // SAFETY: passed on to the caller; `argv` outlives the call
+    let status = unsafe { init(&API, &mut registration, argv.len(), argv.as_ptr()) };
+    match status {
+        OK => Ok(()),
+        status => Err(format!("init failed with {status}")),
+    }
+}
+
+/// A NUL terminated UTF-8 string from a module, if it is one.
+unsafe fn c_name<'a>(name: *const c_char) -> Option<&'a str> {
+    if name.is_null() {
+        return None;
+    }
+    // SAFETY: modules pass NUL terminated names
+    unsafe { CStr::from_ptr(name) }.to_str().ok()
+}
+
+/// `len` bytes at `ptr`, which may be null if there are none.
+unsafe fn bytes<'a>(ptr: *const u8, len: usize) -> &'a [u8] {
+    if len == 0 {
+        return &[];
+    }
+    // SAFETY: modules pass `len` bytes at `ptr`
+    unsafe { std::slice::from_raw_parts(ptr, len) }
+}
+
+unsafe extern "C" fn register_command(
+    registration: *mut Registration,
+    name_ptr: *const c_char,
+    flags: *const c_char,
+    handler: CommandHandler,
+) -> c_int {
+    // SAFETY: registrations are only handed out during init
+    let registration = unsafe { &mut *registration };
+    // SAFETY: see `c_name`
+    let Some(name) = (unsafe { c_name(name_ptr) }) else {
+        tracing::warn!("Module command name is not UTF-8");
+        return ERR;
+    };
+    // SAFETY: see `c_name`
+    let flags = match unsafe { c_name(flags) } {
+        Some(flags) => flags,
+        // no flags at all are as good as empty ones
+        None if flags.is_null() => "",
+        None => {
+            tracing::warn!("Module command {name}: flags are not UTF-8");
+            return ERR;
+        }
+    };
+    let flags = match CommandFlags::parse(flags) {
+        Ok(flags) => flags,
+        Err(err) => {
+            tracing::warn!("Module command {name}: {err}");
+            return ERR;
+        }
+    };
+    let run = move |kv: &mut KvStore, args: &[Bytes]| run_command(handler, kv, args);
+    match registration.modules.command(name, flags, run) {
+        Ok(()) => OK,
+        Err(err) => {
+            tracing::warn!("Module command {name}: {err}");
+            ERR
+        }
+    }
+}
+
+unsafe extern "C" fn register_type(
+    registration: *mut Registration,
+    name_ptr: *const c_char,
+    methods: *const TypeMethods,
+) -> *const ModuleType {
+    // SAFETY: registrations are only handed out during init
+    let registration = unsafe { &mut *registration };
+    // SAFETY: see `c_name`
+    let Some(name) = (unsafe { c_name(name_ptr) }) else {
+        tracing::warn!("Module type name is not UTF-8");
+        return std::ptr::null();
+    };
+    if methods.is_null() {
+        tracing::warn!("Module type {name} has no methods");
+        return std::ptr::null();
+    }
+    let types = &mut registration.modules.types;
+    if types.iter().any(|ty| ty.name == name) {
+        tracing::warn!("Module type {name} already exists");
+        return std::ptr::null();
+    }
+    // registered once for the life of the process, like the library
+    let ty: &'static ModuleType = Box::leak(Box::new(ModuleType {
+        name: Box::leak(name.into()),
+        // SAFETY: checked for null above
+        methods: unsafe { *methods },
+    }));
+    types.push(ty);
+    ty
+}
+
+fn run_command(handler: CommandHandler, kv: &mut KvStore, args: &[Bytes]) -> ResponseValue {
+    let argv: Vec<*const u8> = args.iter().map(|arg| arg.as_ptr()).collect();
+    let argvlen: Vec<usize> = args.iter().map(Bytes::len).collect();
+    let mut ctx = CommandContext {
+        kv,
+        reply: Reply::default(),
+        held: Vec::new(),
+        changed: Vec::new(),
+    };
+    // SAFETY: the arguments and context outlive the call
+    unsafe { handler(&mut ctx, args.len(), argv.as_ptr(), argvlen.as_ptr()) };
+    for key in &ctx.changed {
+        ctx.kv.update(key, |_| ());
+    }
+    ctx.reply.finish()
+}
+
+/// The context of a running handler.
+unsafe fn context<'a, 'b>(ctx: *mut CommandContext<'b>) -> &'a mut CommandContext<'b> {
+    // SAFETY: contexts are only handed to handlers, for as long as they run
+    unsafe { &mut *ctx }
+}
+
+unsafe extern "C" fn reply_simple_string(ctx: *mut CommandContext, s: *const u8, len: usize) {
+    // SAFETY: see `context` and `bytes`
+    let (ctx, s) = unsafe { (context(ctx), bytes(s, len)) };
+    ctx.reply
+        .push(ResponseValue::SimpleString(Bytes::copy_from_slice(s)));
+}
+
+unsafe extern "C" fn reply_error(ctx: *mut CommandContext, s: *const u8, len: usize) {
+    // SAFETY: see `context` and `bytes`
+    let (ctx, s) = unsafe { (context(ctx), bytes(s, len)) };
+    ctx.reply
+        .push(ResponseValue::Error(Bytes::copy_from_slice(s)));
+}
+
+unsafe extern "C" fn reply_integer(ctx: *mut CommandContext, n: i64) {
+    // SAFETY: see `context`
+    unsafe { context(ctx) }
+        .reply
+        .push(ResponseValue::Integer(n));
+}
+
+unsafe extern "C" fn reply_bulk(ctx: *mut CommandContext, s: *const u8, len: usize) {
+    // SAFETY: see `context` and `bytes`
+    let (ctx, s) = unsafe { (context(ctx), bytes(s, len)) };
+    ctx.reply
+        .push(ResponseValue::BulkString(Some(Bytes::copy_from_slice(s))));
+}
+
+unsafe extern "C" fn reply_null(ctx: *mut CommandContext) {
+    // SAFETY: see `context`
+    unsafe { context(ctx) }
+        .reply
+        .push(ResponseValue::BulkString(None));
+}
+
+unsafe extern "C" fn reply_array(ctx: *mut CommandContext, len: usize) {
+    // SAFETY: see `context`
+    unsafe { context(ctx) }.reply.array(len);
+}
+
+unsafe extern "C" fn string_get(
+    ctx: *mut CommandContext,
+    key: *const u8,
+    keylen: usize,
+    value: *mut *const u8,
+    len: *mut usize,
+) -> c_int {
+    // SAFETY: see `context` and `bytes`
+    let (ctx, key) = unsafe { (context(ctx), bytes(key, keylen)) };
+    let string = match ctx.kv.get(&Bytes::copy_from_slice(key)) {
+        Some(RedisValue::String(string)) => string.to_bytes(),
+        Some(_) => return KEY_WRONGTYPE,
+        None => return KEY_MISSING,
+    };
+    // SAFETY: the module passes somewhere to write both
+    unsafe {
+        *value = string.as_ptr();
+        *len = string.len();
+    }
+    ctx.held.push(string);
+    KEY_FOUND
+}
+
+unsafe extern "C" fn string_set(
+    ctx: *mut CommandContext,
+    key: *const u8,
+    keylen: usize,
+    value: *const u8,
+    len: usize,
+) {
+    // SAFETY: see `context` and `bytes`
+    let (ctx, key, value) = unsafe { (context(ctx), bytes(key, keylen), bytes(value, len)) };
+    ctx.kv
+        .set(Bytes::copy_from_slice(key), Bytes::copy_from_slice(value));
+}
+
+unsafe extern "C" fn value_get(
+    ctx: *mut CommandContext,
+    key: *const u8,
+    keylen: usize,
+    ty: *const ModuleType,
+    value: *mut *mut c_void,
+) -> c_int {
+    // SAFETY: see `context` and `bytes`
+    let (ctx, key) = unsafe { (context(ctx), bytes(key, keylen)) };
+    let key = Bytes::copy_from_slice(key);
+    let found = match ctx.kv.get(&key) {
+        Some(RedisValue::Module(stored)) => match stored.downcast_ref::<ForeignValue>() {
+            Some(foreign) if std::ptr::eq(foreign.ty, ty) => foreign.value,
+            _ => return KEY_WRONGTYPE,
+        },
+        Some(_) => return KEY_WRONGTYPE,
+        None => return KEY_MISSING,
+    };
+    // SAFETY: the module passes somewhere to write it
+    unsafe { *value = found };
+    ctx.changed.push(key);
+    KEY_FOUND
+}
+
+unsafe extern "C" fn value_set(
+    ctx: *mut CommandContext,
+    key: *const u8,
+    keylen: usize,
+    ty: *const ModuleType,
+    value: *mut c_void,
+) {
+    // SAFETY: see `context` and `bytes`
+    let (ctx, key) = unsafe { (context(ctx), bytes(key, keylen)) };
+    // SAFETY: types are handed out by `register_type` and live for good
+    let ty: &'static ModuleType = unsafe { &*ty };
+    let value = ModuleValue::new(ForeignValue { ty, value });
+    ctx.kv
+        .insert(Bytes::copy_from_slice(key), RedisValue::Module(value));
+}
+
+unsafe extern "C" fn key_delete(ctx: *mut CommandContext, key: *const u8, keylen: usize) -> c_int {
+    // SAFETY: see `context` and `bytes`
+    let (ctx, key) = unsafe { (context(ctx), bytes(key, keylen)) };
+    if ctx.kv.del(&Bytes::copy_from_slice(key)) {
+        KEY_FOUND
+    } else {
+        KEY_MISSING
+    }
+}
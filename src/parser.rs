@@ -6,11 +6,65 @@ use std::num::ParseIntError;
 #[derive(Debug, PartialEq)]
 pub enum BufParseError {
     Incomplete,
-    UnexpectedEOF { expected: &'static str },
+    UnexpectedEOF {
+        expected: &'static str,
+    },
     InvalidFirstByte(Option<u8>),
-    UnexpectedByte { expected: u8, found: Option<u8> },
+    UnexpectedByte {
+        expected: u8,
+        found: Option<u8>,
+    },
     StringConversionError(ParseIntError),
     ByteConversionError(std::str::Utf8Error),
+    InvalidMultibulkLength,
+    InvalidBulkLength,
+    UnbalancedQuotes,
+    TooBigMbulkCount,
+    TooBigBulkCount,
+    TooBigInlineRequest,
+    /// Arrays or attributes nested deeper than `MAX_NESTING_DEPTH`.
+    TooDeeplyNested,
+}
+
+/// Largest element count accepted in a `*` header, same as Redis.
+pub const MAX_MULTIBULK_LEN: i64 = i32::MAX as i64;
+/// Largest bulk string accepted (Redis' default `proto-max-bulk-len`).
+pub const PROTO_MAX_BULK_LEN: i64 = 512 * 1024 * 1024;
+/// Longest header or inline line we buffer before giving up on finding CRLF.
+pub const PROTO_INLINE_MAX_SIZE: usize = 64 * 1024;
+/// Most arrays and attributes a reply may nest, so a frame made of nothing
+/// but headers can't exhaust the stack. Requests don't nest at all.
+pub const MAX_NESTING_DEPTH: usize = 128;
+
+impl BufParseError {
+    /// The error reply sent to the client before the connection is closed,
+    /// worded exactly as Redis does.
+    pub fn reply_message(&self) -> String {
+        match self {
+            BufParseError::InvalidMultibulkLength => {
+                "ERR Protocol error: invalid multibulk length".to_string()
+            }
+            BufParseError::InvalidBulkLength => {
+                "ERR Protocol error: invalid bulk length".to_string()
+            }
+            BufParseError::UnbalancedQuotes => {
+                "ERR Protocol error: unbalanced quotes in request".to_string()
+            }
+            BufParseError::TooBigMbulkCount => {
+                "ERR Protocol error: too big mbulk count string".to_string()
+            }
+            BufParseError::TooBigBulkCount => {
+                "ERR Protocol error: too big bulk count string".to_string()
+            }
+            BufParseError::TooBigInlineRequest => {
+                "ERR Protocol error: too big inline request".to_string()
+            }
+            BufParseError::InvalidFirstByte(Some(byte)) => {
+                format!("ERR Protocol error: expected '$', got '{}'", *byte as char)
+            }
+            _ => "ERR Protocol error: invalid request".to_string(),
+        }
+    }
 }
 
 impl From<std::str::Utf8Error> for BufParseError {
@@ -29,8 +83,35 @@ fn find_crlf(data: &[u8]) -> Option<usize> {
     memmem::find(data, b"\r\n")
 }
 
+/// Like `find_crlf`, but reports `too_big` instead of `Incomplete` once the
+/// unterminated line grows past `PROTO_INLINE_MAX_SIZE`.
+fn find_header_end(data: &[u8], too_big: BufParseError) -> Result<usize, BufParseError> {
+    match find_crlf(data) {
+        Some(end) => Ok(end),
+        None if data.len() > PROTO_INLINE_MAX_SIZE => Err(too_big),
+        None => Err(BufParseError::Incomplete),
+    }
+}
+
+fn parse_length(slice: &[u8]) -> Option<i64> {
+    std::str::from_utf8(slice).ok()?.parse().ok()
+}
+
+/// Takes the next frame of any type off `buffer`, as a reply from a server.
 pub fn parse(buffer: &mut BytesMut) -> Result<ResponseValue, BufParseError> {
-    let bytes_needed = peek_bytes_needed(&buffer[..])?;
+    parse_next(buffer, true)
+}
+
+/// Takes the next request off `buffer`, as a client sends it: like `parse`,
+/// but the frame types only servers send in RESP3 (`|`, `(`, `=`) are
+/// refused, and so is anything but a bulk string within a multibulk, as
+/// Redis does.
+pub fn parse_request(buffer: &mut BytesMut) -> Result<ResponseValue, BufParseError> {
+    parse_next(buffer, false)
+}
+
+fn parse_next(buffer: &mut BytesMut, resp3: bool) -> Result<ResponseValue, BufParseError> {
+    let bytes_needed = peek_bytes_needed(&buffer[..], resp3, 0)?;
 
     if buffer.len() < bytes_needed {
         return Err(BufParseError::Incomplete);
@@ -41,27 +122,38 @@ pub fn parse(buffer: &mut BytesMut) -> Result<ResponseValue, BufParseError> {
     parse_frame(&frame)
 }
 
-fn peek_bytes_needed(data: &[u8]) -> Result<usize, BufParseError> {
+/// Bytes the frame at the start of `data` takes up, with or without the
+/// frame types only RESP3 servers send, `depth` arrays and attributes down.
+fn peek_bytes_needed(data: &[u8], resp3: bool, depth: usize) -> Result<usize, BufParseError> {
     match data.first() {
         Some(b'+') | Some(b'-') | Some(b':') => {
             let header_end = find_crlf(data).ok_or(BufParseError::Incomplete)?;
             Ok(header_end + 2)
         }
         Some(b'$') => peek_bulk_string_size(data),
-        Some(b'*') => peek_array_size(data),
-        Some(byte) if byte.is_ascii_alphabetic() => {
+        Some(b'*') => peek_array_size(data, resp3, depth),
+        Some(b'(') if resp3 => {
             let header_end = find_crlf(data).ok_or(BufParseError::Incomplete)?;
             Ok(header_end + 2)
         }
+        Some(b'=') if resp3 => peek_bulk_string_size(data),
+        Some(b'|') if resp3 => peek_attribute_size(data, depth),
+        Some(byte) if byte.is_ascii_alphabetic() => {
+            let header_end = find_header_end(data, BufParseError::TooBigInlineRequest)?;
+            Ok(header_end + 2)
+        }
         Some(byte) => Err(BufParseError::InvalidFirstByte(Some(*byte))),
         None => Err(BufParseError::Incomplete),
     }
 }
 
 fn peek_bulk_string_size(data: &[u8]) -> Result<usize, BufParseError> {
-    let header_end = find_crlf(data).ok_or(BufParseError::Incomplete)?;
+    let header_end = find_header_end(data, BufParseError::TooBigBulkCount)?;
     let len_slice = &data[1..header_end];
-    let integer_len: i64 = std::str::from_utf8(len_slice)?.parse()?;
+    let integer_len = match parse_length(len_slice) {
+        Some(len) if (-1..=PROTO_MAX_BULK_LEN).contains(&len) => len,
+        _ => return Err(BufParseError::InvalidBulkLength),
+    };
 
     if integer_len < 0 {
         return Ok(header_end + 2);
@@ -73,10 +165,13 @@ fn peek_bulk_string_size(data: &[u8]) -> Result<usize, BufParseError> {
     Ok(total_length)
 }
 
-fn peek_array_size(data: &[u8]) -> Result<usize, BufParseError> {
-    let header_end = find_crlf(data).ok_or(BufParseError::Incomplete)?;
+fn peek_array_size(data: &[u8], resp3: bool, depth: usize) -> Result<usize, BufParseError> {
+    let header_end = find_header_end(data, BufParseError::TooBigMbulkCount)?;
     let val_slice = &data[1..header_end];
-    let length: i64 = std::str::from_utf8(val_slice)?.parse()?;
+    let length = match parse_length(val_slice) {
+        Some(len) if len <= MAX_MULTIBULK_LEN => len,
+        _ => return Err(BufParseError::InvalidMultibulkLength),
+    };
 
     if length < 0 {
         return Ok(header_end + 2);
@@ -84,13 +179,51 @@ fn peek_array_size(data: &[u8]) -> Result<usize, BufParseError> {
 
     let mut offset = header_end + 2;
 
-    // Recursively peek at each array element
     for _ in 0..length {
         if offset >= data.len() {
             return Err(BufParseError::Incomplete);
         }
-        let element_size = peek_bytes_needed(&data[offset..])?;
-        offset += element_size;
+        offset += if resp3 {
+            if depth >= MAX_NESTING_DEPTH {
+                return Err(BufParseError::TooDeeplyNested);
+            }
+            // Recursively peek at each array element
+            peek_bytes_needed(&data[offset..], resp3, depth + 1)?
+        } else {
+            // a request is a flat list of bulk strings, as Redis has it; not
+            // recursing also keeps nested headers from exhausting the stack
+            match data[offset] {
+                b'$' => peek_bulk_string_size(&data[offset..])?,
+                byte => return Err(BufParseError::InvalidFirstByte(Some(byte))),
+            }
+        };
+    }
+
+    Ok(offset)
+}
+
+fn peek_attribute_size(data: &[u8], depth: usize) -> Result<usize, BufParseError> {
+    let header_end = find_header_end(data, BufParseError::TooBigMbulkCount)?;
+    let pairs = match parse_length(&data[1..header_end]) {
+        Some(pairs) if (0..=MAX_MULTIBULK_LEN).contains(&pairs) => pairs,
+        _ => return Err(BufParseError::InvalidMultibulkLength),
+    };
+    // every key and value of the map, followed by the value being annotated
+    let values = pairs
+        .checked_mul(2)
+        .and_then(|keys_and_values| keys_and_values.checked_add(1))
+        .ok_or(BufParseError::InvalidMultibulkLength)?;
+
+    let mut offset = header_end + 2;
+
+    for _ in 0..values {
+        if offset >= data.len() {
+            return Err(BufParseError::Incomplete);
+        }
+        if depth >= MAX_NESTING_DEPTH {
+            return Err(BufParseError::TooDeeplyNested);
+        }
+        offset += peek_bytes_needed(&data[offset..], true, depth + 1)?;
     }
 
     Ok(offset)
@@ -118,6 +251,9 @@ fn parse_value_from_frame(
         Some(b':') => parse_integer_frame(frame, offset),
         Some(b'$') => parse_bulk_string_frame(frame, offset),
         Some(b'*') => parse_array_frame(frame, offset),
+        Some(b'(') => parse_big_number_frame(frame, offset),
+        Some(b'=') => parse_verbatim_string_frame(frame, offset),
+        Some(b'|') => parse_attribute_frame(frame, offset),
         Some(byte) if byte.is_ascii_alphabetic() => parse_inline_frame(frame, offset),
         Some(byte) => Err(BufParseError::InvalidFirstByte(Some(*byte))),
         None => Err(BufParseError::Incomplete),
@@ -200,6 +336,111 @@ fn parse_bulk_string_frame(
     Ok((ResponseValue::BulkString(Some(string_data)), total_length))
 }
 
+fn parse_big_number_frame(
+    frame: &Bytes,
+    offset: usize,
+) -> Result<(ResponseValue, usize), BufParseError> {
+    let data = &frame[offset..];
+    let header_end = find_crlf(data).ok_or(BufParseError::Incomplete)?;
+
+    let digits = &data[1..header_end];
+    let unsigned = match digits.first() {
+        Some(b'-') | Some(b'+') => &digits[1..],
+        _ => digits,
+    };
+    if unsigned.is_empty() {
+        return Err(BufParseError::UnexpectedEOF { expected: "digit" });
+    }
+    if let Some(&byte) = unsigned.iter().find(|b| !b.is_ascii_digit()) {
+        return Err(BufParseError::UnexpectedByte {
+            expected: b'0',
+            found: Some(byte),
+        });
+    }
+
+    let number = frame.slice((offset + 1)..(offset + header_end));
+    Ok((ResponseValue::BigNumber(number), header_end + 2))
+}
+
+fn parse_verbatim_string_frame(
+    frame: &Bytes,
+    offset: usize,
+) -> Result<(ResponseValue, usize), BufParseError> {
+    let data = &frame[offset..];
+    let header_end = find_crlf(data).ok_or(BufParseError::Incomplete)?;
+
+    let len_slice = &data[1..header_end];
+    let len: usize = std::str::from_utf8(len_slice)?.parse()?;
+
+    let data_start = offset + header_end + 2;
+    let data_end = data_start + len;
+    let total_length = header_end + 2 + len + 2;
+
+    // payload is `xxx:<data>`, the three byte format followed by a colon
+    if len < 4 {
+        return Err(BufParseError::UnexpectedEOF {
+            expected: "verbatim format",
+        });
+    }
+    if frame[data_start + 3] != b':' {
+        return Err(BufParseError::UnexpectedByte {
+            expected: b':',
+            found: Some(frame[data_start + 3]),
+        });
+    }
+    if frame[data_end] != b'\r' || frame[data_end + 1] != b'\n' {
+        return Err(BufParseError::UnexpectedByte {
+            expected: b'\r',
+            found: Some(frame[data_end]),
+        });
+    }
+
+    let mut format = [0u8; 3];
+    format.copy_from_slice(&frame[data_start..data_start + 3]);
+    let string_data = frame.slice((data_start + 4)..data_end);
+
+    Ok((
+        ResponseValue::VerbatimString {
+            format,
+            data: string_data,
+        },
+        total_length,
+    ))
+}
+
+fn parse_attribute_frame(
+    frame: &Bytes,
+    offset: usize,
+) -> Result<(ResponseValue, usize), BufParseError> {
+    let data = &frame[offset..];
+    let header_end = find_crlf(data).ok_or(BufParseError::Incomplete)?;
+
+    // already bounded by `peek_attribute_size`
+    let pairs = parse_length(&data[1..header_end]).ok_or(BufParseError::InvalidMultibulkLength)?;
+
+    let mut local_offset = header_end + 2;
+    let mut attrs = Vec::with_capacity(pairs as usize);
+
+    for _ in 0..pairs {
+        let (key, consumed) = parse_value_from_frame(frame, offset + local_offset)?;
+        local_offset += consumed;
+        let (value, consumed) = parse_value_from_frame(frame, offset + local_offset)?;
+        local_offset += consumed;
+        attrs.push((key, value));
+    }
+
+    let (value, consumed) = parse_value_from_frame(frame, offset + local_offset)?;
+    local_offset += consumed;
+
+    Ok((
+        ResponseValue::Attribute {
+            attrs,
+            value: Box::new(value),
+        },
+        local_offset,
+    ))
+}
+
 fn parse_inline_frame(
     frame: &Bytes,
     offset: usize,
@@ -208,6 +449,16 @@ fn parse_inline_frame(
     let header_end = find_crlf(data).ok_or(BufParseError::Incomplete)?;
 
     let val_slice = &data[..header_end];
+    let bytes_consumed = header_end + 2;
+
+    // quoted arguments may contain escapes, so they can't be zero-copy slices
+    if val_slice.iter().any(|&b| b == b'"' || b == b'\'') {
+        let items = split_quoted_args(val_slice)?
+            .into_iter()
+            .map(|arg| ResponseValue::BulkString(Some(Bytes::from(arg))))
+            .collect();
+        return Ok((ResponseValue::Array(Some(items)), bytes_consumed));
+    }
 
     // Find word boundaries
     let mut items = Vec::new();
@@ -232,11 +483,97 @@ fn parse_inline_frame(
         items.push(ResponseValue::BulkString(Some(word)));
     }
 
-    let bytes_consumed = header_end + 2;
-
     Ok((ResponseValue::Array(Some(items)), bytes_consumed))
 }
 
+fn hex_digit(byte: u8) -> Option<u8> {
+    (byte as char).to_digit(16).map(|d| d as u8)
+}
+
+/// Splits an inline request the way Redis' `sdssplitargs` does, honouring
+/// double quotes (with `\n`, `\xHH`, ... escapes) and single quotes.
+pub fn split_quoted_args(line: &[u8]) -> Result<Vec<Vec<u8>>, BufParseError> {
+    let mut args = Vec::new();
+    let mut i = 0;
+
+    loop {
+        while i < line.len() && line[i].is_ascii_whitespace() {
+            i += 1;
+        }
+        if i == line.len() {
+            return Ok(args);
+        }
+
+        let mut current = Vec::new();
+        let mut in_double = false;
+        let mut in_single = false;
+
+        loop {
+            if in_double {
+                match line.get(i) {
+                    None => return Err(BufParseError::UnbalancedQuotes),
+                    Some(b'\\') if i + 3 < line.len() && line[i + 1] == b'x' => {
+                        match (hex_digit(line[i + 2]), hex_digit(line[i + 3])) {
+                            (Some(hi), Some(lo)) => {
+                                current.push(hi * 16 + lo);
+                                i += 3;
+                            }
+                            _ => current.push(b'\\'),
+                        }
+                    }
+                    Some(b'\\') if i + 1 < line.len() => {
+                        i += 1;
+                        current.push(match line[i] {
+                            b'n' => b'\n',
+                            b'r' => b'\r',
+                            b't' => b'\t',
+                            b'b' => 0x08,
+                            b'a' => 0x07,
+                            other => other,
+                        });
+                    }
+                    Some(b'"') => {
+                        // closing quote must be followed by a space or nothing
+                        if line.get(i + 1).is_some_and(|b| !b.is_ascii_whitespace()) {
+                            return Err(BufParseError::UnbalancedQuotes);
+                        }
+                        i += 1;
+                        break;
+                    }
+                    Some(&byte) => current.push(byte),
+                }
+            } else if in_single {
+                match line.get(i) {
+                    None => return Err(BufParseError::UnbalancedQuotes),
+                    Some(b'\\') if line.get(i + 1) == Some(&b'\'') => {
+                        i += 1;
+                        current.push(b'\'');
+                    }
+                    Some(b'\'') => {
+                        if line.get(i + 1).is_some_and(|b| !b.is_ascii_whitespace()) {
+                            return Err(BufParseError::UnbalancedQuotes);
+                        }
+                        i += 1;
+                        break;
+                    }
+                    Some(&byte) => current.push(byte),
+                }
+            } else {
+                match line.get(i) {
+                    None => break,
+                    Some(byte) if byte.is_ascii_whitespace() => break,
+                    Some(b'"') => in_double = true,
+                    Some(b'\'') => in_single = true,
+                    Some(&byte) => current.push(byte),
+                }
+            }
+            i += 1;
+        }
+
+        args.push(current);
+    }
+}
+
 fn parse_array_frame(
     frame: &Bytes,
     offset: usize,
@@ -269,7 +606,10 @@ impl ResponseValue {
     pub fn as_str(&self) -> Option<&str> {
         match self {
             ResponseValue::SimpleString(b) | ResponseValue::Error(b) => std::str::from_utf8(b).ok(),
-            ResponseValue::BulkString(Some(b)) => std::str::from_utf8(b).ok(),
+            ResponseValue::BulkString(Some(b)) | ResponseValue::BigNumber(b) => {
+                std::str::from_utf8(b).ok()
+            }
+            ResponseValue::VerbatimString { data, .. } => std::str::from_utf8(data).ok(),
             _ => None,
         }
     }
@@ -0,0 +1,793 @@
+//! Reading Redis RDB snapshots, for `rustis-rdb`: what keys a dump holds,
+//! of what type and size, and when they expire, and whether its checksum
+//! holds.
+//!
+//! The reader walks the file the way Redis loads it, up to RDB version 12
+//! (Redis 7.4), but only decodes what it reports: values are skipped over,
+//! counting their elements, so a dump of any size is read in one pass over
+//! its bytes. A file that stops making sense stops the walk; the keys read
+//! until then are kept, with where and why it stopped, since that is what
+//! debugging a broken dump needs.
+
+use std::{
+    fmt::{self, Write},
+    fs, io,
+    path::Path,
+};
+
+use crate::audit::quote;
+
+/// Newest RDB version the reader knows.
+pub const RDB_VERSION: u32 = 12;
+
+const OPCODE_SLOT_INFO: u8 = 0xF4;
+const OPCODE_FUNCTION2: u8 = 0xF5;
+const OPCODE_MODULE_AUX: u8 = 0xF7;
+const OPCODE_IDLE: u8 = 0xF8;
+const OPCODE_FREQ: u8 = 0xF9;
+const OPCODE_AUX: u8 = 0xFA;
+const OPCODE_RESIZEDB: u8 = 0xFB;
+const OPCODE_EXPIRETIME_MS: u8 = 0xFC;
+const OPCODE_EXPIRETIME: u8 = 0xFD;
+const OPCODE_SELECTDB: u8 = 0xFE;
+const OPCODE_EOF: u8 = 0xFF;
+
+/// Ends the fields of a module value.
+const MODULE_OPCODE_EOF: u64 = 0;
+
+/// What was found in a snapshot.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    pub version: u32,
+    /// Fields about the server that wrote it, e.g. `redis-ver`.
+    pub aux: Vec<(String, String)>,
+    pub keys: Vec<KeyInfo>,
+    /// `None` if the walk stopped before the end of the file.
+    pub checksum: Option<Checksum>,
+    /// Why the walk stopped early, if it did.
+    pub error: Option<FormatError>,
+    /// Bytes read: the whole snapshot with its checksum, or up to where the
+    /// walk stopped. An AOF goes on after its snapshot preamble.
+    pub len: usize,
+}
+
+/// A key of a snapshot, with what `rustis-rdb` reports about it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyInfo {
+    pub db: u64,
+    pub key: Vec<u8>,
+    /// `string`, `list`, `set`, `zset`, `hash`, `stream`, or a module's
+    /// type name.
+    pub kind: String,
+    /// How the value is serialized, e.g. `listpack` or `quicklist`.
+    pub encoding: &'static str,
+    /// Elements in the value; bytes for strings, as `MEMORY BIGKEYS` counts.
+    pub len: u64,
+    /// Bytes the value takes in the file.
+    pub bytes: usize,
+    /// When the key expires, in milliseconds since the Unix epoch.
+    pub expires_at_ms: Option<i64>,
+}
+
+/// The checksum at the end of a snapshot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Checksum {
+    Valid(u64),
+    Mismatch {
+        stored: u64,
+        computed: u64,
+    },
+    /// Written with `rdbchecksum no`, or by a version without checksums.
+    Disabled,
+}
+
+/// Where and why a snapshot couldn't be read further.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FormatError {
+    pub offset: usize,
+    pub message: String,
+}
+
+impl fmt::Display for FormatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at offset {}", self.message, self.offset)
+    }
+}
+
+impl Snapshot {
+    /// Whether the whole file was read and its checksum, if any, matches.
+    pub fn is_valid(&self) -> bool {
+        self.error.is_none() && !matches!(self.checksum, Some(Checksum::Mismatch { .. }) | None)
+    }
+
+    /// The snapshot as `rustis-rdb` prints it: a line per aux field and per
+    /// key, then a summary.
+    pub fn to_text(&self) -> String {
+        let mut text = format!("RDB version {}\n", self.version);
+        for (key, value) in &self.aux {
+            let _ = writeln!(text, "aux {key}={value}");
+        }
+        for key in &self.keys {
+            let _ = write!(text, "db={} key=", key.db);
+            quote(&mut text, &key.key, usize::MAX);
+            let _ = write!(
+                text,
+                " type={} encoding={} len={} bytes={}",
+                key.kind, key.encoding, key.len, key.bytes
+            );
+            if let Some(at) = key.expires_at_ms {
+                let _ = write!(text, " expires_at_ms={at}");
+            }
+            text.push('\n');
+        }
+        let _ = write!(text, "{} keys, ", self.keys.len());
+        text.push_str(&match (&self.error, self.checksum) {
+            (Some(err), _) => format!("unreadable: {err}"),
+            (None, Some(Checksum::Valid(crc))) => format!("checksum OK ({crc:016x})"),
+            (None, Some(Checksum::Mismatch { stored, computed })) => {
+                format!("checksum MISMATCH (stored {stored:016x}, computed {computed:016x})")
+            }
+            (None, _) => "no checksum".to_string(),
+        });
+        text
+    }
+
+    /// The snapshot as one JSON object, for `rustis-rdb --json`.
+    pub fn to_json(&self) -> String {
+        let mut json = format!("{{\"version\":{},\"aux\":{{", self.version);
+        for (i, (key, value)) in self.aux.iter().enumerate() {
+            if i > 0 {
+                json.push(',');
+            }
+            json_string(&mut json, key.as_bytes());
+            json.push(':');
+            json_string(&mut json, value.as_bytes());
+        }
+        json.push_str("},\"keys\":[");
+        for (i, key) in self.keys.iter().enumerate() {
+            if i > 0 {
+                json.push(',');
+            }
+            let _ = write!(json, "{{\"db\":{},\"key\":", key.db);
+            json_string(&mut json, &key.key);
+            json.push_str(",\"type\":");
+            json_string(&mut json, key.kind.as_bytes());
+            let _ = write!(
+                json,
+                ",\"encoding\":\"{}\",\"len\":{},\"bytes\":{},\"expires_at_ms\":",
+                key.encoding, key.len, key.bytes
+            );
+            match key.expires_at_ms {
+                Some(at) => {
+                    let _ = write!(json, "{at}}}");
+                }
+                None => json.push_str("null}"),
+            }
+        }
+        json.push_str("],\"checksum\":");
+        match self.checksum {
+            Some(Checksum::Valid(crc)) => {
+                let _ = write!(json, "{{\"status\":\"valid\",\"stored\":\"{crc:016x}\"}}");
+            }
+            Some(Checksum::Mismatch { stored, computed }) => {
+                let _ = write!(
+                    json,
+                    "{{\"status\":\"mismatch\",\"stored\":\"{stored:016x}\",\"computed\":\"{computed:016x}\"}}"
+                );
+            }
+            Some(Checksum::Disabled) => json.push_str("{\"status\":\"disabled\"}"),
+            None => json.push_str("null"),
+        }
+        json.push_str(",\"error\":");
+        match &self.error {
+            Some(err) => {
+                let _ = write!(json, "{{\"offset\":{},\"message\":", err.offset);
+                json_string(&mut json, err.message.as_bytes());
+                json.push('}');
+            }
+            None => json.push_str("null"),
+        }
+        json.push('}');
+        json
+    }
+}
+
+/// Appends `s` as a JSON string; bytes that aren't UTF-8 become U+FFFD.
+fn json_string(json: &mut String, s: &[u8]) {
+    json.push('"');
+    for c in String::from_utf8_lossy(s).chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if c < ' ' => {
+                let _ = write!(json, "\\u{:04x}", c as u32);
+            }
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+}
+
+/// Reads the snapshot at `path`.
+pub fn load(path: &Path) -> io::Result<Snapshot> {
+    Ok(read(&fs::read(path)?))
+}
+
+/// Reads a snapshot from its bytes.
+pub fn read(data: &[u8]) -> Snapshot {
+    let mut snapshot = Snapshot {
+        version: 0,
+        aux: Vec::new(),
+        keys: Vec::new(),
+        checksum: None,
+        error: None,
+        len: 0,
+    };
+    let mut reader = Reader { data, pos: 0 };
+    if let Err(message) = reader.snapshot(&mut snapshot) {
+        snapshot.error = Some(FormatError {
+            offset: reader.pos,
+            message,
+        });
+    }
+    snapshot.len = reader.pos;
+    snapshot
+}
+
+type Result<T> = std::result::Result<T, String>;
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+/// What an encoded length turned out to be.
+enum Length {
+    Len(u64),
+    /// A string stored as an integer of this many bytes.
+    Int(usize),
+    /// An LZF compressed string.
+    Lzf,
+}
+
+impl<'a> Reader<'a> {
+    fn snapshot(&mut self, snapshot: &mut Snapshot) -> Result<()> {
+        let header = self.take(9)?;
+        let version = header
+            .strip_prefix(b"REDIS")
+            .and_then(|digits| std::str::from_utf8(digits).ok()?.parse().ok())
+            .ok_or("not an RDB file")?;
+        if !(1..=RDB_VERSION).contains(&version) {
+            return Err(format!("unsupported RDB version {version}"));
+        }
+        snapshot.version = version;
+
+        let mut db = 0;
+        let mut expires_at_ms = None;
+        loop {
+            let opcode = self.byte()?;
+            match opcode {
+                OPCODE_EOF => break,
+                OPCODE_SELECTDB => db = self.length()?,
+                OPCODE_RESIZEDB => {
+                    self.length()?;
+                    self.length()?;
+                }
+                OPCODE_EXPIRETIME_MS => expires_at_ms = Some(self.u64_le()? as i64),
+                OPCODE_EXPIRETIME => {
+                    let seconds = i32::from_le_bytes(self.array()?);
+                    expires_at_ms = Some(seconds as i64 * 1000);
+                }
+                OPCODE_AUX => {
+                    let key = self.string()?;
+                    let value = self.string()?;
+                    snapshot.aux.push((
+                        String::from_utf8_lossy(&key).into_owned(),
+                        String::from_utf8_lossy(&value).into_owned(),
+                    ));
+                }
+                OPCODE_FREQ => {
+                    self.byte()?;
+                }
+                OPCODE_IDLE => {
+                    self.length()?;
+                }
+                OPCODE_MODULE_AUX => {
+                    self.length()?;
+                    self.length()?;
+                    self.length()?;
+                    self.module_fields()?;
+                }
+                OPCODE_FUNCTION2 => {
+                    self.string()?;
+                }
+                OPCODE_SLOT_INFO => {
+                    self.length()?;
+                    self.length()?;
+                    self.length()?;
+                }
+                value_type => {
+                    let key = self.string()?;
+                    let start = self.pos;
+                    let (kind, encoding, len) = self.value(value_type)?;
+                    snapshot.keys.push(KeyInfo {
+                        db,
+                        key,
+                        kind,
+                        encoding,
+                        len,
+                        bytes: self.pos - start,
+                        expires_at_ms: expires_at_ms.take(),
+                    });
+                }
+            }
+        }
+
+        let computed = crc64(0, &self.data[..self.pos]);
+        snapshot.checksum = Some(if version < 5 {
+            Checksum::Disabled
+        } else {
+            match self.u64_le()? {
+                0 => Checksum::Disabled,
+                stored if stored == computed => Checksum::Valid(stored),
+                stored => Checksum::Mismatch { stored, computed },
+            }
+        });
+        Ok(())
+    }
+
+    /// Skips over a value of `value_type`, returning its type, encoding and
+    /// element count.
+    fn value(&mut self, value_type: u8) -> Result<(String, &'static str, u64)> {
+        let (kind, encoding, len) = match value_type {
+            0 => ("string", "string", self.string()?.len() as u64),
+            1 => ("list", "linkedlist", self.strings(1)?),
+            2 => ("set", "hashtable", self.strings(1)?),
+            3 => {
+                let len = self.length()?;
+                for _ in 0..len {
+                    self.skip_string()?;
+                    // a double as text, its length in the first byte
+                    match self.byte()? {
+                        253..=255 => {}
+                        len => self.skip(len as usize)?,
+                    }
+                }
+                ("zset", "skiplist", len)
+            }
+            4 => ("hash", "hashtable", self.strings(2)?),
+            5 => {
+                let len = self.length()?;
+                for _ in 0..len {
+                    self.skip_string()?;
+                    self.skip(8)?;
+                }
+                ("zset", "skiplist", len)
+            }
+            7 => {
+                let id = self.length()?;
+                self.module_fields()?;
+                return Ok((module_type_name(id), "module", 1));
+            }
+            9 => ("hash", "zipmap", zipmap_len(&self.string()?)?),
+            10 => ("list", "ziplist", ziplist_len(&self.string()?)?),
+            11 => ("set", "intset", intset_len(&self.string()?)?),
+            12 => ("zset", "ziplist", ziplist_len(&self.string()?)? / 2),
+            13 => ("hash", "ziplist", ziplist_len(&self.string()?)? / 2),
+            14 => {
+                let mut len = 0;
+                for _ in 0..self.length()? {
+                    len += ziplist_len(&self.string()?)?;
+                }
+                ("list", "quicklist", len)
+            }
+            15 | 19 | 21 => ("stream", "stream", self.stream(value_type)?),
+            16 => ("hash", "listpack", listpack_len(&self.string()?)? / 2),
+            17 => ("zset", "listpack", listpack_len(&self.string()?)? / 2),
+            18 => {
+                let mut len = 0;
+                for _ in 0..self.length()? {
+                    // 1 for a node holding one plain element, 2 for a listpack
+                    let container = self.length()?;
+                    let node = self.string()?;
+                    len += match container {
+                        1 => 1,
+                        _ => listpack_len(&node)?,
+                    };
+                }
+                ("list", "quicklist", len)
+            }
+            20 => ("set", "listpack", listpack_len(&self.string()?)?),
+            24 => {
+                // hash fields with TTLs: the earliest expiry, then each
+                // field's TTL relative to it, name and value
+                self.skip(8)?;
+                let len = self.length()?;
+                for _ in 0..len {
+                    self.length()?;
+                    self.skip_string()?;
+                    self.skip_string()?;
+                }
+                ("hash", "hashtable", len)
+            }
+            25 => {
+                // the earliest expiry, then field, value and TTL triplets
+                self.skip(8)?;
+                ("hash", "listpack", listpack_len(&self.string()?)? / 3)
+            }
+            other => return Err(format!("unknown value type {other}")),
+        };
+        Ok((kind.to_string(), encoding, len))
+    }
+
+    /// Skips a stream, returning its entry count.
+    fn stream(&mut self, value_type: u8) -> Result<u64> {
+        for _ in 0..self.length()? {
+            // the node's master id, then its entries
+            self.skip_string()?;
+            self.skip_string()?;
+        }
+        let len = self.length()?;
+        // last id
+        self.length()?;
+        self.length()?;
+        if value_type >= 19 {
+            // first id, max deleted id, entries added
+            for _ in 0..5 {
+                self.length()?;
+            }
+        }
+        for _ in 0..self.length()? {
+            // consumer group: name, last id
+            self.skip_string()?;
+            self.length()?;
+            self.length()?;
+            if value_type >= 19 {
+                // entries read
+                self.length()?;
+            }
+            for _ in 0..self.length()? {
+                // pending entry: id, delivery time, delivery count
+                self.skip(16 + 8)?;
+                self.length()?;
+            }
+            for _ in 0..self.length()? {
+                // consumer: name, seen time, active time, pending ids
+                self.skip_string()?;
+                self.skip(8)?;
+                if value_type >= 21 {
+                    self.skip(8)?;
+                }
+                let pending = self.length()?;
+                let pending = usize::try_from(pending).map_err(|_| "bad length")?;
+                self.skip(pending.checked_mul(16).ok_or("length overflow")?)?;
+            }
+        }
+        Ok(len)
+    }
+
+    /// Skips the fields of a module value, up to their end marker.
+    fn module_fields(&mut self) -> Result<()> {
+        loop {
+            match self.length()? {
+                MODULE_OPCODE_EOF => return Ok(()),
+                // signed and unsigned integers
+                1 | 2 => {
+                    self.length()?;
+                }
+                // float and double
+                3 => self.skip(4)?,
+                4 => self.skip(8)?,
+                5 => self.skip_string()?,
+                other => return Err(format!("unknown module field type {other}")),
+            }
+        }
+    }
+
+    /// Skips a length, then `per_element` times that many strings, returning
+    /// the length.
+    fn strings(&mut self, per_element: u64) -> Result<u64> {
+        let len = self.length()?;
+        for _ in 0..len.checked_mul(per_element).ok_or("length overflow")? {
+            self.skip_string()?;
+        }
+        Ok(len)
+    }
+
+    fn encoded_length(&mut self) -> Result<Length> {
+        let first = self.byte()?;
+        Ok(match first >> 6 {
+            0 => Length::Len((first & 0x3F) as u64),
+            1 => Length::Len((((first & 0x3F) as u64) << 8) | self.byte()? as u64),
+            2 if first == 0x80 => Length::Len(u32::from_be_bytes(self.array()?) as u64),
+            2 if first == 0x81 => Length::Len(u64::from_be_bytes(self.array()?)),
+            3 => match first & 0x3F {
+                0 => Length::Int(1),
+                1 => Length::Int(2),
+                2 => Length::Int(4),
+                3 => Length::Lzf,
+                other => return Err(format!("unknown string encoding {other}")),
+            },
+            _ => return Err(format!("bad length byte {first:#04x}")),
+        })
+    }
+
+    fn length(&mut self) -> Result<u64> {
+        match self.encoded_length()? {
+            Length::Len(len) => Ok(len),
+            _ => Err("expected a length".into()),
+        }
+    }
+
+    fn string(&mut self) -> Result<Vec<u8>> {
+        Ok(match self.encoded_length()? {
+            Length::Len(len) => self.take(to_usize(len)?)?.to_vec(),
+            Length::Int(size) => {
+                let bytes = self.take(size)?;
+                let mut le = [0; 8];
+                le[..size].copy_from_slice(bytes);
+                // sign extend from the integer's width
+                let shift = 64 - 8 * size as u32;
+                let n = (i64::from_le_bytes(le) << shift) >> shift;
+                n.to_string().into_bytes()
+            }
+            Length::Lzf => {
+                let compressed = to_usize(self.length()?)?;
+                let len = to_usize(self.length()?)?;
+                lzf_decompress(self.take(compressed)?, len)?
+            }
+        })
+    }
+
+    fn skip_string(&mut self) -> Result<()> {
+        match self.encoded_length()? {
+            Length::Len(len) => self.skip(to_usize(len)?),
+            Length::Int(size) => self.skip(size),
+            Length::Lzf => {
+                let compressed = to_usize(self.length()?)?;
+                self.length()?;
+                self.skip(compressed)
+            }
+        }
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        let bytes = self
+            .data
+            .get(self.pos..)
+            .and_then(|rest| rest.get(..len))
+            .ok_or("unexpected end of file")?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn skip(&mut self, len: usize) -> Result<()> {
+        self.take(len).map(drop)
+    }
+
+    fn byte(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N]> {
+        Ok(self.take(N)?.try_into().expect("took N bytes"))
+    }
+
+    fn u64_le(&mut self) -> Result<u64> {
+        Ok(u64::from_le_bytes(self.array()?))
+    }
+}
+
+fn to_usize(len: u64) -> Result<usize> {
+    usize::try_from(len).map_err(|_| format!("length {len} too large"))
+}
+
+/// A module type's name, from the 9 characters packed in the top 54 bits of
+/// its id.
+fn module_type_name(id: u64) -> String {
+    const CHARSET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+    let mut id = id >> 10;
+    let mut name = [0u8; 9];
+    for c in name.iter_mut().rev() {
+        *c = CHARSET[(id & 63) as usize];
+        id >>= 6;
+    }
+    String::from_utf8_lossy(&name).into_owned()
+}
+
+/// Elements of a listpack, counted one by one when the header doesn't say.
+fn listpack_len(lp: &[u8]) -> Result<u64> {
+    let count = lp.get(4..6).ok_or("truncated listpack")?;
+    let count = u16::from_le_bytes([count[0], count[1]]);
+    if count != u16::MAX {
+        return Ok(count as u64);
+    }
+    let mut pos = 6;
+    let mut len = 0;
+    loop {
+        let &first = lp.get(pos).ok_or("truncated listpack")?;
+        let byte = |i: usize| lp.get(pos + i).copied().ok_or("truncated listpack");
+        let entry = match first {
+            0xFF => return Ok(len),
+            0x00..=0x7F => 1,
+            0x80..=0xBF => 1 + (first & 0x3F) as usize,
+            0xC0..=0xDF => 2,
+            0xE0..=0xEF => 2 + ((((first & 0x0F) as usize) << 8) | byte(1)? as usize),
+            0xF0 => 5 + u32::from_le_bytes([byte(1)?, byte(2)?, byte(3)?, byte(4)?]) as usize,
+            0xF1 => 3,
+            0xF2 => 4,
+            0xF3 => 5,
+            0xF4 => 9,
+            _ => return Err(format!("bad listpack entry {first:#04x}")),
+        };
+        // each entry ends with its own length, in 7 bit groups
+        let backlen = match entry {
+            0..128 => 1,
+            128..16384 => 2,
+            16384..2097152 => 3,
+            2097152..268435456 => 4,
+            _ => 5,
+        };
+        pos += entry + backlen;
+        len += 1;
+    }
+}
+
+/// Elements of a ziplist, counted one by one when the header doesn't say.
+fn ziplist_len(zl: &[u8]) -> Result<u64> {
+    let count = zl.get(8..10).ok_or("truncated ziplist")?;
+    let count = u16::from_le_bytes([count[0], count[1]]);
+    if count != u16::MAX {
+        return Ok(count as u64);
+    }
+    let mut pos = 10;
+    let mut len = 0;
+    loop {
+        let byte = |i: usize| zl.get(i).copied().ok_or("truncated ziplist");
+        if byte(pos)? == 0xFF {
+            return Ok(len);
+        }
+        // the previous entry's length
+        pos += if byte(pos)? < 254 { 1 } else { 5 };
+        let first = byte(pos)?;
+        pos += match first >> 6 {
+            0 => 1 + (first & 0x3F) as usize,
+            1 => 2 + ((((first & 0x3F) as usize) << 8) | byte(pos + 1)? as usize),
+            2 => {
+                5 + u32::from_be_bytes([
+                    byte(pos + 1)?,
+                    byte(pos + 2)?,
+                    byte(pos + 3)?,
+                    byte(pos + 4)?,
+                ]) as usize
+            }
+            _ => match first {
+                0xC0 => 3,
+                0xD0 => 5,
+                0xE0 => 9,
+                0xF0 => 4,
+                0xFE => 2,
+                0xF1..=0xFD => 1,
+                _ => return Err(format!("bad ziplist entry {first:#04x}")),
+            },
+        };
+        len += 1;
+    }
+}
+
+/// Field-value pairs of a zipmap.
+fn zipmap_len(zm: &[u8]) -> Result<u64> {
+    let &count = zm.first().ok_or("truncated zipmap")?;
+    if count < 254 {
+        return Ok(count as u64);
+    }
+    let mut pos = 1;
+    let mut strings = 0;
+    loop {
+        let byte = |i: usize| zm.get(i).copied().ok_or("truncated zipmap");
+        let len = match byte(pos)? {
+            0xFF => return Ok(strings / 2),
+            254 => {
+                let len = u32::from_le_bytes([
+                    byte(pos + 1)?,
+                    byte(pos + 2)?,
+                    byte(pos + 3)?,
+                    byte(pos + 4)?,
+                ]);
+                pos += 5;
+                len as usize
+            }
+            len => {
+                pos += 1;
+                len as usize
+            }
+        };
+        // values are followed by a count of free bytes, and those bytes
+        if strings % 2 == 1 {
+            let free = byte(pos)? as usize;
+            pos += 1 + free;
+        }
+        pos += len;
+        strings += 1;
+    }
+}
+
+/// Set members in an intset.
+fn intset_len(is: &[u8]) -> Result<u64> {
+    let len = is.get(4..8).ok_or("truncated intset")?;
+    Ok(u32::from_le_bytes([len[0], len[1], len[2], len[3]]) as u64)
+}
+
+/// Decompresses LZF `input` into `len` bytes, as Redis compresses strings.
+pub fn lzf_decompress(input: &[u8], len: usize) -> Result<Vec<u8>> {
+    let corrupt = || "corrupt LZF string".to_string();
+    // no three bytes expand to more than 264, so a larger `len` is a lie
+    // and mustn't be preallocated
+    if len > input.len().saturating_mul(88) {
+        return Err(corrupt());
+    }
+    let mut out = Vec::with_capacity(len);
+    let mut i = 0;
+    while i < input.len() {
+        let ctrl = input[i] as usize;
+        i += 1;
+        if ctrl < 32 {
+            // a literal run of ctrl + 1 bytes
+            let run = input.get(i..i + ctrl + 1).ok_or_else(corrupt)?;
+            out.extend_from_slice(run);
+            i += ctrl + 1;
+        } else {
+            // a back reference
+            let mut run = ctrl >> 5;
+            if run == 7 {
+                run += *input.get(i).ok_or_else(corrupt)? as usize;
+                i += 1;
+            }
+            let low = *input.get(i).ok_or_else(corrupt)? as usize;
+            i += 1;
+            let back = ((ctrl & 0x1F) << 8) + low + 1;
+            let start = out.len().checked_sub(back).ok_or_else(corrupt)?;
+            for j in 0..run + 2 {
+                out.push(out[start + j]);
+            }
+        }
+        if out.len() > len {
+            return Err(corrupt());
+        }
+    }
+    if out.len() != len {
+        return Err(corrupt());
+    }
+    Ok(out)
+}
+
+/// CRC-64/Jones, as Redis checksums snapshots with, continuing from `crc`.
+pub fn crc64(crc: u64, data: &[u8]) -> u64 {
+    const TABLE: [u64; 256] = {
+        // the polynomial 0xad93d23594c935a9, reflected
+        const POLY: u64 = 0x95ac9329ac4bc9b5;
+        let mut table = [0; 256];
+        let mut i = 0;
+        while i < 256 {
+            let mut crc = i as u64;
+            let mut bit = 0;
+            while bit < 8 {
+                crc = if crc & 1 == 1 {
+                    (crc >> 1) ^ POLY
+                } else {
+                    crc >> 1
+                };
+                bit += 1;
+            }
+            table[i] = crc;
+            i += 1;
+        }
+        table
+    };
+    data.iter().fold(crc, |crc, &byte| {
+        TABLE[((crc ^ byte as u64) & 0xFF) as usize] ^ (crc >> 8)
+    })
+}
@@ -1,51 +1,205 @@
 use std::hash::{DefaultHasher, Hash, Hasher};
 
 use bytes::Bytes;
-use tokio::sync::mpsc::UnboundedSender;
+#[cfg(feature = "server")]
+use tokio::sync::{
+    mpsc::{error::SendError, OwnedPermit, Sender},
+    oneshot,
+};
 
-use crate::message::{ResponseMessage, ResponseValue, WorkerMessage};
+use crate::{
+    allocator, handler::object_help, info::render_info, kv::ValueType, message::ResponseValue,
+    stats::STATS,
+};
+#[cfg(feature = "server")]
+use crate::{
+    bigkeys,
+    handler::arity_error,
+    message::{ReplyTo, ResponseMessage, WorkerMessage},
+    snapshot::{self, Unanswered},
+    telemetry::CommandTrace,
+};
 
-pub fn route_message(
-    router: &[UnboundedSender<WorkerMessage>],
+/// How the per-shard replies of a command sent to several shards become one
+/// reply.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Gather {
+    /// One reply element per key, put back in the order the keys were given.
+    PerKey,
+    /// Integer replies added up.
+    Sum,
+    /// `+OK` once every shard said `+OK`.
+    AllOk,
+    /// `MEMORY BIGKEYS`: the shards' largest keys, merged.
+    Largest,
+}
+
+/// Key specs of the commands whose keys may live on different shards: the
+/// distance between keys in the arguments (`MSET k v k v`) and how the
+/// replies are combined.
+const MULTI_KEY_COMMANDS: &[(&[u8], usize, Gather)] = &[
+    (b"MGET", 1, Gather::PerKey),
+    (b"MSET", 2, Gather::AllOk),
+    (b"DEL", 1, Gather::Sum),
+    (b"UNLINK", 1, Gather::Sum),
+    (b"EXISTS", 1, Gather::Sum),
+];
+
+/// Commands whose key is not their first argument, and where it is.
+const KEY_POSITIONS: &[(&[u8], usize)] = &[(b"OBJECT", 1), (b"MEMORY", 1)];
+
+/// Subcommands of keyless commands that do take a key, and so go to the
+/// shard owning it.
+const KEYED_SUBCOMMANDS: &[(&[u8], &[u8])] = &[(b"MEMORY", b"USAGE")];
+
+/// Subcommands served otherwise than the rest of their command: those of
+/// commands the router answers that need every shard instead, and those of
+/// keyed commands that take no key.
+const KEYLESS_SUBCOMMANDS: &[(&[u8], &[u8], Keyless)] = &[
+    (b"MEMORY", b"BIGKEYS", Keyless::Broadcast(Gather::Largest)),
+    (b"OBJECT", b"HELP", Keyless::Inline(object_help)),
+];
+
+/// Where a command without keys is served.
+#[derive(Clone, Copy)]
+#[cfg_attr(not(feature = "server"), allow(dead_code))]
+enum Keyless {
+    /// Answered by the router itself, without involving a worker.
+    Inline(fn(&[ResponseValue]) -> ResponseValue),
+    /// Sent to every shard, replies combined.
+    Broadcast(Gather),
+    /// `EXPORT`: sent to the shard its cursor is in, walking one shard
+    /// after the other.
+    Walk,
+}
+
+const KEYLESS_COMMANDS: &[(&[u8], Keyless)] = &[
+    (b"PING", Keyless::Inline(ping)),
+    (b"ECHO", Keyless::Inline(echo)),
+    (b"INFO", Keyless::Inline(info)),
+    (b"CONFIG", Keyless::Inline(config)),
+    (b"COMMAND", Keyless::Inline(command)),
+    (b"MEMORY", Keyless::Inline(memory)),
+    (b"HOTKEYS", Keyless::Inline(hotkeys)),
+    (b"DBSIZE", Keyless::Broadcast(Gather::Sum)),
+    (b"FLUSHALL", Keyless::Broadcast(Gather::AllOk)),
+    (b"FLUSHDB", Keyless::Broadcast(Gather::AllOk)),
+    (b"EXPORT", Keyless::Walk),
+];
+
+/// Sends `frame` to the worker owning its key. `writer_tx` is a slot already
+/// reserved in the connection's reply channel, so the worker can answer
+/// without ever waiting on a slow client. Waits while the worker's mailbox is
+/// full, which in turn stops the reader and pushes back on the socket.
+///
+/// Multi-key and broadcast commands are split per shard and sent before this
+/// returns, so they stay in order with the commands after them; their
+/// replies are gathered by a task of their own, leaving the reader free to
+/// route what follows. `trace` goes along to the worker and back to the
+/// writer.
+#[cfg(feature = "server")]
+pub async fn route_message(
+    router: &[Sender<WorkerMessage>],
+    frame: ResponseValue,
+    seq: u64,
+    writer_tx: OwnedPermit<ResponseMessage>,
+    trace: CommandTrace,
+) {
+    route_command(router, frame, seq, writer_tx, trace, None).await
+}
+
+/// `route_message` for a connection, telling `unanswered` the shards each
+/// command is sent to, so its snapshot reads don't overtake them.
+#[cfg(feature = "server")]
+pub(crate) async fn route_command(
+    router: &[Sender<WorkerMessage>],
     frame: ResponseValue,
     seq: u64,
-    writer_tx: UnboundedSender<ResponseMessage>,
+    writer_tx: OwnedPermit<ResponseMessage>,
+    trace: CommandTrace,
+    unanswered: Option<&Unanswered>,
 ) {
     // make sure parsed frame is an array
     let items = match &frame {
         ResponseValue::Array(Some(items)) => items,
         _ => {
-            send_error(&writer_tx, seq, "Value must be array");
+            send_error(writer_tx, seq, "Value must be array");
             return;
         }
     };
 
     // make sure array is not empty
     if items.is_empty() {
-        send_error(&writer_tx, seq, "empty request");
+        send_error(writer_tx, seq, "empty request");
+        return;
+    }
+
+    // checked here as well as by the worker, so a command short of its key
+    // is told so rather than failing to be routed
+    if let Some(error) = arity_error(items) {
+        writer_tx.send(ResponseMessage {
+            seq,
+            response_value: error,
+            trace,
+        });
+        return;
+    }
+
+    match keyless_command(items) {
+        Some(Keyless::Inline(handler)) => {
+            writer_tx.send(ResponseMessage {
+                seq,
+                response_value: handler(&items[1..]),
+                trace,
+            });
+            return;
+        }
+        Some(Keyless::Broadcast(gather)) => {
+            let parts = (0..router.len())
+                .map(|shard| (shard, Vec::new(), frame.clone()))
+                .collect();
+            scatter_gather(router, parts, 0, gather, seq, writer_tx, trace, unanswered).await;
+            return;
+        }
+        Some(Keyless::Walk) => {
+            route_export(router, items, seq, writer_tx, trace, unanswered).await;
+            return;
+        }
+        None => {}
+    }
+
+    if let Some(reply) = snapshot::read(router, items, unanswered) {
+        writer_tx.send(ResponseMessage {
+            seq,
+            response_value: reply,
+            trace,
+        });
+        return;
+    }
+
+    if let Some((step, gather)) = multi_key_spec(items) {
+        route_multi_key(
+            router, items, seq, writer_tx, step, gather, trace, unanswered,
+        )
+        .await;
         return;
     }
 
     // extract key
-    let key = match extract_key(&writer_tx, seq, items) {
-        Some(key) => key,
+    let (key, writer_tx) = match extract_key(writer_tx, seq, items) {
+        Some(found) => found,
         None => {
             return;
         }
     };
 
-    // hash and send
-    let router_len = router.len() as u64;
-    let mut hasher = DefaultHasher::new();
-    key.hash(&mut hasher);
-    let worker_mailbox = hasher.finish() % router_len;
-
     // send frame to correct worker
-    let tx = match router.get(worker_mailbox as usize) {
+    let shard = shard_for(&key, router.len());
+    let tx = match router.get(shard) {
         Some(tx) => tx,
         None => {
             send_error(
-                &writer_tx,
+                writer_tx,
                 seq,
                 "internal server error, invalid worker index",
             );
@@ -53,54 +207,431 @@ pub fn route_message(
         }
     };
 
-    tx.send(WorkerMessage {
+    if let Some(unanswered) = unanswered {
+        unanswered.sent(shard, seq);
+    }
+    let msg = WorkerMessage {
         seq,
         response_value: frame,
-        tx: writer_tx,
-    })
-    .unwrap()
+        tx: writer_tx.into(),
+        trace,
+    };
+    if let Err(SendError(msg)) = tx.send(msg).await {
+        msg.tx.send(ResponseMessage {
+            seq,
+            response_value: ResponseValue::Error("internal server error, worker is gone".into()),
+            trace: msg.trace,
+        });
+    }
 }
 
-fn send_error(writer_tx: &UnboundedSender<ResponseMessage>, seq: u64, error_msg: &'static str) {
-    writer_tx
-        .send(ResponseMessage {
-            seq,
-            response_value: ResponseValue::Error(error_msg.into()),
+/// The worker owning `key`. Only the key's hash tag is hashed, so keys sharing
+/// one always land on the same worker.
+pub fn shard_for(key: &[u8], shards: usize) -> usize {
+    let mut hasher = DefaultHasher::new();
+    hash_tag(key).hash(&mut hasher);
+    (hasher.finish() % shards as u64) as usize
+}
+
+/// The part of `key` that decides its shard, following Redis Cluster: if the
+/// key has a `{...}` with at least one byte inside, only what is between the
+/// first `{` and the first `}` after it counts; otherwise the whole key.
+pub fn hash_tag(key: &[u8]) -> &[u8] {
+    let Some(open) = memchr::memchr(b'{', key) else {
+        return key;
+    };
+    match memchr::memchr(b'}', &key[open + 1..]) {
+        Some(len) if len > 0 => &key[open + 1..open + 1 + len],
+        _ => key,
+    }
+}
+
+fn keyless_command(items: &[ResponseValue]) -> Option<Keyless> {
+    let Some(ResponseValue::BulkString(Some(cmd))) = items.first() else {
+        return None;
+    };
+    if let Some(ResponseValue::BulkString(Some(sub))) = items.get(1) {
+        let keyed = KEYED_SUBCOMMANDS.iter().any(|(name, subcommand)| {
+            cmd.eq_ignore_ascii_case(name) && sub.eq_ignore_ascii_case(subcommand)
+        });
+        if keyed {
+            return None;
+        }
+        let keyless = KEYLESS_SUBCOMMANDS.iter().find(|(name, subcommand, _)| {
+            cmd.eq_ignore_ascii_case(name) && sub.eq_ignore_ascii_case(subcommand)
+        });
+        if let Some(&(_, _, keyless)) = keyless {
+            return Some(keyless);
+        }
+    }
+    KEYLESS_COMMANDS
+        .iter()
+        .find(|(name, _)| cmd.eq_ignore_ascii_case(name))
+        .map(|&(_, keyless)| keyless)
+}
+
+/// Whether the router answers or spreads the command called `name` itself,
+/// rather than sending it to the worker owning its key.
+pub(crate) fn is_routed(name: &[u8]) -> bool {
+    KEYLESS_COMMANDS
+        .iter()
+        .any(|(command, _)| name.eq_ignore_ascii_case(command))
+        || MULTI_KEY_COMMANDS
+            .iter()
+            .any(|(command, _, _)| name.eq_ignore_ascii_case(command))
+}
+
+/// How many keys the command in `items` takes.
+pub fn key_count(items: &[ResponseValue]) -> usize {
+    if let Some((step, _)) = multi_key_spec(items) {
+        return (items.len() - 1) / step;
+    }
+    match keyless_command(items) {
+        Some(_) => 0,
+        None => usize::from(items.len() > 1),
+    }
+}
+
+/// The keys the command in `items` takes, as its worker sees them: after a
+/// multi-key command was split, those of the worker's shard.
+pub fn command_keys(items: &[ResponseValue]) -> impl Iterator<Item = &Bytes> {
+    let (start, step, count) = match multi_key_spec(items) {
+        Some((step, _)) => (1, step, usize::MAX),
+        None if keyless_command(items).is_some() => (0, 1, 0),
+        None => {
+            let position = match items.first() {
+                Some(ResponseValue::BulkString(Some(cmd))) => KEY_POSITIONS
+                    .iter()
+                    .find(|(name, _)| cmd.eq_ignore_ascii_case(name))
+                    .map_or(0, |(_, position)| *position),
+                _ => 0,
+            };
+            (1 + position, 1, 1)
+        }
+    };
+    items
+        .iter()
+        .skip(start)
+        .step_by(step)
+        .take(count)
+        .filter_map(|item| match item {
+            ResponseValue::BulkString(Some(key)) => Some(key),
+            _ => None,
         })
-        .unwrap();
 }
 
-fn send_string(writer_tx: &UnboundedSender<ResponseMessage>, seq: u64, msg: &'static str) {
-    writer_tx
-        .send(ResponseMessage {
-            seq,
-            response_value: ResponseValue::Error(msg.into()),
+/// The key spec of a multi-key command with well-formed arguments. Anything
+/// else goes down the single-key path, where the worker reports arity errors.
+fn multi_key_spec(items: &[ResponseValue]) -> Option<(usize, Gather)> {
+    let Some(ResponseValue::BulkString(Some(cmd))) = items.first() else {
+        return None;
+    };
+    let &(_, step, gather) = MULTI_KEY_COMMANDS
+        .iter()
+        .find(|(name, _, _)| cmd.eq_ignore_ascii_case(name))?;
+
+    let args = &items[1..];
+    let well_formed = !args.is_empty()
+        && args.len().is_multiple_of(step)
+        && args
+            .iter()
+            .all(|arg| matches!(arg, ResponseValue::BulkString(Some(_))));
+    well_formed.then_some((step, gather))
+}
+
+/// Splits a multi-key command into one sub-command per shard and hands them
+/// to `scatter_gather`.
+#[cfg(feature = "server")]
+#[allow(clippy::too_many_arguments)]
+async fn route_multi_key(
+    router: &[Sender<WorkerMessage>],
+    items: &[ResponseValue],
+    seq: u64,
+    writer_tx: OwnedPermit<ResponseMessage>,
+    step: usize,
+    gather: Gather,
+    trace: CommandTrace,
+    unanswered: Option<&Unanswered>,
+) {
+    let (cmd, args) = (&items[0], &items[1..]);
+
+    // per shard: the positions of its keys among all keys, and its arguments
+    let mut parts: Vec<(Vec<usize>, Vec<ResponseValue>)> =
+        vec![(Vec::new(), Vec::new()); router.len()];
+    for (position, chunk) in args.chunks(step).enumerate() {
+        let ResponseValue::BulkString(Some(key)) = &chunk[0] else {
+            continue; // multi_key_spec only lets bulk strings through
+        };
+        let (positions, part_args) = &mut parts[shard_for(key, router.len())];
+        positions.push(position);
+        part_args.extend_from_slice(chunk);
+    }
+
+    let parts = parts
+        .into_iter()
+        .enumerate()
+        .filter(|(_, (positions, _))| !positions.is_empty())
+        .map(|(shard, (positions, part_args))| {
+            let mut sub_command = Vec::with_capacity(part_args.len() + 1);
+            sub_command.push(cmd.clone());
+            sub_command.extend(part_args);
+            (shard, positions, ResponseValue::Array(Some(sub_command)))
         })
-        .unwrap();
+        .collect();
+
+    scatter_gather(
+        router,
+        parts,
+        args.len() / step,
+        gather,
+        seq,
+        writer_tx,
+        trace,
+        unanswered,
+    )
+    .await;
+}
+
+/// Sends each `(shard, key positions, command)` part to its shard, then
+/// spawns `gather_replies` to write their replies combined as one once every
+/// shard has answered. `key_count` sizes the reply of `Gather::PerKey`, where
+/// each part's reply elements land at its positions.
+#[cfg(feature = "server")]
+#[allow(clippy::too_many_arguments)]
+async fn scatter_gather(
+    router: &[Sender<WorkerMessage>],
+    parts: Vec<(usize, Vec<usize>, ResponseValue)>,
+    key_count: usize,
+    gather: Gather,
+    seq: u64,
+    writer_tx: OwnedPermit<ResponseMessage>,
+    trace: CommandTrace,
+    unanswered: Option<&Unanswered>,
+) {
+    // every shard gets the same `MEMORY BIGKEYS`, checked once here
+    let mut largest_count = 0;
+    if gather == Gather::Largest {
+        match parts
+            .first()
+            .and_then(|(_, _, command)| bigkeys::requested_count(command))
+        {
+            Some(Ok(count)) => largest_count = count,
+            Some(Err(err)) => {
+                writer_tx.send(ResponseMessage {
+                    seq,
+                    response_value: err,
+                    trace,
+                });
+                return;
+            }
+            None => {}
+        }
+    }
+
+    let mut pending = Vec::with_capacity(parts.len());
+    for (shard, positions, command) in parts {
+        let (tx, rx) = oneshot::channel();
+        let msg = WorkerMessage {
+            seq,
+            response_value: command,
+            tx: ReplyTo::Gather(tx),
+            trace: trace.clone(),
+        };
+        if let Some(unanswered) = unanswered {
+            unanswered.sent(shard, seq);
+        }
+        if router[shard].send(msg).await.is_err() {
+            send_error(writer_tx, seq, "internal server error, worker is gone");
+            return;
+        }
+        pending.push((positions, rx));
+    }
+
+    tokio::spawn(gather_replies(
+        pending,
+        key_count,
+        gather,
+        largest_count,
+        seq,
+        writer_tx,
+        trace,
+    ));
+}
+
+/// Waits for the reply to every part `scatter_gather` sent and writes them
+/// combined as one, or the first error among them.
+#[cfg(feature = "server")]
+async fn gather_replies(
+    pending: Vec<(Vec<usize>, oneshot::Receiver<ResponseValue>)>,
+    key_count: usize,
+    gather: Gather,
+    largest_count: usize,
+    seq: u64,
+    writer_tx: OwnedPermit<ResponseMessage>,
+    trace: CommandTrace,
+) {
+    let mut per_key = vec![ResponseValue::BulkString(None); key_count];
+    let mut sum = 0;
+    let mut largest = Vec::new();
+    for (positions, rx) in pending {
+        let reply = match rx.await {
+            Ok(reply) => reply,
+            Err(_) => {
+                send_error(writer_tx, seq, "internal server error, worker is gone");
+                return;
+            }
+        };
+        match (gather, reply) {
+            (Gather::PerKey, ResponseValue::Array(Some(values))) => {
+                for (position, value) in positions.into_iter().zip(values) {
+                    per_key[position] = value;
+                }
+            }
+            (Gather::Sum, ResponseValue::Integer(n)) => sum += n,
+            (Gather::AllOk, ResponseValue::SimpleString(_)) => {}
+            (Gather::Largest, ResponseValue::Array(Some(entries))) => largest.extend(entries),
+            (_, other) => {
+                // an error from any shard is the reply
+                writer_tx.send(ResponseMessage {
+                    seq,
+                    response_value: other,
+                    trace,
+                });
+                return;
+            }
+        }
+    }
+
+    let response_value = match gather {
+        Gather::PerKey => ResponseValue::Array(Some(per_key)),
+        Gather::Sum => ResponseValue::Integer(sum),
+        Gather::AllOk => ResponseValue::SimpleString("OK".into()),
+        Gather::Largest => bigkeys::merge(largest, largest_count),
+    };
+    writer_tx.send(ResponseMessage {
+        seq,
+        response_value,
+        trace,
+    });
 }
 
+/// Sends an `EXPORT` to the shard its cursor is in, with the cursor within
+/// that shard, and turns the cursor of the reply back into one of the whole
+/// keyspace: `shard + shards * shard cursor`, in a task of its own as
+/// `scatter_gather` does. Once a shard is walked, the next starts from its
+/// first bucket.
+#[cfg(feature = "server")]
+async fn route_export(
+    router: &[Sender<WorkerMessage>],
+    items: &[ResponseValue],
+    seq: u64,
+    writer_tx: OwnedPermit<ResponseMessage>,
+    trace: CommandTrace,
+    unanswered: Option<&Unanswered>,
+) {
+    let shards = router.len() as u64;
+    let cursor = match &items[1] {
+        ResponseValue::BulkString(Some(cursor)) => std::str::from_utf8(cursor)
+            .ok()
+            .and_then(|cursor| cursor.parse::<u64>().ok()),
+        _ => None,
+    };
+    let Some(cursor) = cursor else {
+        send_error(writer_tx, seq, "ERR invalid cursor");
+        return;
+    };
+    let shard = cursor % shards;
+
+    let mut command = items.to_vec();
+    command[1] = ResponseValue::BulkString(Some((cursor / shards).to_string().into()));
+    let (tx, rx) = oneshot::channel();
+    let msg = WorkerMessage {
+        seq,
+        response_value: ResponseValue::Array(Some(command)),
+        tx: ReplyTo::Gather(tx),
+        trace: trace.clone(),
+    };
+    if let Some(unanswered) = unanswered {
+        unanswered.sent(shard as usize, seq);
+    }
+    if router[shard as usize].send(msg).await.is_err() {
+        send_error(writer_tx, seq, "internal server error, worker is gone");
+        return;
+    }
+    tokio::spawn(export_reply(rx, shard, shards, seq, writer_tx, trace));
+}
+
+/// Writes the reply to an `EXPORT` sent by `route_export` to `shard`, its
+/// cursor turned back into one of the whole keyspace.
+#[cfg(feature = "server")]
+async fn export_reply(
+    rx: oneshot::Receiver<ResponseValue>,
+    shard: u64,
+    shards: u64,
+    seq: u64,
+    writer_tx: OwnedPermit<ResponseMessage>,
+    trace: CommandTrace,
+) {
+    let Ok(reply) = rx.await else {
+        send_error(writer_tx, seq, "internal server error, worker is gone");
+        return;
+    };
+
+    let response_value = match reply {
+        ResponseValue::Array(Some(mut parts)) => {
+            let next = match &parts[0] {
+                ResponseValue::BulkString(Some(next)) => std::str::from_utf8(next)
+                    .ok()
+                    .and_then(|next| next.parse::<u64>().ok())
+                    .unwrap_or(0),
+                _ => 0,
+            };
+            let next = match next {
+                0 if shard + 1 < shards => shard + 1,
+                0 => 0,
+                next => shard + shards * next,
+            };
+            parts[0] = ResponseValue::BulkString(Some(next.to_string().into()));
+            ResponseValue::Array(Some(parts))
+        }
+        // an error
+        other => other,
+    };
+    writer_tx.send(ResponseMessage {
+        seq,
+        response_value,
+        trace,
+    });
+}
+
+#[cfg(feature = "server")]
+fn send_error(writer_tx: OwnedPermit<ResponseMessage>, seq: u64, error_msg: &'static str) {
+    writer_tx.send(ResponseMessage {
+        seq,
+        response_value: ResponseValue::Error(error_msg.into()),
+        trace: CommandTrace::default(),
+    });
+}
+
+#[cfg(feature = "server")]
 fn extract_key(
-    writer_tx: &UnboundedSender<ResponseMessage>,
+    writer_tx: OwnedPermit<ResponseMessage>,
     seq: u64,
     items: &[ResponseValue],
-) -> Option<Bytes> {
+) -> Option<(Bytes, OwnedPermit<ResponseMessage>)> {
     let (cmd, args) = match items.split_first() {
-        Some((ResponseValue::BulkString(Some(bytes)), rest)) => (bytes, rest),
+        Some((ResponseValue::BulkString(Some(cmd)), rest)) => (cmd, rest),
         _ => {
             send_error(writer_tx, seq, "command must be bulk string");
             return None;
         }
     };
 
-    if cmd.eq_ignore_ascii_case(b"PING") {
-        send_string(writer_tx, seq, "PONG");
-        return None;
-    } else if cmd.eq_ignore_ascii_case(b"CONFIG") {
-        send_string(writer_tx, seq, "");
-        return None;
-    }
-
-    let key = match args.first() {
+    let position = KEY_POSITIONS
+        .iter()
+        .find(|(name, _)| cmd.eq_ignore_ascii_case(name))
+        .map_or(0, |(_, position)| *position);
+    let key = match args.get(position) {
         Some(ResponseValue::BulkString(Some(bytes))) => bytes,
         _ => {
             send_error(writer_tx, seq, "error while parsing key");
@@ -108,5 +639,145 @@ fn extract_key(
         }
     };
 
-    Some(key.clone())
+    Some((key.clone(), writer_tx))
+}
+
+fn ping(args: &[ResponseValue]) -> ResponseValue {
+    match args {
+        [] => ResponseValue::SimpleString("PONG".into()),
+        [message] => message.clone(),
+        _ => ResponseValue::Error("ERR wrong number of arguments for 'ping' command".into()),
+    }
+}
+
+fn echo(args: &[ResponseValue]) -> ResponseValue {
+    match args {
+        [message] => message.clone(),
+        _ => ResponseValue::Error("ERR wrong number of arguments for 'echo' command".into()),
+    }
+}
+
+fn info(args: &[ResponseValue]) -> ResponseValue {
+    let section = match args.first() {
+        Some(ResponseValue::BulkString(Some(bytes))) => std::str::from_utf8(bytes).ok(),
+        _ => None,
+    };
+    ResponseValue::BulkString(Some(render_info(section).into()))
+}
+
+/// No runtime configuration yet: `CONFIG GET` matches nothing, which is all
+/// clients like redis-benchmark need. Workers answer a `CONFIG` that reaches
+/// them with this too.
+pub(crate) fn config(args: &[ResponseValue]) -> ResponseValue {
+    match args.first() {
+        Some(ResponseValue::BulkString(Some(sub))) if sub.eq_ignore_ascii_case(b"GET") => {
+            ResponseValue::Array(Some(Vec::new()))
+        }
+        _ => ResponseValue::Error("ERR unsupported CONFIG subcommand".into()),
+    }
+}
+
+/// `MEMORY STATS`, from the usage each worker publishes after every batch.
+/// `MEMORY USAGE` goes to the key's shard instead.
+fn memory(args: &[ResponseValue]) -> ResponseValue {
+    match args.first() {
+        Some(ResponseValue::BulkString(Some(sub))) if sub.eq_ignore_ascii_case(b"STATS") => {}
+        _ => return ResponseValue::Error("ERR unknown MEMORY subcommand".into()),
+    }
+
+    let mut reply = Vec::new();
+    let mut field = |name: String, value: usize| {
+        reply.push(ResponseValue::BulkString(Some(name.into())));
+        reply.push(ResponseValue::Integer(value as i64));
+    };
+
+    let usage = STATS.dataset_usage();
+    let keys: usize = usage.iter().map(|usage| usage.keys).sum();
+    let bytes: usize = usage.iter().map(|usage| usage.bytes).sum();
+    field("keys.count".into(), keys);
+    field(
+        "keys.bytes-per-key".into(),
+        bytes.checked_div(keys).unwrap_or(0),
+    );
+    field("dataset.bytes".into(), bytes);
+    for (value_type, usage) in ValueType::ALL.iter().zip(usage) {
+        field(format!("{}.keys", value_type.name()), usage.keys);
+        field(format!("{}.bytes", value_type.name()), usage.bytes);
+    }
+    for (id, worker) in STATS.workers() {
+        field(format!("worker.{id}.bytes"), worker.used_memory() as usize);
+    }
+
+    let stats = allocator::stats();
+    for (name, bytes) in [
+        ("allocator.allocated", stats.allocated),
+        ("allocator.active", stats.active),
+        ("allocator.resident", stats.resident),
+        ("rss", allocator::process_rss()),
+    ] {
+        if let Some(bytes) = bytes {
+            field(name.into(), bytes);
+        }
+    }
+    for (name, ratio) in [
+        ("allocator-fragmentation.ratio", stats.frag_ratio()),
+        ("allocator-rss.ratio", stats.rss_ratio()),
+    ] {
+        if let Some(ratio) = ratio {
+            reply.push(ResponseValue::BulkString(Some(name.into())));
+            reply.push(ResponseValue::BulkString(Some(
+                format!("{ratio:.3}").into(),
+            )));
+        }
+    }
+    ResponseValue::Array(Some(reply))
+}
+
+/// `HOTKEYS [count]`: the `count` (default 10) most accessed keys lately,
+/// hottest first, each with its estimated access count, from the sketches
+/// the workers keep.
+fn hotkeys(args: &[ResponseValue]) -> ResponseValue {
+    let count = match args {
+        [] => 10,
+        [ResponseValue::BulkString(Some(count))] => {
+            match std::str::from_utf8(count)
+                .ok()
+                .and_then(|count| count.parse().ok())
+            {
+                Some(count) => count,
+                None => {
+                    return ResponseValue::Error(
+                        "ERR value is not an integer or out of range".into(),
+                    )
+                }
+            }
+        }
+        _ => {
+            return ResponseValue::Error(
+                "ERR wrong number of arguments for 'hotkeys' command".into(),
+            )
+        }
+    };
+    let keys = STATS
+        .hot_keys(count)
+        .into_iter()
+        .map(|(key, accesses)| {
+            ResponseValue::Array(Some(vec![
+                ResponseValue::BulkString(Some(key)),
+                ResponseValue::Integer(accesses as i64),
+            ]))
+        })
+        .collect();
+    ResponseValue::Array(Some(keys))
+}
+
+/// Command introspection is not implemented; an empty reply keeps clients that
+/// ask for it at startup (e.g. `COMMAND DOCS` from redis-cli) working.
+fn command(args: &[ResponseValue]) -> ResponseValue {
+    match args.first() {
+        Some(ResponseValue::BulkString(Some(sub))) if sub.eq_ignore_ascii_case(b"COUNT") => {
+            ResponseValue::Integer(0)
+        }
+        _ => ResponseValue::Array(Some(Vec::new())),
+    }
 }
@@ -0,0 +1,105 @@
+//! The server's identity and what it knows about its own process, for INFO
+//! `server`, `replication` and `cpu`.
+//!
+//! The run id and replication id are drawn at startup, 40 random hex
+//! characters each like Redis': the run id tells a restarted server from the
+//! one before, and the replication id names the history of the dataset, what
+//! a replica asks to continue from with PSYNC.
+
+use std::{
+    fmt::Write,
+    hash::{BuildHasher, RandomState},
+    sync::OnceLock,
+    time::{Duration, Instant},
+};
+
+use crate::config::Config;
+
+/// Length of run and replication ids.
+pub const ID_LEN: usize = 40;
+
+/// Who the server running in this process is.
+#[derive(Debug)]
+pub struct Identity {
+    pub run_id: String,
+    pub replid: String,
+    pub tcp_port: u16,
+    pub supervised: bool,
+    started: Instant,
+}
+
+static IDENTITY: OnceLock<Identity> = OnceLock::new();
+
+impl Identity {
+    fn new(config: &Config) -> Self {
+        Self {
+            run_id: random_id(),
+            replid: random_id(),
+            tcp_port: config.port,
+            supervised: config.supervised.is_systemd(),
+            started: Instant::now(),
+        }
+    }
+
+    pub fn uptime(&self) -> Duration {
+        self.started.elapsed()
+    }
+}
+
+/// Draws the server's ids and starts its uptime clock. Does nothing if that
+/// already happened, e.g. because INFO ran first in an embedding program.
+pub fn init(config: &Config) {
+    let _ = IDENTITY.set(Identity::new(config));
+}
+
+/// The server's identity, as `init` set it up, or with the default
+/// configuration if it never ran.
+pub fn identity() -> &'static Identity {
+    IDENTITY.get_or_init(|| Identity::new(&Config::default()))
+}
+
+/// `ID_LEN` random lowercase hex digits.
+pub fn random_id() -> String {
+    // SipHash under fresh random keys; std seeds them from the OS
+    let state = RandomState::new();
+    let mut id = String::with_capacity(ID_LEN + 16);
+    let mut word = 0u64;
+    while id.len() < ID_LEN {
+        let _ = write!(id, "{:016x}", state.hash_one(word));
+        word += 1;
+    }
+    id.truncate(ID_LEN);
+    id
+}
+
+/// The event loop connections are served from.
+pub const MULTIPLEXING_API: &str = if cfg!(all(feature = "io-uring", target_os = "linux")) {
+    "io_uring"
+} else if cfg!(any(target_os = "linux", target_os = "android")) {
+    "epoll"
+} else if cfg!(windows) {
+    "iocp"
+} else {
+    "kqueue"
+};
+
+/// CPU time the process has used so far, in system and user mode.
+#[cfg(unix)]
+pub fn cpu_time() -> Option<(Duration, Duration)> {
+    let mut usage = std::mem::MaybeUninit::<libc::rusage>::uninit();
+    // SAFETY: getrusage fills `usage` in when it returns 0
+    if unsafe { libc::getrusage(libc::RUSAGE_SELF, usage.as_mut_ptr()) } != 0 {
+        return None;
+    }
+    // SAFETY: see above
+    let usage = unsafe { usage.assume_init() };
+    let duration = |time: libc::timeval| {
+        Duration::from_secs(time.tv_sec as u64) + Duration::from_micros(time.tv_usec as u64)
+    };
+    Some((duration(usage.ru_stime), duration(usage.ru_utime)))
+}
+
+#[cfg(not(unix))]
+pub fn cpu_time() -> Option<(Duration, Duration)> {
+    None
+}
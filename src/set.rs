@@ -0,0 +1,245 @@
+use std::{
+    collections::HashMap,
+    hash::{BuildHasher, Hasher, RandomState},
+};
+
+use bytes::Bytes;
+
+use crate::{kv::element_size, string::parse_int};
+
+/// Default `set-max-intset-entries`.
+pub const SET_MAX_INTSET_ENTRIES: usize = 512;
+
+/// A set value. Sets of integers are kept as a sorted array while they stay
+/// under `set-max-intset-entries`, like Redis' intset; the first member that
+/// isn't an integer, or one too many, turns the set into a hash table for
+/// good. The table keeps the members in a `Vec` and their positions in a
+/// `HashMap`, so a random member is one index away and popping it is a swap
+/// with the last.
+#[derive(Clone, Debug)]
+pub enum Set {
+    Ints(Vec<i64>),
+    Hash {
+        members: Vec<Bytes>,
+        /// Position of each member in `members`.
+        index: HashMap<Bytes, usize>,
+        /// Sum of `element_size` over `members`.
+        bytes: usize,
+    },
+}
+
+impl Default for Set {
+    fn default() -> Self {
+        Set::Ints(Vec::new())
+    }
+}
+
+impl PartialEq for Set {
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len() && self.iter().all(|member| other.contains(&member))
+    }
+}
+
+impl Set {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            Set::Ints(ints) => ints.len(),
+            Set::Hash { members, .. } => members.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Name of the encoding, as `OBJECT ENCODING` reports it.
+    pub fn encoding(&self) -> &'static str {
+        match self {
+            Set::Ints(_) => "intset",
+            Set::Hash { .. } => "hashtable",
+        }
+    }
+
+    /// Approximate bytes held by the members.
+    pub fn memory_usage(&self) -> usize {
+        match self {
+            Set::Ints(ints) => ints.len() * std::mem::size_of::<i64>(),
+            Set::Hash { bytes, .. } => *bytes,
+        }
+    }
+
+    pub fn contains(&self, member: &[u8]) -> bool {
+        match self {
+            Set::Ints(ints) => parse_int(member).is_some_and(|n| ints.binary_search(&n).is_ok()),
+            Set::Hash { index, .. } => index.contains_key(member),
+        }
+    }
+
+    /// Adds `member`, returning whether it was new.
+    pub fn insert(&mut self, member: Bytes, max_intset_entries: usize) -> bool {
+        if let Set::Ints(ints) = self {
+            if let Some(n) = parse_int(&member) {
+                match ints.binary_search(&n) {
+                    Ok(_) => return false,
+                    Err(at) if ints.len() < max_intset_entries => {
+                        ints.insert(at, n);
+                        return true;
+                    }
+                    Err(_) => {}
+                }
+            }
+            self.convert();
+        }
+
+        let Set::Hash {
+            members,
+            index,
+            bytes,
+        } = self
+        else {
+            unreachable!("converted above");
+        };
+        if index.contains_key(&member) {
+            return false;
+        }
+        *bytes += element_size(&member);
+        index.insert(member.clone(), members.len());
+        members.push(member);
+        true
+    }
+
+    /// Removes and returns a member picked uniformly at random, in O(1) for
+    /// the hash table.
+    pub fn pop(&mut self) -> Option<Bytes> {
+        if self.is_empty() {
+            return None;
+        }
+        let at = random_index(self.len());
+        match self {
+            Set::Ints(ints) => Some(int_to_bytes(ints.remove(at))),
+            Set::Hash {
+                members,
+                index,
+                bytes,
+            } => {
+                let member = members.swap_remove(at);
+                index.remove(&member);
+                if let Some(moved) = members.get(at) {
+                    index.insert(moved.clone(), at);
+                }
+                *bytes -= element_size(&member);
+                Some(member)
+            }
+        }
+    }
+
+    /// The members, integers formatted back into strings.
+    pub fn iter(&self) -> impl Iterator<Item = Bytes> + '_ {
+        let (ints, hash) = match self {
+            Set::Ints(ints) => (Some(ints.iter().copied().map(int_to_bytes)), None),
+            Set::Hash { members, .. } => (None, Some(members.iter().cloned())),
+        };
+        ints.into_iter().flatten().chain(hash.into_iter().flatten())
+    }
+
+    /// Up to `count` members for `SSCAN` and the cursor to carry on from, 0
+    /// once done. The table is walked from its last position down: `pop`
+    /// only moves the last member and `insert` appends, so a member there
+    /// for the whole walk is returned, though maybe twice. An intset shifts
+    /// on every insert and is returned whole, as Redis does its small
+    /// encodings.
+    pub fn scan(&self, cursor: usize, count: usize) -> (usize, Vec<Bytes>) {
+        match self {
+            Set::Ints(_) => (0, self.iter().collect()),
+            Set::Hash { members, .. } => {
+                let end = match cursor {
+                    0 => members.len(),
+                    cursor => cursor.min(members.len()),
+                };
+                let start = end.saturating_sub(count);
+                (start, members[start..end].iter().rev().cloned().collect())
+            }
+        }
+    }
+
+    /// Moves the set into fresh allocations, for active defrag. Returns the
+    /// allocations moved.
+    pub fn defrag(&mut self) -> usize {
+        match self {
+            Set::Ints(ints) => {
+                *ints = ints.clone();
+                1
+            }
+            Set::Hash { members, index, .. } => {
+                *members = members
+                    .iter()
+                    .map(|member| Bytes::copy_from_slice(member))
+                    .collect();
+                *index = hash_index(members);
+                members.len() + 1
+            }
+        }
+    }
+
+    fn convert(&mut self) {
+        let members: Vec<Bytes> = self.iter().collect();
+        let index = hash_index(&members);
+        let bytes = members.iter().map(element_size).sum();
+        *self = Set::Hash {
+            members,
+            index,
+            bytes,
+        };
+    }
+}
+
+fn hash_index(members: &[Bytes]) -> HashMap<Bytes, usize> {
+    members
+        .iter()
+        .enumerate()
+        .map(|(at, member)| (member.clone(), at))
+        .collect()
+}
+
+/// A uniformly random index below `len`, which is not 0. Multiplying
+/// instead of taking the remainder keeps the bias under `len / 2^64`.
+fn random_index(len: usize) -> usize {
+    let random = RandomState::new().build_hasher().finish();
+    ((random as u128 * len as u128) >> 64) as usize
+}
+
+fn int_to_bytes(n: i64) -> Bytes {
+    Bytes::from(n.to_string())
+}
+
+/// Serialized as the sequence of its members, in no particular order.
+#[cfg(feature = "serde")]
+impl serde::Serialize for Set {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeSeq;
+
+        // with its length, which the chained iterator can't tell
+        let mut seq = serializer.serialize_seq(Some(self.len()))?;
+        for member in self.iter() {
+            seq.serialize_element(&member)?;
+        }
+        seq.end()
+    }
+}
+
+/// Encoded as the default `set-max-intset-entries` would have it; repeated
+/// members are kept once.
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Set {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let mut set = Set::new();
+        for member in Vec::<Bytes>::deserialize(deserializer)? {
+            set.insert(member, SET_MAX_INTSET_ENTRIES);
+        }
+        Ok(set)
+    }
+}
@@ -0,0 +1,235 @@
+//! `Simulation`, the whole server on one thread with a virtual clock, for
+//! testing what depends on time without sleeping through it. Built with the
+//! `sim` feature.
+//!
+//! The workers, and the reader and writer of every connection, are tasks of
+//! a single current-thread runtime whose clock is paused: it only moves when
+//! a task sleeps and every task is waiting, and then jumps straight to the
+//! next timer. A test waiting out a 5 second idle `timeout` takes no time,
+//! and runs the same way every time, as tasks are polled in a fixed order
+//! rather than raced across threads.
+//!
+//! Key expiry is on the virtual clock too: within `block_on`, the Unix time
+//! TTLs are counted in starts from when the simulation was set up and moves
+//! with it, so a key set with `EX 10` is gone once a test sleeps 10 seconds.
+//!
+//! Connections are in-memory pipes rather than sockets, served by the same
+//! code as TCP ones past the accept, and count toward `maxclients` and INFO
+//! like them; the connection hooks aren't called for them. Background
+//! threads, such as lazy freeing and the sampler behind INFO, still run on
+//! their own and on the real clock, as does the writer's corking, which
+//! only decides how replies are split into writes.
+//!
+//! ```
+//! use std::time::Duration;
+//!
+//! use rustis::{config::Config, sim::Simulation};
+//!
+//! let sim = Simulation::new(Config { timeout: 60, ..Config::default() });
+//! sim.block_on(async {
+//!     let mut connection = sim.connect();
+//!     connection.command(["SET", "key", "value"]).await;
+//!     tokio::time::sleep(Duration::from_secs(61)).await;
+//!     // closed for idling past the timeout
+//!     assert!(connection.reply().await.is_none());
+//! });
+//! assert!(sim.elapsed() >= Duration::from_secs(61));
+//! ```
+
+use std::{
+    cell::Cell,
+    future::Future,
+    net::{Ipv4Addr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
+
+use bytes::{Bytes, BytesMut};
+use tokio::{
+    io::{self, AsyncReadExt, AsyncWriteExt, DuplexStream},
+    runtime::{Builder, Runtime},
+    sync::mpsc::{self, Sender},
+    task::LocalSet,
+    time::Instant,
+};
+
+use crate::{
+    config::Config,
+    connection::{serve_stream, Client, MAX_CLIENTS_REPLY, READ_BUFFER_SIZE},
+    kv::unix_time_ms,
+    local::LocalClient,
+    message::{ResponseValue, WorkerMessage},
+    parser::{parse, BufParseError},
+    snapshot::SnapshotWriter,
+    stats::admit_client,
+    threads::WORKER_MAILBOX_CAPACITY,
+    worker::worker_loop,
+};
+
+/// Workers of a simulation whose config leaves it at 0: enough for
+/// commands to split across shards.
+pub const WORKERS: usize = 2;
+
+thread_local! {
+    /// The Unix time, in milliseconds, the simulation running on this thread
+    /// was set up at, and its clock then.
+    static VIRTUAL_EPOCH: Cell<Option<(u64, Instant)>> = const { Cell::new(None) };
+}
+
+/// The Unix time in milliseconds on the clock of the simulation running on
+/// this thread, if any, for `unix_time_ms`.
+pub(crate) fn virtual_unix_time_ms() -> Option<u64> {
+    let (unix_started, started) = VIRTUAL_EPOCH.get()?;
+    Some(unix_started + started.elapsed().as_millis() as u64)
+}
+
+/// The server, on the current thread, with its clock stopped.
+pub struct Simulation {
+    runtime: Runtime,
+    local: LocalSet,
+    router: Arc<Vec<Sender<WorkerMessage>>>,
+    config: Arc<Config>,
+    started: Instant,
+    /// `unix_time_ms()` at `started`.
+    unix_started: u64,
+}
+
+impl Simulation {
+    /// Sets up `config.workers` workers, or `WORKERS`; nothing runs until
+    /// `block_on`.
+    pub fn new(config: Config) -> Self {
+        let runtime = Builder::new_current_thread()
+            .enable_all()
+            .start_paused(true)
+            .build()
+            .expect("failed to build the simulation's runtime");
+        let local = LocalSet::new();
+        let config = Arc::new(config);
+        let workers = match config.workers {
+            0 => WORKERS,
+            workers => workers,
+        };
+
+        let mut router = Vec::with_capacity(workers);
+        for worker_id in 0..workers {
+            let (tx, rx) = mpsc::channel(WORKER_MAILBOX_CAPACITY);
+            let snapshot = config.read_snapshots.then(|| SnapshotWriter::register(&tx));
+            router.push(tx);
+            let config = config.clone();
+            local.spawn_local(async move { worker_loop(worker_id, rx, &config, snapshot).await });
+        }
+        // the paused clock can only be read within the runtime
+        let started = {
+            let _runtime = runtime.enter();
+            Instant::now()
+        };
+
+        Self {
+            runtime,
+            local,
+            router: Arc::new(router),
+            config,
+            started,
+            unix_started: unix_time_ms(),
+        }
+    }
+
+    /// Runs `future`, along with the server, until it is done. The clock
+    /// moves on whenever everything is waiting, so `tokio::time::sleep` in
+    /// `future` returns at once with that much time gone by.
+    pub fn block_on<F: Future>(&self, future: F) -> F::Output {
+        let outer = VIRTUAL_EPOCH.replace(Some((self.unix_started, self.started)));
+        let output = self.local.block_on(&self.runtime, future);
+        VIRTUAL_EPOCH.set(outer);
+        output
+    }
+
+    /// Virtual time since the simulation was set up.
+    pub fn elapsed(&self) -> Duration {
+        let _runtime = self.runtime.enter();
+        self.started.elapsed()
+    }
+
+    /// A client running commands in-process, without a connection.
+    pub fn client(&self) -> LocalClient {
+        LocalClient::new(&self.router)
+    }
+
+    /// A new client connection, served once `block_on` runs. Past
+    /// `maxclients` it is sent the error and closed, as over TCP.
+    pub fn connect(&self) -> Connection {
+        let (client_end, server_end) = io::duplex(READ_BUFFER_SIZE);
+        let (read_half, mut write_half) = io::split(server_end);
+        let router = self.router.clone();
+        let config = self.config.clone();
+        let slot = admit_client(config.maxclients);
+
+        self.local.spawn_local(async move {
+            let Some(slot) = slot else {
+                let _ = write_half.write_all(MAX_CLIENTS_REPLY).await;
+                return;
+            };
+            let client = Client {
+                id: slot.id(),
+                addr: SocketAddr::from((Ipv4Addr::LOCALHOST, 0)),
+            };
+            let result = serve_stream(read_half, write_half, client, &router, &config).await;
+            if let Err(err) = result {
+                tracing::warn!("Error handling simulated connection: {:?}", err);
+            }
+            drop(slot);
+        });
+
+        Connection {
+            stream: client_end,
+            buffer: BytesMut::new(),
+        }
+    }
+}
+
+/// The client's end of a simulated connection; dropping it disconnects.
+pub struct Connection {
+    stream: DuplexStream,
+    buffer: BytesMut,
+}
+
+impl Connection {
+    /// Sends the command made of `args` and waits for its reply, or `None`
+    /// if the server closed the connection instead.
+    pub async fn command<I>(&mut self, args: I) -> Option<ResponseValue>
+    where
+        I: IntoIterator,
+        I::Item: Into<Bytes>,
+    {
+        let frame = args
+            .into_iter()
+            .map(|arg| ResponseValue::BulkString(Some(arg.into())))
+            .collect();
+        let mut request = BytesMut::new();
+        ResponseValue::Array(Some(frame)).serialize(&mut request);
+        self.send(&request).await;
+        self.reply().await
+    }
+
+    /// Writes `bytes` as they are, such as a pipeline or a broken request.
+    /// A server that has closed the connection ignores them.
+    pub async fn send(&mut self, bytes: &[u8]) {
+        let _ = self.stream.write_all(bytes).await;
+    }
+
+    /// Waits for the next reply, or `None` once the server has closed the
+    /// connection.
+    pub async fn reply(&mut self) -> Option<ResponseValue> {
+        loop {
+            match parse(&mut self.buffer) {
+                Ok(reply) => return Some(reply),
+                Err(BufParseError::Incomplete) => {}
+                Err(err) => panic!("the server sent an unparseable reply: {err:?}"),
+            }
+            match self.stream.read_buf(&mut self.buffer).await {
+                Ok(0) | Err(_) => return None,
+                Ok(_) => {}
+            }
+        }
+    }
+}
@@ -0,0 +1,227 @@
+//! Read snapshots, with `--read-snapshots yes`: `GET` and `MGET` answered
+//! by the connection's own thread from a copy of the strings of each shard,
+//! without queuing in the worker's mailbox behind its writes.
+//!
+//! Each worker keeps two copies, left-right style: readers load the
+//! published one through an `ArcSwap`, without locking, while the worker
+//! brings the other up to date with the keys changed since, then swaps them
+//! at the end of every batch, before sending the batch's replies. A copy is
+//! only written to once the readers still on it are done with their lookup.
+//! Reads thus see every write whose reply went out. A connection's read of
+//! a shard it sent a command still unanswered goes to the worker instead,
+//! behind that command, so a pipeline reads its own writes. Reads don't
+//! count as keyspace hits or misses, nor as accesses for eviction or
+//! `HOTKEYS`, and misses of keys a loader covers are left to the worker, see
+//! `loader`.
+//!
+//! A shard's snapshot is found through its mailbox, so each server in a
+//! process only reads its own.
+
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use arc_swap::ArcSwap;
+use bytes::Bytes;
+use tokio::sync::mpsc::{Sender, WeakSender};
+
+use crate::{
+    kv::{unix_time_ms, Changes, KvStore, RedisValue},
+    loader,
+    message::{ResponseValue, WorkerMessage},
+    router::shard_for,
+};
+
+/// A shard's key, as a snapshot holds it.
+#[derive(Debug, Clone)]
+struct SnapshotEntry {
+    /// The string, or `None` for a key of another type, which reads leave to
+    /// the worker to refuse.
+    value: Option<Bytes>,
+    expires_at: Option<u64>,
+}
+
+type Snapshot = HashMap<Bytes, SnapshotEntry>;
+
+/// A worker's published snapshot, by its mailbox.
+struct Published {
+    mailbox: WeakSender<WorkerMessage>,
+    snapshot: Arc<ArcSwap<Snapshot>>,
+}
+
+static PUBLISHED: std::sync::LazyLock<ArcSwap<Vec<Arc<Published>>>> =
+    std::sync::LazyLock::new(ArcSwap::default);
+
+/// The worker's side of its shard's snapshot.
+pub struct SnapshotWriter {
+    published: Arc<Published>,
+    /// The copy readers don't see, and the changes it lacks.
+    standby: Arc<Snapshot>,
+    standby_behind: Changes,
+    /// The changes the published copy lacks.
+    published_behind: Changes,
+}
+
+impl SnapshotWriter {
+    /// Publishes an empty snapshot for the worker behind `mailbox`, which
+    /// serves reads until the writer is dropped.
+    pub fn register(mailbox: &Sender<WorkerMessage>) -> Self {
+        let published = Arc::new(Published {
+            mailbox: mailbox.downgrade(),
+            snapshot: Arc::new(ArcSwap::from_pointee(Snapshot::new())),
+        });
+        PUBLISHED.rcu(|all| {
+            let mut all = Vec::clone(all);
+            all.push(published.clone());
+            all
+        });
+        Self {
+            published,
+            standby: Arc::new(Snapshot::new()),
+            standby_behind: Changes::default(),
+            published_behind: Changes::default(),
+        }
+    }
+
+    /// Publishes what changed in `kv` since the last call.
+    pub fn publish(&mut self, kv: &mut KvStore) {
+        let changes = kv.take_changes();
+        self.standby_behind.merge(&changes);
+        self.published_behind.merge(&changes);
+        if self.standby_behind.is_empty() {
+            return;
+        }
+        // readers only hold a copy for a lookup
+        let standby = loop {
+            if Arc::get_mut(&mut self.standby).is_some() {
+                break Arc::get_mut(&mut self.standby).unwrap();
+            }
+            std::thread::yield_now();
+        };
+        let behind = std::mem::take(&mut self.standby_behind);
+        if behind.flushed {
+            standby.clear();
+        }
+        for key in behind.keys {
+            match kv.peek(&key) {
+                Some(value) => {
+                    let value = match value {
+                        RedisValue::String(s) => Some(s.to_bytes()),
+                        _ => None,
+                    };
+                    let expires_at = kv.expires_at(&key);
+                    standby.insert(key, SnapshotEntry { value, expires_at });
+                }
+                None => {
+                    standby.remove(&key);
+                }
+            }
+        }
+        let standby = std::mem::take(&mut self.standby);
+        self.standby = self.published.snapshot.swap(standby);
+        self.standby_behind = std::mem::take(&mut self.published_behind);
+    }
+}
+
+impl Drop for SnapshotWriter {
+    fn drop(&mut self) {
+        PUBLISHED.rcu(|all| {
+            let mut all = Vec::clone(all);
+            all.retain(|published| !Arc::ptr_eq(published, &self.published));
+            all
+        });
+    }
+}
+
+/// The commands a connection sent to each shard that are yet to be answered,
+/// shared by its reader, which numbers and sends them, and its writer. Both
+/// run on the connection's thread; atomics only keep routing futures `Send`.
+#[derive(Debug)]
+pub(crate) struct Unanswered {
+    /// The last command sent to each shard, by sequence number.
+    last_sent: Box<[AtomicU64]>,
+    /// Every command numbered below this has been answered.
+    answered_below: AtomicU64,
+}
+
+impl Unanswered {
+    pub(crate) fn new(shards: usize) -> Self {
+        Self {
+            last_sent: (0..shards).map(|_| AtomicU64::new(0)).collect(),
+            // commands are numbered from 1
+            answered_below: AtomicU64::new(1),
+        }
+    }
+
+    pub(crate) fn sent(&self, shard: usize, seq: u64) {
+        self.last_sent[shard].store(seq, Ordering::Relaxed);
+    }
+
+    pub(crate) fn answered_below(&self, seq: u64) {
+        self.answered_below.store(seq, Ordering::Relaxed);
+    }
+
+    fn waiting_on(&self, shard: usize) -> bool {
+        self.last_sent[shard].load(Ordering::Relaxed) >= self.answered_below.load(Ordering::Relaxed)
+    }
+}
+
+/// The reply to `items`, a `GET` or `MGET`, from the snapshots of the
+/// shards behind `router`, or `None` if it's for their workers to answer,
+/// as it is when `unanswered` is still waiting on one of them.
+pub(crate) fn read(
+    router: &[Sender<WorkerMessage>],
+    items: &[ResponseValue],
+    unanswered: Option<&Unanswered>,
+) -> Option<ResponseValue> {
+    let all = PUBLISHED.load();
+    if all.is_empty() {
+        return None;
+    }
+    let Some(ResponseValue::BulkString(Some(cmd))) = items.first() else {
+        return None;
+    };
+    let single = if cmd.eq_ignore_ascii_case(b"GET") {
+        true
+    } else if cmd.eq_ignore_ascii_case(b"MGET") {
+        false
+    } else {
+        return None;
+    };
+
+    let now = unix_time_ms();
+    let mut values = Vec::with_capacity(items.len() - 1);
+    for item in &items[1..] {
+        let ResponseValue::BulkString(Some(key)) = item else {
+            return None;
+        };
+        let shard = shard_for(key, router.len());
+        if unanswered.is_some_and(|unanswered| unanswered.waiting_on(shard)) {
+            return None;
+        }
+        let mailbox = &router[shard];
+        let published = all.iter().find(|published| {
+            let registered = published.mailbox.upgrade();
+            registered.is_some_and(|registered| registered.same_channel(mailbox))
+        })?;
+        let value = match published.snapshot.load().get(key) {
+            Some(entry) if entry.expires_at.is_none_or(|at| at > now) => match &entry.value {
+                Some(value) => Some(value.clone()),
+                None if single => return None,
+                None => None,
+            },
+            _ if single && loader::covers(key) => return None,
+            _ => None,
+        };
+        values.push(ResponseValue::BulkString(value));
+    }
+    if single {
+        values.pop()
+    } else {
+        Some(ResponseValue::Array(Some(values)))
+    }
+}
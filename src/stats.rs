@@ -0,0 +1,453 @@
+use std::{
+    cmp::Reverse,
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, MutexGuard,
+    },
+    time::{Duration, Instant},
+};
+
+use bytes::Bytes;
+
+use crate::{
+    hotkeys::HotKeys,
+    kv::{TypeUsage, VALUE_TYPES},
+    latency::CommandLatencies,
+};
+
+/// How often the instantaneous metrics are sampled, and how many samples they
+/// are averaged over (same as Redis: 16 samples, 100ms apart).
+pub const SAMPLE_INTERVAL: Duration = Duration::from_millis(100);
+const SAMPLES: usize = 16;
+
+/// Consecutive samples a worker's mailbox has to stay saturated (at least
+/// three quarters full) before it is reported as hot: one second.
+pub const HOT_WORKER_SAMPLES: u64 = 10;
+
+/// Distinct error prefixes counted in `errorstats`, as in Redis. Errors with
+/// a prefix beyond these only count toward `total_error_replies`, so a client
+/// making up prefixes can't grow the table without bound.
+pub const ERRORSTATS_LIMIT: usize = 128;
+
+/// Server wide counters reported by INFO. Updated with relaxed atomics from
+/// the IO threads; readers only need a roughly consistent view.
+pub struct ServerStats {
+    pub total_connections_received: AtomicU64,
+    pub connected_clients: AtomicU64,
+    pub rejected_connections: AtomicU64,
+    pub total_commands_processed: AtomicU64,
+    pub total_net_input_bytes: AtomicU64,
+    pub total_net_output_bytes: AtomicU64,
+    /// Approximate bytes held by the dataset, summed over the workers.
+    pub used_memory: AtomicU64,
+    pub evicted_keys: AtomicU64,
+    /// Values handed to the lazy-free thread and not dropped yet.
+    pub lazyfree_pending_objects: AtomicU64,
+    pub lazyfreed_objects: AtomicU64,
+    /// Allocations active defrag moved, and keys it moved them for.
+    pub active_defrag_hits: AtomicU64,
+    pub active_defrag_key_hits: AtomicU64,
+    pub total_error_replies: AtomicU64,
+    /// Error replies per prefix, for `errorstats`.
+    error_replies: Mutex<ErrorReplies>,
+    instantaneous: Mutex<Instantaneous>,
+    workers: Mutex<BTreeMap<usize, Arc<WorkerStats>>>,
+}
+
+pub static STATS: ServerStats = ServerStats::new();
+
+impl Default for ServerStats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ServerStats {
+    pub const fn new() -> Self {
+        Self {
+            total_connections_received: AtomicU64::new(0),
+            connected_clients: AtomicU64::new(0),
+            rejected_connections: AtomicU64::new(0),
+            total_commands_processed: AtomicU64::new(0),
+            total_net_input_bytes: AtomicU64::new(0),
+            total_net_output_bytes: AtomicU64::new(0),
+            used_memory: AtomicU64::new(0),
+            evicted_keys: AtomicU64::new(0),
+            lazyfree_pending_objects: AtomicU64::new(0),
+            lazyfreed_objects: AtomicU64::new(0),
+            active_defrag_hits: AtomicU64::new(0),
+            active_defrag_key_hits: AtomicU64::new(0),
+            total_error_replies: AtomicU64::new(0),
+            error_replies: Mutex::new(ErrorReplies {
+                prefixes: BTreeMap::new(),
+                full: false,
+            }),
+            instantaneous: Mutex::new(Instantaneous::new()),
+            workers: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    pub fn incr(counter: &AtomicU64, by: u64) {
+        counter.fetch_add(by, Ordering::Relaxed);
+    }
+
+    pub fn get(counter: &AtomicU64) -> u64 {
+        counter.load(Ordering::Relaxed)
+    }
+
+    /// Records one sample of the per-second rates; called every `SAMPLE_INTERVAL`.
+    pub fn sample(&self, now: Instant) {
+        let totals = [
+            Self::get(&self.total_commands_processed),
+            Self::get(&self.total_net_input_bytes),
+            Self::get(&self.total_net_output_bytes),
+        ];
+        if let Ok(mut instantaneous) = self.instantaneous.lock() {
+            instantaneous.record(now, totals);
+        }
+        for (id, worker) in self.workers() {
+            worker.sample(id);
+        }
+    }
+
+    /// Counts an error reply sent to a client, under its prefix.
+    pub fn record_error(&self, message: &[u8]) {
+        Self::incr(&self.total_error_replies, 1);
+        let prefix = error_prefix(message);
+        let mut errors = self.error_replies.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(count) = errors.prefixes.get_mut(prefix) {
+            *count += 1;
+        } else if errors.prefixes.len() < ERRORSTATS_LIMIT {
+            errors.prefixes.insert(prefix.to_string(), 1);
+        } else if !errors.full {
+            errors.full = true;
+            tracing::warn!(
+                "errorstats is tracking {ERRORSTATS_LIMIT} error prefixes; replies with new \
+                 prefixes only count toward total_error_replies"
+            );
+        }
+    }
+
+    /// Error replies per prefix, in prefix order.
+    pub fn error_replies(&self) -> Vec<(String, u64)> {
+        self.error_replies
+            .lock()
+            .map(|errors| {
+                errors
+                    .prefixes
+                    .iter()
+                    .map(|(prefix, count)| (prefix.clone(), *count))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// The counters of worker `id`, registered on first use.
+    pub fn worker(&self, id: usize, mailbox_capacity: usize) -> Arc<WorkerStats> {
+        let mut workers = self.workers.lock().unwrap_or_else(|e| e.into_inner());
+        workers
+            .entry(id)
+            .or_insert_with(|| Arc::new(WorkerStats::new(mailbox_capacity)))
+            .clone()
+    }
+
+    /// Keys and bytes per type summed over every worker, indexed by
+    /// `ValueType`.
+    pub fn dataset_usage(&self) -> [TypeUsage; VALUE_TYPES] {
+        let mut total = [TypeUsage::default(); VALUE_TYPES];
+        for (_, worker) in self.workers() {
+            for (total, usage) in total.iter_mut().zip(worker.type_usage()) {
+                total.keys += usage.keys;
+                total.bytes += usage.bytes;
+            }
+        }
+        total
+    }
+
+    /// Execution time histograms per command, merged over every worker.
+    pub fn command_latencies(&self) -> CommandLatencies {
+        let mut total = CommandLatencies::default();
+        for (_, worker) in self.workers() {
+            total.merge(&worker.latencies());
+        }
+        total
+    }
+
+    /// The `count` hottest keys over every worker, hottest first, with their
+    /// estimated access counts. Keys live on one worker each, so the
+    /// workers' lists only need merging.
+    pub fn hot_keys(&self, count: usize) -> Vec<(Bytes, u32)> {
+        let mut keys: Vec<_> = self
+            .workers()
+            .iter()
+            .flat_map(|(_, worker)| worker.hot_keys().top().to_vec())
+            .collect();
+        keys.sort_by_key(|(_, count)| Reverse(*count));
+        keys.truncate(count);
+        keys
+    }
+
+    /// Reads that found their key, summed over every worker.
+    pub fn keyspace_hits(&self) -> u64 {
+        self.workers()
+            .iter()
+            .map(|(_, worker)| ServerStats::get(&worker.keyspace_hits))
+            .sum()
+    }
+
+    /// Reads that didn't find their key, summed over every worker.
+    pub fn keyspace_misses(&self) -> u64 {
+        self.workers()
+            .iter()
+            .map(|(_, worker)| ServerStats::get(&worker.keyspace_misses))
+            .sum()
+    }
+
+    /// Keys removed because their TTL passed, summed over every worker.
+    pub fn expired_keys(&self) -> u64 {
+        self.workers()
+            .iter()
+            .map(|(_, worker)| ServerStats::get(&worker.expired_keys))
+            .sum()
+    }
+
+    /// Keys with a TTL over every worker, and the average milliseconds they
+    /// have left.
+    pub fn expires(&self) -> (u64, u64) {
+        let (mut keys, mut total_ttl) = (0, 0);
+        for (_, worker) in self.workers() {
+            let count = ServerStats::get(&worker.expires);
+            keys += count;
+            total_ttl += count as u128 * ServerStats::get(&worker.avg_ttl) as u128;
+        }
+        let avg_ttl = match keys {
+            0 => 0,
+            keys => (total_ttl / keys as u128) as u64,
+        };
+        (keys, avg_ttl)
+    }
+
+    /// Every registered worker, by id.
+    pub fn workers(&self) -> Vec<(usize, Arc<WorkerStats>)> {
+        self.workers
+            .lock()
+            .map(|workers| workers.iter().map(|(id, w)| (*id, w.clone())).collect())
+            .unwrap_or_default()
+    }
+
+    pub fn instantaneous_ops_per_sec(&self) -> u64 {
+        self.instantaneous_rate(0)
+    }
+
+    pub fn instantaneous_input_kbps(&self) -> f64 {
+        self.instantaneous_rate(1) as f64 / 1024.0
+    }
+
+    pub fn instantaneous_output_kbps(&self) -> f64 {
+        self.instantaneous_rate(2) as f64 / 1024.0
+    }
+
+    fn instantaneous_rate(&self, metric: usize) -> u64 {
+        self.instantaneous
+            .lock()
+            .map(|instantaneous| instantaneous.average(metric))
+            .unwrap_or(0)
+    }
+}
+
+struct ErrorReplies {
+    prefixes: BTreeMap<String, u64>,
+    /// Set once a new prefix was turned away, so that is only logged once.
+    full: bool,
+}
+
+/// The prefix an error reply is counted under: its first word if that is
+/// upper case, like `WRONGTYPE` or `NOAUTH`, and `ERR` for the few replies
+/// that start with a plain message instead.
+pub fn error_prefix(message: &[u8]) -> &str {
+    let word = message.split(|b| *b == b' ').next().unwrap_or_default();
+    if !word.is_empty() && word.iter().all(|b| b.is_ascii_uppercase()) {
+        std::str::from_utf8(word).unwrap_or("ERR")
+    } else {
+        "ERR"
+    }
+}
+
+/// Per-worker counters. Since keys are pinned to a worker, a hot key shows up
+/// as one worker with a much deeper mailbox and processed count than the rest.
+pub struct WorkerStats {
+    pub commands_processed: AtomicU64,
+    /// Messages left in the mailbox after the worker's last batch.
+    pub queue_depth: AtomicU64,
+    /// The shard's keyspace hits and misses as of the worker's last batch.
+    pub keyspace_hits: AtomicU64,
+    pub keyspace_misses: AtomicU64,
+    /// The shard's expired keys, keys with a TTL and their average TTL in
+    /// milliseconds, as of the worker's last batch.
+    pub expired_keys: AtomicU64,
+    pub expires: AtomicU64,
+    pub avg_ttl: AtomicU64,
+    mailbox_capacity: u64,
+    saturated_samples: AtomicU64,
+    /// Keys and bytes of the worker's shard per type, indexed by `ValueType`,
+    /// as of its last batch.
+    type_keys: [AtomicU64; VALUE_TYPES],
+    type_bytes: [AtomicU64; VALUE_TYPES],
+    /// Execution times of the commands the worker ran.
+    latencies: Mutex<CommandLatencies>,
+    /// Access counts of the keys its commands touched.
+    hot_keys: Mutex<HotKeys>,
+}
+
+impl WorkerStats {
+    pub fn new(mailbox_capacity: usize) -> Self {
+        Self {
+            commands_processed: AtomicU64::new(0),
+            queue_depth: AtomicU64::new(0),
+            keyspace_hits: AtomicU64::new(0),
+            keyspace_misses: AtomicU64::new(0),
+            expired_keys: AtomicU64::new(0),
+            expires: AtomicU64::new(0),
+            avg_ttl: AtomicU64::new(0),
+            mailbox_capacity: mailbox_capacity as u64,
+            saturated_samples: AtomicU64::new(0),
+            type_keys: Default::default(),
+            type_bytes: Default::default(),
+            latencies: Mutex::default(),
+            hot_keys: Mutex::default(),
+        }
+    }
+
+    /// Publishes the shard's per-type usage.
+    pub fn record_memory(&self, usage: &[TypeUsage; VALUE_TYPES]) {
+        for (i, usage) in usage.iter().enumerate() {
+            self.type_keys[i].store(usage.keys as u64, Ordering::Relaxed);
+            self.type_bytes[i].store(usage.bytes as u64, Ordering::Relaxed);
+        }
+    }
+
+    /// The shard's per-type usage as last published.
+    pub fn type_usage(&self) -> [TypeUsage; VALUE_TYPES] {
+        std::array::from_fn(|i| TypeUsage {
+            keys: ServerStats::get(&self.type_keys[i]) as usize,
+            bytes: ServerStats::get(&self.type_bytes[i]) as usize,
+        })
+    }
+
+    /// Bytes held by the shard as last published.
+    pub fn used_memory(&self) -> u64 {
+        self.type_bytes.iter().map(ServerStats::get).sum()
+    }
+
+    /// The worker's execution time histograms, which it holds while running
+    /// a batch.
+    pub fn latencies(&self) -> MutexGuard<'_, CommandLatencies> {
+        self.latencies.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// The worker's key access counts, which it holds while running a batch.
+    pub fn hot_keys(&self) -> MutexGuard<'_, HotKeys> {
+        self.hot_keys.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Whether the mailbox has stayed saturated for `HOT_WORKER_SAMPLES`.
+    pub fn is_hot(&self) -> bool {
+        ServerStats::get(&self.saturated_samples) >= HOT_WORKER_SAMPLES
+    }
+
+    fn sample(&self, id: usize) {
+        let depth = ServerStats::get(&self.queue_depth);
+        if depth * 4 < self.mailbox_capacity * 3 {
+            self.saturated_samples.store(0, Ordering::Relaxed);
+            return;
+        }
+        let samples = self.saturated_samples.fetch_add(1, Ordering::Relaxed) + 1;
+        if samples == HOT_WORKER_SAMPLES {
+            tracing::warn!(
+                "worker {id} mailbox has been over 75% full for a second ({depth}/{} queued); \
+                 a hot key is likely pinning it. Spread the load over more keys, or check \
+                 INFO workers for the imbalance",
+                self.mailbox_capacity
+            );
+        }
+    }
+}
+
+/// Keeps `connected_clients` up to date for as long as a client is connected.
+/// Also carries the client's id, numbered in order of connection from 1.
+pub struct ClientSlot(u64);
+
+impl ClientSlot {
+    pub fn id(&self) -> u64 {
+        self.0
+    }
+}
+
+impl Drop for ClientSlot {
+    fn drop(&mut self) {
+        STATS.connected_clients.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Counts a newly accepted connection and admits it, unless `maxclients`
+/// clients are already connected (0 means no limit).
+pub fn admit_client(maxclients: usize) -> Option<ClientSlot> {
+    let id = STATS
+        .total_connections_received
+        .fetch_add(1, Ordering::Relaxed)
+        + 1;
+
+    let connected = STATS.connected_clients.fetch_add(1, Ordering::Relaxed);
+    let slot = ClientSlot(id);
+    if maxclients > 0 && connected >= maxclients as u64 {
+        drop(slot);
+        ServerStats::incr(&STATS.rejected_connections, 1);
+        return None;
+    }
+    Some(slot)
+}
+
+/// Ring of per-second rates for commands, input bytes and output bytes.
+struct Instantaneous {
+    last: Option<(Instant, [u64; 3])>,
+    rates: [[u64; SAMPLES]; 3],
+    idx: usize,
+}
+
+impl Instantaneous {
+    const fn new() -> Self {
+        Self {
+            last: None,
+            rates: [[0; SAMPLES]; 3],
+            idx: 0,
+        }
+    }
+
+    fn record(&mut self, now: Instant, totals: [u64; 3]) {
+        if let Some((then, previous)) = self.last {
+            let millis = now.duration_since(then).as_millis().max(1) as u64;
+            for metric in 0..3 {
+                let delta = totals[metric].saturating_sub(previous[metric]);
+                self.rates[metric][self.idx] = delta * 1000 / millis;
+            }
+            self.idx = (self.idx + 1) % SAMPLES;
+        }
+        self.last = Some((now, totals));
+    }
+
+    fn average(&self, metric: usize) -> u64 {
+        self.rates[metric].iter().sum::<u64>() / SAMPLES as u64
+    }
+}
+
+/// Starts the background thread that samples the instantaneous metrics.
+pub fn spawn_sampler() {
+    std::thread::Builder::new()
+        .name("stats-sampler".into())
+        .spawn(|| loop {
+            STATS.sample(Instant::now());
+            std::thread::sleep(SAMPLE_INTERVAL);
+        })
+        .expect("failed to spawn stats sampler");
+}
@@ -0,0 +1,182 @@
+//! Pushes the server's metrics to StatsD (and through it Graphite) over UDP,
+//! with `--statsd <host:port>`.
+//!
+//! Every `--statsd-interval` seconds a thread sends what INFO reports, under
+//! `--statsd-prefix`: the running totals (`commands_processed`,
+//! `net_input_bytes`, `keyspace_hits`, ...) as counters of what changed since
+//! the last push, the current levels (`connected_clients`, `used_memory`,
+//! `keys`, ...) as gauges, and the latency percentiles of each command, in
+//! milliseconds since startup, as gauges too: they are already aggregated, so
+//! sending them as timers would have StatsD average percentiles.
+
+use std::{
+    collections::HashMap,
+    io,
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs, UdpSocket},
+    time::Duration,
+};
+
+use crate::{
+    config::Config,
+    latency::PERCENTILES,
+    stats::{ServerStats, STATS},
+};
+
+/// Most bytes sent in one datagram, which fits the payload of a 1500 byte
+/// Ethernet frame with room to spare, as StatsD recommends.
+pub const MAX_PACKET: usize = 1432;
+
+/// Sends rounds of metrics to a StatsD server.
+pub struct Exporter {
+    socket: UdpSocket,
+    prefix: String,
+    /// Each counter's total at the previous push.
+    previous: HashMap<&'static str, u64>,
+}
+
+impl Exporter {
+    /// An exporter sending to `target`, a `host:port` resolved once, every
+    /// name prefixed with `prefix.` (or nothing if `prefix` is empty).
+    pub fn new(target: &str, prefix: &str) -> io::Result<Self> {
+        let target = target.to_socket_addrs()?.next().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("can't resolve {target}"),
+            )
+        })?;
+        let local: SocketAddr = match target {
+            SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+            SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+        };
+        let socket = UdpSocket::bind(local)?;
+        socket.connect(target)?;
+        let prefix = if prefix.is_empty() {
+            String::new()
+        } else {
+            format!("{prefix}.")
+        };
+        Ok(Self {
+            socket,
+            prefix,
+            previous: HashMap::new(),
+        })
+    }
+
+    /// Sends one round of metrics, packed into as few datagrams as fit.
+    pub fn push(&mut self) -> io::Result<()> {
+        let mut packet = String::with_capacity(MAX_PACKET);
+        for line in self.lines() {
+            if !packet.is_empty() && packet.len() + 1 + line.len() > MAX_PACKET {
+                self.socket.send(packet.as_bytes())?;
+                packet.clear();
+            }
+            if !packet.is_empty() {
+                packet.push('\n');
+            }
+            packet.push_str(&line);
+        }
+        if !packet.is_empty() {
+            self.socket.send(packet.as_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// This round's metrics, one StatsD line each.
+    fn lines(&mut self) -> Vec<String> {
+        let counters = [
+            (
+                "connections_received",
+                ServerStats::get(&STATS.total_connections_received),
+            ),
+            (
+                "rejected_connections",
+                ServerStats::get(&STATS.rejected_connections),
+            ),
+            (
+                "commands_processed",
+                ServerStats::get(&STATS.total_commands_processed),
+            ),
+            (
+                "net_input_bytes",
+                ServerStats::get(&STATS.total_net_input_bytes),
+            ),
+            (
+                "net_output_bytes",
+                ServerStats::get(&STATS.total_net_output_bytes),
+            ),
+            ("evicted_keys", ServerStats::get(&STATS.evicted_keys)),
+            ("keyspace_hits", STATS.keyspace_hits()),
+            ("keyspace_misses", STATS.keyspace_misses()),
+            (
+                "lazyfreed_objects",
+                ServerStats::get(&STATS.lazyfreed_objects),
+            ),
+            (
+                "active_defrag_hits",
+                ServerStats::get(&STATS.active_defrag_hits),
+            ),
+            (
+                "error_replies",
+                ServerStats::get(&STATS.total_error_replies),
+            ),
+        ];
+        let keys: usize = STATS.dataset_usage().iter().map(|usage| usage.keys).sum();
+        let gauges = [
+            (
+                "connected_clients",
+                ServerStats::get(&STATS.connected_clients),
+            ),
+            ("used_memory", ServerStats::get(&STATS.used_memory)),
+            ("keys", keys as u64),
+            (
+                "lazyfree_pending_objects",
+                ServerStats::get(&STATS.lazyfree_pending_objects),
+            ),
+            (
+                "instantaneous_ops_per_sec",
+                STATS.instantaneous_ops_per_sec(),
+            ),
+        ];
+
+        let prefix = &self.prefix;
+        let mut lines = Vec::new();
+        for (name, total) in counters {
+            let previous = self.previous.insert(name, total).unwrap_or(0);
+            lines.push(format!(
+                "{prefix}{name}:{}|c",
+                total.saturating_sub(previous)
+            ));
+        }
+        for (name, value) in gauges {
+            lines.push(format!("{prefix}{name}:{value}|g"));
+        }
+        for (command, histogram) in STATS.command_latencies().iter() {
+            for percentile in PERCENTILES {
+                // Graphite takes dots for path separators: p99.9 is p99_9
+                let name = format!("p{percentile}").replace('.', "_");
+                let millis = histogram.percentile(percentile) as f64 / 1e6;
+                lines.push(format!("{prefix}latency.{command}.{name}:{millis:.3}|g"));
+            }
+        }
+        lines
+    }
+}
+
+/// Starts pushing metrics if `config` names a StatsD server.
+pub fn init(config: &Config) -> io::Result<()> {
+    let Some(target) = &config.statsd else {
+        return Ok(());
+    };
+    let mut exporter = Exporter::new(target, &config.statsd_prefix)?;
+    let interval = Duration::from_secs(config.statsd_interval);
+    std::thread::Builder::new()
+        .name("statsd".into())
+        .spawn(move || loop {
+            std::thread::sleep(interval);
+            // nothing listening is refused every round; don't flood the log
+            if let Err(err) = exporter.push() {
+                tracing::debug!("Can't push StatsD metrics: {err}");
+            }
+        })?;
+    Ok(())
+}
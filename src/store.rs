@@ -0,0 +1,244 @@
+//! `Store`, typed async calls on the data (`store.get(key).await`) for
+//! programs embedding the server.
+//!
+//! Each call is the command of the same name run through a `LocalClient`, so
+//! it is routed to the shard owning the key, or split across shards, just as
+//! over the network, and behaves the same: `get` on a list fails with
+//! `WRONGTYPE`, `mget` reads lists as missing, and so on. Commands without a
+//! method here are a `store.client().command(..)` away.
+
+use std::fmt;
+
+use bytes::Bytes;
+
+use crate::{
+    local::{LocalClient, SHUT_DOWN_ERROR},
+    message::ResponseValue,
+};
+
+/// Why a `Store` call failed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum StoreError {
+    /// The server is shut down, or wasn't started.
+    ShutDown,
+    /// The error reply a network client would have had, e.g.
+    /// `WRONGTYPE Operation against a key holding the wrong kind of value`.
+    Reply(String),
+}
+
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StoreError::ShutDown => f.write_str(SHUT_DOWN_ERROR),
+            StoreError::Reply(message) => f.write_str(message),
+        }
+    }
+}
+
+impl std::error::Error for StoreError {}
+
+pub type Result<T> = std::result::Result<T, StoreError>;
+
+/// The dataset of a running server; cheap to clone.
+#[derive(Clone, Debug)]
+pub struct Store {
+    client: LocalClient,
+}
+
+impl Store {
+    pub fn new(client: LocalClient) -> Self {
+        Self { client }
+    }
+
+    pub fn client(&self) -> &LocalClient {
+        &self.client
+    }
+
+    pub async fn get(&self, key: impl Into<Bytes>) -> Result<Option<Bytes>> {
+        bulk(self.run(vec![bytes("GET"), key.into()]).await?)
+    }
+
+    pub async fn set(&self, key: impl Into<Bytes>, value: impl Into<Bytes>) -> Result<()> {
+        ok(self
+            .run(vec![bytes("SET"), key.into(), value.into()])
+            .await?)
+    }
+
+    /// The string at each key, `None` for missing keys and other types.
+    pub async fn mget<K: Into<Bytes>>(
+        &self,
+        keys: impl IntoIterator<Item = K>,
+    ) -> Result<Vec<Option<Bytes>>> {
+        let reply = self.run(command("MGET", keys)).await?;
+        array(reply)?.into_iter().map(bulk).collect()
+    }
+
+    pub async fn mset<K: Into<Bytes>, V: Into<Bytes>>(
+        &self,
+        pairs: impl IntoIterator<Item = (K, V)>,
+    ) -> Result<()> {
+        let mut args = vec![bytes("MSET")];
+        for (key, value) in pairs {
+            args.push(key.into());
+            args.push(value.into());
+        }
+        ok(self.run(args).await?)
+    }
+
+    /// Deletes `keys`, returning how many there were.
+    pub async fn del<K: Into<Bytes>>(&self, keys: impl IntoIterator<Item = K>) -> Result<i64> {
+        integer(self.run(command("DEL", keys)).await?)
+    }
+
+    /// How many of `keys` exist, counting repeated keys each time.
+    pub async fn exists<K: Into<Bytes>>(&self, keys: impl IntoIterator<Item = K>) -> Result<i64> {
+        integer(self.run(command("EXISTS", keys)).await?)
+    }
+
+    /// Pushes `values` to the head of the list, returning its new length.
+    #[cfg(feature = "lists")]
+    pub async fn lpush<V: Into<Bytes>>(
+        &self,
+        key: impl Into<Bytes>,
+        values: impl IntoIterator<Item = V>,
+    ) -> Result<i64> {
+        integer(self.run(keyed("LPUSH", key, values)).await?)
+    }
+
+    /// Pushes `values` to the tail of the list, returning its new length.
+    #[cfg(feature = "lists")]
+    pub async fn rpush<V: Into<Bytes>>(
+        &self,
+        key: impl Into<Bytes>,
+        values: impl IntoIterator<Item = V>,
+    ) -> Result<i64> {
+        integer(self.run(keyed("RPUSH", key, values)).await?)
+    }
+
+    /// Pops up to `count` items off the head of the list.
+    #[cfg(feature = "lists")]
+    pub async fn lpop(&self, key: impl Into<Bytes>, count: usize) -> Result<Vec<Bytes>> {
+        strings(self.run(keyed("LPOP", key, [count.to_string()])).await?)
+    }
+
+    /// Pops up to `count` items off the tail of the list, the last one first.
+    #[cfg(feature = "lists")]
+    pub async fn rpop(&self, key: impl Into<Bytes>, count: usize) -> Result<Vec<Bytes>> {
+        strings(self.run(keyed("RPOP", key, [count.to_string()])).await?)
+    }
+
+    /// Items `start` to `stop` of the list, both included; negative indexes
+    /// count from the tail.
+    #[cfg(feature = "lists")]
+    pub async fn lrange(&self, key: impl Into<Bytes>, start: i64, stop: i64) -> Result<Vec<Bytes>> {
+        let args = keyed("LRANGE", key, [start.to_string(), stop.to_string()]);
+        strings(self.run(args).await?)
+    }
+
+    /// Adds `members` to the set, returning how many weren't in it yet.
+    #[cfg(feature = "sets")]
+    pub async fn sadd<M: Into<Bytes>>(
+        &self,
+        key: impl Into<Bytes>,
+        members: impl IntoIterator<Item = M>,
+    ) -> Result<i64> {
+        integer(self.run(keyed("SADD", key, members)).await?)
+    }
+
+    /// Removes and returns up to `count` random members of the set.
+    #[cfg(feature = "sets")]
+    pub async fn spop(&self, key: impl Into<Bytes>, count: usize) -> Result<Vec<Bytes>> {
+        strings(self.run(keyed("SPOP", key, [count.to_string()])).await?)
+    }
+
+    #[cfg(feature = "sets")]
+    pub async fn smembers(&self, key: impl Into<Bytes>) -> Result<Vec<Bytes>> {
+        strings(self.run(vec![bytes("SMEMBERS"), key.into()]).await?)
+    }
+
+    /// Keys across every shard.
+    pub async fn dbsize(&self) -> Result<i64> {
+        integer(self.run(vec![bytes("DBSIZE")]).await?)
+    }
+
+    /// Empties every shard.
+    pub async fn flushall(&self) -> Result<()> {
+        ok(self.run(vec![bytes("FLUSHALL")]).await?)
+    }
+
+    async fn run(&self, args: Vec<Bytes>) -> Result<ResponseValue> {
+        match self.client.command(args).await {
+            ResponseValue::Error(message) if message == SHUT_DOWN_ERROR.as_bytes() => {
+                Err(StoreError::ShutDown)
+            }
+            ResponseValue::Error(message) => Err(StoreError::Reply(
+                String::from_utf8_lossy(&message).into_owned(),
+            )),
+            reply => Ok(reply),
+        }
+    }
+}
+
+fn bytes(name: &'static str) -> Bytes {
+    Bytes::from_static(name.as_bytes())
+}
+
+fn command<A: Into<Bytes>>(name: &'static str, args: impl IntoIterator<Item = A>) -> Vec<Bytes> {
+    std::iter::once(bytes(name))
+        .chain(args.into_iter().map(Into::into))
+        .collect()
+}
+
+#[cfg(any(feature = "lists", feature = "sets"))]
+fn keyed<A: Into<Bytes>>(
+    name: &'static str,
+    key: impl Into<Bytes>,
+    args: impl IntoIterator<Item = A>,
+) -> Vec<Bytes> {
+    let mut command = command(name, args);
+    command.insert(1, key.into());
+    command
+}
+
+fn unexpected(reply: ResponseValue) -> StoreError {
+    StoreError::Reply(format!("ERR unexpected reply {reply:?}"))
+}
+
+fn ok(reply: ResponseValue) -> Result<()> {
+    match reply {
+        ResponseValue::SimpleString(_) => Ok(()),
+        reply => Err(unexpected(reply)),
+    }
+}
+
+fn integer(reply: ResponseValue) -> Result<i64> {
+    match reply {
+        ResponseValue::Integer(n) => Ok(n),
+        reply => Err(unexpected(reply)),
+    }
+}
+
+fn bulk(reply: ResponseValue) -> Result<Option<Bytes>> {
+    match reply {
+        ResponseValue::BulkString(value) => Ok(value),
+        reply => Err(unexpected(reply)),
+    }
+}
+
+fn array(reply: ResponseValue) -> Result<Vec<ResponseValue>> {
+    match reply {
+        ResponseValue::Array(items) => Ok(items.unwrap_or_default()),
+        reply => Err(unexpected(reply)),
+    }
+}
+
+#[cfg(any(feature = "lists", feature = "sets"))]
+fn strings(reply: ResponseValue) -> Result<Vec<Bytes>> {
+    array(reply)?
+        .into_iter()
+        .map(|item| match item {
+            ResponseValue::BulkString(Some(value)) => Ok(value),
+            item => Err(unexpected(item)),
+        })
+        .collect()
+}
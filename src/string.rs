@@ -0,0 +1,135 @@
+use bytes::Bytes;
+
+/// Longest string stored inline in the entry, same cut-off as Redis'
+/// `OBJ_ENCODING_EMBSTR_SIZE_LIMIT`.
+pub const EMBSTR_SIZE_LIMIT: usize = 44;
+
+/// The integer `bytes` spells, if it is one written the way Redis would
+/// print it back: no sign other than `-`, no leading zeros, no spaces.
+pub fn parse_int(bytes: &[u8]) -> Option<i64> {
+    let digits = bytes.strip_prefix(b"-").unwrap_or(bytes);
+    let canonical = match digits {
+        [b'0'] => bytes.len() == 1,
+        [b'1'..=b'9', rest @ ..] => rest.iter().all(u8::is_ascii_digit),
+        _ => false,
+    };
+    if !canonical || bytes.len() > 20 {
+        return None;
+    }
+    std::str::from_utf8(bytes).ok()?.parse().ok()
+}
+
+/// A string value, in the cheapest of Redis' three encodings that holds
+/// it: an integer, a short string kept inline, or shared `Bytes`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum StringValue {
+    Int(i64),
+    Embedded {
+        len: u8,
+        buf: [u8; EMBSTR_SIZE_LIMIT],
+    },
+    Raw(Bytes),
+}
+
+impl From<Bytes> for StringValue {
+    fn from(bytes: Bytes) -> Self {
+        if let Some(n) = parse_int(&bytes) {
+            StringValue::Int(n)
+        } else if bytes.len() <= EMBSTR_SIZE_LIMIT {
+            let mut buf = [0; EMBSTR_SIZE_LIMIT];
+            buf[..bytes.len()].copy_from_slice(&bytes);
+            StringValue::Embedded {
+                len: bytes.len() as u8,
+                buf,
+            }
+        } else {
+            StringValue::Raw(bytes)
+        }
+    }
+}
+
+impl StringValue {
+    pub fn len(&self) -> usize {
+        match self {
+            StringValue::Int(n) => itoa_len(*n),
+            StringValue::Embedded { len, .. } => *len as usize,
+            StringValue::Raw(bytes) => bytes.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Name of the encoding, as `OBJECT ENCODING` reports it.
+    pub fn encoding(&self) -> &'static str {
+        match self {
+            StringValue::Int(_) => "int",
+            StringValue::Embedded { .. } => "embstr",
+            StringValue::Raw(_) => "raw",
+        }
+    }
+
+    /// Approximate bytes held by the string. An integer takes none besides
+    /// the entry itself.
+    pub fn memory_usage(&self) -> usize {
+        match self {
+            StringValue::Int(_) => 0,
+            _ => self.len(),
+        }
+    }
+
+    /// The value as an integer, when it is stored as one.
+    pub fn as_int(&self) -> Option<i64> {
+        match self {
+            StringValue::Int(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    /// Moves the string into a fresh allocation, for active defrag. Returns
+    /// the allocations moved: none for the encodings kept inline.
+    pub fn defrag(&mut self) -> usize {
+        match self {
+            StringValue::Raw(bytes) => {
+                *bytes = Bytes::copy_from_slice(bytes);
+                1
+            }
+            _ => 0,
+        }
+    }
+
+    /// The string's bytes. Integers are formatted and short strings copied
+    /// out; long ones are shared.
+    pub fn to_bytes(&self) -> Bytes {
+        match self {
+            StringValue::Int(n) => Bytes::from(n.to_string()),
+            StringValue::Embedded { len, buf } => Bytes::copy_from_slice(&buf[..*len as usize]),
+            StringValue::Raw(bytes) => bytes.clone(),
+        }
+    }
+}
+
+/// Digits, and sign, in `n` written out.
+fn itoa_len(n: i64) -> usize {
+    let sign = usize::from(n < 0);
+    sign + n
+        .unsigned_abs()
+        .checked_ilog10()
+        .map_or(1, |digits| digits as usize + 1)
+}
+
+/// Serialized as its bytes, whatever the encoding.
+#[cfg(feature = "serde")]
+impl serde::Serialize for StringValue {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.to_bytes().serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for StringValue {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Bytes::deserialize(deserializer).map(StringValue::from)
+    }
+}
@@ -0,0 +1,192 @@
+//! OpenTelemetry traces of sampled commands, enabled with the `otel` cargo
+//! feature.
+//!
+//! One in every `1 / --otel-sample-ratio` commands gets a `command` span,
+//! with the command's name and key count as attributes, and a child span for
+//! each stage it goes through: `parse` on the connection, `route` until its
+//! worker took it (mailbox wait included), `execute` on the worker and
+//! `serialize` back on the connection. Spans are exported over OTLP/HTTP to
+//! `--otel-endpoint` from a background thread.
+//!
+//! Without the feature the types here are empty and every call is a no-op,
+//! so the command path pays nothing for them.
+
+#[cfg(feature = "otel")]
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        OnceLock,
+    },
+    time::SystemTime,
+};
+
+#[cfg(feature = "otel")]
+use opentelemetry::{
+    trace::{Span as _, TraceContextExt, Tracer as _, TracerProvider as _},
+    Context, KeyValue,
+};
+#[cfg(feature = "otel")]
+use opentelemetry_sdk::trace::{SdkTracer, SdkTracerProvider, Span};
+
+use crate::{config::Config, message::ResponseValue};
+
+/// Trace one command in this many, 0 for none.
+#[cfg(feature = "otel")]
+static SAMPLE_EVERY: AtomicU64 = AtomicU64::new(0);
+#[cfg(feature = "otel")]
+static COMMANDS: AtomicU64 = AtomicU64::new(0);
+#[cfg(feature = "otel")]
+static TRACER: OnceLock<(SdkTracerProvider, SdkTracer)> = OnceLock::new();
+
+/// Starts exporting traces if `--otel-sample-ratio` is above 0.
+#[cfg(feature = "otel")]
+pub fn init(config: &Config) -> Result<(), String> {
+    use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+    use opentelemetry_sdk::Resource;
+
+    if config.otel_sample_ratio <= 0.0 {
+        return Ok(());
+    }
+    let exporter = SpanExporter::builder()
+        .with_http()
+        .with_endpoint(config.otel_endpoint.as_str())
+        .build()
+        .map_err(|err| err.to_string())?;
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(Resource::builder().with_service_name("rustis").build())
+        .build();
+    install(provider, config.otel_sample_ratio);
+    Ok(())
+}
+
+#[cfg(not(feature = "otel"))]
+pub fn init(config: &Config) -> Result<(), String> {
+    if config.otel_sample_ratio > 0.0 {
+        return Err("--otel-sample-ratio needs a build with the otel feature".to_string());
+    }
+    Ok(())
+}
+
+/// Traces one in every `1 / sample_ratio` commands into `provider`, for
+/// `init` and for tests with their own exporter. Only the first call counts.
+#[cfg(feature = "otel")]
+pub fn install(provider: SdkTracerProvider, sample_ratio: f64) {
+    let tracer = provider.tracer("rustis");
+    if TRACER.set((provider, tracer)).is_ok() {
+        let every = (1.0 / sample_ratio).round().max(1.0) as u64;
+        SAMPLE_EVERY.store(every, Ordering::Relaxed);
+    }
+}
+
+/// Stops tracing and exports the spans still buffered; called on the way
+/// out.
+pub fn shutdown() {
+    #[cfg(feature = "otel")]
+    if let Some((provider, _)) = TRACER.get() {
+        SAMPLE_EVERY.store(0, Ordering::Relaxed);
+        let _ = provider.shutdown();
+    }
+}
+
+#[cfg(feature = "otel")]
+fn tracer() -> Option<&'static SdkTracer> {
+    TRACER.get().map(|(_, tracer)| tracer)
+}
+
+/// Whether the next command is traced, decided before it is parsed so its
+/// trace can cover the parse.
+#[derive(Clone, Copy)]
+pub struct Sample {
+    #[cfg(feature = "otel")]
+    started: Option<SystemTime>,
+}
+
+impl Sample {
+    pub fn next() -> Self {
+        #[cfg(feature = "otel")]
+        {
+            let every = SAMPLE_EVERY.load(Ordering::Relaxed);
+            let sampled = every > 0
+                && COMMANDS
+                    .fetch_add(1, Ordering::Relaxed)
+                    .is_multiple_of(every);
+            Sample {
+                started: sampled.then(SystemTime::now),
+            }
+        }
+        #[cfg(not(feature = "otel"))]
+        Sample {}
+    }
+}
+
+/// A command's trace, carried with it from the connection to its worker and
+/// back. Empty, the default, for a command that isn't traced. The `command`
+/// span ends when the last copy is dropped.
+#[derive(Clone, Default)]
+pub struct CommandTrace {
+    #[cfg(feature = "otel")]
+    cx: Option<Context>,
+}
+
+impl CommandTrace {
+    /// Starts the trace of the command parsed into `frame`, if `sample` says
+    /// so, with its `parse` stage already done.
+    pub fn start(sample: Sample, frame: &ResponseValue) -> Self {
+        #[cfg(feature = "otel")]
+        if let (Some(started), Some(tracer)) = (sample.started, tracer()) {
+            let items = match frame {
+                ResponseValue::Array(Some(items)) => &items[..],
+                _ => &[],
+            };
+            let name = match items.first() {
+                Some(ResponseValue::BulkString(Some(name))) => {
+                    String::from_utf8_lossy(name).to_ascii_uppercase()
+                }
+                _ => String::new(),
+            };
+            let command = tracer
+                .span_builder("command")
+                .with_start_time(started)
+                .with_attributes([
+                    KeyValue::new("db.system.name", "redis"),
+                    KeyValue::new("db.operation.name", name),
+                    KeyValue::new("rustis.keys", crate::router::key_count(items) as i64),
+                ])
+                .start(tracer);
+            let cx = Context::new().with_span(command);
+            tracer
+                .span_builder("parse")
+                .with_start_time(started)
+                .start_with_context(tracer, &cx)
+                .end();
+            return CommandTrace { cx: Some(cx) };
+        }
+        let _ = (sample, frame);
+        CommandTrace::default()
+    }
+
+    /// Starts the stage `name` of the command, if it is traced.
+    pub fn stage(&self, name: &'static str) -> Stage {
+        #[cfg(feature = "otel")]
+        {
+            let span = self
+                .cx
+                .as_ref()
+                .zip(tracer())
+                .map(|(cx, tracer)| tracer.span_builder(name).start_with_context(tracer, cx));
+            Stage { _span: span }
+        }
+        #[cfg(not(feature = "otel"))]
+        {
+            let _ = name;
+            Stage {}
+        }
+    }
+}
+
+/// One stage of a traced command, ending when dropped.
+pub struct Stage {
+    #[cfg(feature = "otel")]
+    _span: Option<Span>,
+}
@@ -0,0 +1,71 @@
+//! `TestServer`, a throwaway server for other crates' integration tests, so
+//! they don't need a Redis installed locally. Built with the `testing`
+//! feature, e.g. as a dev-dependency:
+//!
+//! ```toml
+//! [dev-dependencies]
+//! rustis = { version = "0.1", features = ["testing"] }
+//! ```
+//!
+//! Every `TestServer` listens on a free port of its own, so tests can run in
+//! parallel, each against a server with an empty dataset. Server-wide state,
+//! INFO's counters and the installed hooks, is shared by the whole process
+//! though.
+
+use std::net::SocketAddr;
+
+use crate::{
+    embed::{Server, ServerBuilder},
+    local::LocalClient,
+};
+
+/// Workers of a `TestServer`: enough to split multi-key commands across
+/// shards, without a thread per core for every test.
+pub const WORKERS: usize = 2;
+
+/// A running server on `127.0.0.1` and a free port, shut down on drop.
+#[derive(Debug)]
+pub struct TestServer {
+    server: Server,
+}
+
+impl TestServer {
+    /// Starts a server with `WORKERS` workers and otherwise the defaults.
+    ///
+    /// # Panics
+    ///
+    /// If it can't bind a port or spawn its threads, which fails the test.
+    pub fn start() -> Self {
+        Self::with(Server::builder().workers(WORKERS))
+    }
+
+    /// Starts a server with `builder`'s settings, on a free port whatever
+    /// port it was given.
+    ///
+    /// # Panics
+    ///
+    /// Like `start`.
+    pub fn with(builder: ServerBuilder) -> Self {
+        let mut server = builder.port(0).build().expect("can't bind the test server");
+        server.start().expect("can't start the test server");
+        Self { server }
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.server.addr()
+    }
+
+    /// The address as a `redis://` URL, for client libraries taking those.
+    pub fn url(&self) -> String {
+        format!("redis://{}", self.addr())
+    }
+
+    /// A client running commands in-process, without a connection.
+    pub fn client(&self) -> LocalClient {
+        self.server.client()
+    }
+
+    pub fn server(&self) -> &Server {
+        &self.server
+    }
+}
@@ -1,39 +1,205 @@
-use core_affinity;
+use std::{
+    panic::{self, AssertUnwindSafe},
+    sync::{Arc, Barrier},
+    thread::JoinHandle,
+};
+
+use core_affinity::{self, CoreId};
 use thread_priority::{set_current_thread_priority, ThreadPriority};
-use tokio::sync::mpsc::{self, UnboundedSender};
+use tokio::sync::mpsc::{self, Sender};
+
+use crate::{
+    config::Config,
+    crash,
+    message::WorkerMessage,
+    snapshot::SnapshotWriter,
+    worker::{worker_main_reuseport, worker_main_snapshot},
+};
+
+/// Commands that can queue up for a single worker before the connections
+/// routing to it have to wait.
+pub const WORKER_MAILBOX_CAPACITY: usize = 8192;
+
+/// Whether workers can be pinned here. macOS only takes affinity as a hint
+/// for threads to share a cache, so workers are left to its scheduler.
+pub const PINNING_SUPPORTED: bool = cfg!(any(target_os = "linux", windows));
+
+pub fn spawn_threads(config: &Config) -> Vec<Sender<WorkerMessage>> {
+    let (txs, _) = spawn_workers(Arc::new(config.clone()), false);
+
+    // return the router
+    txs
+}
 
-use crate::{message::WorkerMessage, worker::worker_main};
+/// Spawns the workers as `spawn_workers` does, each accepting its own
+/// connections on the configured address through a `SO_REUSEPORT` listener.
+/// Returns the worker thread handles once every worker is listening.
+pub fn spawn_reuseport_threads(config: &Config) -> Vec<JoinHandle<()>> {
+    let (_, handles) = spawn_workers(Arc::new(config.clone()), true);
+    handles
+}
 
-pub fn spawn_threads() -> Vec<UnboundedSender<WorkerMessage>> {
-    let core_ids = core_affinity::get_core_ids().unwrap();
-    let num_cores = core_ids.len();
+/// Spawns `config.workers` workers, or one per core, pinned to the cores in
+/// turn: those of `config.worker_cpulist`, or all but the first
+/// `config.reserved_cores`. Returns their mailboxes and thread handles.
+pub(crate) fn spawn_workers(
+    config: Arc<Config>,
+    listen: bool,
+) -> (Vec<Sender<WorkerMessage>>, Vec<JoinHandle<()>>) {
+    let core_ids = match &config.worker_cpulist {
+        Some(cpulist) => cpulist.iter().map(|&id| CoreId { id }).collect(),
+        None => unreserved_cores(config.reserved_cores),
+    };
+    let num_workers = match config.workers {
+        0 => core_ids.len(),
+        workers => workers,
+    };
 
-    let mut txs = Vec::with_capacity(num_cores);
-    let mut rxs = Vec::with_capacity(num_cores);
+    let mut txs = Vec::with_capacity(num_workers);
+    let mut rxs = Vec::with_capacity(num_workers);
+    let mut snapshots = Vec::with_capacity(num_workers);
 
-    for _ in 0..num_cores {
-        let (tx, rx) = mpsc::unbounded_channel::<WorkerMessage>();
+    for _ in 0..num_workers {
+        let (tx, rx) = mpsc::channel::<WorkerMessage>(WORKER_MAILBOX_CAPACITY);
+        snapshots.push(config.read_snapshots.then(|| SnapshotWriter::register(&tx)));
         txs.push(tx);
         rxs.push(rx);
     }
 
-    for core_id in core_ids.into_iter() {
-        let mailxbox = rxs.remove(0);
+    let router = Arc::new(txs.clone());
+    // every listening worker, plus us, meet here once bound
+    let bound = listen.then(|| Arc::new(Barrier::new(num_workers + 1)));
+    let mut handles = Vec::with_capacity(num_workers);
+
+    let cores = core_ids.into_iter().cycle().take(num_workers);
+    for (worker_id, core_id) in cores.enumerate() {
+        let mailbox = rxs.remove(0);
+        let snapshot = snapshots.remove(0);
+        let config = config.clone();
+        // only listening workers hold the router: the others' mailboxes close,
+        // and they exit, once the connections are gone
+        let listening = bound.clone().map(|bound| (bound, router.clone()));
+
+        let worker = move || {
+            if config.worker_high_priority
+                && let Err(err) = set_current_thread_priority(ThreadPriority::Max)
+            {
+                tracing::warn!("failed to set priority to thread {:?}", err);
+            }
 
-        std::thread::spawn(move || {
-            if let Err(err) = set_current_thread_priority(ThreadPriority::Max) {
-                eprintln!("Warning: failed to set priority to thread {:?}", err);
+            if config.worker_pinning {
+                pin_worker(&config, core_id);
             }
 
-            #[cfg(target_os = "linux")]
-            if !core_affinity::set_for_current(core_id) {
-                eprintln!("failed to pin thread to core: {:?}", core_id);
+            let served = panic::catch_unwind(AssertUnwindSafe(|| match listening {
+                Some((bound, router)) => {
+                    worker_main_reuseport(worker_id, mailbox, router, config, bound, snapshot)
+                }
+                None => worker_main_snapshot(worker_id, mailbox, config, snapshot),
+            }));
+            if served.is_err() {
+                // its shard can no longer be served
+                tracing::error!("Worker {worker_id} died, shutting down");
+                crash::shutdown();
             }
+        };
 
-            worker_main(core_id.id, mailxbox);
-        });
+        let handle = std::thread::Builder::new()
+            .name(format!("worker-{worker_id}"))
+            .spawn(worker)
+            .expect("failed to spawn worker thread");
+        handles.push(handle);
     }
 
-    // return the router
-    txs
+    if let Some(bound) = bound {
+        bound.wait();
+    }
+
+    (txs, handles)
+}
+
+/// Pins the current thread to `core_id`, then, with `worker-numa-local`,
+/// has it prefer memory of the core's node. Does nothing where pinning
+/// isn't supported.
+fn pin_worker(config: &Config, core_id: CoreId) {
+    if !PINNING_SUPPORTED {
+        return;
+    }
+    if !core_affinity::set_for_current(core_id) {
+        tracing::warn!("failed to pin thread to core: {:?}", core_id);
+        return;
+    }
+    #[cfg(target_os = "linux")]
+    if config.worker_numa_local
+        && let Err(err) = prefer_local_node(core_id.id)
+    {
+        tracing::warn!(
+            "failed to prefer the NUMA node of core {}: {err}",
+            core_id.id
+        );
+    }
+    #[cfg(not(target_os = "linux"))]
+    let _ = config;
+}
+
+/// The cores, or where the platform can't list them, as many stand-ins as
+/// threads it runs at once, so there are as many workers either way.
+fn all_cores() -> Vec<CoreId> {
+    core_affinity::get_core_ids().unwrap_or_else(|| {
+        let count = std::thread::available_parallelism().map_or(1, |count| count.get());
+        (0..count).map(|id| CoreId { id }).collect()
+    })
+}
+
+/// Every core but the first `reserved`, or every core if that would leave
+/// none.
+fn unreserved_cores(reserved: usize) -> Vec<CoreId> {
+    let mut core_ids = all_cores();
+    if reserved < core_ids.len() {
+        core_ids.drain(..reserved);
+    } else if reserved > 0 {
+        tracing::warn!(
+            "can't reserve {reserved} of {} cores, workers use them all",
+            core_ids.len()
+        );
+    }
+    core_ids
+}
+
+/// Sets the current thread's memory policy to prefer the NUMA node of
+/// `core`, so what it allocates from now on is placed there first. Does
+/// nothing on a machine without NUMA nodes in sysfs.
+#[cfg(target_os = "linux")]
+fn prefer_local_node(core: usize) -> std::io::Result<()> {
+    // the core's directory holds a `node<N>` link to its node
+    let dir = std::fs::read_dir(format!("/sys/devices/system/cpu/cpu{core}"))?;
+    let node = dir.filter_map(Result::ok).find_map(|entry| {
+        let name = entry.file_name();
+        name.to_str()?.strip_prefix("node")?.parse::<usize>().ok()
+    });
+    let Some(node) = node else {
+        return Ok(());
+    };
+
+    // room for the kernel's largest MAX_NUMNODES
+    let mut mask = [0 as libc::c_ulong; 1024 / libc::c_ulong::BITS as usize];
+    let bits = libc::c_ulong::BITS as usize;
+    if node >= mask.len() * bits {
+        return Err(std::io::Error::other(format!("node {node} out of range")));
+    }
+    mask[node / bits] |= 1 << (node % bits);
+    // SAFETY: the mask is as long as the node count given, plus the one
+    // the kernel leaves out
+    let set = unsafe {
+        libc::syscall(
+            libc::SYS_set_mempolicy,
+            libc::MPOL_PREFERRED,
+            mask.as_ptr(),
+            mask.len() * bits + 1,
+        )
+    };
+    if set != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
 }
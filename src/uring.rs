@@ -0,0 +1,308 @@
+//! Connection layer built on io_uring through `tokio-uring`, enabled with the
+//! `io-uring` cargo feature (Linux only).
+//!
+//! It mirrors `connection.rs` task for task and reuses its frame dispatch and
+//! reply ordering, so parsing, routing and the workers are untouched. The only
+//! difference is that io_uring owns the buffers while the kernel reads into or
+//! writes out of them, so they are moved into each call and handed back.
+
+use std::{
+    os::fd::{AsRawFd, BorrowedFd},
+    rc::Rc,
+    sync::Arc,
+    time::Duration,
+};
+
+use bytes::{Bytes, BytesMut};
+use socket2::SockRef;
+use tokio::sync::{
+    mpsc::{self, Receiver, Sender},
+    Semaphore,
+};
+use tokio_uring::{
+    buf::IoBuf,
+    net::{TcpListener, TcpStream},
+};
+use tracing::{debug, info, info_span, warn, Instrument};
+
+use crate::{
+    config::{ClientClass, Config},
+    connection::{
+        cork, dispatch_frames, is_resource_exhausted, is_transient_accept_error,
+        over_query_buffer_limit, read_or_stop, set_keepalive, shrink_read_buffer, Client,
+        OutputLimitTracker, ReplyQueue, ReservedFd, ACCEPT_BACKOFF, MAX_CLIENTS_REPLY,
+        MAX_IN_FLIGHT, OUTPUT_LIMIT_CHECK_INTERVAL, READ_BUFFER_SIZE, REPLY_CHANNEL_CAPACITY,
+    },
+    daemon::{notify_supervisor, shutdown_signal},
+    hooks,
+    message::{ResponseMessage, WorkerMessage},
+    snapshot::Unanswered,
+    stats::{admit_client, ServerStats, STATS},
+};
+
+/// Runs the accept loop on an io_uring runtime on the current thread, until a
+/// shutdown signal arrives. `io-threads` isn't supported here.
+pub fn spawn_io(router: Arc<Vec<Sender<WorkerMessage>>>, config: &Config) -> std::io::Result<()> {
+    if config.io_threads > 1 {
+        warn!("io-threads is ignored with io_uring, serving clients on one thread");
+    }
+    let config = Rc::new(config.clone());
+
+    tokio_uring::start(async move {
+        let listener = TcpListener::bind(config.addr())?;
+        info!("Listening on port {} (io_uring)", config.port);
+        notify_supervisor(config.supervised, "READY=1");
+
+        tokio::select! {
+            result = accept_loop(listener, router, config) => result,
+            _ = shutdown_signal() => {
+                info!("Received shutdown signal, exiting");
+                Ok(())
+            }
+        }
+    })
+}
+
+async fn accept_loop(
+    listener: TcpListener,
+    router: Arc<Vec<Sender<WorkerMessage>>>,
+    config: Rc<Config>,
+) -> std::io::Result<()> {
+    let mut reserve = ReservedFd::new();
+    loop {
+        let (stream, addr) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(err) => {
+                if is_transient_accept_error(&err) {
+                    continue;
+                }
+                warn!("Error accepting connection: {}", err);
+                if is_resource_exhausted(&err) && reserve.release() {
+                    if let Ok(Ok(_)) = tokio::time::timeout(ACCEPT_BACKOFF, listener.accept()).await
+                    {
+                        ServerStats::incr(&STATS.rejected_connections, 1);
+                    }
+                    reserve.restore();
+                }
+                tokio::time::sleep(ACCEPT_BACKOFF).await;
+                continue;
+            }
+        };
+        let Some(slot) = admit_client(config.maxclients) else {
+            tokio_uring::spawn(async move {
+                let _ = stream.write_all(MAX_CLIENTS_REPLY).await;
+            });
+            continue;
+        };
+
+        let client = Client {
+            id: slot.id(),
+            addr,
+        };
+        let span = info_span!("client", id = client.id, %addr);
+        if let Some(refusal) = hooks::connect(&client) {
+            span.in_scope(|| debug!("Connection refused by hook"));
+            tokio_uring::spawn(async move {
+                let _ = stream.write_all(refusal).await;
+            });
+            continue;
+        }
+
+        let router = router.clone();
+        let config = config.clone();
+        tokio_uring::spawn(
+            async move {
+                debug!("Accepted connection");
+                let result = handle_connection(stream, client, &router, &config).await;
+                match &result {
+                    Ok(()) => debug!("Client closed connection"),
+                    Err(e) if e.kind() == std::io::ErrorKind::ConnectionReset => {
+                        debug!("Client reset connection")
+                    }
+                    Err(e) => warn!("Error handling connection: {:?}", e),
+                }
+                hooks::disconnect(&client, result.as_ref().err());
+                drop(slot);
+            }
+            .instrument(span),
+        );
+    }
+}
+
+async fn handle_connection(
+    stream: TcpStream,
+    client: Client,
+    router: &[Sender<WorkerMessage>],
+    config: &Config,
+) -> std::io::Result<()> {
+    {
+        // SAFETY: the fd is owned by `stream`, which outlives this borrow.
+        let fd = unsafe { BorrowedFd::borrow_raw(stream.as_raw_fd()) };
+        let socket = SockRef::from(&fd);
+        socket.set_tcp_nodelay(true)?;
+        if config.tcp_keepalive > 0 {
+            set_keepalive(socket, Duration::from_secs(config.tcp_keepalive))?;
+        }
+    }
+
+    // reads and writes both take &self, so the two tasks just share the stream
+    let stream = Rc::new(stream);
+    let (tx, rx) = mpsc::channel(REPLY_CHANNEL_CAPACITY);
+    let in_flight = Rc::new(Semaphore::new(MAX_IN_FLIGHT));
+    let unanswered = Rc::new(Unanswered::new(router.len()));
+
+    let limit = OutputLimitTracker::new(
+        config
+            .client_output_buffer_limit
+            .for_class(ClientClass::Normal),
+    );
+    let writer_stream = stream.clone();
+    let writer_in_flight = in_flight.clone();
+    let writer_unanswered = unanswered.clone();
+    tokio_uring::spawn(
+        async move {
+            let result = writer_task(
+                writer_stream,
+                rx,
+                limit,
+                &writer_in_flight,
+                &writer_unanswered,
+            )
+            .await;
+            writer_in_flight.close();
+            result
+        }
+        .in_current_span(),
+    );
+
+    reader_task(
+        &stream,
+        tx,
+        &in_flight,
+        &unanswered,
+        &client,
+        router,
+        config,
+    )
+    .await
+}
+
+async fn writer_task(
+    stream: Rc<TcpStream>,
+    mut rx: Receiver<ResponseMessage>,
+    mut limit: OutputLimitTracker,
+    in_flight: &Semaphore,
+    unanswered: &Unanswered,
+) -> std::io::Result<()> {
+    let mut queue = ReplyQueue::new();
+    let mut write_buffer = BytesMut::with_capacity(64 * 1024);
+    let mut chunks: Vec<Bytes> = Vec::new();
+
+    let mut receiving = true;
+    loop {
+        // replies taken in during the last write may be ready already
+        if !queue.has_ready() {
+            let Some(msg) = rx.recv().await else {
+                break;
+            };
+            queue.insert(msg);
+        }
+
+        let replies = cork(&mut rx, &mut queue, &mut write_buffer, &mut chunks).await;
+        unanswered.answered_below(queue.next_seq());
+
+        let mut unwritten = write_buffer.len() + chunks.iter().map(Bytes::len).sum::<usize>();
+        if limit.check(&queue, unwritten) {
+            return stream.shutdown(std::net::Shutdown::Both);
+        }
+
+        // large bulk strings go out as their own buffers, still without copying
+        if !write_buffer.is_empty() {
+            chunks.push(write_buffer.split().freeze());
+        }
+        for chunk in chunks.drain(..) {
+            let len = chunk.len();
+            let write = stream.write_all(chunk);
+            tokio::pin!(write);
+            // a client that stops reading leaves the write pending, so the
+            // limits are checked on the replies that arrive meanwhile and on
+            // a timer, counting the whole chunk as unwritten
+            let mut check = tokio::time::interval_at(
+                tokio::time::Instant::now() + OUTPUT_LIMIT_CHECK_INTERVAL,
+                OUTPUT_LIMIT_CHECK_INTERVAL,
+            );
+            loop {
+                tokio::select! {
+                    (res, _) = &mut write => {
+                        res?;
+                        break;
+                    }
+                    msg = rx.recv(), if receiving && !limit.is_unlimited() => match msg {
+                        Some(msg) => queue.insert(msg),
+                        None => receiving = false,
+                    },
+                    _ = check.tick(), if !limit.is_unlimited() => {}
+                }
+                if limit.check(&queue, unwritten) {
+                    return stream.shutdown(std::net::Shutdown::Both);
+                }
+            }
+            unwritten -= len;
+            ServerStats::incr(&STATS.total_net_output_bytes, len as u64);
+        }
+        in_flight.add_permits(replies);
+    }
+
+    stream.shutdown(std::net::Shutdown::Write)
+}
+
+async fn reader_task(
+    stream: &TcpStream,
+    tx: Sender<ResponseMessage>,
+    in_flight: &Semaphore,
+    unanswered: &Unanswered,
+    client: &Client,
+    router: &[Sender<WorkerMessage>],
+    config: &Config,
+) -> std::io::Result<()> {
+    let idle_timeout = (config.timeout > 0).then(|| Duration::from_secs(config.timeout));
+    let mut read_buffer = BytesMut::with_capacity(READ_BUFFER_SIZE);
+
+    let mut seq: u64 = 0;
+    loop {
+        read_buffer.reserve(1024);
+        shrink_read_buffer(&mut read_buffer);
+
+        // the kernel fills the spare capacity after what we already hold
+        let filled = read_buffer.len();
+        let spare = read_buffer.slice(filled..);
+        let Some((res, spare)) = read_or_stop(stream.read(spare), idle_timeout, &tx).await else {
+            break;
+        };
+        read_buffer = spare.into_inner();
+        let read = res?;
+        if read == 0 {
+            break;
+        }
+        ServerStats::incr(&STATS.total_net_input_bytes, read as u64);
+
+        if !dispatch_frames(
+            &mut read_buffer,
+            &mut seq,
+            &tx,
+            in_flight,
+            unanswered,
+            client,
+            router,
+        )
+        .await
+        {
+            break;
+        }
+        if over_query_buffer_limit(&read_buffer, config) {
+            break;
+        }
+    }
+
+    Ok(())
+}
@@ -1,23 +1,280 @@
-use tokio::{runtime::Builder, sync::mpsc::UnboundedReceiver};
+use std::{
+    sync::{atomic::Ordering, Arc, Barrier},
+    time::{Duration, Instant},
+};
+
+use bytes::Bytes;
+use tokio::{
+    runtime::Builder,
+    sync::mpsc::{Receiver, Sender},
+    task,
+    time::{self, MissedTickBehavior},
+};
 
 use crate::{
-    handler::process_command,
+    bigkeys::{self, BigKeysScan},
+    config::{Config, WorkerPanic},
+    connection::{accept_loop, reuseport_listener},
+    crash,
+    defrag::{Defragger, DEFRAG_INTERVAL},
+    evict::MemoryLimit,
+    handler::{command_name, denies_oom, process_command, OOM_ERROR},
     kv::KvStore,
-    message::{ResponseMessage, WorkerMessage},
+    loader::Loads,
+    message::{ResponseMessage, ResponseValue, WorkerMessage},
+    router::command_keys,
+    snapshot::SnapshotWriter,
+    stats::{ServerStats, WorkerStats, STATS},
 };
 
-pub fn worker_main(_worker_id: usize, mut rx: UnboundedReceiver<WorkerMessage>) {
-    let kv = KvStore::new();
+pub fn worker_main(worker_id: usize, rx: Receiver<WorkerMessage>, config: Arc<Config>) {
+    worker_main_snapshot(worker_id, rx, config, None)
+}
 
+/// Runs a worker like `worker_main`, publishing its shard to `snapshot`
+/// after every batch if given.
+pub(crate) fn worker_main_snapshot(
+    worker_id: usize,
+    rx: Receiver<WorkerMessage>,
+    config: Arc<Config>,
+    snapshot: Option<SnapshotWriter>,
+) {
     let runtime = Builder::new_current_thread().enable_all().build().unwrap();
 
-    runtime.block_on(async move {
-        while let Some(msg) = rx.recv().await {
-            let response = process_command(&kv, msg.response_value);
-            let _ = msg.tx.send(ResponseMessage {
+    runtime.block_on(worker_loop(worker_id, rx, &config, snapshot))
+}
+
+/// Runs a worker that also owns a `SO_REUSEPORT` listener on the configured
+/// address, so the
+/// connections it accepts are served by this thread's runtime. Waits on
+/// `bound` once the listener is up.
+pub fn worker_main_reuseport(
+    worker_id: usize,
+    rx: Receiver<WorkerMessage>,
+    router: Arc<Vec<Sender<WorkerMessage>>>,
+    config: Arc<Config>,
+    bound: Arc<Barrier>,
+    snapshot: Option<SnapshotWriter>,
+) {
+    let runtime = Builder::new_current_thread().enable_all().build().unwrap();
+    let local = task::LocalSet::new();
+
+    local.block_on(&runtime, async move {
+        let addr = config.addr();
+        let listener = match reuseport_listener(addr) {
+            Ok(listener) => listener,
+            Err(err) => {
+                tracing::error!("worker {worker_id} failed to bind {addr}: {err}");
+                std::process::exit(1);
+            }
+        };
+        task::spawn_local(accept_loop(listener, router, config.clone()));
+        bound.wait();
+
+        worker_loop(worker_id, rx, &config, snapshot).await
+    })
+}
+
+/// Buckets of a resizing shard moved after each batch, on top of the one
+/// every write moves, so a read-heavy shard still finishes rehashing.
+const REHASH_BUCKETS: usize = 100;
+
+/// How often an idle worker wakes to remove keys whose TTL passed, like
+/// Redis' active expire cycle at the default `hz` of 10.
+const ACTIVE_EXPIRE_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Keys whose TTL passed removed after each batch and on each
+/// `ACTIVE_EXPIRE_INTERVAL`, at most, so a mass expiry doesn't hold up the
+/// mailbox. The expiry index hands them out soonest first, so none are
+/// looked at in vain.
+const ACTIVE_EXPIRE_KEYS: usize = 200;
+
+/// Serves the worker's mailbox. Each wakeup pops everything queued, up to
+/// `worker_batch_size`, in one go and runs it back to back, so the replies of
+/// a pipeline reach the writer together and it flushes them in one write; an
+/// idle worker parks on the channel's waker rather than spinning. With
+/// `activedefrag` on, it also wakes every `DEFRAG_INTERVAL` to defragment,
+/// and while it holds keys with a TTL, every `ACTIVE_EXPIRE_INTERVAL` to
+/// remove those that expired.
+/// It counts the keys of each command for `HOTKEYS` and, with
+/// `latency-tracking` on, times it into its histograms; both are locked for
+/// the length of the batch rather than per command. `MEMORY BIGKEYS` scans
+/// take a step after every batch, and whenever the mailbox is empty. `GET`s
+/// of missing keys a loader covers wait for it, see `loader`. A command that
+/// panics is answered with an error, see `crash`. With `snapshot`, the
+/// shard's changes are published to it whenever they are to INFO, and a
+/// batch's replies only sent once its writes are.
+pub(crate) async fn worker_loop(
+    worker_id: usize,
+    mut rx: Receiver<WorkerMessage>,
+    config: &Config,
+    mut snapshot: Option<SnapshotWriter>,
+) {
+    crash::set_worker(worker_id);
+    let mut kv = new_shard(config, snapshot.is_some());
+    let mut memory = MemoryLimit::new(config);
+    let mut defrag = Defragger::new(config);
+    let mut defrag_timer = time::interval(DEFRAG_INTERVAL);
+    defrag_timer.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let mut expire_timer = time::interval(ACTIVE_EXPIRE_INTERVAL);
+    expire_timer.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let batch_size = config.worker_batch_size;
+    let mut batch = Vec::with_capacity(batch_size);
+    let stats = STATS.worker(worker_id, rx.max_capacity());
+    let mut scans = Vec::new();
+    let mut loads = Loads::new();
+    let mut replies = Vec::new();
+
+    loop {
+        let received = tokio::select! {
+            received = rx.recv_many(&mut batch, batch_size) => received,
+            _ = defrag_timer.tick(), if defrag.enabled() => {
+                defrag.cycle(&mut kv);
+                continue;
+            }
+            _ = expire_timer.tick(), if kv.volatile_keys() > 0 => {
+                kv.expire_keys(ACTIVE_EXPIRE_KEYS);
+                publish(&mut kv, &mut memory, &stats, &mut snapshot);
+                continue;
+            }
+            (key, loaded) = loads.next() => {
+                loads.finish(&mut kv, &mut memory, key, loaded);
+                publish(&mut kv, &mut memory, &stats, &mut snapshot);
+                continue;
+            }
+            _ = task::yield_now(), if !scans.is_empty() => {
+                step_scans(&mut scans, &mut kv);
+                continue;
+            }
+        };
+        if received == 0 {
+            break;
+        }
+        ServerStats::incr(&stats.commands_processed, batch.len() as u64);
+        stats.queue_depth.store(rx.len() as u64, Ordering::Relaxed);
+        let mut latencies = config.latency_tracking.then(|| stats.latencies());
+        let mut hot_keys = stats.hot_keys();
+        for msg in batch.drain(..) {
+            let (key, touched) = match &msg.response_value {
+                ResponseValue::Array(Some(items)) => {
+                    command_keys(items).for_each(|key| hot_keys.record(key));
+                    // only kept for dropping them should the command panic
+                    let touched: Vec<Bytes> = match config.worker_panic {
+                        WorkerPanic::Restart => command_keys(items).cloned().collect(),
+                        WorkerPanic::Shutdown => Vec::new(),
+                    };
+                    (command_keys(items).next().cloned(), touched)
+                }
+                _ => (None, Vec::new()),
+            };
+            if let Some(count) = bigkeys::requested_count(&msg.response_value) {
+                // the router already checked the count
+                let count = count.unwrap_or(bigkeys::DEFAULT_COUNT);
+                scans.push(BigKeysScan::new(msg, count));
+                continue;
+            }
+            let Some(msg) = loads.wait(&kv, msg) else {
+                continue;
+            };
+            let name = command_name(&msg.response_value);
+            let timer = latencies
+                .as_ref()
+                .and(name)
+                .map(|command| (command, Instant::now()));
+            let response = {
+                let _execute = msg.trace.stage("execute");
+                if denies_oom(&msg.response_value) && !memory.make_room(&mut kv) {
+                    ResponseValue::Error(OOM_ERROR.into())
+                } else if let Some(response) = crash::catch_command(
+                    name.unwrap_or("unknown"),
+                    key.as_ref(),
+                    config.worker_panic,
+                    || process_command(&mut kv, msg.response_value),
+                ) {
+                    response
+                } else {
+                    if config.worker_panic == WorkerPanic::Restart {
+                        let dropped = touched.iter().filter(|key| kv.discard(key)).count();
+                        tracing::warn!(
+                            "Dropped {dropped} keys '{}' may have left half updated when it panicked",
+                            name.unwrap_or("unknown")
+                        );
+                    }
+                    ResponseValue::Error(crash::PANIC_ERROR.into())
+                }
+            };
+            if let (Some(latencies), Some((command, started))) = (latencies.as_mut(), timer) {
+                latencies.record(command, started.elapsed().as_nanos() as u64);
+            }
+            let reply = ResponseMessage {
                 seq: msg.seq,
                 response_value: response,
-            });
+                trace: msg.trace,
+            };
+            if snapshot.is_some() {
+                replies.push((msg.tx, reply));
+            } else {
+                msg.tx.send(reply);
+            }
         }
-    })
+        drop(latencies);
+        drop(hot_keys);
+        step_scans(&mut scans, &mut kv);
+        kv.rehash(REHASH_BUCKETS);
+        kv.expire_keys(ACTIVE_EXPIRE_KEYS);
+        publish(&mut kv, &mut memory, &stats, &mut snapshot);
+        // once what they wrote can be read from the snapshot
+        for (tx, reply) in replies.drain(..) {
+            tx.send(reply);
+        }
+    }
+    stats.record_memory(&Default::default());
+    stats.expires.store(0, Ordering::Relaxed);
+}
+
+/// An empty shard, for a worker starting, which records its changes for a
+/// snapshot if `tracked`.
+fn new_shard(config: &Config, tracked: bool) -> KvStore {
+    let mut kv = KvStore::from_config(config);
+    if tracked {
+        kv.track_changes();
+        // readers drop what they had of the old shard
+        kv.flush(false);
+    }
+    kv
+}
+
+/// Publishes the shard's memory usage and keyspace counters for `maxmemory`
+/// and INFO, and its changes to `snapshot`.
+fn publish(
+    kv: &mut KvStore,
+    memory: &mut MemoryLimit,
+    stats: &WorkerStats,
+    snapshot: &mut Option<SnapshotWriter>,
+) {
+    if let Some(snapshot) = snapshot {
+        snapshot.publish(kv);
+    }
+    memory.publish(kv);
+    stats.record_memory(&kv.type_usage());
+    stats
+        .keyspace_hits
+        .store(kv.keyspace_hits(), Ordering::Relaxed);
+    stats
+        .keyspace_misses
+        .store(kv.keyspace_misses(), Ordering::Relaxed);
+    stats
+        .expired_keys
+        .store(kv.expired_keys(), Ordering::Relaxed);
+    stats
+        .expires
+        .store(kv.volatile_keys() as u64, Ordering::Relaxed);
+    stats.avg_ttl.store(kv.avg_ttl(), Ordering::Relaxed);
+}
+
+/// Moves every `MEMORY BIGKEYS` scan along by a step, replying to those done.
+fn step_scans(scans: &mut Vec<BigKeysScan>, kv: &mut KvStore) {
+    for scan in scans.extract_if(.., |scan| scan.step(kv)) {
+        scan.finish();
+    }
 }
@@ -0,0 +1,128 @@
+#![cfg(feature = "server")]
+
+use rustis::{
+    aof::{check, truncate},
+    rdb::crc64,
+};
+
+/// A command as an AOF holds it.
+fn command(args: &[&str]) -> Vec<u8> {
+    let mut out = format!("*{}\r\n", args.len()).into_bytes();
+    for arg in args {
+        out.extend(format!("${}\r\n{arg}\r\n", arg.len()).into_bytes());
+    }
+    out
+}
+
+fn aof(commands: &[&[&str]]) -> Vec<u8> {
+    commands.iter().flat_map(|args| command(args)).collect()
+}
+
+#[test]
+fn test_valid_aof() {
+    let mut data = aof(&[&["SET", "k", "v"], &["MULTI"], &["INCR", "n"], &["EXEC"]]);
+    data.extend(b"#TS:1700000000\r\n");
+    data.extend(command(&["DEL", "k"]));
+    let result = check(&data);
+    assert!(result.is_valid(), "{:?}", result.error);
+    assert_eq!(result.commands, 5);
+    assert_eq!(result.ok_up_to, data.len());
+    assert_eq!(result.diff(), 0);
+    // 7 lines for SET, 3 for MULTI, 5 for INCR, 3 for EXEC, 1, then 5
+    assert_eq!(result.ok_up_to_line, 24);
+
+    assert!(check(b"").is_valid());
+}
+
+#[test]
+fn test_truncated_aof() {
+    let good = aof(&[&["SET", "k", "v"], &["SET", "k2", "v2"]]);
+    let mut data = good.clone();
+    data.extend(&command(&["SET", "k3", "value"])[..20]);
+    let result = check(&data);
+    assert!(!result.is_valid());
+    assert_eq!(result.ok_up_to, good.len());
+    assert_eq!(result.ok_up_to_line, 14);
+    assert_eq!(result.commands, 2);
+    assert_eq!(result.diff(), 20);
+    assert_eq!(
+        result.error.unwrap().message,
+        "Unexpected EOF reading an argument"
+    );
+}
+
+#[test]
+fn test_corrupt_aof() {
+    let first = command(&["SET", "k", "v"]);
+    let mut data = first.clone();
+    data.extend(b"xyz\r\n");
+    data.extend(command(&["SET", "k2", "v2"]));
+    let result = check(&data);
+    assert_eq!(result.ok_up_to, first.len());
+    let error = result.error.unwrap();
+    assert_eq!(error.message, "Expected prefix '*', got: 'x'");
+
+    let mut data = first.clone();
+    data.extend(b"*1\r\n$3\r\nDELX\r\n");
+    assert_eq!(
+        check(&data).error.unwrap().message,
+        "Expected \\r\\n after an argument"
+    );
+}
+
+#[test]
+fn test_unbalanced_multi() {
+    let before = aof(&[&["SET", "k", "v"]]);
+    let mut data = before.clone();
+    data.extend(aof(&[&["MULTI"], &["SET", "a", "1"]]));
+    let result = check(&data);
+    // the transaction is cut whole
+    assert_eq!(result.ok_up_to, before.len());
+    assert_eq!(
+        result.error.unwrap().message,
+        "Reached EOF before reading EXEC for MULTI"
+    );
+
+    let data = aof(&[&["MULTI"], &["MULTI"]]);
+    assert_eq!(check(&data).error.unwrap().message, "Unexpected MULTI");
+    let data = aof(&[&["SET", "k", "v"], &["EXEC"]]);
+    let result = check(&data);
+    assert_eq!(result.error.unwrap().message, "Unexpected EXEC");
+    assert_eq!(result.commands, 1);
+}
+
+#[test]
+fn test_rdb_preamble() {
+    let mut data = b"REDIS0011".to_vec();
+    data.extend([0, 1, b'k', 1, b'v', 0xFF]);
+    let crc = crc64(0, &data);
+    data.extend(crc.to_le_bytes());
+    let preamble = data.len();
+    data.extend(command(&["SET", "k2", "v2"]));
+    let result = check(&data);
+    assert!(result.is_valid(), "{:?}", result.error);
+    assert_eq!(result.commands, 1);
+
+    data[preamble - 1] ^= 0xFF;
+    let result = check(&data);
+    assert_eq!(
+        result.error.unwrap().message,
+        "RDB preamble checksum mismatch"
+    );
+}
+
+#[test]
+fn test_fix_truncates() {
+    let path = std::env::temp_dir().join(format!("rustis-aof-{}.aof", std::process::id()));
+    let good = aof(&[&["SET", "k", "v"]]);
+    let mut data = good.clone();
+    data.extend(b"*2\r\n$3\r\nGET");
+    std::fs::write(&path, &data).unwrap();
+
+    let result = check(&std::fs::read(&path).unwrap());
+    truncate(&path, result.ok_up_to).unwrap();
+    let fixed = std::fs::read(&path).unwrap();
+    assert_eq!(fixed, good);
+    assert!(check(&fixed).is_valid());
+    std::fs::remove_file(&path).unwrap();
+}
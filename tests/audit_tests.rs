@@ -0,0 +1,93 @@
+#![cfg(feature = "server")]
+
+use std::{
+    fs,
+    time::{Duration, UNIX_EPOCH},
+};
+
+use rustis::{
+    audit::{entry, RotatingFile, MAX_ARGS_LEN},
+    connection::Client,
+    message::ResponseValue,
+};
+
+fn command(args: &[&[u8]]) -> ResponseValue {
+    ResponseValue::Array(Some(
+        args.iter()
+            .map(|arg| ResponseValue::BulkString(Some(arg.to_vec().into())))
+            .collect(),
+    ))
+}
+
+fn client() -> Client {
+    Client {
+        id: 7,
+        addr: "127.0.0.1:50312".parse().unwrap(),
+    }
+}
+
+#[test]
+fn test_entry() {
+    let time = UNIX_EPOCH + Duration::from_millis(1_792_144_800_123);
+    assert_eq!(
+        entry(
+            time,
+            &client(),
+            &command(&[b"set", b"key", b"a \"b\"\r\n\x00"])
+        )
+        .unwrap(),
+        "16 Oct 2026 10:00:00.123 id=7 addr=127.0.0.1:50312 user=default \
+         \"set\" \"key\" \"a \\\"b\\\"\\r\\n\\x00\""
+    );
+    assert!(entry(time, &client(), &command(&[b"GET", b"key"])).is_none());
+    assert!(entry(time, &client(), &command(&[b"PING"])).is_none());
+}
+
+#[test]
+fn test_entry_truncated() {
+    let value = vec![b'v'; 10 * MAX_ARGS_LEN];
+    let line = entry(UNIX_EPOCH, &client(), &command(&[b"SET", b"key", &value])).unwrap();
+    let (_, args) = line.split_once("user=default").unwrap();
+    assert_eq!(args.len(), MAX_ARGS_LEN + "...".len());
+    assert!(args.starts_with(" \"SET\" \"key\" \"vvv"));
+    assert!(args.ends_with("v..."));
+
+    // arguments past the cap are left out
+    let mut args: Vec<&[u8]> = vec![b"DEL"];
+    args.extend(std::iter::repeat_n(&b"k"[..], MAX_ARGS_LEN));
+    let line = entry(UNIX_EPOCH, &client(), &command(&args)).unwrap();
+    assert!(line.ends_with("..."));
+    assert!(line.len() < 2 * MAX_ARGS_LEN);
+}
+
+#[test]
+fn test_rotating_file() {
+    let dir = std::env::temp_dir().join(format!("rustis-audit-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("audit.log");
+
+    // room for two 9-byte lines per file
+    let mut file = RotatingFile::open(&path, 20, 2).unwrap();
+    for n in 0..7 {
+        file.write_line(&format!("line {n:03}")).unwrap();
+    }
+    file.flush().unwrap();
+    drop(file);
+
+    let read = |name: &str| fs::read_to_string(dir.join(name)).unwrap();
+    assert_eq!(read("audit.log"), "line 006\n");
+    assert_eq!(read("audit.log.1"), "line 004\nline 005\n");
+    assert_eq!(read("audit.log.2"), "line 002\nline 003\n");
+    assert!(!dir.join("audit.log.3").exists());
+
+    // reopening appends, counting what is already there
+    let mut file = RotatingFile::open(&path, 20, 2).unwrap();
+    file.write_line("line 007").unwrap();
+    file.write_line("line 008").unwrap();
+    file.flush().unwrap();
+    assert_eq!(read("audit.log"), "line 008\n");
+    assert_eq!(read("audit.log.1"), "line 006\nline 007\n");
+
+    fs::remove_dir_all(&dir).unwrap();
+}
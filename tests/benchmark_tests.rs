@@ -0,0 +1,125 @@
+#![cfg(feature = "server")]
+
+use std::{sync::Arc, time::Duration};
+
+use bytes::BytesMut;
+use rustis::{
+    benchmark::{run, BenchmarkConfig, Report, Test},
+    config::Config,
+    connection::{handle_connection, Client},
+    worker::worker_main,
+};
+use tokio::{net::TcpListener, sync::mpsc, task::LocalSet};
+
+fn args(args: &[&str]) -> Result<BenchmarkConfig, String> {
+    BenchmarkConfig::from_args(args.iter().map(|arg| arg.to_string()))
+}
+
+#[test]
+fn test_benchmark_args() {
+    assert_eq!(args(&[]).unwrap(), BenchmarkConfig::default());
+
+    let config = args(&[
+        "-h",
+        "10.0.0.1",
+        "-p",
+        "6380",
+        "-c",
+        "8",
+        "-n",
+        "500",
+        "-P",
+        "16",
+        "-d",
+        "64",
+        "-r",
+        "1000",
+        "-t",
+        "set,Get,LPUSH",
+        "-q",
+    ])
+    .unwrap();
+    assert_eq!(config.host, "10.0.0.1");
+    assert_eq!(config.port, 6380);
+    assert_eq!(config.clients, 8);
+    assert_eq!(config.requests, 500);
+    assert_eq!(config.pipeline, 16);
+    assert_eq!(config.data_size, 64);
+    assert_eq!(config.keyspace, Some(1000));
+    assert_eq!(config.tests, vec![Test::Set, Test::Get, Test::Lpush]);
+    assert!(config.quiet);
+
+    assert!(args(&["-c", "0"]).is_err());
+    assert!(args(&["-P"]).is_err());
+    assert!(args(&["-t", "set,flushall"]).is_err());
+    assert!(args(&["--bogus"]).is_err());
+}
+
+#[test]
+fn test_benchmark_encode() {
+    let mut buf = BytesMut::new();
+    Test::Set.encode(&mut buf, 42, b"xyz");
+    Test::Ping.encode(&mut buf, 0, b"xyz");
+    assert_eq!(
+        &buf[..],
+        b"*3\r\n$3\r\nSET\r\n$16\r\nkey:000000000042\r\n$3\r\nxyz\r\n*1\r\n$4\r\nPING\r\n"
+    );
+}
+
+#[test]
+fn test_benchmark_percentiles() {
+    let latencies = (1..=100).rev().map(Duration::from_millis).collect();
+    let report = Report::new(Test::Get, 0, Duration::from_secs(2), latencies);
+
+    assert_eq!(report.requests, 100);
+    assert_eq!(report.requests_per_sec(), 50.0);
+    assert_eq!(report.percentile(0.0), Duration::from_millis(1));
+    assert_eq!(report.percentile(50.0), Duration::from_millis(50));
+    assert_eq!(report.percentile(99.0), Duration::from_millis(99));
+    assert_eq!(report.percentile(100.0), Duration::from_millis(100));
+    assert_eq!(report.average(), Duration::from_micros(50_500));
+}
+
+#[tokio::test]
+async fn test_benchmark_run() {
+    let (tx, rx) = mpsc::channel(64);
+    std::thread::spawn(move || worker_main(0, rx, Arc::default()));
+    let router = vec![tx];
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+
+    let local = LocalSet::new();
+    local
+        .run_until(async move {
+            tokio::task::spawn_local(async move {
+                let router = Arc::new(router);
+                loop {
+                    let (stream, addr) = listener.accept().await.unwrap();
+                    let router = router.clone();
+                    tokio::task::spawn_local(async move {
+                        let _ = handle_connection(
+                            stream,
+                            Client { id: 1, addr },
+                            &router,
+                            &Config::default(),
+                        )
+                        .await;
+                    });
+                }
+            });
+
+            let config =
+                args(&["-p", &port.to_string(), "-c", "4", "-n", "1001", "-P", "8"]).unwrap();
+            let report = run(&config, Test::Lpush).await.unwrap();
+            assert_eq!(report.requests, 1001);
+            // a build without lists doesn't know LPUSH
+            let errors = if cfg!(feature = "lists") { 0 } else { 1001 };
+            assert_eq!(report.errors, errors);
+
+            // INCR isn't implemented, so every reply is an error
+            let report = run(&config, Test::Incr).await.unwrap();
+            assert_eq!(report.requests, 1001);
+            assert_eq!(report.errors, 1001);
+        })
+        .await;
+}
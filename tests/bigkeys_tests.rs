@@ -0,0 +1,162 @@
+#![cfg(all(feature = "server", feature = "lists", feature = "sets"))]
+
+use std::sync::Arc;
+
+use bytes::Bytes;
+use rustis::{
+    bigkeys::merge,
+    message::{ResponseMessage, ResponseValue, WorkerMessage},
+    router::route_message,
+    telemetry::CommandTrace,
+    worker::worker_main,
+};
+use tokio::sync::mpsc::{self, Receiver, Sender};
+
+fn bulk(s: &str) -> ResponseValue {
+    ResponseValue::BulkString(Some(Bytes::copy_from_slice(s.as_bytes())))
+}
+
+fn command(args: &[&str]) -> ResponseValue {
+    ResponseValue::Array(Some(args.iter().map(|arg| bulk(arg)).collect()))
+}
+
+async fn run(
+    workers: &[Sender<WorkerMessage>],
+    writer: &Sender<ResponseMessage>,
+    replies: &mut Receiver<ResponseMessage>,
+    args: &[&str],
+) -> ResponseValue {
+    let permit = writer.clone().reserve_owned().await.unwrap();
+    route_message(workers, command(args), 0, permit, CommandTrace::default()).await;
+    replies.recv().await.unwrap().response_value
+}
+
+/// `(type, key, elements)` of each entry of a `MEMORY BIGKEYS` reply.
+fn listed(reply: ResponseValue) -> Vec<(String, String, i64)> {
+    let ResponseValue::Array(Some(entries)) = reply else {
+        panic!("expected an array, got {reply:?}");
+    };
+    entries
+        .into_iter()
+        .map(|entry| match entry {
+            ResponseValue::Array(Some(fields)) => match fields.as_slice() {
+                [ResponseValue::BulkString(Some(value_type)), ResponseValue::BulkString(Some(key)), ResponseValue::Integer(elements), ResponseValue::Integer(bytes)] =>
+                {
+                    assert!(*bytes as usize > key.len());
+                    (
+                        String::from_utf8_lossy(value_type).into_owned(),
+                        String::from_utf8_lossy(key).into_owned(),
+                        *elements,
+                    )
+                }
+                other => panic!("unexpected entry {other:?}"),
+            },
+            other => panic!("unexpected entry {other:?}"),
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn test_memory_bigkeys() {
+    let mut workers = Vec::new();
+    for id in 0..3 {
+        let (tx, rx) = mpsc::channel(16);
+        std::thread::spawn(move || worker_main(id, rx, Arc::default()));
+        workers.push(tx);
+    }
+    let (writer, mut replies) = mpsc::channel(64);
+
+    // enough small keys that every shard takes several steps to walk
+    let mut mset = vec!["MSET".to_string()];
+    for i in 0..3000 {
+        mset.push(format!("small:{i}"));
+        mset.push("x".to_string());
+    }
+    let mset: Vec<&str> = mset.iter().map(String::as_str).collect();
+    run(&workers, &writer, &mut replies, &mset).await;
+
+    let value = "v".repeat(1000);
+    run(&workers, &writer, &mut replies, &["SET", "big", &value]).await;
+    run(
+        &workers,
+        &writer,
+        &mut replies,
+        &["SET", "bigger", &value.repeat(2)],
+    )
+    .await;
+    run(
+        &workers,
+        &writer,
+        &mut replies,
+        &["SET", "biggest", &value.repeat(3)],
+    )
+    .await;
+    for (key, len) in [("list:a", 5), ("list:b", 50)] {
+        let mut rpush = vec!["RPUSH", key];
+        rpush.extend(std::iter::repeat_n("item", len));
+        run(&workers, &writer, &mut replies, &rpush).await;
+    }
+    run(
+        &workers,
+        &writer,
+        &mut replies,
+        &["SADD", "set", "a", "b", "c"],
+    )
+    .await;
+
+    let reply = run(&workers, &writer, &mut replies, &["MEMORY", "BIGKEYS", "2"]).await;
+    assert_eq!(
+        listed(reply),
+        [
+            ("string".to_string(), "biggest".to_string(), 3000),
+            ("string".to_string(), "bigger".to_string(), 2000),
+            ("list".to_string(), "list:b".to_string(), 50),
+            ("list".to_string(), "list:a".to_string(), 5),
+            ("set".to_string(), "set".to_string(), 3),
+        ]
+    );
+
+    // default count of 5
+    let reply = run(&workers, &writer, &mut replies, &["memory", "bigkeys"]).await;
+    assert_eq!(listed(reply).len(), 5 + 2 + 1);
+
+    for args in [
+        &["MEMORY", "BIGKEYS", "0"][..],
+        &["MEMORY", "BIGKEYS", "many"],
+        &["MEMORY", "BIGKEYS", "1", "2"],
+    ] {
+        let reply = run(&workers, &writer, &mut replies, args).await;
+        assert!(matches!(reply, ResponseValue::Error(_)), "{args:?}");
+    }
+}
+
+#[test]
+fn test_merge() {
+    let entry = |value_type: &str, key: &str, bytes: i64| {
+        ResponseValue::Array(Some(vec![
+            bulk(value_type),
+            bulk(key),
+            ResponseValue::Integer(1),
+            ResponseValue::Integer(bytes),
+        ]))
+    };
+    let merged = merge(
+        vec![
+            entry("set", "s", 10),
+            entry("string", "a", 10),
+            entry("string", "b", 30),
+            entry("string", "c", 20),
+            entry("list", "l", 5),
+        ],
+        2,
+    );
+    assert_eq!(
+        merged,
+        ResponseValue::Array(Some(vec![
+            entry("string", "b", 30),
+            entry("string", "c", 20),
+            entry("list", "l", 5),
+            entry("set", "s", 10),
+        ]))
+    );
+}
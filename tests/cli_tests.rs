@@ -0,0 +1,359 @@
+#![cfg(feature = "server")]
+
+use std::{
+    io::{Read, Write},
+    net::TcpListener,
+    sync::{Arc, OnceLock},
+    thread,
+};
+
+use bytes::{Bytes, BytesMut};
+use rustis::{
+    cli::{
+        format_reply, key_slot, split_line, CliConfig, ClusterConnection, Connection, Output,
+        Redirect, MAX_REDIRECTS,
+    },
+    message::ResponseValue,
+    parser, Server,
+};
+
+fn args(list: &[&str]) -> Vec<String> {
+    list.iter().map(|s| s.to_string()).collect()
+}
+
+fn bulk(s: &str) -> ResponseValue {
+    ResponseValue::BulkString(Some(Bytes::copy_from_slice(s.as_bytes())))
+}
+
+fn formatted(reply: &ResponseValue) -> String {
+    String::from_utf8(format_reply(reply, Output::Formatted)).unwrap()
+}
+
+#[test]
+fn test_cli_config() {
+    let config = CliConfig::from_args(Vec::new()).unwrap();
+    assert_eq!(config, CliConfig::default());
+    assert_eq!(config.prompt(), "127.0.0.1:6379> ");
+
+    let config = CliConfig::from_args(args(&[
+        "-h", "cache", "-p", "6380", "-a", "secret", "-n", "2", "--raw", "SET", "-k", "v",
+    ]))
+    .unwrap();
+    assert_eq!(config.host, "cache");
+    assert_eq!(config.port, 6380);
+    assert_eq!(config.password.as_deref(), Some("secret"));
+    assert_eq!(config.db, 2);
+    assert_eq!(config.output, Some(Output::Raw));
+    // everything after the command's name is its arguments
+    assert_eq!(config.command, args(&["SET", "-k", "v"]));
+    assert_eq!(config.prompt(), "cache:6380[2]> ");
+
+    assert!(CliConfig::from_args(args(&["-p", "port"])).is_err());
+    assert!(CliConfig::from_args(args(&["-n"])).is_err());
+    assert!(CliConfig::from_args(args(&["--pipeline"])).is_err());
+    assert!(CliConfig::from_args(args(&["--pipe"])).unwrap().pipe);
+    assert!(CliConfig::from_args(args(&["-c"])).unwrap().cluster);
+}
+
+#[test]
+fn test_split_line() {
+    assert_eq!(
+        split_line(r#"SET "a key" 'it\'s' "\x41\n""#).unwrap(),
+        vec![
+            b"SET".to_vec(),
+            b"a key".to_vec(),
+            b"it's".to_vec(),
+            b"A\n".to_vec()
+        ]
+    );
+    assert_eq!(split_line("   ").unwrap(), Vec::<Vec<u8>>::new());
+    assert!(split_line(r#"GET "open"#).is_none());
+}
+
+#[test]
+fn test_format_reply() {
+    assert_eq!(formatted(&ResponseValue::SimpleString("OK".into())), "OK");
+    assert_eq!(formatted(&ResponseValue::Integer(3)), "(integer) 3");
+    assert_eq!(formatted(&bulk("a \"b\"\n")), r#""a \"b\"\n""#);
+    assert_eq!(formatted(&ResponseValue::BulkString(None)), "(nil)");
+    assert_eq!(
+        formatted(&ResponseValue::Error("ERR unknown".into())),
+        "(error) ERR unknown"
+    );
+    assert_eq!(
+        formatted(&ResponseValue::Array(Some(Vec::new()))),
+        "(empty array)"
+    );
+
+    let mut items: Vec<ResponseValue> = (1..=10).map(|i| bulk(&i.to_string())).collect();
+    items[1] = ResponseValue::Array(Some(vec![bulk("x"), ResponseValue::Integer(7)]));
+    assert_eq!(
+        formatted(&ResponseValue::Array(Some(items.clone()))),
+        [
+            r#" 1) "1""#,
+            r#" 2) 1) "x""#,
+            r#"    2) (integer) 7"#,
+            r#" 3) "3""#,
+            r#" 4) "4""#,
+            r#" 5) "5""#,
+            r#" 6) "6""#,
+            r#" 7) "7""#,
+            r#" 8) "8""#,
+            r#" 9) "9""#,
+            r#"10) "10""#,
+        ]
+        .join("\n")
+    );
+
+    let attribute = ResponseValue::Attribute {
+        attrs: vec![(bulk("ttl"), ResponseValue::Integer(5))],
+        value: Box::new(bulk("v")),
+    };
+    assert_eq!(formatted(&attribute), "1# \"ttl\" => (integer) 5\n\"v\"");
+
+    let raw = ResponseValue::Array(Some(vec![
+        bulk("a b"),
+        ResponseValue::BulkString(None),
+        ResponseValue::Integer(-1),
+    ]));
+    assert_eq!(format_reply(&raw, Output::Raw), b"a b\n\n-1");
+    assert_eq!(
+        format_reply(&ResponseValue::Error("ERR x".into()), Output::Raw),
+        b"ERR x"
+    );
+}
+
+#[test]
+fn test_connection() {
+    let mut server = Server::builder().port(0).workers(2).build().unwrap();
+    server.start().unwrap();
+    let config = CliConfig {
+        port: server.addr().port(),
+        ..CliConfig::default()
+    };
+
+    let mut connection = Connection::open(&config).unwrap();
+    let reply = connection
+        .command(&split_line("SET greeting 'hello world'").unwrap())
+        .unwrap();
+    assert_eq!(reply, ResponseValue::SimpleString("OK".into()));
+    let reply = connection
+        .command(&split_line("GET greeting").unwrap())
+        .unwrap();
+    assert_eq!(reply, bulk("hello world"));
+
+    // a database the server doesn't have
+    let config = CliConfig { db: 3, ..config };
+    let err = Connection::open(&config).unwrap_err();
+    assert!(err.to_string().starts_with("SELECT failed"));
+
+    server.shutdown();
+}
+
+#[test]
+fn test_pipe() {
+    let mut server = Server::builder().port(0).workers(2).build().unwrap();
+    server.start().unwrap();
+    let config = CliConfig {
+        port: server.addr().port(),
+        ..CliConfig::default()
+    };
+
+    let mut input = Vec::new();
+    for i in 0..10_000 {
+        let (key, value) = (format!("key:{i}"), i.to_string());
+        input.extend(
+            format!(
+                "*3\r\n$3\r\nSET\r\n${}\r\n{key}\r\n${}\r\n{value}\r\n",
+                key.len(),
+                value.len()
+            )
+            .into_bytes(),
+        );
+    }
+    input.extend(b"*2\r\n$3\r\nSET\r\n$1\r\nk\r\n");
+
+    let mut connection = Connection::open(&config).unwrap();
+    let mut errors = Vec::new();
+    let mut sent = false;
+    let report = connection
+        .pipe(
+            &input[..],
+            |message| errors.push(message.to_vec()),
+            || sent = true,
+        )
+        .unwrap();
+    assert!(sent);
+    assert_eq!(report.replies, 10_001);
+    assert_eq!(report.errors, 1);
+    assert_eq!(errors.len(), 1);
+    assert_eq!(
+        errors[0],
+        b"ERR wrong number of arguments for 'set' command"
+    );
+
+    // the connection goes on working after
+    let reply = connection.command(&split_line("DBSIZE").unwrap()).unwrap();
+    assert_eq!(reply, ResponseValue::Integer(10_000));
+
+    server.shutdown();
+}
+
+#[test]
+fn test_key_slot() {
+    assert_eq!(key_slot(b"123456789"), 0x31c3);
+    assert_eq!(key_slot(b"foo"), 12182);
+    assert_eq!(key_slot(b"{user1000}.following"), key_slot(b"user1000"));
+    assert_eq!(key_slot(b"foo{}{bar}"), key_slot(b"foo{}{bar}"));
+    assert_ne!(key_slot(b"foo{}{bar}"), key_slot(b"bar"));
+    assert_eq!(key_slot(b"foo{{bar}}zap"), key_slot(b"{bar"));
+}
+
+#[test]
+fn test_redirect_parse() {
+    assert_eq!(
+        Redirect::parse(b"MOVED 3999 127.0.0.1:6381"),
+        Some(Redirect {
+            slot: 3999,
+            host: "127.0.0.1".to_string(),
+            port: 6381,
+            ask: false,
+        })
+    );
+    assert!(Redirect::parse(b"ASK 3999 ::1:6381").unwrap().ask);
+    assert_eq!(Redirect::parse(b"ASK 1 ::1:6381").unwrap().host, "::1");
+    assert_eq!(Redirect::parse(b"ERR unknown command"), None);
+    assert_eq!(Redirect::parse(b"MOVED 3999"), None);
+    assert_eq!(Redirect::parse(b"MOVED x 127.0.0.1:6381"), None);
+}
+
+/// A node that answers every command on its first connection with
+/// `reply(command)`, returning the commands it got once the client hangs
+/// up.
+fn fake_node(
+    reply: impl Fn(&[Bytes]) -> String + Send + 'static,
+) -> (u16, thread::JoinHandle<Vec<Vec<Bytes>>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let node = thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut buffer = BytesMut::new();
+        let mut commands = Vec::new();
+        let mut chunk = [0; 1024];
+        loop {
+            while let Ok(ResponseValue::Array(Some(items))) = parser::parse(&mut buffer) {
+                let command: Vec<Bytes> = items
+                    .into_iter()
+                    .map(|item| match item {
+                        ResponseValue::BulkString(Some(arg)) => arg,
+                        other => panic!("unexpected argument {other:?}"),
+                    })
+                    .collect();
+                stream.write_all(reply(&command).as_bytes()).unwrap();
+                commands.push(command);
+            }
+            match stream.read(&mut chunk) {
+                Ok(0) | Err(_) => return commands,
+                Ok(read) => buffer.extend_from_slice(&chunk[..read]),
+            }
+        }
+    });
+    (port, node)
+}
+
+#[test]
+fn test_cluster_redirects() {
+    let mut server = Server::builder().port(0).workers(2).build().unwrap();
+    server.start().unwrap();
+    let port = server.addr().port();
+
+    // the node the client starts from moved the key's slot to the server
+    let slot = key_slot(b"greeting");
+    let (moved_port, moved) = fake_node(move |_| format!("-MOVED {slot} 127.0.0.1:{port}\r\n"));
+    let config = CliConfig {
+        port: moved_port,
+        cluster: true,
+        ..CliConfig::default()
+    };
+    let mut cluster = ClusterConnection::open(&config).unwrap();
+    let mut redirects = Vec::new();
+    let reply = cluster
+        .command(&split_line("SET greeting hello").unwrap(), |redirect| {
+            redirects.push(redirect.clone())
+        })
+        .unwrap();
+    assert_eq!(reply, ResponseValue::SimpleString("OK".into()));
+    assert_eq!(redirects.len(), 1);
+    assert_eq!((redirects[0].slot, redirects[0].port), (slot, port));
+    assert!(!redirects[0].ask);
+    // the prompt follows the node that served the reply
+    assert_eq!(cluster.config().prompt(), format!("127.0.0.1:{port}> "));
+
+    // the slot map sends the key straight to its owner now
+    cluster
+        .command(&split_line("SET greeting again").unwrap(), |_| {
+            panic!("redirected")
+        })
+        .unwrap();
+    let reply = cluster
+        .command(&split_line("GET greeting").unwrap(), |_| {
+            panic!("redirected")
+        })
+        .unwrap();
+    assert_eq!(reply, bulk("again"));
+
+    // an ASK is followed after an ASKING, for that command only
+    let (asked_port, asked) = fake_node(|command| match &command[0][..] {
+        b"ASKING" => "+OK\r\n".to_string(),
+        _ => "$8\r\nimported\r\n".to_string(),
+    });
+    let (ask_port, ask) =
+        fake_node(move |_| format!("-ASK {} 127.0.0.1:{asked_port}\r\n", key_slot(b"migrating")));
+    let config = CliConfig {
+        port: ask_port,
+        ..config
+    };
+    let mut cluster = ClusterConnection::open(&config).unwrap();
+    let mut redirects = Vec::new();
+    for _ in 0..2 {
+        let reply = cluster
+            .command(&split_line("GET migrating").unwrap(), |redirect| {
+                redirects.push(redirect.clone())
+            })
+            .unwrap();
+        assert_eq!(reply, bulk("imported"));
+    }
+    assert_eq!(redirects.len(), 2);
+    assert!(redirects.iter().all(|redirect| redirect.ask));
+    drop(cluster);
+    assert_eq!(ask.join().unwrap().len(), 2);
+    let asked = asked.join().unwrap();
+    let names: Vec<&[u8]> = asked.iter().map(|command| &command[0][..]).collect();
+    assert_eq!(names, [&b"ASKING"[..], b"GET", b"ASKING", b"GET"]);
+
+    drop(moved);
+    server.shutdown();
+}
+
+#[test]
+fn test_cluster_redirect_loop() {
+    // a node that keeps sending the client back to itself
+    let own_port = Arc::new(OnceLock::new());
+    let (port, node) = fake_node({
+        let own_port = own_port.clone();
+        move |_| format!("-MOVED 1 127.0.0.1:{}\r\n", own_port.get().unwrap())
+    });
+    own_port.set(port).unwrap();
+    let config = CliConfig {
+        port,
+        cluster: true,
+        ..CliConfig::default()
+    };
+    let mut cluster = ClusterConnection::open(&config).unwrap();
+    let err = cluster
+        .command(&split_line("GET k").unwrap(), |_| {})
+        .unwrap_err();
+    assert_eq!(err.to_string(), "Too many cluster redirections");
+    drop(cluster);
+    assert_eq!(node.join().unwrap().len(), MAX_REDIRECTS + 1);
+}
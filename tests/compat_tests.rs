@@ -0,0 +1,218 @@
+#![cfg(feature = "redis-compat")]
+
+//! Runs the scripts in `tests/compat` against rustis and a real Redis and
+//! compares the replies byte for byte. The Redis is the one at
+//! `RUSTIS_COMPAT_REDIS` (`host:port`), which gets flushed, or else a
+//! `redis-server` on the `PATH` started on a free port; with neither the
+//! test is skipped.
+
+use std::{
+    env, fs,
+    io::{Read, Write},
+    net::{TcpListener, TcpStream},
+    path::{Path, PathBuf},
+    process::{Child, Command, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+use bytes::BytesMut;
+use rustis::{
+    parser::{self, split_quoted_args, BufParseError},
+    Server,
+};
+
+/// A connection reading replies as the bytes the server sent.
+struct Raw {
+    stream: TcpStream,
+    buffer: BytesMut,
+}
+
+impl Raw {
+    fn connect(addr: &str) -> std::io::Result<Self> {
+        let stream = TcpStream::connect(addr)?;
+        stream.set_read_timeout(Some(Duration::from_secs(10)))?;
+        Ok(Self {
+            stream,
+            buffer: BytesMut::new(),
+        })
+    }
+
+    fn command(&mut self, args: &[Vec<u8>]) -> Vec<u8> {
+        let mut request = format!("*{}\r\n", args.len()).into_bytes();
+        for arg in args {
+            request.extend(format!("${}\r\n", arg.len()).into_bytes());
+            request.extend(arg);
+            request.extend(b"\r\n");
+        }
+        self.stream.write_all(&request).unwrap();
+
+        let mut chunk = [0; 16 * 1024];
+        loop {
+            // parsed only to find where the reply ends
+            let mut rest = self.buffer.clone();
+            match parser::parse(&mut rest) {
+                Ok(_) => {
+                    let len = self.buffer.len() - rest.len();
+                    return self.buffer.split_to(len).to_vec();
+                }
+                Err(BufParseError::Incomplete) => {}
+                Err(err) => panic!("unparseable reply: {err:?}"),
+            }
+            let read = self.stream.read(&mut chunk).unwrap();
+            assert_ne!(read, 0, "the server closed the connection");
+            self.buffer.extend_from_slice(&chunk[..read]);
+        }
+    }
+}
+
+/// The `redis-server` this test started, stopped when dropped.
+struct Spawned(Child);
+
+impl Drop for Spawned {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+/// The Redis to compare with, if there is one.
+fn redis() -> Option<(Raw, Option<Spawned>)> {
+    if let Ok(addr) = env::var("RUSTIS_COMPAT_REDIS") {
+        let raw = Raw::connect(&addr).unwrap_or_else(|err| panic!("can't reach {addr}: {err}"));
+        return Some((raw, None));
+    }
+
+    let port = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let child = Command::new("redis-server")
+        .args(["--port", &port.to_string(), "--bind", "127.0.0.1"])
+        .args(["--save", "", "--appendonly", "no"])
+        .stdout(Stdio::null())
+        .spawn()
+        .ok()?;
+    let spawned = Spawned(child);
+    let addr = format!("127.0.0.1:{port}");
+    let started = Instant::now();
+    loop {
+        match Raw::connect(&addr) {
+            Ok(raw) => return Some((raw, Some(spawned))),
+            Err(_) if started.elapsed() < Duration::from_secs(5) => {
+                thread::sleep(Duration::from_millis(20))
+            }
+            Err(err) => panic!("redis-server didn't start on {addr}: {err}"),
+        }
+    }
+}
+
+/// How a script line's replies are compared.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Compare {
+    Exact,
+    /// Array elements in any order.
+    Unordered,
+    /// Known not to match yet.
+    Differs,
+}
+
+/// `reply` with the elements of a flat array sorted, so replies holding
+/// the same ones compare equal.
+fn sorted(reply: &[u8]) -> Vec<u8> {
+    let mut rest = BytesMut::from(reply);
+    let header = match memchr::memmem::find(reply, b"\r\n") {
+        Some(end) if reply[0] == b'*' => end + 2,
+        _ => return reply.to_vec(),
+    };
+    let _ = rest.split_to(header);
+    let mut elements = Vec::new();
+    while !rest.is_empty() {
+        let before = rest.clone();
+        parser::parse(&mut rest).unwrap();
+        elements.push(before[..before.len() - rest.len()].to_vec());
+    }
+    elements.sort();
+    let mut out = reply[..header].to_vec();
+    out.extend(elements.concat());
+    out
+}
+
+fn scripts() -> Vec<PathBuf> {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/compat");
+    let mut scripts: Vec<PathBuf> = fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "redis"))
+        .collect();
+    scripts.sort();
+    scripts
+}
+
+#[test]
+fn test_replies_match_redis() {
+    let Some((mut redis, _spawned)) = redis() else {
+        eprintln!(
+            "no Redis to compare with: set RUSTIS_COMPAT_REDIS or put redis-server on the PATH"
+        );
+        return;
+    };
+    let mut server = Server::builder().port(0).workers(2).build().unwrap();
+    server.start().unwrap();
+    let mut rustis = Raw::connect(&server.addr().to_string()).unwrap();
+
+    let mut failures = Vec::new();
+    let (mut matched, mut known) = (0, 0);
+    for script in scripts() {
+        let flush = vec![b"FLUSHALL".to_vec()];
+        redis.command(&flush);
+        rustis.command(&flush);
+
+        let name = script.file_name().unwrap().to_string_lossy().into_owned();
+        for (i, line) in fs::read_to_string(&script).unwrap().lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (compare, command) = if let Some(command) = line.strip_prefix("!unordered ") {
+                (Compare::Unordered, command)
+            } else if let Some(command) = line.strip_prefix("!differs ") {
+                (Compare::Differs, command)
+            } else {
+                (Compare::Exact, line)
+            };
+            let args = split_quoted_args(command.as_bytes())
+                .unwrap_or_else(|_| panic!("{name}:{}: unbalanced quotes", i + 1));
+
+            let mut expected = redis.command(&args);
+            let mut got = rustis.command(&args);
+            if compare == Compare::Unordered {
+                expected = sorted(&expected);
+                got = sorted(&got);
+            }
+            let failure = match (compare, expected == got) {
+                (Compare::Differs, true) => "now matches Redis, drop `!differs`",
+                (Compare::Differs, false) => {
+                    known += 1;
+                    continue;
+                }
+                (_, true) => {
+                    matched += 1;
+                    continue;
+                }
+                (_, false) => "differs from Redis",
+            };
+            failures.push(format!(
+                "{name}:{}: {command}: {failure}\n    redis:  {}\n    rustis: {}",
+                i + 1,
+                expected.escape_ascii(),
+                got.escape_ascii()
+            ));
+        }
+    }
+    server.shutdown();
+
+    eprintln!("{matched} replies matched Redis, {known} known differences");
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}
@@ -0,0 +1,451 @@
+use std::{path::PathBuf, time::Duration};
+
+use rustis::config::{
+    parse_cpulist, parse_memory, ClientClass, Config, LazyFree, LoadModule, LogLevel,
+    MaxmemoryPolicy, OutputBufferLimit, Supervised, WorkerPanic, DEFAULT_PIDFILE,
+};
+
+fn args(list: &[&str]) -> Vec<String> {
+    list.iter().map(|s| s.to_string()).collect()
+}
+
+#[test]
+fn test_defaults() {
+    let config = Config::from_args(args(&[])).unwrap();
+    assert_eq!(config, Config::default());
+    assert_eq!(config.addr().to_string(), "127.0.0.1:6379");
+}
+
+#[test]
+fn test_positional_port() {
+    let config = Config::from_args(args(&["6380"])).unwrap();
+    assert_eq!(config.port, 6380);
+}
+
+#[test]
+fn test_flags() {
+    let config = Config::from_args(args(&[
+        "--port",
+        "7000",
+        "--bind",
+        "0.0.0.0",
+        "--reuseport",
+    ]))
+    .unwrap();
+    assert_eq!(config.addr().to_string(), "0.0.0.0:7000");
+    assert!(config.reuseport);
+}
+
+#[test]
+fn test_timeouts() {
+    let config = Config::from_args(args(&["--timeout", "30", "--tcp-keepalive", "0"])).unwrap();
+    assert_eq!(config.timeout, 30);
+    assert_eq!(config.tcp_keepalive, 0);
+}
+
+#[test]
+fn test_maxclients() {
+    assert_eq!(Config::default().maxclients, 10000);
+    let config = Config::from_args(args(&["--maxclients", "2"])).unwrap();
+    assert_eq!(config.maxclients, 2);
+}
+
+#[test]
+fn test_thread_counts() {
+    let config = Config::default();
+    assert_eq!(
+        (config.workers, config.io_threads, config.reserved_cores),
+        (0, 1, 0)
+    );
+    let config = Config::from_args(args(&[
+        "--worker-threads",
+        "6",
+        "--io-threads",
+        "2",
+        "--reserved-cores",
+        "2",
+    ]))
+    .unwrap();
+    assert_eq!(
+        (config.workers, config.io_threads, config.reserved_cores),
+        (6, 2, 2)
+    );
+    assert!(Config::from_args(args(&["--io-threads", "0"])).is_err());
+    assert!(Config::from_args(args(&["--worker-threads", "x"])).is_err());
+}
+
+#[test]
+fn test_worker_placement() {
+    let config = Config::default();
+    assert_eq!(config.worker_cpulist, None);
+    assert!(config.worker_pinning);
+    assert!(!config.worker_numa_local);
+    assert!(!config.worker_high_priority);
+
+    let config = Config::from_args(args(&[
+        "--worker-cpulist",
+        "0-3,8,10-15:2",
+        "--worker-pinning",
+        "no",
+        "--worker-numa-local",
+        "yes",
+        "--worker-high-priority",
+        "yes",
+    ]))
+    .unwrap();
+    assert_eq!(config.worker_cpulist, Some(vec![0, 1, 2, 3, 8, 10, 12, 14]));
+    assert!(!config.worker_pinning);
+    assert!(config.worker_numa_local);
+    assert!(config.worker_high_priority);
+
+    assert_eq!(parse_cpulist("7"), Some(vec![7]));
+    assert_eq!(parse_cpulist("0-7:3"), Some(vec![0, 3, 6]));
+    for invalid in ["", "3-1", "0-7:0", "a", "1,,2", "0-"] {
+        assert_eq!(parse_cpulist(invalid), None, "{invalid}");
+    }
+    assert!(Config::from_args(args(&["--worker-cpulist", "x"])).is_err());
+}
+
+#[test]
+fn test_worker_batch_size() {
+    assert_eq!(Config::default().worker_batch_size, 128);
+    let config = Config::from_args(args(&["--worker-batch-size", "16"])).unwrap();
+    assert_eq!(config.worker_batch_size, 16);
+    assert!(Config::from_args(args(&["--worker-batch-size", "0"])).is_err());
+}
+
+#[test]
+fn test_maxmemory() {
+    let config = Config::default();
+    assert_eq!(config.maxmemory, 0);
+    assert_eq!(config.maxmemory_policy, MaxmemoryPolicy::NoEviction);
+    assert_eq!(config.maxmemory_samples, 5);
+
+    let config = Config::from_args(args(&[
+        "--maxmemory",
+        "100mb",
+        "--maxmemory-policy",
+        "ALLKEYS-LFU",
+        "--maxmemory-samples",
+        "10",
+    ]))
+    .unwrap();
+    assert_eq!(config.maxmemory, 100 * 1024 * 1024);
+    assert_eq!(config.maxmemory_policy, MaxmemoryPolicy::AllKeysLfu);
+    assert_eq!(config.maxmemory_samples, 10);
+
+    assert!(Config::from_args(args(&["--maxmemory-policy", "lru"])).is_err());
+
+    let config =
+        Config::from_args(args(&["--lfu-log-factor", "0", "--lfu-decay-time", "5"])).unwrap();
+    assert_eq!(config.lfu_log_factor, 0);
+    assert_eq!(config.lfu_decay_time, 5);
+    assert!(Config::from_args(args(&["--maxmemory-samples", "0"])).is_err());
+}
+
+#[test]
+fn test_lazyfree() {
+    assert_eq!(Config::default().lazyfree, LazyFree::default());
+    let config = Config::from_args(args(&[
+        "--lazyfree-lazy-eviction",
+        "yes",
+        "--lazyfree-lazy-user-del",
+        "yes",
+        "--lazyfree-lazy-user-flush",
+        "no",
+    ]))
+    .unwrap();
+    assert!(config.lazyfree.eviction);
+    assert!(config.lazyfree.user_del);
+    assert!(!config.lazyfree.user_flush);
+    assert!(!config.lazyfree.server_del);
+}
+
+#[cfg(feature = "lists")]
+#[test]
+fn test_list_max_listpack_size() {
+    assert_eq!(Config::default().list_max_listpack_size, -2);
+    let config = Config::from_args(args(&["--list-max-listpack-size", "128"])).unwrap();
+    assert_eq!(config.list_max_listpack_size, 128);
+    let config = Config::from_args(args(&["--list-max-listpack-size", "-5"])).unwrap();
+    assert_eq!(config.list_max_listpack_size, -5);
+    assert!(Config::from_args(args(&["--list-max-listpack-size", "0"])).is_err());
+}
+
+#[cfg(feature = "sets")]
+#[test]
+fn test_set_max_intset_entries() {
+    assert_eq!(Config::default().set_max_intset_entries, 512);
+    let config = Config::from_args(args(&["--set-max-intset-entries", "64"])).unwrap();
+    assert_eq!(config.set_max_intset_entries, 64);
+    assert!(Config::from_args(args(&["--set-max-intset-entries", "-1"])).is_err());
+}
+
+#[test]
+fn test_command_budget() {
+    assert_eq!(Config::default().command_budget, 0);
+    let config = Config::from_args(args(&["--command-budget", "100000"])).unwrap();
+    assert_eq!(config.command_budget, 100_000);
+    assert!(Config::from_args(args(&["--command-budget", "-1"])).is_err());
+}
+
+#[test]
+fn test_expire_jitter_percent() {
+    assert_eq!(Config::default().expire_jitter_percent, 0);
+    let config = Config::from_args(args(&["--expire-jitter-percent", "10"])).unwrap();
+    assert_eq!(config.expire_jitter_percent, 10);
+    let config = Config::from_args(args(&["--expire-jitter-percent", "50"])).unwrap();
+    assert_eq!(config.expire_jitter_percent, 50);
+    assert!(Config::from_args(args(&["--expire-jitter-percent", "51"])).is_err());
+}
+
+#[test]
+fn test_shard_capacity() {
+    assert_eq!(Config::default().shard_capacity, 0);
+    let config = Config::from_args(args(&["--shard-capacity", "1000000"])).unwrap();
+    assert_eq!(config.shard_capacity, 1_000_000);
+}
+
+#[test]
+fn test_active_defrag() {
+    assert!(!Config::default().active_defrag.enabled);
+    let config = Config::from_args(args(&[
+        "--activedefrag",
+        "yes",
+        "--active-defrag-ignore-bytes",
+        "10mb",
+        "--active-defrag-threshold-lower",
+        "20",
+        "--active-defrag-cycle-max",
+        "50",
+    ]))
+    .unwrap();
+    let defrag = config.active_defrag;
+    assert!(defrag.enabled);
+    assert_eq!(defrag.ignore_bytes, 10 * 1024 * 1024);
+    assert_eq!(defrag.threshold_lower, 20);
+    assert_eq!(defrag.threshold_upper, 100);
+    assert_eq!((defrag.cycle_min, defrag.cycle_max), (1, 50));
+    assert!(Config::from_args(args(&["--active-defrag-cycle-max", "100"])).is_err());
+}
+
+#[test]
+fn test_daemonize_and_files() {
+    let config = Config::default();
+    assert!(!config.daemonize);
+    assert_eq!(config.pidfile_path(), None);
+
+    let config = Config::from_args(args(&["--daemonize", "yes"])).unwrap();
+    assert!(config.daemonize);
+    assert_eq!(config.pidfile_path(), Some(PathBuf::from(DEFAULT_PIDFILE)));
+
+    let config = Config::from_args(args(&[
+        "--daemonize",
+        "no",
+        "--pidfile",
+        "/tmp/r.pid",
+        "--logfile",
+        "/tmp/r.log",
+    ]))
+    .unwrap();
+    assert!(!config.daemonize);
+    assert_eq!(config.pidfile_path(), Some(PathBuf::from("/tmp/r.pid")));
+    assert_eq!(config.logfile, Some(PathBuf::from("/tmp/r.log")));
+
+    assert!(Config::from_args(args(&["--daemonize", "maybe"])).is_err());
+}
+
+#[test]
+fn test_supervised() {
+    assert_eq!(Config::default().supervised, Supervised::No);
+    let config = Config::from_args(args(&["--supervised", "systemd"])).unwrap();
+    assert_eq!(config.supervised, Supervised::Systemd);
+    assert!(config.supervised.is_systemd());
+    assert!(Config::from_args(args(&["--supervised", "upstart"])).is_err());
+}
+
+#[test]
+fn test_loglevel() {
+    assert_eq!(Config::default().loglevel, LogLevel::Notice);
+    let config = Config::from_args(args(&["--loglevel", "VERBOSE"])).unwrap();
+    assert_eq!(config.loglevel, LogLevel::Verbose);
+    assert!(Config::from_args(args(&["--loglevel", "loud"])).is_err());
+}
+
+#[test]
+fn test_otel() {
+    let config = Config::default();
+    assert_eq!(config.otel_sample_ratio, 0.0);
+    assert_eq!(config.otel_endpoint, "http://localhost:4318/v1/traces");
+
+    let config = Config::from_args(args(&[
+        "--otel-endpoint",
+        "http://collector:4318/v1/traces",
+        "--otel-sample-ratio",
+        "0.01",
+    ]))
+    .unwrap();
+    assert_eq!(config.otel_endpoint, "http://collector:4318/v1/traces");
+    assert_eq!(config.otel_sample_ratio, 0.01);
+    assert!(Config::from_args(args(&["--otel-sample-ratio", "1.5"])).is_err());
+    assert!(Config::from_args(args(&["--otel-sample-ratio", "NaN"])).is_err());
+}
+
+#[test]
+fn test_latency_tracking() {
+    assert!(Config::default().latency_tracking);
+    let config = Config::from_args(args(&["--latency-tracking", "no"])).unwrap();
+    assert!(!config.latency_tracking);
+    assert!(Config::from_args(args(&["--latency-tracking", "maybe"])).is_err());
+}
+
+#[test]
+fn test_read_snapshots() {
+    assert!(!Config::default().read_snapshots);
+    let config = Config::from_args(args(&["--read-snapshots", "yes"])).unwrap();
+    assert!(config.read_snapshots);
+}
+
+#[test]
+fn test_invalid_arguments() {
+    assert!(Config::from_args(args(&["--port"])).is_err());
+    assert!(Config::from_args(args(&["--port", "abc"])).is_err());
+    assert!(Config::from_args(args(&["--nope"])).is_err());
+}
+
+#[test]
+fn test_parse_memory_units() {
+    assert_eq!(parse_memory("1024"), Some(1024));
+    assert_eq!(parse_memory("64kb"), Some(64 * 1024));
+    assert_eq!(parse_memory("256MB"), Some(256 * 1024 * 1024));
+    assert_eq!(parse_memory("1gb"), Some(1024 * 1024 * 1024));
+    assert_eq!(parse_memory("2m"), Some(2_000_000));
+    assert_eq!(parse_memory("12tb"), None);
+    assert_eq!(parse_memory("mb"), None);
+}
+
+#[test]
+fn test_client_output_buffer_limit() {
+    let defaults = Config::default().client_output_buffer_limit;
+    assert!(defaults.for_class(ClientClass::Normal).is_unlimited());
+    assert_eq!(
+        defaults.for_class(ClientClass::PubSub),
+        OutputBufferLimit::new(32 << 20, 8 << 20, 60)
+    );
+
+    let config = Config::from_args(args(&[
+        "--client-output-buffer-limit",
+        "normal 1mb 512kb 10",
+        "--client-output-buffer-limit",
+        "slave 0 0 0",
+    ]))
+    .unwrap();
+    let limits = config.client_output_buffer_limit;
+    assert_eq!(limits.normal.hard, 1 << 20);
+    assert_eq!(limits.normal.soft, 512 << 10);
+    assert_eq!(limits.normal.soft_duration, Duration::from_secs(10));
+    assert!(limits.for_class(ClientClass::Replica).is_unlimited());
+
+    assert!(Config::from_args(args(&["--client-output-buffer-limit", "normal 1mb"])).is_err());
+    assert!(Config::from_args(args(&["--client-output-buffer-limit", "bogus 0 0 0"])).is_err());
+}
+
+#[test]
+fn test_client_query_buffer_limit() {
+    assert_eq!(Config::default().client_query_buffer_limit, 1 << 30);
+
+    let config = Config::from_args(args(&["--client-query-buffer-limit", "16mb"])).unwrap();
+    assert_eq!(config.client_query_buffer_limit, 16 << 20);
+}
+
+#[test]
+fn test_audit_log() {
+    let defaults = Config::default();
+    assert_eq!(defaults.audit_log, None);
+    assert_eq!(defaults.audit_log_max_size, 64 << 20);
+    assert_eq!(defaults.audit_log_files, 10);
+
+    let config = Config::from_args(args(&[
+        "--audit-log",
+        "/var/log/rustis/audit.log",
+        "--audit-log-max-size",
+        "1gb",
+        "--audit-log-files",
+        "3",
+    ]))
+    .unwrap();
+    assert_eq!(
+        config.audit_log.as_deref(),
+        Some(std::path::Path::new("/var/log/rustis/audit.log"))
+    );
+    assert_eq!(config.audit_log_max_size, 1 << 30);
+    assert_eq!(config.audit_log_files, 3);
+}
+
+#[test]
+fn test_worker_panic() {
+    assert_eq!(Config::default().worker_panic, WorkerPanic::Shutdown);
+
+    let config = Config::from_args(args(&["--worker-panic", "RESTART"])).unwrap();
+    assert_eq!(config.worker_panic, WorkerPanic::Restart);
+    assert!(Config::from_args(args(&["--worker-panic", "abort"])).is_err());
+}
+
+#[test]
+fn test_statsd() {
+    let defaults = Config::default();
+    assert_eq!(defaults.statsd, None);
+    assert_eq!(defaults.statsd_interval, 10);
+    assert_eq!(defaults.statsd_prefix, "rustis");
+
+    let config = Config::from_args(args(&[
+        "--statsd",
+        "graphite.local:8125",
+        "--statsd-interval",
+        "60",
+        "--statsd-prefix",
+        "cache.eu1",
+    ]))
+    .unwrap();
+    assert_eq!(config.statsd.as_deref(), Some("graphite.local:8125"));
+    assert_eq!(config.statsd_interval, 60);
+    assert_eq!(config.statsd_prefix, "cache.eu1");
+    assert!(Config::from_args(args(&["--statsd-interval", "0"])).is_err());
+}
+
+#[test]
+fn test_loadmodule() {
+    assert!(Config::default().loadmodule.is_empty());
+
+    let config = Config::from_args(args(&[
+        "--loadmodule",
+        "/opt/modules/json.so",
+        "--loadmodule",
+        "  ./search.so  MAXRESULTS 100 ",
+    ]))
+    .unwrap();
+    assert_eq!(
+        config.loadmodule,
+        vec![
+            LoadModule {
+                path: PathBuf::from("/opt/modules/json.so"),
+                args: Vec::new(),
+            },
+            LoadModule {
+                path: PathBuf::from("./search.so"),
+                args: vec!["MAXRESULTS".to_string(), "100".to_string()],
+            },
+        ]
+    );
+    assert!(Config::from_args(args(&["--loadmodule", " "])).is_err());
+    assert!(Config::from_args(args(&["--loadmodule"])).is_err());
+}
+
+#[test]
+fn test_load() {
+    assert_eq!(Config::default().load, None);
+    let config = Config::from_args(args(&["--load", "data/seed.resp"])).unwrap();
+    assert_eq!(config.load, Some(PathBuf::from("data/seed.resp")));
+    assert!(Config::from_args(args(&["--load"])).is_err());
+    assert!(Config::from_args(args(&["--load", "seed.resp", "--reuseport"])).is_err());
+}
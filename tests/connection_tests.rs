@@ -0,0 +1,381 @@
+#![cfg(feature = "server")]
+
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use bytes::BytesMut;
+use rustis::{
+    config::{Config, OutputBufferLimit, OutputBufferLimits},
+    connection::{
+        handle_connection, is_resource_exhausted, is_transient_accept_error, serve_stream,
+        shrink_read_buffer, Client, OutputLimitTracker, MAX_IN_FLIGHT,
+        READ_BUFFER_SHRINK_THRESHOLD, READ_BUFFER_SIZE,
+    },
+    message::WorkerMessage,
+    worker::worker_main,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    sync::mpsc::{self, Sender},
+    task::LocalSet,
+};
+
+/// Spawns a single worker thread and returns its mailbox.
+fn spawn_worker() -> Vec<Sender<WorkerMessage>> {
+    let (tx, rx) = mpsc::channel(64);
+    std::thread::spawn(move || worker_main(0, rx, Arc::default()));
+    vec![tx]
+}
+
+/// Writes `request` on a fresh connection and returns everything the server
+/// sends back until it closes the socket.
+async fn roundtrip(request: &[u8]) -> Vec<u8> {
+    roundtrip_with(request, Config::default()).await
+}
+
+async fn roundtrip_with(request: &[u8], config: Config) -> Vec<u8> {
+    let router = spawn_worker();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let local = LocalSet::new();
+    local
+        .run_until(async move {
+            tokio::task::spawn_local(async move {
+                let (stream, addr) = listener.accept().await.unwrap();
+                let _ = handle_connection(stream, Client { id: 1, addr }, &router, &config).await;
+            });
+
+            let mut client = TcpStream::connect(addr).await.unwrap();
+            client.write_all(request).await.unwrap();
+
+            let mut reply = Vec::new();
+            client.read_to_end(&mut reply).await.unwrap();
+            reply
+        })
+        .await
+}
+
+#[tokio::test]
+async fn test_quit_replies_ok_and_closes() {
+    let reply = roundtrip(b"*1\r\n$4\r\nQUIT\r\n").await;
+    assert_eq!(reply, b"+OK\r\n");
+}
+
+#[tokio::test]
+async fn test_quit_flushes_pipelined_replies_first() {
+    let reply = roundtrip(
+        b"*3\r\n$3\r\nSET\r\n$1\r\nk\r\n$1\r\nv\r\n*2\r\n$3\r\nGET\r\n$1\r\nk\r\nQUIT\r\n*1\r\n$4\r\nPING\r\n",
+    )
+    .await;
+    assert_eq!(reply, b"+OK\r\n$1\r\nv\r\n+OK\r\n");
+}
+
+#[tokio::test]
+async fn test_protocol_error_replies_and_closes() {
+    let reply = roundtrip(b"*abc\r\n").await;
+    assert_eq!(reply, b"-ERR Protocol error: invalid multibulk length\r\n");
+
+    let reply = roundtrip(b"*1\r\n$foo\r\n").await;
+    assert_eq!(reply, b"-ERR Protocol error: invalid bulk length\r\n");
+
+    let reply = roundtrip(b"SET \"unterminated\r\n").await;
+    assert_eq!(
+        reply,
+        b"-ERR Protocol error: unbalanced quotes in request\r\n"
+    );
+    // RESP3 frames are for servers to send, whatever their header says
+    let reply = roundtrip(b"|9223372036854775808\r\n+a\r\n").await;
+    assert_eq!(reply, b"-ERR Protocol error: expected '$', got '|'\r\n");
+
+    // a request's arguments are bulk strings, never nested
+    let reply = roundtrip(b"*1\r\n:1\r\n").await;
+    assert_eq!(reply, b"-ERR Protocol error: expected '$', got ':'\r\n");
+    let reply = roundtrip(b"*1\r\n*1\r\n$4\r\nPING\r\n").await;
+    assert_eq!(reply, b"-ERR Protocol error: expected '$', got '*'\r\n");
+}
+
+#[tokio::test]
+async fn test_protocol_error_after_valid_commands() {
+    let reply = roundtrip(b"*3\r\n$3\r\nSET\r\n$1\r\nk\r\n$1\r\nv\r\n*x\r\n").await;
+    assert_eq!(
+        reply,
+        b"+OK\r\n-ERR Protocol error: invalid multibulk length\r\n".as_slice()
+    );
+}
+
+#[tokio::test]
+async fn test_long_pipeline_replies_in_order() {
+    let mut request = Vec::new();
+    let mut expected = Vec::new();
+    // more commands than a connection may have in flight at once
+    for i in 0..(MAX_IN_FLIGHT + 500) {
+        let value = i.to_string();
+        request.extend_from_slice(
+            format!(
+                "*3\r\n$3\r\nSET\r\n$3\r\nk{:02}\r\n${}\r\n{}\r\n",
+                i % 100,
+                value.len(),
+                value
+            )
+            .as_bytes(),
+        );
+        request
+            .extend_from_slice(format!("*2\r\n$3\r\nGET\r\n$3\r\nk{:02}\r\n", i % 100).as_bytes());
+        expected.extend_from_slice(format!("+OK\r\n${}\r\n{}\r\n", value.len(), value).as_bytes());
+    }
+    request.extend_from_slice(b"*1\r\n$4\r\nQUIT\r\n");
+    expected.extend_from_slice(b"+OK\r\n");
+
+    let reply = roundtrip(&request).await;
+    assert_eq!(reply, expected);
+}
+
+#[test]
+fn test_accept_error_classification() {
+    use std::io::{Error, ErrorKind};
+
+    let aborted = Error::from(ErrorKind::ConnectionAborted);
+    assert!(is_transient_accept_error(&aborted));
+    assert!(!is_resource_exhausted(&aborted));
+
+    #[cfg(target_os = "linux")]
+    {
+        // EMFILE and ENFILE
+        for errno in [24, 23] {
+            let exhausted = Error::from_raw_os_error(errno);
+            assert!(is_resource_exhausted(&exhausted));
+            assert!(!is_transient_accept_error(&exhausted));
+        }
+    }
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn test_reuseport_listeners_share_address() {
+    use rustis::connection::reuseport_listener;
+
+    let first = reuseport_listener("127.0.0.1:0".parse().unwrap()).unwrap();
+    let addr = first.local_addr().unwrap();
+    let second = reuseport_listener(addr).unwrap();
+    assert_eq!(second.local_addr().unwrap(), addr);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_io_threads_serve_one_address() {
+    use rustis::connection::spawn_io;
+
+    let port = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let config = Config {
+        port,
+        io_threads: 3,
+        ..Config::default()
+    };
+    let router = Arc::new(spawn_worker());
+    // serves until the test process exits
+    std::thread::spawn(move || {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        runtime.block_on(spawn_io(router, &config)).unwrap();
+    });
+
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    runtime.block_on(async {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        for _ in 0..30 {
+            let mut client = loop {
+                match TcpStream::connect(("127.0.0.1", port)).await {
+                    Ok(client) => break client,
+                    Err(_) if Instant::now() < deadline => {
+                        tokio::time::sleep(Duration::from_millis(10)).await
+                    }
+                    Err(err) => panic!("can't connect: {err}"),
+                }
+            };
+            client.write_all(b"PING\r\n").await.unwrap();
+            let mut reply = [0; 7];
+            client.read_exact(&mut reply).await.unwrap();
+            assert_eq!(&reply, b"+PONG\r\n");
+        }
+    });
+}
+
+#[tokio::test]
+async fn test_idle_client_is_disconnected() {
+    let config = Config {
+        timeout: 1,
+        ..Config::default()
+    };
+
+    let started = Instant::now();
+    // never sends QUIT, so only the idle timeout can end the connection
+    let reply = roundtrip_with(b"*1\r\n$4\r\nPING\r\n", config).await;
+    assert_eq!(reply, b"+PONG\r\n");
+    assert!(started.elapsed() >= Duration::from_secs(1));
+}
+
+#[test]
+fn test_output_limit_tracker() {
+    let start = Instant::now();
+
+    let mut tracker = OutputLimitTracker::new(OutputBufferLimit::new(0, 0, 0));
+    assert!(!tracker.exceeded(usize::MAX, start));
+
+    let mut tracker = OutputLimitTracker::new(OutputBufferLimit::new(1000, 100, 10));
+    assert!(tracker.exceeded(1000, start));
+
+    // over the soft limit, but not for long enough yet
+    assert!(!tracker.exceeded(200, start));
+    assert!(!tracker.exceeded(200, start + Duration::from_secs(9)));
+    assert!(tracker.exceeded(200, start + Duration::from_secs(10)));
+
+    // dropping back under the soft limit resets the clock
+    let mut tracker = OutputLimitTracker::new(OutputBufferLimit::new(0, 100, 10));
+    assert!(!tracker.exceeded(200, start));
+    assert!(!tracker.exceeded(50, start + Duration::from_secs(5)));
+    assert!(!tracker.exceeded(200, start + Duration::from_secs(11)));
+}
+
+#[tokio::test]
+async fn test_output_buffer_hard_limit_disconnects() {
+    let config = Config {
+        client_output_buffer_limit: OutputBufferLimits {
+            normal: OutputBufferLimit::new(64, 0, 0),
+            ..OutputBufferLimits::default()
+        },
+        ..Config::default()
+    };
+
+    let router = spawn_worker();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let local = LocalSet::new();
+    local
+        .run_until(async move {
+            tokio::task::spawn_local(async move {
+                let (stream, addr) = listener.accept().await.unwrap();
+                let _ = handle_connection(stream, Client { id: 1, addr }, &router, &config).await;
+            });
+
+            let mut client = TcpStream::connect(addr).await.unwrap();
+            let value = "x".repeat(100);
+            let set = format!("*3\r\n$3\r\nSET\r\n$1\r\nk\r\n$100\r\n{}\r\n", value);
+            client.write_all(set.as_bytes()).await.unwrap();
+
+            let mut ok = [0u8; 5];
+            client.read_exact(&mut ok).await.unwrap();
+            assert_eq!(&ok, b"+OK\r\n");
+
+            // the 100 byte reply is over the 64 byte hard limit
+            client
+                .write_all(b"*2\r\n$3\r\nGET\r\n$1\r\nk\r\n")
+                .await
+                .unwrap();
+            let mut rest = Vec::new();
+            let _ = client.read_to_end(&mut rest).await;
+            assert!(rest.is_empty());
+        })
+        .await
+}
+
+#[tokio::test]
+async fn test_output_buffer_limit_disconnects_a_client_that_never_reads() {
+    let config = Config {
+        client_output_buffer_limit: OutputBufferLimits {
+            normal: OutputBufferLimit::new(64 * 1024, 0, 0),
+            ..OutputBufferLimits::default()
+        },
+        ..Config::default()
+    };
+
+    let router = spawn_worker();
+    // the pipe holds 1KB, so the writer blocks on the first GET reply
+    let (mut client, server) = tokio::io::duplex(1024);
+
+    let local = LocalSet::new();
+    local
+        .run_until(async move {
+            tokio::task::spawn_local(async move {
+                let (read_half, write_half) = tokio::io::split(server);
+                let addr = "127.0.0.1:1".parse().unwrap();
+                let client = Client { id: 1, addr };
+                let _ = serve_stream(read_half, write_half, client, &router, &config).await;
+            });
+
+            let value = "x".repeat(8 * 1024);
+            let set = format!("*3\r\n$3\r\nSET\r\n$1\r\nk\r\n$8192\r\n{}\r\n", value);
+            client.write_all(set.as_bytes()).await.unwrap();
+            for _ in 0..20 {
+                let get = client.write_all(b"*2\r\n$3\r\nGET\r\n$1\r\nk\r\n");
+                if get.await.is_err() {
+                    // already hung up on
+                    break;
+                }
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+            let _ = client.shutdown().await;
+
+            // the queued replies go over the 64KB hard limit while the first
+            // write is still pending, so the server hangs up before the client
+            // reads a byte, leaving only what fit in the pipe
+            tokio::time::sleep(Duration::from_millis(500)).await;
+            let mut rest = Vec::new();
+            tokio::time::timeout(Duration::from_secs(5), client.read_to_end(&mut rest))
+                .await
+                .expect("client was never disconnected")
+                .unwrap();
+            assert!(rest.starts_with(b"+OK\r\n$8192\r\n"));
+            assert!(rest.len() <= 1024);
+        })
+        .await
+}
+
+#[test]
+fn test_shrink_read_buffer() {
+    // a drained buffer that grew for a big request is reallocated
+    let mut buf = BytesMut::with_capacity(READ_BUFFER_SHRINK_THRESHOLD * 4);
+    buf.extend_from_slice(b"*1\r\n");
+    shrink_read_buffer(&mut buf);
+    assert_eq!(buf.capacity(), READ_BUFFER_SIZE);
+    assert_eq!(&buf[..], b"*1\r\n");
+
+    // still holding a big partial request, keep it
+    let mut buf = BytesMut::with_capacity(READ_BUFFER_SHRINK_THRESHOLD * 4);
+    buf.resize(READ_BUFFER_SHRINK_THRESHOLD, b'x');
+    shrink_read_buffer(&mut buf);
+    assert!(buf.capacity() >= READ_BUFFER_SHRINK_THRESHOLD * 4);
+
+    // normal sized buffers are left alone
+    let mut buf = BytesMut::with_capacity(READ_BUFFER_SIZE);
+    shrink_read_buffer(&mut buf);
+    assert_eq!(buf.capacity(), READ_BUFFER_SIZE);
+}
+
+#[tokio::test]
+async fn test_query_buffer_limit_disconnects() {
+    let config = Config {
+        client_query_buffer_limit: 1024,
+        ..Config::default()
+    };
+
+    // a bulk string that never finishes, larger than the limit
+    let mut request = b"*1\r\n$100000\r\n".to_vec();
+    request.extend(std::iter::repeat_n(b'x', 4096));
+
+    let reply = roundtrip_with(&request, config).await;
+    assert!(reply.is_empty());
+}
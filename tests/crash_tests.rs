@@ -0,0 +1,124 @@
+#![cfg(feature = "server")]
+
+use std::{
+    panic,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use bytes::Bytes;
+use rustis::{
+    config::{Config, WorkerPanic},
+    crash::{self, catch_command},
+    kv::KvStore,
+    local::LocalClient,
+    message::ResponseValue,
+    module::{self, CommandFlags, Modules},
+    worker::worker_main,
+};
+use tokio::sync::mpsc;
+
+/// Held by tests that panic, so reports don't end up in another test's hook.
+static PANICS: Mutex<()> = Mutex::new(());
+
+#[test]
+fn test_command_panic() {
+    let _panics = PANICS.lock().unwrap();
+    let reports = Arc::new(Mutex::new(Vec::new()));
+    let captured = reports.clone();
+    panic::set_hook(Box::new(move |info| {
+        captured.lock().unwrap().push(crash::report(info))
+    }));
+
+    let key = Bytes::from_static(b"user:\"1\"");
+    let ok = catch_command("get", Some(&key), WorkerPanic::Restart, || {
+        ResponseValue::Integer(1)
+    });
+    assert_eq!(ok, Some(ResponseValue::Integer(1)));
+    assert!(reports.lock().unwrap().is_empty());
+
+    crash::set_worker(3);
+    let reply = catch_command("set", Some(&key), WorkerPanic::Restart, || {
+        panic!("index out of bounds")
+    });
+    assert_eq!(reply, None);
+    assert!(!crash::crashed());
+
+    let report = reports.lock().unwrap().pop().unwrap();
+    assert!(report.starts_with("=== RUSTIS BUG REPORT START ===\nthread 'test_command_panic' panicked at tests/crash_tests.rs:"));
+    assert!(report.contains(
+        ":\nindex out of bounds\nworker: 3\ncommand: set, key \"user:\\\"1\\\"\"\nbacktrace:\n"
+    ));
+    assert!(report.ends_with("=== RUSTIS BUG REPORT END ==="));
+
+    // with the default policy the server shuts down
+    let reply = catch_command("set", None, WorkerPanic::Shutdown, || panic!("oops"));
+    assert_eq!(reply, None);
+    assert!(crash::crashed());
+    let report = reports.lock().unwrap().pop().unwrap();
+    assert!(report.contains(":\noops\nworker: 3\ncommand: set\nbacktrace:\n"));
+    let _ = panic::take_hook();
+
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    runtime.block_on(async {
+        tokio::time::timeout(Duration::from_secs(1), crash::shutdown_requested())
+            .await
+            .expect("shutdown was not requested");
+    });
+}
+
+/// `crash.set key value`: sets the key, then panics halfway through.
+fn crash_set(kv: &mut KvStore, args: &[Bytes]) -> ResponseValue {
+    kv.set(args[0].clone(), args[1].clone());
+    panic!("crash.set always panics");
+}
+
+#[test]
+fn test_worker_carries_on_after_panic() {
+    let _panics = PANICS.lock().unwrap();
+    let mut modules = Modules::new();
+    modules
+        .command("crash.set", CommandFlags::default(), crash_set)
+        .unwrap();
+    assert!(module::install(modules));
+
+    // a single worker, so every key is in the same shard
+    let config = Config {
+        worker_panic: WorkerPanic::Restart,
+        ..Config::default()
+    };
+    let (tx, rx) = mpsc::channel(16);
+    std::thread::spawn(move || worker_main(0, rx, Arc::new(config)));
+    let router = Arc::new(vec![tx]);
+    let client = LocalClient::new(&router);
+    let bulk = |value: &'static str| ResponseValue::BulkString(Some(Bytes::from(value)));
+
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    runtime.block_on(async {
+        client.command(["SET", "kept", "1"]).await;
+        client.command(["SET", "victim", "1"]).await;
+        assert_eq!(
+            client.command(["crash.set", "victim", "2"]).await,
+            ResponseValue::Error(crash::PANIC_ERROR.into())
+        );
+
+        // the rest of the shard is still there and the worker still serves it
+        assert_eq!(client.command(["GET", "kept"]).await, bulk("1"));
+        assert_eq!(
+            client.command(["SET", "new", "3"]).await,
+            ResponseValue::SimpleString("OK".into())
+        );
+        assert_eq!(client.command(["DBSIZE"]).await, ResponseValue::Integer(2));
+        // only the key the command was on is dropped, half updated as it is
+        assert_eq!(
+            client.command(["GET", "victim"]).await,
+            ResponseValue::BulkString(None)
+        );
+    });
+}
@@ -0,0 +1,93 @@
+#![cfg(feature = "server")]
+
+use rustis::daemon::PidFile;
+
+#[test]
+fn test_pidfile_written_and_removed() {
+    let path = std::env::temp_dir().join(format!("rustis-test-{}.pid", std::process::id()));
+
+    let pidfile = PidFile::create(&path).unwrap();
+    assert_eq!(pidfile.path(), path);
+    let contents = std::fs::read_to_string(&path).unwrap();
+    assert_eq!(contents.trim(), std::process::id().to_string());
+
+    drop(pidfile);
+    assert!(!path.exists());
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_sd_notify_sends_state() {
+    use std::os::unix::net::UnixDatagram;
+
+    use rustis::daemon::sd_notify;
+
+    let path = std::env::temp_dir().join(format!("rustis-notify-{}.sock", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let socket = UnixDatagram::bind(&path).unwrap();
+
+    // SAFETY: no other test in this binary reads or writes the environment.
+    unsafe { std::env::set_var("NOTIFY_SOCKET", &path) };
+    sd_notify("READY=1").unwrap();
+    unsafe { std::env::remove_var("NOTIFY_SOCKET") };
+
+    let mut buf = [0u8; 64];
+    let n = socket.recv(&mut buf).unwrap();
+    assert_eq!(&buf[..n], b"READY=1");
+    let _ = std::fs::remove_file(&path);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_signals_dump_stats_and_reopen_log() {
+    use std::{
+        process::{Command, Stdio},
+        thread,
+        time::{Duration, Instant},
+    };
+
+    let dir = std::env::temp_dir().join(format!("rustis-signals-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let log = dir.join("rustis.log");
+    let rotated = dir.join("rustis.log.1");
+    let mut server = Command::new(env!("CARGO_BIN_EXE_rustis"))
+        .args(["--port", "0", "--worker-threads", "2", "--logfile"])
+        .arg(&log)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    let pid = server.id() as libc::pid_t;
+    let wait_for = |path: &std::path::Path, text: &str| {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        loop {
+            let contents = std::fs::read_to_string(path).unwrap_or_default();
+            if contents.contains(text) {
+                return contents;
+            }
+            assert!(Instant::now() < deadline, "no {text:?} in {contents}");
+            thread::sleep(Duration::from_millis(20));
+        }
+    };
+
+    wait_for(&log, "Listening on port");
+    // SAFETY: plain syscall on the child we spawned
+    unsafe { libc::kill(pid, libc::SIGUSR1) };
+    let contents = wait_for(&log, "worker1:commands_processed=");
+    assert!(contents.contains("Stats snapshot requested by SIGUSR1"));
+    assert!(contents.contains("used_memory:"));
+
+    // what logrotate does
+    std::fs::rename(&log, &rotated).unwrap();
+    unsafe { libc::kill(pid, libc::SIGHUP) };
+    wait_for(&log, "Reopened the log file on SIGHUP");
+    unsafe { libc::kill(pid, libc::SIGUSR1) };
+    wait_for(&log, "worker1:commands_processed=");
+    assert!(!std::fs::read_to_string(&rotated)
+        .unwrap()
+        .contains("Reopened"));
+
+    unsafe { libc::kill(pid, libc::SIGTERM) };
+    assert!(server.wait().unwrap().success());
+    let _ = std::fs::remove_dir_all(&dir);
+}
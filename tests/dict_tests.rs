@@ -0,0 +1,204 @@
+use std::collections::HashSet;
+
+use rustis::dict::Dict;
+
+#[test]
+fn test_insert_get_remove() {
+    let mut dict = Dict::new();
+    assert!(dict.is_empty());
+    assert_eq!(dict.buckets(), 0);
+
+    for i in 0..1000 {
+        assert_eq!(dict.insert(i, i * 2), None);
+    }
+    assert_eq!(dict.len(), 1000);
+    assert_eq!(dict.insert(7, 0), Some(14));
+    assert_eq!(dict.get(&7), Some(&0));
+    *dict.get_mut(&7).unwrap() = 14;
+
+    for i in 0..1000 {
+        assert_eq!(dict.get(&i), Some(&(i * 2)), "key {i}");
+    }
+    assert!(!dict.contains_key(&1000));
+
+    for i in (0..1000).step_by(2) {
+        assert_eq!(dict.remove(&i), Some(i * 2));
+    }
+    assert_eq!(dict.remove(&0), None);
+    assert_eq!(dict.len(), 500);
+    for i in 0..1000 {
+        assert_eq!(dict.contains_key(&i), i % 2 == 1, "key {i}");
+    }
+}
+
+#[test]
+fn test_incremental_rehash() {
+    let mut dict = Dict::new();
+    for i in 0..64 {
+        dict.insert(i, i);
+    }
+    while dict.rehash(1) {}
+    assert_eq!(dict.buckets(), 64);
+
+    // one more key than buckets starts a rehash into twice as many
+    dict.insert(64, 64);
+    assert!(dict.is_rehashing());
+    assert_eq!(dict.buckets(), 64 + 128);
+
+    // while it runs, every key is found and iterated exactly once
+    for i in 65..100 {
+        dict.insert(i, i);
+        assert!(dict.is_rehashing() || dict.buckets() == 128);
+        assert!((0..=i).all(|key| dict.get(&key) == Some(&key)));
+        let keys: HashSet<_> = dict.iter().map(|(key, _)| *key).collect();
+        assert_eq!(keys.len(), dict.len());
+    }
+    while dict.rehash(10) {}
+    assert_eq!(dict.buckets(), 128);
+    assert_eq!(dict.len(), 100);
+
+    // emptying it shrinks the table back down
+    for i in 0..100 {
+        dict.remove(&i);
+    }
+    while dict.rehash(10) {}
+    assert!(dict.buckets() < 128);
+    assert!(dict.is_empty());
+}
+
+#[test]
+fn test_with_capacity() {
+    let mut dict = Dict::with_capacity(1000);
+    assert_eq!(dict.buckets(), 1024);
+    for i in 0..1024 {
+        dict.get_or_insert_with(i, |key| *key);
+    }
+    assert!(!dict.is_rehashing());
+    assert_eq!(dict.buckets(), 1024);
+    assert_eq!(*dict.get_or_insert_with(3, |_| 0), 3);
+
+    // removals never shrink it below its capacity
+    for i in 0..1024 {
+        dict.remove(&i);
+    }
+    assert_eq!(dict.buckets(), 1024);
+
+    dict.insert(1, 1);
+    let taken = dict.take();
+    assert_eq!(taken.len(), 1);
+    assert!(dict.is_empty());
+    assert_eq!(dict.buckets(), 1024);
+}
+
+/// Walks `dict` to the end, calling `between` after every step.
+fn scan(dict: &mut Dict<u32, u32>, mut between: impl FnMut(&mut Dict<u32, u32>)) -> Vec<u32> {
+    let mut seen = Vec::new();
+    let mut cursor = Some(0);
+    while let Some(at) = cursor {
+        cursor = dict.scan_bucket(at, |key, _| seen.push(*key));
+        between(dict);
+    }
+    seen
+}
+
+#[test]
+fn test_scan() {
+    let mut dict = Dict::new();
+    assert!(scan(&mut dict, |_| {}).is_empty());
+    for i in 0..100 {
+        dict.insert(i, i);
+    }
+    while dict.rehash(10) {}
+    let mut seen = scan(&mut dict, |_| {});
+    seen.sort();
+    assert_eq!(seen, (0..100).collect::<Vec<_>>());
+
+    // halfway through a rehash, every key is seen exactly once
+    for i in 100..200 {
+        dict.insert(i, i);
+    }
+    assert!(dict.is_rehashing());
+    let mut seen = scan(&mut dict, |_| {});
+    seen.sort();
+    assert_eq!(seen, (0..200).collect::<Vec<_>>());
+}
+
+#[test]
+fn test_scan_across_resizes() {
+    // keys 0..100 stay for the whole walk, while others come and go and
+    // the table grows to 1024 buckets and back
+    let mut dict = Dict::new();
+    for i in 0..100 {
+        dict.insert(i, i);
+    }
+    let mut next = 1000;
+    let seen: HashSet<_> = scan(&mut dict, |dict| {
+        for _ in 0..20 {
+            if next < 2000 {
+                dict.insert(next, next);
+                next += 1;
+            }
+        }
+        dict.rehash(1);
+    })
+    .into_iter()
+    .collect();
+    assert!((0..100).all(|key| seen.contains(&key)));
+    assert!(dict.buckets() >= 1024);
+
+    let seen: HashSet<_> = scan(&mut dict, |dict| {
+        for _ in 0..20 {
+            if next > 1000 {
+                next -= 1;
+                dict.remove(&next);
+            }
+        }
+        dict.rehash(1);
+    })
+    .into_iter()
+    .collect();
+    assert!((0..100).all(|key| seen.contains(&key)));
+    assert_eq!(dict.len(), 100);
+}
+
+#[test]
+fn test_sample() {
+    let mut state = 0x2545_f491_4f6c_dd1d_u64;
+    let mut random = move || {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        state
+    };
+    let mut dict = Dict::new();
+    assert!(dict.sample(5, &mut random).is_empty());
+    for i in 0..1000 {
+        dict.insert(i, i);
+    }
+    while dict.rehash(10) {}
+
+    let sampled = dict.sample(5, &mut random);
+    assert_eq!(sampled.len(), 5);
+    assert!(sampled
+        .iter()
+        .all(|(key, value)| key == value && **key < 1000));
+    let distinct: HashSet<_> = sampled.iter().map(|(key, _)| **key).collect();
+    assert_eq!(distinct.len(), 5);
+
+    // from all over the table, not one neighbourhood
+    let firsts: HashSet<_> = (0..1000)
+        .filter_map(|_| dict.sample(1, &mut random).first().map(|(key, _)| **key))
+        .collect();
+    assert!(firsts.len() > 300, "{} distinct", firsts.len());
+
+    // both tables while rehashing
+    for i in 1000..1100 {
+        dict.insert(i, i);
+    }
+    assert!(dict.is_rehashing());
+    let firsts: HashSet<_> = (0..2000)
+        .filter_map(|_| dict.sample(1, &mut random).first().map(|(key, _)| **key))
+        .collect();
+    assert!(firsts.iter().any(|key| *key >= 1000));
+    assert!(firsts.iter().any(|key| *key < 1000));
+}
@@ -0,0 +1,93 @@
+#![cfg(feature = "server")]
+
+use std::{
+    io::{Read, Write},
+    net::TcpStream,
+    time::Duration,
+};
+
+use rustis::{
+    config::{Config, MaxmemoryPolicy},
+    Server, ServerBuilder,
+};
+
+fn request(stream: &mut TcpStream, command: &[u8], reply_len: usize) -> Vec<u8> {
+    stream.write_all(command).unwrap();
+    let mut reply = vec![0u8; reply_len];
+    stream.read_exact(&mut reply).unwrap();
+    reply
+}
+
+#[test]
+fn test_embedded_server() {
+    let mut server = Server::builder()
+        .port(0)
+        .workers(2)
+        .maxmemory(64 << 20)
+        .maxmemory_policy(MaxmemoryPolicy::AllKeysLru)
+        .build()
+        .unwrap();
+    let addr = server.addr();
+    assert!(addr.ip().is_loopback());
+    assert_ne!(addr.port(), 0);
+    assert_eq!(server.config().workers, 2);
+    assert_eq!(server.config().maxmemory, 64 << 20);
+
+    server.start().unwrap();
+    assert!(server.start().is_err());
+
+    let mut client = TcpStream::connect(addr).unwrap();
+    client
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    // keys on both workers
+    for key in [b"a", b"b", b"c", b"d"] {
+        let mut set = b"*3\r\n$3\r\nSET\r\n$1\r\n".to_vec();
+        set.extend_from_slice(key);
+        set.extend_from_slice(b"\r\n$1\r\nv\r\n");
+        assert_eq!(request(&mut client, &set, 5), b"+OK\r\n");
+    }
+    assert_eq!(
+        request(&mut client, b"*1\r\n$6\r\nDBSIZE\r\n", 4),
+        b":4\r\n"
+    );
+
+    server.shutdown();
+    // the connection is closed, and nothing listens anymore
+    let mut rest = Vec::new();
+    assert_eq!(client.read_to_end(&mut rest).unwrap_or(0), 0);
+    assert!(TcpStream::connect(addr).is_err());
+    server.shutdown();
+}
+
+#[test]
+fn test_drop_shuts_down() {
+    let addr = {
+        let mut server = Server::builder().port(0).workers(1).build().unwrap();
+        server.start().unwrap();
+        let mut client = TcpStream::connect(server.addr()).unwrap();
+        assert_eq!(request(&mut client, b"PING\r\n", 7), b"+PONG\r\n");
+        server.addr()
+    };
+    assert!(TcpStream::connect(addr).is_err());
+}
+
+#[test]
+fn test_unpinned_workers() {
+    // as on a platform without affinity: the workers still serve every key
+    let config = Config {
+        port: 0,
+        worker_pinning: false,
+        worker_cpulist: Some(vec![0, 0, 0]),
+        ..Config::default()
+    };
+    let mut server = ServerBuilder::config(config).build().unwrap();
+    server.start().unwrap();
+    let mut client = TcpStream::connect(server.addr()).unwrap();
+    for i in 0..30 {
+        let set = format!("*3\r\n$3\r\nSET\r\n$2\r\nk{}\r\n$1\r\nv\r\n", i % 10);
+        assert_eq!(request(&mut client, set.as_bytes(), 5), b"+OK\r\n");
+    }
+    assert_eq!(request(&mut client, b"DBSIZE\r\n", 5), b":10\r\n");
+    server.shutdown();
+}
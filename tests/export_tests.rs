@@ -0,0 +1,288 @@
+#![cfg(all(feature = "cli", feature = "lists", feature = "sets"))]
+
+use std::{collections::BTreeMap, io::Read};
+
+use bytes::{Bytes, BytesMut};
+use rustis::{
+    cli::{CliConfig, Connection},
+    export::{self, Format, Importer, Reader, Record, RecordValue, Writer, IMPORT_BATCH},
+    handler::process_command,
+    kv::KvStore,
+    message::ResponseValue,
+    parser::parse_request,
+    Server,
+};
+
+fn make_cmd(args: &[&[u8]]) -> ResponseValue {
+    let items = args
+        .iter()
+        .map(|arg| ResponseValue::BulkString(Some(Bytes::copy_from_slice(arg))))
+        .collect();
+    ResponseValue::Array(Some(items))
+}
+
+/// Every key of `kv` `EXPORT` walks to, `count` buckets at a time.
+fn export_all(kv: &mut KvStore, pattern: Option<&str>, count: usize) -> Vec<Record> {
+    let mut records = Vec::new();
+    let mut cursor = "0".to_string();
+    loop {
+        let count = count.to_string();
+        let mut args: Vec<&[u8]> = vec![b"EXPORT", cursor.as_bytes(), b"COUNT", count.as_bytes()];
+        if let Some(pattern) = pattern {
+            args.extend([&b"MATCH"[..], pattern.as_bytes()]);
+        }
+        let ResponseValue::Array(Some(parts)) = process_command(kv, make_cmd(&args)) else {
+            panic!("EXPORT failed");
+        };
+        let [ResponseValue::BulkString(Some(next)), ResponseValue::Array(Some(entries))] =
+            &parts[..]
+        else {
+            panic!("malformed reply {parts:?}");
+        };
+        records.extend(
+            entries
+                .iter()
+                .map(|entry| Record::from_entry(entry.clone()).unwrap()),
+        );
+        if next[..] == *b"0" {
+            return sorted(records);
+        }
+        cursor = String::from_utf8(next.to_vec()).unwrap();
+    }
+}
+
+/// Records by key, sets' members in order, so dumps compare.
+fn sorted(records: Vec<Record>) -> Vec<Record> {
+    let mut by_key = BTreeMap::new();
+    for mut record in records {
+        if let RecordValue::Set(members) = &mut record.value {
+            members.sort();
+        }
+        by_key.insert(record.key.clone(), record);
+    }
+    by_key.into_values().collect()
+}
+
+fn record(key: &str, value: RecordValue) -> Record {
+    Record {
+        key: Bytes::copy_from_slice(key.as_bytes()),
+        ttl: -1,
+        value,
+    }
+}
+
+fn bytes(values: &[&[u8]]) -> Vec<Bytes> {
+    values
+        .iter()
+        .map(|value| Bytes::copy_from_slice(value))
+        .collect()
+}
+
+#[test]
+fn test_export_command() {
+    let mut kv = KvStore::new();
+    for i in 0..100 {
+        process_command(
+            &mut kv,
+            make_cmd(&[b"SET", format!("string:{i}").as_bytes(), b"v"]),
+        );
+    }
+    process_command(&mut kv, make_cmd(&[b"RPUSH", b"list", b"a", b"b", b"a"]));
+    process_command(&mut kv, make_cmd(&[b"SADD", b"set", b"1", b"x"]));
+
+    for count in [1, 3, 1000] {
+        let records = export_all(&mut kv, None, count);
+        assert_eq!(records.len(), 102, "COUNT {count}");
+    }
+    let records = export_all(&mut kv, Some("[ls][ie]*"), 10);
+    assert_eq!(
+        records,
+        vec![
+            record("list", RecordValue::List(bytes(&[b"a", b"b", b"a"]))),
+            record("set", RecordValue::Set(bytes(&[b"1", b"x"]))),
+        ]
+    );
+    assert_eq!(export_all(&mut kv, Some("string:4?"), 10).len(), 10,);
+
+    for (args, error) in [
+        (
+            &[&b"EXPORT"[..]][..],
+            "ERR wrong number of arguments for 'export' command",
+        ),
+        (&[b"EXPORT", b"-1"], "ERR invalid cursor"),
+        (&[b"EXPORT", b"x"], "ERR invalid cursor"),
+        (&[b"EXPORT", b"0", b"COUNT", b"0"], "ERR syntax error"),
+        (&[b"EXPORT", b"0", b"MATCH"], "ERR syntax error"),
+        (&[b"EXPORT", b"0", b"LIMIT", b"1"], "ERR syntax error"),
+    ] {
+        assert_eq!(
+            process_command(&mut kv, make_cmd(args)),
+            ResponseValue::Error(error.into())
+        );
+    }
+}
+
+fn dump_and_read(records: &[Record], format: Format) -> (Vec<u8>, Vec<Record>) {
+    let mut out = Vec::new();
+    let mut writer = Writer::new(&mut out, format);
+    for record in records {
+        writer.write(record).unwrap();
+    }
+    let mut reader = Reader::new(&out[..], format);
+    let mut read = Vec::new();
+    while let Some(record) = reader.next_record().unwrap() {
+        read.push(record);
+    }
+    (out, read)
+}
+
+#[test]
+fn test_dump_formats() {
+    let records = vec![
+        record("plain", RecordValue::String(Bytes::from("value"))),
+        record(
+            "quotes, commas\r\nand \"lines\"",
+            RecordValue::String(Bytes::from("日本語,\n\"")),
+        ),
+        record(
+            "binary",
+            RecordValue::String(Bytes::from(&b"\xff\x00\xfe"[..])),
+        ),
+        record("empty", RecordValue::String(Bytes::new())),
+        record(
+            "list",
+            RecordValue::List(bytes(&[b"a", b"", b"\x80", b"a,b", b"1234"])),
+        ),
+        record("set", RecordValue::Set(bytes(&[b"x", b"y"]))),
+    ];
+
+    let (json, read) = dump_and_read(&records, Format::Json);
+    assert_eq!(read, records);
+    let json = String::from_utf8(json).unwrap();
+    assert_eq!(
+        json.lines().next().unwrap(),
+        r#"{"key":"plain","type":"string","ttl":-1,"value":"value"}"#
+    );
+    assert!(json.contains(r#""value":{"base64":"/wD+"}"#), "{json}");
+    assert!(
+        json.contains(r#"["a","",{"base64":"gA=="},"a,b","1234"]"#),
+        "{json}"
+    );
+
+    // a row per element, read back as a record each
+    let (csv, read) = dump_and_read(&records, Format::Csv);
+    assert!(csv.starts_with(b"key,type,ttl,value\r\nplain,string,-1,value\r\n"));
+    assert_eq!(read.len(), 4 + 5 + 2);
+    assert_eq!(&read[..4], &records[..4]);
+    assert_eq!(
+        read[4 + 3],
+        record("list", RecordValue::List(bytes(&[b"a,b"])))
+    );
+
+    let mut reader = Reader::new(&b"{\"key\":\"k\"}\n"[..], Format::Json);
+    let err = reader.next_record().unwrap_err();
+    assert_eq!(err.to_string(), "line 1: no \"ttl\"");
+    let mut reader = Reader::new(&b"key,type,ttl,value\nk,hash,-1,v\n"[..], Format::Csv);
+    let err = reader.next_record().unwrap_err();
+    assert_eq!(err.to_string(), "line 2: unknown type \"hash\"");
+}
+
+#[test]
+fn test_import_keeps_string_ttls() {
+    let dump = br#"{"key":"session","type":"string","ttl":60000,"value":"v"}
+{"key":"plain","type":"string","ttl":-1,"value":"v"}
+{"key":"queue","type":"list","ttl":60000,"value":["a"]}
+"#;
+    let mut importer = Importer::new(&dump[..], Format::Json);
+    let mut resp = Vec::new();
+    importer.read_to_end(&mut resp).unwrap();
+    assert_eq!(importer.keys, 3);
+    assert_eq!(importer.ttls_dropped, 1);
+
+    let mut kv = KvStore::new();
+    let mut resp = BytesMut::from(&resp[..]);
+    while !resp.is_empty() {
+        let command = parse_request(&mut resp).unwrap();
+        process_command(&mut kv, command);
+    }
+    let ttls: Vec<i64> = export_all(&mut kv, None, 10)
+        .iter()
+        .map(|record| record.ttl)
+        .collect();
+    // plain, queue, session
+    assert_eq!(ttls[..2], [-1, -1]);
+    assert!((1..=60000).contains(&ttls[2]), "{ttls:?}");
+}
+
+/// `EXPORT` of every key over `connection`, sorted.
+fn export_server(connection: &mut Connection, format: Format) -> (Vec<u8>, Vec<Record>) {
+    let mut out = Vec::new();
+    let mut writer = Writer::new(&mut out, format);
+    export::export(connection, None, &mut writer, |err| panic!("{err}")).unwrap();
+    let mut reader = Reader::new(&out[..], format);
+    let mut records = Vec::new();
+    while let Some(record) = reader.next_record().unwrap() {
+        records.push(record);
+    }
+    (out, records)
+}
+
+#[test]
+fn test_export_and_import_between_servers() {
+    let mut source = Server::builder().port(0).workers(3).build().unwrap();
+    source.start().unwrap();
+    let mut target = Server::builder().port(0).workers(2).build().unwrap();
+    target.start().unwrap();
+    let connect = |server: &Server| {
+        Connection::open(&CliConfig {
+            port: server.addr().port(),
+            ..CliConfig::default()
+        })
+        .unwrap()
+    };
+    let mut from = connect(&source);
+    let mut to = connect(&target);
+
+    for i in 0..500 {
+        let key = format!("key:{i}").into_bytes();
+        from.command(&[b"SET".to_vec(), key, vec![i as u8; i]])
+            .unwrap();
+    }
+    let mut push = vec![b"RPUSH".to_vec(), b"long".to_vec()];
+    push.extend((0..IMPORT_BATCH * 2 + 1).map(|i| i.to_string().into_bytes()));
+    from.command(&push).unwrap();
+    from.command(&[
+        b"SADD".to_vec(),
+        b"set".to_vec(),
+        b"a".to_vec(),
+        b"7".to_vec(),
+    ])
+    .unwrap();
+    // replaced, not added to
+    to.command(&[b"RPUSH".to_vec(), b"long".to_vec(), b"old".to_vec()])
+        .unwrap();
+
+    let (json, exported) = export_server(&mut from, Format::Json);
+    assert_eq!(exported.len(), 502);
+    let mut importer = Importer::new(&json[..], Format::Json);
+    let report = to
+        .pipe(&mut importer, |err| panic!("{err:?}"), || {})
+        .unwrap();
+    assert_eq!(report.errors, 0);
+    assert_eq!(importer.keys, 502);
+    assert_eq!(importer.ttls_dropped, 0);
+    let (_, imported) = export_server(&mut to, Format::Json);
+    assert_eq!(sorted(imported), sorted(exported.clone()));
+
+    // and again from CSV, over what's there
+    let (csv, _) = export_server(&mut from, Format::Csv);
+    let mut importer = Importer::new(&csv[..], Format::Csv);
+    to.pipe(&mut importer, |err| panic!("{err:?}"), || {})
+        .unwrap();
+    assert_eq!(importer.keys, 502);
+    let (_, imported) = export_server(&mut to, Format::Json);
+    assert_eq!(sorted(imported), sorted(exported));
+
+    source.shutdown();
+    target.shutdown();
+}
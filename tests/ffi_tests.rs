@@ -0,0 +1,109 @@
+#![cfg(feature = "ffi")]
+
+use std::{ptr, slice, thread};
+
+use rustis::ffi::{
+    rustis_execute, rustis_reply_data, rustis_reply_free, rustis_reply_len, rustis_store,
+    rustis_store_free, rustis_store_new, INCOMPLETE_ERROR,
+};
+
+/// Runs `request` through the C ABI, as C would, and returns the reply.
+fn execute(store: *const rustis_store, request: &[u8]) -> Vec<u8> {
+    unsafe {
+        let reply = rustis_execute(store, request.as_ptr(), request.len());
+        assert!(!reply.is_null());
+        let bytes = slice::from_raw_parts(rustis_reply_data(reply), rustis_reply_len(reply));
+        let bytes = bytes.to_vec();
+        rustis_reply_free(reply);
+        bytes
+    }
+}
+
+#[test]
+fn test_set_and_get() {
+    let store = rustis_store_new();
+    assert_eq!(
+        execute(store, b"*3\r\n$3\r\nSET\r\n$3\r\nkey\r\n$5\r\nvalue\r\n"),
+        b"+OK\r\n"
+    );
+    assert_eq!(
+        execute(store, b"*2\r\n$3\r\nGET\r\n$3\r\nkey\r\n"),
+        b"$5\r\nvalue\r\n"
+    );
+    assert_eq!(
+        execute(store, b"*2\r\n$3\r\nGET\r\n$4\r\nnone\r\n"),
+        b"$-1\r\n"
+    );
+    unsafe { rustis_store_free(store) };
+}
+
+#[test]
+fn test_pipeline() {
+    let store = rustis_store_new();
+    assert_eq!(
+        execute(
+            store,
+            b"SET a 1\r\nSET a 2\r\n*2\r\n$3\r\nGET\r\n$1\r\na\r\nDBSIZE\r\n"
+        ),
+        b"+OK\r\n+OK\r\n$1\r\n2\r\n:1\r\n"
+    );
+    assert_eq!(execute(store, b""), b"");
+    unsafe { rustis_store_free(store) };
+}
+
+#[test]
+fn test_bad_requests() {
+    let store = rustis_store_new();
+    // the rest of a broken request is ignored
+    let reply = execute(store, b"SET a 1\r\n*1\r\n$x\r\nSET b 2\r\n");
+    assert!(
+        reply.starts_with(b"+OK\r\n-ERR Protocol error"),
+        "{reply:?}"
+    );
+    assert_eq!(reply.iter().filter(|&&byte| byte == b'\n').count(), 2);
+    assert_eq!(
+        execute(store, b"SET c 3\r\n*2\r\n$3\r\nGET\r\n"),
+        format!("+OK\r\n-{INCOMPLETE_ERROR}\r\n").as_bytes()
+    );
+    assert_eq!(execute(store, b"EXISTS a b c\r\n"), b":2\r\n");
+    assert!(execute(store, b"NOSUCHCOMMAND\r\n").starts_with(b"-"));
+    unsafe { rustis_store_free(store) };
+}
+
+#[test]
+fn test_null() {
+    unsafe {
+        assert!(rustis_execute(ptr::null(), b"PING\r\n".as_ptr(), 6).is_null());
+        rustis_store_free(ptr::null_mut());
+        rustis_reply_free(ptr::null_mut());
+    }
+    let store = rustis_store_new();
+    unsafe {
+        let reply = rustis_execute(store, ptr::null(), 0);
+        assert_eq!(rustis_reply_len(reply), 0);
+        rustis_reply_free(reply);
+        rustis_store_free(store);
+    }
+}
+
+#[test]
+fn test_shared_between_threads() {
+    struct Shared(*const rustis_store);
+    unsafe impl Sync for Shared {}
+
+    let store = Shared(rustis_store_new());
+    thread::scope(|scope| {
+        for thread in 0..4 {
+            let store = &store;
+            scope.spawn(move || {
+                for i in 0..250 {
+                    let request = format!("SET {thread}:{i} {i}\r\nGET {thread}:{i}\r\n");
+                    let expected = format!("+OK\r\n${}\r\n{i}\r\n", i.to_string().len());
+                    assert_eq!(execute(store.0, request.as_bytes()), expected.as_bytes());
+                }
+            });
+        }
+    });
+    assert_eq!(execute(store.0, b"DBSIZE\r\n"), b":1000\r\n");
+    unsafe { rustis_store_free(store.0.cast_mut()) };
+}
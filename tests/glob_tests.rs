@@ -0,0 +1,39 @@
+use rustis::glob::glob_match;
+
+#[test]
+fn test_glob_match() {
+    for (pattern, string, expected) in [
+        ("*", "", true),
+        ("*", "anything", true),
+        ("user:*", "user:42", true),
+        ("user:*", "users:42", false),
+        ("*:*:name", "a:b:name", true),
+        ("*:*:name", "a:name", false),
+        ("h?llo", "hello", true),
+        ("h?llo", "hllo", false),
+        ("h[ae]llo", "hallo", true),
+        ("h[ae]llo", "hillo", false),
+        ("h[^e]llo", "hallo", true),
+        ("h[^e]llo", "hello", false),
+        ("h[a-c]llo", "hbllo", true),
+        ("h[c-a]llo", "hbllo", true),
+        ("h[a-c]llo", "hdllo", false),
+        ("h\\*llo", "h*llo", true),
+        ("h\\*llo", "hello", false),
+        ("[\\]]", "]", true),
+        ("a*b*c", "aXbYbZc", true),
+        ("a*b*c", "aXbYbZ", false),
+        ("**", "x", true),
+        // a class left open runs to the end
+        ("h[ab", "ha", true),
+        ("", "", true),
+        ("", "a", false),
+    ] {
+        assert_eq!(
+            glob_match(pattern.as_bytes(), string.as_bytes()),
+            expected,
+            "{pattern:?} against {string:?}"
+        );
+    }
+    assert!(glob_match(b"\xff*", b"\xff\x00\x01"));
+}
@@ -1,8 +1,9 @@
 #[cfg(test)]
 mod tests {
     use bytes::Bytes;
-    use rustis::handler::process_command;
-    use rustis::kv::KvStore;
+    use rustis::config::MAX_EXPIRE_JITTER_PERCENT;
+    use rustis::handler::{arity_error, command_name, process_command, COMPACT_THRESHOLD};
+    use rustis::kv::{unix_time_ms, KvStore};
     use rustis::message::ResponseValue;
 
     // Helper to construct a command request (Array of BulkStrings)
@@ -26,42 +27,258 @@ mod tests {
 
     #[test]
     fn test_ping() {
-        let kv = KvStore::new();
-        let res = process_command(&kv, make_cmd(vec!["PING"]));
+        let mut kv = KvStore::new();
+        let res = process_command(&mut kv, make_cmd(vec!["PING"]));
         assert_eq!(res, ResponseValue::SimpleString("PONG".into()));
     }
 
+    #[test]
+    fn test_config() {
+        // as the router answers it
+        let mut kv = KvStore::new();
+        let res = process_command(&mut kv, make_cmd(vec!["CONFIG", "GET", "save"]));
+        assert_eq!(res, ResponseValue::Array(Some(vec![])));
+        let res = process_command(&mut kv, make_cmd(vec!["CONFIG", "SET", "save", ""]));
+        assert_eq!(
+            res,
+            ResponseValue::Error("ERR unsupported CONFIG subcommand".into())
+        );
+    }
+
     #[test]
     fn test_set_get() {
-        let kv = KvStore::new();
+        let mut kv = KvStore::new();
 
         // SET key value
-        let res = process_command(&kv, make_cmd(vec!["SET", "mykey", "hello"]));
+        let res = process_command(&mut kv, make_cmd(vec!["SET", "mykey", "hello"]));
         assert_eq!(res, ResponseValue::SimpleString("OK".into()));
 
         // GET key
-        let res = process_command(&kv, make_cmd(vec!["GET", "mykey"]));
+        let res = process_command(&mut kv, make_cmd(vec!["GET", "mykey"]));
         assert_eq!(extract_str(res), "hello");
 
         // GET missing
-        let res = process_command(&kv, make_cmd(vec!["GET", "missing"]));
+        let res = process_command(&mut kv, make_cmd(vec!["GET", "missing"]));
+        assert_eq!(res, ResponseValue::BulkString(None));
+    }
+
+    #[test]
+    fn test_set_get_option_and_getset() {
+        let mut kv = KvStore::new();
+
+        // SET ... GET on a missing key replies nil and still sets
+        let res = process_command(&mut kv, make_cmd(vec!["SET", "k", "v1", "GET"]));
+        assert_eq!(res, ResponseValue::BulkString(None));
+
+        let res = process_command(&mut kv, make_cmd(vec!["SET", "k", "v2", "get"]));
+        assert_eq!(extract_str(res), "v1");
+
+        let res = process_command(&mut kv, make_cmd(vec!["GETSET", "k", "v3"]));
+        assert_eq!(extract_str(res), "v2");
+
+        let res = process_command(&mut kv, make_cmd(vec!["GET", "k"]));
+        assert_eq!(extract_str(res), "v3");
+
+        let res = process_command(&mut kv, make_cmd(vec!["SET", "k", "v4", "NOPE"]));
+        assert_eq!(extract_str(res), "ERR syntax error");
+
+        let res = process_command(&mut kv, make_cmd(vec!["GETSET", "k"]));
+        assert_eq!(
+            extract_str(res),
+            "ERR wrong number of arguments for 'getset' command"
+        );
+    }
+
+    #[test]
+    fn test_set_conditions_and_expiry() {
+        let mut kv = KvStore::new();
+
+        let res = process_command(&mut kv, make_cmd(vec!["SET", "k", "v1", "XX"]));
+        assert_eq!(res, ResponseValue::BulkString(None));
+        let res = process_command(&mut kv, make_cmd(vec!["SETNX", "k", "v1"]));
+        assert_eq!(res, ResponseValue::Integer(1));
+        let res = process_command(&mut kv, make_cmd(vec!["SETNX", "k", "v2"]));
+        assert_eq!(res, ResponseValue::Integer(0));
+        let res = process_command(&mut kv, make_cmd(vec!["SET", "k", "v2", "NX", "GET"]));
+        assert_eq!(extract_str(res), "v1");
+        let res = process_command(
+            &mut kv,
+            make_cmd(vec!["SET", "k", "v2", "XX", "PX", "60000"]),
+        );
+        assert_eq!(res, ResponseValue::SimpleString("OK".into()));
+        let ttl = kv.expires_at(&Bytes::from("k")).unwrap() - unix_time_ms();
+        assert!(ttl <= 60_000 && ttl > 50_000);
+
+        // KEEPTTL keeps it, a plain SET clears it
+        process_command(&mut kv, make_cmd(vec!["SET", "k", "v3", "KEEPTTL"]));
+        assert!(kv.expires_at(&Bytes::from("k")).is_some());
+        process_command(&mut kv, make_cmd(vec!["SET", "k", "v4"]));
+        assert_eq!(kv.expires_at(&Bytes::from("k")), None);
+
+        let res = process_command(&mut kv, make_cmd(vec!["SETEX", "k", "100", "v5"]));
+        assert_eq!(res, ResponseValue::SimpleString("OK".into()));
+        let ttl = kv.expires_at(&Bytes::from("k")).unwrap() - unix_time_ms();
+        assert!(ttl <= 100_000 && ttl > 90_000);
+
+        // a time already past expires the key at once
+        process_command(&mut kv, make_cmd(vec!["SET", "k", "v6", "PXAT", "1"]));
+        let res = process_command(&mut kv, make_cmd(vec!["GET", "k"]));
         assert_eq!(res, ResponseValue::BulkString(None));
+        let res = process_command(&mut kv, make_cmd(vec!["SETNX", "k", "v7"]));
+        assert_eq!(res, ResponseValue::Integer(1));
+
+        process_command(&mut kv, make_cmd(vec!["PSETEX", "k", "20", "v8"]));
+        std::thread::sleep(std::time::Duration::from_millis(40));
+        let res = process_command(&mut kv, make_cmd(vec!["GET", "k"]));
+        assert_eq!(res, ResponseValue::BulkString(None));
+
+        for (args, error) in [
+            (vec!["SET", "k", "v", "NX", "XX"], "ERR syntax error"),
+            (
+                vec!["SET", "k", "v", "EX", "10", "PX", "10"],
+                "ERR syntax error",
+            ),
+            (
+                vec!["SET", "k", "v", "EX", "10", "KEEPTTL"],
+                "ERR syntax error",
+            ),
+            (vec!["SET", "k", "v", "EX"], "ERR syntax error"),
+            (
+                vec!["SET", "k", "v", "EX", "0"],
+                "ERR invalid expire time in 'set' command",
+            ),
+            (
+                vec!["SET", "k", "v", "EX", "ten"],
+                "ERR value is not an integer or out of range",
+            ),
+            (
+                vec!["SET", "k", "v", "EX", "9223372036854775807"],
+                "ERR invalid expire time in 'set' command",
+            ),
+            (
+                vec!["SETEX", "k", "-1", "v"],
+                "ERR invalid expire time in 'setex' command",
+            ),
+            (
+                vec!["PSETEX", "k", "0", "v"],
+                "ERR invalid expire time in 'psetex' command",
+            ),
+            (
+                vec!["SETNX", "k"],
+                "ERR wrong number of arguments for 'setnx' command",
+            ),
+            (
+                vec!["SETEX", "k", "10"],
+                "ERR wrong number of arguments for 'setex' command",
+            ),
+        ] {
+            let res = process_command(&mut kv, make_cmd(args.clone()));
+            assert_eq!(extract_str(res), error, "{args:?}");
+        }
+    }
+
+    /// What each way of setting a string does to a key already holding a
+    /// value of each type, and to its TTL. Hashes and sorted sets aren't
+    /// types here yet.
+    #[cfg(all(feature = "lists", feature = "sets"))]
+    #[test]
+    fn test_set_type_transitions() {
+        use rustis::kv::RedisValue;
+
+        let setups: [(&str, &[&str]); 3] = [
+            ("string", &["SET", "k", "old"]),
+            ("list", &["RPUSH", "k", "old"]),
+            ("set", &["SADD", "k", "old"]),
+        ];
+        let key = Bytes::from("k");
+        let is_string = |kv: &KvStore, expected: &str| {
+            kv.get(&Bytes::from("k"))
+                == Some(&RedisValue::String(Bytes::from(expected.to_owned()).into()))
+        };
+        for (kind, setup) in setups {
+            let fresh = || {
+                let mut kv = KvStore::new();
+                process_command(&mut kv, make_cmd(setup.to_vec()));
+                kv.set_expires_at(&Bytes::from("k"), Some(unix_time_ms() + 60_000));
+                kv
+            };
+            let old_type = |kv: &KvStore| kv.object_encoding(&Bytes::from("k"));
+            let untouched = |kv: &KvStore, before| {
+                old_type(kv) == before && kv.expires_at(&Bytes::from("k")).is_some()
+            };
+
+            // plain SET replaces any type, and clears the TTL
+            let mut kv = fresh();
+            let res = process_command(&mut kv, make_cmd(vec!["SET", "k", "new"]));
+            assert_eq!(res, ResponseValue::SimpleString("OK".into()), "{kind}");
+            assert!(is_string(&kv, "new"), "{kind}");
+            assert_eq!(kv.expires_at(&key), None, "{kind}");
+
+            // XX replaces any type too, and KEEPTTL keeps the TTL across it
+            let mut kv = fresh();
+            let res = process_command(&mut kv, make_cmd(vec!["SET", "k", "new", "XX", "KEEPTTL"]));
+            assert_eq!(res, ResponseValue::SimpleString("OK".into()), "{kind}");
+            assert!(is_string(&kv, "new"), "{kind}");
+            assert!(kv.expires_at(&key).is_some(), "{kind}");
+
+            // NX and SETNX see a key of any type as existing
+            let mut kv = fresh();
+            let before = old_type(&kv);
+            let res = process_command(&mut kv, make_cmd(vec!["SET", "k", "new", "NX"]));
+            assert_eq!(res, ResponseValue::BulkString(None), "{kind}");
+            let res = process_command(&mut kv, make_cmd(vec!["SETNX", "k", "new"]));
+            assert_eq!(res, ResponseValue::Integer(0), "{kind}");
+            assert!(untouched(&kv, before), "{kind}");
+
+            // GET, and GETSET, can't read another type's value, so they
+            // leave the key alone
+            for get in [vec!["SET", "k", "new", "GET"], vec!["GETSET", "k", "new"]] {
+                let mut kv = fresh();
+                let before = old_type(&kv);
+                let res = process_command(&mut kv, make_cmd(get));
+                if kind == "string" {
+                    assert_eq!(extract_str(res), "old");
+                    assert!(is_string(&kv, "new"));
+                    assert_eq!(kv.expires_at(&key), None);
+                } else {
+                    assert!(extract_str(res).starts_with(b"WRONGTYPE"), "{kind}");
+                    assert!(untouched(&kv, before), "{kind}");
+                }
+            }
+        }
+    }
+
+    #[cfg(feature = "lists")]
+    #[test]
+    fn test_getset_wrongtype_leaves_key() {
+        let mut kv = KvStore::new();
+        process_command(&mut kv, make_cmd(vec!["RPUSH", "list", "a"]));
+
+        let res = process_command(&mut kv, make_cmd(vec!["GETSET", "list", "v"]));
+        assert!(extract_str(res).starts_with(b"WRONGTYPE"));
+
+        let res = process_command(&mut kv, make_cmd(vec!["LRANGE", "list", "0", "-1"]));
+        assert_eq!(
+            res,
+            ResponseValue::Array(Some(vec![ResponseValue::BulkString(Some("a".into()))]))
+        );
     }
 
+    #[cfg(feature = "lists")]
     #[test]
     fn test_list_integration() {
-        let kv = KvStore::new();
+        let mut kv = KvStore::new();
 
         // LPUSH list a
-        let res = process_command(&kv, make_cmd(vec!["LPUSH", "mylist", "a"]));
+        let res = process_command(&mut kv, make_cmd(vec!["LPUSH", "mylist", "a"]));
         assert_eq!(res, ResponseValue::Integer(1));
 
         // RPUSH list b
-        let res = process_command(&kv, make_cmd(vec!["RPUSH", "mylist", "b"]));
+        let res = process_command(&mut kv, make_cmd(vec!["RPUSH", "mylist", "b"]));
         assert_eq!(res, ResponseValue::Integer(2));
 
         // LRANGE list 0 -1 (expect ["a", "b"])
-        let res = process_command(&kv, make_cmd(vec!["LRANGE", "mylist", "0", "-1"]));
+        let res = process_command(&mut kv, make_cmd(vec!["LRANGE", "mylist", "0", "-1"]));
         if let ResponseValue::Array(Some(items)) = res {
             assert_eq!(items.len(), 2);
             assert_eq!(extract_str(items[0].clone()), "a");
@@ -71,20 +288,21 @@ mod tests {
         }
 
         // LPOP list (default count 1, returns BulkString("a"))
-        let res = process_command(&kv, make_cmd(vec!["LPOP", "mylist"]));
+        let res = process_command(&mut kv, make_cmd(vec!["LPOP", "mylist"]));
         assert_eq!(extract_str(res), "a");
     }
 
+    #[cfg(feature = "sets")]
     #[test]
     fn test_set_integration() {
-        let kv = KvStore::new();
+        let mut kv = KvStore::new();
 
         // SADD set val
-        let res = process_command(&kv, make_cmd(vec!["SADD", "myset", "val"]));
+        let res = process_command(&mut kv, make_cmd(vec!["SADD", "myset", "val"]));
         assert_eq!(res, ResponseValue::Integer(1));
 
         // SMEMBERS set
-        let res = process_command(&kv, make_cmd(vec!["SMEMBERS", "myset"]));
+        let res = process_command(&mut kv, make_cmd(vec!["SMEMBERS", "myset"]));
         if let ResponseValue::Array(Some(items)) = res {
             assert_eq!(items.len(), 1);
             assert_eq!(extract_str(items[0].clone()), "val");
@@ -92,30 +310,580 @@ mod tests {
             panic!("Expected Array response for SMEMBERS");
         }
 
-        // SPOP set (returns Array because logic might vary, but handle_spop returns Array for consistency if >1,
-        // though your specific implementation wraps it in Array regardless for single item?)
-        // Checking your implementation: handle_spop maps everything to Array regardless of count.
-        let res = process_command(&kv, make_cmd(vec!["SPOP", "myset"]));
-        if let ResponseValue::Array(Some(items)) = res {
-            assert_eq!(items.len(), 1);
-            assert_eq!(extract_str(items[0].clone()), "val");
-        } else {
-            panic!("Expected Array response for SPOP");
+        // SPOP set (no count: a bulk string, as Redis replies)
+        let res = process_command(&mut kv, make_cmd(vec!["SPOP", "myset"]));
+        assert_eq!(extract_str(res), "val");
+    }
+
+    #[cfg(feature = "sets")]
+    #[test]
+    fn test_sscan() {
+        let mut kv = KvStore::new();
+        let members: Vec<String> = (0..25).map(|i| format!("m{i}")).collect();
+        let mut sadd = vec!["SADD", "set"];
+        sadd.extend(members.iter().map(String::as_str));
+        process_command(&mut kv, make_cmd(sadd));
+
+        // a cursor per page, 0 after the last
+        let mut seen = Vec::new();
+        let mut cursors = Vec::new();
+        let mut cursor = Bytes::from("0");
+        loop {
+            let at = String::from_utf8(cursor.to_vec()).unwrap();
+            let res = process_command(&mut kv, make_cmd(vec!["SSCAN", "set", &at, "count", "10"]));
+            let ResponseValue::Array(Some(reply)) = res else {
+                panic!("Expected Array response for SSCAN");
+            };
+            let ResponseValue::Array(Some(page)) = &reply[1] else {
+                panic!("Expected Array of members");
+            };
+            seen.extend(page.iter().cloned().map(extract_str));
+            cursor = extract_str(reply[0].clone());
+            cursors.push(cursor.clone());
+            if cursor == "0" {
+                break;
+            }
+        }
+        assert_eq!(cursors, ["15", "5", "0"].map(Bytes::from));
+        seen.sort();
+        let mut expected: Vec<Bytes> = members.into_iter().map(Bytes::from).collect();
+        expected.sort();
+        assert_eq!(seen, expected);
+
+        // COUNT defaults to 10, and a missing key is an empty set
+        let res = process_command(&mut kv, make_cmd(vec!["SSCAN", "set", "5"]));
+        assert!(matches!(res, ResponseValue::Array(Some(reply))
+            if matches!(&reply[1], ResponseValue::Array(Some(page)) if page.len() == 5)));
+        let res = process_command(&mut kv, make_cmd(vec!["SSCAN", "nope", "0"]));
+        assert_eq!(
+            res,
+            ResponseValue::Array(Some(vec![
+                ResponseValue::BulkString(Some("0".into())),
+                ResponseValue::Array(Some(vec![])),
+            ]))
+        );
+
+        let res = process_command(&mut kv, make_cmd(vec!["SSCAN", "set", "x"]));
+        assert_eq!(extract_str(res), "ERR invalid cursor");
+        let res = process_command(&mut kv, make_cmd(vec!["SSCAN", "set", "0", "COUNT", "0"]));
+        assert_eq!(extract_str(res), "ERR syntax error");
+        let res = process_command(&mut kv, make_cmd(vec!["SSCAN", "set", "0", "MATCH", "m*"]));
+        assert_eq!(extract_str(res), "ERR syntax error");
+        process_command(&mut kv, make_cmd(vec!["SET", "str", "v"]));
+        let res = process_command(&mut kv, make_cmd(vec!["SSCAN", "str", "0"]));
+        assert!(extract_str(res).starts_with(b"WRONGTYPE"));
+
+        // an intset comes whole
+        process_command(&mut kv, make_cmd(vec!["SADD", "ints", "3", "1", "2"]));
+        let res = process_command(&mut kv, make_cmd(vec!["SSCAN", "ints", "0", "COUNT", "1"]));
+        let bulk = |s: &str| ResponseValue::BulkString(Some(Bytes::copy_from_slice(s.as_bytes())));
+        assert_eq!(
+            res,
+            ResponseValue::Array(Some(vec![
+                bulk("0"),
+                ResponseValue::Array(Some(vec![bulk("1"), bulk("2"), bulk("3")])),
+            ]))
+        );
+    }
+
+    #[cfg(feature = "sets")]
+    #[test]
+    fn test_sscan_during_spop() {
+        let mut kv = KvStore::new();
+        let members: Vec<String> = (0..100).map(|i| format!("m{i}")).collect();
+        let mut sadd = vec!["SADD", "set"];
+        sadd.extend(members.iter().map(String::as_str));
+        process_command(&mut kv, make_cmd(sadd));
+
+        // whatever is popped between pages, every member left is returned
+        let mut seen = std::collections::HashSet::new();
+        let mut cursor = Bytes::from("0");
+        loop {
+            let at = String::from_utf8(cursor.to_vec()).unwrap();
+            let res = process_command(&mut kv, make_cmd(vec!["SSCAN", "set", &at, "COUNT", "7"]));
+            let ResponseValue::Array(Some(reply)) = res else {
+                panic!("Expected Array response for SSCAN");
+            };
+            let ResponseValue::Array(Some(page)) = &reply[1] else {
+                panic!("Expected Array of members");
+            };
+            seen.extend(page.iter().cloned().map(extract_str));
+            cursor = extract_str(reply[0].clone());
+            if cursor == "0" {
+                break;
+            }
+            process_command(&mut kv, make_cmd(vec!["SPOP", "set", "3"]));
         }
+        let res = process_command(&mut kv, make_cmd(vec!["SMEMBERS", "set"]));
+        let ResponseValue::Array(Some(left)) = res else {
+            panic!("Expected Array response for SMEMBERS");
+        };
+        assert!(left.len() < 100);
+        assert!(left
+            .into_iter()
+            .map(extract_str)
+            .all(|member| seen.contains(&member)));
+    }
+
+    #[cfg(feature = "lists")]
+    #[test]
+    fn test_pop_counts() {
+        let mut kv = KvStore::new();
+        let bulks = |items: &[&str]| {
+            ResponseValue::Array(Some(
+                items
+                    .iter()
+                    .map(|item| {
+                        ResponseValue::BulkString(Some(Bytes::copy_from_slice(item.as_bytes())))
+                    })
+                    .collect(),
+            ))
+        };
+        process_command(
+            &mut kv,
+            make_cmd(vec!["RPUSH", "list", "a", "b", "c", "d", "e"]),
+        );
+
+        let res = process_command(&mut kv, make_cmd(vec!["LPOP", "list", "0"]));
+        assert_eq!(res, bulks(&[]));
+        let res = process_command(&mut kv, make_cmd(vec!["LPOP", "list", "1"]));
+        assert_eq!(res, bulks(&["a"]));
+        // rightmost first, as they come off
+        let res = process_command(&mut kv, make_cmd(vec!["RPOP", "list", "2"]));
+        assert_eq!(res, bulks(&["e", "d"]));
+        let res = process_command(&mut kv, make_cmd(vec!["RPOP", "list"]));
+        assert_eq!(extract_str(res), "c");
+        for pop in ["LPOP", "RPOP"] {
+            let res = process_command(&mut kv, make_cmd(vec![pop, "list", "-1"]));
+            assert_eq!(
+                extract_str(res),
+                "ERR value is out of range, must be positive"
+            );
+        }
+        let res = process_command(&mut kv, make_cmd(vec!["LPOP", "list", "5"]));
+        assert_eq!(res, bulks(&["b"]));
+
+        // the list is gone
+        let res = process_command(&mut kv, make_cmd(vec!["LPOP", "list"]));
+        assert_eq!(res, ResponseValue::BulkString(None));
+        let res = process_command(&mut kv, make_cmd(vec!["RPOP", "list", "0"]));
+        assert_eq!(res, ResponseValue::Array(None));
+
+        process_command(&mut kv, make_cmd(vec!["SET", "s", "v"]));
+        let res = process_command(&mut kv, make_cmd(vec!["LPOP", "s", "1"]));
+        assert!(extract_str(res).starts_with(b"WRONGTYPE"));
+        for push in ["LPUSH", "RPUSH"] {
+            let res = process_command(&mut kv, make_cmd(vec![push, "s", "x"]));
+            assert!(extract_str(res).starts_with(b"WRONGTYPE"), "{push}");
+        }
+        let res = process_command(&mut kv, make_cmd(vec!["RPOP", "s", "1", "2"]));
+        assert_eq!(
+            extract_str(res),
+            "ERR wrong number of arguments for 'rpop' command"
+        );
+    }
+
+    #[cfg(feature = "sets")]
+    #[test]
+    fn test_spop_reply_shapes() {
+        let mut kv = KvStore::new();
+        process_command(&mut kv, make_cmd(vec!["SADD", "myset", "a", "b", "c"]));
+
+        let res = process_command(&mut kv, make_cmd(vec!["SPOP", "myset", "1"]));
+        assert!(matches!(res, ResponseValue::Array(Some(items)) if items.len() == 1));
+        let res = process_command(&mut kv, make_cmd(vec!["SPOP", "myset", "0"]));
+        assert_eq!(res, ResponseValue::Array(Some(vec![])));
+        let res = process_command(&mut kv, make_cmd(vec!["SPOP", "myset", "-1"]));
+        assert_eq!(
+            extract_str(res),
+            "ERR value is out of range, must be positive"
+        );
+        let res = process_command(&mut kv, make_cmd(vec!["SPOP", "myset", "10"]));
+        assert!(matches!(res, ResponseValue::Array(Some(items)) if items.len() == 2));
+
+        // the set is gone
+        let res = process_command(&mut kv, make_cmd(vec!["SPOP", "myset"]));
+        assert_eq!(res, ResponseValue::BulkString(None));
+        let res = process_command(&mut kv, make_cmd(vec!["SPOP", "myset", "2"]));
+        assert_eq!(res, ResponseValue::Array(Some(vec![])));
+
+        process_command(&mut kv, make_cmd(vec!["SET", "s", "v"]));
+        let res = process_command(&mut kv, make_cmd(vec!["SPOP", "s"]));
+        assert!(extract_str(res).starts_with(b"WRONGTYPE"));
+        let res = process_command(&mut kv, make_cmd(vec!["SADD", "s", "x"]));
+        assert!(extract_str(res).starts_with(b"WRONGTYPE"));
+        let res = process_command(&mut kv, make_cmd(vec!["SPOP", "s", "1", "2"]));
+        assert_eq!(extract_str(res), "ERR syntax error");
+    }
+
+    #[cfg(feature = "lists")]
+    #[test]
+    fn test_multi_key_commands() {
+        let mut kv = KvStore::new();
+
+        let res = process_command(&mut kv, make_cmd(vec!["MSET", "a", "1", "b", "2"]));
+        assert_eq!(res, ResponseValue::SimpleString("OK".into()));
+        process_command(&mut kv, make_cmd(vec!["LPUSH", "list", "x"]));
+
+        let res = process_command(&mut kv, make_cmd(vec!["MGET", "a", "missing", "list", "b"]));
+        assert_eq!(
+            res,
+            ResponseValue::Array(Some(vec![
+                ResponseValue::BulkString(Some("1".into())),
+                ResponseValue::BulkString(None),
+                ResponseValue::BulkString(None),
+                ResponseValue::BulkString(Some("2".into())),
+            ]))
+        );
+
+        let res = process_command(&mut kv, make_cmd(vec!["EXISTS", "a", "a", "missing"]));
+        assert_eq!(res, ResponseValue::Integer(2));
+
+        let res = process_command(&mut kv, make_cmd(vec!["DEL", "a", "list", "missing", "a"]));
+        assert_eq!(res, ResponseValue::Integer(2));
+        let res = process_command(&mut kv, make_cmd(vec!["EXISTS", "a", "list", "b"]));
+        assert_eq!(res, ResponseValue::Integer(1));
+
+        let res = process_command(&mut kv, make_cmd(vec!["MSET", "a"]));
+        assert!(matches!(res, ResponseValue::Error(_)));
+    }
+
+    #[test]
+    fn test_unlink_and_async_flush() {
+        let mut kv = KvStore::new();
+        process_command(
+            &mut kv,
+            make_cmd(vec!["MSET", "a", "1", "b", "2", "c", "3"]),
+        );
+
+        let res = process_command(&mut kv, make_cmd(vec!["UNLINK", "a", "missing"]));
+        assert_eq!(res, ResponseValue::Integer(1));
+        let res = process_command(&mut kv, make_cmd(vec!["UNLINK"]));
+        assert!(matches!(res, ResponseValue::Error(_)));
+
+        let res = process_command(&mut kv, make_cmd(vec!["FLUSHALL", "now"]));
+        assert!(matches!(res, ResponseValue::Error(_)));
+        let res = process_command(&mut kv, make_cmd(vec!["FLUSHALL", "async"]));
+        assert_eq!(res, ResponseValue::SimpleString("OK".into()));
+        assert!(kv.is_empty());
+    }
+
+    #[test]
+    fn test_stored_values_do_not_pin_request_buffers() {
+        // stand-in for a read buffer the parser sliced the arguments from
+        let small = "v".repeat(16);
+        let big = "x".repeat(COMPACT_THRESHOLD);
+        let buffer = Bytes::from(format!("{small}{big}"));
+        let request = |key: &str, value: Bytes| {
+            ResponseValue::Array(Some(vec![
+                ResponseValue::BulkString(Some("SET".into())),
+                ResponseValue::BulkString(Some(Bytes::copy_from_slice(key.as_bytes()))),
+                ResponseValue::BulkString(Some(value)),
+            ]))
+        };
+        let in_buffer = |bytes: &Bytes| buffer.as_ptr_range().contains(&bytes.as_ptr());
+
+        let mut kv = KvStore::new();
+        process_command(&mut kv, request("small", buffer.slice(..16)));
+        process_command(&mut kv, request("big", buffer.slice(16..)));
+
+        let ResponseValue::BulkString(Some(stored)) =
+            process_command(&mut kv, make_cmd(vec!["GET", "small"]))
+        else {
+            panic!("expected a bulk string");
+        };
+        assert_eq!(stored, small.as_bytes());
+        assert!(!in_buffer(&stored));
+
+        let ResponseValue::BulkString(Some(stored)) =
+            process_command(&mut kv, make_cmd(vec!["GET", "big"]))
+        else {
+            panic!("expected a bulk string");
+        };
+        assert_eq!(stored.len(), COMPACT_THRESHOLD);
+        assert!(in_buffer(&stored));
+    }
+
+    #[test]
+    fn test_memory_usage() {
+        let mut kv = KvStore::new();
+        process_command(&mut kv, make_cmd(vec!["SET", "a", "hello"]));
+
+        let res = process_command(&mut kv, make_cmd(vec!["MEMORY", "USAGE", "a"]));
+        let ResponseValue::Integer(bytes) = res else {
+            panic!("expected integer, got {res:?}");
+        };
+        assert!(bytes > 5);
+        let res = process_command(
+            &mut kv,
+            make_cmd(vec!["memory", "usage", "a", "SAMPLES", "0"]),
+        );
+        assert_eq!(res, ResponseValue::Integer(bytes));
+
+        let res = process_command(&mut kv, make_cmd(vec!["MEMORY", "USAGE", "missing"]));
+        assert_eq!(res, ResponseValue::BulkString(None));
+        let res = process_command(&mut kv, make_cmd(vec!["MEMORY", "USAGE", "a", "SAMPLES"]));
+        assert!(matches!(res, ResponseValue::Error(_)));
+    }
+
+    #[test]
+    fn test_object_freq_and_idletime() {
+        let mut kv = KvStore::new();
+        process_command(&mut kv, make_cmd(vec!["SET", "a", "1"]));
+
+        let res = process_command(&mut kv, make_cmd(vec!["OBJECT", "FREQ", "a"]));
+        assert_eq!(res, ResponseValue::Integer(5));
+        let res = process_command(&mut kv, make_cmd(vec!["object", "idletime", "a"]));
+        assert_eq!(res, ResponseValue::Integer(0));
+
+        let res = process_command(&mut kv, make_cmd(vec!["OBJECT", "FREQ", "missing"]));
+        assert_eq!(res, ResponseValue::BulkString(None));
+
+        let res = process_command(&mut kv, make_cmd(vec!["OBJECT", "NOPE", "a"]));
+        assert!(extract_str(res).starts_with(b"ERR unknown subcommand 'NOPE'"));
+        let res = process_command(&mut kv, make_cmd(vec!["OBJECT", "FREQ"]));
+        assert!(matches!(res, ResponseValue::Error(_)));
+    }
+
+    #[test]
+    fn test_object_refcount_and_help() {
+        let mut kv = KvStore::new();
+        process_command(&mut kv, make_cmd(vec!["SET", "a", "1"]));
+
+        let res = process_command(&mut kv, make_cmd(vec!["OBJECT", "REFCOUNT", "a"]));
+        assert_eq!(res, ResponseValue::Integer(1));
+        let res = process_command(&mut kv, make_cmd(vec!["OBJECT", "REFCOUNT", "missing"]));
+        assert_eq!(res, ResponseValue::BulkString(None));
+
+        let res = process_command(&mut kv, make_cmd(vec!["OBJECT", "HELP"]));
+        let ResponseValue::Array(Some(lines)) = res else {
+            panic!("Expected Array response for OBJECT HELP");
+        };
+        assert!(lines.contains(&ResponseValue::SimpleString("REFCOUNT <key>".into())));
+        let res = process_command(&mut kv, make_cmd(vec!["OBJECT", "HELP", "a"]));
+        assert!(matches!(res, ResponseValue::Error(_)));
+    }
+
+    #[cfg(all(feature = "lists", feature = "sets"))]
+    #[test]
+    fn test_object_encoding() {
+        let mut kv = KvStore::new();
+        process_command(&mut kv, make_cmd(vec!["SET", "s", "hello"]));
+        process_command(&mut kv, make_cmd(vec!["SET", "n", "-12"]));
+        let long = "x".repeat(45);
+        process_command(&mut kv, make_cmd(vec!["SET", "long", &long]));
+        process_command(&mut kv, make_cmd(vec!["RPUSH", "l", "a", "b"]));
+        process_command(&mut kv, make_cmd(vec!["SADD", "ints", "3", "-1", "2"]));
+        process_command(&mut kv, make_cmd(vec!["SADD", "words", "3", "x"]));
+
+        let encoding =
+            |kv: &mut KvStore, key| process_command(kv, make_cmd(vec!["OBJECT", "ENCODING", key]));
+        assert_eq!(extract_str(encoding(&mut kv, "s")), Bytes::from("embstr"));
+        assert_eq!(extract_str(encoding(&mut kv, "n")), Bytes::from("int"));
+        assert_eq!(extract_str(encoding(&mut kv, "long")), Bytes::from("raw"));
+        let res = process_command(&mut kv, make_cmd(vec!["GET", "n"]));
+        assert_eq!(extract_str(res), Bytes::from("-12"));
+        let res = process_command(&mut kv, make_cmd(vec!["GET", "long"]));
+        assert_eq!(extract_str(res), Bytes::from(long));
+        assert_eq!(extract_str(encoding(&mut kv, "l")), Bytes::from("listpack"));
+        assert_eq!(
+            extract_str(encoding(&mut kv, "ints")),
+            Bytes::from("intset")
+        );
+        assert_eq!(
+            extract_str(encoding(&mut kv, "words")),
+            Bytes::from("hashtable")
+        );
+        assert_eq!(
+            encoding(&mut kv, "missing"),
+            ResponseValue::BulkString(None)
+        );
+
+        // a non-integer member converts the set
+        process_command(&mut kv, make_cmd(vec!["SADD", "ints", "four"]));
+        assert_eq!(
+            extract_str(encoding(&mut kv, "ints")),
+            Bytes::from("hashtable")
+        );
+        let res = process_command(&mut kv, make_cmd(vec!["SMEMBERS", "ints"]));
+        let ResponseValue::Array(Some(members)) = res else {
+            panic!("Expected Array response for SMEMBERS");
+        };
+        let mut members: Vec<Bytes> = members.into_iter().map(extract_str).collect();
+        members.sort();
+        assert_eq!(members, ["-1", "2", "3", "four"].map(Bytes::from));
     }
 
     #[test]
     fn test_invalid_command() {
-        let kv = KvStore::new();
-        let res = process_command(&kv, make_cmd(vec!["FOOBAR"]));
+        let mut kv = KvStore::new();
+        let res = process_command(&mut kv, make_cmd(vec!["FOOBAR"]));
         assert!(matches!(res, ResponseValue::Error(_)));
     }
 
+    #[test]
+    fn test_command_name() {
+        assert_eq!(command_name(&make_cmd(vec!["GET", "k"])), Some("get"));
+        #[cfg(feature = "sets")]
+        assert_eq!(
+            command_name(&make_cmd(vec!["sMeMbErS", "k"])),
+            Some("smembers")
+        );
+        assert_eq!(command_name(&make_cmd(vec!["FOOBAR"])), None);
+        assert_eq!(command_name(&ResponseValue::Integer(1)), None);
+    }
+
     #[test]
     fn test_argument_validation() {
-        let kv = KvStore::new();
+        let mut kv = KvStore::new();
         // SET without value
-        let res = process_command(&kv, make_cmd(vec!["SET", "key"]));
+        let res = process_command(&mut kv, make_cmd(vec!["SET", "key"]));
         assert!(String::from_utf8_lossy(&extract_str(res)).contains("wrong number of arguments"));
     }
+
+    #[test]
+    fn test_arity_from_command_table() {
+        let mut kv = KvStore::new();
+        let rejected = [
+            vec!["GET"],
+            vec!["GET", "a", "b"],
+            vec!["mset", "a"],
+            vec!["MSET", "a", "1", "b"],
+            vec!["DEL"],
+            vec!["DBSIZE", "x"],
+            vec!["SETEX", "k", "10"],
+            #[cfg(feature = "lists")]
+            vec!["LPUSH", "list"],
+            #[cfg(feature = "lists")]
+            vec!["LPOP", "list", "1", "2"],
+            #[cfg(feature = "lists")]
+            vec!["LRANGE", "list", "0"],
+        ];
+        for args in rejected {
+            let name = args[0].to_lowercase();
+            let expected = format!("ERR wrong number of arguments for '{name}' command");
+            assert_eq!(
+                arity_error(match &make_cmd(args.clone()) {
+                    ResponseValue::Array(Some(items)) => items,
+                    _ => unreachable!(),
+                }),
+                Some(ResponseValue::Error(expected.clone().into()))
+            );
+            let res = process_command(&mut kv, make_cmd(args));
+            assert_eq!(extract_str(res), expected);
+        }
+        // nothing was run
+        assert_eq!(kv.len(), 0);
+
+        let res = process_command(&mut kv, make_cmd(vec!["MSET", "a", "1", "b", "2"]));
+        assert_eq!(res, ResponseValue::SimpleString("OK".into()));
+        assert_eq!(arity_error(&[]), None);
+        assert_eq!(
+            arity_error(&[ResponseValue::BulkString(Some("FOOBAR".into()))]),
+            None
+        );
+    }
+
+    #[cfg(all(feature = "lists", feature = "sets"))]
+    #[test]
+    fn test_command_budget() {
+        let config = rustis::config::Config {
+            command_budget: 3,
+            ..Default::default()
+        };
+        let mut kv = KvStore::from_config(&config);
+        process_command(&mut kv, make_cmd(vec!["RPUSH", "list", "a", "b", "c", "d"]));
+        process_command(&mut kv, make_cmd(vec!["SADD", "set", "a", "b", "c", "d"]));
+
+        let res = process_command(&mut kv, make_cmd(vec!["LRANGE", "list", "0", "-1"]));
+        assert_eq!(
+            extract_str(res),
+            "ERR the reply would have 4 elements, more than command-budget 3 allows; \
+             use smaller LRANGE ranges"
+        );
+        let res = process_command(&mut kv, make_cmd(vec!["LRANGE", "list", "1", "-1"]));
+        assert!(matches!(res, ResponseValue::Array(Some(items)) if items.len() == 3));
+        let res = process_command(&mut kv, make_cmd(vec!["SMEMBERS", "set"]));
+        assert_eq!(
+            extract_str(res),
+            "ERR the reply would have 4 elements, more than command-budget 3 allows; use SSCAN"
+        );
+        // which walks it no more than the budget at a time
+        let res = process_command(&mut kv, make_cmd(vec!["SSCAN", "set", "0", "COUNT", "10"]));
+        let ResponseValue::Array(Some(reply)) = res else {
+            panic!("Expected Array response for SSCAN");
+        };
+        assert_eq!(extract_str(reply[0].clone()), "1");
+        assert!(matches!(&reply[1], ResponseValue::Array(Some(items)) if items.len() == 3));
+        process_command(&mut kv, make_cmd(vec!["SPOP", "set"]));
+        let res = process_command(&mut kv, make_cmd(vec!["SMEMBERS", "set"]));
+        assert!(matches!(res, ResponseValue::Array(Some(items)) if items.len() == 3));
+
+        // a flush of more keys than that still empties the shard, in the
+        // background
+        process_command(&mut kv, make_cmd(vec!["MSET", "a", "1", "b", "2"]));
+        let res = process_command(&mut kv, make_cmd(vec!["FLUSHALL"]));
+        assert_eq!(res, ResponseValue::SimpleString("OK".into()));
+        assert_eq!(kv.len(), 0);
+        assert_eq!(kv.used_memory(), 0);
+    }
+
+    #[test]
+    fn test_expire_jitter() {
+        let config = rustis::config::Config {
+            expire_jitter_percent: 50,
+            ..Default::default()
+        };
+        let mut kv = KvStore::from_config(&config);
+        let start = unix_time_ms();
+        for i in 0..100 {
+            let key = format!("k{i}");
+            let set = match i % 3 {
+                0 => vec!["SET", &key, "v", "EX", "100"],
+                1 => vec!["SET", &key, "v", "PX", "100000"],
+                _ => vec!["SETEX", &key, "100", "v"],
+            };
+            process_command(&mut kv, make_cmd(set));
+        }
+        let ttls: std::collections::HashSet<u64> = (0..100)
+            .map(|i| kv.expires_at(&Bytes::from(format!("k{i}"))).unwrap() - start)
+            .collect();
+        // cut short by up to half, and spread out
+        assert!(ttls.iter().all(|ttl| (50_000..=100_100).contains(ttl)));
+        assert!(ttls.len() > 50);
+        assert!(ttls.iter().any(|ttl| *ttl < 75_000));
+
+        // a time given as such is kept
+        let at = (start + 100_000).to_string();
+        process_command(&mut kv, make_cmd(vec!["SET", "k", "v", "PXAT", &at]));
+        assert_eq!(kv.expires_at(&Bytes::from("k")), Some(start + 100_000));
+    }
+
+    #[test]
+    fn test_expire_jitter_keeps_ttls_positive() {
+        // at the most allowed, and even past it, a TTL keeps at least half
+        // of what it was given
+        for percent in [MAX_EXPIRE_JITTER_PERCENT, 100] {
+            let config = rustis::config::Config {
+                expire_jitter_percent: percent,
+                ..Default::default()
+            };
+            let mut kv = KvStore::from_config(&config);
+            for i in 0..200 {
+                let key = format!("k{i}");
+                let set = match i % 3 {
+                    0 => vec!["SET", &key, "v", "EX", "2"],
+                    1 => vec!["SET", &key, "v", "PX", "2000"],
+                    _ => vec!["PSETEX", &key, "2000", "v"],
+                };
+                let before = unix_time_ms();
+                process_command(&mut kv, make_cmd(set));
+                let at = kv.expires_at(&Bytes::from(key)).unwrap();
+                assert!(
+                    at >= before + 1_000,
+                    "{percent}%: expires at {at}, set at {before}"
+                );
+            }
+        }
+    }
 }
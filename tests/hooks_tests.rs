@@ -0,0 +1,109 @@
+#![cfg(feature = "server")]
+
+use std::{
+    io,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use rustis::{
+    config::Config,
+    connection::{accept_loop, Client},
+    hooks::{self, ConnectionHooks},
+    stats::{ServerStats, STATS},
+    worker::worker_main,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    sync::mpsc,
+    task::LocalSet,
+};
+
+/// Lets one client in at a time and writes down what happened.
+#[derive(Clone, Default)]
+struct OneAtATime {
+    connected: Arc<Mutex<Option<u64>>>,
+    events: Arc<Mutex<Vec<String>>>,
+}
+
+impl ConnectionHooks for OneAtATime {
+    fn on_connect(&self, client: &Client) -> Result<(), String> {
+        assert!(client.addr.ip().is_loopback());
+        let mut connected = self.connected.lock().unwrap();
+        if connected.is_some() {
+            self.events.lock().unwrap().push("refused".into());
+            return Err("ERR quota\r\nexceeded".into());
+        }
+        *connected = Some(client.id);
+        self.events.lock().unwrap().push("connected".into());
+        Ok(())
+    }
+
+    fn on_disconnect(&self, client: &Client, error: Option<&io::Error>) {
+        let mut connected = self.connected.lock().unwrap();
+        assert_eq!(*connected, Some(client.id));
+        *connected = None;
+        self.events
+            .lock()
+            .unwrap()
+            .push(format!("disconnected, error: {}", error.is_some()));
+    }
+}
+
+#[tokio::test]
+async fn test_connection_hooks() {
+    let hooks = OneAtATime::default();
+    assert!(hooks::install(hooks.clone()));
+    assert!(!hooks::install(OneAtATime::default()));
+
+    let (tx, rx) = mpsc::channel(64);
+    std::thread::spawn(move || worker_main(0, rx, Arc::default()));
+    let router = Arc::new(vec![tx]);
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    LocalSet::new()
+        .run_until(async move {
+            tokio::task::spawn_local(accept_loop(listener, router, Arc::new(Config::default())));
+
+            let mut first = TcpStream::connect(addr).await.unwrap();
+            first.write_all(b"PING\r\n").await.unwrap();
+            let mut reply = [0u8; 7];
+            first.read_exact(&mut reply).await.unwrap();
+            assert_eq!(&reply, b"+PONG\r\n");
+
+            let rejected = ServerStats::get(&STATS.rejected_connections);
+            let mut second = TcpStream::connect(addr).await.unwrap();
+            let mut reply = Vec::new();
+            second.read_to_end(&mut reply).await.unwrap();
+            assert_eq!(reply, b"-ERR quota  exceeded\r\n");
+            assert!(ServerStats::get(&STATS.rejected_connections) > rejected);
+
+            drop(first);
+            for _ in 0..100 {
+                if hooks.connected.lock().unwrap().is_none() {
+                    break;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+
+            // with the first one gone there's room again
+            let mut third = TcpStream::connect(addr).await.unwrap();
+            third.write_all(b"PING\r\n").await.unwrap();
+            let mut reply = [0u8; 7];
+            third.read_exact(&mut reply).await.unwrap();
+            assert_eq!(&reply, b"+PONG\r\n");
+        })
+        .await;
+
+    assert_eq!(
+        *hooks.events.lock().unwrap(),
+        [
+            "connected",
+            "refused",
+            "disconnected, error: false",
+            "connected"
+        ]
+    );
+}
@@ -0,0 +1,124 @@
+#![cfg(feature = "server")]
+
+use std::sync::Arc;
+
+use bytes::Bytes;
+use rustis::{
+    hotkeys::{HotKeys, SAMPLE_SIZE, TOP_KEYS},
+    message::ResponseValue,
+    router::route_message,
+    telemetry::CommandTrace,
+    worker::worker_main,
+};
+use tokio::sync::mpsc;
+
+fn key(i: usize) -> Bytes {
+    Bytes::from(format!("key:{i}"))
+}
+
+#[test]
+fn test_finds_hot_keys() {
+    let mut hot_keys = HotKeys::new();
+    // key:0 to key:4 get 500, 400, ... 100 accesses among 2000 cold keys
+    for round in 0..500 {
+        for hot in 0..5 {
+            if round < 500 - hot * 100 {
+                hot_keys.record(&key(hot));
+            }
+        }
+        for cold in 0..4 {
+            hot_keys.record(&key(1000 + round * 4 + cold));
+        }
+    }
+
+    let top = hot_keys.top();
+    assert_eq!(top.len(), TOP_KEYS);
+    let hottest: Vec<_> = top[..5].iter().map(|(key, _)| key.clone()).collect();
+    assert_eq!(hottest, (0..5).map(key).collect::<Vec<_>>());
+    // estimates never undercount, and with few collisions barely overcount
+    assert!(top[0].1 >= 500 && top[0].1 < 520, "{:?}", top[0]);
+    assert!(top.windows(2).all(|pair| pair[0].1 >= pair[1].1));
+}
+
+#[test]
+fn test_counts_decay() {
+    let mut hot_keys = HotKeys::new();
+    for _ in 0..100 {
+        hot_keys.record(&key(0));
+    }
+    // the rest of a sample period on other keys halves key:0's count
+    for i in 0..SAMPLE_SIZE as usize - 100 {
+        hot_keys.record(&key(1 + i % 100_000));
+    }
+    let (hottest, count) = &hot_keys.top()[0];
+    assert_eq!(hottest, &key(0));
+    assert!((50..60).contains(count), "{count}");
+}
+
+#[tokio::test]
+async fn test_hotkeys_command() {
+    let mut worker_txs = Vec::new();
+    for id in 0..2 {
+        let (tx, rx) = mpsc::channel(16);
+        std::thread::spawn(move || worker_main(id, rx, Arc::default()));
+        worker_txs.push(tx);
+    }
+    let (writer_tx, mut writer_rx) = mpsc::channel(64);
+    let command = |args: &[&str]| {
+        ResponseValue::Array(Some(
+            args.iter()
+                .map(|arg| ResponseValue::BulkString(Some(Bytes::copy_from_slice(arg.as_bytes()))))
+                .collect(),
+        ))
+    };
+
+    let mut seq = 0;
+    for (name, times) in [("a", 30), ("b", 20), ("c", 10)] {
+        for _ in 0..times {
+            seq += 1;
+            let permit = writer_tx.clone().reserve_owned().await.unwrap();
+            route_message(
+                &worker_txs,
+                command(&["GET", name]),
+                seq,
+                permit,
+                CommandTrace::default(),
+            )
+            .await;
+            writer_rx.recv().await.unwrap();
+        }
+    }
+
+    let permit = writer_tx.clone().reserve_owned().await.unwrap();
+    route_message(
+        &worker_txs,
+        command(&["HOTKEYS", "2"]),
+        0,
+        permit,
+        CommandTrace::default(),
+    )
+    .await;
+    let reply = writer_rx.recv().await.unwrap().response_value;
+    let pair = |key: &'static str, count| {
+        ResponseValue::Array(Some(vec![
+            ResponseValue::BulkString(Some(key.into())),
+            ResponseValue::Integer(count),
+        ]))
+    };
+    assert_eq!(
+        reply,
+        ResponseValue::Array(Some(vec![pair("a", 30), pair("b", 20)]))
+    );
+
+    let permit = writer_tx.clone().reserve_owned().await.unwrap();
+    route_message(
+        &worker_txs,
+        command(&["HOTKEYS", "many"]),
+        0,
+        permit,
+        CommandTrace::default(),
+    )
+    .await;
+    let reply = writer_rx.recv().await.unwrap().response_value;
+    assert!(matches!(reply, ResponseValue::Error(_)));
+}
@@ -0,0 +1,57 @@
+use std::sync::{Arc, Mutex};
+
+use bytes::Bytes;
+use rustis::{
+    config::MaxmemoryPolicy,
+    keyevents::{self, KeyEvent},
+    kv::{unix_time_ms, KvStore},
+};
+
+#[test]
+fn test_key_event_hooks() {
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let recorded = events.clone();
+    assert!(keyevents::install(move |key: &Bytes, event: KeyEvent| {
+        recorded.lock().unwrap().push((key.clone(), event));
+    }));
+    assert!(!keyevents::install(|_: &Bytes, _: KeyEvent| {}));
+    let take = || std::mem::take(&mut *events.lock().unwrap());
+
+    let mut store = KvStore::new();
+    store.set(Bytes::from("a"), Bytes::from("1"));
+    store.set(Bytes::from("b"), Bytes::from("2"));
+
+    // what clients remove themselves isn't reported
+    store.set(Bytes::from("a"), Bytes::from("3"));
+    assert!(store.del(&Bytes::from("b")));
+    assert!(take().is_empty());
+
+    store.set_expires_at(&Bytes::from("a"), Some(unix_time_ms() - 1));
+    assert_eq!(store.expire_keys(10), 1);
+    assert_eq!(take(), [(Bytes::from("a"), KeyEvent::Expired)]);
+
+    store.set(Bytes::from("c"), Bytes::from("4"));
+    assert!(store.evict(MaxmemoryPolicy::AllKeysLru, 5).is_some());
+    assert_eq!(take(), [(Bytes::from("c"), KeyEvent::Evicted)]);
+
+    store.set(Bytes::from("d"), Bytes::from("5"));
+    store.set(Bytes::from("e"), Bytes::from("6"));
+    store.flush(true);
+    let mut flushed = take();
+    flushed.sort_by(|a, b| a.0.cmp(&b.0));
+    assert_eq!(
+        flushed,
+        [
+            (Bytes::from("d"), KeyEvent::Flushed),
+            (Bytes::from("e"), KeyEvent::Flushed),
+        ]
+    );
+    store.flush(false);
+    assert!(take().is_empty());
+
+    // keys dropped after a command on them panicked
+    store.set(Bytes::from("f"), Bytes::from("7"));
+    assert!(store.discard(&Bytes::from("f")));
+    assert!(!store.discard(&Bytes::from("f")));
+    assert_eq!(take(), [(Bytes::from("f"), KeyEvent::Dropped)]);
+}
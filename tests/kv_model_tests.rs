@@ -0,0 +1,328 @@
+#![cfg(all(feature = "lists", feature = "sets"))]
+
+//! Random sequences of operations run on a `KvStore` and on a plain model
+//! of what Redis would hold, comparing every result and, after each step,
+//! every key. Keys are few and values small, so operations keep landing on
+//! the same keys, of every type, and lists and sets cross their encodings'
+//! size limits both ways.
+
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+
+use bytes::Bytes;
+use proptest::{collection::vec, prelude::*};
+use rustis::{
+    config::Config,
+    kv::{DatabaseError, KvStore, RedisValue, ValueType},
+};
+
+const KEYS: &[&str] = &["a", "b", "c", "d"];
+
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    String(Vec<u8>),
+    List(VecDeque<Vec<u8>>),
+    Set(BTreeSet<Vec<u8>>),
+}
+
+#[derive(Debug, Clone)]
+enum Op {
+    Set(usize, Vec<u8>),
+    Get(usize),
+    Del(usize),
+    Unlink(usize),
+    LPush(usize, Vec<Vec<u8>>),
+    RPush(usize, Vec<Vec<u8>>),
+    LPop(usize, i64),
+    RPop(usize, i64),
+    LRange(usize, i64, i64),
+    SAdd(usize, Vec<Vec<u8>>),
+    SPop(usize, i64),
+    Flush,
+}
+
+fn key() -> impl Strategy<Value = usize> {
+    0..KEYS.len()
+}
+
+/// Integers, which sets keep in an intset, short strings that repeat, and
+/// some long enough to push a list or set out of its compact encoding.
+fn element() -> impl Strategy<Value = Vec<u8>> {
+    prop_oneof![
+        4 => (-5i64..300).prop_map(|n| n.to_string().into_bytes()),
+        4 => "[a-d]{0,2}".prop_map(String::into_bytes),
+        1 => vec(any::<u8>(), 60..120),
+    ]
+}
+
+fn elements() -> impl Strategy<Value = Vec<Vec<u8>>> {
+    vec(element(), 1..6)
+}
+
+/// Indexes around and past both ends of a short list.
+fn index() -> impl Strategy<Value = i64> {
+    prop_oneof![
+        8 => -12i64..12,
+        1 => Just(i64::MIN),
+        1 => Just(i64::MAX),
+    ]
+}
+
+fn op() -> impl Strategy<Value = Op> {
+    prop_oneof![
+        3 => (key(), element()).prop_map(|(k, v)| Op::Set(k, v)),
+        2 => key().prop_map(Op::Get),
+        1 => key().prop_map(Op::Del),
+        1 => key().prop_map(Op::Unlink),
+        3 => (key(), elements()).prop_map(|(k, v)| Op::LPush(k, v)),
+        3 => (key(), elements()).prop_map(|(k, v)| Op::RPush(k, v)),
+        2 => (key(), 0i64..5).prop_map(|(k, n)| Op::LPop(k, n)),
+        2 => (key(), 0i64..5).prop_map(|(k, n)| Op::RPop(k, n)),
+        3 => (key(), index(), index()).prop_map(|(k, start, stop)| Op::LRange(k, start, stop)),
+        3 => (key(), elements()).prop_map(|(k, v)| Op::SAdd(k, v)),
+        2 => (key(), 0i64..5).prop_map(|(k, n)| Op::SPop(k, n)),
+        1 => Just(Op::Flush),
+    ]
+}
+
+fn bytes(values: &[Vec<u8>]) -> Vec<Bytes> {
+    values.iter().cloned().map(Bytes::from).collect()
+}
+
+fn strings(values: Vec<Bytes>) -> Vec<Vec<u8>> {
+    values.into_iter().map(|value| value.to_vec()).collect()
+}
+
+/// What `LRANGE start stop` returns for `list`, worked out the long way.
+fn model_range(list: &VecDeque<Vec<u8>>, start: i64, stop: i64) -> Vec<Vec<u8>> {
+    let len = list.len() as i128;
+    let resolve = |index: i64| {
+        let index = index as i128;
+        if index < 0 {
+            len + index
+        } else {
+            index
+        }
+    };
+    let (start, stop) = (resolve(start), resolve(stop));
+    (0..len)
+        .filter(|&i| i >= start && i <= stop)
+        .map(|i| list[i as usize].clone())
+        .collect()
+}
+
+/// Pushes onto the list at `key` in `model`, as the store must.
+fn model_push(
+    model: &mut BTreeMap<usize, Value>,
+    key: usize,
+    values: &[Vec<u8>],
+    front: bool,
+) -> Result<i64, DatabaseError> {
+    let entry = model
+        .entry(key)
+        .or_insert_with(|| Value::List(VecDeque::new()));
+    let Value::List(list) = entry else {
+        return Err(DatabaseError::WrongType);
+    };
+    for value in values {
+        if front {
+            list.push_front(value.clone());
+        } else {
+            list.push_back(value.clone());
+        }
+    }
+    Ok(list.len() as i64)
+}
+
+fn model_pop(
+    model: &mut BTreeMap<usize, Value>,
+    key: usize,
+    count: i64,
+    front: bool,
+) -> Result<Vec<Vec<u8>>, DatabaseError> {
+    let Some(value) = model.get_mut(&key) else {
+        return Ok(Vec::new());
+    };
+    let Value::List(list) = value else {
+        return Err(DatabaseError::WrongType);
+    };
+    let count = (count as usize).min(list.len());
+    let popped = if front {
+        list.drain(..count).collect()
+    } else {
+        list.drain(list.len() - count..).rev().collect()
+    };
+    if list.is_empty() {
+        model.remove(&key);
+    }
+    Ok(popped)
+}
+
+/// Checks every key of `kv` against `model`, and the memory accounting
+/// against the keys.
+fn check_keys(kv: &KvStore, model: &BTreeMap<usize, Value>) -> Result<(), TestCaseError> {
+    prop_assert_eq!(kv.len(), model.len());
+    let mut used = 0;
+    let mut types = [0; 3];
+    for (i, name) in KEYS.iter().enumerate() {
+        let key = Bytes::from_static(name.as_bytes());
+        let stored = match kv.get(&key) {
+            None => None,
+            Some(RedisValue::String(s)) => Some(Value::String(s.to_bytes().to_vec())),
+            Some(RedisValue::List(list)) => {
+                prop_assert_eq!(list.len(), list.iter().count());
+                Some(Value::List(list.iter().map(|b| b.to_vec()).collect()))
+            }
+            Some(RedisValue::Set(set)) => {
+                let members: Vec<Vec<u8>> = set.iter().map(|b| b.to_vec()).collect();
+                let unique: BTreeSet<Vec<u8>> = members.iter().cloned().collect();
+                prop_assert_eq!(members.len(), unique.len(), "a member twice in {}", name);
+                prop_assert_eq!(set.len(), unique.len());
+                Some(Value::Set(unique))
+            }
+            Some(other) => {
+                return Err(TestCaseError::fail(format!("unexpected value {other:?}")));
+            }
+        };
+        prop_assert_eq!(stored.as_ref(), model.get(&i), "key {}", name);
+        prop_assert_eq!(kv.exists(&key), stored.is_some());
+        if let Some(value) = &stored {
+            used += kv.memory_usage(&key).unwrap();
+            types[match value {
+                Value::String(_) => 0,
+                Value::List(_) => 1,
+                Value::Set(_) => 2,
+            }] += 1;
+        }
+    }
+    prop_assert_eq!(kv.used_memory(), used);
+    let usage = kv.type_usage();
+    prop_assert_eq!(usage[ValueType::String as usize].keys, types[0]);
+    prop_assert_eq!(usage[ValueType::List as usize].keys, types[1]);
+    prop_assert_eq!(usage[ValueType::Set as usize].keys, types[2]);
+    Ok(())
+}
+
+fn run(ops: Vec<Op>, listpack_size: i64, intset_entries: usize) -> Result<(), TestCaseError> {
+    let mut kv = KvStore::from_config(&Config {
+        list_max_listpack_size: listpack_size,
+        set_max_intset_entries: intset_entries,
+        ..Config::default()
+    });
+    let mut model = BTreeMap::new();
+    let name = |k: usize| Bytes::from_static(KEYS[k].as_bytes());
+
+    for op in ops {
+        match op {
+            Op::Set(k, value) => {
+                kv.set(name(k), Bytes::from(value.clone()));
+                model.insert(k, Value::String(value));
+            }
+            Op::Get(k) => {
+                let got = match kv.get(&name(k)) {
+                    Some(RedisValue::String(s)) => Some(Ok(s.to_bytes().to_vec())),
+                    Some(_) => Some(Err(())),
+                    None => None,
+                };
+                let expected = match model.get(&k) {
+                    Some(Value::String(s)) => Some(Ok(s.clone())),
+                    Some(_) => Some(Err(())),
+                    None => None,
+                };
+                prop_assert_eq!(got, expected);
+            }
+            Op::Del(k) => prop_assert_eq!(kv.del(&name(k)), model.remove(&k).is_some()),
+            Op::Unlink(k) => prop_assert_eq!(kv.unlink(&name(k)), model.remove(&k).is_some()),
+            Op::LPush(k, values) => prop_assert_eq!(
+                kv.lpush(name(k), bytes(&values)),
+                model_push(&mut model, k, &values, true)
+            ),
+            Op::RPush(k, values) => prop_assert_eq!(
+                kv.rpush(name(k), bytes(&values)),
+                model_push(&mut model, k, &values, false)
+            ),
+            Op::LPop(k, count) => prop_assert_eq!(
+                kv.lpop(&name(k), count).map(strings),
+                model_pop(&mut model, k, count, true)
+            ),
+            Op::RPop(k, count) => prop_assert_eq!(
+                kv.rpop(&name(k), count).map(strings),
+                model_pop(&mut model, k, count, false)
+            ),
+            Op::LRange(k, start, stop) => {
+                let expected = match model.get(&k) {
+                    Some(Value::List(list)) => Ok(model_range(list, start, stop)),
+                    Some(_) => Err(DatabaseError::WrongType),
+                    None => Ok(Vec::new()),
+                };
+                prop_assert_eq!(kv.lrange(&name(k), start, stop).map(strings), expected);
+            }
+            Op::SAdd(k, values) => {
+                let expected = match model
+                    .entry(k)
+                    .or_insert_with(|| Value::Set(BTreeSet::new()))
+                {
+                    Value::Set(set) => Ok(values
+                        .iter()
+                        .filter(|value| set.insert(value.to_vec()))
+                        .count() as i64),
+                    _ => Err(DatabaseError::WrongType),
+                };
+                prop_assert_eq!(kv.sadd(name(k), bytes(&values)), expected);
+            }
+            Op::SPop(k, count) => match (kv.spop(&name(k), count), model.get_mut(&k)) {
+                (Ok(popped), None) => prop_assert!(popped.is_empty()),
+                (Ok(popped), Some(Value::Set(set))) => {
+                    // any members may come out, each once
+                    prop_assert_eq!(popped.len(), (count as usize).min(set.len()));
+                    for member in popped {
+                        prop_assert!(set.remove(&member[..]), "popped {:?} twice", member);
+                    }
+                    if set.is_empty() {
+                        model.remove(&k);
+                    }
+                }
+                (Err(DatabaseError::WrongType), Some(Value::String(_) | Value::List(_))) => {}
+                (got, expected) => prop_assert!(false, "spop gave {:?} on {:?}", got, expected),
+            },
+            Op::Flush => {
+                kv.flush(false);
+                model.clear();
+            }
+        }
+        check_keys(&kv, &model)?;
+    }
+    Ok(())
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(512))]
+
+    #[test]
+    fn test_store_matches_model(
+        ops in vec(op(), 1..80),
+        listpack_size in prop_oneof![1i64..5, Just(-1i64)],
+        intset_entries in 1usize..6,
+    ) {
+        run(ops, listpack_size, intset_entries)?;
+    }
+}
+
+#[test]
+fn test_lrange_edges() {
+    let mut kv = KvStore::new();
+    let key = Bytes::from("list");
+    kv.rpush(
+        key.clone(),
+        bytes(&[b"a".to_vec(), b"b".to_vec(), b"c".to_vec()]),
+    )
+    .unwrap();
+    let range = |start, stop| strings(kv.lrange(&key, start, stop).unwrap());
+
+    // an empty range is empty, not the first element
+    assert!(range(2, 1).is_empty());
+    assert!(range(5, 10).is_empty());
+    assert!(range(0, -10).is_empty());
+    assert_eq!(range(-100, 0), [b"a".to_vec()]);
+    assert_eq!(range(2, i64::MAX), [b"c".to_vec()]);
+    assert_eq!(range(i64::MIN, -1).len(), 3);
+}
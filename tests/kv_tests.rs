@@ -1,23 +1,138 @@
 use bytes::Bytes;
-use rustis::kv::{DatabaseError, KvStore, RedisValue};
+#[cfg(any(feature = "lists", feature = "sets"))]
+use rustis::kv::DatabaseError;
+use rustis::kv::{unix_time_ms, KvStore, RedisValue};
 
 // =================== HAPPY PATH TESTS ===================
 
 #[test]
 fn happy_set_get() {
-    let store = KvStore::new();
+    let mut store = KvStore::new();
     let key = Bytes::from("key");
     let val = Bytes::from("value");
 
-    store.set(key.clone(), val.clone()).unwrap();
+    store.set(key.clone(), val.clone());
+
+    let result = store.get(&key);
+    assert_eq!(result, Some(&RedisValue::String(val.into())));
+}
+
+#[test]
+fn happy_expired_key_is_gone() {
+    let mut store = KvStore::new();
+    let key = Bytes::from("key");
+    store.set(key.clone(), Bytes::from("value"));
+    let soon = unix_time_ms() + 60_000;
+    assert!(store.set_expires_at(&key, Some(soon)));
+    assert_eq!(store.expires_at(&key), Some(soon));
+    assert!(store.exists(&key));
+
+    store.set_expires_at(&key, Some(unix_time_ms() - 1));
+    assert_eq!(store.get(&key), None);
+    assert!(!store.exists(&key));
+    assert_eq!(store.expires_at(&key), None);
+    // still held until a write gets to it
+    assert_eq!(store.len(), 1);
+    assert!(!store.del(&key));
+    assert_eq!(store.len(), 0);
+    assert_eq!(store.used_memory(), 0);
+}
+
+#[test]
+fn happy_set_clears_ttl() {
+    let mut store = KvStore::new();
+    let key = Bytes::from("key");
+    store.set(key.clone(), Bytes::from("value"));
+    store.set_expires_at(&key, Some(unix_time_ms() + 60_000));
+    store.set(key.clone(), Bytes::from("other"));
+    assert_eq!(store.expires_at(&key), None);
+    assert!(!store.set_expires_at(&Bytes::from("missing"), Some(0)));
+}
+
+#[test]
+fn happy_expire_keys_soonest_first() {
+    let mut store = KvStore::new();
+    let now = unix_time_ms();
+    for (key, at) in [("c", now - 1), ("a", now - 3), ("b", now - 2)] {
+        store.set(Bytes::from(key), Bytes::from("value"));
+        store.set_expires_at(&Bytes::from(key), Some(at));
+    }
+    store.set(Bytes::from("later"), Bytes::from("value"));
+    store.set_expires_at(&Bytes::from("later"), Some(now + 60_000));
+    store.set(Bytes::from("forever"), Bytes::from("value"));
+    assert_eq!(store.volatile_keys(), 4);
+
+    assert_eq!(store.expire_keys(2), 2);
+    // "a" and "b" went first; "c" is still held
+    assert_eq!(store.len(), 3);
+    assert_eq!(store.expire_keys(10), 1);
+    assert_eq!(store.expire_keys(10), 0);
+    assert_eq!(store.len(), 2);
+    assert_eq!(store.volatile_keys(), 1);
+    assert_eq!(store.expired_keys(), 3);
+    assert!(store.avg_ttl() > 59_000 && store.avg_ttl() <= 60_000);
+}
+
+#[test]
+fn happy_expiry_index_follows_the_keys() {
+    let mut store = KvStore::new();
+    let key = Bytes::from("key");
+    let later = unix_time_ms() + 60_000;
+    store.set(key.clone(), Bytes::from("value"));
+    store.set_expires_at(&key, Some(later));
+    store.set_expires_at(&key, Some(later + 1000));
+    assert_eq!(store.volatile_keys(), 1);
+    store.set_expires_at(&key, None);
+    assert_eq!((store.volatile_keys(), store.avg_ttl()), (0, 0));
+
+    // replacing or removing a key drops its TTL from the index
+    store.set_expires_at(&key, Some(later));
+    store.set(key.clone(), Bytes::from("other"));
+    assert_eq!(store.volatile_keys(), 0);
+    store.set_expires_at(&key, Some(later));
+    assert!(store.del(&key));
+    assert_eq!(store.volatile_keys(), 0);
+    store.set(key.clone(), Bytes::from("value"));
+    store.set_expires_at(&key, Some(later));
+    store.flush(false);
+    assert_eq!(store.volatile_keys(), 0);
+
+    // a key a command found expired counts too
+    store.set(key.clone(), Bytes::from("value"));
+    store.set_expires_at(&key, Some(unix_time_ms() - 1));
+    assert!(!store.del(&key));
+    assert_eq!((store.volatile_keys(), store.expired_keys()), (0, 1));
+}
+
+#[cfg(all(feature = "lists", feature = "sets"))]
+#[test]
+fn happy_keyspace_hits_misses() {
+    let mut store = KvStore::new();
+    store.set(Bytes::from("key"), Bytes::from("value"));
+    store
+        .lpush(Bytes::from("list"), vec![Bytes::from("a")])
+        .unwrap();
 
-    let result = store.get(&key).unwrap();
-    assert_eq!(result, Some(RedisValue::String(val)));
+    store.get(&Bytes::from("key"));
+    store.get(&Bytes::from("nope"));
+    assert!(store.exists(&Bytes::from("key")));
+    assert!(!store.exists(&Bytes::from("nope")));
+    store.lrange(&Bytes::from("list"), 0, -1).unwrap();
+    store.smembers(&Bytes::from("nope")).unwrap();
+    // writes and OBJECT lookups aren't reads
+    store
+        .lpush(Bytes::from("list"), vec![Bytes::from("b")])
+        .unwrap();
+    store.object_freq(&Bytes::from("nope"));
+
+    assert_eq!(store.keyspace_hits(), 3);
+    assert_eq!(store.keyspace_misses(), 3);
 }
 
+#[cfg(feature = "lists")]
 #[test]
 fn happy_lpush() {
-    let store = KvStore::new();
+    let mut store = KvStore::new();
     let key = Bytes::from("list");
 
     let len = store
@@ -25,7 +140,8 @@ fn happy_lpush() {
         .unwrap();
     assert_eq!(len, 2);
 
-    if let Some(RedisValue::List(list)) = store.get(&key).unwrap() {
+    if let Some(RedisValue::List(list)) = store.get(&key) {
+        let list: Vec<Bytes> = list.iter().collect();
         assert_eq!(list[0], Bytes::from("a"));
         assert_eq!(list[1], Bytes::from("b"));
     } else {
@@ -33,9 +149,10 @@ fn happy_lpush() {
     }
 }
 
+#[cfg(feature = "lists")]
 #[test]
 fn happy_rpush() {
-    let store = KvStore::new();
+    let mut store = KvStore::new();
     let key = Bytes::from("list");
 
     let len = store
@@ -43,7 +160,8 @@ fn happy_rpush() {
         .unwrap();
     assert_eq!(len, 2);
 
-    if let Some(RedisValue::List(list)) = store.get(&key).unwrap() {
+    if let Some(RedisValue::List(list)) = store.get(&key) {
+        let list: Vec<Bytes> = list.iter().collect();
         assert_eq!(list[0], Bytes::from("a"));
         assert_eq!(list[1], Bytes::from("b"));
     } else {
@@ -51,9 +169,10 @@ fn happy_rpush() {
     }
 }
 
+#[cfg(feature = "lists")]
 #[test]
 fn happy_lrange() {
-    let store = KvStore::new();
+    let mut store = KvStore::new();
     let key = Bytes::from("list");
 
     store
@@ -73,9 +192,10 @@ fn happy_lrange() {
 fn unhappy_get_missing_key() {
     let store = KvStore::new();
     let key = Bytes::from("missing");
-    assert!(store.get(&key).unwrap().is_none());
+    assert!(store.get(&key).is_none());
 }
 
+#[cfg(feature = "lists")]
 #[test]
 fn unhappy_lrange_missing_key() {
     let store = KvStore::new();
@@ -85,9 +205,10 @@ fn unhappy_lrange_missing_key() {
 
 // =================== LIST POP TESTS ===================
 
+#[cfg(feature = "lists")]
 #[test]
 fn happy_lpop() {
-    let store = KvStore::new();
+    let mut store = KvStore::new();
     let key = Bytes::from("key");
 
     // lpush adds to front: "a" then "b" -> ["b", "a"]
@@ -104,9 +225,10 @@ fn happy_lpop() {
     assert_eq!(remaining, vec![Bytes::from("a")]);
 }
 
+#[cfg(feature = "lists")]
 #[test]
 fn happy_rpop() {
-    let store = KvStore::new();
+    let mut store = KvStore::new();
     let key = Bytes::from("key");
 
     // rpush adds to back: "a" then "b" -> ["a", "b"]
@@ -123,25 +245,44 @@ fn happy_rpop() {
     assert_eq!(remaining, vec![Bytes::from("a")]);
 }
 
+#[cfg(feature = "lists")]
+#[test]
+fn happy_rpop_many_in_pop_order() {
+    let mut store = KvStore::new();
+    let key = Bytes::from("key");
+    store
+        .rpush(
+            key.clone(),
+            vec![Bytes::from("a"), Bytes::from("b"), Bytes::from("c")],
+        )
+        .unwrap();
+
+    let result = store.rpop(&key, 2).unwrap();
+    assert_eq!(result, vec![Bytes::from("c"), Bytes::from("b")]);
+}
+
+#[cfg(feature = "lists")]
 #[test]
 fn unhappy_lpop_missing_key() {
-    let store = KvStore::new();
+    let mut store = KvStore::new();
     let key = Bytes::from("missing");
     assert_eq!(store.lpop(&key, 1).unwrap(), Vec::<Bytes>::new());
 }
 
+#[cfg(feature = "lists")]
 #[test]
 fn unhappy_rpop_missing_key() {
-    let store = KvStore::new();
+    let mut store = KvStore::new();
     let key = Bytes::from("missing");
     assert_eq!(store.rpop(&key, 1).unwrap(), Vec::<Bytes>::new());
 }
 
 // =================== SET TESTS ===================
 
+#[cfg(feature = "sets")]
 #[test]
 fn happy_sadd_and_smembers() {
-    let store = KvStore::new();
+    let mut store = KvStore::new();
     let key = Bytes::from("set");
 
     // Add "a", "b", and duplicate "a". Should return 2 new items.
@@ -160,9 +301,10 @@ fn happy_sadd_and_smembers() {
     assert_eq!(members, vec![Bytes::from("a"), Bytes::from("b")]);
 }
 
+#[cfg(feature = "sets")]
 #[test]
 fn happy_spop() {
-    let store = KvStore::new();
+    let mut store = KvStore::new();
     let key = Bytes::from("set");
 
     store
@@ -184,6 +326,28 @@ fn happy_spop() {
     assert!(!remaining.contains(&popped[0]));
 }
 
+#[cfg(feature = "sets")]
+#[test]
+fn spop_is_uniform() {
+    // each member of a hash table set comes out first about as often, and
+    // the rest stay poppable after the swaps
+    let members: Vec<Bytes> = ["a", "b", "c", "d"].map(Bytes::from).to_vec();
+    let mut first = std::collections::HashMap::new();
+    for _ in 0..4000 {
+        let mut store = KvStore::new();
+        let key = Bytes::from("set");
+        store.sadd(key.clone(), members.clone()).unwrap();
+        let popped = store.spop(&key, 4).unwrap();
+        let mut sorted = popped.clone();
+        sorted.sort();
+        assert_eq!(sorted, members);
+        *first.entry(popped[0].clone()).or_insert(0) += 1;
+    }
+    assert_eq!(first.len(), 4);
+    assert!(first.values().all(|&count| (800..1200).contains(&count)));
+}
+
+#[cfg(feature = "sets")]
 #[test]
 fn unhappy_smembers_missing_key() {
     let store = KvStore::new();
@@ -191,80 +355,88 @@ fn unhappy_smembers_missing_key() {
     assert_eq!(store.smembers(&key).unwrap(), Vec::<Bytes>::new());
 }
 
+#[cfg(feature = "sets")]
 #[test]
 fn unhappy_spop_missing_key() {
-    let store = KvStore::new();
+    let mut store = KvStore::new();
     let key = Bytes::from("missing");
     assert_eq!(store.spop(&key, 1).unwrap(), Vec::<Bytes>::new());
 }
 
 // =================== TYPE MISMATCH TESTS ===================
 
+#[cfg(feature = "lists")]
 #[test]
 fn type_mismatch_lpush_on_string() {
-    let store = KvStore::new();
+    let mut store = KvStore::new();
     let key = Bytes::from("key");
 
-    store.set(key.clone(), Bytes::from("value")).unwrap();
+    store.set(key.clone(), Bytes::from("value"));
 
     let result = store.lpush(key, vec![Bytes::from("item")]);
     assert!(matches!(result, Err(DatabaseError::WrongType)));
 }
 
+#[cfg(feature = "lists")]
 #[test]
 fn type_mismatch_rpush_on_string() {
-    let store = KvStore::new();
+    let mut store = KvStore::new();
     let key = Bytes::from("key");
 
-    store.set(key.clone(), Bytes::from("value")).unwrap();
+    store.set(key.clone(), Bytes::from("value"));
 
     let result = store.rpush(key, vec![Bytes::from("item")]);
     assert!(matches!(result, Err(DatabaseError::WrongType)));
 }
 
+#[cfg(feature = "lists")]
 #[test]
 fn type_mismatch_lrange_on_string() {
-    let store = KvStore::new();
+    let mut store = KvStore::new();
     let key = Bytes::from("key");
 
-    store.set(key.clone(), Bytes::from("value")).unwrap();
+    store.set(key.clone(), Bytes::from("value"));
 
     let result = store.lrange(&key, 0, 10);
     assert!(matches!(result, Err(DatabaseError::WrongType)));
 }
 
+#[cfg(feature = "lists")]
 #[test]
 fn type_mismatch_lpop_on_string() {
-    let store = KvStore::new();
+    let mut store = KvStore::new();
     let key = Bytes::from("key");
 
-    store.set(key.clone(), Bytes::from("value")).unwrap();
+    store.set(key.clone(), Bytes::from("value"));
     assert!(matches!(store.lpop(&key, 1), Err(DatabaseError::WrongType)));
 }
 
+#[cfg(feature = "lists")]
 #[test]
 fn type_mismatch_rpop_on_string() {
-    let store = KvStore::new();
+    let mut store = KvStore::new();
     let key = Bytes::from("key");
 
-    store.set(key.clone(), Bytes::from("value")).unwrap();
+    store.set(key.clone(), Bytes::from("value"));
     assert!(matches!(store.rpop(&key, 1), Err(DatabaseError::WrongType)));
 }
 
+#[cfg(feature = "sets")]
 #[test]
 fn type_mismatch_sadd_on_string() {
-    let store = KvStore::new();
+    let mut store = KvStore::new();
     let key = Bytes::from("key");
 
-    store.set(key.clone(), Bytes::from("value")).unwrap();
+    store.set(key.clone(), Bytes::from("value"));
 
     let result = store.sadd(key, vec![Bytes::from("a")]);
     assert!(matches!(result, Err(DatabaseError::WrongType)));
 }
 
+#[cfg(all(feature = "lists", feature = "sets"))]
 #[test]
 fn type_mismatch_smembers_on_list() {
-    let store = KvStore::new();
+    let mut store = KvStore::new();
     let key = Bytes::from("key");
 
     store.lpush(key.clone(), vec![Bytes::from("val")]).unwrap();
@@ -275,12 +447,13 @@ fn type_mismatch_smembers_on_list() {
     ));
 }
 
+#[cfg(feature = "sets")]
 #[test]
 fn type_mismatch_spop_on_string() {
-    let store = KvStore::new();
+    let mut store = KvStore::new();
     let key = Bytes::from("key");
 
-    store.set(key.clone(), Bytes::from("value")).unwrap();
+    store.set(key.clone(), Bytes::from("value"));
 
     assert!(matches!(store.spop(&key, 1), Err(DatabaseError::WrongType)));
 }
@@ -0,0 +1,50 @@
+use rustis::latency::{CommandLatencies, Histogram, MAX_NANOS};
+
+#[test]
+fn test_histogram_percentiles() {
+    let mut histogram = Histogram::new();
+    assert!(histogram.is_empty());
+    assert_eq!(histogram.percentile(50.0), 0);
+
+    for nanos in 1..=10_000 {
+        histogram.record(nanos);
+    }
+    assert_eq!(histogram.len(), 10_000);
+    // values are exact below 128 and within 1% above
+    for (percentile, exact) in [(0.5, 50), (50.0, 5_000), (99.0, 9_900), (99.9, 9_990)] {
+        let value = histogram.percentile(percentile);
+        assert!(value >= exact, "p{percentile} = {value}");
+        assert!(value <= exact + exact / 100, "p{percentile} = {value}");
+    }
+    assert_eq!(histogram.percentile(0.5), 50);
+    assert!(histogram.percentile(100.0) >= 10_000);
+}
+
+#[test]
+fn test_histogram_clamps_slow_values() {
+    let mut histogram = Histogram::new();
+    histogram.record(u64::MAX);
+    histogram.record(MAX_NANOS);
+    assert_eq!(histogram.percentile(100.0), MAX_NANOS);
+}
+
+#[test]
+fn test_command_latencies_merge() {
+    let mut first = CommandLatencies::default();
+    first.record("get", 1_000);
+    first.record("set", 100);
+    let mut second = CommandLatencies::default();
+    second.record("get", 3_000);
+
+    let mut total = CommandLatencies::default();
+    total.merge(&first);
+    total.merge(&second);
+    let commands: Vec<_> = total
+        .iter()
+        .map(|(command, histogram)| (command, histogram.len()))
+        .collect();
+    assert_eq!(commands, [("get", 2), ("set", 1)]);
+    let get = total.iter().next().unwrap().1;
+    assert_eq!(get.percentile(50.0), 1_003);
+    assert_eq!(get.percentile(100.0), 3_007);
+}
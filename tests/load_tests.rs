@@ -0,0 +1,116 @@
+#![cfg(feature = "server")]
+
+use std::path::PathBuf;
+
+use bytes::{Bytes, BytesMut};
+use rustis::{config::Config, load::load, message::ResponseValue, threads::spawn_threads, Server};
+
+fn bulk(s: &str) -> ResponseValue {
+    ResponseValue::BulkString(Some(Bytes::copy_from_slice(s.as_bytes())))
+}
+
+/// A command as `redis-cli --pipe` expects it.
+fn command(args: &[&str]) -> Vec<u8> {
+    let mut out = format!("*{}\r\n", args.len()).into_bytes();
+    for arg in args {
+        out.extend(format!("${}\r\n{arg}\r\n", arg.len()).into_bytes());
+    }
+    out
+}
+
+fn temp_file(name: &str, data: &[u8]) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("rustis-load-{}-{name}", std::process::id()));
+    std::fs::write(&path, data).unwrap();
+    path
+}
+
+#[tokio::test]
+async fn test_load_at_start() {
+    let mut data = Vec::new();
+    for i in 0..5000 {
+        data.extend(command(&["SET", &format!("key:{i}"), &i.to_string()]));
+    }
+    data.extend(b"SET inline 'a b'\r\n");
+    data.extend(command(&["NOSUCHCOMMAND"]));
+    let path = temp_file("dataset.resp", &data);
+
+    let mut server = Server::builder()
+        .port(0)
+        .workers(2)
+        .load(&path)
+        .build()
+        .unwrap();
+    server.start().unwrap();
+    let client = server.client();
+    assert_eq!(
+        client.command(["DBSIZE"]).await,
+        ResponseValue::Integer(5001)
+    );
+    assert_eq!(client.command(["GET", "key:4999"]).await, bulk("4999"));
+    assert_eq!(client.command(["GET", "inline"]).await, bulk("a b"));
+
+    server.shutdown();
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_load_report() {
+    let router = spawn_threads(&Config {
+        workers: 2,
+        ..Config::default()
+    });
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap();
+
+    let mut data = command(&["SET", "a", "1"]);
+    data.extend(command(&["SET", "a"]));
+    data.extend(command(&["NOSUCHCOMMAND"]));
+    let report = runtime
+        .block_on(load(&router, BytesMut::from(&data[..])))
+        .unwrap();
+    assert_eq!(report.commands, 3);
+    assert_eq!(report.errors, 2);
+    assert_eq!(
+        report.first_error.as_deref(),
+        Some("ERR wrong number of arguments for 'set' command")
+    );
+
+    let mut data = command(&["SET", "b", "2"]);
+    let at = data.len();
+    data.extend(b"*2\r\n$3\r\nGET");
+    let err = runtime
+        .block_on(load(&router, BytesMut::from(&data[..])))
+        .unwrap_err();
+    assert_eq!(err, format!("Unexpected end of file at byte {at}"));
+    let err = runtime
+        .block_on(load(&router, BytesMut::from(&b"*x\r\n"[..])))
+        .unwrap_err();
+    assert_eq!(
+        err,
+        "ERR Protocol error: invalid multibulk length at byte 0"
+    );
+}
+
+#[test]
+fn test_load_failure() {
+    let path = temp_file("broken.resp", b"*1\r\n$4\r\nPING\r\n$3\r\n");
+    let mut server = Server::builder()
+        .port(0)
+        .workers(1)
+        .load(&path)
+        .build()
+        .unwrap();
+    assert_eq!(
+        server.start().unwrap_err().kind(),
+        std::io::ErrorKind::InvalidData
+    );
+    std::fs::remove_file(&path).unwrap();
+
+    let mut server = Server::builder()
+        .port(0)
+        .load("/nonexistent/rustis.resp")
+        .build()
+        .unwrap();
+    assert!(server.start().is_err());
+}
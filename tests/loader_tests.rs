@@ -0,0 +1,72 @@
+#![cfg(feature = "server")]
+
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use bytes::Bytes;
+use rustis::{loader, store::StoreError, Server};
+
+#[tokio::test]
+async fn test_loader() {
+    let calls = Arc::new(AtomicUsize::new(0));
+    let counted = calls.clone();
+    loader::register("user:*", Some(Duration::from_millis(500)), move |key| {
+        counted.fetch_add(1, Ordering::Relaxed);
+        async move {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            match &key[..] {
+                b"user:none" => Ok(None),
+                b"user:down" => Err("ERR backend\r\nunavailable".into()),
+                _ => Ok(Some(Bytes::from([b"loaded ", &key[..]].concat()))),
+            }
+        }
+    });
+
+    let mut server = Server::builder().port(0).workers(2).build().unwrap();
+    server.start().unwrap();
+    let store = server.store();
+
+    // a stampede on a cold key makes one call
+    let gets: Vec<_> = (0..20)
+        .map(|_| {
+            let store = store.clone();
+            tokio::spawn(async move { store.get("user:1").await })
+        })
+        .collect();
+    for get in gets {
+        assert_eq!(get.await.unwrap(), Ok(Some(Bytes::from("loaded user:1"))));
+    }
+    assert_eq!(calls.load(Ordering::Relaxed), 1);
+    // then it's cached, with the loader's TTL
+    assert_eq!(
+        store.get("user:1").await,
+        Ok(Some(Bytes::from("loaded user:1")))
+    );
+    assert_eq!(calls.load(Ordering::Relaxed), 1);
+    tokio::time::sleep(Duration::from_millis(600)).await;
+    assert_eq!(store.exists(["user:1"]).await, Ok(0));
+    assert!(store.get("user:1").await.unwrap().is_some());
+    assert_eq!(calls.load(Ordering::Relaxed), 2);
+
+    // what the backend lacks, or fails on, isn't stored
+    assert_eq!(store.get("user:none").await, Ok(None));
+    assert_eq!(
+        store.get("user:down").await,
+        Err(StoreError::Reply("ERR backend  unavailable".into()))
+    );
+    assert_eq!(store.exists(["user:none", "user:down"]).await, Ok(0));
+    assert_eq!(calls.load(Ordering::Relaxed), 4);
+
+    // keys set by clients, and keys no loader covers, are left alone
+    store.set("user:2", "set").await.unwrap();
+    assert_eq!(store.get("user:2").await, Ok(Some(Bytes::from("set"))));
+    assert_eq!(store.get("session:1").await, Ok(None));
+    assert_eq!(calls.load(Ordering::Relaxed), 4);
+
+    server.shutdown();
+}
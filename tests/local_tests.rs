@@ -0,0 +1,68 @@
+#![cfg(feature = "server")]
+
+use bytes::Bytes;
+use rustis::{
+    local::{LocalClient, SHUT_DOWN_ERROR},
+    message::ResponseValue,
+    Server,
+};
+
+fn bulk(s: &str) -> ResponseValue {
+    ResponseValue::BulkString(Some(Bytes::copy_from_slice(s.as_bytes())))
+}
+
+#[tokio::test]
+async fn test_local_client() {
+    let mut server = Server::builder().port(0).workers(2).build().unwrap();
+    server.start().unwrap();
+    let client = server.client();
+
+    assert_eq!(
+        client.command(["PING"]).await,
+        ResponseValue::SimpleString("PONG".into())
+    );
+    // keys on both workers, gathered back in order
+    for (key, value) in [("a", "1"), ("b", "2"), ("c", "3"), ("d", "4")] {
+        assert_eq!(
+            client.command(["SET", key, value]).await,
+            ResponseValue::SimpleString("OK".into())
+        );
+    }
+    assert_eq!(
+        client.command(["MGET", "d", "a", "missing", "c"]).await,
+        ResponseValue::Array(Some(vec![
+            bulk("4"),
+            bulk("1"),
+            ResponseValue::BulkString(None),
+            bulk("3"),
+        ]))
+    );
+    assert_eq!(
+        client.clone().command(["DBSIZE"]).await,
+        ResponseValue::Integer(4)
+    );
+    assert!(matches!(
+        client.command(["NOSUCHCOMMAND"]).await,
+        ResponseValue::Error(_)
+    ));
+
+    server.shutdown();
+    assert_eq!(
+        client.command(["GET", "a"]).await,
+        ResponseValue::Error(SHUT_DOWN_ERROR.into())
+    );
+}
+
+#[tokio::test]
+async fn test_disconnected_client() {
+    let server = Server::builder().port(0).workers(1).build().unwrap();
+    // not started yet
+    assert_eq!(
+        server.client().command(["PING"]).await,
+        ResponseValue::Error(SHUT_DOWN_ERROR.into())
+    );
+    assert_eq!(
+        LocalClient::disconnected().command(["PING"]).await,
+        ResponseValue::Error(SHUT_DOWN_ERROR.into())
+    );
+}
@@ -0,0 +1,101 @@
+#![cfg(feature = "server")]
+
+use std::{
+    io,
+    sync::{Arc, Mutex},
+    time::{Duration, UNIX_EPOCH},
+};
+
+use rustis::{
+    config::LogLevel,
+    log::{timestamp, RedisFormat},
+};
+use tracing::level_filters::LevelFilter;
+
+/// Collects whatever the subscriber writes.
+#[derive(Clone, Default)]
+struct Captured(Arc<Mutex<Vec<u8>>>);
+
+impl io::Write for Captured {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Captured {
+    fn lines(&self) -> Vec<String> {
+        let bytes = self.0.lock().unwrap();
+        String::from_utf8_lossy(&bytes)
+            .lines()
+            .map(str::to_string)
+            .collect()
+    }
+}
+
+fn log_at(level: LogLevel, f: impl FnOnce()) -> Vec<String> {
+    let captured = Captured::default();
+    let writer = captured.clone();
+    let subscriber = tracing_subscriber::fmt()
+        .with_max_level(level.filter())
+        .event_format(RedisFormat)
+        .with_writer(move || writer.clone())
+        .finish();
+    tracing::subscriber::with_default(subscriber, f);
+    captured.lines()
+}
+
+#[test]
+fn test_timestamp() {
+    assert_eq!(timestamp(UNIX_EPOCH), "01 Jan 1970 00:00:00.000");
+    let leap_day = UNIX_EPOCH + Duration::from_millis(951_782_400_000 + 45_296_789);
+    assert_eq!(timestamp(leap_day), "29 Feb 2000 12:34:56.789");
+    let new_years_eve = UNIX_EPOCH + Duration::from_secs(1_798_761_599);
+    assert_eq!(timestamp(new_years_eve), "31 Dec 2026 23:59:59.000");
+}
+
+#[test]
+fn test_loglevel_filter() {
+    assert_eq!(LogLevel::Debug.filter(), LevelFilter::TRACE);
+    assert_eq!(LogLevel::Notice.filter(), LevelFilter::INFO);
+    assert_eq!(LogLevel::Nothing.filter(), LevelFilter::OFF);
+}
+
+#[test]
+fn test_redis_format() {
+    let lines = log_at(LogLevel::Verbose, || {
+        tracing::trace!("not logged");
+        tracing::info!("Listening on port {}", 6379);
+        let span = tracing::info_span!("client", id = 7, addr = "127.0.0.1:50000");
+        let _entered = span.enter();
+        tracing::debug!("Accepted");
+        tracing::warn!(pending = 42, "Client closed");
+    });
+    assert_eq!(lines.len(), 3, "{lines:?}");
+
+    let prefix = format!("{}:M ", std::process::id());
+    for line in &lines {
+        assert!(line.starts_with(&prefix), "{line}");
+    }
+    assert!(
+        lines[0].ends_with(" * Listening on port 6379"),
+        "{}",
+        lines[0]
+    );
+    assert!(
+        lines[1].ends_with(" - Accepted id=7 addr=\"127.0.0.1:50000\""),
+        "{}",
+        lines[1]
+    );
+    assert!(
+        lines[2].ends_with(" # Client closed pending=42 id=7 addr=\"127.0.0.1:50000\""),
+        "{}",
+        lines[2]
+    );
+
+    assert!(log_at(LogLevel::Nothing, || tracing::warn!("dropped")).is_empty());
+}
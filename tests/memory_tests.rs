@@ -0,0 +1,589 @@
+#![cfg(feature = "server")]
+
+use std::{sync::Arc, thread, time::Duration};
+
+use bytes::Bytes;
+use rustis::{
+    allocator::AllocatorStats,
+    config::{ActiveDefrag, Config, MaxmemoryPolicy},
+    defrag::Defragger,
+    kv::{unix_time_ms, KvStore, LFU_INIT_VAL},
+    message::ResponseValue,
+    router::route_message,
+    stats::{ServerStats, STATS},
+    string::StringValue,
+    telemetry::CommandTrace,
+    worker::worker_main,
+};
+use tokio::sync::mpsc;
+
+#[cfg(all(feature = "lists", feature = "sets"))]
+use rustis::config::LazyFree;
+#[cfg(any(feature = "lists", feature = "sets"))]
+use rustis::kv::{RedisValue, ValueType};
+#[cfg(feature = "lists")]
+use rustis::{
+    list::{fits_listpack, List},
+    listpack::Listpack,
+};
+#[cfg(feature = "sets")]
+use rustis::{set::Set, string::parse_int};
+
+fn b(s: &str) -> Bytes {
+    Bytes::copy_from_slice(s.as_bytes())
+}
+
+#[cfg(all(feature = "lists", feature = "sets"))]
+#[test]
+fn test_used_memory_tracks_changes() {
+    let mut store = KvStore::new();
+    assert_eq!(store.used_memory(), 0);
+
+    store.set(b("key"), b("value"));
+    let one_string = store.used_memory();
+    assert!(one_string > "keyvalue".len());
+
+    // overwriting with a longer value grows by the difference
+    store.set(b("key"), b("longer value"));
+    assert_eq!(store.used_memory(), one_string + 7);
+
+    store
+        .rpush(b("list"), vec![b("a"), b("b"), b("c")])
+        .unwrap();
+    store.sadd(b("set"), vec![b("x"), b("y")]).unwrap();
+    store.sadd(b("set"), vec![b("x")]).unwrap();
+    let full = store.used_memory();
+
+    // per-key sizes add up to the shard's total, and match a full walk
+    let keys = [b("key"), b("list"), b("set")];
+    let per_key: usize = keys
+        .iter()
+        .map(|key| store.memory_usage(key).unwrap())
+        .sum();
+    assert_eq!(per_key, full);
+    for key in &keys {
+        let value = store.get(key).unwrap().memory_usage();
+        assert!(store.memory_usage(key).unwrap() > value);
+    }
+    assert_eq!(store.memory_usage(&b("missing")), None);
+
+    let usage = store.type_usage();
+    assert_eq!(usage[ValueType::String as usize].keys, 1);
+    assert_eq!(usage[ValueType::List as usize].keys, 1);
+    assert_eq!(usage[ValueType::Set as usize].keys, 1);
+    assert_eq!(
+        usage[ValueType::List as usize].bytes,
+        store.memory_usage(&b("list")).unwrap()
+    );
+
+    store.lpop(&b("list"), 1).unwrap();
+    assert!(store.used_memory() < full);
+
+    // replacing a list with a string moves the key between types
+    store.set(b("list"), b("now a string"));
+    let usage = store.type_usage();
+    assert_eq!(usage[ValueType::String as usize].keys, 2);
+    assert_eq!(usage[ValueType::List as usize], Default::default());
+    store.del(&b("list"));
+    store.rpush(b("list"), vec![b("b"), b("c")]).unwrap();
+
+    store.rpop(&b("list"), 5).unwrap();
+    store.spop(&b("set"), 5).unwrap();
+    store.del(&b("key"));
+    assert_eq!(store.used_memory(), 0);
+
+    store.lpush(b("list"), vec![b("a")]).unwrap();
+    store.clear();
+    assert_eq!(store.used_memory(), 0);
+}
+
+#[cfg(feature = "lists")]
+#[test]
+fn test_listpack() {
+    let mut pack = Listpack::new();
+    pack.push_back(&b("b"));
+    pack.push_front(&b(""));
+    pack.push_back(&Bytes::from(vec![b'x'; 300]));
+    pack.push_front(&b("a"));
+    assert_eq!(pack.len(), 4);
+    let items: Vec<&[u8]> = pack.iter().collect();
+    assert_eq!(items, [&b"a"[..], b"", b"b", &[b'x'; 300]]);
+    assert_eq!(pack.bytes(), 3 + 2 + 3 + (300 + 4));
+
+    assert_eq!(pack.pop_back(), Some(Bytes::from(vec![b'x'; 300])));
+    assert_eq!(pack.pop_front(), Some(b("a")));
+    assert_eq!(pack.pop_front(), Some(b("")));
+    assert_eq!(pack.pop_back(), Some(b("b")));
+    assert_eq!(pack.pop_back(), None);
+    assert!(pack.is_empty());
+    assert_eq!(pack.bytes(), 0);
+}
+
+#[cfg(feature = "lists")]
+#[test]
+fn test_list_encoding() {
+    assert!(fits_listpack(3, 3, 1 << 20));
+    assert!(!fits_listpack(3, 4, 0));
+    assert!(fits_listpack(-2, 1000, 8192));
+    assert!(!fits_listpack(-2, 1, 8193));
+    assert!(fits_listpack(-5, 1, 65536));
+
+    let mut list = List::new();
+    for i in 0..3 {
+        list.push_back(b(&i.to_string()), 3);
+    }
+    assert_eq!(list.encoding(), "listpack");
+    list.push_front(b("x"), 3);
+    assert_eq!(list.encoding(), "quicklist");
+    let items: Vec<Bytes> = list.iter().collect();
+    assert_eq!(items, [b("x"), b("0"), b("1"), b("2")]);
+
+    // shrinking back under the limit keeps the general encoding
+    list.pop_front();
+    list.pop_back();
+    assert_eq!(list.encoding(), "quicklist");
+    assert_eq!(list.len(), 2);
+
+    // the store packs small lists and converts them past 8KB
+    let mut store = KvStore::new();
+    let values: Vec<Bytes> = (0..100).map(|i| b(&format!("item{}", i))).collect();
+    store.rpush(b("list"), values.clone()).unwrap();
+    let Some(RedisValue::List(list)) = store.get(&b("list")) else {
+        panic!("expected list");
+    };
+    assert_eq!(list.encoding(), "listpack");
+    assert_eq!(list.iter().collect::<Vec<_>>(), values);
+
+    // packing saves the per-element overhead
+    let mut deque = List::new();
+    for value in &values {
+        deque.push_back(value.clone(), 1);
+    }
+    assert_eq!(deque.encoding(), "quicklist");
+    assert!(list.memory_usage() * 4 < deque.memory_usage());
+
+    store
+        .rpush(b("list"), vec![Bytes::from(vec![b'x'; 8192])])
+        .unwrap();
+    let Some(RedisValue::List(list)) = store.get(&b("list")) else {
+        panic!("expected list");
+    };
+    assert_eq!(list.encoding(), "quicklist");
+    assert_eq!(list.len(), 101);
+    assert_eq!(
+        store.used_memory(),
+        store.type_usage()[ValueType::List as usize].bytes
+    );
+    assert_eq!(store.rpop(&b("list"), 101).unwrap().len(), 101);
+    assert_eq!(store.used_memory(), 0);
+}
+
+#[test]
+fn test_string_encodings() {
+    for (value, encoding) in [
+        ("0", "int"),
+        ("-9223372036854775808", "int"),
+        ("007", "embstr"),
+        ("", "embstr"),
+        ("12345678901234567890", "embstr"),
+        (&"x".repeat(44), "embstr"),
+        (&"x".repeat(45), "raw"),
+    ] {
+        let string = StringValue::from(b(value));
+        assert_eq!(string.encoding(), encoding, "{value:?}");
+        assert_eq!(string.len(), value.len());
+        assert_eq!(string.to_bytes(), value.as_bytes());
+    }
+    assert_eq!(StringValue::from(b("42")).as_int(), Some(42));
+    assert_eq!(StringValue::from(b("42 ")).as_int(), None);
+
+    // integers take nothing besides the entry
+    let mut store = KvStore::new();
+    store.set(b("n"), b("1000"));
+    let int = store.used_memory();
+    store.set(b("n"), b("1000x"));
+    assert_eq!(store.used_memory(), int + 5);
+}
+
+#[cfg(feature = "sets")]
+#[test]
+fn test_intset() {
+    assert_eq!(parse_int(b"0"), Some(0));
+    assert_eq!(parse_int(b"-42"), Some(-42));
+    assert_eq!(parse_int(b"9223372036854775807"), Some(i64::MAX));
+    assert_eq!(parse_int(b"-9223372036854775808"), Some(i64::MIN));
+    for bad in [
+        "",
+        "-",
+        "-0",
+        "007",
+        "+1",
+        " 1",
+        "1.0",
+        "9223372036854775808",
+    ] {
+        assert_eq!(parse_int(bad.as_bytes()), None, "{bad:?}");
+    }
+
+    let mut set = Set::new();
+    assert!(set.insert(b("10"), 3));
+    assert!(set.insert(b("-5"), 3));
+    assert!(!set.insert(b("10"), 3));
+    assert!(set.insert(b("7"), 3));
+    assert_eq!(set, Set::Ints(vec![-5, 7, 10]));
+    assert_eq!(set.memory_usage(), 24);
+    assert!(set.contains(b"7"));
+    assert!(!set.contains(b"07"));
+
+    // one entry too many converts it, keeping the members
+    assert!(set.insert(b("1"), 3));
+    assert_eq!(set.encoding(), "hashtable");
+    assert_eq!(set.len(), 4);
+    assert!(set.contains(b"-5"));
+    assert!(!set.insert(b("10"), 3));
+
+    // so does a member that isn't an integer
+    let mut set = Set::new();
+    set.insert(b("1"), 512);
+    set.insert(b("007"), 512);
+    assert_eq!(set.encoding(), "hashtable");
+    assert!(set.contains(b"1") && set.contains(b"007"));
+
+    // the store accounts for both encodings
+    let mut store = KvStore::new();
+    let ints: Vec<Bytes> = (0..100).map(|i| b(&i.to_string())).collect();
+    store.sadd(b("set"), ints.clone()).unwrap();
+    let Some(RedisValue::Set(set)) = store.get(&b("set")) else {
+        panic!("expected set");
+    };
+    assert_eq!(set.encoding(), "intset");
+    let mut members: Vec<Bytes> = set.iter().collect();
+    members.sort();
+    let mut expected = ints.clone();
+    expected.sort();
+    assert_eq!(members, expected);
+
+    store.sadd(b("set"), vec![b("x")]).unwrap();
+    assert_eq!(store.get(&b("set")).unwrap().encoding(), "hashtable");
+    assert_eq!(
+        store.used_memory(),
+        store.type_usage()[ValueType::Set as usize].bytes
+    );
+    assert_eq!(store.spop(&b("set"), 200).unwrap().len(), 101);
+    assert_eq!(store.used_memory(), 0);
+
+    store.sadd(b("set"), ints).unwrap();
+    assert_eq!(store.spop(&b("set"), 200).unwrap().len(), 100);
+    assert_eq!(store.used_memory(), 0);
+}
+
+#[test]
+fn test_defrag_effort() {
+    let config = Config {
+        active_defrag: ActiveDefrag {
+            enabled: true,
+            ignore_bytes: 1000,
+            ..ActiveDefrag::default()
+        },
+        ..Config::default()
+    };
+    let defrag = Defragger::new(&config);
+    let stats = |allocated, active| AllocatorStats {
+        allocated: Some(allocated),
+        active: Some(active),
+        resident: None,
+    };
+
+    // under either threshold there is nothing to do
+    assert_eq!(defrag.effort(&stats(100_000, 105_000)), None);
+    assert_eq!(defrag.effort(&stats(1000, 1500)), None);
+    assert_eq!(defrag.effort(&AllocatorStats::default()), None);
+    // effort grows from cycle-min at the lower threshold to cycle-max
+    assert_eq!(defrag.effort(&stats(100_000, 110_000)), Some(1));
+    assert_eq!(defrag.effort(&stats(100_000, 155_000)), Some(13));
+    assert_eq!(defrag.effort(&stats(100_000, 200_000)), Some(25));
+    assert_eq!(defrag.effort(&stats(100_000, 900_000)), Some(25));
+}
+
+#[cfg(all(feature = "lists", feature = "sets"))]
+#[test]
+fn test_defrag_walk() {
+    let mut store = KvStore::new();
+    let long = Bytes::from(vec![b'x'; 100]);
+    for i in 0..100 {
+        let key = b(&format!("key{i}"));
+        match i % 4 {
+            0 => store.set(key, long.clone()),
+            1 => store.set(key, b(&i.to_string())),
+            2 => drop(store.rpush(key, vec![b("a"), long.clone()])),
+            _ => drop(store.sadd(key, vec![b("1"), b("2")])),
+        }
+    }
+    let used = store.used_memory();
+    // defrag waits out a rehash, which moves every node anyway
+    while store.rehash(100) {}
+
+    let (mut cursor, mut keys) = (Some(0), 0);
+    while let Some(at) = cursor {
+        let (next, hits, walked) = store.defrag(at, 3);
+        assert!(hits >= walked * 2);
+        keys += walked;
+        cursor = next;
+    }
+    assert_eq!(keys, 100);
+    assert_eq!(store.used_memory(), used);
+
+    // the copies no longer share the original buffer
+    let Some(RedisValue::String(string)) = store.get(&b("key0")) else {
+        panic!("expected a string");
+    };
+    assert_eq!(string.to_bytes(), long);
+    assert_ne!(string.to_bytes().as_ptr(), long.as_ptr());
+    assert_eq!(store.lrange(&b("key2"), 0, -1).unwrap(), [b("a"), long]);
+    assert_eq!(store.smembers(&b("key3")).unwrap().len(), 2);
+}
+
+#[test]
+fn test_evict_policies() {
+    let mut store = KvStore::new();
+    assert_eq!(store.evict(MaxmemoryPolicy::AllKeysRandom, 5), None);
+
+    store.set(b("a"), b("1"));
+    assert_eq!(store.evict(MaxmemoryPolicy::NoEviction, 5), None);
+    // nothing has a TTL
+    for policy in [
+        MaxmemoryPolicy::VolatileLru,
+        MaxmemoryPolicy::VolatileLfu,
+        MaxmemoryPolicy::VolatileRandom,
+        MaxmemoryPolicy::VolatileTtl,
+    ] {
+        assert_eq!(store.evict(policy, 5), None);
+    }
+
+    let used = store.used_memory();
+    assert_eq!(store.evict(MaxmemoryPolicy::AllKeysRandom, 5), Some(used));
+    assert!(store.is_empty());
+}
+
+#[test]
+fn test_evict_lru_and_lfu() {
+    let mut store = KvStore::new();
+    for key in ["a", "b", "c"] {
+        store.set(b(key), b(key));
+        thread::sleep(Duration::from_millis(5));
+    }
+    store.get(&b("a"));
+    thread::sleep(Duration::from_millis(5));
+    store.get(&b("c"));
+
+    // sampling every key makes the choice exact
+    store.evict(MaxmemoryPolicy::AllKeysLru, 3).unwrap();
+    assert!(!store.exists(&b("b")));
+    store.evict(MaxmemoryPolicy::AllKeysLru, 3).unwrap();
+    assert!(!store.exists(&b("a")));
+
+    let mut store = KvStore::new();
+    store.set(b("hot"), b("1"));
+    store.set(b("cold"), b("1"));
+    for _ in 0..10 {
+        store.get(&b("hot"));
+    }
+    store.evict(MaxmemoryPolicy::AllKeysLfu, 2).unwrap();
+    assert!(store.exists(&b("hot")));
+    assert!(!store.exists(&b("cold")));
+}
+
+#[test]
+fn test_evict_volatile() {
+    let now = unix_time_ms();
+    let mut store = KvStore::new();
+    for key in ["a", "b", "c", "d"] {
+        store.set(b(key), b(key));
+    }
+    store.set_expires_at(&b("c"), Some(now + 20_000));
+    store.set_expires_at(&b("b"), Some(now + 10_000));
+    store.set_expires_at(&b("d"), Some(now + 30_000));
+
+    // the key that expires first, however many are sampled
+    store.evict(MaxmemoryPolicy::VolatileTtl, 1).unwrap();
+    assert!(!store.exists(&b("b")));
+    store.evict(MaxmemoryPolicy::VolatileTtl, 5).unwrap();
+    assert!(!store.exists(&b("c")));
+
+    for policy in [
+        MaxmemoryPolicy::VolatileLru,
+        MaxmemoryPolicy::VolatileLfu,
+        MaxmemoryPolicy::VolatileRandom,
+    ] {
+        store.set(b("e"), b("e"));
+        store.set_expires_at(&b("e"), Some(now + 40_000));
+        store.evict(policy, 5).unwrap();
+        store.evict(policy, 5).unwrap();
+        // only keys with a TTL go
+        assert!(store.exists(&b("a")));
+        assert_eq!(store.volatile_keys(), 0);
+        assert_eq!(store.evict(policy, 5), None);
+        store.set(b("d"), b("d"));
+        store.set_expires_at(&b("d"), Some(now + 30_000));
+    }
+}
+
+#[test]
+fn test_lfu_counter() {
+    // with a log factor of 0 every access counts
+    let mut store = KvStore::with_lfu(0, 1);
+    store.set(b("key"), b("value"));
+    assert_eq!(store.object_freq(&b("key")), Some(LFU_INIT_VAL));
+    for _ in 0..10 {
+        store.get(&b("key"));
+    }
+    assert_eq!(store.object_freq(&b("key")), Some(LFU_INIT_VAL + 10));
+    // looking at it is not an access
+    assert_eq!(store.object_freq(&b("key")), Some(LFU_INIT_VAL + 10));
+    assert_eq!(store.object_idletime(&b("key")), Some(0));
+    assert_eq!(store.object_freq(&b("missing")), None);
+
+    // with the default factor hits become rare steps once the counter grows
+    let mut store = KvStore::new();
+    store.set(b("key"), b("value"));
+    for _ in 0..10_000 {
+        store.get(&b("key"));
+    }
+    let freq = store.object_freq(&b("key")).unwrap();
+    assert!(freq > LFU_INIT_VAL + 10 && freq < 100, "counter {freq}");
+}
+
+/// Waits for the lazy-free thread to have dropped `count` values in total.
+#[cfg(all(feature = "lists", feature = "sets"))]
+fn wait_for_lazyfreed(count: u64) {
+    for _ in 0..200 {
+        if ServerStats::get(&STATS.lazyfreed_objects) >= count {
+            return;
+        }
+        thread::sleep(Duration::from_millis(5));
+    }
+    panic!("lazy-free thread did not catch up");
+}
+
+#[cfg(all(feature = "lists", feature = "sets"))]
+#[test]
+fn test_lazy_free() {
+    let before = ServerStats::get(&STATS.lazyfreed_objects);
+    // long enough that the list outgrows the packed encoding
+    let big: Vec<Bytes> = (0..1000).map(|i| b(&format!("{i:>16}"))).collect();
+
+    let mut store = KvStore::new();
+    store.rpush(b("small"), vec![b("a")]).unwrap();
+    store.rpush(b("big"), big.clone()).unwrap();
+
+    // small values are always dropped in place
+    assert!(store.unlink(&b("small")));
+    assert!(store.unlink(&b("big")));
+    assert!(!store.unlink(&b("big")));
+    wait_for_lazyfreed(before + 1);
+
+    // DEL only goes through the thread with lazyfree-lazy-user-del
+    store.sadd(b("big"), big.clone()).unwrap();
+    store.del(&b("big"));
+    let config = Config {
+        lazyfree: LazyFree {
+            user_del: true,
+            ..LazyFree::default()
+        },
+        ..Config::default()
+    };
+    let mut lazy = KvStore::from_config(&config);
+    lazy.sadd(b("big"), big).unwrap();
+    lazy.del(&b("big"));
+    wait_for_lazyfreed(before + 2);
+
+    // an async flush hands over the whole shard
+    lazy.set(b("a"), b("1"));
+    lazy.flush(true);
+    assert!(lazy.is_empty());
+    assert_eq!(lazy.used_memory(), 0);
+    wait_for_lazyfreed(before + 3);
+    assert_eq!(ServerStats::get(&STATS.lazyfreed_objects), before + 3);
+}
+
+fn bulk(s: &str) -> ResponseValue {
+    ResponseValue::BulkString(Some(b(s)))
+}
+
+fn command(args: &[&str]) -> ResponseValue {
+    ResponseValue::Array(Some(args.iter().map(|arg| bulk(arg)).collect()))
+}
+
+async fn run(
+    worker: &[mpsc::Sender<rustis::message::WorkerMessage>],
+    args: &[&str],
+) -> ResponseValue {
+    let (writer_tx, mut writer_rx) = mpsc::channel(1);
+    let permit = writer_tx.reserve_owned().await.unwrap();
+    route_message(worker, command(args), 0, permit, CommandTrace::default()).await;
+    writer_rx.recv().await.unwrap().response_value
+}
+
+#[tokio::test]
+async fn test_maxmemory_on_worker() {
+    let ok = ResponseValue::SimpleString("OK".into());
+
+    // noeviction: writes are refused once over the limit, reads and deletes are not
+    let config = Config {
+        maxmemory: 1,
+        ..Config::default()
+    };
+    let (tx, rx) = mpsc::channel(16);
+    let worker = thread::spawn(move || worker_main(0, rx, Arc::new(config)));
+    let shard = vec![tx];
+
+    assert_eq!(run(&shard, &["SET", "a", "1"]).await, ok);
+    match run(&shard, &["SET", "b", "2"]).await {
+        ResponseValue::Error(err) => assert!(err.starts_with(b"OOM ")),
+        other => panic!("expected OOM, got {other:?}"),
+    }
+    assert_eq!(run(&shard, &["GET", "a"]).await, bulk("1"));
+    assert_eq!(run(&shard, &["DEL", "a"]).await, ResponseValue::Integer(1));
+    assert_eq!(run(&shard, &["SET", "b", "2"]).await, ok);
+
+    let usage = match run(&shard, &["MEMORY", "USAGE", "b"]).await {
+        ResponseValue::Integer(bytes) => bytes,
+        other => panic!("expected integer, got {other:?}"),
+    };
+    assert!(usage > 2);
+    match run(&shard, &["MEMORY", "STATS"]).await {
+        ResponseValue::Array(Some(fields)) => {
+            let at = fields
+                .iter()
+                .position(|f| *f == bulk("string.keys"))
+                .unwrap();
+            assert_eq!(fields[at + 1], ResponseValue::Integer(1));
+        }
+        other => panic!("expected array, got {other:?}"),
+    }
+
+    drop(shard);
+    worker.join().unwrap();
+    assert_eq!(ServerStats::get(&STATS.used_memory), 0);
+
+    // allkeys-lru: old keys make room for new ones
+    let config = Config {
+        maxmemory: 4096,
+        maxmemory_policy: MaxmemoryPolicy::AllKeysLru,
+        ..Config::default()
+    };
+    let (tx, rx) = mpsc::channel(16);
+    thread::spawn(move || worker_main(0, rx, Arc::new(config)));
+    let shard = vec![tx];
+
+    for i in 0..200 {
+        let key = format!("key:{i}");
+        assert_eq!(run(&shard, &["SET", &key, "value"]).await, ok);
+    }
+    match run(&shard, &["DBSIZE"]).await {
+        ResponseValue::Integer(keys) => assert!(keys > 0 && keys < 200, "{keys} keys"),
+        other => panic!("expected integer, got {other:?}"),
+    }
+    assert!(ServerStats::get(&STATS.evicted_keys) > 0);
+    assert!(ServerStats::get(&STATS.used_memory) <= 4096 + 256);
+}
@@ -0,0 +1,131 @@
+use bytes::{Bytes, BytesMut};
+use rustis::message::{ResponseValue, VECTORED_WRITE_THRESHOLD};
+
+fn sample_values() -> Vec<ResponseValue> {
+    vec![
+        ResponseValue::SimpleString("OK".into()),
+        ResponseValue::Error("ERR boom".into()),
+        ResponseValue::Integer(0),
+        ResponseValue::Integer(-12345),
+        ResponseValue::Integer(i64::MIN),
+        ResponseValue::Integer(i64::MAX),
+        ResponseValue::BulkString(None),
+        ResponseValue::BulkString(Some(Bytes::from("hello"))),
+        ResponseValue::Array(None),
+        ResponseValue::Array(Some(vec![])),
+        ResponseValue::Array(Some(vec![
+            ResponseValue::Integer(1),
+            ResponseValue::BulkString(Some(Bytes::from(vec![b'x'; 1000]))),
+            ResponseValue::Array(Some(vec![ResponseValue::SimpleString("nested".into())])),
+        ])),
+        ResponseValue::BigNumber("1234567890123456789012345678901234567890".into()),
+        ResponseValue::VerbatimString {
+            format: *b"txt",
+            data: Bytes::from("Some string"),
+        },
+        ResponseValue::Attribute {
+            attrs: vec![(
+                ResponseValue::SimpleString("key-popularity".into()),
+                ResponseValue::Integer(1),
+            )],
+            value: Box::new(ResponseValue::BulkString(Some(Bytes::from("v")))),
+        },
+    ]
+}
+
+#[test]
+fn test_encoded_len_matches_serialize() {
+    for value in sample_values() {
+        let mut buf = BytesMut::new();
+        value.serialize(&mut buf);
+        assert_eq!(
+            value.encoded_len(),
+            buf.len(),
+            "size hint wrong for {:?}",
+            value
+        );
+    }
+}
+
+#[test]
+fn test_serialize_vectored_small_values_stay_in_buffer() {
+    for value in sample_values() {
+        let mut expected = BytesMut::new();
+        value.serialize(&mut expected);
+
+        let mut buf = BytesMut::new();
+        let mut chunks = Vec::new();
+        value.serialize_vectored(&mut buf, &mut chunks);
+
+        assert!(chunks.is_empty());
+        assert_eq!(buf, expected);
+    }
+}
+
+#[test]
+fn test_serialize_vectored_large_bulk_is_not_copied() {
+    let payload = Bytes::from(vec![b'a'; VECTORED_WRITE_THRESHOLD]);
+    let value = ResponseValue::Array(Some(vec![
+        ResponseValue::Integer(7),
+        ResponseValue::BulkString(Some(payload.clone())),
+    ]));
+
+    let mut expected = BytesMut::new();
+    value.serialize(&mut expected);
+
+    let mut buf = BytesMut::new();
+    let mut chunks = Vec::new();
+    value.serialize_vectored(&mut buf, &mut chunks);
+
+    assert_eq!(chunks.len(), 2);
+    // the payload chunk points at the original allocation
+    assert_eq!(chunks[1].as_ptr(), payload.as_ptr());
+
+    let mut joined = BytesMut::new();
+    for chunk in &chunks {
+        joined.extend_from_slice(chunk);
+    }
+    joined.extend_from_slice(&buf);
+    assert_eq!(joined, expected);
+}
+
+#[test]
+fn test_serialize_resp3_frames() {
+    let mut buf = BytesMut::new();
+    ResponseValue::BigNumber("-42".into()).serialize(&mut buf);
+    assert_eq!(&buf[..], b"(-42\r\n");
+
+    let mut buf = BytesMut::new();
+    ResponseValue::VerbatimString {
+        format: *b"txt",
+        data: Bytes::from("Some string"),
+    }
+    .serialize(&mut buf);
+    assert_eq!(&buf[..], b"=15\r\ntxt:Some string\r\n");
+
+    let mut buf = BytesMut::new();
+    ResponseValue::Attribute {
+        attrs: vec![(
+            ResponseValue::SimpleString("a".into()),
+            ResponseValue::Integer(1),
+        )],
+        value: Box::new(ResponseValue::SimpleString("OK".into())),
+    }
+    .serialize(&mut buf);
+    assert_eq!(&buf[..], b"|1\r\n+a\r\n:1\r\n+OK\r\n");
+}
+
+#[test]
+fn test_serialize_line_breaks() {
+    // a client's argument echoed in an error can't end the line early
+    let error = ResponseValue::Error("ERR unknown subcommand 'a\r\n+OK\r\n'".into());
+    let mut buf = BytesMut::new();
+    error.serialize(&mut buf);
+    assert_eq!(&buf[..], b"-ERR unknown subcommand 'a  +OK  '\r\n");
+    assert_eq!(buf.len(), error.encoded_len());
+
+    // lone ones don't end it
+    let mut buf = BytesMut::new();
+    ResponseValue::SimpleString("a\rb\n".into()).serialize(&mut buf);
+    assert_eq!(&buf[..], b"+a\rb\n\r\n");
+}
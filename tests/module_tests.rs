@@ -0,0 +1,397 @@
+use std::{
+    ffi::{c_char, c_int, c_void},
+    path::Path,
+    sync::{
+        atomic::{AtomicPtr, Ordering},
+        Once,
+    },
+};
+
+use bytes::Bytes;
+use rustis::{
+    handler::{command_name, denies_oom, is_write_command, process_command},
+    kv::{KvStore, RedisValue},
+    message::ResponseValue,
+    module::{self, CommandFlags, ModuleData, ModuleValue, Modules},
+    module_abi::{
+        CommandContext, ModuleApi, ModuleType, Registration, TypeMethods, ABI_VERSION, ERR,
+        KEY_FOUND, KEY_MISSING, OK,
+    },
+};
+
+fn make_cmd(args: &[&str]) -> ResponseValue {
+    let items = args
+        .iter()
+        .map(|s| ResponseValue::BulkString(Some(Bytes::copy_from_slice(s.as_bytes()))))
+        .collect();
+    ResponseValue::Array(Some(items))
+}
+
+/// A Rust module type: a counter.
+#[derive(Debug, Clone, PartialEq)]
+struct Counter(i64);
+
+impl ModuleData for Counter {
+    fn type_name(&self) -> &'static str {
+        "counter"
+    }
+
+    fn memory_usage(&self) -> usize {
+        std::mem::size_of::<Self>()
+    }
+
+    fn clone_data(&self) -> Box<dyn ModuleData> {
+        Box::new(self.clone())
+    }
+}
+
+fn counter_incr(kv: &mut KvStore, args: &[Bytes]) -> ResponseValue {
+    let [key] = args else {
+        return ResponseValue::Error("ERR wrong number of arguments".into());
+    };
+    let value = kv.update(key, |value| match value {
+        RedisValue::Module(value) => value.downcast_mut::<Counter>().map(|counter| {
+            counter.0 += 1;
+            counter.0
+        }),
+        _ => None,
+    });
+    match value {
+        Some(Some(n)) => ResponseValue::Integer(n),
+        Some(None) => ResponseValue::Error("WRONGTYPE not a counter".into()),
+        None => {
+            kv.insert(
+                key.clone(),
+                RedisValue::Module(ModuleValue::new(Counter(1))),
+            );
+            ResponseValue::Integer(1)
+        }
+    }
+}
+
+// A module over the C ABI: `abi.incr <key>` counts in a boxed i64 of type
+// `abicount`, `abi.args` replies with the init arguments.
+
+static API: AtomicPtr<ModuleApi> = AtomicPtr::new(std::ptr::null_mut());
+static COUNTER: AtomicPtr<ModuleType> = AtomicPtr::new(std::ptr::null_mut());
+static ARGS: std::sync::Mutex<Vec<String>> = std::sync::Mutex::new(Vec::new());
+
+fn api() -> &'static ModuleApi {
+    unsafe { &*API.load(Ordering::Relaxed) }
+}
+
+unsafe extern "C" fn free_count(value: *mut c_void) {
+    drop(unsafe { Box::from_raw(value as *mut i64) });
+}
+
+unsafe extern "C" fn copy_count(value: *const c_void) -> *mut c_void {
+    Box::into_raw(Box::new(unsafe { *(value as *const i64) })) as *mut c_void
+}
+
+unsafe extern "C" fn abi_incr(
+    ctx: *mut CommandContext,
+    argc: usize,
+    argv: *const *const u8,
+    argvlen: *const usize,
+) {
+    let api = api();
+    if argc != 1 {
+        let message = b"ERR wrong number of arguments";
+        unsafe { (api.reply_error)(ctx, message.as_ptr(), message.len()) };
+        return;
+    }
+    let (key, keylen) = unsafe { (*argv, *argvlen) };
+    let ty = COUNTER.load(Ordering::Relaxed);
+    let mut value = std::ptr::null_mut();
+    match unsafe { (api.value_get)(ctx, key, keylen, ty, &mut value) } {
+        KEY_FOUND => {
+            let count = unsafe { &mut *(value as *mut i64) };
+            *count += 1;
+            unsafe { (api.reply_integer)(ctx, *count) };
+        }
+        KEY_MISSING => {
+            let value = Box::into_raw(Box::new(1i64)) as *mut c_void;
+            unsafe {
+                (api.value_set)(ctx, key, keylen, ty, value);
+                (api.reply_integer)(ctx, 1);
+            }
+        }
+        _ => {
+            let message = b"WRONGTYPE not an abicount";
+            unsafe { (api.reply_error)(ctx, message.as_ptr(), message.len()) };
+        }
+    }
+}
+
+unsafe extern "C" fn abi_args(
+    ctx: *mut CommandContext,
+    _argc: usize,
+    _argv: *const *const u8,
+    _argvlen: *const usize,
+) {
+    let api = api();
+    let args = ARGS.lock().unwrap();
+    unsafe { (api.reply_array)(ctx, args.len()) };
+    for arg in args.iter() {
+        unsafe { (api.reply_bulk)(ctx, arg.as_ptr(), arg.len()) };
+    }
+}
+
+unsafe extern "C" fn abi_silent(
+    _ctx: *mut CommandContext,
+    _argc: usize,
+    _argv: *const *const u8,
+    _argvlen: *const usize,
+) {
+}
+
+unsafe extern "C" fn abi_init(
+    api: *const ModuleApi,
+    registration: *mut Registration,
+    argc: usize,
+    argv: *const *const c_char,
+) -> c_int {
+    let api_ref = unsafe { &*api };
+    if api_ref.abi_version != ABI_VERSION {
+        return ERR;
+    }
+    API.store(api as *mut ModuleApi, Ordering::Relaxed);
+    let args = unsafe { std::slice::from_raw_parts(argv, argc) };
+    *ARGS.lock().unwrap() = args
+        .iter()
+        .map(|arg| {
+            unsafe { std::ffi::CStr::from_ptr(*arg) }
+                .to_string_lossy()
+                .into_owned()
+        })
+        .collect();
+
+    let methods = TypeMethods {
+        free: free_count,
+        copy: copy_count,
+        memory_usage: None,
+    };
+    let ty = unsafe { (api_ref.register_type)(registration, c"abicount".as_ptr(), &methods) };
+    if ty.is_null() {
+        return ERR;
+    }
+    COUNTER.store(ty as *mut ModuleType, Ordering::Relaxed);
+    let commands: [(&std::ffi::CStr, _); 3] = [
+        (c"abi.incr", abi_incr as _),
+        (c"abi.args", abi_args as _),
+        (c"abi.silent", abi_silent as _),
+    ];
+    for (name, handler) in commands {
+        let flags = c"write deny-oom";
+        if unsafe {
+            (api_ref.register_command)(registration, name.as_ptr(), flags.as_ptr(), handler)
+        } != OK
+        {
+            return ERR;
+        }
+    }
+    OK
+}
+
+unsafe extern "C" fn failing_init(
+    api: *const ModuleApi,
+    registration: *mut Registration,
+    _argc: usize,
+    _argv: *const *const c_char,
+) -> c_int {
+    let api = unsafe { &*api };
+    unsafe { (api.register_command)(registration, c"fail.cmd".as_ptr(), c"".as_ptr(), abi_silent) };
+    ERR
+}
+
+/// Succeeds if flags that aren't UTF-8, and a type without methods, are
+/// refused.
+unsafe extern "C" fn refused_init(
+    api: *const ModuleApi,
+    registration: *mut Registration,
+    _argc: usize,
+    _argv: *const *const c_char,
+) -> c_int {
+    let api = unsafe { &*api };
+    let flags = c"write \xff";
+    let command = unsafe {
+        (api.register_command)(
+            registration,
+            c"bad.flags".as_ptr(),
+            flags.as_ptr(),
+            abi_silent,
+        )
+    };
+    let ty = unsafe { (api.register_type)(registration, c"nomethods".as_ptr(), std::ptr::null()) };
+    match (command, ty.is_null()) {
+        (ERR, true) => OK,
+        _ => ERR,
+    }
+}
+
+fn setup() {
+    static SETUP: Once = Once::new();
+    SETUP.call_once(|| {
+        let mut modules = Modules::new();
+        modules
+            .command(
+                "Counter.Incr",
+                CommandFlags::parse("write deny-oom").unwrap(),
+                counter_incr,
+            )
+            .unwrap();
+        modules
+            .command("counter.peek", CommandFlags::default(), |_, _| {
+                ResponseValue::SimpleString("PEEK".into())
+            })
+            .unwrap();
+        unsafe { modules.init(abi_init, &["one".into(), "two".into()]) }.unwrap();
+        assert!(module::install(modules));
+    });
+}
+
+#[test]
+fn test_rust_module_command() {
+    setup();
+    let mut kv = KvStore::new();
+    for expected in 1..=3 {
+        let reply = process_command(&mut kv, make_cmd(&["COUNTER.INCR", "hits"]));
+        assert_eq!(reply, ResponseValue::Integer(expected));
+    }
+    match kv.get(&Bytes::from("hits")) {
+        Some(RedisValue::Module(value)) => {
+            assert_eq!(value.type_name(), "counter");
+            assert_eq!(value.downcast_ref::<Counter>(), Some(&Counter(3)));
+        }
+        other => panic!("unexpected value {other:?}"),
+    }
+
+    let reply = process_command(&mut kv, make_cmd(&["OBJECT", "ENCODING", "hits"]));
+    assert_eq!(reply, ResponseValue::BulkString(Some("counter".into())));
+    let reply = process_command(&mut kv, make_cmd(&["GET", "hits"]));
+    assert!(matches!(reply, ResponseValue::Error(e) if e.starts_with(b"WRONGTYPE")));
+
+    process_command(&mut kv, make_cmd(&["SET", "plain", "x"]));
+    let reply = process_command(&mut kv, make_cmd(&["counter.incr", "plain"]));
+    assert!(matches!(reply, ResponseValue::Error(e) if e.starts_with(b"WRONGTYPE")));
+
+    let reply = process_command(&mut kv, make_cmd(&["DEL", "hits"]));
+    assert_eq!(reply, ResponseValue::Integer(1));
+    assert_eq!(kv.memory_usage(&Bytes::from("hits")), None);
+}
+
+#[test]
+fn test_abi_module_command() {
+    setup();
+    let mut kv = KvStore::new();
+    for expected in 1..=3 {
+        let reply = process_command(&mut kv, make_cmd(&["abi.incr", "n"]));
+        assert_eq!(reply, ResponseValue::Integer(expected));
+    }
+    let reply = process_command(&mut kv, make_cmd(&["OBJECT", "ENCODING", "n"]));
+    assert_eq!(reply, ResponseValue::BulkString(Some("abicount".into())));
+
+    // another module's type is the wrong type
+    process_command(&mut kv, make_cmd(&["counter.incr", "rust"]));
+    let reply = process_command(&mut kv, make_cmd(&["abi.incr", "rust"]));
+    assert!(matches!(reply, ResponseValue::Error(e) if e.starts_with(b"WRONGTYPE")));
+
+    let reply = process_command(&mut kv, make_cmd(&["abi.incr"]));
+    assert!(matches!(reply, ResponseValue::Error(_)));
+    let reply = process_command(&mut kv, make_cmd(&["abi.args"]));
+    assert_eq!(
+        reply,
+        ResponseValue::Array(Some(vec![
+            ResponseValue::BulkString(Some("one".into())),
+            ResponseValue::BulkString(Some("two".into())),
+        ]))
+    );
+    let reply = process_command(&mut kv, make_cmd(&["abi.silent"]));
+    assert_eq!(
+        reply,
+        ResponseValue::Error("ERR module command sent no reply".into())
+    );
+
+    // values are freed through the module
+    let reply = process_command(&mut kv, make_cmd(&["DEL", "n"]));
+    assert_eq!(reply, ResponseValue::Integer(1));
+}
+
+#[test]
+fn test_module_command_flags() {
+    setup();
+    assert_eq!(
+        command_name(&make_cmd(&["COUNTER.INCR", "k"])),
+        Some("counter.incr")
+    );
+    assert_eq!(
+        command_name(&make_cmd(&["abi.incr", "k"])),
+        Some("abi.incr")
+    );
+    assert_eq!(command_name(&make_cmd(&["nosuch.cmd", "k"])), None);
+    assert!(is_write_command(&make_cmd(&["counter.incr", "k"])));
+    assert!(denies_oom(&make_cmd(&["counter.incr", "k"])));
+    assert!(!is_write_command(&make_cmd(&["counter.peek", "k"])));
+    assert!(!denies_oom(&make_cmd(&["counter.peek", "k"])));
+
+    assert_eq!(
+        CommandFlags::parse("readonly fast").unwrap(),
+        CommandFlags::default()
+    );
+    assert!(CommandFlags::parse("write bogus").is_err());
+}
+
+#[test]
+fn test_module_registration_errors() {
+    setup();
+    let mut modules = Modules::new();
+    let flags = CommandFlags::default();
+    let reply = |_: &mut KvStore, _: &[Bytes]| ResponseValue::Integer(0);
+    assert!(modules.command("get", flags, reply).is_err());
+    assert!(modules.command("MGET", flags, reply).is_err());
+    assert!(modules.command("INFO", flags, reply).is_err());
+    assert!(modules.command("bad name", flags, reply).is_err());
+    assert!(modules.command("", flags, reply).is_err());
+    modules.command("mine", flags, reply).unwrap();
+    assert!(modules.command("MINE", flags, reply).is_err());
+
+    // a failed init takes back what it registered
+    assert!(unsafe { modules.init(failing_init, &[]) }.is_err());
+    assert!(!format!("{modules:?}").contains("fail.cmd"));
+    unsafe { modules.init(refused_init, &[]) }.unwrap();
+    assert!(!format!("{modules:?}").contains("bad.flags"));
+
+    assert!(modules
+        .load(Path::new("/nonexistent/module.so"), &[])
+        .is_err());
+    // only the first modules installed count
+    assert!(!module::install(modules));
+    let mut kv = KvStore::new();
+    let reply = process_command(&mut kv, make_cmd(&["mine"]));
+    assert_eq!(reply, ResponseValue::Error("invalid command".into()));
+}
+
+#[test]
+fn test_module_value_in_keyspace() {
+    let mut kv = KvStore::new();
+    let key = Bytes::from("c");
+    kv.insert(
+        key.clone(),
+        RedisValue::Module(ModuleValue::new(Counter(5))),
+    );
+    let before = kv.memory_usage(&key).unwrap();
+    let old = kv.update(&key, |value| match value {
+        RedisValue::Module(value) => value.downcast_mut::<Counter>().unwrap().0,
+        _ => unreachable!(),
+    });
+    assert_eq!(old, Some(5));
+    assert_eq!(kv.memory_usage(&key), Some(before));
+    assert_eq!(kv.update(&Bytes::from("missing"), |_| ()), None);
+
+    let value = ModuleValue::new(Counter(1));
+    assert_ne!(value, value.clone());
+
+    // replacing a value
+    kv.insert(key.clone(), RedisValue::String(Bytes::from("s").into()));
+    assert!(matches!(kv.get(&key), Some(RedisValue::String(_))));
+}
@@ -1,7 +1,7 @@
 use bytes::BytesMut;
 use rustis::{
     message::ResponseValue,
-    parser::{parse, BufParseError},
+    parser::{parse, parse_request, BufParseError, MAX_NESTING_DEPTH},
 };
 
 // Helper to reduce boilerplate
@@ -218,3 +218,322 @@ fn test_array_incomplete() {
     assert!(matches!(result, Err(BufParseError::Incomplete)));
     // Or UnexpectedEOF, depending on where your loop hits the end
 }
+
+// =========================================================================
+// 6. RESP3 BIG NUMBER ( ( ), VERBATIM STRING (=), ATTRIBUTE (|)
+// =========================================================================
+
+#[test]
+fn test_big_number_happy_path() {
+    let input = b"(3492890328409238509324850943850943825024385\r\n";
+    let result = parse_buffer(input).unwrap();
+
+    match result {
+        ResponseValue::BigNumber(n) => {
+            assert_eq!(n, "3492890328409238509324850943850943825024385")
+        }
+        _ => panic!("Expected BigNumber"),
+    }
+}
+
+#[test]
+fn test_big_number_rejects_non_digits() {
+    let input = b"(12a4\r\n";
+    let result = parse_buffer(input);
+
+    assert!(matches!(result, Err(BufParseError::UnexpectedByte { .. })));
+}
+
+#[test]
+fn test_verbatim_string_happy_path() {
+    let input = b"=15\r\ntxt:Some string\r\n";
+    let result = parse_buffer(input).unwrap();
+
+    match result {
+        ResponseValue::VerbatimString { format, data } => {
+            assert_eq!(&format, b"txt");
+            assert_eq!(data, "Some string");
+        }
+        _ => panic!("Expected VerbatimString"),
+    }
+}
+
+#[test]
+fn test_verbatim_string_incomplete() {
+    let input = b"=15\r\ntxt:Some";
+    let result = parse_buffer(input);
+
+    assert!(matches!(result, Err(BufParseError::Incomplete)));
+}
+
+#[test]
+fn test_attribute_wraps_following_value() {
+    let input = b"|1\r\n+key-popularity\r\n:1\r\n*1\r\n:2\r\n";
+    let result = parse_buffer(input).unwrap();
+
+    match result {
+        ResponseValue::Attribute { attrs, value } => {
+            assert_eq!(attrs.len(), 1);
+            assert_eq!(
+                attrs[0].0,
+                ResponseValue::SimpleString("key-popularity".into())
+            );
+            assert_eq!(attrs[0].1, ResponseValue::Integer(1));
+            assert_eq!(
+                *value,
+                ResponseValue::Array(Some(vec![ResponseValue::Integer(2)]))
+            );
+        }
+        _ => panic!("Expected Attribute"),
+    }
+}
+
+#[test]
+fn test_attribute_missing_value_is_incomplete() {
+    let input = b"|1\r\n+a\r\n:1\r\n";
+    let result = parse_buffer(input);
+
+    assert!(matches!(result, Err(BufParseError::Incomplete)));
+}
+
+#[test]
+fn test_attribute_pair_counts_are_bounded() {
+    // used to overflow `pairs * 2 + 1` and panic
+    let result = parse_buffer(b"|9223372036854775808\r\n+a\r\n");
+    assert!(matches!(result, Err(BufParseError::InvalidMultibulkLength)));
+    let result = parse_buffer(b"|4611686018427387904\r\n+a\r\n");
+    assert!(matches!(result, Err(BufParseError::InvalidMultibulkLength)));
+    let result = parse_buffer(b"|-1\r\n+a\r\n");
+    assert!(matches!(result, Err(BufParseError::InvalidMultibulkLength)));
+
+    let mut input = b"|".to_vec();
+    input.extend(std::iter::repeat_n(b'1', 70 * 1024));
+    assert!(matches!(
+        parse_buffer(&input),
+        Err(BufParseError::TooBigMbulkCount)
+    ));
+}
+
+#[test]
+fn test_requests_refuse_resp3_frames() {
+    for input in [
+        &b"|1\r\n+a\r\n:1\r\n+OK\r\n"[..],
+        b"(12345678901234567890\r\n",
+        b"=8\r\ntxt:some\r\n",
+        b"*2\r\n$3\r\nGET\r\n|0\r\n$1\r\nk\r\n",
+    ] {
+        let mut buf = BytesMut::from(input);
+        let err = parse_request(&mut buf).unwrap_err();
+        assert!(
+            matches!(
+                err,
+                BufParseError::InvalidFirstByte(Some(b'|' | b'(' | b'='))
+            ),
+            "{err:?}"
+        );
+        assert!(parse_buffer(input).is_ok());
+    }
+
+    let mut buf = BytesMut::from(&b"|9223372036854775808\r\n+a\r\n"[..]);
+    let err = parse_request(&mut buf).unwrap_err();
+    assert_eq!(
+        err.reply_message(),
+        "ERR Protocol error: expected '$', got '|'"
+    );
+
+    let mut buf = BytesMut::from(&b"*1\r\n$4\r\nPING\r\n"[..]);
+    assert!(parse_request(&mut buf).is_ok());
+}
+
+#[test]
+fn test_requests_only_take_bulk_strings() {
+    for (input, message) in [
+        (
+            &b"*1\r\n:1\r\n"[..],
+            "ERR Protocol error: expected '$', got ':'",
+        ),
+        (
+            b"*1\r\n*1\r\n$1\r\na\r\n",
+            "ERR Protocol error: expected '$', got '*'",
+        ),
+        (
+            b"*2\r\n$3\r\nGET\r\n+k\r\n",
+            "ERR Protocol error: expected '$', got '+'",
+        ),
+    ] {
+        let mut buf = BytesMut::from(input);
+        assert_eq!(
+            parse_request(&mut buf).unwrap_err().reply_message(),
+            message
+        );
+        assert!(parse_buffer(input).is_ok());
+    }
+
+    // nested headers are refused at the first, however deep they go
+    let nested = b"*1\r\n".repeat(20_000);
+    let mut buf = BytesMut::from(&nested[..]);
+    assert_eq!(
+        parse_request(&mut buf),
+        Err(BufParseError::InvalidFirstByte(Some(b'*')))
+    );
+}
+
+#[test]
+fn test_resp3_round_trip() {
+    let value = ResponseValue::Attribute {
+        attrs: vec![(
+            ResponseValue::SimpleString("ttl".into()),
+            ResponseValue::Integer(3600),
+        )],
+        value: Box::new(ResponseValue::Array(Some(vec![
+            ResponseValue::BigNumber("-12345678901234567890".into()),
+            ResponseValue::VerbatimString {
+                format: *b"mkd",
+                data: "# title".into(),
+            },
+        ]))),
+    };
+
+    let mut buf = BytesMut::new();
+    value.serialize(&mut buf);
+    assert_eq!(parse(&mut buf).unwrap(), value);
+    assert!(buf.is_empty());
+}
+
+// =========================================================================
+// 7. PROTOCOL ERRORS
+// =========================================================================
+
+#[test]
+fn test_invalid_multibulk_length() {
+    let result = parse_buffer(b"*abc\r\n");
+    assert!(matches!(result, Err(BufParseError::InvalidMultibulkLength)));
+}
+
+#[test]
+fn test_invalid_bulk_length() {
+    let result = parse_buffer(b"*1\r\n$x\r\n");
+    assert!(matches!(result, Err(BufParseError::InvalidBulkLength)));
+
+    let result = parse_buffer(b"$-5\r\n");
+    assert!(matches!(result, Err(BufParseError::InvalidBulkLength)));
+
+    let result = parse_buffer(b"$536870913\r\n");
+    assert!(matches!(result, Err(BufParseError::InvalidBulkLength)));
+}
+
+#[test]
+fn test_too_big_count_strings() {
+    let mut input = b"*".to_vec();
+    input.extend(std::iter::repeat_n(b'1', 70 * 1024));
+    assert!(matches!(
+        parse_buffer(&input),
+        Err(BufParseError::TooBigMbulkCount)
+    ));
+
+    input[0] = b'$';
+    assert!(matches!(
+        parse_buffer(&input),
+        Err(BufParseError::TooBigBulkCount)
+    ));
+
+    let inline = vec![b'a'; 70 * 1024];
+    assert!(matches!(
+        parse_buffer(&inline),
+        Err(BufParseError::TooBigInlineRequest)
+    ));
+}
+
+#[test]
+fn test_inline_quoted_arguments() {
+    let result = parse_buffer(b"SET \"hello world\" 'it''s' \"\\x41\\n\"\r\n").unwrap_err();
+    // a closing quote must be followed by whitespace
+    assert_eq!(result, BufParseError::UnbalancedQuotes);
+
+    let result = parse_buffer(b"SET \"hello world\" 'it\\'s' \"\\x41\\n\"\r\n").unwrap();
+    let expected: Vec<&[u8]> = vec![b"SET", b"hello world", b"it's", b"A\n"];
+    match result {
+        ResponseValue::Array(Some(items)) => {
+            let words: Vec<_> = items
+                .iter()
+                .map(|item| match item {
+                    ResponseValue::BulkString(Some(b)) => b.to_vec(),
+                    _ => panic!("Expected BulkString"),
+                })
+                .collect();
+            assert_eq!(words, expected);
+        }
+        _ => panic!("Expected Array"),
+    }
+}
+
+#[test]
+fn test_inline_unbalanced_quotes() {
+    let result = parse_buffer(b"SET \"key value\r\n");
+    assert!(matches!(result, Err(BufParseError::UnbalancedQuotes)));
+}
+
+#[test]
+fn test_protocol_error_reply_messages() {
+    let cases = [
+        (
+            BufParseError::InvalidMultibulkLength,
+            "ERR Protocol error: invalid multibulk length",
+        ),
+        (
+            BufParseError::InvalidBulkLength,
+            "ERR Protocol error: invalid bulk length",
+        ),
+        (
+            BufParseError::UnbalancedQuotes,
+            "ERR Protocol error: unbalanced quotes in request",
+        ),
+        (
+            BufParseError::TooBigMbulkCount,
+            "ERR Protocol error: too big mbulk count string",
+        ),
+        (
+            BufParseError::TooBigBulkCount,
+            "ERR Protocol error: too big bulk count string",
+        ),
+        (
+            BufParseError::TooBigInlineRequest,
+            "ERR Protocol error: too big inline request",
+        ),
+        (
+            BufParseError::InvalidFirstByte(Some(b'!')),
+            "ERR Protocol error: expected '$', got '!'",
+        ),
+    ];
+
+    for (err, message) in cases {
+        assert_eq!(err.reply_message(), message);
+    }
+}
+
+#[test]
+fn test_reply_nesting_is_bounded() {
+    // used to recurse once per header and overflow the stack
+    let mut input = b"*1\r\n".repeat(20_000);
+    input.extend_from_slice(b":1\r\n");
+    assert!(matches!(
+        parse_buffer(&input),
+        Err(BufParseError::TooDeeplyNested)
+    ));
+    let mut input = b"|1\r\n+a\r\n".repeat(20_000);
+    input.extend_from_slice(b":1\r\n");
+    assert!(matches!(
+        parse_buffer(&input),
+        Err(BufParseError::TooDeeplyNested)
+    ));
+
+    let mut input = b"*1\r\n".repeat(MAX_NESTING_DEPTH);
+    input.extend_from_slice(b":1\r\n");
+    assert!(parse_buffer(&input).is_ok());
+    let mut input = b"*1\r\n".repeat(MAX_NESTING_DEPTH + 1);
+    input.extend_from_slice(b":1\r\n");
+    assert!(matches!(
+        parse_buffer(&input),
+        Err(BufParseError::TooDeeplyNested)
+    ));
+}
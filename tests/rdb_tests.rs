@@ -0,0 +1,291 @@
+#![cfg(feature = "server")]
+
+use rustis::rdb::{crc64, lzf_decompress, read, Checksum, KeyInfo};
+
+/// A snapshot being written by hand, the way Redis would.
+struct Rdb(Vec<u8>);
+
+impl Rdb {
+    fn new(version: u32) -> Self {
+        Self(format!("REDIS{version:04}").into_bytes())
+    }
+
+    fn len(&mut self, len: usize) -> &mut Self {
+        match len {
+            0..64 => self.0.push(len as u8),
+            64..16384 => self.0.extend([0x40 | (len >> 8) as u8, len as u8]),
+            _ => {
+                self.0.push(0x80);
+                self.0.extend((len as u32).to_be_bytes());
+            }
+        }
+        self
+    }
+
+    fn string(&mut self, s: &[u8]) -> &mut Self {
+        self.len(s.len());
+        self.0.extend_from_slice(s);
+        self
+    }
+
+    fn byte(&mut self, byte: u8) -> &mut Self {
+        self.0.push(byte);
+        self
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) -> &mut Self {
+        self.0.extend_from_slice(bytes);
+        self
+    }
+
+    fn finish(&mut self) -> Vec<u8> {
+        self.0.push(0xFF);
+        let crc = crc64(0, &self.0);
+        self.0.extend(crc.to_le_bytes());
+        self.0.clone()
+    }
+}
+
+/// A listpack of short strings, with its element count in the header or
+/// not.
+fn listpack(items: &[&str], counted: bool) -> Vec<u8> {
+    let mut entries = Vec::new();
+    for item in items {
+        entries.push(0x80 | item.len() as u8);
+        entries.extend_from_slice(item.as_bytes());
+        entries.push(1 + item.len() as u8);
+    }
+    let mut lp = ((6 + entries.len() + 1) as u32).to_le_bytes().to_vec();
+    let count = if counted {
+        items.len() as u16
+    } else {
+        u16::MAX
+    };
+    lp.extend(count.to_le_bytes());
+    lp.extend(entries);
+    lp.push(0xFF);
+    lp
+}
+
+/// A ziplist of short strings whose header doesn't count them.
+fn ziplist(items: &[&str]) -> Vec<u8> {
+    let mut entries = Vec::new();
+    let mut prev = 0;
+    for item in items {
+        entries.push(prev as u8);
+        entries.push(item.len() as u8);
+        entries.extend_from_slice(item.as_bytes());
+        prev = 2 + item.len();
+    }
+    let mut zl = ((10 + entries.len() + 1) as u32).to_le_bytes().to_vec();
+    zl.extend(0u32.to_le_bytes());
+    zl.extend(u16::MAX.to_le_bytes());
+    zl.extend(entries);
+    zl.push(0xFF);
+    zl
+}
+
+fn key<'a>(keys: &'a [KeyInfo], name: &str) -> &'a KeyInfo {
+    keys.iter().find(|key| key.key == name.as_bytes()).unwrap()
+}
+
+fn sample() -> Vec<u8> {
+    let mut rdb = Rdb::new(11);
+    rdb.byte(0xFA).string(b"redis-ver").string(b"7.2.4");
+    // an integer encoded as a string
+    rdb.byte(0xFA).string(b"redis-bits").bytes(&[0xC0, 64]);
+    rdb.byte(0xFE).len(0).byte(0xFB).len(6).len(1);
+
+    rdb.byte(0).string(b"greeting").string(b"hello");
+    rdb.byte(0xFC).bytes(&1_900_000_000_000i64.to_le_bytes());
+    rdb.byte(0).string(b"session").bytes(&[0xC1, 0x39, 0x30]);
+    // a quicklist of one listpack node and one plain node
+    let lp = listpack(&["a", "b", "c"], true);
+    rdb.byte(18).string(b"list").len(2).len(2).string(&lp);
+    rdb.len(1).string(b"a big element");
+    let mut intset = 2u32.to_le_bytes().to_vec();
+    intset.extend(2u32.to_le_bytes());
+    intset.extend([1, 0, 2, 0]);
+    rdb.byte(11).string(b"ids").string(&intset);
+    let lp = listpack(&["f1", "v1", "f2", "v2"], false);
+    rdb.byte(16).string(b"user").string(&lp);
+    rdb.byte(5)
+        .string(b"scores")
+        .len(1)
+        .string(b"alice")
+        .bytes(&1.5f64.to_le_bytes());
+    // "abcabcabc", compressed
+    rdb.byte(0)
+        .string(b"lzf")
+        .byte(0xC3)
+        .len(6)
+        .len(9)
+        .bytes(&[2, b'a', b'b', b'c', 0x80, 2]);
+    rdb.byte(13)
+        .string(b"old-hash")
+        .string(&ziplist(&["k", "v", "k2", "v2"]));
+
+    rdb.byte(0xFE).len(1);
+    rdb.byte(0xFD).bytes(&1_900_000_000i32.to_le_bytes());
+    rdb.byte(2).string(b"tags").len(2).string(b"x").string(b"y");
+    rdb.finish()
+}
+
+#[test]
+fn test_crc64() {
+    assert_eq!(crc64(0, b"123456789"), 0xe9c6d914c4b8d9ca);
+    assert_eq!(crc64(crc64(0, b"1234"), b"56789"), 0xe9c6d914c4b8d9ca);
+}
+
+#[test]
+fn test_lzf_decompress() {
+    assert_eq!(
+        lzf_decompress(&[2, b'a', b'b', b'c', 0x80, 2], 9).unwrap(),
+        b"abcabcabc"
+    );
+    // reaching back before the start
+    assert!(lzf_decompress(&[0, b'a', 0x20, 5], 3).is_err());
+    assert!(lzf_decompress(&[2, b'a', b'b'], 3).is_err());
+    assert!(lzf_decompress(&[0, b'a'], usize::MAX).is_err());
+}
+
+#[test]
+fn test_read_snapshot() {
+    let snapshot = read(&sample());
+    assert_eq!(snapshot.error, None);
+    assert!(snapshot.is_valid());
+    assert!(matches!(snapshot.checksum, Some(Checksum::Valid(_))));
+    assert_eq!(snapshot.version, 11);
+    assert_eq!(
+        snapshot.aux,
+        vec![
+            ("redis-ver".to_string(), "7.2.4".to_string()),
+            ("redis-bits".to_string(), "64".to_string()),
+        ]
+    );
+
+    let keys = &snapshot.keys;
+    assert_eq!(keys.len(), 9);
+    let greeting = key(keys, "greeting");
+    assert_eq!(
+        (greeting.kind.as_str(), greeting.len, greeting.bytes),
+        ("string", 5, 6)
+    );
+    assert_eq!(greeting.expires_at_ms, None);
+    let session = key(keys, "session");
+    assert_eq!(session.len, 5, "12345, stored as an integer");
+    assert_eq!(session.expires_at_ms, Some(1_900_000_000_000));
+
+    let summary = |name| {
+        let key = key(keys, name);
+        (key.kind.as_str(), key.encoding, key.len)
+    };
+    assert_eq!(summary("list"), ("list", "quicklist", 4));
+    assert_eq!(summary("ids"), ("set", "intset", 2));
+    assert_eq!(summary("user"), ("hash", "listpack", 2));
+    assert_eq!(summary("scores"), ("zset", "skiplist", 1));
+    assert_eq!(summary("lzf"), ("string", "string", 9));
+    assert_eq!(summary("old-hash"), ("hash", "ziplist", 2));
+
+    let tags = key(keys, "tags");
+    assert_eq!((tags.db, tags.len), (1, 2));
+    assert_eq!(tags.expires_at_ms, Some(1_900_000_000_000));
+    assert!(keys
+        .iter()
+        .filter(|key| key.key != b"tags")
+        .all(|key| key.db == 0));
+}
+
+#[test]
+fn test_broken_snapshots() {
+    let good = sample();
+
+    // a flipped bit in a value only shows in the checksum
+    let mut flipped = good.clone();
+    let at = flipped.windows(5).position(|w| w == b"hello").unwrap();
+    flipped[at] ^= 1;
+    let snapshot = read(&flipped);
+    assert_eq!(snapshot.error, None);
+    assert!(matches!(snapshot.checksum, Some(Checksum::Mismatch { .. })));
+    assert!(!snapshot.is_valid());
+
+    // cut short: what came before is kept
+    let truncated = &good[..good.len() / 2];
+    let snapshot = read(truncated);
+    let error = snapshot.error.clone().unwrap();
+    assert_eq!(error.message, "unexpected end of file");
+    assert!(error.offset <= truncated.len());
+    assert_eq!(snapshot.checksum, None);
+    assert!(!snapshot.keys.is_empty());
+    assert!(!snapshot.is_valid());
+
+    let mut unknown = Rdb::new(11);
+    unknown.byte(0).string(b"k").string(b"v");
+    unknown.byte(42).string(b"weird");
+    let snapshot = read(&unknown.finish());
+    assert_eq!(snapshot.keys.len(), 1);
+    assert_eq!(snapshot.error.unwrap().message, "unknown value type 42");
+
+    // a hash claiming u64::MAX fields can't overflow the field count
+    let mut huge = Rdb::new(11);
+    huge.byte(4)
+        .string(b"h")
+        .byte(0x81)
+        .bytes(&u64::MAX.to_be_bytes());
+    let snapshot = read(&huge.finish());
+    assert!(snapshot.keys.is_empty());
+    assert_eq!(snapshot.error.unwrap().message, "length overflow");
+
+    // an LZF string claiming 2^62 bytes can't make us allocate them
+    let mut lzf = Rdb::new(11);
+    lzf.byte(0)
+        .string(b"k")
+        .byte(0xC3)
+        .len(2)
+        .byte(0x81)
+        .bytes(&(1u64 << 62).to_be_bytes())
+        .bytes(&[0, b'v']);
+    let snapshot = read(&lzf.finish());
+    assert!(snapshot.keys.is_empty());
+    assert_eq!(snapshot.error.unwrap().message, "corrupt LZF string");
+
+    assert_eq!(
+        read(b"NOTRDB0011").error.unwrap().message,
+        "not an RDB file"
+    );
+    assert!(read(b"REDIS0099").error.is_some());
+
+    // version 4 files have no checksum
+    let mut old = Rdb::new(4);
+    old.byte(0).string(b"k").string(b"v").byte(0xFF);
+    let snapshot = read(&old.0);
+    assert_eq!(snapshot.checksum, Some(Checksum::Disabled));
+    assert!(snapshot.is_valid());
+}
+
+#[test]
+fn test_snapshot_output() {
+    let snapshot = read(&sample());
+    let text = snapshot.to_text();
+    assert!(text.starts_with("RDB version 11\naux redis-ver=7.2.4\n"));
+    assert!(text.contains(
+        "db=0 key=\"session\" type=string encoding=string len=5 bytes=3 expires_at_ms=1900000000000\n"
+    ));
+    assert!(text.ends_with(&format!(
+        "9 keys, checksum OK ({:016x})",
+        match snapshot.checksum {
+            Some(Checksum::Valid(crc)) => crc,
+            _ => unreachable!(),
+        }
+    )));
+
+    let json = snapshot.to_json();
+    assert!(json.starts_with(r#"{"version":11,"aux":{"redis-ver":"7.2.4","redis-bits":"64"},"keys":[{"db":0,"key":"greeting","type":"string","encoding":"string","len":5,"bytes":6,"expires_at_ms":null},"#));
+    assert!(json.contains(r#""checksum":{"status":"valid","#));
+    assert!(json.ends_with(r#","error":null}"#));
+
+    let mut odd = Rdb::new(11);
+    odd.byte(0).string(b"quo\"te\n\xff").string(b"v");
+    let json = read(&odd.finish()).to_json();
+    assert!(json.contains("\"key\":\"quo\\\"te\\n\u{fffd}\""));
+}
@@ -0,0 +1,243 @@
+#![cfg(feature = "server")]
+
+//! The reply pipeline under load: a connection's commands are numbered from
+//! 1 by the reader, answered by whichever workers own their keys, or by the
+//! router itself, in any order, and put back in order by the writer. Every
+//! reply must come back, in order, exactly once.
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    io::{Read, Write},
+    net::{Shutdown, TcpStream},
+    thread,
+};
+
+use bytes::{Bytes, BytesMut};
+use proptest::{collection::vec, prelude::*};
+use rustis::{
+    connection::{ReplyQueue, MAX_IN_FLIGHT},
+    message::{ResponseMessage, ResponseValue, VECTORED_WRITE_THRESHOLD},
+    telemetry::CommandTrace,
+    Server,
+};
+
+/// A reply telling which command it answers; some big enough to be written
+/// from their own buffer rather than copied.
+fn reply(seq: u64) -> ResponseValue {
+    let mut value = seq.to_string().into_bytes();
+    if seq.is_multiple_of(7) {
+        value.resize(VECTORED_WRITE_THRESHOLD + seq as usize, b'x');
+    }
+    ResponseValue::BulkString(Some(value.into()))
+}
+
+fn encoded(values: impl IntoIterator<Item = ResponseValue>) -> Vec<u8> {
+    let mut out = BytesMut::new();
+    for value in values {
+        value.serialize(&mut out);
+    }
+    out.to_vec()
+}
+
+/// Replies numbered 1 to some n, in any order.
+fn arrivals() -> impl Strategy<Value = Vec<u64>> {
+    (1u64..200).prop_flat_map(|n| Just((1..=n).collect::<Vec<_>>()).prop_shuffle())
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(256))]
+
+    /// Replies arriving in any order, with the writer flushing in between
+    /// any of them, are each written once, in order, as soon as every
+    /// earlier one has been.
+    #[test]
+    fn test_queue_writes_replies_in_order(
+        arrivals in arrivals(),
+        flushes in vec(any::<bool>(), 200),
+    ) {
+        let mut queue = ReplyQueue::new();
+        let mut buffer = BytesMut::new();
+        let mut chunks: Vec<Bytes> = Vec::new();
+        let mut arrived = BTreeSet::new();
+        let mut count = 0;
+
+        for (i, seq) in arrivals.iter().enumerate() {
+            queue.insert(ResponseMessage {
+                seq: *seq,
+                response_value: reply(*seq),
+                trace: CommandTrace::default(),
+            });
+            arrived.insert(*seq);
+            // always after the last
+            if !flushes[i] && i + 1 < arrivals.len() {
+                continue;
+            }
+            let serialized = queue.serialize_ready(&mut buffer, &mut chunks);
+            // as the writer does, chunks first, then what's left in the buffer
+            chunks.push(buffer.split().freeze());
+            let written: Vec<u8> = chunks.drain(..).flat_map(|chunk| chunk.to_vec()).collect();
+
+            let before = count;
+            count = (1..).take_while(|seq| arrived.contains(seq)).count();
+            prop_assert_eq!(serialized, count - before);
+            let expected = encoded((before as u64 + 1..=count as u64).map(reply));
+            prop_assert_eq!(written, expected);
+            // the running total covers exactly the replies still held back
+            let held: usize = arrived
+                .range(count as u64 + 1..)
+                .map(|seq| reply(*seq).encoded_len())
+                .sum();
+            prop_assert_eq!(queue.pending_bytes(), held);
+        }
+        prop_assert_eq!(count, arrivals.len());
+        prop_assert_eq!(queue.pending_bytes(), 0);
+    }
+}
+
+/// xorshift, seeded per client so each pipelines something different.
+struct Rng(u64);
+
+impl Rng {
+    fn below(&mut self, n: usize) -> usize {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 % n as u64) as usize
+    }
+}
+
+const KEYS: usize = 12;
+
+/// `count` random commands on the client's own keys, spread over every
+/// shard, as RESP, with the replies they must get, worked out from a model
+/// of those keys. Single-key commands go to one worker, multi-key ones to
+/// several and ECHO to none, so replies are ready in any order.
+fn pipeline(client: usize, count: usize) -> (Vec<u8>, Vec<u8>) {
+    let mut rng = Rng(0x9E37_79B9_7F4A_7C15 ^ (client as u64 + 1));
+    let key = |j: usize| Bytes::from(format!("{client}:key:{j}"));
+    let mut model: HashMap<usize, Bytes> = HashMap::new();
+    let mut commands = Vec::with_capacity(count);
+    let mut replies = Vec::with_capacity(count);
+
+    for i in 0..count {
+        let (a, b, c) = (rng.below(KEYS), rng.below(KEYS), rng.below(KEYS));
+        let value = match rng.below(100) {
+            0 => Bytes::from(format!("{i}:").repeat(VECTORED_WRITE_THRESHOLD / 4)),
+            _ => Bytes::from(i.to_string()),
+        };
+        let get =
+            |model: &HashMap<usize, Bytes>, j| ResponseValue::BulkString(model.get(&j).cloned());
+        let ok = ResponseValue::SimpleString("OK".into());
+        let (args, reply) = match rng.below(6) {
+            0 => {
+                model.insert(a, value.clone());
+                (vec!["SET".into(), key(a), value], ok)
+            }
+            1 => (vec!["GET".into(), key(a)], get(&model, a)),
+            2 => {
+                model.insert(a, value.clone());
+                model.insert(b, value.clone());
+                (
+                    vec!["MSET".into(), key(a), value.clone(), key(b), value],
+                    ok,
+                )
+            }
+            3 => {
+                let values = vec![get(&model, a), get(&model, b), get(&model, c)];
+                (
+                    vec!["MGET".into(), key(a), key(b), key(c)],
+                    ResponseValue::Array(Some(values)),
+                )
+            }
+            4 => {
+                let deleted = BTreeSet::from([a, b])
+                    .into_iter()
+                    .filter(|j| model.remove(j).is_some())
+                    .count();
+                (
+                    vec!["DEL".into(), key(a), key(b)],
+                    ResponseValue::Integer(deleted as i64),
+                )
+            }
+            _ => (
+                vec!["ECHO".into(), value.clone()],
+                ResponseValue::BulkString(Some(value)),
+            ),
+        };
+        let args = args
+            .into_iter()
+            .map(|arg| ResponseValue::BulkString(Some(arg)))
+            .collect();
+        commands.push(ResponseValue::Array(Some(args)));
+        replies.push(reply);
+    }
+    (encoded(commands), encoded(replies))
+}
+
+/// Pipelines `commands` over a connection to `server`, written in random
+/// pieces while the replies are read, and returns every byte of them.
+fn run_client(server: &Server, client: usize, commands: Vec<u8>) -> Vec<u8> {
+    let mut stream = TcpStream::connect(server.addr()).unwrap();
+    let mut writer = stream.try_clone().unwrap();
+    let sending = thread::spawn(move || {
+        let mut rng = Rng(client as u64 + 7);
+        let mut rest = &commands[..];
+        while !rest.is_empty() {
+            let (piece, after) = rest.split_at((1 + rng.below(8192)).min(rest.len()));
+            writer.write_all(piece).unwrap();
+            rest = after;
+        }
+        writer.shutdown(Shutdown::Write).unwrap();
+    });
+    let mut replies = Vec::new();
+    stream.read_to_end(&mut replies).unwrap();
+    sending.join().unwrap();
+    replies
+}
+
+#[test]
+fn test_pipelined_replies_complete_and_in_order() {
+    // many times what a connection may have in flight
+    let count = 5 * MAX_IN_FLIGHT;
+    for workers in [1, 3, 8] {
+        let mut server = Server::builder().port(0).workers(workers).build().unwrap();
+        server.start().unwrap();
+
+        thread::scope(|scope| {
+            let clients: Vec<_> = (0..8)
+                .map(|client| {
+                    let server = &server;
+                    scope.spawn(move || {
+                        let (commands, expected) = pipeline(client, count);
+                        let replies = run_client(server, client, commands);
+                        (client, replies, expected)
+                    })
+                })
+                .collect();
+            for client in clients {
+                let (client, replies, expected) = client.join().unwrap();
+                if replies != expected {
+                    let at = replies
+                        .iter()
+                        .zip(&expected)
+                        .position(|(got, want)| got != want)
+                        .unwrap_or(replies.len().min(expected.len()));
+                    let context = |bytes: &[u8]| {
+                        bytes[at.saturating_sub(40)..(at + 40).min(bytes.len())]
+                            .escape_ascii()
+                            .to_string()
+                    };
+                    panic!(
+                        "client {client} with {workers} workers: replies differ at byte {at} \
+                         of {} (expected {})\n    got:      {}\n    expected: {}",
+                        replies.len(),
+                        expected.len(),
+                        context(&replies),
+                        context(&expected),
+                    );
+                }
+            }
+        });
+        server.shutdown();
+    }
+}
@@ -1,27 +1,34 @@
+#![cfg(feature = "server")]
+
+use std::sync::Arc;
+
 use bytes::Bytes;
 use rustis::message::{ResponseMessage, ResponseValue, WorkerMessage};
-use rustis::router::route_message;
+use rustis::router::{command_keys, hash_tag, key_count, route_message, shard_for};
+use rustis::telemetry::CommandTrace;
+use rustis::worker::worker_main;
 use tokio::sync::mpsc;
 
+type MockEnv = (
+    Vec<mpsc::Sender<WorkerMessage>>,
+    Vec<mpsc::Receiver<WorkerMessage>>,
+    mpsc::Sender<ResponseMessage>,
+    mpsc::Receiver<ResponseMessage>,
+);
+
 /// Helper to setup a mock environment
-fn setup(
-    worker_count: usize,
-) -> (
-    Vec<mpsc::UnboundedSender<WorkerMessage>>,
-    Vec<mpsc::UnboundedReceiver<WorkerMessage>>,
-    mpsc::UnboundedSender<ResponseMessage>,
-    mpsc::UnboundedReceiver<ResponseMessage>,
-) {
+fn setup(worker_count: usize) -> MockEnv {
     let mut worker_txs = Vec::new();
     let mut worker_rxs = Vec::new();
 
     for _ in 0..worker_count {
-        let (tx, rx) = mpsc::unbounded_channel();
+        let (tx, rx) = mpsc::channel(16);
         worker_txs.push(tx);
         worker_rxs.push(rx);
     }
 
-    let (writer_tx, writer_rx) = mpsc::unbounded_channel();
+    // roomier than the mailboxes so tests can fill a mailbox with permits to spare
+    let (writer_tx, writer_rx) = mpsc::channel(64);
 
     (worker_txs, worker_rxs, writer_tx, writer_rx)
 }
@@ -37,7 +44,14 @@ async fn test_happy_path_routing() {
     ]));
 
     // Execute
-    route_message(&worker_txs, frame.clone(), 42, writer_tx);
+    route_message(
+        &worker_txs,
+        frame.clone(),
+        42,
+        writer_tx.reserve_owned().await.unwrap(),
+        CommandTrace::default(),
+    )
+    .await;
 
     // 1. Ensure NO error was sent to the writer
     assert!(writer_rx.try_recv().is_err());
@@ -64,15 +78,22 @@ async fn test_ping_pong_intercept() {
         "PING",
     )))]));
 
-    route_message(&worker_txs, frame, 1, writer_tx);
+    route_message(
+        &worker_txs,
+        frame,
+        1,
+        writer_tx.reserve_owned().await.unwrap(),
+        CommandTrace::default(),
+    )
+    .await;
 
     let response = writer_rx.try_recv().expect("Should receive PONG response");
     // Check the ResponseMessage structure
     match response.response_value {
-        ResponseValue::Error(msg) => {
+        ResponseValue::SimpleString(msg) => {
             assert_eq!(msg, "PONG");
         }
-        _ => panic!("Expected Error variant with PONG"),
+        _ => panic!("Expected SimpleString variant with PONG"),
     }
 }
 
@@ -84,7 +105,14 @@ async fn test_invalid_frame_type() {
     // Sending a SimpleString where an Array is expected
     let frame = ResponseValue::SimpleString("I am not an array".into());
 
-    route_message(&worker_txs, frame, 1, writer_tx);
+    route_message(
+        &worker_txs,
+        frame,
+        1,
+        writer_tx.reserve_owned().await.unwrap(),
+        CommandTrace::default(),
+    )
+    .await;
 
     let response = writer_rx.try_recv().expect("Should receive error response");
     match response.response_value {
@@ -103,7 +131,14 @@ async fn test_missing_key_error() {
         "GET",
     )))]));
 
-    route_message(&worker_txs, frame, 1, writer_tx);
+    route_message(
+        &worker_txs,
+        frame,
+        1,
+        writer_tx.reserve_owned().await.unwrap(),
+        CommandTrace::default(),
+    )
+    .await;
 
     let response = writer_rx.try_recv().expect("Should receive parsing error");
     match response.response_value {
@@ -111,3 +146,277 @@ async fn test_missing_key_error() {
         _ => panic!("Expected Error variant"),
     }
 }
+
+#[tokio::test]
+async fn test_full_mailbox_waits_for_worker() {
+    let (worker_txs, mut worker_rxs, writer_tx, _writer_rx) = setup(1);
+
+    let frame = ResponseValue::Array(Some(vec![
+        ResponseValue::BulkString(Some(Bytes::from("GET"))),
+        ResponseValue::BulkString(Some(Bytes::from("key"))),
+    ]));
+
+    // fill the mailbox to capacity
+    for seq in 0..16 {
+        let permit = writer_tx.clone().reserve_owned().await.unwrap();
+        route_message(
+            &worker_txs,
+            frame.clone(),
+            seq,
+            permit,
+            CommandTrace::default(),
+        )
+        .await;
+    }
+
+    let permit = writer_tx.clone().reserve_owned().await.unwrap();
+    let blocked = route_message(
+        &worker_txs,
+        frame.clone(),
+        16,
+        permit,
+        CommandTrace::default(),
+    );
+    tokio::pin!(blocked);
+
+    // the next command can't be delivered until the worker makes room
+    assert!(
+        tokio::time::timeout(std::time::Duration::from_millis(50), &mut blocked)
+            .await
+            .is_err()
+    );
+
+    let first = worker_rxs[0].recv().await.unwrap();
+    assert_eq!(first.seq, 0);
+    blocked.await;
+}
+
+fn bulk(s: &str) -> ResponseValue {
+    ResponseValue::BulkString(Some(Bytes::copy_from_slice(s.as_bytes())))
+}
+
+fn command(args: &[&str]) -> ResponseValue {
+    ResponseValue::Array(Some(args.iter().map(|arg| bulk(arg)).collect()))
+}
+
+/// The next `count` replies, put back in request order as the writer does:
+/// commands gathered from several shards may answer after later ones.
+async fn replies_in_order(
+    writer_rx: &mut mpsc::Receiver<ResponseMessage>,
+    count: usize,
+) -> Vec<ResponseValue> {
+    let mut replies = Vec::with_capacity(count);
+    for _ in 0..count {
+        replies.push(writer_rx.recv().await.unwrap());
+    }
+    replies.sort_by_key(|reply| reply.seq);
+    replies
+        .into_iter()
+        .map(|reply| reply.response_value)
+        .collect()
+}
+
+#[tokio::test]
+async fn test_gather_does_not_hold_up_routing() {
+    let (worker_txs, mut worker_rxs, writer_tx, mut writer_rx) = setup(2);
+    let keys = ["a", "b", "c", "d", "e", "f"];
+    let mut mget = vec!["MGET"];
+    mget.extend(keys);
+
+    // returns as soon as the parts are sent, with no shard answering yet
+    let permit = writer_tx.clone().reserve_owned().await.unwrap();
+    route_message(
+        &worker_txs,
+        command(&mget),
+        1,
+        permit,
+        CommandTrace::default(),
+    )
+    .await;
+    let permit = writer_tx.clone().reserve_owned().await.unwrap();
+    route_message(
+        &worker_txs,
+        command(&["PING"]),
+        2,
+        permit,
+        CommandTrace::default(),
+    )
+    .await;
+    let pong = writer_rx.recv().await.unwrap();
+    assert_eq!(pong.seq, 2);
+
+    // each shard answers its keys with their names
+    for rx in &mut worker_rxs {
+        if let Ok(msg) = rx.try_recv() {
+            let ResponseValue::Array(Some(items)) = msg.response_value else {
+                panic!("not a command");
+            };
+            let values = items[1..].to_vec();
+            msg.tx.send(ResponseMessage {
+                seq: msg.seq,
+                response_value: ResponseValue::Array(Some(values)),
+                trace: msg.trace,
+            });
+        }
+    }
+    let reply = writer_rx.recv().await.unwrap();
+    assert_eq!(reply.seq, 1);
+    assert_eq!(
+        reply.response_value,
+        ResponseValue::Array(Some(keys.iter().map(|key| bulk(key)).collect()))
+    );
+}
+
+#[tokio::test]
+async fn test_multi_key_commands_fan_out() {
+    // real workers, so keys spread over several shards
+    let mut worker_txs = Vec::new();
+    for id in 0..4 {
+        let (tx, rx) = mpsc::channel(16);
+        std::thread::spawn(move || worker_main(id, rx, Arc::default()));
+        worker_txs.push(tx);
+    }
+    let (writer_tx, mut writer_rx) = mpsc::channel(64);
+
+    let keys: Vec<String> = (0..16).map(|i| format!("key:{i}")).collect();
+    let mut mset = vec!["MSET"];
+    for key in &keys {
+        mset.push(key);
+        mset.push(key);
+    }
+
+    let mut mget = vec!["MGET"];
+    mget.extend(keys.iter().map(String::as_str));
+    mget.insert(3, "missing");
+
+    let mut del = vec!["DEL", "missing"];
+    del.extend(keys.iter().take(10).map(String::as_str));
+
+    let mut exists = vec!["EXISTS"];
+    exists.extend(keys.iter().map(String::as_str));
+
+    for (seq, args) in [mset, mget, del, exists].iter().enumerate() {
+        let permit = writer_tx.clone().reserve_owned().await.unwrap();
+        route_message(
+            &worker_txs,
+            command(args),
+            seq as u64,
+            permit,
+            CommandTrace::default(),
+        )
+        .await;
+    }
+
+    let mut expected: Vec<ResponseValue> = keys.iter().map(|key| bulk(key)).collect();
+    expected.insert(2, ResponseValue::BulkString(None));
+    assert_eq!(
+        replies_in_order(&mut writer_rx, 4).await,
+        vec![
+            ResponseValue::SimpleString("OK".into()),
+            ResponseValue::Array(Some(expected)),
+            ResponseValue::Integer(10),
+            ResponseValue::Integer(6),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_keyless_commands() {
+    let mut worker_txs = Vec::new();
+    for id in 0..3 {
+        let (tx, rx) = mpsc::channel(16);
+        std::thread::spawn(move || worker_main(id, rx, Arc::default()));
+        worker_txs.push(tx);
+    }
+    let (writer_tx, mut writer_rx) = mpsc::channel(64);
+
+    let requests: [&[&str]; 8] = [
+        &["MSET", "a", "1", "b", "2", "c", "3", "d", "4"],
+        &["DBSIZE"],
+        &["ECHO", "hello"],
+        &["CONFIG", "GET", "save"],
+        &["COMMAND", "DOCS"],
+        &["FLUSHALL"],
+        &["dbsize"],
+        &["PING", "there"],
+    ];
+    for (seq, args) in requests.iter().enumerate() {
+        let permit = writer_tx.clone().reserve_owned().await.unwrap();
+        route_message(
+            &worker_txs,
+            command(args),
+            seq as u64,
+            permit,
+            CommandTrace::default(),
+        )
+        .await;
+    }
+
+    assert_eq!(
+        replies_in_order(&mut writer_rx, requests.len()).await,
+        vec![
+            ResponseValue::SimpleString("OK".into()),
+            ResponseValue::Integer(4),
+            bulk("hello"),
+            ResponseValue::Array(Some(vec![])),
+            ResponseValue::Array(Some(vec![])),
+            ResponseValue::SimpleString("OK".into()),
+            ResponseValue::Integer(0),
+            bulk("there"),
+        ]
+    );
+}
+
+#[test]
+fn test_hash_tags() {
+    assert_eq!(hash_tag(b"{user:42}:profile"), b"user:42");
+    assert_eq!(hash_tag(b"profile:{user:42}"), b"user:42");
+    assert_eq!(hash_tag(b"foo{}{bar}"), b"foo{}{bar}");
+    assert_eq!(hash_tag(b"foo{{bar}}zap"), b"{bar");
+    assert_eq!(hash_tag(b"foo{bar}{zap}"), b"bar");
+    assert_eq!(hash_tag(b"{unclosed"), b"{unclosed");
+    assert_eq!(hash_tag(b"plain"), b"plain");
+
+    for shards in [2, 4, 16] {
+        assert_eq!(
+            shard_for(b"{user:42}:profile", shards),
+            shard_for(b"{user:42}:sessions", shards)
+        );
+    }
+}
+
+#[test]
+fn test_key_count() {
+    let items = |args: &[&str]| args.iter().map(|arg| bulk(arg)).collect::<Vec<_>>();
+    assert_eq!(key_count(&items(&["GET", "a"])), 1);
+    assert_eq!(key_count(&items(&["LPUSH", "list", "x", "y"])), 1);
+    assert_eq!(
+        key_count(&items(&["MSET", "a", "1", "b", "2", "c", "3"])),
+        3
+    );
+    assert_eq!(key_count(&items(&["DEL", "a", "b"])), 2);
+    assert_eq!(key_count(&items(&["PING"])), 0);
+    assert_eq!(key_count(&items(&["DBSIZE"])), 0);
+    assert_eq!(key_count(&items(&["MEMORY", "USAGE", "a"])), 1);
+    assert_eq!(key_count(&items(&["OBJECT", "HELP"])), 0);
+}
+
+#[test]
+fn test_command_keys() {
+    let items = |args: &[&str]| args.iter().map(|arg| bulk(arg)).collect::<Vec<_>>();
+    let keys = |args: &[&str]| {
+        command_keys(&items(args))
+            .map(|key| String::from_utf8_lossy(key).into_owned())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(keys(&["GET", "a"]), ["a"]);
+    assert_eq!(keys(&["LPUSH", "list", "x", "y"]), ["list"]);
+    assert_eq!(keys(&["MSET", "a", "1", "b", "2"]), ["a", "b"]);
+    assert_eq!(keys(&["EXISTS", "a", "b", "c"]), ["a", "b", "c"]);
+    assert_eq!(keys(&["OBJECT", "FREQ", "a"]), ["a"]);
+    assert_eq!(keys(&["MEMORY", "USAGE", "a"]), ["a"]);
+    assert!(keys(&["OBJECT", "help"]).is_empty());
+    assert!(keys(&["PING"]).is_empty());
+    assert!(keys(&["FLUSHALL", "ASYNC"]).is_empty());
+    assert!(keys(&["GET"]).is_empty());
+}
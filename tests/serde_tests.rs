@@ -0,0 +1,156 @@
+#![cfg(all(feature = "serde", feature = "lists", feature = "sets"))]
+
+use bytes::Bytes;
+use proptest::{collection::vec, prelude::*};
+use rustis::{
+    kv::RedisValue,
+    list::List,
+    message::ResponseValue,
+    module::{ModuleData, ModuleValue},
+    set::Set,
+    string::StringValue,
+};
+use serde::{de::DeserializeOwned, Serialize};
+
+/// `value` through JSON, CBOR and bincode, and back.
+fn round_trips<T: Serialize + DeserializeOwned>(value: &T) -> [T; 3] {
+    let json = serde_json::to_vec(value).unwrap();
+    let mut cbor = Vec::new();
+    ciborium::into_writer(value, &mut cbor).unwrap();
+    let bincode = bincode::serialize(value).unwrap();
+    [
+        serde_json::from_slice(&json).unwrap(),
+        ciborium::from_reader(&cbor[..]).unwrap(),
+        bincode::deserialize(&bincode).unwrap(),
+    ]
+}
+
+fn bytes() -> impl Strategy<Value = Bytes> {
+    vec(any::<u8>(), 0..64).prop_map(Bytes::from)
+}
+
+fn frame() -> impl Strategy<Value = ResponseValue> {
+    let leaf = prop_oneof![
+        bytes().prop_map(ResponseValue::SimpleString),
+        bytes().prop_map(ResponseValue::Error),
+        any::<i64>().prop_map(ResponseValue::Integer),
+        proptest::option::of(bytes()).prop_map(ResponseValue::BulkString),
+        Just(ResponseValue::Array(None)),
+        bytes().prop_map(ResponseValue::BigNumber),
+        (any::<[u8; 3]>(), bytes())
+            .prop_map(|(format, data)| ResponseValue::VerbatimString { format, data }),
+    ];
+    leaf.prop_recursive(3, 32, 4, |inner| {
+        prop_oneof![
+            vec(inner.clone(), 0..4).prop_map(|items| ResponseValue::Array(Some(items))),
+            (vec((inner.clone(), inner.clone()), 0..3), inner).prop_map(|(attrs, value)| {
+                ResponseValue::Attribute {
+                    attrs,
+                    value: Box::new(value),
+                }
+            }),
+        ]
+    })
+}
+
+proptest! {
+    #[test]
+    fn test_frames_round_trip(frame in frame()) {
+        for decoded in round_trips(&frame) {
+            prop_assert_eq!(&decoded, &frame);
+        }
+    }
+
+    #[test]
+    fn test_values_round_trip(items in vec(bytes(), 0..200), ints in vec(any::<i64>(), 0..20)) {
+        let mut list = List::new();
+        let mut set = Set::new();
+        let mut int_set = Set::new();
+        for item in &items {
+            list.push_back(item.clone(), -2);
+            set.insert(item.clone(), 512);
+        }
+        for n in &ints {
+            int_set.insert(Bytes::from(n.to_string()), 512);
+        }
+        let values = [
+            RedisValue::String(StringValue::from(items.concat().into_iter().collect::<Bytes>())),
+            RedisValue::List(list),
+            RedisValue::Set(set),
+            RedisValue::Set(int_set),
+        ];
+        for value in values {
+            for decoded in round_trips(&value) {
+                prop_assert_eq!(&decoded, &value);
+                prop_assert_eq!(decoded.encoding(), value.encoding());
+            }
+        }
+    }
+}
+
+#[test]
+fn test_strings_keep_their_encoding() {
+    for (value, encoding) in [
+        ("-42", "int"),
+        ("042", "embstr"),
+        ("hello", "embstr"),
+        (&"x".repeat(100)[..], "raw"),
+    ] {
+        let value = RedisValue::String(StringValue::from(Bytes::from(value.to_string())));
+        assert_eq!(value.encoding(), encoding);
+        for decoded in round_trips(&value) {
+            assert_eq!(decoded, value);
+            assert_eq!(decoded.encoding(), encoding);
+        }
+    }
+}
+
+#[test]
+fn test_values_serialize_as_their_contents() {
+    let mut list = List::new();
+    list.push_back(Bytes::from("a"), -2);
+    list.push_back(Bytes::from("b"), -2);
+    let value = RedisValue::List(list);
+    assert_eq!(
+        serde_json::to_string(&value).unwrap(),
+        r#"{"List":[[97],[98]]}"#
+    );
+    let value = RedisValue::String(StringValue::from(Bytes::from("12")));
+    assert_eq!(
+        serde_json::to_string(&value).unwrap(),
+        r#"{"String":[49,50]}"#
+    );
+
+    // the variant's index doesn't move with the types built
+    let value = RedisValue::Set(Set::new());
+    assert_eq!(bincode::serialize(&value).unwrap()[..4], 2u32.to_le_bytes());
+
+    let value: RedisValue = serde_json::from_str(r#"{"Set":[[49],[50],[49]]}"#).unwrap();
+    assert_eq!(value.elements(), 2);
+    assert_eq!(value.encoding(), "intset");
+}
+
+#[derive(Debug, Clone)]
+struct Opaque;
+
+impl ModuleData for Opaque {
+    fn type_name(&self) -> &'static str {
+        "opaque"
+    }
+
+    fn memory_usage(&self) -> usize {
+        0
+    }
+
+    fn clone_data(&self) -> Box<dyn ModuleData> {
+        Box::new(self.clone())
+    }
+}
+
+#[test]
+fn test_module_values_are_not_serialized() {
+    let value = RedisValue::Module(ModuleValue::new(Opaque));
+    let err = serde_json::to_string(&value).unwrap_err();
+    assert!(err.to_string().contains("opaque"), "{err}");
+    assert!(serde_json::from_str::<RedisValue>(r#"{"Module":[]}"#).is_err());
+}
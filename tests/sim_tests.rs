@@ -0,0 +1,179 @@
+#![cfg(feature = "sim")]
+
+use std::{cell::RefCell, rc::Rc, time::Duration};
+
+use bytes::Bytes;
+use rustis::{config::Config, message::ResponseValue, sim::Simulation};
+use tokio::{task, time};
+
+fn bulk(value: &str) -> ResponseValue {
+    ResponseValue::BulkString(Some(Bytes::copy_from_slice(value.as_bytes())))
+}
+
+fn ok() -> ResponseValue {
+    ResponseValue::SimpleString("OK".into())
+}
+
+#[test]
+fn test_idle_timeout() {
+    let sim = Simulation::new(Config {
+        timeout: 5,
+        ..Config::default()
+    });
+    sim.block_on(async {
+        let mut idle = sim.connect();
+        let mut busy = sim.connect();
+        assert_eq!(idle.command(["SET", "k", "v"]).await, Some(ok()));
+        for _ in 0..4 {
+            time::sleep(Duration::from_secs(2)).await;
+            assert_eq!(busy.command(["GET", "k"]).await, Some(bulk("v")));
+        }
+        // idle for 8 seconds by now
+        assert_eq!(idle.reply().await, None);
+        assert_eq!(idle.command(["GET", "k"]).await, None);
+    });
+    let elapsed = sim.elapsed();
+    assert!(elapsed >= Duration::from_secs(8), "{elapsed:?}");
+    assert!(elapsed < Duration::from_secs(9), "{elapsed:?}");
+}
+
+#[test]
+fn test_expiry_on_virtual_time() {
+    let sim = Simulation::new(Config::default());
+    sim.block_on(async {
+        let mut connection = sim.connect();
+        assert_eq!(
+            connection.command(["SET", "a", "1", "EX", "10"]).await,
+            Some(ok())
+        );
+        assert_eq!(
+            connection.command(["SET", "b", "2", "PX", "500"]).await,
+            Some(ok())
+        );
+        assert_eq!(connection.command(["SET", "c", "3"]).await, Some(ok()));
+
+        time::sleep(Duration::from_secs(9)).await;
+        assert_eq!(connection.command(["GET", "a"]).await, Some(bulk("1")));
+        // removed by the active expire cycle, without being read
+        assert_eq!(
+            connection.command(["DBSIZE"]).await,
+            Some(ResponseValue::Integer(2))
+        );
+
+        time::sleep(Duration::from_secs(2)).await;
+        assert_eq!(
+            connection.command(["GET", "a"]).await,
+            Some(ResponseValue::BulkString(None))
+        );
+        assert_eq!(connection.command(["GET", "c"]).await, Some(bulk("3")));
+    });
+    assert!(sim.elapsed() < Duration::from_secs(12));
+}
+
+#[test]
+fn test_commands_across_shards() {
+    let sim = Simulation::new(Config {
+        workers: 4,
+        ..Config::default()
+    });
+    let client = sim.client();
+    sim.block_on(async {
+        let mut connection = sim.connect();
+        let reply = connection
+            .command(["MSET", "a", "1", "b", "2", "c", "3", "d", "4"])
+            .await;
+        assert_eq!(reply, Some(ok()));
+        assert_eq!(
+            client.command(["MGET", "d", "c", "b", "a", "e"]).await,
+            ResponseValue::Array(Some(vec![
+                bulk("4"),
+                bulk("3"),
+                bulk("2"),
+                bulk("1"),
+                ResponseValue::BulkString(None),
+            ]))
+        );
+        assert_eq!(
+            connection.command(["DBSIZE"]).await,
+            Some(ResponseValue::Integer(4))
+        );
+    });
+}
+
+#[test]
+fn test_pipeline_and_protocol_error() {
+    let sim = Simulation::new(Config::default());
+    sim.block_on(async {
+        let mut connection = sim.connect();
+        connection
+            .send(b"SET a 1\r\nSET b 2\r\nGET a\r\nGET b\r\n*1\r\n$x\r\n")
+            .await;
+        assert_eq!(connection.reply().await, Some(ok()));
+        assert_eq!(connection.reply().await, Some(ok()));
+        assert_eq!(connection.reply().await, Some(bulk("1")));
+        assert_eq!(connection.reply().await, Some(bulk("2")));
+        assert!(matches!(
+            connection.reply().await,
+            Some(ResponseValue::Error(_))
+        ));
+        assert_eq!(connection.reply().await, None);
+    });
+}
+
+#[test]
+fn test_lone_request_is_not_corked() {
+    let sim = Simulation::new(Config::default());
+    sim.block_on(async {
+        let mut connection = sim.connect();
+        assert_eq!(connection.command(["SET", "k", "v"]).await, Some(ok()));
+        assert_eq!(connection.command(["GET", "k"]).await, Some(bulk("v")));
+    });
+    // with nothing else in flight the writer flushes each reply at once,
+    // so the clock never moved on to a cork deadline
+    assert_eq!(sim.elapsed(), Duration::ZERO);
+}
+
+/// Clients taking turns on the same keys, each at its own pace, logging
+/// what they saw and when.
+fn interleaved() -> Vec<(u128, usize, ResponseValue)> {
+    let sim = Simulation::new(Config::default());
+    let log = Rc::new(RefCell::new(Vec::new()));
+    sim.block_on(async {
+        let started = time::Instant::now();
+        let clients: Vec<_> = (0..3)
+            .map(|i| {
+                let mut connection = sim.connect();
+                let log = log.clone();
+                task::spawn_local(async move {
+                    for round in 0..5 {
+                        time::sleep(Duration::from_millis(7 * (i as u64 + 1))).await;
+                        let key = format!("k{}", round % 2);
+                        let value = format!("{i}:{round}");
+                        connection.command(["SET".to_string(), key, value]).await;
+                        let reply = connection.command(["GET", "k0"]).await.unwrap();
+                        log.borrow_mut()
+                            .push((started.elapsed().as_millis(), i, reply));
+                    }
+                })
+            })
+            .collect();
+        for client in clients {
+            client.await.unwrap();
+        }
+    });
+    Rc::try_unwrap(log).unwrap().into_inner()
+}
+
+#[test]
+fn test_runs_are_reproducible() {
+    let first = interleaved();
+    assert_eq!(first.len(), 15);
+    // every client's last round is at 5 times its pace, whatever the load
+    for (i, pace) in [7, 14, 21].into_iter().enumerate() {
+        let last = first.iter().filter(|(_, client, _)| *client == i).last();
+        assert_eq!(last.unwrap().0, 5 * pace);
+    }
+    for _ in 0..5 {
+        assert_eq!(interleaved(), first);
+    }
+}
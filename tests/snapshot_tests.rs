@@ -0,0 +1,152 @@
+#![cfg(feature = "server")]
+
+use std::time::Duration;
+
+use bytes::{Bytes, BytesMut};
+use rustis::{
+    config::Config,
+    message::ResponseValue,
+    parser::{parse, BufParseError},
+    ServerBuilder,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+fn bulk(value: &str) -> ResponseValue {
+    ResponseValue::BulkString(Some(Bytes::copy_from_slice(value.as_bytes())))
+}
+
+fn info_field(info: &ResponseValue, field: &str) -> String {
+    let ResponseValue::BulkString(Some(info)) = info else {
+        panic!("not an INFO reply: {info:?}");
+    };
+    let info = String::from_utf8_lossy(info);
+    let line = info
+        .lines()
+        .find_map(|line| line.strip_prefix(&format!("{field}:")))
+        .unwrap();
+    line.to_string()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_read_snapshots() {
+    let config = Config {
+        port: 0,
+        workers: 2,
+        read_snapshots: true,
+        ..Config::default()
+    };
+    let mut server = ServerBuilder::config(config).build().unwrap();
+    server.start().unwrap();
+    let client = server.client();
+
+    // every write is readable once its reply is in
+    for i in 0..200 {
+        let value = i.to_string();
+        for key in ["a", "{a}b"] {
+            let set = ["SET", key, &value].map(|arg| Bytes::copy_from_slice(arg.as_bytes()));
+            client.command(set).await;
+        }
+        assert_eq!(client.command(["GET", "a"]).await, bulk(&value));
+        assert_eq!(
+            client.command(["MGET", "a", "{a}b", "c"]).await,
+            ResponseValue::Array(Some(vec![
+                bulk(&value),
+                bulk(&value),
+                ResponseValue::BulkString(None)
+            ]))
+        );
+    }
+    // the workers didn't see the reads
+    let info = client.command(["INFO", "stats"]).await;
+    assert_eq!(info_field(&info, "keyspace_hits"), "0");
+    assert_eq!(info_field(&info, "keyspace_misses"), "0");
+
+    // other types are the workers' to refuse, and read as nil by MGET
+    #[cfg(feature = "lists")]
+    {
+        client.command(["RPUSH", "list", "x"]).await;
+        let reply = client.command(["GET", "list"]).await;
+        assert!(matches!(reply, ResponseValue::Error(e) if e.starts_with(b"WRONGTYPE")));
+        assert_eq!(
+            client.command(["MGET", "list"]).await,
+            ResponseValue::Array(Some(vec![ResponseValue::BulkString(None)]))
+        );
+    }
+
+    client.command(["DEL", "a"]).await;
+    assert_eq!(
+        client.command(["GET", "a"]).await,
+        ResponseValue::BulkString(None)
+    );
+    client.command(["SET", "soon", "v", "PX", "100"]).await;
+    assert_eq!(client.command(["GET", "soon"]).await, bulk("v"));
+    tokio::time::sleep(Duration::from_millis(150)).await;
+    assert_eq!(
+        client.command(["GET", "soon"]).await,
+        ResponseValue::BulkString(None)
+    );
+
+    client.command(["FLUSHALL"]).await;
+    assert_eq!(
+        client.command(["GET", "{a}b"]).await,
+        ResponseValue::BulkString(None)
+    );
+    client.command(["SET", "a", "again"]).await;
+    assert_eq!(client.command(["GET", "a"]).await, bulk("again"));
+
+    server.shutdown();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_pipelined_reads_see_their_writes() {
+    let config = Config {
+        port: 0,
+        workers: 2,
+        read_snapshots: true,
+        ..Config::default()
+    };
+    let mut server = ServerBuilder::config(config).build().unwrap();
+    server.start().unwrap();
+    let mut stream = TcpStream::connect(server.addr()).await.unwrap();
+
+    // each read in one write with the writes before it, which are still
+    // unanswered when it is routed
+    let mut pipeline = BytesMut::new();
+    let command = |args: &[&str]| {
+        let items = args.iter().map(|arg| bulk(arg)).collect();
+        ResponseValue::Array(Some(items))
+    };
+    for i in 0..200 {
+        let value = i.to_string();
+        command(&["SET", "a", &value]).serialize(&mut pipeline);
+        command(&["GET", "a"]).serialize(&mut pipeline);
+        command(&["MSET", "b", &value, "{a}c", &value]).serialize(&mut pipeline);
+        command(&["MGET", "a", "b", "{a}c"]).serialize(&mut pipeline);
+    }
+    stream.write_all(&pipeline).await.unwrap();
+
+    let mut buffer = BytesMut::new();
+    let mut replies = Vec::new();
+    while replies.len() < 800 {
+        match parse(&mut buffer) {
+            Ok(reply) => replies.push(reply),
+            Err(BufParseError::Incomplete) => {
+                assert_ne!(stream.read_buf(&mut buffer).await.unwrap(), 0);
+            }
+            Err(err) => panic!("{err:?}"),
+        }
+    }
+    for (i, replies) in replies.chunks(4).enumerate() {
+        let value = i.to_string();
+        assert_eq!(replies[1], bulk(&value));
+        assert_eq!(
+            replies[3],
+            ResponseValue::Array(Some(vec![bulk(&value), bulk(&value), bulk(&value)]))
+        );
+    }
+
+    server.shutdown();
+}
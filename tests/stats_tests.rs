@@ -0,0 +1,307 @@
+#![cfg(feature = "server")]
+
+use std::{
+    sync::{atomic::Ordering, Arc},
+    time::{Duration, Instant},
+};
+
+use rustis::{
+    allocator::{self, AllocatorStats},
+    info::render_info,
+    kv::{TypeUsage, VALUE_TYPES},
+    server::{identity, random_id, ID_LEN},
+    stats::{error_prefix, ServerStats, ERRORSTATS_LIMIT, HOT_WORKER_SAMPLES, STATS},
+};
+
+#[cfg(feature = "lists")]
+use rustis::{config::Config, connection::accept_loop, worker::worker_main};
+#[cfg(feature = "lists")]
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    sync::mpsc,
+    task::LocalSet,
+};
+
+#[test]
+fn test_instantaneous_rates() {
+    let stats = ServerStats::new();
+    let start = Instant::now();
+    stats.sample(start);
+
+    // 16 samples of 100 commands and 2KB in, 100ms apart: 1000 ops/sec, 20 kbps
+    for i in 1..=16 {
+        ServerStats::incr(&stats.total_commands_processed, 100);
+        ServerStats::incr(&stats.total_net_input_bytes, 2048);
+        stats.sample(start + Duration::from_millis(100 * i));
+    }
+
+    assert_eq!(stats.instantaneous_ops_per_sec(), 1000);
+    assert_eq!(stats.instantaneous_input_kbps(), 20.0);
+    assert_eq!(stats.instantaneous_output_kbps(), 0.0);
+}
+
+#[test]
+fn test_instantaneous_rates_decay() {
+    let stats = ServerStats::new();
+    let start = Instant::now();
+    stats.sample(start);
+    ServerStats::incr(&stats.total_commands_processed, 1600);
+    stats.sample(start + Duration::from_millis(100));
+    assert_eq!(stats.instantaneous_ops_per_sec(), 1000);
+
+    // nothing happens for a full window
+    for i in 2..=17 {
+        stats.sample(start + Duration::from_millis(100 * i));
+    }
+    assert_eq!(stats.instantaneous_ops_per_sec(), 0);
+}
+
+#[test]
+fn test_hot_worker_detection() {
+    let stats = ServerStats::new();
+    let cold = stats.worker(0, 100);
+    let hot = stats.worker(1, 100);
+    assert!(Arc::ptr_eq(&hot, &stats.worker(1, 100)));
+
+    hot.queue_depth.store(80, Ordering::Relaxed);
+    cold.queue_depth.store(10, Ordering::Relaxed);
+    let start = Instant::now();
+    for i in 0..HOT_WORKER_SAMPLES {
+        assert!(!hot.is_hot());
+        stats.sample(start + Duration::from_millis(100 * i));
+    }
+    assert!(hot.is_hot());
+    assert!(!cold.is_hot());
+
+    // one sample below the threshold resets it
+    hot.queue_depth.store(0, Ordering::Relaxed);
+    stats.sample(start + Duration::from_secs(2));
+    assert!(!hot.is_hot());
+}
+
+#[test]
+fn test_allocator_stats() {
+    let stats = AllocatorStats {
+        allocated: Some(100),
+        active: Some(150),
+        resident: Some(300),
+    };
+    assert_eq!(stats.frag_ratio(), Some(1.5));
+    assert_eq!(stats.rss_ratio(), Some(2.0));
+    assert_eq!(AllocatorStats::default().frag_ratio(), None);
+    let empty = AllocatorStats {
+        allocated: Some(0),
+        ..stats
+    };
+    assert_eq!(empty.frag_ratio(), None);
+
+    // whichever allocator this is built with answers for itself
+    let stats = allocator::stats();
+    if let (Some(allocated), Some(resident)) = (stats.allocated, stats.resident) {
+        assert!(allocated > 0 && resident >= allocated);
+    }
+    #[cfg(target_os = "linux")]
+    assert!(allocator::process_rss().unwrap() > 0);
+}
+
+#[test]
+fn test_info_sections() {
+    let all = render_info(None);
+    assert!(all.starts_with("# Server\r\n"));
+    assert!(all.contains("\r\n\r\n# Clients\r\nconnected_clients:"));
+    assert!(all.contains("\r\n\r\n# Memory\r\n"));
+    assert!(all.contains("\r\n\r\n# Stats\r\n"));
+    for field in [
+        "total_connections_received:",
+        "total_commands_processed:",
+        "instantaneous_ops_per_sec:",
+        "total_net_input_bytes:",
+        "total_net_output_bytes:",
+        "rejected_connections:",
+        "evicted_keys:",
+        "keyspace_hits:",
+        "keyspace_misses:",
+        "# Memory\r\nused_memory:",
+        "# Workers\r\n",
+        "hot_workers:",
+        "used_memory_rss:",
+        "mem_fragmentation_ratio:",
+        "\r\nmem_allocator:",
+    ] {
+        assert!(all.contains(field), "missing {field}");
+    }
+
+    let stats = render_info(Some("STATS"));
+    assert!(stats.starts_with("# Stats\r\n"));
+    assert!(!stats.contains("# Clients"));
+
+    assert_eq!(render_info(Some("nope")), "");
+}
+
+#[test]
+fn test_info_server() {
+    let id = random_id();
+    assert_eq!(id.len(), ID_LEN);
+    assert!(id
+        .bytes()
+        .all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b)));
+    assert_ne!(id, random_id());
+
+    let info = render_info(Some("server"));
+    let field = |name: &str| {
+        info.split("\r\n")
+            .find_map(|line| line.strip_prefix(name)?.strip_prefix(':'))
+            .unwrap_or_else(|| panic!("missing {name}"))
+            .to_string()
+    };
+    assert!(info.starts_with("# Server\r\n"));
+    assert_eq!(field("run_id"), identity().run_id);
+    assert_eq!(field("process_id"), std::process::id().to_string());
+    assert_eq!(field("arch_bits"), usize::BITS.to_string());
+    assert!(field("uptime_in_seconds").parse::<u64>().is_ok());
+    assert!(field("server_time_usec").parse::<u64>().unwrap() > 1_700_000_000_000_000);
+
+    let replication = render_info(Some("replication"));
+    assert!(replication.starts_with("# Replication\r\nrole:master\r\n"));
+    assert!(replication.contains(&format!("\r\nmaster_replid:{}\r\n", identity().replid)));
+    assert_ne!(identity().replid, identity().run_id);
+
+    let cpu = render_info(Some("cpu"));
+    assert!(cpu.starts_with("# CPU\r\n"));
+    #[cfg(unix)]
+    assert!(cpu.contains("\r\nused_cpu_user:"));
+}
+
+#[test]
+fn test_info_keyspace() {
+    assert!(render_info(None).contains("\r\n\r\n# Keyspace\r\n"));
+
+    let worker = STATS.worker(9001, 16);
+    let mut usage = [TypeUsage::default(); VALUE_TYPES];
+    usage[0].keys = 3;
+    usage[1].keys = 2;
+    worker.record_memory(&usage);
+    worker.expires.store(2, Ordering::Relaxed);
+    worker.avg_ttl.store(1500, Ordering::Relaxed);
+    // other tests' workers may hold keys too
+    let keyspace = render_info(Some("keyspace"));
+    let line = keyspace.strip_prefix("# Keyspace\r\ndb0:keys=").unwrap();
+    let (keys, rest) = line.split_once(',').unwrap();
+    assert!(keys.parse::<usize>().unwrap() >= 5);
+    assert_eq!(rest, "expires=2,avg_ttl=1500\r\n");
+    worker.record_memory(&Default::default());
+    worker.expires.store(0, Ordering::Relaxed);
+}
+
+#[test]
+fn test_errorstats() {
+    assert_eq!(
+        error_prefix(b"WRONGTYPE Operation against a key"),
+        "WRONGTYPE"
+    );
+    assert_eq!(error_prefix(b"ERR unknown command"), "ERR");
+    assert_eq!(error_prefix(b"NOAUTH"), "NOAUTH");
+    assert_eq!(error_prefix(b"request must be array"), "ERR");
+    assert_eq!(error_prefix(b""), "ERR");
+
+    let stats = ServerStats::new();
+    stats.record_error(b"ERR syntax error");
+    stats.record_error(b"WRONGTYPE Operation against a key");
+    stats.record_error(b"invalid command");
+    assert_eq!(
+        stats.error_replies(),
+        [("ERR".to_string(), 2), ("WRONGTYPE".to_string(), 1)]
+    );
+    assert_eq!(ServerStats::get(&stats.total_error_replies), 3);
+
+    // past the limit, new prefixes only count toward the total
+    for i in 0..ERRORSTATS_LIMIT {
+        let prefix = [b'X', b'A' + (i / 26) as u8, b'A' + (i % 26) as u8];
+        stats.record_error(&[&prefix[..], b" boom"].concat());
+    }
+    let errors = stats.error_replies();
+    assert_eq!(errors.len(), ERRORSTATS_LIMIT);
+    assert!(!errors.iter().any(|(prefix, _)| prefix == "XEX"));
+    stats.record_error(b"ERR again");
+    assert!(stats.error_replies().contains(&("ERR".to_string(), 3)));
+    assert_eq!(
+        ServerStats::get(&stats.total_error_replies),
+        3 + ERRORSTATS_LIMIT as u64 + 1
+    );
+
+    assert!(render_info(None).contains("\r\n\r\n# Errorstats\r\n"));
+    assert!(render_info(Some("stats")).contains("\r\ntotal_error_replies:"));
+}
+
+#[test]
+fn test_info_latencystats() {
+    let worker = STATS.worker(9002, 16);
+    for nanos in [2_000, 2_000, 2_000, 900_000] {
+        worker.latencies().record("object", nanos);
+    }
+    STATS.worker(9003, 16).latencies().record("object", 1_000);
+
+    // only in INFO all, as in Redis
+    assert!(!render_info(None).contains("# Latencystats"));
+    assert!(render_info(Some("all")).contains("\r\n\r\n# Latencystats\r\n"));
+    let latencystats = render_info(Some("latencystats"));
+    assert!(latencystats.starts_with("# Latencystats\r\n"));
+    assert!(latencystats
+        .contains("\r\nlatency_percentiles_usec_object:p50=2.007,p99=901.119,p99.9=901.119\r\n"));
+}
+
+#[cfg(feature = "lists")]
+#[tokio::test]
+async fn test_maxclients_rejects_and_counts() {
+    let (tx, rx) = mpsc::channel(64);
+    std::thread::spawn(move || worker_main(0, rx, Arc::default()));
+    let router = Arc::new(vec![tx]);
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let config = Arc::new(Config {
+        maxclients: 1,
+        ..Config::default()
+    });
+
+    let local = LocalSet::new();
+    local
+        .run_until(async move {
+            tokio::task::spawn_local(accept_loop(listener, router, config));
+
+            // the first client takes the only slot
+            let mut first = TcpStream::connect(addr).await.unwrap();
+            first
+                .write_all(b"*3\r\n$3\r\nSET\r\n$1\r\nk\r\n$1\r\nv\r\n")
+                .await
+                .unwrap();
+            let mut reply = [0u8; 5];
+            first.read_exact(&mut reply).await.unwrap();
+            assert_eq!(&reply, b"+OK\r\n");
+
+            // error replies are counted as they are written out
+            first
+                .write_all(b"*3\r\n$5\r\nLPUSH\r\n$1\r\nk\r\n$1\r\nv\r\n")
+                .await
+                .unwrap();
+            let wrongtype =
+                b"-WRONGTYPE Operation against a key holding the wrong kind of value\r\n";
+            let mut reply = vec![0u8; wrongtype.len()];
+            first.read_exact(&mut reply).await.unwrap();
+            assert_eq!(reply, wrongtype);
+            assert!(render_info(Some("errorstats")).contains("\r\nerrorstat_WRONGTYPE:count="));
+
+            let mut second = TcpStream::connect(addr).await.unwrap();
+            let mut reply = Vec::new();
+            second.read_to_end(&mut reply).await.unwrap();
+            assert_eq!(reply, b"-ERR max number of clients reached\r\n");
+
+            assert!(ServerStats::get(&STATS.rejected_connections) >= 1);
+            assert!(ServerStats::get(&STATS.total_connections_received) >= 2);
+            assert!(ServerStats::get(&STATS.total_commands_processed) >= 1);
+            assert!(ServerStats::get(&STATS.total_net_input_bytes) >= 27);
+            assert!(ServerStats::get(&STATS.total_net_output_bytes) >= 5);
+        })
+        .await;
+}
@@ -0,0 +1,59 @@
+#![cfg(feature = "server")]
+
+use std::{net::UdpSocket, time::Duration};
+
+use rustis::{
+    stats::{ServerStats, STATS},
+    statsd::{Exporter, MAX_PACKET},
+};
+
+/// Everything one push sent, one metric per line.
+fn receive(socket: &UdpSocket) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut buf = [0u8; 65536];
+    while let Ok(len) = socket.recv(&mut buf) {
+        assert!(len <= MAX_PACKET);
+        let packet = std::str::from_utf8(&buf[..len]).unwrap();
+        lines.extend(packet.split('\n').map(str::to_string));
+    }
+    lines
+}
+
+#[test]
+fn test_push() {
+    let statsd = UdpSocket::bind("127.0.0.1:0").unwrap();
+    statsd
+        .set_read_timeout(Some(Duration::from_millis(200)))
+        .unwrap();
+    let mut exporter = Exporter::new(&statsd.local_addr().unwrap().to_string(), "cache").unwrap();
+
+    let worker = STATS.worker(9004, 16);
+    worker.latencies().record("get", 2_000_000);
+    ServerStats::incr(&STATS.evicted_keys, 3);
+    exporter.push().unwrap();
+    let lines = receive(&statsd);
+    assert!(lines.contains(&"cache.evicted_keys:3|c".to_string()));
+    assert!(lines.contains(&"cache.latency.get.p99_9:2.007|g".to_string()));
+    for name in ["cache.commands_processed:", "cache.keyspace_hits:"] {
+        assert!(lines
+            .iter()
+            .any(|line| line.starts_with(name) && line.ends_with("|c")));
+    }
+    for name in [
+        "cache.connected_clients:",
+        "cache.used_memory:",
+        "cache.keys:",
+    ] {
+        assert!(lines
+            .iter()
+            .any(|line| line.starts_with(name) && line.ends_with("|g")));
+    }
+
+    // counters send what changed since the last push
+    ServerStats::incr(&STATS.evicted_keys, 2);
+    exporter.push().unwrap();
+    let lines = receive(&statsd);
+    assert!(lines.contains(&"cache.evicted_keys:2|c".to_string()));
+    exporter.push().unwrap();
+    assert!(receive(&statsd).contains(&"cache.evicted_keys:0|c".to_string()));
+}
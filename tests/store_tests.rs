@@ -0,0 +1,58 @@
+#![cfg(all(feature = "server", feature = "lists", feature = "sets"))]
+
+use bytes::Bytes;
+use rustis::{
+    store::{Store, StoreError},
+    LocalClient, Server,
+};
+
+#[tokio::test]
+async fn test_store() {
+    let mut server = Server::builder().port(0).workers(2).build().unwrap();
+    server.start().unwrap();
+    let store = server.store();
+
+    assert_eq!(store.get("missing").await, Ok(None));
+    store.set("a", "1").await.unwrap();
+    store.mset([("b", "2"), ("c", "3")]).await.unwrap();
+    assert_eq!(store.get("a").await, Ok(Some(Bytes::from("1"))));
+
+    assert_eq!(store.rpush("list", ["x", "y", "z"]).await, Ok(3));
+    assert_eq!(store.lpush("list", ["w"]).await, Ok(4));
+    assert_eq!(
+        store.lrange("list", 0, -1).await.unwrap(),
+        ["w", "x", "y", "z"]
+    );
+    assert_eq!(store.lpop("list", 1).await.unwrap(), ["w"]);
+    assert_eq!(store.rpop("list", 2).await.unwrap().len(), 2);
+
+    assert_eq!(store.sadd("set", ["m", "n", "m"]).await, Ok(2));
+    let mut members = store.smembers("set").await.unwrap();
+    members.sort();
+    assert_eq!(members, ["m", "n"]);
+    assert_eq!(store.spop("set", 5).await.unwrap().len(), 2);
+
+    // across shards, lists reading as missing
+    assert_eq!(
+        store.mget(["c", "list", "a", "nope"]).await.unwrap(),
+        [Some(Bytes::from("3")), None, Some(Bytes::from("1")), None]
+    );
+    assert_eq!(
+        store.get("list").await,
+        Err(StoreError::Reply(
+            "WRONGTYPE Operation against a key holding the wrong kind of value".into()
+        ))
+    );
+    assert_eq!(store.exists(["a", "b", "a", "nope"]).await, Ok(3));
+    assert_eq!(store.dbsize().await, Ok(4));
+    assert_eq!(store.del(["a", "nope"]).await, Ok(1));
+    store.flushall().await.unwrap();
+    assert_eq!(store.dbsize().await, Ok(0));
+
+    server.shutdown();
+    assert_eq!(store.get("b").await, Err(StoreError::ShutDown));
+    assert_eq!(
+        Store::new(LocalClient::disconnected()).dbsize().await,
+        Err(StoreError::ShutDown)
+    );
+}
@@ -0,0 +1,96 @@
+#![cfg(feature = "otel")]
+
+use std::sync::{Arc, Mutex};
+
+use opentelemetry::Value;
+use opentelemetry_sdk::{
+    error::OTelSdkResult,
+    trace::{SdkTracerProvider, SpanData, SpanExporter},
+};
+use rustis::{
+    config::Config,
+    connection::{handle_connection, Client},
+    telemetry,
+    worker::worker_main,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    sync::mpsc,
+    task::LocalSet,
+};
+
+/// Keeps every span exported.
+#[derive(Clone, Debug, Default)]
+struct Captured(Arc<Mutex<Vec<SpanData>>>);
+
+impl SpanExporter for Captured {
+    async fn export(&self, batch: Vec<SpanData>) -> OTelSdkResult {
+        self.0.lock().unwrap().extend(batch);
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn test_command_spans() {
+    let captured = Captured::default();
+    let provider = SdkTracerProvider::builder()
+        .with_simple_exporter(captured.clone())
+        .build();
+    telemetry::install(provider, 1.0);
+
+    let (tx, rx) = mpsc::channel(64);
+    std::thread::spawn(move || worker_main(0, rx, Arc::default()));
+    let router = vec![tx];
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    LocalSet::new()
+        .run_until(async move {
+            tokio::task::spawn_local(async move {
+                let (stream, addr) = listener.accept().await.unwrap();
+                let _ =
+                    handle_connection(stream, Client { id: 1, addr }, &router, &Config::default())
+                        .await;
+            });
+            let mut client = TcpStream::connect(addr).await.unwrap();
+            client
+                .write_all(b"*3\r\n$3\r\nSET\r\n$1\r\na\r\n$1\r\n1\r\n*1\r\n$4\r\nQUIT\r\n")
+                .await
+                .unwrap();
+            let mut reply = Vec::new();
+            client.read_to_end(&mut reply).await.unwrap();
+            assert_eq!(reply, b"+OK\r\n+OK\r\n");
+        })
+        .await;
+    telemetry::shutdown();
+
+    let spans = captured.0.lock().unwrap();
+    let set = spans
+        .iter()
+        .find(|span| {
+            span.name == "command"
+                && span.attributes.iter().any(|kv| {
+                    kv.key.as_str() == "db.operation.name" && kv.value == Value::from("SET")
+                })
+        })
+        .expect("SET was traced");
+    assert!(set
+        .attributes
+        .iter()
+        .any(|kv| kv.key.as_str() == "rustis.keys" && kv.value == Value::I64(1)));
+
+    let trace_id = set.span_context.trace_id();
+    let mut stages: Vec<_> = spans
+        .iter()
+        .filter(|span| span.span_context.trace_id() == trace_id && span.name != "command")
+        .map(|span| {
+            assert_eq!(span.parent_span_id, set.span_context.span_id());
+            assert!(span.start_time >= set.start_time && span.end_time <= set.end_time);
+            (span.start_time, span.name.to_string())
+        })
+        .collect();
+    stages.sort();
+    let stages: Vec<_> = stages.into_iter().map(|(_, name)| name).collect();
+    assert_eq!(stages, ["parse", "route", "execute", "serialize"]);
+}
@@ -0,0 +1,41 @@
+#![cfg(feature = "testing")]
+
+use std::{
+    io::{Read, Write},
+    net::TcpStream,
+};
+
+use rustis::{message::ResponseValue, testing::TestServer, Server};
+
+#[tokio::test]
+async fn test_test_server() {
+    let first = TestServer::start();
+    let second = TestServer::with(Server::builder().port(6379).workers(1));
+    assert_ne!(first.addr(), second.addr());
+    assert_ne!(second.addr().port(), 6379);
+    assert_eq!(
+        first.url(),
+        format!("redis://127.0.0.1:{}", first.addr().port())
+    );
+    assert_eq!(first.server().config().workers, 2);
+
+    // separate datasets
+    assert_eq!(
+        first.client().command(["SET", "k", "v"]).await,
+        ResponseValue::SimpleString("OK".into())
+    );
+    assert_eq!(
+        second.client().command(["EXISTS", "k"]).await,
+        ResponseValue::Integer(0)
+    );
+
+    let mut stream = TcpStream::connect(first.addr()).unwrap();
+    stream.write_all(b"*2\r\n$3\r\nGET\r\n$1\r\nk\r\n").unwrap();
+    let mut reply = [0u8; 7];
+    stream.read_exact(&mut reply).unwrap();
+    assert_eq!(&reply, b"$1\r\nv\r\n");
+
+    let addr = first.addr();
+    drop(first);
+    assert!(TcpStream::connect(addr).is_err());
+}